@@ -0,0 +1,230 @@
+//! An offline-first analytics client: events are queued on disk while the collector is
+//! unreachable, survive an app restart, and are uploaded once it comes back.
+//!
+//! The collector is a mock HTTP server running on a background thread, which can be
+//! switched offline (connections are dropped) and online. The example checks what the
+//! server received and exits with an error if anything was lost or duplicated, so it
+//! doubles as an end-to-end test of the delivery loop.
+
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use transientdb::{
+	DataResult, DeliveryResult, DirectoryConfig, DirectoryContentStore, DirectoryStore,
+	DrainPolicy, Sink, TransientDB,
+};
+
+/// Events and batch ids the mock collector has accepted
+#[derive(Default)]
+struct Received {
+	events: Vec<Value>,
+	batch_ids: HashSet<String>,
+}
+
+/// A collector accepting `POST /v1/batch` with a JSON envelope.
+struct MockCollector {
+	addr: SocketAddr,
+	online: Arc<AtomicBool>,
+	received: Arc<Mutex<Received>>,
+}
+
+impl MockCollector {
+	fn start() -> Result<Self> {
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		let addr = listener.local_addr()?;
+		let online = Arc::new(AtomicBool::new(false));
+		let received = Arc::new(Mutex::new(Received::default()));
+
+		let (server_online, server_received) = (online.clone(), received.clone());
+		thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				if !server_online.load(Ordering::SeqCst) {
+					// Offline: drop the connection, like an unreachable network
+					continue;
+				}
+				if let Err(e) = Self::handle(stream, &server_received) {
+					eprintln!("collector: {}", e);
+				}
+			}
+		});
+		Ok(Self {
+			addr,
+			online,
+			received,
+		})
+	}
+
+	fn set_online(&self, online: bool) {
+		self.online.store(online, Ordering::SeqCst);
+	}
+
+	fn handle(stream: TcpStream, received: &Mutex<Received>) -> Result<()> {
+		let mut reader = BufReader::new(stream.try_clone()?);
+		let mut content_length = 0;
+		let mut line = String::new();
+		loop {
+			line.clear();
+			reader.read_line(&mut line)?;
+			let header = line.trim_end();
+			if header.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = header.split_once(':') {
+				if name.eq_ignore_ascii_case("content-length") {
+					content_length = value.trim().parse().unwrap_or(0);
+				}
+			}
+		}
+		let mut body = vec![0; content_length];
+		reader.read_exact(&mut body)?;
+
+		let status = match serde_json::from_slice::<Value>(&body) {
+			Ok(envelope) => {
+				let mut received = received.lock().unwrap();
+				let batch_id = envelope["batchId"].as_str().unwrap_or_default().to_string();
+				// A retried batch the collector already has is acknowledged, not stored twice
+				if received.batch_ids.insert(batch_id) {
+					let batch = envelope["batch"].as_array().cloned().unwrap_or_default();
+					received.events.extend(batch);
+				}
+				"200 OK"
+			}
+			Err(_) => "400 Bad Request",
+		};
+		let mut stream = stream;
+		write!(
+			stream,
+			"HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+			status
+		)
+	}
+}
+
+/// Uploads batches to the collector over plain HTTP/1.1.
+struct HttpSink {
+	addr: SocketAddr,
+}
+
+impl HttpSink {
+	fn post(&self, body: &[u8]) -> Result<u16> {
+		let mut stream = TcpStream::connect_timeout(&self.addr, Duration::from_secs(1))?;
+		stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+		write!(
+			stream,
+			"POST /v1/batch HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+			self.addr,
+			body.len()
+		)?;
+		stream.write_all(body)?;
+
+		let mut status_line = String::new();
+		BufReader::new(stream).read_line(&mut status_line)?;
+		status_line
+			.split_whitespace()
+			.nth(1)
+			.and_then(|code| code.parse().ok())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no HTTP status"))
+	}
+}
+
+impl Sink<Value> for HttpSink {
+	fn deliver(&mut self, batch: &DataResult<Value>) -> DeliveryResult {
+		let Some(envelope) = &batch.data else {
+			return DeliveryResult::Rejected;
+		};
+		// Send the batch id so the collector can drop batches it already has
+		let mut envelope = envelope.clone();
+		envelope["batchId"] = json!(batch.batch_id);
+		let body = envelope.to_string();
+		match self.post(body.as_bytes()) {
+			Ok(200..=299) => DeliveryResult::Delivered,
+			Ok(429) | Ok(500..=599) => DeliveryResult::Retry,
+			Ok(_) => DeliveryResult::Rejected,
+			// Connection refused, reset or timed out: we're offline
+			Err(_) => DeliveryResult::Retry,
+		}
+	}
+}
+
+fn open_db(dir: &Path) -> Result<TransientDB<Value>> {
+	let store = DirectoryStore::new(DirectoryConfig {
+		write_key: "offline-first-demo".into(),
+		storage_location: dir.to_owned(),
+		base_filename: "events".into(),
+		max_file_size: 2 * 1024,
+	})?;
+	Ok(TransientDB::new(DirectoryContentStore::new(store)))
+}
+
+fn check(condition: bool, message: &str) -> Result<()> {
+	if condition {
+		println!("   ok: {}", message);
+		Ok(())
+	} else {
+		Err(io::Error::other(format!("check failed: {}", message)))
+	}
+}
+
+fn offline_first_example() -> Result<()> {
+	let dir = tempfile::tempdir()?;
+	let collector = MockCollector::start()?;
+	let mut sink = HttpSink {
+		addr: collector.addr,
+	};
+	let policy = DrainPolicy {
+		batch_count: Some(10),
+		max_retries: 2,
+		retry_delay: Duration::from_millis(50),
+		..DrainPolicy::default()
+	};
+
+	println!("Session 1: collector offline");
+	let db = open_db(dir.path())?;
+	for i in 0..25 {
+		db.append(json!({"event": "screen_view", "index": i}))?;
+	}
+	let summary = db.drain_into(&mut sink, &policy)?;
+	println!("   drain: {:?}", summary);
+	check(summary.gave_up, "upload gives up while offline")?;
+	check(summary.events_sent == 0, "nothing was sent")?;
+	check(db.has_data(), "events stay queued")?;
+
+	println!("App restart: events are recovered from disk");
+	db.flush()?;
+	drop(db);
+	let db = open_db(dir.path())?;
+	for i in 25..30 {
+		db.append(json!({"event": "screen_view", "index": i}))?;
+	}
+
+	println!("Session 2: collector back online");
+	collector.set_online(true);
+	let summary = db.drain_into(&mut sink, &policy)?;
+	println!("   drain: {:?}", summary);
+	check(!summary.gave_up, "the queue drains")?;
+	check(!db.has_data(), "nothing is left queued")?;
+
+	let received = collector.received.lock().unwrap();
+	let mut indices: Vec<u64> = received
+		.events
+		.iter()
+		.filter_map(|event| event["index"].as_u64())
+		.collect();
+	indices.sort_unstable();
+	check(
+		indices == (0..30).collect::<Vec<u64>>(),
+		"the collector got every event exactly once",
+	)?;
+	Ok(())
+}
+
+fn main() -> Result<()> {
+	println!("=== Offline-First Example ===");
+	offline_first_example()
+}
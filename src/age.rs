@@ -0,0 +1,155 @@
+//! Tracking how long events have been waiting in the queue.
+//!
+//! A wedged uploader and an idle one both look like a queue that isn't shrinking.
+//! What tells them apart is age: an idle queue holds only recent events, while a
+//! wedged one accumulates old ones.
+
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// A histogram of the ages of pending items.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use transientdb::{AgeHistogram, MemoryConfig, MemoryStore, TransientDB};
+/// use serde_json::json;
+///
+/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// }));
+/// db.append(json!({"event": "test"})).unwrap();
+///
+/// let histogram = db.age_histogram(AgeHistogram::DEFAULT_BOUNDS).unwrap();
+/// assert_eq!(histogram.total, 1);
+/// assert_eq!(histogram.counts[0], 1, "Just appended, so under a minute old");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgeHistogram {
+	/// Upper bounds of the buckets, ascending.
+	pub bounds: Vec<Duration>,
+	/// Number of items per bucket. `counts[i]` holds items no older than `bounds[i]` (and
+	/// older than `bounds[i - 1]`); the extra last entry holds items older than every bound.
+	pub counts: Vec<usize>,
+	/// Age of the oldest pending item, if any.
+	pub oldest: Option<Duration>,
+	/// Total number of items counted.
+	pub total: usize,
+}
+
+impl AgeHistogram {
+	/// Bucket bounds of 1 minute, 5 minutes, 15 minutes, 1 hour, 6 hours and 1 day.
+	pub const DEFAULT_BOUNDS: &'static [Duration] = &[
+		Duration::from_secs(60),
+		Duration::from_secs(5 * 60),
+		Duration::from_secs(15 * 60),
+		Duration::from_secs(60 * 60),
+		Duration::from_secs(6 * 60 * 60),
+		Duration::from_secs(24 * 60 * 60),
+	];
+
+	/// Builds a histogram from enqueue times, as of `now`.
+	///
+	/// `bounds` are sorted if they aren't already. Times in the future count as age zero.
+	pub fn from_times(times: &[DateTime<Utc>], now: DateTime<Utc>, bounds: &[Duration]) -> Self {
+		let mut bounds = bounds.to_vec();
+		bounds.sort();
+
+		let mut counts = vec![0; bounds.len() + 1];
+		let mut oldest: Option<Duration> = None;
+		for time in times {
+			let age = age_at(*time, now);
+			let bucket = bounds.partition_point(|bound| *bound < age);
+			counts[bucket] += 1;
+			oldest = oldest.max(Some(age));
+		}
+
+		Self {
+			bounds,
+			counts,
+			oldest,
+			total: times.len(),
+		}
+	}
+}
+
+/// How long before `now` an item was enqueued.
+pub(crate) fn age_at(enqueued: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+	(now - enqueued).to_std().unwrap_or_default()
+}
+
+/// Callback invoked with the age of the oldest pending item once it exceeds the threshold.
+pub(crate) type StalenessCallback = Box<dyn Fn(Duration) + Send + Sync>;
+
+/// A staleness alert registered on a TransientDB.
+pub(crate) struct StalenessAlert {
+	pub(crate) threshold: Duration,
+	pub(crate) callback: StalenessCallback,
+	/// When the queue was last checked, to keep checks on the append path cheap.
+	pub(crate) last_check: Option<DateTime<Utc>>,
+	/// Whether the callback already fired for the current stale episode.
+	pub(crate) fired: bool,
+}
+
+impl StalenessAlert {
+	/// Minimum time between automatic checks.
+	pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::AgeHistogram;
+	use crate::{MemoryConfig, MemoryStore, TransientDB};
+	use chrono::Utc;
+	use serde_json::json;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	#[test]
+	fn test_histogram_buckets() {
+		let now = Utc::now();
+		let times = [
+			now,
+			now - chrono::Duration::seconds(30),
+			now - chrono::Duration::seconds(90),
+			now - chrono::Duration::hours(48),
+		];
+		let histogram = AgeHistogram::from_times(&times, now, AgeHistogram::DEFAULT_BOUNDS);
+
+		assert_eq!(histogram.counts, vec![2, 1, 0, 0, 0, 0, 1]);
+		assert_eq!(histogram.total, 4);
+		assert_eq!(histogram.oldest, Some(Duration::from_secs(48 * 60 * 60)));
+	}
+
+	#[test]
+	fn test_staleness_alert_fires_once_per_episode() {
+		let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		}));
+
+		let fired = Arc::new(AtomicUsize::new(0));
+		let counter = fired.clone();
+		db.set_staleness_alert(Duration::ZERO, move |_| {
+			counter.fetch_add(1, Ordering::SeqCst);
+		});
+
+		assert!(db.check_staleness().is_none(), "Empty queue is never stale");
+		db.append(json!({"event": 1})).unwrap();
+		assert!(db.check_staleness().is_some());
+		assert!(db.check_staleness().is_some());
+		assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+		// Draining the queue re-arms the alert
+		let result = db.fetch(None, None).unwrap().unwrap();
+		db.remove(&result.removable.unwrap()).unwrap();
+		assert!(db.check_staleness().is_none());
+		db.append(json!({"event": 2})).unwrap();
+		db.check_staleness();
+		assert_eq!(fired.load(Ordering::SeqCst), 2);
+	}
+}
@@ -0,0 +1,137 @@
+//! Rewriting identifying fields of queued events to salted hashes.
+
+use crate::{JsonFormat, JsonPointer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::{self, Result};
+
+/// Replaces fields of events with salted SHA-256 hashes, for when a user opts out of
+/// tracking but aggregate events may still be sent.
+///
+/// Each field is replaced by the lowercase hex hash of the salt followed by its value
+/// (the string itself, or the canonical JSON of any other value). Equal values hash
+/// equally under the same salt, so events can still be grouped, but without the salt
+/// the hashes can't be matched against known values. Missing and `null` fields are
+/// left alone.
+///
+/// Usually created for you by [`TransientDB::anonymize`](crate::TransientDB::anonymize).
+/// Requires the `anonymize` feature.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{Anonymizer, JsonPointer};
+///
+/// let anonymizer = Anonymizer::with_salt(&[JsonPointer::new("/userId")?], b"salt".to_vec());
+/// let mut event = json!({"event": "purchase", "userId": "u-123"});
+/// assert!(anonymizer.anonymize(&mut event));
+/// assert_eq!(event["event"], "purchase");
+/// assert_ne!(event["userId"], "u-123");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+	fields: Vec<JsonPointer>,
+	salt: Vec<u8>,
+}
+
+impl Anonymizer {
+	/// Creates an anonymizer for `fields` with a random salt, which is never stored, so
+	/// the hashes it produces can't be linked to those of any other anonymizer.
+	///
+	/// # Errors
+	/// Fails if the platform has no source of randomness.
+	pub fn new(fields: &[JsonPointer]) -> Result<Self> {
+		let mut salt = vec![0u8; 16];
+		getrandom::getrandom(&mut salt)
+			.map_err(|e| io::Error::other(format!("Failed to generate salt: {}", e)))?;
+		Ok(Self::with_salt(fields, salt))
+	}
+
+	/// Creates an anonymizer for `fields` using a fixed salt, so hashes stay comparable
+	/// across passes.
+	pub fn with_salt(fields: &[JsonPointer], salt: impl Into<Vec<u8>>) -> Self {
+		Self {
+			fields: fields.to_vec(),
+			salt: salt.into(),
+		}
+	}
+
+	/// The fields being anonymized.
+	pub fn fields(&self) -> &[JsonPointer] {
+		&self.fields
+	}
+
+	/// Anonymizes the fields of one event, returning whether any were present.
+	pub fn anonymize(&self, event: &mut Value) -> bool {
+		let mut changed = false;
+		for field in &self.fields {
+			match field.get_mut(event) {
+				Some(Value::Null) | None => {}
+				Some(value) => {
+					*value = Value::String(self.hash(value));
+					changed = true;
+				}
+			}
+		}
+		changed
+	}
+
+	fn hash(&self, value: &Value) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(&self.salt);
+		match value {
+			Value::String(s) => hasher.update(s.as_bytes()),
+			other => hasher.update(JsonFormat::Canonical.serialize(other).as_bytes()),
+		}
+		hasher
+			.finalize()
+			.iter()
+			.map(|b| format!("{:02x}", b))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Anonymizer;
+	use crate::JsonPointer;
+	use serde_json::json;
+
+	#[test]
+	fn test_anonymize_fields() {
+		let fields = [
+			JsonPointer::new("/userId").unwrap(),
+			JsonPointer::new("/context/ip").unwrap(),
+			JsonPointer::new("/traits/age").unwrap(),
+			JsonPointer::new("/missing").unwrap(),
+		];
+		let anonymizer = Anonymizer::with_salt(&fields, b"salt".to_vec());
+		let mut event = json!({
+			"event": "login",
+			"userId": "u-1",
+			"context": {"ip": "10.0.0.1", "locale": "en-US"},
+			"traits": {"age": 42, "email": null},
+		});
+		assert!(anonymizer.anonymize(&mut event));
+
+		// SHA-256 of "salt" followed by "u-1"
+		assert_eq!(
+			event["userId"],
+			"036aa83a33e3d32388363322ca653511543392835563b114001df630f520103d"
+		);
+		assert_eq!(event["context"]["locale"], "en-US");
+		assert_eq!(event["traits"]["age"].as_str().unwrap().len(), 64);
+		assert!(event.get("missing").is_none());
+
+		// Same salt, same hash; different salt, different hash
+		let mut again = json!({"userId": "u-1"});
+		anonymizer.anonymize(&mut again);
+		assert_eq!(again["userId"], event["userId"]);
+		let mut other = json!({"userId": "u-1"});
+		Anonymizer::new(&fields).unwrap().anonymize(&mut other);
+		assert_ne!(other["userId"], event["userId"]);
+
+		assert!(!anonymizer.anonymize(&mut json!({"event": "no fields"})));
+	}
+}
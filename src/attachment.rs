@@ -0,0 +1,116 @@
+//! Binary attachments stored alongside queued events.
+//!
+//! An attachment (screenshot, minidump, ...) is appended together with the event that
+//! references it. The event gains an `_attachments` array describing each attachment,
+//! and the bytes are kept by the store until the event is removed.
+
+use crate::delivery::new_uuid;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, Result};
+use std::path::PathBuf;
+
+/// Key of the array of attachment references added to events.
+pub(crate) const ATTACHMENTS_KEY: &str = "_attachments";
+
+/// A binary payload to store alongside an event.
+///
+/// # Examples
+/// ```
+/// use transientdb::Attachment;
+///
+/// let attachment = Attachment {
+///     name: "screenshot.png".into(),
+///     content_type: "image/png".into(),
+///     data: vec![0x89, 0x50, 0x4e, 0x47],
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+	/// File name to use when uploading, e.g. in a multipart `filename` parameter.
+	pub name: String,
+	/// MIME type of the data.
+	pub content_type: String,
+	/// The raw bytes.
+	pub data: Vec<u8>,
+}
+
+/// Where the bytes of a fetched attachment live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentContent {
+	/// The bytes themselves (MemoryStore, WebStore).
+	Bytes(Vec<u8>),
+	/// A file holding the bytes (DirectoryStore), which can be streamed into an upload.
+	File(PathBuf),
+}
+
+impl AttachmentContent {
+	/// Reads the attachment bytes, loading them from disk if needed.
+	pub fn read(&self) -> Result<Vec<u8>> {
+		match self {
+			AttachmentContent::Bytes(bytes) => Ok(bytes.clone()),
+			AttachmentContent::File(path) => fs::read(path),
+		}
+	}
+}
+
+/// An attachment returned with a fetch result.
+///
+/// `id` matches the `id` in the owning event's `_attachments` array, so it can be used as
+/// the multipart part name for the server to correlate parts with events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentHandle {
+	/// Unique id of the attachment.
+	pub id: String,
+	/// File name given when the attachment was appended.
+	pub name: String,
+	/// MIME type given when the attachment was appended.
+	pub content_type: String,
+	/// The attachment bytes or the file holding them.
+	pub content: AttachmentContent,
+}
+
+/// Assigns ids to attachments and records references to them in the event.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the references.
+pub(crate) fn attach(
+	data: &mut Value,
+	attachments: Vec<Attachment>,
+) -> Result<Vec<(String, Attachment)>> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can carry attachments",
+		)
+	})?;
+
+	let attachments: Vec<(String, Attachment)> = attachments
+		.into_iter()
+		.map(|attachment| (new_uuid(), attachment))
+		.collect();
+
+	let references: Vec<Value> = attachments
+		.iter()
+		.map(|(id, attachment)| {
+			json!({
+				"id": id,
+				"name": attachment.name,
+				"contentType": attachment.content_type,
+				"size": attachment.data.len()
+			})
+		})
+		.collect();
+	object.insert(ATTACHMENTS_KEY.to_string(), Value::from(references));
+
+	Ok(attachments)
+}
+
+/// Returns the ids of attachments referenced by an event.
+pub(crate) fn referenced_ids(value: &Value) -> impl Iterator<Item = &str> {
+	value
+		.get(ATTACHMENTS_KEY)
+		.and_then(Value::as_array)
+		.into_iter()
+		.flatten()
+		.filter_map(|reference| reference.get("id").and_then(Value::as_str))
+}
@@ -0,0 +1,168 @@
+//! Discovering which optional parts of the [`DataStore`](crate::DataStore) trait a store
+//! implements.
+
+/// An optional capability of a [`DataStore`](crate::DataStore), from
+/// [`supports()`](crate::DataStore::supports).
+///
+/// Each names trait methods whose default implementation fails with
+/// `ErrorKind::Unsupported`, so code can check for a capability up front instead of
+/// calling and handling the error. New capabilities are added along with the methods
+/// that provide them, so matches on this enum need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Capability {
+	/// `append_with_attachments`.
+	Attachments,
+	/// `append_pinned`.
+	Pinning,
+	/// `append_for`, queueing events for other write keys.
+	Routing,
+	/// `append_delayed`.
+	Delayed,
+	/// `upsert`.
+	Upsert,
+	/// `reserve` and `commit_reserved`.
+	Reservation,
+	/// `mark_delivered`.
+	Delivery,
+	/// `enqueue_times`.
+	EnqueueTimes,
+	/// `pending_size`.
+	PendingSize,
+	/// `preview_fetch`, peeking at the next batch.
+	Preview,
+	/// `pending_events`.
+	PendingEvents,
+	/// `set_allowed_categories` and `purge_revoked`.
+	Consent,
+	/// `anonymize`. Requires the `anonymize` feature.
+	#[cfg(feature = "anonymize")]
+	Anonymize,
+	/// `drop_report`.
+	DropReport,
+	/// `begin_move`, `discard_move`: being the source of `move_events`.
+	MoveSource,
+	/// `stage_move`, `release_move`: being the destination of `move_events`.
+	MoveDestination,
+	/// `recently_removed` and `replay`.
+	RemovedBatches,
+	/// `write_batch_to`.
+	WriteBatchTo,
+	/// `fetch_older_than`.
+	FetchOlderThan,
+	/// `status`.
+	Status,
+	/// `expire_older_than`.
+	Expire,
+}
+
+impl Capability {
+	/// Every capability, in declaration order.
+	pub const ALL: &'static [Capability] = &[
+		Capability::Attachments,
+		Capability::Pinning,
+		Capability::Routing,
+		Capability::Delayed,
+		Capability::Upsert,
+		Capability::Reservation,
+		Capability::Delivery,
+		Capability::EnqueueTimes,
+		Capability::PendingSize,
+		Capability::Preview,
+		Capability::PendingEvents,
+		Capability::Consent,
+		#[cfg(feature = "anonymize")]
+		Capability::Anonymize,
+		Capability::DropReport,
+		Capability::MoveSource,
+		Capability::MoveDestination,
+		Capability::RemovedBatches,
+		Capability::WriteBatchTo,
+		Capability::FetchOlderThan,
+		Capability::Status,
+		Capability::Expire,
+	];
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[cfg(feature = "anonymize")]
+	use crate::Anonymizer;
+	use crate::{
+		DataStore, DataStoreExt, DirectoryConfig, DirectoryContentStore, DirectoryStore,
+		MemoryConfig, MemoryStore,
+	};
+	use chrono::Utc;
+	use serde_json::{json, Value};
+	use std::io::{ErrorKind, Result};
+	use std::time::Duration;
+	use tempfile::TempDir;
+
+	/// Whether calling the methods of a capability gets past `Unsupported`
+	fn works<S: DataStoreExt<Output = Value>>(store: &mut S, capability: Capability) -> bool {
+		let result: Result<()> = match capability {
+			Capability::Attachments => store.append_with_attachments(json!({}), Vec::new()),
+			Capability::Pinning => store.append_pinned(json!({})),
+			Capability::Routing => store.append_for("other-key", json!({})),
+			Capability::Delayed => store.append_delayed(json!({}), Utc::now()),
+			Capability::Upsert => store.upsert("key", json!({})),
+			Capability::Reservation => store
+				.reserve(16)
+				.and_then(|()| store.commit_reserved(json!({}), 16)),
+			Capability::Delivery => store.mark_delivered("batch"),
+			Capability::EnqueueTimes => store.enqueue_times().map(drop),
+			Capability::PendingSize => store.pending_size().map(drop),
+			Capability::Preview => store.preview_fetch(None, None).map(drop),
+			Capability::PendingEvents => store.pending_events().map(drop),
+			Capability::Consent => store.set_allowed_categories(None),
+			#[cfg(feature = "anonymize")]
+			Capability::Anonymize => store.anonymize(&Anonymizer::with_salt(&[], "")).map(drop),
+			Capability::DropReport => store.drop_report().map(drop),
+			Capability::MoveSource => store.begin_move("move", 0).map(drop),
+			Capability::MoveDestination => store.stage_move("move", Vec::new()),
+			Capability::RemovedBatches => store.recently_removed().map(drop),
+			Capability::WriteBatchTo => store.write_batch_to(&mut Vec::new(), None, None).map(drop),
+			Capability::FetchOlderThan => store.fetch_older_than(Duration::ZERO).map(drop),
+			Capability::Status => store.status().map(drop),
+			Capability::Expire => store.expire_older_than(Duration::from_secs(3600)).map(drop),
+		};
+		!matches!(result, Err(e) if e.kind() == ErrorKind::Unsupported)
+	}
+
+	fn assert_honest<S: DataStoreExt<Output = Value>>(store: &mut S) {
+		for &capability in Capability::ALL {
+			assert_eq!(
+				store.supports(capability),
+				works(store, capability),
+				"{:?}",
+				capability
+			);
+		}
+	}
+
+	#[test]
+	fn test_stores_report_what_they_support() -> Result<()> {
+		assert_honest(&mut MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		}));
+
+		let temp_dir = TempDir::new()?;
+		let store = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		})?;
+		assert!(!store.supports(Capability::Pinning));
+		assert!(store.supports(Capability::MoveDestination));
+		assert!(!store.supports(Capability::MoveSource));
+
+		let mut store = DirectoryContentStore::new(store);
+		assert_honest(&mut store);
+		assert!(store.supports(Capability::WriteBatchTo));
+		Ok(())
+	}
+}
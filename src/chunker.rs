@@ -0,0 +1,264 @@
+use crate::attachment::{self, AttachmentHandle};
+use crate::sized::serialized_len;
+use crate::{DataResult, Equivalent};
+use serde_json::{Map, Value};
+
+/// Splits fetched batch envelopes into smaller envelopes under a size cap.
+///
+/// Useful when an ingestion endpoint limits request size below what a single fetch returns.
+/// Each chunk is a complete envelope carrying the same metadata as the original, plus:
+/// - `batchId` - a per-chunk id (`{parent}-{index}`) usable as an idempotency key
+/// - `parentBatchId` - the `batch_id` of the original fetch result
+/// - `chunkIndex` / `chunkCount` - the chunk's position in the parent batch
+///
+/// Chunks can be acknowledged independently with
+/// [`TransientDB::remove_chunks`](crate::TransientDB::remove_chunks), so a failed chunk stays
+/// queued while the delivered ones are removed.
+///
+/// # Examples
+/// ```
+/// use transientdb::{BatchChunker, MemoryConfig, MemoryStore, TransientDB};
+/// use serde_json::json;
+///
+/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 4096,
+/// }));
+/// for i in 0..20 {
+///     db.append(json!({"index": i, "padding": "x".repeat(50)})).unwrap();
+/// }
+///
+/// let result = db.fetch(None, None).unwrap().unwrap();
+/// let chunked = BatchChunker::new(500).split(result);
+/// assert!(chunked.chunks.len() > 1);
+///
+/// // Suppose only the first chunk was delivered
+/// db.remove_chunks(&chunked, &[0]).unwrap();
+/// assert!(db.has_data());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct BatchChunker {
+	max_chunk_bytes: usize,
+}
+
+/// A fetch result split into chunks by a [`BatchChunker`].
+#[derive(Debug)]
+pub struct ChunkedBatch {
+	/// The `batch_id` of the fetch result the chunks came from.
+	pub parent_batch_id: Option<String>,
+	/// The chunks, in the order of the original batch.
+	pub chunks: Vec<BatchChunk>,
+}
+
+/// A single chunk of a [`ChunkedBatch`].
+#[derive(Debug)]
+pub struct BatchChunk {
+	/// Position of this chunk in the parent batch.
+	pub index: usize,
+	/// The chunk's envelope, ready to upload.
+	pub envelope: Value,
+	/// Attachments referenced by events in this chunk.
+	pub attachments: Vec<AttachmentHandle>,
+	/// Items to pass to `remove()` once this chunk is delivered.
+	pub removable: Vec<Box<dyn Equivalent>>,
+}
+
+impl BatchChunker {
+	/// Creates a chunker producing envelopes of at most `max_chunk_bytes` serialized bytes.
+	///
+	/// An event that doesn't fit within the cap on its own is placed alone in its own
+	/// chunk, which will exceed the cap.
+	pub fn new(max_chunk_bytes: usize) -> Self {
+		Self { max_chunk_bytes }
+	}
+
+	/// Splits a fetched envelope into chunks.
+	///
+	/// Expects the `{"batch": [...], ...}` envelope produced by MemoryStore and WebStore,
+	/// whose removable items line up with the batch entries. Anything else is returned
+	/// as a single chunk.
+	pub fn split(&self, result: DataResult<Value>) -> ChunkedBatch {
+		let parent_batch_id = result.batch_id;
+		let mut removable = result.removable.unwrap_or_default();
+		let attachments = result.attachments.unwrap_or_default();
+
+		let Some(Value::Object(mut envelope)) = result.data else {
+			return ChunkedBatch {
+				parent_batch_id,
+				chunks: Vec::new(),
+			};
+		};
+
+		let items = match envelope.remove("batch") {
+			Some(Value::Array(items)) if items.len() == removable.len() => items,
+			other => {
+				if let Some(batch) = other {
+					envelope.insert("batch".to_string(), batch);
+				}
+				return ChunkedBatch {
+					parent_batch_id,
+					chunks: vec![BatchChunk {
+						index: 0,
+						envelope: Value::Object(envelope),
+						attachments,
+						removable,
+					}],
+				};
+			}
+		};
+
+		// Size of the envelope without any items, including the chunk fields
+		let overhead = serialized_len(&Self::chunk_envelope(
+			&envelope,
+			Vec::new(),
+			&parent_batch_id,
+			usize::MAX,
+			usize::MAX,
+		));
+
+		let mut groups: Vec<Vec<Value>> = Vec::new();
+		let mut current: Vec<Value> = Vec::new();
+		let mut current_size = overhead;
+		for item in items {
+			let item_size = serialized_len(&item);
+			// Items after the first are preceded by a comma
+			if !current.is_empty() && current_size + 1 + item_size > self.max_chunk_bytes {
+				groups.push(std::mem::take(&mut current));
+				current_size = overhead;
+			}
+			current_size += item_size + usize::from(!current.is_empty());
+			current.push(item);
+		}
+		if !current.is_empty() {
+			groups.push(current);
+		}
+
+		let count = groups.len();
+		let chunks = groups
+			.into_iter()
+			.enumerate()
+			.map(|(index, items)| {
+				let chunk_removable: Vec<Box<dyn Equivalent>> =
+					removable.drain(..items.len()).collect();
+				let chunk_attachments: Vec<AttachmentHandle> = items
+					.iter()
+					.flat_map(attachment::referenced_ids)
+					.filter_map(|id| attachments.iter().find(|a| a.id == id).cloned())
+					.collect();
+				BatchChunk {
+					index,
+					envelope: Self::chunk_envelope(
+						&envelope,
+						items,
+						&parent_batch_id,
+						index,
+						count,
+					),
+					attachments: chunk_attachments,
+					removable: chunk_removable,
+				}
+			})
+			.collect();
+
+		ChunkedBatch {
+			parent_batch_id,
+			chunks,
+		}
+	}
+
+	fn chunk_envelope(
+		envelope: &Map<String, Value>,
+		items: Vec<Value>,
+		parent_batch_id: &Option<String>,
+		index: usize,
+		count: usize,
+	) -> Value {
+		let mut chunk = envelope.clone();
+		chunk.insert("batch".to_string(), Value::Array(items));
+		if let Some(parent) = parent_batch_id {
+			chunk.insert(
+				"batchId".to_string(),
+				Value::from(format!("{}-{}", parent, index)),
+			);
+			chunk.insert("parentBatchId".to_string(), Value::from(parent.as_str()));
+		}
+		chunk.insert("chunkIndex".to_string(), Value::from(index));
+		chunk.insert("chunkCount".to_string(), Value::from(count));
+		Value::Object(chunk)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::BatchChunker;
+	use crate::{DataStore, MemoryConfig, MemoryStore};
+	use serde_json::{json, Value};
+	use std::io::Result;
+
+	#[test]
+	fn test_split_respects_size_cap() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 10_000,
+		});
+		for i in 0..30 {
+			store.append(json!({"index": i, "padding": "x".repeat(40)}))?;
+		}
+
+		let result = store.fetch(None, None)?.unwrap();
+		let parent = result.batch_id.clone().unwrap();
+		let chunked = BatchChunker::new(400).split(result);
+
+		assert_eq!(chunked.parent_batch_id.as_deref(), Some(parent.as_str()));
+		assert!(chunked.chunks.len() > 1);
+
+		let mut next_index = 0;
+		for chunk in &chunked.chunks {
+			assert!(chunk.envelope.to_string().len() <= 400, "Chunk exceeds cap");
+			assert_eq!(chunk.envelope["parentBatchId"], parent.as_str());
+			assert_eq!(chunk.envelope["chunkCount"], chunked.chunks.len());
+			assert_eq!(chunk.envelope["writeKey"], "test-key");
+
+			// Events stay in order and line up with their removables
+			let items = chunk.envelope["batch"].as_array().unwrap();
+			assert_eq!(items.len(), chunk.removable.len());
+			for (item, removable) in items.iter().zip(&chunk.removable) {
+				assert_eq!(item["index"], next_index);
+				assert!(removable.equals(item));
+				next_index += 1;
+			}
+		}
+		assert_eq!(next_index, 30);
+
+		// Acknowledge every chunk except the last one
+		let last = chunked.chunks.len() - 1;
+		for chunk in &chunked.chunks[..last] {
+			store.remove(&chunk.removable)?;
+		}
+		let remaining = store.fetch(None, None)?.unwrap().data.unwrap();
+		let remaining: &Vec<Value> = remaining["batch"].as_array().unwrap();
+		assert_eq!(remaining.len(), chunked.chunks[last].removable.len());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_oversized_item_gets_own_chunk() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 10_000,
+		});
+		store.append(json!({"small": 1}))?;
+		store.append(json!({"large": "x".repeat(1000)}))?;
+		store.append(json!({"small": 2}))?;
+
+		let chunked = BatchChunker::new(300).split(store.fetch(None, None)?.unwrap());
+		assert_eq!(chunked.chunks.len(), 3);
+		assert_eq!(chunked.chunks[1].removable.len(), 1);
+
+		Ok(())
+	}
+}
@@ -0,0 +1,62 @@
+//! Consent categories for queued events.
+//!
+//! An event appended with a consent category (e.g. `analytics`, `advertising`,
+//! `functional`) carries it in a `_consent` field. Stores can be told which categories
+//! the user currently consents to, and then only fetch events in those categories.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{self, Result};
+
+/// Key of the consent category added to events.
+pub(crate) const CONSENT_KEY: &str = "_consent";
+
+/// Records the consent category an event belongs to.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the category.
+pub(crate) fn tag(data: &mut Value, category: &str) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can carry a consent category",
+		)
+	})?;
+	object.insert(CONSENT_KEY.to_string(), Value::from(category));
+	Ok(())
+}
+
+/// The consent categories a store may fetch events from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConsentFilter {
+	/// `None` allows every category
+	allowed: Option<HashSet<String>>,
+}
+
+impl ConsentFilter {
+	pub(crate) fn set(&mut self, allowed: Option<HashSet<String>>) {
+		self.allowed = allowed;
+	}
+
+	/// Whether an event may be fetched. Events without a category always may.
+	pub(crate) fn allows(&self, event: &Value) -> bool {
+		let Some(allowed) = &self.allowed else {
+			return true;
+		};
+		match event.get(CONSENT_KEY).and_then(Value::as_str) {
+			Some(category) => allowed.contains(category),
+			None => true,
+		}
+	}
+
+	/// Describes the filter for `debug_config`.
+	pub(crate) fn to_json(&self) -> Value {
+		match &self.allowed {
+			Some(allowed) => {
+				let mut categories: Vec<&String> = allowed.iter().collect();
+				categories.sort();
+				Value::from(categories.into_iter().cloned().collect::<Vec<_>>())
+			}
+			None => Value::Null,
+		}
+	}
+}
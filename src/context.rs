@@ -0,0 +1,59 @@
+//! Shared context added to fetched batch envelopes.
+
+use serde_json::Value;
+
+/// Supplies the `context` object a store adds to each batch envelope it fetches, set
+/// with `set_context_provider`.
+///
+/// Details that are the same for every event, like the device model, OS, locale or app
+/// version, can then be sent once per batch instead of with each event. The provider
+/// is asked at fetch time, so the context describes the client as it is when the batch
+/// is sent. Returning `Value::Null` leaves the field out.
+///
+/// Implemented for closures returning a `Value`.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{DataStore, MemoryConfig, MemoryStore};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "my-key".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// });
+/// store.set_context_provider(|| json!({"app": {"version": "2.4.1"}, "locale": "en-US"}));
+///
+/// store.append(json!({"event": "page_view"}))?;
+/// let envelope = store.fetch(None, None)?.unwrap().data.unwrap();
+/// assert_eq!(envelope["context"]["locale"], "en-US");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait ContextProvider: Send + Sync {
+	/// Returns the context for a batch being fetched.
+	fn context(&self) -> Value;
+}
+
+impl<F> ContextProvider for F
+where
+	F: Fn() -> Value + Send + Sync,
+{
+	fn context(&self) -> Value {
+		self()
+	}
+}
+
+/// Adds the provider's context to an envelope, unless there's no provider or it has
+/// nothing to add.
+pub(crate) fn add_to(envelope: &mut Value, provider: Option<&dyn ContextProvider>) {
+	let Some(provider) = provider else {
+		return;
+	};
+	let context = provider.context();
+	if context.is_null() {
+		return;
+	}
+	if let Some(fields) = envelope.as_object_mut() {
+		fields.insert("context".to_string(), context);
+	}
+}
@@ -0,0 +1,144 @@
+//! Redacted debug dumps for support bundles.
+//!
+//! Event values are replaced by their type and a short hash, so a dump shows the
+//! shape of what's queued (and whether two values are equal) without revealing it.
+//! Hashes are keyed with a random salt that is never written out, so values can't be
+//! recovered by hashing guesses, and they only compare equal within one dump.
+
+use crate::hash::{Hasher, XxHash64Hasher};
+use crate::QueueStats;
+use serde_json::{json, Map, Value};
+use std::io::{self, Result};
+
+/// Config keys whose values are hashed in dumps. Paths can contain user names.
+const SECRET_KEYS: &[&str] = &["writeKey", "storageLocation", "stagingLocation"];
+
+/// Replaces values with salted hashes, for one dump.
+pub(crate) struct Redactor {
+	salt: [u8; 16],
+}
+
+impl Redactor {
+	/// Creates a redactor with a new random salt.
+	pub(crate) fn new() -> Result<Self> {
+		let mut salt = [0u8; 16];
+		getrandom::getrandom(&mut salt)
+			.map_err(|e| io::Error::other(format!("Failed to generate salt: {}", e)))?;
+		Ok(Self { salt })
+	}
+
+	/// XXH64 of the salt followed by `bytes`, as lowercase hex
+	fn hash(&self, bytes: &[u8]) -> String {
+		let mut salted = Vec::with_capacity(self.salt.len() + bytes.len());
+		salted.extend_from_slice(&self.salt);
+		salted.extend_from_slice(bytes);
+		XxHash64Hasher.hash(&salted)
+	}
+
+	/// Replaces every string and number in `value` with its type and hash, keeping
+	/// object keys, array lengths, booleans and nulls.
+	pub(crate) fn skeleton(&self, value: &Value) -> Value {
+		match value {
+			Value::String(s) => Value::String(format!("string:{}", self.hash(s.as_bytes()))),
+			Value::Number(n) => {
+				Value::String(format!("number:{}", self.hash(n.to_string().as_bytes())))
+			}
+			Value::Array(items) => {
+				Value::Array(items.iter().map(|item| self.skeleton(item)).collect())
+			}
+			Value::Object(map) => Value::Object(
+				map.iter()
+					.map(|(key, value)| (key.clone(), self.skeleton(value)))
+					.collect(),
+			),
+			Value::Bool(_) | Value::Null => value.clone(),
+		}
+	}
+
+	/// Hashes the values of secret keys in a store's config.
+	pub(crate) fn redact_config(&self, config: Value) -> Value {
+		match config {
+			Value::Object(map) => Value::Object(
+				map.into_iter()
+					.map(|(key, value)| {
+						let value = if SECRET_KEYS.contains(&key.as_str()) {
+							self.skeleton(&value)
+						} else {
+							value
+						};
+						(key, value)
+					})
+					.collect::<Map<String, Value>>(),
+			),
+			other => other,
+		}
+	}
+}
+
+pub(crate) fn stats_json(stats: &QueueStats) -> Value {
+	json!({
+		"pendingItems": stats.pending.map(|p| p.items),
+		"pendingBytes": stats.pending.map(|p| p.bytes),
+		"oldestAgeSeconds": stats.oldest_age.map(|age| age.as_secs_f64()),
+		"appended": stats.appended,
+		"appendErrors": stats.append_errors,
+		"fetched": stats.fetched,
+		"fetchErrors": stats.fetch_errors,
+		"removed": stats.removed,
+		"removeErrors": stats.remove_errors,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_skeleton_hides_values() {
+		let event = json!({
+			"event": "purchase",
+			"properties": {"email": "someone@example.com", "total": 42.5, "items": ["a", "b"]},
+			"consent": true,
+			"referrer": null,
+		});
+		let redactor = Redactor::new().unwrap();
+		let redacted = redactor.skeleton(&event);
+		let text = redacted.to_string();
+
+		assert!(!text.contains("purchase"));
+		assert!(!text.contains("someone@example.com"));
+		assert!(!text.contains("42.5"));
+		assert_eq!(redacted["properties"]["items"].as_array().unwrap().len(), 2);
+		assert!(redacted["event"].as_str().unwrap().starts_with("string:"));
+		assert!(redacted["properties"]["total"]
+			.as_str()
+			.unwrap()
+			.starts_with("number:"));
+		assert_eq!(redacted["consent"], true);
+		assert_eq!(redacted["referrer"], Value::Null);
+		// Equal values hash equally, so duplicates are still recognizable
+		assert_eq!(redactor.skeleton(&event), redacted);
+
+		let config = redactor.redact_config(json!({
+			"writeKey": "secret",
+			"storageLocation": "/home/someone/queue",
+			"stagingLocation": "/home/someone/staging",
+			"maxItems": 10,
+		}));
+		assert!(!config.to_string().contains("secret"));
+		assert!(!config.to_string().contains("someone"));
+		assert_eq!(config["maxItems"], 10);
+	}
+
+	#[test]
+	fn test_skeleton_hashes_are_salted() {
+		let value = json!("purchase");
+		let redacted = Redactor::new().unwrap().skeleton(&value);
+
+		assert_ne!(
+			redacted,
+			json!(format!("string:{}", XxHash64Hasher.hash(b"purchase")))
+		);
+		assert_ne!(Redactor::new().unwrap().skeleton(&value), redacted);
+	}
+}
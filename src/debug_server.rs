@@ -0,0 +1,385 @@
+//! Local HTTP endpoint for inspecting a store during testing.
+
+use crate::debug;
+use crate::TransientDB;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Lets QA inspect a TransientDB's pending queue from a browser while testing a build.
+///
+/// Serves, from a background thread:
+/// - `GET /`: a page with the stats and links to the other endpoints
+/// - `GET /stats`: the [`stats`](TransientDB::stats) as JSON
+/// - `GET /events?limit=N`: the first `N` (by default 100) pending events as a JSON
+///   array, from [`snapshot`](TransientDB::snapshot)
+/// - `POST /purge`: [`reset`](TransientDB::reset)s the store, deleting every pending event
+///
+/// Every request must carry the server's random [`token`](Self::token) as a `token`
+/// query parameter, as in the [`url`](Self::url) to open. Requests whose `Host` isn't the
+/// listener's address (or `localhost` on its port), and `POST`s from another origin, are
+/// rejected, so web pages open in the same browser can't purge the queue or read events
+/// through DNS rebinding. Events are still served unredacted, so bind the listener to a
+/// loopback address and don't ship the `debug-server` feature in release builds.
+///
+/// Requires the `debug-server` feature.
+///
+/// # Examples
+/// ```
+/// use std::net::TcpListener;
+/// use std::sync::Arc;
+/// use transientdb::{DebugServer, MemoryConfig, MemoryStore, TransientDB};
+///
+/// let db = Arc::new(TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// })));
+///
+/// let listener = TcpListener::bind("127.0.0.1:0")?;
+/// let server = DebugServer::new(db)?;
+/// println!("Inspect the queue at {}", server.url(listener.local_addr()?));
+/// server.serve(listener);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct DebugServer<T> {
+	db: Arc<TransientDB<T>>,
+	token: String,
+}
+
+impl<T> Clone for DebugServer<T> {
+	fn clone(&self) -> Self {
+		Self {
+			db: self.db.clone(),
+			token: self.token.clone(),
+		}
+	}
+}
+
+impl<T> DebugServer<T> {
+	/// Number of events `/events` returns without a `limit`.
+	pub const DEFAULT_LIMIT: usize = 100;
+
+	/// Creates a server for the given database, with a new random token.
+	///
+	/// # Errors
+	/// Fails if the platform has no source of randomness.
+	pub fn new(db: Arc<TransientDB<T>>) -> Result<Self> {
+		let mut bytes = [0u8; 16];
+		getrandom::getrandom(&mut bytes)
+			.map_err(|e| io::Error::other(format!("Failed to generate token: {}", e)))?;
+		let token = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+		Ok(Self { db, token })
+	}
+
+	/// The token every request must carry as its `token` query parameter.
+	pub fn token(&self) -> &str {
+		&self.token
+	}
+
+	/// The address of the server's page when serving on `addr`, including the token.
+	pub fn url(&self, addr: SocketAddr) -> String {
+		format!("http://{}/?token={}", addr, self.token)
+	}
+
+	/// Checks a request against the token and, given the listener's address, its `Host`
+	/// and `Origin` headers, returning the response rejecting it, if any.
+	fn reject(
+		&self,
+		addr: Option<SocketAddr>,
+		method: &str,
+		target: &str,
+		host: Option<&str>,
+		origin: Option<&str>,
+	) -> Option<(&'static str, &'static str, String)> {
+		let forbidden = |reason: &str| {
+			Some((
+				"403 Forbidden",
+				"text/plain; charset=utf-8",
+				format!("Forbidden: {}\n", reason),
+			))
+		};
+
+		// Another name resolving to the loopback address is a DNS rebinding attempt
+		let allowed_host = |host: &str| {
+			addr.is_some_and(|addr| {
+				host == addr.to_string()
+					|| (addr.ip().is_loopback() && host == format!("localhost:{}", addr.port()))
+			})
+		};
+		let Some(host) = host.filter(|host| allowed_host(host)) else {
+			return forbidden("unexpected Host");
+		};
+
+		// Forms on other sites can post here without a CORS preflight
+		if method == "POST" && origin.is_some_and(|origin| origin != format!("http://{}", host)) {
+			return forbidden("cross-origin request");
+		}
+
+		let query = target.split_once('?').map_or("", |(_, query)| query);
+		let token = query
+			.split('&')
+			.find_map(|pair| pair.strip_prefix("token="));
+		if token != Some(self.token.as_str()) {
+			return forbidden("missing or wrong token");
+		}
+		None
+	}
+
+	/// Handles a request, returning the status, content type and body of the response.
+	fn handle(&self, method: &str, target: &str) -> (&'static str, &'static str, String) {
+		let (path, query) = target.split_once('?').unwrap_or((target, ""));
+		match (method, path) {
+			("GET", "/") => ("200 OK", "text/html; charset=utf-8", self.index()),
+			("GET", "/stats") => json_response(&debug::stats_json(&self.db.stats())),
+			("GET", "/events") => {
+				let limit = query
+					.split('&')
+					.find_map(|pair| pair.strip_prefix("limit="))
+					.and_then(|limit| limit.parse().ok())
+					.unwrap_or(Self::DEFAULT_LIMIT);
+				match self.db.snapshot() {
+					Ok(mut events) => {
+						events.truncate(limit);
+						json_response(&Value::from(events))
+					}
+					Err(e) => (
+						"500 Internal Server Error",
+						"text/plain; charset=utf-8",
+						format!("{}\n", e),
+					),
+				}
+			}
+			("POST", "/purge") => {
+				self.db.reset();
+				// Send browsers back to the page, which now shows the empty queue
+				("303 See Other", "text/plain; charset=utf-8", String::new())
+			}
+			(_, "/" | "/stats" | "/events" | "/purge") => (
+				"405 Method Not Allowed",
+				"text/plain; charset=utf-8",
+				String::from("Method Not Allowed\n"),
+			),
+			_ => (
+				"404 Not Found",
+				"text/plain; charset=utf-8",
+				String::from("Not Found\n"),
+			),
+		}
+	}
+
+	fn index(&self) -> String {
+		let stats =
+			serde_json::to_string_pretty(&debug::stats_json(&self.db.stats())).unwrap_or_default();
+		format!(
+			"<!DOCTYPE html>\n<html><head><title>TransientDB</title></head><body>\n\
+			 <h1>TransientDB</h1>\n<pre>{}</pre>\n\
+			 <p><a href=\"/events?token={token}\">Pending events</a> | <a href=\"/stats?token={token}\">Stats as JSON</a></p>\n\
+			 <form method=\"post\" action=\"/purge?token={token}\" onsubmit=\"return confirm('Delete every pending event?')\">\
+			 <button>Purge</button></form>\n</body></html>\n",
+			stats,
+			token = self.token
+		)
+	}
+}
+
+impl<T: 'static> DebugServer<T> {
+	/// Serves the endpoints on the given listener from a background thread.
+	///
+	/// The thread runs until the listener fails.
+	pub fn serve(self, listener: TcpListener) -> JoinHandle<()> {
+		// Without it no Host is accepted
+		let addr = listener.local_addr().ok();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					// A misbehaving client only affects its own request.
+					Ok(stream) => {
+						let _ = self.respond(stream, addr);
+					}
+					Err(_) => continue,
+				}
+			}
+		})
+	}
+
+	fn respond(&self, mut stream: TcpStream, addr: Option<SocketAddr>) -> Result<()> {
+		stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+		let mut reader = BufReader::new(stream.try_clone()?);
+		let mut request_line = String::new();
+		reader.read_line(&mut request_line)?;
+		// Read the headers, which also keeps the client from being reset before reading
+		// the response.
+		let (mut host, mut origin) = (None, None);
+		let mut header = String::new();
+		while reader.read_line(&mut header)? > 2 {
+			if let Some((name, value)) = header.split_once(':') {
+				let value = Some(value.trim().to_string());
+				if name.eq_ignore_ascii_case("host") {
+					host = value;
+				} else if name.eq_ignore_ascii_case("origin") {
+					origin = value;
+				}
+			}
+			header.clear();
+		}
+
+		let mut parts = request_line.split_whitespace();
+		let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+		let (status, content_type, body) = self
+			.reject(addr, method, target, host.as_deref(), origin.as_deref())
+			.unwrap_or_else(|| self.handle(method, target));
+		let location = if status.starts_with("303") {
+			format!("Location: /?token={}\r\n", self.token)
+		} else {
+			String::new()
+		};
+		write!(
+			stream,
+			"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+			status,
+			content_type,
+			body.len(),
+			location,
+			body
+		)?;
+		stream.flush()
+	}
+}
+
+fn json_response(value: &Value) -> (&'static str, &'static str, String) {
+	let body = serde_json::to_string_pretty(value).unwrap_or_default();
+	("200 OK", "application/json", body)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{MemoryConfig, MemoryStore};
+	use serde_json::json;
+	use std::io::Read;
+	use std::net::Shutdown;
+
+	fn serve_db() -> (Arc<TransientDB<Value>>, SocketAddr, String) {
+		let db = Arc::new(TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test".into(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		})));
+		for i in 0..3 {
+			db.append(json!({"event": "tap", "n": i})).unwrap();
+		}
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = DebugServer::new(db.clone()).unwrap();
+		let token = server.token().to_string();
+		server.serve(listener);
+		(db, addr, token)
+	}
+
+	fn send(addr: SocketAddr, method: &str, target: &str, headers: &str) -> String {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		write!(stream, "{} {} HTTP/1.1\r\n{}\r\n", method, target, headers).unwrap();
+		stream.shutdown(Shutdown::Write).unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		response
+	}
+
+	#[test]
+	fn test_stats_events_and_purge() {
+		let (db, addr, token) = serve_db();
+		let request = |method: &str, path: &str| {
+			let separator = if path.contains('?') { '&' } else { '?' };
+			let target = format!("{}{}token={}", path, separator, token);
+			send(addr, method, &target, &format!("Host: {}\r\n", addr))
+		};
+		let body = |response: &str| -> Value {
+			serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+		};
+
+		let page = request("GET", "/");
+		assert!(page.starts_with("HTTP/1.1 200 OK"));
+		assert!(page.contains("\"pendingItems\": 3"));
+		assert!(page.contains(&format!("action=\"/purge?token={}\"", token)));
+
+		assert_eq!(body(&request("GET", "/stats"))["appended"], 3);
+		let events = body(&request("GET", "/events?limit=2"));
+		assert_eq!(
+			events,
+			json!([{"event": "tap", "n": 0}, {"event": "tap", "n": 1}])
+		);
+
+		assert!(request("GET", "/purge").starts_with("HTTP/1.1 405"));
+		assert!(db.has_data());
+		let purged = request("POST", "/purge");
+		assert!(purged.starts_with("HTTP/1.1 303"));
+		assert!(purged.contains(&format!("Location: /?token={}\r\n", token)));
+		assert!(!db.has_data());
+		assert_eq!(body(&request("GET", "/events")), json!([]));
+
+		assert!(request("GET", "/other").starts_with("HTTP/1.1 404"));
+	}
+
+	#[test]
+	fn test_url_includes_token() {
+		let (_, addr, token) = serve_db();
+		let page = send(
+			addr,
+			"GET",
+			&format!("/?token={}", token),
+			&format!("Host: localhost:{}\r\n", addr.port()),
+		);
+		assert!(page.starts_with("HTTP/1.1 200 OK"));
+		assert_eq!(token.len(), 32);
+		assert_ne!(serve_db().2, token);
+	}
+
+	#[test]
+	fn test_rejects_missing_or_wrong_token() {
+		let (db, addr, token) = serve_db();
+		let host = format!("Host: {}\r\n", addr);
+		for target in [
+			"/events",
+			"/events?token=",
+			"/events?token=0123456789abcdef",
+		] {
+			assert!(send(addr, "GET", target, &host).starts_with("HTTP/1.1 403"));
+		}
+		assert!(send(addr, "POST", "/purge", &host).starts_with("HTTP/1.1 403"));
+		assert!(db.has_data());
+		let target = format!("/events?token={}x", token);
+		assert!(send(addr, "GET", &target, &host).starts_with("HTTP/1.1 403"));
+	}
+
+	#[test]
+	fn test_rejects_unexpected_host() {
+		let (db, addr, token) = serve_db();
+		let events = format!("/events?token={}", token);
+		let purge = format!("/purge?token={}", token);
+		let rebound = format!("Host: attacker.example:{}\r\n", addr.port());
+		assert!(send(addr, "GET", &events, &rebound).starts_with("HTTP/1.1 403"));
+		assert!(send(addr, "GET", &events, "").starts_with("HTTP/1.1 403"));
+		assert!(send(addr, "POST", &purge, &rebound).starts_with("HTTP/1.1 403"));
+		assert!(db.has_data());
+	}
+
+	#[test]
+	fn test_rejects_cross_origin_post() {
+		let (db, addr, token) = serve_db();
+		let purge = format!("/purge?token={}", token);
+		for origin in ["http://attacker.example", "null"] {
+			let headers = format!("Host: {}\r\nOrigin: {}\r\n", addr, origin);
+			assert!(send(addr, "POST", &purge, &headers).starts_with("HTTP/1.1 403"));
+		}
+		assert!(db.has_data());
+
+		let headers = format!("Host: {}\r\nOrigin: http://{}\r\n", addr, addr);
+		assert!(send(addr, "POST", &purge, &headers).starts_with("HTTP/1.1 303"));
+		assert!(!db.has_data());
+	}
+}
@@ -0,0 +1,45 @@
+//! Events that aren't fetched before a given time.
+//!
+//! An event appended with `append_delayed` carries the time it becomes visible in a
+//! `_notBefore` field, as RFC 3339, so stores persist it along with the event.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::io::{self, Result};
+
+/// Key of the visibility time added to events.
+pub(crate) const NOT_BEFORE_KEY: &str = "_notBefore";
+
+/// Records the time before which an event mustn't be fetched.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the time.
+pub(crate) fn tag(data: &mut Value, not_before: DateTime<Utc>) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can be delayed",
+		)
+	})?;
+	object.insert(
+		NOT_BEFORE_KEY.to_string(),
+		Value::from(not_before.to_rfc3339()),
+	);
+	Ok(())
+}
+
+/// Whether an event carries a visibility time.
+pub(crate) fn is_delayed(event: &Value) -> bool {
+	event.get(NOT_BEFORE_KEY).is_some()
+}
+
+/// Whether an event may be fetched at `now`. Events without a valid time always may.
+pub(crate) fn is_due(event: &Value, now: DateTime<Utc>) -> bool {
+	let not_before = event
+		.get(NOT_BEFORE_KEY)
+		.and_then(Value::as_str)
+		.and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+	match not_before {
+		Some(not_before) => not_before <= now,
+		None => true,
+	}
+}
@@ -0,0 +1,225 @@
+//! Batch identifiers and delivered-batch tracking.
+//!
+//! Every fetch result carries a `batch_id` that uploaders can send along as an
+//! idempotency key. Fetching the same items again before they're removed returns the
+//! same id, so a retried upload carries the key of the first attempt. Once a batch has
+//! been accepted by the server, the uploader marks it delivered; stores remember a
+//! bounded number of delivered ids and ignore any later attempt to re-add an envelope
+//! carrying one of them.
+
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Generates a random (version 4) UUID string, used to identify batches and attachments.
+pub(crate) fn new_uuid() -> String {
+	let mut bytes = [0u8; 16];
+	if getrandom::getrandom(&mut bytes).is_err() {
+		// No entropy source available - fall back to time and a process-wide counter,
+		// which is still unique within this process.
+		static COUNTER: AtomicU64 = AtomicU64::new(0);
+		let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+		let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+		bytes[..8].copy_from_slice(&nanos.to_be_bytes());
+		bytes[8..].copy_from_slice(&count.to_be_bytes());
+	}
+
+	// Set version (4) and variant (RFC 4122) bits
+	bytes[6] = (bytes[6] & 0x0f) | 0x40;
+	bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+	let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+	format!(
+		"{}-{}-{}-{}-{}",
+		&hex[0..8],
+		&hex[8..12],
+		&hex[12..16],
+		&hex[16..20],
+		&hex[20..32]
+	)
+}
+
+/// A bounded, insertion-ordered set of batch ids that have been delivered.
+///
+/// Once `capacity` ids are held, recording a new id forgets the oldest one.
+#[derive(Debug, Clone)]
+pub(crate) struct DeliveredBatches {
+	order: VecDeque<String>,
+	ids: HashSet<String>,
+	capacity: usize,
+}
+
+impl DeliveredBatches {
+	/// Number of delivered batch ids remembered by default.
+	pub(crate) const DEFAULT_CAPACITY: usize = 1000;
+
+	pub(crate) fn new(capacity: usize) -> Self {
+		Self {
+			order: VecDeque::new(),
+			ids: HashSet::new(),
+			capacity: capacity.max(1),
+		}
+	}
+
+	/// Restores a set from a JSON array of ids, as produced by [`to_json`](Self::to_json).
+	/// Anything that isn't a string is ignored.
+	pub(crate) fn from_json(value: &Value, capacity: usize) -> Self {
+		let mut delivered = Self::new(capacity);
+		if let Some(ids) = value.as_array() {
+			for id in ids.iter().filter_map(Value::as_str) {
+				delivered.insert(id);
+			}
+		}
+		delivered
+	}
+
+	pub(crate) fn to_json(&self) -> Value {
+		Value::from(self.order.iter().cloned().collect::<Vec<_>>())
+	}
+
+	/// Records a delivered batch id. Returns false if it was already known.
+	pub(crate) fn insert(&mut self, batch_id: &str) -> bool {
+		if self.ids.contains(batch_id) {
+			return false;
+		}
+		self.order.push_back(batch_id.to_string());
+		self.ids.insert(batch_id.to_string());
+
+		while self.order.len() > self.capacity {
+			if let Some(oldest) = self.order.pop_front() {
+				self.ids.remove(&oldest);
+			}
+		}
+		true
+	}
+
+	pub(crate) fn contains(&self, batch_id: &str) -> bool {
+		self.ids.contains(batch_id)
+	}
+
+	/// Checks whether a value being appended is an envelope from an already-delivered batch.
+	///
+	/// Only whole envelopes are recognized, by their `batchId`. Events re-added one by one
+	/// carry no batch id, so they're queued again even if their batch was delivered.
+	pub(crate) fn is_redelivery(&self, value: &Value) -> bool {
+		value
+			.get("batchId")
+			.and_then(Value::as_str)
+			.is_some_and(|id| self.contains(id))
+	}
+}
+
+/// Ids of recent fetches whose items haven't been removed, so fetching the same items
+/// again returns the same id.
+///
+/// Each batch is identified by keys for its items, in order, compared by the store.
+/// Only the last [`CAPACITY`](Self::CAPACITY) batches are remembered.
+#[derive(Debug, Clone)]
+pub(crate) struct InFlightBatches<K> {
+	batches: VecDeque<(String, Vec<K>)>,
+}
+
+impl<K> Default for InFlightBatches<K> {
+	fn default() -> Self {
+		Self {
+			batches: VecDeque::new(),
+		}
+	}
+}
+
+impl<K> InFlightBatches<K> {
+	/// Number of in-flight batches remembered.
+	pub(crate) const CAPACITY: usize = 16;
+
+	/// Id of the batch of the items with `keys`: that of an earlier fetch of the same
+	/// items, going by `same`, or a new one.
+	pub(crate) fn id_for(&mut self, keys: Vec<K>, same: impl Fn(&K, &K) -> bool) -> String {
+		let earlier = self.batches.iter().find(|(_, batch)| {
+			batch.len() == keys.len() && batch.iter().zip(&keys).all(|(a, b)| same(a, b))
+		});
+		if let Some((id, _)) = earlier {
+			return id.clone();
+		}
+		let id = new_uuid();
+		self.insert(id.clone(), keys);
+		id
+	}
+
+	/// Remembers a batch, forgetting the oldest if over capacity.
+	pub(crate) fn insert(&mut self, batch_id: String, keys: Vec<K>) {
+		self.batches.push_back((batch_id, keys));
+		while self.batches.len() > Self::CAPACITY {
+			self.batches.pop_front();
+		}
+	}
+
+	/// Forgets the batches holding an item matching `removed`, as their items can't all
+	/// be fetched again.
+	pub(crate) fn forget(&mut self, removed: impl Fn(&K) -> bool) {
+		self.batches.retain(|(_, keys)| !keys.iter().any(&removed));
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.batches.clear();
+	}
+
+	/// The remembered batches, oldest first.
+	#[cfg(all(feature = "web", target_arch = "wasm32"))]
+	pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &[K])> {
+		self.batches
+			.iter()
+			.map(|(id, keys)| (id.as_str(), keys.as_slice()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{new_uuid, DeliveredBatches, InFlightBatches};
+	use serde_json::json;
+
+	#[test]
+	fn test_batch_ids_are_unique_uuids() {
+		let a = new_uuid();
+		let b = new_uuid();
+		assert_ne!(a, b);
+		assert_eq!(a.len(), 36);
+		assert_eq!(a.chars().nth(14), Some('4'), "Should be a version 4 UUID");
+	}
+
+	#[test]
+	fn test_delivered_batches_are_bounded() {
+		let mut delivered = DeliveredBatches::new(2);
+		assert!(delivered.insert("a"));
+		assert!(!delivered.insert("a"));
+		delivered.insert("b");
+		delivered.insert("c");
+
+		assert!(!delivered.contains("a"), "Oldest id should be forgotten");
+		assert!(delivered.contains("b"));
+		assert!(delivered.contains("c"));
+
+		let restored = DeliveredBatches::from_json(&delivered.to_json(), 2);
+		assert!(restored.contains("b") && restored.contains("c"));
+		assert!(restored.is_redelivery(&json!({"batchId": "c", "batch": []})));
+		assert!(!restored.is_redelivery(&json!({"event": "c"})));
+	}
+
+	#[test]
+	fn test_refetched_items_keep_their_batch_id() {
+		let mut in_flight = InFlightBatches::default();
+		let first = in_flight.id_for(vec![1, 2], |a, b| a == b);
+		assert_eq!(in_flight.id_for(vec![1, 2], |a, b| a == b), first);
+		// A batch with more (or other) items is a different batch
+		let second = in_flight.id_for(vec![1, 2, 3], |a, b| a == b);
+		assert_ne!(second, first);
+
+		in_flight.forget(|key| *key == 3);
+		assert_eq!(in_flight.id_for(vec![1, 2], |a, b| a == b), first);
+		assert_ne!(in_flight.id_for(vec![1, 2, 3], |a, b| a == b), second);
+
+		for key in 0..InFlightBatches::<u32>::CAPACITY as u32 {
+			in_flight.id_for(vec![key + 10], |a, b| a == b);
+		}
+		assert_ne!(in_flight.id_for(vec![1, 2], |a, b| a == b), first);
+	}
+}
@@ -0,0 +1,110 @@
+//! Browser console bindings for inspecting a queue, including in production builds.
+
+use crate::debug;
+use crate::TransientDB;
+use serde_json::Value;
+use std::io::{Error, Result};
+use std::ops::Deref;
+use wasm_bindgen::prelude::*;
+
+/// Name of the global the bindings are installed as.
+const GLOBAL: &str = "__transientdb";
+
+/// Number of events `browse()` returns without a limit.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Exposes a TransientDB on `window.__transientdb`, so engineers can inspect the queue
+/// from the browser console:
+/// - `__transientdb.stats()`: the [`stats`](TransientDB::stats) as an object
+/// - `__transientdb.browse(limit)`: the first `limit` (by default 100) pending events,
+///   from [`snapshot`](TransientDB::snapshot)
+/// - `__transientdb.purge()`: [`reset`](TransientDB::reset)s the store, deleting every
+///   pending event
+///
+/// Events are returned unredacted, so call this only when the user or an engineer asks
+/// for it, e.g. behind a query parameter or a localStorage flag, rather than on every
+/// page load. Installing again replaces the previous bindings.
+///
+/// `db` is shared with the bindings: an `Rc<TransientDB>`, or an `Arc` in threaded
+/// builds.
+///
+/// Requires the `devtools` feature.
+///
+/// # Errors
+/// Fails outside a window, e.g. in a worker, or if the global can't be set.
+///
+/// # Examples
+/// ```no_run
+/// use std::rc::Rc;
+/// use transientdb::{install_devtools, TransientDB, WebConfig, WebStore};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let store = WebStore::new(WebConfig {
+///     write_key: "my-key".into(),
+///     database_name: "events".into(),
+///     max_items: 1000,
+///     max_fetch_size: 1024 * 1024,
+/// })
+/// .await;
+/// let db = Rc::new(TransientDB::new(store));
+///
+/// let flagged = web_sys::window()
+///     .and_then(|window| window.local_storage().ok().flatten())
+///     .and_then(|storage| storage.get_item("transientdb-devtools").ok().flatten())
+///     .is_some();
+/// if flagged {
+///     install_devtools(db.clone())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn install_devtools<T, P>(db: P) -> Result<()>
+where
+	T: 'static,
+	P: Deref<Target = TransientDB<T>> + Clone + 'static,
+{
+	let window = web_sys::window().ok_or_else(|| Error::other("No window object"))?;
+	let bindings = js_sys::Object::new();
+
+	let stats_db = db.clone();
+	let stats = Closure::<dyn Fn() -> std::result::Result<JsValue, JsValue>>::new(move || {
+		to_js(&debug::stats_json(&stats_db.stats()))
+	});
+
+	let browse_db = db.clone();
+	let browse = Closure::<dyn Fn(JsValue) -> std::result::Result<JsValue, JsValue>>::new(
+		move |limit: JsValue| {
+			let limit = limit
+				.as_f64()
+				.filter(|limit| *limit >= 0.0)
+				.map_or(DEFAULT_LIMIT, |limit| limit as usize);
+			let mut events = browse_db
+				.snapshot()
+				.map_err(|e| JsValue::from_str(&e.to_string()))?;
+			events.truncate(limit);
+			to_js(&Value::from(events))
+		},
+	);
+
+	let purge = Closure::<dyn Fn()>::new(move || db.reset());
+
+	for (name, function) in [
+		("stats", stats.into_js_value()),
+		("browse", browse.into_js_value()),
+		("purge", purge.into_js_value()),
+	] {
+		set(&bindings, name, &function)?;
+	}
+	set(&window, GLOBAL, &bindings)
+}
+
+/// Converts JSON to a plain JS value, as `JSON.parse` would produce it.
+fn to_js(value: &Value) -> std::result::Result<JsValue, JsValue> {
+	js_sys::JSON::parse(&value.to_string())
+}
+
+fn set(target: &JsValue, name: &str, value: &JsValue) -> Result<()> {
+	js_sys::Reflect::set(target, &JsValue::from_str(name), value)
+		.map_err(|_| Error::other(format!("Failed to set {}", name)))?;
+	Ok(())
+}
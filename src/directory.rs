@@ -1,11 +1,35 @@
-use crate::{DataResult, DataStore, Equivalent};
-use chrono::Utc;
-use serde_json::Value;
+use crate::attachment::{self, Attachment, AttachmentContent, AttachmentHandle};
+use crate::consent::ConsentFilter;
+use crate::context;
+use crate::delivery::{new_uuid, DeliveredBatches, InFlightBatches};
+use crate::drops::{DropLog, DropReason, EvictionNotifier};
+use crate::envelope;
+use crate::field_filter::FieldFilter;
+use crate::fs::{Fs, FsWriter, MappedFile, ReadOnlyFs, StdFs, SystemClock, TimeSource};
+use crate::logging;
+use crate::platform;
+use crate::received;
+use crate::removed::{FetchedBatches, RemovedRing};
+use crate::replay;
+use crate::schema::SchemaMigrations;
+use crate::skew;
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	BatchPreview, Capability, ClockOffsetProvider, ContextProvider, DataResult, DataStore,
+	DataStoreExt, DropRecord, Equivalent, EvictionListener, Hasher, ImportReport, JsonFormat,
+	PendingSize, RemovedBatch, StoreStatus, TransientError, XxHash64Hasher,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{json, Value};
 use std::any::Any;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufWriter, Result, Write};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, IoSlice, Result, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 impl Equivalent for PathBuf {
 	fn equals(&self, other: &dyn Equivalent) -> bool {
@@ -21,6 +45,32 @@ impl Equivalent for PathBuf {
 	}
 }
 
+/// Identifies a single event within a finalized data file.
+///
+/// Returned by [`DirectoryStore::item_removables`] so individual events can be removed
+/// from a fetched file while the rest of the file stays queued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileItem {
+	/// Path of the data file holding the event.
+	pub path: PathBuf,
+	/// Position of the event in the file's `batch` array.
+	pub index: usize,
+}
+
+impl Equivalent for FileItem {
+	fn equals(&self, other: &dyn Equivalent) -> bool {
+		if let Some(other_item) = other.as_any().downcast_ref::<FileItem>() {
+			self == other_item
+		} else {
+			false
+		}
+	}
+
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
 /// Configuration options for the file-based data store.
 ///
 /// This struct provides the configuration parameters needed to create a new DirectoryStore instance.
@@ -56,6 +106,95 @@ pub struct DirectoryConfig {
 	pub max_file_size: usize,
 }
 
+/// A finalized data file, as listed by [`DirectoryStore::list_batches`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchInfo {
+	/// Index of the file, which [`DirectoryStore::fetch_batch`] and
+	/// [`DirectoryStore::remove_batch`] take.
+	pub id: String,
+	/// Path of the data file.
+	pub path: PathBuf,
+	/// Number of events in the file.
+	pub items: usize,
+	/// Size of the file in bytes.
+	pub bytes: u64,
+	/// When the file was created, if the file system records it.
+	pub created: Option<DateTime<Utc>>,
+}
+
+/// What a [`DirectoryStore`] does when a write would dip into its disk reserve.
+///
+/// See [`DirectoryStore::set_disk_reserve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiskFullPolicy {
+	/// Reject the write with [`TransientError::DiskFull`].
+	#[default]
+	Reject,
+	/// Delete the oldest finalized files until the write fits, then reject it with
+	/// [`TransientError::DiskFull`] if it still doesn't.
+	EvictOldest,
+}
+
+/// When a [`DirectoryStore`] hands appended events to the file being written.
+///
+/// See [`DirectoryStore::set_flush_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+	/// Write every event as it is appended, so it is on disk if the process crashes.
+	#[default]
+	EveryAppend,
+	/// Hold appended events in memory until this many bytes are waiting, then write them
+	/// together. Events still held are lost if the process crashes.
+	Buffered(usize),
+}
+
+/// Which finalized files a [`DirectoryStore`] fetches first.
+///
+/// See [`DirectoryStore::set_fetch_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchOrder {
+	/// In the order files were started, which is the order their events were appended.
+	#[default]
+	FileOrder,
+	/// By the time of the oldest event in each file, going by its `timestamp` (or
+	/// received-at field), so imported or compacted files holding older events are sent
+	/// before newer ones whatever their position.
+	OldestFirstAcrossFiles,
+}
+
+/// How [`DirectoryStore::open`] treats the storage location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenMode {
+	/// Create the storage location if it's missing. What [`DirectoryStore::new`] does.
+	#[default]
+	Create,
+	/// Fail with `NotFound` if the storage location is missing.
+	OpenExisting,
+	/// Fail with `NotFound` if the storage location is missing, and leave everything in
+	/// it as found: files a crash left unfinished aren't recovered, and anything that
+	/// would append, remove or rewrite fails with [`TransientError::ReadOnly`]. Fetching
+	/// and browsing work, for inspectors and forensic tooling.
+	ReadOnly,
+}
+
+/// Keeps a [`DirectoryStore`] from finalizing or deleting files while held, returned by
+/// [`DirectoryStore::quiesce`].
+///
+/// The guard doesn't borrow the store, so it can be held while the store is used
+/// elsewhere (e.g. inside a [`TransientDB`](crate::TransientDB)) or sent to a thread
+/// doing the backup. Dropping it lets the store finalize and delete files again.
+#[must_use = "the store resumes as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct QuiesceGuard {
+	holders: Arc<AtomicUsize>,
+}
+
+impl Drop for QuiesceGuard {
+	fn drop(&mut self) {
+		self.holders.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
 /// Type alias for the file validator function
 pub type FileValidator = Box<dyn Fn(&Path) -> Result<()> + Send + Sync>;
 
@@ -69,17 +208,189 @@ pub type FileValidator = Box<dyn Fn(&Path) -> Result<()> + Send + Sync>;
 /// - A `batch` array containing the stored items
 /// - A `sentAt` timestamp in RFC3339 format
 /// - The store's `writeKey`
+///
+/// Ids of delivered batches are kept in a hidden `.{base_filename}-delivered.json`
+/// file alongside the data files, so they survive restarts.
+///
+/// The version of the file format is kept in a hidden `.{base_filename}-format.json`
+/// manifest. Opening a directory written in a newer format (by a later release of this
+/// crate) fails with [`TransientError::IncompatibleFormat`] instead of misreading it.
+///
+/// Attachments are written as hidden sibling files named after the data file holding
+/// their event (`.{index}-{base_filename}.{id}.attachment`, plus a `.json` file with
+/// their name and content type), and are deleted together with that data file.
+///
+/// All file access goes through an [`Fs`], the real filesystem unless the store is
+/// created with [`with_fs`](Self::with_fs).
 pub struct DirectoryStore {
 	config: DirectoryConfig,
-	writer: Option<BufWriter<File>>,
+	fs: Arc<dyn Fs>,
+	clock: Arc<dyn TimeSource>,
+	writer: Option<FsWriter>,
+	/// Events appended but not yet written to `writer`, with their separators
+	buffer: Vec<u8>,
+	flush_policy: FlushPolicy,
+	fetch_order: FetchOrder,
+	/// Reused buffer appended events are serialized into
+	scratch: Vec<u8>,
+	scratch_capacity: usize,
 	current_size: usize,
 	current_path: Option<PathBuf>,
+	/// Where files are written until they're finalized, if not `storage_location`
+	staging_location: Option<PathBuf>,
 	file_validator: Option<FileValidator>,
 	next_index: AtomicU32,
+	delivered: DeliveredBatches,
+	/// Ids of fetched batches not yet removed, by file name, without a delivery cursor
+	in_flight: InFlightBatches<String>,
+	content_addressed: bool,
+	/// Names and deduplicates files when `content_addressed` is set
+	hasher: Box<dyn Hasher>,
+	json_format: JsonFormat,
+	disk_reserve: Option<u64>,
+	disk_full_policy: DiskFullPolicy,
+	/// Notified with the events of files evicted on a full disk, if set
+	eviction_listener: Option<EvictionNotifier>,
+	accept_external: bool,
+	#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+	watcher: Option<notify::RecommendedWatcher>,
+	cursor: Option<DeliveryCursor>,
+	field_filter: FieldFilter,
+	migrations: Option<SchemaMigrations>,
+	/// Field stamped with the time on events appended without a timestamp
+	received_at: Option<String>,
+	/// Estimated bytes of reserved events not yet committed
+	reserved_bytes: usize,
+	/// How many removed batches to retain for debugging
+	retain_removed: usize,
+	/// Ids of recent fetches, while removed batches are retained
+	fetched: FetchedBatches,
+	/// Number of live [`QuiesceGuard`]s
+	quiesced: Arc<AtomicUsize>,
+	/// Opened with [`OpenMode::ReadOnly`]
+	read_only: bool,
+}
+
+/// What's left of an unfinished data file after repairing it on open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repaired {
+	/// It held no complete event and was deleted
+	Removed,
+	/// It still needs its trailer
+	Unfinished,
+	/// It already holds a complete envelope and only needs renaming
+	Complete,
+}
+
+/// Drain progress persisted by a DirectoryStore with a delivery cursor enabled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct DeliveryCursor {
+	/// Index of the newest data file known to be delivered. Files up to and including
+	/// it are removed instead of being fetched again.
+	delivered_through: Option<u32>,
+	/// Batch id and file names of the most recent fetch, so fetching the same files
+	/// again (e.g. after a crash mid-upload) reuses the same batch id.
+	in_flight: Option<(String, Vec<String>)>,
+}
+
+impl DeliveryCursor {
+	fn from_json(value: &Value) -> Self {
+		let in_flight = value.get("inFlight").and_then(|in_flight| {
+			let batch_id = in_flight.get("batchId")?.as_str()?.to_string();
+			let files = in_flight
+				.get("files")?
+				.as_array()?
+				.iter()
+				.filter_map(|f| f.as_str().map(str::to_string))
+				.collect();
+			Some((batch_id, files))
+		});
+		Self {
+			delivered_through: value
+				.get("deliveredThrough")
+				.and_then(Value::as_u64)
+				.and_then(|index| u32::try_from(index).ok()),
+			in_flight,
+		}
+	}
+
+	fn to_json(&self) -> Value {
+		serde_json::json!({
+			"deliveredThrough": self.delivered_through,
+			"inFlight": self.in_flight.as_ref().map(|(batch_id, files)| {
+				serde_json::json!({"batchId": batch_id, "files": files})
+			}),
+		})
+	}
+}
+
+/// Writes all of `parts` with as few vectored writes as the writer allows.
+fn write_all_vectored(writer: &mut dyn Write, mut parts: &[&[u8]]) -> Result<()> {
+	// Bytes of parts[0] already written
+	let mut offset = 0;
+	while !parts.is_empty() {
+		let slices: Vec<IoSlice> = parts
+			.iter()
+			.enumerate()
+			.map(|(i, part)| IoSlice::new(if i == 0 { &part[offset..] } else { part }))
+			.collect();
+		let mut written = match writer.write_vectored(&slices) {
+			Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+			Ok(written) => written + offset,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		};
+		offset = 0;
+		while let Some((first, rest)) = parts.split_first() {
+			if written < first.len() {
+				offset = written;
+				break;
+			}
+			written -= first.len();
+			parts = rest;
+		}
+	}
+	Ok(())
+}
+
+/// The name of a file, or "" if it has none or it isn't UTF-8
+fn file_name(path: &Path) -> &str {
+	path.file_name()
+		.and_then(|n| n.to_str())
+		.unwrap_or_default()
+}
+
+/// A [`Hasher`] output usable in a file name, hex-encoded (after an `x`, so an empty
+/// one isn't) unless it's already non-empty and ASCII alphanumeric
+fn file_hash(hash: String) -> String {
+	if !hash.is_empty() && hash.bytes().all(|b| b.is_ascii_alphanumeric()) {
+		return hash;
+	}
+	let hex: String = hash.bytes().map(|b| format!("{:02x}", b)).collect();
+	format!("x{}", hex)
+}
+
+/// Parses an RFC 3339 time, as stamped in files and events
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+	DateTime::parse_from_rfc3339(time)
+		.ok()
+		.map(|time| time.with_timezone(&Utc))
 }
 
 impl DirectoryStore {
 	const TEMP_EXTENSION: &'static str = "temp";
+	const ATTACHMENT_EXTENSION: &'static str = "attachment";
+	const REJECTED_EXTENSION: &'static str = "rejected";
+	/// Extension of hidden files holding events moved in until the move completes
+	const STAGED_EXTENSION: &'static str = "staged";
+	/// Marks a directory as a cache, see <https://bford.info/cachedir/>
+	const CACHEDIR_TAG: &'static str = "CACHEDIR.TAG";
+	const HEADER: &'static [u8] = b"{ \"batch\": [";
+	const DEFAULT_SCRATCH_CAPACITY: usize = 16 * 1024;
+
+	/// Version of the file format, recorded in a hidden `.{base_filename}-format.json`
+	/// manifest. Stores refuse to open directories written in a newer format.
+	pub const FORMAT_VERSION: u64 = 1;
 
 	/// Creates a new DirectoryStore with the specified configuration.
 	///
@@ -93,28 +404,166 @@ impl DirectoryStore {
 	/// Returns an IO error if:
 	/// - The storage directory cannot be created
 	/// - The directory cannot be read when scanning for existing files
+	/// - On Windows, the storage location or base filename contains a reserved device name
 	///
 	/// # Panics
 	/// * If max_file_size is less than 100 bytes
 	pub fn new(config: DirectoryConfig) -> Result<Self> {
+		Self::with_fs(config, Arc::new(StdFs), Arc::new(SystemClock))
+	}
+
+	/// Opens a DirectoryStore in the given mode, e.g. [`OpenMode::ReadOnly`] to inspect a
+	/// store without changing it.
+	///
+	/// # Errors
+	/// Same as [`new`](Self::new), and `NotFound` if the storage location is missing and
+	/// `mode` isn't [`OpenMode::Create`].
+	///
+	/// # Panics
+	/// * If max_file_size is less than 100 bytes
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DataStoreExt, DirectoryConfig, DirectoryStore, OpenMode, TransientError};
+	///
+	/// let config = DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-read-only"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// };
+	/// let mut store = DirectoryStore::open(config.clone(), OpenMode::Create)?;
+	/// store.reset();
+	/// store.append(json!({"event": "login"}))?;
+	/// drop(store);
+	///
+	/// let mut store = DirectoryStore::open(config, OpenMode::ReadOnly)?;
+	/// assert_eq!(store.pending_events()?, [json!({"event": "login"})]);
+	/// let err = store.append(json!({"event": "logout"})).unwrap_err();
+	/// assert_eq!(TransientError::from_io(&err), Some(&TransientError::ReadOnly));
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn open(config: DirectoryConfig, mode: OpenMode) -> Result<Self> {
+		Self::open_with_fs(config, mode, Arc::new(StdFs), Arc::new(SystemClock))
+	}
+
+	/// Creates a DirectoryStore that does its file access through `fs` and reads the
+	/// time from `clock`.
+	///
+	/// Used with [`SimFs`](crate::SimFs) and [`SimClock`](crate::SimClock) to run the
+	/// store deterministically in memory, e.g. to test recovery from a crash at every
+	/// point of a workload. Paths returned by `fetch()` and passed to the file validator
+	/// then only exist within `fs`, attachments are returned as bytes, and
+	/// `restrict_permissions()` and `watch_external()` fail with `Unsupported`.
+	///
+	/// # Errors
+	/// Same as [`new`](Self::new).
+	///
+	/// # Panics
+	/// * If max_file_size is less than 100 bytes
+	pub fn with_fs(
+		config: DirectoryConfig,
+		fs: Arc<dyn Fs>,
+		clock: Arc<dyn TimeSource>,
+	) -> Result<Self> {
+		Self::open_with_fs(config, OpenMode::Create, fs, clock)
+	}
+
+	/// Opens a DirectoryStore in the given mode, doing its file access through `fs` and
+	/// reading the time from `clock`. See [`open`](Self::open) and
+	/// [`with_fs`](Self::with_fs).
+	///
+	/// # Errors
+	/// Same as [`open`](Self::open).
+	///
+	/// # Panics
+	/// * If max_file_size is less than 100 bytes
+	#[allow(clippy::panic)] // Invalid configuration is a programming error
+	pub fn open_with_fs(
+		mut config: DirectoryConfig,
+		mode: OpenMode,
+		fs: Arc<dyn Fs>,
+		clock: Arc<dyn TimeSource>,
+	) -> Result<Self> {
 		if config.max_file_size < 100 {
 			panic!("Seriously? max_file_size < 100 bytes? What exactly do you expect to store in there?");
 		}
 
-		fs::create_dir_all(&config.storage_location)?;
+		config.storage_location =
+			platform::prepare_location(&config.storage_location, &config.base_filename)?;
+		let fs: Arc<dyn Fs> = match mode {
+			OpenMode::Create => {
+				fs.create_dir_all(&config.storage_location)?;
+				fs
+			}
+			OpenMode::OpenExisting | OpenMode::ReadOnly => {
+				if let Err(e) = fs.read_dir(&config.storage_location) {
+					return Err(io::Error::new(
+						e.kind(),
+						format!(
+							"Can't open storage location {:?}: {}",
+							config.storage_location, e
+						),
+					));
+				}
+				if mode == OpenMode::ReadOnly {
+					Arc::new(ReadOnlyFs(fs))
+				} else {
+					fs
+				}
+			}
+		};
 
-		let store = DirectoryStore {
+		let mut store = DirectoryStore {
 			config,
+			fs,
+			clock,
 			writer: None,
+			buffer: Vec::new(),
+			flush_policy: FlushPolicy::default(),
+			fetch_order: FetchOrder::default(),
+			scratch: Vec::with_capacity(Self::DEFAULT_SCRATCH_CAPACITY),
+			scratch_capacity: Self::DEFAULT_SCRATCH_CAPACITY,
 			current_size: 0,
 			current_path: None,
+			staging_location: None,
 			file_validator: None,
 			next_index: AtomicU32::new(0),
+			delivered: DeliveredBatches::new(DeliveredBatches::DEFAULT_CAPACITY),
+			in_flight: InFlightBatches::default(),
+			content_addressed: false,
+			hasher: Box::new(XxHash64Hasher),
+			json_format: JsonFormat::default(),
+			disk_reserve: None,
+			disk_full_policy: DiskFullPolicy::default(),
+			eviction_listener: None,
+			accept_external: false,
+			#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+			watcher: None,
+			cursor: None,
+			field_filter: FieldFilter::default(),
+			migrations: None,
+			received_at: None,
+			reserved_bytes: 0,
+			retain_removed: 0,
+			fetched: FetchedBatches::default(),
+			quiesced: Arc::new(AtomicUsize::new(0)),
+			read_only: mode == OpenMode::ReadOnly,
 		};
 
-		// Initialize directory and get max index
-		let max_index = store.initialize_directory()?;
-		store.next_index.store(max_index + 1, Ordering::SeqCst);
+		// Don't touch data written by a newer version, recovery could mangle it
+		store.check_format()?;
+
+		// Recover the directory and continue its file indexes, unless nothing may change
+		if !store.read_only {
+			let location = store.config.storage_location.clone();
+			let max_index = store.initialize_directory(&location)?;
+			store.next_index.store(max_index + 1, Ordering::SeqCst);
+			store.remove_orphaned_attachments();
+		}
+		store.delivered = store.load_delivered();
 
 		Ok(store)
 	}
@@ -164,304 +613,5137 @@ impl DirectoryStore {
 		self.file_validator = Some(Box::new(validator));
 	}
 
-	fn next_index(&self) -> u32 {
-		self.next_index.fetch_add(1, Ordering::SeqCst)
+	/// Enables or disables content-addressed naming of finalized files.
+	///
+	/// When enabled, finalized files are named `{index}-{base_filename}-{hash}.temp`, where
+	/// `hash` is computed over the batched events (not the `sentAt` timestamp). A batch whose
+	/// events are identical to an already finalized file is discarded instead of finalized,
+	/// which keeps the same events from being uploaded twice when two instances end up
+	/// processing the same directory.
+	///
+	/// Files recovered during `new()` are finalized before this can be set, and keep plain names.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-dedup"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	///
+	/// store.set_content_addressed(true);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_content_addressed(&mut self, enabled: bool) {
+		self.content_addressed = enabled;
 	}
 
-	fn start_file_if_needed(&mut self) -> Result<bool> {
-		if self.writer.is_some() {
-			return Ok(false);
-		}
+	/// Sets the hash content-addressed files are named and deduplicated by, XXH64
+	/// ([`XxHash64Hasher`]) by default.
+	///
+	/// Use `Sha256Hasher`, with the `sha256` feature, where a deliberately crafted batch
+	/// must not be able to collide with another and get it dropped. Files already named by
+	/// a different hasher aren't recognized as duplicates of new ones.
+	///
+	/// # Examples
+	/// ```
+	/// # #[cfg(feature = "sha256")]
+	/// # {
+	/// use std::path::PathBuf;
+	/// use transientdb::{DirectoryConfig, DirectoryStore, Sha256Hasher};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-sha256"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	///
+	/// store.set_content_addressed(true);
+	/// store.set_hasher(Sha256Hasher);
+	/// # }
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_hasher<H: Hasher + 'static>(&mut self, hasher: H) {
+		self.hasher = Box::new(hasher);
+	}
 
-		let mut index = self.next_index();
-		let mut attempts = 0;
-		const MAX_ATTEMPTS: u32 = 1000; // Safeguard against infinite loops
+	/// Sets the JSON format used when writing events to data files.
+	///
+	/// Combine `Canonical` with [`set_content_addressed`](Self::set_content_addressed) so
+	/// events that differ only in key order are recognized as duplicates.
+	/// `Pretty` is handy for inspecting files by hand, at the cost of larger files.
+	pub fn set_json_format(&mut self, format: JsonFormat) {
+		self.json_format = format;
+	}
 
-		loop {
-			let file_path = self
-				.config
-				.storage_location
-				.join(format!("{}-{}", index, self.config.base_filename));
+	/// Sets fields to strip from events as they are appended, so they are never written
+	/// to disk.
+	///
+	/// Only affects events appended afterwards; files adopted with
+	/// [`set_accept_external`](Self::set_accept_external) are taken as they are.
+	pub fn set_field_filter(&mut self, filter: FieldFilter) {
+		self.field_filter = filter;
+	}
 
-			match OpenOptions::new()
-				.write(true)
-				.create_new(true)
-				.open(&file_path)
-			{
-				Ok(file) => {
-					let mut writer = BufWriter::new(file);
-					self.current_path = Some(file_path);
+	/// Sets the event schema version stamped on appended events.
+	///
+	/// Data files are migrated when they are fetched: any events stored with an older
+	/// version are upgraded and the file is rewritten before its path is returned.
+	pub fn set_migrations(&mut self, migrations: SchemaMigrations) {
+		self.migrations = Some(migrations);
+	}
 
-					if self.current_size == 0 {
-						writer.write_all(b"{ \"batch\": [")?;
-						self.current_size = "{ \"batch\": [".len();
-						self.writer = Some(writer);
-						return Ok(true);
-					}
+	/// Sets the field (e.g. `receivedAt`) stamped with the time an event is appended, on
+	/// events appended without a `timestamp` field, so they can still be ordered
+	/// downstream. `None` stops stamping.
+	///
+	/// Only affects events appended afterwards.
+	pub fn set_received_at_field(&mut self, field: Option<&str>) {
+		self.received_at = field.map(str::to_string);
+	}
 
-					self.writer = Some(writer);
-					return Ok(false);
-				}
-				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-					// File exists, try next index
-					index = self.next_index();
-					attempts += 1;
+	/// Checks whether a finalized file with the given content hash already exists
+	fn has_finalized_hash(&self, hash: &str) -> Result<bool> {
+		let suffix = format!("-{}.{}", hash, Self::TEMP_EXTENSION);
+		Ok(self
+			.fs
+			.read_dir(&self.config.storage_location)?
+			.iter()
+			.any(|e| file_name(&e.path).ends_with(&suffix)))
+	}
 
-					if attempts >= MAX_ATTEMPTS {
-						return Err(io::Error::other(
-							"Failed to find available index after maximum attempts",
-						));
-					}
-				}
-				Err(e) => return Err(e), // Other errors are propagated
-			}
-		}
+	fn delivered_log_path(&self) -> PathBuf {
+		self.config
+			.storage_location
+			.join(format!(".{}-delivered.json", self.config.base_filename))
 	}
 
-	/// Scans the directory for existing files, finalizes unfinished ones, and returns the highest index found
-	fn initialize_directory(&self) -> Result<u32> {
-		let entries = fs::read_dir(&self.config.storage_location)?;
-		let mut max_index = 0;
+	/// Path of the manifest recording the format of the store's files.
+	fn manifest_path(&self) -> PathBuf {
+		self.config
+			.storage_location
+			.join(format!(".{}-format.json", self.config.base_filename))
+	}
 
-		for entry in entries {
-			let entry = entry?;
-			let path = entry.path();
-
-			if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-				// Extract index from filename
-				if let Some(index_str) = file_name.split('-').next() {
-					if let Ok(index) = index_str.parse::<u32>() {
-						max_index = max_index.max(index);
-
-						// If file doesn't have .temp extension, it's unfinished
-						if path.extension().and_then(|ext| ext.to_str())
-							!= Some(Self::TEMP_EXTENSION)
-						{
-							// Attempt to finalize the file
-							if let Err(e) = self.finalize_file(&path) {
-								eprintln!("Failed to finalize file {:?}: {}", path, e);
-								// Continue processing other files even if this one fails
-							}
+	/// Fails with [`TransientError::IncompatibleFormat`] if the directory was written in
+	/// a newer format, and records the format in directories without a manifest.
+	fn check_format(&self) -> Result<()> {
+		let path = self.manifest_path();
+		match self.fs.read(&path) {
+			Ok(bytes) => {
+				let found = serde_json::from_slice::<Value>(&bytes)
+					.ok()
+					.and_then(|manifest| manifest.get("format")?.as_u64());
+				match found {
+					Some(found) if found > Self::FORMAT_VERSION => {
+						return Err(TransientError::IncompatibleFormat {
+							found,
+							supported: Self::FORMAT_VERSION,
 						}
+						.into());
 					}
+					Some(_) => return Ok(()),
+					// Torn by a crash while it was first written
+					None => logging::log_warn!("Rewriting unreadable manifest {:?}", path),
 				}
 			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(e),
 		}
-
-		Ok(max_index)
-	}
-
-	/// Finalizes a file by completing the JSON structure and renaming with .temp extension
-	fn finalize_file(&self, path: &Path) -> Result<()> {
-		{
-			let mut file = OpenOptions::new().append(true).open(path)?;
-			write!(
-				file,
-				"],\"sentAt\":\"{}\",\"writeKey\":\"{}\"}}",
-				Utc::now().format("%Y-%m-%dT%H:%M:%S.%3fZ"),
-				self.config.write_key
-			)?;
-			file.flush()?;
+		if self.read_only {
+			return Ok(());
 		}
 
-		// Run validation if configured
-		if let Some(validator) = &self.file_validator {
-			validator(path)?;
-		}
+		let manifest = json!({
+			"format": Self::FORMAT_VERSION,
+			"writer": concat!("transientdb ", env!("CARGO_PKG_VERSION")),
+		});
+		let tmp_path = path.with_extension("json.tmp");
+		self.fs.write(&tmp_path, manifest.to_string().as_bytes())?;
+		self.fs.rename(&tmp_path, &path)
+	}
 
-		// Rename to .temp to mark as complete
-		let new_path = path.with_extension(Self::TEMP_EXTENSION);
-		fs::rename(path, new_path)?;
+	/// Reads the delivered batch ids persisted by a previous instance.
+	/// A missing or unreadable log just means nothing is known to be delivered.
+	fn load_delivered(&self) -> DeliveredBatches {
+		self.fs
+			.read_to_string(&self.delivered_log_path())
+			.ok()
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|ids| DeliveredBatches::from_json(&ids, DeliveredBatches::DEFAULT_CAPACITY))
+			.unwrap_or_else(|| DeliveredBatches::new(DeliveredBatches::DEFAULT_CAPACITY))
+	}
 
-		Ok(())
+	fn drop_log_path(&self) -> PathBuf {
+		self.config
+			.storage_location
+			.join(format!(".{}-drops.json", self.config.base_filename))
 	}
 
-	fn finish_file(&mut self) -> Result<()> {
-		let writer = match self.writer.take() {
-			Some(mut writer) => {
-				writer.flush()?;
-				writer
-			}
-			None => return Ok(()),
-		};
+	/// Reads the drop log, which is empty if missing or unreadable.
+	fn load_drops(&self) -> DropLog {
+		self.fs
+			.read_to_string(&self.drop_log_path())
+			.ok()
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|records| DropLog::from_json(&records))
+			.unwrap_or_default()
+	}
 
-		// Drop the writer to close the file
-		drop(writer);
+	/// Adds a record to the drop log. Failing to write it is logged, not returned, so
+	/// the drop it records still goes ahead.
+	fn record_drop(
+		&self,
+		reason: DropReason,
+		count: usize,
+		first: DateTime<Utc>,
+		last: DateTime<Utc>,
+	) {
+		let mut drops = self.load_drops();
+		drops.record(reason, count, first, last);
 
-		// Use the stored path
-		if let Some(current_path) = self.current_path.take() {
-			self.finalize_file(&current_path)?;
+		let path = self.drop_log_path();
+		let tmp_path = path.with_extension("json.tmp");
+		let written = self
+			.fs
+			.write(&tmp_path, drops.to_json().to_string().as_bytes())
+			.and_then(|()| self.fs.rename(&tmp_path, &path));
+		if let Err(e) = written {
+			logging::log_warn!("Failed to write drop log {:?}: {}", path, e);
 		}
+	}
 
-		self.current_size = 0;
-		Ok(())
+	/// Envelope of a data file written whole, rather than appended to
+	fn batch_file_content(&self, events: Vec<Value>) -> Value {
+		json!({
+			"batch": events,
+			"sentAt": self.clock.now().format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string(),
+			"writeKey": self.config.write_key,
+		})
 	}
 
-	fn sorted_files(&self, include_unfinished: bool) -> Result<Vec<PathBuf>> {
-		let mut files: Vec<PathBuf> = fs::read_dir(&self.config.storage_location)?
-			.filter_map(Result::ok)
-			.map(|e| e.path())
-			.filter(|p| {
-				if include_unfinished {
-					true
-				} else {
-					p.extension().and_then(|ext| ext.to_str()) == Some(Self::TEMP_EXTENSION)
-				}
-			})
-			.collect();
+	/// Writes a whole data file, so it never appears half written
+	fn write_batch_file(&mut self, path: &Path, content: &Value) -> Result<()> {
+		let serialized = self.json_format.serialize(content);
+		self.ensure_space(serialized.len(), true)?;
 
-		files.sort_by(|a, b| {
-			a.file_name()
-				.unwrap_or_default()
-				.cmp(b.file_name().unwrap_or_default())
-		});
-		Ok(files)
+		let tmp_path = path.with_file_name(format!(".{}.rewrite", file_name(path)));
+		self.fs.write(&tmp_path, serialized.as_bytes())?;
+		self.fs.rename(&tmp_path, path)
 	}
 
-	fn up_to_size(&self, max_bytes: usize, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
-		let mut result = Vec::new();
-		let mut total_size: u64 = 0;
-
-		for file in files {
-			if let Ok(metadata) = fs::metadata(file) {
-				let size = metadata.len();
-				if total_size + size <= max_bytes as u64 {
-					result.push(file.clone());
-					total_size += size;
-				} else {
-					break;
-				}
-			}
-		}
+	fn removed_log_path(&self) -> PathBuf {
+		self.config
+			.storage_location
+			.join(format!(".{}-removed.json", self.config.base_filename))
+	}
 
-		Ok(result)
+	fn load_removed(&self) -> RemovedRing {
+		self.fs
+			.read_to_string(&self.removed_log_path())
+			.ok()
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|batches| RemovedRing::from_json(&batches, self.retain_removed))
+			.unwrap_or_else(|| RemovedRing::new(self.retain_removed))
 	}
-}
 
-impl DataStore for DirectoryStore {
-	type Output = Vec<PathBuf>;
+	/// Adds a batch to the retained removed batches. Failing to write them is logged,
+	/// not returned, since they're only for debugging.
+	fn retain_removed_batch(&self, batch_id: Option<String>, events: Vec<Value>) {
+		let mut removed = self.load_removed();
+		removed.push(batch_id, events, self.clock.now());
 
-	fn has_data(&self) -> bool {
-		// Check if we have an active writer with data
-		if self.writer.is_some() {
-			return true;
+		let path = self.removed_log_path();
+		let tmp_path = path.with_extension("json.tmp");
+		let written = self
+			.fs
+			.write(&tmp_path, removed.to_json().to_string().as_bytes())
+			.and_then(|()| self.fs.rename(&tmp_path, &path));
+		if let Err(e) = written {
+			logging::log_warn!("Failed to write removed batches {:?}: {}", path, e);
 		}
+	}
 
-		// Check directory for any files matching our base filename pattern
-		fs::read_dir(&self.config.storage_location)
-			.map(|entries| {
-				entries.filter_map(Result::ok).any(|e| {
-					let path = e.path();
-					if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-						// Check if filename starts with a number and contains our base_filename
-						let is_our_file = file_name
-							.split('-')
-							.next()
-							.and_then(|s| s.parse::<u32>().ok())
-							.is_some() && file_name
-							.contains(&self.config.base_filename);
-						return is_our_file;
-					}
-					false
-				})
-			})
-			.unwrap_or(false)
-	}
-
-	fn reset(&mut self) {
-		if let Ok(files) = self.sorted_files(true) {
-			let _ = self.remove(
-				&files
-					.iter()
-					.map(|p| Box::new(p.clone()) as Box<dyn Equivalent>)
-					.collect::<Vec<_>>(),
-			);
+	/// Path of the hidden file holding the events staged by a move, see
+	/// [`stage_move`](DataStoreExt::stage_move)
+	fn staged_path(&self, move_id: &str) -> Result<PathBuf> {
+		if move_id.is_empty()
+			|| !move_id
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || c == '-')
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("Invalid move id {:?}", move_id),
+			));
 		}
+		Ok(self.config.storage_location.join(format!(
+			".{}.{}.{}",
+			move_id,
+			self.config.base_filename,
+			Self::STAGED_EXTENSION
+		)))
 	}
 
-	fn append(&mut self, data: Value) -> Result<()> {
-		let started = self.start_file_if_needed()?;
-		let writer = self
-			.writer
-			.as_mut()
-			.ok_or_else(|| io::Error::other("No active writer"))?;
+	/// Writes the delivered batch ids via a temporary file so a crash can't leave a torn log.
+	fn save_delivered(&self) -> Result<()> {
+		let path = self.delivered_log_path();
+		let tmp_path = path.with_extension("json.tmp");
+		self.fs
+			.write(&tmp_path, self.delivered.to_json().to_string().as_bytes())?;
+		self.fs.rename(&tmp_path, &path)
+	}
 
-		if self.current_size >= self.config.max_file_size {
-			self.finish_file()?;
-			return self.append(data);
+	/// Enables or disables a persisted delivery cursor, making interrupted drains resumable.
+	///
+	/// When enabled, the store records in a hidden `.{base_filename}-cursor.json` file
+	/// which files the latest fetch returned and, once `mark_delivered()` is called for
+	/// that batch, that they were delivered. After a restart:
+	/// - files already marked delivered but not yet removed are removed by the next
+	///   `fetch()` instead of being sent again
+	/// - fetching the same files that were in flight returns the same `batch_id`, so a
+	///   server deduplicating on it can drop the repeat
+	///
+	/// To benefit, call `mark_delivered()` before `remove()` once a batch is accepted, as
+	/// [`TransientDB::drain_into`](crate::TransientDB::drain_into) does. Enable this right
+	/// after creating the store, before appending.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DataStoreExt, DirectoryConfig, DirectoryStore};
+	///
+	/// let config = DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-cursor"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// };
+	/// let mut store = DirectoryStore::new(config.clone())?;
+	/// store.set_delivery_cursor(true)?;
+	/// store.reset();
+	/// store.append(json!({"event": "test"}))?;
+	///
+	/// // Upload succeeds, but the process dies before removing the file
+	/// let batch_id = store.fetch(None, None)?.unwrap().batch_id.unwrap();
+	/// store.mark_delivered(&batch_id)?;
+	/// drop(store);
+	///
+	/// let mut store = DirectoryStore::new(config)?;
+	/// store.set_delivery_cursor(true)?;
+	/// assert!(store.fetch(None, None)?.is_none());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_delivery_cursor(&mut self, enabled: bool) -> Result<()> {
+		if !enabled {
+			self.cursor = None;
+			return Ok(());
 		}
-
-		if !started {
-			writer.write_all(b",")?;
+		if self.read_only {
+			// Fetches would have to record their progress
+			return Err(TransientError::ReadOnly.into());
 		}
-		serde_json::to_writer(&mut *writer, &data)?;
-		writer.flush()?;
 
-		self.current_size += data.to_string().len();
+		let cursor = self
+			.fs
+			.read_to_string(&self.cursor_path())
+			.ok()
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|value| DeliveryCursor::from_json(&value))
+			.unwrap_or_default();
+
+		// Files created from now on must sort after everything already delivered
+		if let Some(through) = cursor.delivered_through {
+			self.next_index
+				.fetch_max(through.saturating_add(1), Ordering::SeqCst);
+		}
+		self.cursor = Some(cursor);
 		Ok(())
 	}
 
-	fn fetch(
+	fn cursor_path(&self) -> PathBuf {
+		self.config
+			.storage_location
+			.join(format!(".{}-cursor.json", self.config.base_filename))
+	}
+
+	/// Writes the delivery cursor via a temporary file so a crash can't leave it torn.
+	fn save_cursor(&self) -> Result<()> {
+		let Some(cursor) = &self.cursor else {
+			return Ok(());
+		};
+		let path = self.cursor_path();
+		let tmp_path = path.with_extension("json.tmp");
+		self.fs
+			.write(&tmp_path, cursor.to_json().to_string().as_bytes())?;
+		self.fs.rename(&tmp_path, &path)
+	}
+
+	/// Returns one removable per event in a fetched data file.
+	///
+	/// Passing a subset of these to `remove()` deletes just those events, rewriting the
+	/// file with the remaining ones, which stay queued and are returned by later fetches.
+	/// This lets an uploader drop individual events the server rejected.
+	///
+	/// # Arguments
+	/// * `path` - A data file returned by `fetch()`
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-items"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	/// store.reset();
+	///
+	/// store.append(json!({"event": "good"}))?;
+	/// store.append(json!({"event": "rejected"}))?;
+	///
+	/// let files = store.fetch(None, None)?.unwrap().data.unwrap();
+	/// let items = store.item_removables(&files[0])?;
+	///
+	/// // The server rejected the second event - drop only that one
+	/// store.remove(&items[1..])?;
+	/// assert!(store.has_data());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn item_removables(&self, path: &Path) -> Result<Vec<Box<dyn Equivalent>>> {
+		let path = self.resolve_path(path);
+		let count = self.read_batch(&path)?.len();
+
+		Ok((0..count)
+			.map(|index| {
+				Box::new(FileItem {
+					path: path.to_path_buf(),
+					index,
+				}) as Box<dyn Equivalent>
+			})
+			.collect())
+	}
+
+	/// Sets an amount of disk space that appends must leave free.
+	///
+	/// Before each append, the free space on the storage volume is checked. If writing the
+	/// event would leave less than `reserve` bytes free, the append fails with
+	/// [`TransientError::DiskFull`] (or evicts old files, see
+	/// [`set_disk_full_policy`](Self::set_disk_full_policy)) instead of running out of space
+	/// mid-write and leaving a partial file.
+	///
+	/// Finalizing a file only needs room for its closing trailer and ignores the reserve,
+	/// so already queued data can always be fetched and uploaded to free up space.
+	///
+	/// Pass `None` to disable the check (the default). The check is skipped on platforms
+	/// where free space can't be determined.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore, TransientError};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-reserve"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	///
+	/// // Keep 50MB free for the rest of the app
+	/// store.set_disk_reserve(Some(50 * 1024 * 1024));
+	///
+	/// if let Err(e) = store.append(json!({"event": "test"})) {
+	///     if let Some(TransientError::DiskFull { .. }) = TransientError::from_io(&e) {
+	///         // Back off until an upload frees some space
+	///     }
+	/// }
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_disk_reserve(&mut self, reserve: Option<u64>) {
+		self.disk_reserve = reserve;
+	}
+
+	/// Retains the last `batches` removed batches for
+	/// [`recently_removed`](DataStoreExt::recently_removed), so developers can inspect what
+	/// was uploaded. 0, the default, retains none.
+	///
+	/// Retained batches are kept in a hidden `.{base_filename}-removed.json` file, which
+	/// survives restarts. Meant for debug builds: each removal rewrites the file.
+	pub fn set_retain_removed(&mut self, batches: usize) {
+		self.retain_removed = batches;
+	}
+
+	/// Sets what happens when an append would dip into the disk reserve.
+	///
+	/// Has no effect unless a reserve is set with [`set_disk_reserve`](Self::set_disk_reserve).
+	pub fn set_disk_full_policy(&mut self, policy: DiskFullPolicy) {
+		self.disk_full_policy = policy;
+	}
+
+	/// Sets a listener notified with the events of each data file evicted under
+	/// [`DiskFullPolicy::EvictOldest`], passing it at most `max_events` of them per file.
+	/// Files that can't be read are reported with no events.
+	pub fn set_eviction_listener<L: EvictionListener + 'static>(
 		&mut self,
-		count: Option<usize>,
-		max_bytes: Option<usize>,
-	) -> Result<Option<DataResult<Self::Output>>> {
-		if self.writer.is_some() {
-			self.finish_file()?;
-		}
+		listener: L,
+		max_events: usize,
+	) {
+		self.eviction_listener = Some(EvictionNotifier::new(Box::new(listener), max_events));
+	}
 
-		let mut files = self.sorted_files(false)?;
+	/// Sets when appended events are written to the data file.
+	///
+	/// The default, [`FlushPolicy::EveryAppend`], writes each event as it is appended.
+	/// [`FlushPolicy::Buffered`] trades durability for throughput when appending small
+	/// events at a high rate: events are held in memory and written together once enough
+	/// have built up, and also whenever the file is finished (on rotation and before a
+	/// fetch), on [`flush`](DataStore::flush), and when the store is dropped. Buffered
+	/// events are lost if the process crashes before then.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore, FlushPolicy};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-buffered"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024 * 1024,
+	/// })?;
+	/// store.set_flush_policy(FlushPolicy::Buffered(64 * 1024))?;
+	///
+	/// for i in 0..1000 {
+	///     store.append(json!({"event": "tick", "index": i}))?;
+	/// }
+	/// // Before the app is suspended
+	/// store.flush()?;
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_flush_policy(&mut self, policy: FlushPolicy) -> Result<()> {
+		self.flush_buffer()?;
+		self.flush_policy = policy;
+		Ok(())
+	}
 
-		if let Some(max_bytes) = max_bytes {
-			files = self.up_to_size(max_bytes, &files)?;
+	/// Sets which files [`fetch`](DataStore::fetch) takes first.
+	///
+	/// The default, [`FetchOrder::FileOrder`], follows the order files were started in.
+	/// [`FetchOrder::OldestFirstAcrossFiles`] is for stores whose files don't follow event
+	/// time, such as ones holding [imported](Self::import_file) files or compacted out of
+	/// order. Files are still fetched whole, so each is placed by its oldest event, and
+	/// every file is read to find it on each fetch. Files with no event times are placed
+	/// by when they were created.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore, FetchOrder};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-fetch-order"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024 * 1024,
+	/// })?;
+	/// store.reset();
+	/// store.set_fetch_order(FetchOrder::OldestFirstAcrossFiles);
+	///
+	/// store.append(json!({"event": "b", "timestamp": "2024-01-02T00:00:00Z"}))?;
+	/// store.fetch(None, None)?;
+	/// store.append(json!({"event": "a", "timestamp": "2024-01-01T00:00:00Z"}))?;
+	///
+	/// // The second file holds the older event
+	/// let files = store.fetch(Some(1), None)?.unwrap().data.unwrap();
+	/// assert!(std::fs::read_to_string(&files[0])?.contains("\"a\""));
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_fetch_order(&mut self, order: FetchOrder) {
+		self.fetch_order = order;
+	}
+
+	/// Puts files in [fetch order](Self::set_fetch_order).
+	fn in_fetch_order(&self, mut files: Vec<PathBuf>) -> Vec<PathBuf> {
+		if self.fetch_order == FetchOrder::OldestFirstAcrossFiles {
+			// Stable, so files with the same time stay in index order
+			files.sort_by_cached_key(|file| {
+				let oldest = self
+					.file_events(file)
+					.ok()
+					.and_then(|events| events.iter().filter_map(|e| self.event_time(e)).min())
+					.or_else(|| self.created_at(file));
+				(oldest.is_none(), oldest)
+			});
 		}
+		files
+	}
 
-		if let Some(count) = count {
-			files.truncate(count);
+	/// Fetches like [`fetch`](DataStore::fetch), but returns the contents of the files
+	/// instead of their paths, so they can be uploaded without copying them into memory.
+	///
+	/// On Unix, with the default [`StdFs`], files are memory-mapped; elsewhere they are
+	/// read. Finalized files are never changed in place (partial removals replace them),
+	/// so a mapping stays valid until it's dropped, even after the batch is removed.
+	///
+	/// # Safety
+	///
+	/// Nothing outside the store may truncate or write to its data files in place while
+	/// any returned [`MappedFile`] is alive, as reading a mapped page past the new end of
+	/// a file raises `SIGBUS`. See [`Fs::read_mapped`].
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?;
+	/// store.append(json!({"event": "login"}))?;
+	///
+	/// // SAFETY: nothing else writes to the store's directory
+	/// let result = unsafe { store.fetch_mapped(None, None)? }.unwrap();
+	/// for file in result.data.unwrap() {
+	///     // Hand `&file[..]` to the HTTP client as the request body
+	///     assert!(file.starts_with(b"{ \"batch\": ["));
+	/// }
+	/// store.remove(&result.removable.unwrap())?;
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub unsafe fn fetch_mapped(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Vec<MappedFile>>>> {
+		let Some(result) = self.fetch(count, max_bytes)? else {
+			return Ok(None);
+		};
+		let files = result
+			.data
+			.unwrap_or_default()
+			.iter()
+			// SAFETY: the caller keeps the files from being truncated while mapped
+			.map(|path| unsafe { self.fs.read_mapped(path) })
+			.collect::<Result<Vec<_>>>()?;
+		Ok(Some(DataResult {
+			data: Some(files),
+			removable: result.removable,
+			batch_id: result.batch_id,
+			attachments: result.attachments,
+			remaining_items: result.remaining_items,
+			remaining_bytes: result.remaining_bytes,
+		}))
+	}
+
+	/// Lists the finalized data files waiting to be fetched, oldest first, for uploaders
+	/// that pick batches themselves (largest first, say) with
+	/// [`fetch_batch`](Self::fetch_batch).
+	///
+	/// Each file is read to count its events. Events still in the file being written
+	/// aren't listed until it's finished, by a fetch or when it fills up.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 100,
+	/// })?;
+	/// for i in 0..3 {
+	///     store.append(json!({"event": i, "padding": "x".repeat(60)}))?;
+	/// }
+	///
+	/// let batches = store.list_batches()?;
+	/// if let Some(largest) = batches.iter().max_by_key(|batch| batch.bytes) {
+	///     let result = store.fetch_batch(&largest.id)?.unwrap();
+	///     // ... upload result.data ...
+	///     store.remove(&result.removable.unwrap())?;
+	/// }
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn list_batches(&self) -> Result<Vec<BatchInfo>> {
+		let mut batches = Vec::new();
+		for path in self.undelivered_files()? {
+			let Some(id) = Self::file_index(&path).map(str::to_string) else {
+				continue;
+			};
+			// The file may have been removed since it was listed
+			let Ok(metadata) = self.fs.metadata(&path) else {
+				continue;
+			};
+			let items = match Self::read_batch_from(self.fs.as_ref(), &path) {
+				Ok(batch) => batch.len(),
+				Err(e) => {
+					logging::log_warn!("Failed to read file {:?}: {}", path, e);
+					continue;
+				}
+			};
+			batches.push(BatchInfo {
+				id,
+				path,
+				items,
+				bytes: metadata.len,
+				created: metadata.created,
+			});
 		}
+		Ok(batches)
+	}
 
-		if files.is_empty() {
+	/// Fetches the finalized data file with the given [`BatchInfo::id`], or `None` if
+	/// there is none.
+	///
+	/// Unlike [`fetch`](DataStore::fetch), this doesn't advance the delivery cursor when
+	/// the batch is marked delivered.
+	pub fn fetch_batch(&mut self, id: &str) -> Result<Option<DataResult<Vec<PathBuf>>>> {
+		let Some(path) = self.batch_path(id)? else {
 			return Ok(None);
+		};
+		if let Some(migrations) = &self.migrations {
+			self.migrate_file(migrations, &path);
 		}
 
-		let removable = files
+		let others: Vec<PathBuf> = self
+			.undelivered_files()?
+			.into_iter()
+			.filter(|file| *file != path)
+			.collect();
+		let (remaining_items, remaining_bytes) = others
 			.iter()
-			.map(|p| Box::new(p.clone()) as Box<dyn Equivalent>)
-			.collect::<Vec<_>>();
+			.filter_map(|file| self.fs.metadata(file).ok())
+			.fold((0, 0), |(count, bytes), metadata| {
+				(count + 1, bytes + metadata.len)
+			});
 
+		let names = path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.map(str::to_string)
+			.into_iter()
+			.collect();
+		let batch_id = self.in_flight.id_for(names, |a, b| a == b);
+		let files = vec![path];
 		Ok(Some(DataResult {
+			removable: Some(vec![Box::new(files[0].clone()) as Box<dyn Equivalent>]),
+			batch_id: Some(batch_id),
+			attachments: self.attachment_handles(&files),
 			data: Some(files),
-			removable: Some(removable),
+			remaining_items,
+			remaining_bytes,
 		}))
 	}
 
-	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
-		for item in data {
-			if let Some(path) = item.as_any().downcast_ref::<PathBuf>() {
-				if let Err(e) = fs::remove_file(path) {
-					eprintln!("Failed to remove file {:?}: {}", path, e);
-				}
-			}
+	/// Deletes the finalized data file with the given [`BatchInfo::id`] and its
+	/// attachments, returning whether there was one.
+	pub fn remove_batch(&mut self, id: &str) -> Result<bool> {
+		self.check_writable()?;
+		let Some(path) = self.batch_path(id)? else {
+			return Ok(false);
+		};
+		self.fs.remove_file(&path)?;
+		self.remove_attachments(&path);
+		Ok(true)
+	}
+
+	/// Finalizes the file being written, then keeps the store from finalizing or
+	/// deleting files until the returned guard is dropped, so the directory can be
+	/// copied or backed up without racing the store.
+	///
+	/// While quiesced, appends keep going to a new file that isn't finalized (no
+	/// `.temp` extension), fetches only return the files finalized before, and calls
+	/// that would delete or rewrite files (`remove`, `remove_batch`, `anonymize`,
+	/// `purge_revoked`, `set_staging_location`) fail with `WouldBlock`. `reset` does
+	/// nothing and logs a warning, and [`DiskFullPolicy::EvictOldest`] doesn't evict.
+	///
+	/// A backup may skip the unfinished file or copy it as it is: an unfinished file
+	/// found when a store is opened is finalized then.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let backup = tempfile::tempdir()?;
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?;
+	/// store.append(json!({"event": "login"}))?;
+	///
+	/// let guard = store.quiesce()?;
+	/// for entry in std::fs::read_dir(dir.path())? {
+	///     let entry = entry?;
+	///     std::fs::copy(entry.path(), backup.path().join(entry.file_name()))?;
+	/// }
+	/// drop(guard);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn quiesce(&mut self) -> Result<QuiesceGuard> {
+		self.finish_file()?;
+		self.flush_buffer()?;
+		self.quiesced.fetch_add(1, Ordering::SeqCst);
+		Ok(QuiesceGuard {
+			holders: Arc::clone(&self.quiesced),
+		})
+	}
+
+	/// Whether a [`QuiesceGuard`] from [`quiesce`](Self::quiesce) is still held.
+	pub fn is_quiesced(&self) -> bool {
+		self.quiesced.load(Ordering::SeqCst) > 0
+	}
+
+	/// Whether the store was opened with [`OpenMode::ReadOnly`].
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
+	}
+
+	/// Fails unless files may be deleted or rewritten
+	fn check_writable(&self) -> Result<()> {
+		if self.read_only {
+			return Err(TransientError::ReadOnly.into());
+		}
+		if self.is_quiesced() {
+			return Err(io::Error::new(
+				io::ErrorKind::WouldBlock,
+				"Store is quiesced, files can't be deleted or rewritten",
+			));
 		}
 		Ok(())
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::{DirectoryConfig, DirectoryStore};
-	use crate::DataStore;
-	use serde_json::json;
-	use serde_json::Value;
-	use std::fs;
-	use std::io;
-	use std::io::Result;
-	use tempfile::TempDir;
+	/// Finalized data files not yet delivered through the delivery cursor
+	fn undelivered_files(&self) -> Result<Vec<PathBuf>> {
+		let mut files = self.sorted_files(false)?;
+		if let Some(through) = self.cursor.as_ref().and_then(|c| c.delivered_through) {
+			files.retain(|file| {
+				Self::file_index(file)
+					.and_then(|index| index.parse::<u32>().ok())
+					.filter(|index| *index <= through)
+					.is_none()
+			});
+		}
+		Ok(files)
+	}
 
-	#[test]
-	fn test_directory_store_basic_operations() -> Result<()> {
+	/// Path of the finalized data file with the given index
+	fn batch_path(&self, id: &str) -> Result<Option<PathBuf>> {
+		Ok(self
+			.undelivered_files()?
+			.into_iter()
+			.find(|file| Self::file_index(file) == Some(id)))
+	}
+
+	/// Writes files to a separate staging directory until they're finalized, so backup
+	/// tools and directory watchers sweeping the storage location never see
+	/// partially-written files. `None` writes them in the storage location, which is the
+	/// default.
+	///
+	/// The staging directory is created if needed, and must be on the same filesystem as
+	/// the storage location, so finalized files can be moved with a rename; otherwise
+	/// this fails with `InvalidInput`. Unfinished files left in it by a crash are
+	/// recovered into the storage location. Call this right after opening the store, so
+	/// they're recovered before new events are appended.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().join("outbox"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?;
+	/// store.set_staging_location(Some(dir.path().join("staging")))?;
+	///
+	/// store.append(json!({"event": "login"}))?;
+	/// // The file being written is in the staging directory until it's finalized
+	/// assert_eq!(std::fs::read_dir(dir.path().join("staging"))?.count(), 1);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_staging_location(&mut self, location: Option<PathBuf>) -> Result<()> {
+		self.check_writable()?;
+		// Files started in the old location are finalized from there
+		self.finish_file()?;
+
+		let location = location
+			.map(|location| platform::prepare_location(&location, &self.config.base_filename))
+			.transpose()?
+			.filter(|location| *location != self.config.storage_location);
+		if let Some(staging) = &location {
+			self.fs.create_dir_all(staging)?;
+			self.check_same_filesystem(staging)?;
+			let max_index = self.initialize_directory(staging)?;
+			self.next_index.fetch_max(max_index + 1, Ordering::SeqCst);
+		}
+		self.staging_location = location;
+		Ok(())
+	}
+
+	/// Sets how many bytes of the buffer appended events are serialized into are kept
+	/// between appends. Defaults to 16 KiB.
+	///
+	/// Events are serialized into the same buffer on every append instead of a new
+	/// string each time, so steady appends don't churn the allocator. Size it to hold a
+	/// typical `append_many` group; the buffer grows for larger events and shrinks back
+	/// to this capacity afterwards.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?
+	/// .with_scratch_capacity(256 * 1024);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn with_scratch_capacity(mut self, bytes: usize) -> Self {
+		self.scratch_capacity = bytes;
+		self.scratch = Vec::with_capacity(bytes);
+		self
+	}
+
+	/// Enables or disables picking up batch files written by other processes.
+	///
+	/// When enabled, other processes (possibly written in other languages) can hand events
+	/// to this store by dropping complete batch files into its directory, turning it into a
+	/// simple cross-process queue. Handoff files must:
+	/// - be named `{anything}.{base_filename}.json`, e.g. `1700000000-worker3.events.json`
+	/// - contain the store's file format: a JSON object with a `batch` array of events
+	/// - appear atomically: write them under another name (e.g. a leading `.`) and rename
+	///
+	/// Handoff files are adopted into the queue by [`adopt_external_files`](Self::adopt_external_files),
+	/// which runs automatically before each `fetch()` while this is enabled. `has_data()`
+	/// counts handoff files waiting to be adopted, without touching them.
+	/// With the `watch` feature, `watch_external` additionally
+	/// reports new files as they arrive.
+	pub fn set_accept_external(&mut self, enabled: bool) {
+		self.accept_external = enabled;
+	}
+
+	/// Moves batch files dropped into the directory by other processes into the queue.
+	///
+	/// See [`set_accept_external`](Self::set_accept_external) for the handoff file format.
+	/// Valid files are renamed into the store's own naming scheme and become fetchable.
+	/// Files that aren't a JSON object with a `batch` array are renamed with a
+	/// `.rejected` extension and otherwise left alone.
+	///
+	/// Returns the number of files adopted.
+	///
+	/// # Examples
+	/// ```
+	/// use std::fs;
+	/// use std::path::PathBuf;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-handoff"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	/// store.reset();
+	///
+	/// // Another process hands over a batch
+	/// fs::write(
+	///     "/tmp/data-handoff/.producer.events.json",
+	///     r#"{"batch":[{"event":"from elsewhere"}],"writeKey":"test"}"#,
+	/// )?;
+	/// fs::rename(
+	///     "/tmp/data-handoff/.producer.events.json",
+	///     "/tmp/data-handoff/producer.events.json",
+	/// )?;
+	///
+	/// assert_eq!(store.adopt_external_files()?, 1);
+	/// assert!(store.fetch(None, None)?.is_some());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn adopt_external_files(&self) -> Result<usize> {
+		let mut handoffs: Vec<PathBuf> = self
+			.fs
+			.read_dir(&self.config.storage_location)?
+			.into_iter()
+			.filter(|e| e.is_file)
+			.map(|e| e.path)
+			.filter(|p| self.is_handoff(p))
+			.collect();
+		handoffs.sort();
+
+		let mut adopted = 0;
+		for path in handoffs {
+			if let Err(e) = self.read_batch(&path) {
+				logging::log_warn!("Rejecting external batch file {:?}: {}", path, e);
+				let mut rejected = path.clone().into_os_string();
+				rejected.push(".");
+				rejected.push(Self::REJECTED_EXTENSION);
+				self.fs.rename(&path, Path::new(&rejected))?;
+				continue;
+			}
+
+			let new_path = self.config.storage_location.join(format!(
+				"{}-{}.{}",
+				self.next_index(),
+				self.config.base_filename,
+				Self::TEMP_EXTENSION
+			));
+			self.fs.rename(&path, &new_path)?;
+			logging::log_info!("Adopted external batch file {:?} as {:?}", path, new_path);
+			adopted += 1;
+		}
+		Ok(adopted)
+	}
+
+	/// Imports events from an externally produced file into the queue.
+	///
+	/// Use this to migrate files left behind by other tools or older SDK versions. The file
+	/// may be NDJSON (one event per line), a JSON array of events, or a batch envelope.
+	/// Malformed entries are listed in the returned report rather than failing the import.
+	/// The file itself is left in place.
+	///
+	/// # Examples
+	/// ```
+	/// use std::fs;
+	/// use std::path::PathBuf;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-import"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	/// store.reset();
+	///
+	/// fs::write("/tmp/legacy-events.ndjson", "{\"event\":\"a\"}\nnot json\n{\"event\":\"b\"}\n")?;
+	/// let report = store.import_file("/tmp/legacy-events.ndjson")?;
+	///
+	/// assert_eq!(report.imported, 2);
+	/// assert_eq!(report.errors[0].position, 2);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn import_file(&mut self, path: impl AsRef<Path>) -> Result<ImportReport> {
+		let file = File::open(path)?;
+		crate::import::import_events(self, io::BufReader::new(file))
+	}
+
+	/// Migrates the pending events of a Segment analytics-swift or analytics-kotlin file
+	/// queue into this store.
+	///
+	/// `dir` is the directory the SDK writes its numbered queue files to, e.g.
+	/// `segment/{writeKey}` for analytics-swift or `analytics-kotlin/{writeKey}/events`
+	/// for analytics-kotlin. Finished files and the one being written are imported
+	/// oldest first, and each is deleted once its events are queued, so an interrupted
+	/// migration can simply be run again. Other files in `dir` are left alone.
+	///
+	/// Events are queued under this store's write key. Entries that can't be read, such
+	/// as an event cut short when the app was killed, are listed in the report, with
+	/// their file's name in the message.
+	///
+	/// # Examples
+	/// ```
+	/// use std::fs;
+	/// use std::path::PathBuf;
+	/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore};
+	///
+	/// let legacy = PathBuf::from("/tmp/segment-legacy/segment/my-key");
+	/// fs::create_dir_all(&legacy)?;
+	/// fs::write(
+	///     legacy.join("0-segment-events.temp"),
+	///     r#"{"batch":[{"event":"a"}],"sentAt":"2024-01-01T00:00:00.000Z","writeKey":"my-key"}"#,
+	/// )?;
+	/// fs::write(legacy.join("1-segment-events"), r#"{"batch":[{"event":"b"},"#)?;
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: PathBuf::from("/tmp/segment-legacy/events"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	/// store.reset();
+	///
+	/// let report = store.import_segment_queue(&legacy)?;
+	/// assert_eq!(report.imported, 2);
+	/// assert!(fs::read_dir(&legacy)?.next().is_none());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn import_segment_queue(&mut self, dir: impl AsRef<Path>) -> Result<ImportReport> {
+		crate::segment::import_queue(self, dir.as_ref())
+	}
+
+	/// Watches the directory for handoff files from other processes.
+	///
+	/// Enables [`set_accept_external`](Self::set_accept_external) and calls `on_arrival`
+	/// from a background thread whenever a handoff file appears, so an uploader can wake
+	/// up instead of polling. The files are adopted by the next `fetch()` or `has_data()`.
+	/// Watching stops when the store is dropped.
+	///
+	/// Uses inotify on Linux, FSEvents on macOS and ReadDirectoryChangesW on Windows.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use std::sync::mpsc;
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let mut store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-watch"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	///
+	/// let (wake, woken) = mpsc::channel();
+	/// store.watch_external(move || {
+	///     let _ = wake.send(());
+	/// })?;
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+	pub fn watch_external<F>(&mut self, on_arrival: F) -> Result<()>
+	where
+		F: Fn() + Send + 'static,
+	{
+		use notify::{EventKind, RecursiveMode, Watcher};
+
+		if !self.fs.is_native() {
+			return Err(Self::not_native());
+		}
+
+		let suffix = format!(".{}.json", self.config.base_filename);
+		let mut watcher =
+			notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+				let Ok(event) = event else {
+					return;
+				};
+				if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+					return;
+				}
+				let is_handoff = event.paths.iter().any(|p| {
+					p.file_name()
+						.and_then(|n| n.to_str())
+						.is_some_and(|n| !n.starts_with('.') && n.ends_with(&suffix))
+				});
+				if is_handoff {
+					on_arrival();
+				}
+			})
+			.map_err(io::Error::other)?;
+		watcher
+			.watch(&self.config.storage_location, RecursiveMode::NonRecursive)
+			.map_err(io::Error::other)?;
+
+		self.watcher = Some(watcher);
+		self.accept_external = true;
+		Ok(())
+	}
+
+	/// Upper bound on the size of the trailer written when a file is finalized
+	fn trailer_len(&self) -> usize {
+		64 + self.config.write_key.len()
+	}
+
+	/// Checks there is room to write `bytes` more bytes, plus the reserve if `with_reserve`.
+	/// Evicts the oldest finalized files first if the policy allows it.
+	fn ensure_space(&self, bytes: usize, with_reserve: bool) -> Result<()> {
+		let Some(reserve) = self.disk_reserve else {
+			return Ok(());
+		};
+		let required = (bytes as u64).saturating_add(if with_reserve { reserve } else { 0 });
+
+		loop {
+			let Some(available) = self.fs.available_space(&self.config.storage_location) else {
+				return Ok(());
+			};
+			if available >= required {
+				return Ok(());
+			}
+
+			if self.disk_full_policy == DiskFullPolicy::EvictOldest && !self.is_quiesced() {
+				let files = self.sorted_files(false)?;
+				if let Some(oldest) = files.first() {
+					let events = self.read_batch(oldest).unwrap_or_default();
+					let now = self.clock.now();
+					let first = self.created_at(oldest).unwrap_or(now);
+					// Its events were appended before the next file was started
+					let last = files
+						.get(1)
+						.and_then(|next| self.created_at(next))
+						.unwrap_or(now);
+					self.fs.remove_file(oldest)?;
+					self.remove_attachments(oldest);
+					self.record_drop(DropReason::DiskFull, events.len(), first, last.max(first));
+					if let Some(listener) = &self.eviction_listener {
+						listener.notify(DropReason::DiskFull, &events, events.len());
+					}
+					continue;
+				}
+			}
+
+			return Err(TransientError::DiskFull {
+				available,
+				required,
+			}
+			.into());
+		}
+	}
+
+	/// Restricts the storage directory to the current user.
+	///
+	/// Queued events may contain user data, and the directory may live somewhere shared
+	/// such as a temp directory. This fails with `PermissionDenied` if the directory is a
+	/// symbolic link or (on Unix) owned by another user, and otherwise sets its permissions
+	/// to `0700`. Call it right after creating the store.
+	///
+	/// Independently of this, the store never follows symbolic links found inside its
+	/// directory: they are ignored when recovering, fetching and listing attachments.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-restricted"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	/// store.restrict_permissions()?;
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn restrict_permissions(&self) -> Result<()> {
+		if !self.fs.is_native() {
+			return Err(Self::not_native());
+		}
+		if self.read_only {
+			return Err(TransientError::ReadOnly.into());
+		}
+		platform::restrict_directory(&self.config.storage_location)
+	}
+
+	/// Marks the store's directories as excluded from backups.
+	///
+	/// Queued analytics are transient and shouldn't inflate users' backups. This writes a
+	/// [`CACHEDIR.TAG`](https://bford.info/cachedir/), honored by tools such as restic,
+	/// Borg and GNU tar, into the storage location and staging location, and on Windows
+	/// sets `FILE_ATTRIBUTE_TEMPORARY` on them. Call it after
+	/// [`set_staging_location`](Self::set_staging_location), if that's used.
+	///
+	/// Platforms whose exclusion needs an OS API outside Rust's reach, like
+	/// `NSURLIsExcludedFromBackupKey` on iOS and macOS, can use
+	/// [`exclude_from_backup_with`](Self::exclude_from_backup_with).
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?;
+	/// store.exclude_from_backup()?;
+	/// assert!(dir.path().join("CACHEDIR.TAG").exists());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn exclude_from_backup(&self) -> Result<()> {
+		self.exclude_from_backup_with(|_| Ok(()))
+	}
+
+	/// Like [`exclude_from_backup`](Self::exclude_from_backup), and also calls `mark`
+	/// with each of the store's directories, so the app can apply a platform exclusion
+	/// itself.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::Path;
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// // Implemented by the app, e.g. through objc2 or a Swift callback, setting
+	/// // NSURLIsExcludedFromBackupKey on the directory's URL
+	/// fn set_excluded_from_backup(path: &Path) -> std::io::Result<()> {
+	///     # let _ = path;
+	///     Ok(())
+	/// }
+	///
+	/// let dir = tempfile::tempdir()?;
+	/// let store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_path_buf(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 512 * 1024,
+	/// })?;
+	/// store.exclude_from_backup_with(set_excluded_from_backup)?;
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn exclude_from_backup_with<F>(&self, mark: F) -> Result<()>
+	where
+		F: Fn(&Path) -> Result<()>,
+	{
+		if !self.fs.is_native() {
+			return Err(Self::not_native());
+		}
+		let directories =
+			std::iter::once(&self.config.storage_location).chain(&self.staging_location);
+		for directory in directories {
+			self.fs.write(
+				&directory.join(Self::CACHEDIR_TAG),
+				b"Signature: 8a477f597d28d172789f06886806bc55\n\
+				# This file is a cache directory tag created by transientdb.\n\
+				# For information about cache directory tags, see https://bford.info/cachedir/\n",
+			)?;
+			platform::mark_temporary(directory)?;
+			mark(directory)?;
+		}
+		Ok(())
+	}
+
+	fn not_native() -> io::Error {
+		io::Error::new(
+			io::ErrorKind::Unsupported,
+			"Not supported on a simulated filesystem",
+		)
+	}
+
+	/// Returns a data file path relative to the storage location.
+	///
+	/// Absolute paths can go stale between launches, e.g. when iOS moves the app container.
+	/// Persist this relative form instead, and turn it back into a usable path with
+	/// [`resolve_path`](Self::resolve_path).
+	///
+	/// Returns `None` if the path isn't inside the storage location.
+	pub fn relative_path(&self, path: &Path) -> Option<PathBuf> {
+		path.strip_prefix(&self.config.storage_location)
+			.ok()
+			.map(Path::to_path_buf)
+	}
+
+	/// Resolves a data file reference against the current storage location.
+	///
+	/// Relative paths are joined to the storage location. An absolute path that no longer
+	/// exists - typically one returned before the storage directory was relocated - is
+	/// re-resolved by file name within the storage location. `remove()` applies this to
+	/// every path it receives, so removables from a previous launch keep working.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::{Path, PathBuf};
+	/// use transientdb::{DirectoryConfig, DirectoryStore};
+	///
+	/// let store = DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/data-resolve"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?;
+	///
+	/// let stale = Path::new("/old/container/data/0-events.temp");
+	/// assert_eq!(store.resolve_path(stale), PathBuf::from("/tmp/data-resolve/0-events.temp"));
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn resolve_path(&self, path: &Path) -> PathBuf {
+		if path.is_relative() {
+			return self.config.storage_location.join(path);
+		}
+		if self.fs.metadata(path).is_ok() || path.starts_with(&self.config.storage_location) {
+			return path.to_path_buf();
+		}
+		match path.file_name() {
+			Some(file_name) => self.config.storage_location.join(file_name),
+			None => path.to_path_buf(),
+		}
+	}
+
+	/// Reads the events of a finalized data file.
+	fn read_batch(&self, path: &Path) -> Result<Vec<Value>> {
+		Self::read_batch_from(&*self.fs, path)
+	}
+
+	/// Reads the events of several finalized data files, in the same order.
+	///
+	/// With the `parallel` feature, the files are read and parsed on scoped threads, up
+	/// to one per available core. Threads are started per call rather than taken from a
+	/// rayon pool, which keeps the feature free of dependencies; a fetch reads few
+	/// enough files that the spawn cost is small next to the reads.
+	fn read_batches(&self, paths: &[PathBuf]) -> Vec<Result<Vec<Value>>> {
+		#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+		{
+			let threads = std::thread::available_parallelism().map_or(1, usize::from);
+			if threads > 1 && paths.len() > 1 {
+				let fs: &dyn Fs = &*self.fs;
+				let chunk_size = paths.len().div_ceil(threads);
+				return std::thread::scope(|scope| {
+					let readers: Vec<_> = paths
+						.chunks(chunk_size)
+						.map(|chunk| {
+							let reader = scope.spawn(move || {
+								chunk
+									.iter()
+									.map(|path| Self::read_batch_from(fs, path))
+									.collect::<Vec<_>>()
+							});
+							(chunk.len(), reader)
+						})
+						.collect();
+					readers
+						.into_iter()
+						.flat_map(|(len, reader)| {
+							reader.join().unwrap_or_else(|_| {
+								(0..len)
+									.map(|_| Err(io::Error::other("File reader thread panicked")))
+									.collect()
+							})
+						})
+						.collect()
+				});
+			}
+		}
+		paths.iter().map(|path| self.read_batch(path)).collect()
+	}
+
+	fn read_batch_from(fs: &dyn Fs, path: &Path) -> Result<Vec<Value>> {
+		let mut content: Value = serde_json::from_slice(&fs.read(path)?)?;
+		match content.get_mut("batch").map(Value::take) {
+			Some(Value::Array(items)) => Ok(items),
+			_ => Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Data file has no batch array",
+			)),
+		}
+	}
+
+	/// Removes individual events from a finalized data file.
+	/// The file is rewritten with the remaining events, or deleted if none remain.
+	fn remove_file_items(&self, path: &Path, indices: &[usize]) -> Result<()> {
+		let mut content: Value = serde_json::from_slice(&self.fs.read(path)?)?;
+		let Some(batch) = content.get_mut("batch").and_then(Value::as_array_mut) else {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Data file has no batch array",
+			));
+		};
+
+		let mut removed_attachment_ids: Vec<String> = Vec::new();
+		let mut position = 0;
+		batch.retain(|item| {
+			let keep = !indices.contains(&position);
+			if !keep {
+				removed_attachment_ids.extend(attachment::referenced_ids(item).map(str::to_string));
+			}
+			position += 1;
+			keep
+		});
+
+		if batch.is_empty() {
+			self.fs.remove_file(path)?;
+			self.remove_attachments(path);
+			return Ok(());
+		}
+
+		self.rewrite_file(path, &content)?;
+
+		if !removed_attachment_ids.is_empty() {
+			for attachment_file in self.attachment_files(path) {
+				let name = file_name(&attachment_file);
+				if removed_attachment_ids
+					.iter()
+					.any(|id| name.contains(id.as_str()))
+				{
+					let _ = self.fs.remove_file(&attachment_file);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Replaces the contents of a finalized data file via a hidden temporary, which
+	/// recovery ignores, so a crash leaves either the old or the new file.
+	fn rewrite_file(&self, path: &Path, content: &Value) -> Result<()> {
+		let tmp_path = path.with_file_name(format!(".{}.rewrite", file_name(path)));
+		self.fs
+			.write(&tmp_path, self.json_format.serialize(content).as_bytes())?;
+		self.fs.rename(&tmp_path, path)
+	}
+
+	/// Upgrades events in a finalized data file stored with an older schema version.
+	/// Failures are logged and leave the file as it was.
+	fn migrate_file(&self, migrations: &SchemaMigrations, path: &Path) {
+		let result = self.fs.read(path).and_then(|bytes| {
+			let mut content: Value = serde_json::from_slice(&bytes)?;
+			let Some(batch) = content.get_mut("batch").and_then(Value::as_array_mut) else {
+				return Ok(());
+			};
+			let mut migrated = false;
+			for item in batch.iter_mut() {
+				migrated |= migrations.migrate(item);
+			}
+			if migrated {
+				self.rewrite_file(path, &content)?;
+			}
+			Ok(())
+		});
+		if let Err(e) = result {
+			logging::log_warn!("Failed to migrate file {:?}: {}", path, e);
+		}
+	}
+
+	/// Returns the index of one of the store's own data files: an unfinished
+	/// `{index}-{base_filename}` or a finalized `{index}-{base_filename}[-{hash}].temp`.
+	/// Handoff files from other processes, like `1700000000-worker3.events.json`, aren't.
+	fn own_index(&self, path: &Path) -> Option<u32> {
+		let (index, rest) = file_name(path).split_once('-')?;
+		let rest = rest.strip_prefix(self.config.base_filename.as_str())?;
+		let own = match rest.strip_suffix(&format!(".{}", Self::TEMP_EXTENSION)) {
+			None => rest.is_empty(),
+			Some(hash) => {
+				hash.is_empty()
+					|| hash.strip_prefix('-').is_some_and(|hash| {
+						!hash.is_empty() && hash.chars().all(|c| c.is_ascii_alphanumeric())
+					})
+			}
+		};
+		if !own || !index.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		index.parse().ok()
+	}
+
+	/// Whether a file is a batch handed over by another process, waiting to be adopted
+	fn is_handoff(&self, path: &Path) -> bool {
+		let name = file_name(path);
+		!name.starts_with('.') && name.ends_with(&format!(".{}.json", self.config.base_filename))
+	}
+
+	/// Returns the index prefix of a data file name, e.g. "3" for "3-events.temp"
+	fn file_index(path: &Path) -> Option<&str> {
+		path.file_name()?
+			.to_str()?
+			.split('-')
+			.next()
+			.filter(|index| index.parse::<u32>().is_ok())
+	}
+
+	/// When a data file was created, falling back to its modification time on file
+	/// systems that don't record creation times
+	fn created_at(&self, path: &Path) -> Option<DateTime<Utc>> {
+		self.fs.metadata(path).ok()?.created
+	}
+
+	/// Reads the events of a data file, finalized or still being written
+	fn file_events(&self, file: &Path) -> Result<Vec<Value>> {
+		if file.extension().and_then(|ext| ext.to_str()) == Some(Self::TEMP_EXTENSION) {
+			return self.read_batch(file);
+		}
+		// In-progress files lack the trailer, and may have events still buffered
+		let mut content = self.fs.read_to_string(file)?;
+		if Some(file) == self.current_path.as_deref() {
+			content.push_str(&String::from_utf8_lossy(&self.buffer));
+		}
+		content.push_str("]}");
+		let mut content: Value = serde_json::from_str(&content)?;
+		match content.get_mut("batch").map(Value::take) {
+			Some(Value::Array(items)) => Ok(items),
+			_ => Ok(Vec::new()),
+		}
+	}
+
+	/// When an event happened, by its `timestamp` or received-at field
+	fn event_time(&self, event: &Value) -> Option<DateTime<Utc>> {
+		[Some(skew::TIMESTAMP_KEY), self.received_at.as_deref()]
+			.into_iter()
+			.flatten()
+			.find_map(|field| event.get(field)?.as_str().and_then(parse_time))
+	}
+
+	/// Prefix shared by all attachment files belonging to the data file with this index
+	fn attachment_prefix(&self, index: &str) -> String {
+		format!(".{}-{}.", index, self.config.base_filename)
+	}
+
+	/// Lists attachment files (data and metadata) belonging to a data file
+	fn attachment_files(&self, data_file: &Path) -> Vec<PathBuf> {
+		let Some(index) = Self::file_index(data_file) else {
+			return Vec::new();
+		};
+		let prefix = self.attachment_prefix(index);
+		let Ok(entries) = self.fs.read_dir(&self.config.storage_location) else {
+			return Vec::new();
+		};
+		let mut files: Vec<PathBuf> = entries
+			.into_iter()
+			.filter(|e| file_name(&e.path).starts_with(&prefix) && !e.is_symlink)
+			.map(|e| e.path)
+			.collect();
+		files.sort();
+		files
+	}
+
+	/// Writes attachments next to the data file currently being written
+	fn write_attachments(&self, attachments: &[(String, Attachment)]) -> Result<()> {
+		let index = self
+			.current_path
+			.as_deref()
+			.and_then(Self::file_index)
+			.ok_or_else(|| io::Error::other("No active file for attachments"))?;
+		let prefix = self.attachment_prefix(index);
+
+		for (id, attachment) in attachments {
+			let path = self.config.storage_location.join(format!(
+				"{}{}.{}",
+				prefix,
+				id,
+				Self::ATTACHMENT_EXTENSION
+			));
+			self.fs.write(&path, &attachment.data)?;
+			let metadata = serde_json::json!({
+				"name": attachment.name,
+				"contentType": attachment.content_type
+			});
+			self.fs.write(
+				&path.with_extension("attachment.json"),
+				metadata.to_string().as_bytes(),
+			)?;
+		}
+		Ok(())
+	}
+
+	/// Builds handles for the attachments belonging to the given data files
+	fn attachment_handles(&self, data_files: &[PathBuf]) -> Option<Vec<AttachmentHandle>> {
+		let handles: Vec<AttachmentHandle> = data_files
+			.iter()
+			.flat_map(|file| self.attachment_files(file))
+			.filter(|p| {
+				p.extension().and_then(|ext| ext.to_str()) == Some(Self::ATTACHMENT_EXTENSION)
+			})
+			.filter_map(|path| {
+				let file_name = path.file_name()?.to_str()?;
+				let id = file_name
+					.strip_suffix(&format!(".{}", Self::ATTACHMENT_EXTENSION))?
+					.rsplit('.')
+					.next()?
+					.to_string();
+				let metadata: Value = serde_json::from_slice(
+					&self.fs.read(&path.with_extension("attachment.json")).ok()?,
+				)
+				.ok()?;
+				// Virtual paths mean nothing outside the store, so hand out the bytes instead
+				let content = if self.fs.is_native() {
+					AttachmentContent::File(path)
+				} else {
+					AttachmentContent::Bytes(self.fs.read(&path).ok()?)
+				};
+				Some(AttachmentHandle {
+					id,
+					name: metadata["name"].as_str().unwrap_or_default().to_string(),
+					content_type: metadata["contentType"]
+						.as_str()
+						.unwrap_or_default()
+						.to_string(),
+					content,
+				})
+			})
+			.collect();
+		(!handles.is_empty()).then_some(handles)
+	}
+
+	fn remove_attachments(&self, data_file: &Path) {
+		for path in self.attachment_files(data_file) {
+			if let Err(e) = self.fs.remove_file(&path) {
+				logging::log_warn!("Failed to remove attachment {:?}: {}", path, e);
+			}
+		}
+	}
+
+	/// Deletes attachments left behind when a crash interrupted removal of their data file
+	fn remove_orphaned_attachments(&self) {
+		let Ok(entries) = self.fs.read_dir(&self.config.storage_location) else {
+			return;
+		};
+		let paths: Vec<PathBuf> = entries.into_iter().map(|e| e.path).collect();
+		let live_indexes: Vec<&str> = paths.iter().filter_map(|p| Self::file_index(p)).collect();
+
+		for path in &paths {
+			let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+				continue;
+			};
+			let is_ours = file_name.starts_with('.')
+				&& (file_name.ends_with(".attachment") || file_name.ends_with(".attachment.json"));
+			let orphaned = !live_indexes
+				.iter()
+				.any(|index| file_name.starts_with(&self.attachment_prefix(index)));
+			if is_ours && orphaned {
+				let _ = self.fs.remove_file(path);
+			}
+		}
+	}
+
+	fn next_index(&self) -> u32 {
+		self.next_index.fetch_add(1, Ordering::SeqCst)
+	}
+
+	/// Directory files are written in until they're finalized
+	fn staging_dir(&self) -> &Path {
+		self.staging_location
+			.as_deref()
+			.unwrap_or(&self.config.storage_location)
+	}
+
+	/// Fails unless files can be renamed from `staging` into the storage location, which
+	/// requires both to be on the same filesystem
+	fn check_same_filesystem(&self, staging: &Path) -> Result<()> {
+		let probe = format!(".{}-staging-probe", self.config.base_filename);
+		let staged = staging.join(&probe);
+		self.fs.write(&staged, b"")?;
+		let moved = self.config.storage_location.join(&probe);
+		match self.fs.rename(&staged, &moved) {
+			Ok(()) => self.fs.remove_file(&moved),
+			Err(e) => {
+				let _ = self.fs.remove_file(&staged);
+				Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!(
+						"Staging location {:?} must be on the same filesystem as {:?}: {}",
+						staging, self.config.storage_location, e
+					),
+				))
+			}
+		}
+	}
+
+	fn start_file_if_needed(&mut self) -> Result<()> {
+		if self.writer.is_some() {
+			return Ok(());
+		}
+
+		let mut index = self.next_index();
+		let mut attempts = 0;
+		const MAX_ATTEMPTS: u32 = 1000; // Safeguard against infinite loops
+
+		loop {
+			let file_path = self
+				.staging_dir()
+				.join(format!("{}-{}", index, self.config.base_filename));
+
+			match self.fs.create_new(&file_path) {
+				Ok(mut writer) => {
+					self.current_path = Some(file_path);
+
+					if self.current_size == 0 {
+						writer.write_all(Self::HEADER)?;
+						self.current_size = Self::HEADER.len();
+					}
+
+					self.writer = Some(writer);
+					return Ok(());
+				}
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+					// File exists, try next index
+					index = self.next_index();
+					attempts += 1;
+
+					if attempts >= MAX_ATTEMPTS {
+						return Err(io::Error::other(
+							"Failed to find available index after maximum attempts",
+						));
+					}
+				}
+				Err(e) => return Err(e), // Other errors are propagated
+			}
+		}
+	}
+
+	/// Scans a directory for existing files, finalizes unfinished ones, and returns the highest index found
+	fn initialize_directory(&self, dir: &Path) -> Result<u32> {
+		let entries = self.fs.read_dir(dir)?;
+		let mut max_index = 0;
+
+		for entry in entries {
+			let path = entry.path;
+
+			// Never follow links planted in the store; finalizing would write through them
+			if entry.is_symlink {
+				continue;
+			}
+
+			let file_name = file_name(&path);
+
+			// A rewrite interrupted before its rename; the original is still in place
+			if file_name.starts_with('.') && file_name.ends_with(".rewrite") {
+				let _ = self.fs.remove_file(&path);
+				continue;
+			}
+
+			// Only the store's own files; others, like handoff files, are left alone
+			let Some(index) = self.own_index(&path) else {
+				continue;
+			};
+			max_index = max_index.max(index);
+
+			// If file doesn't have .temp extension, it's unfinished
+			if path.extension().and_then(|ext| ext.to_str()) == Some(Self::TEMP_EXTENSION) {
+				continue;
+			}
+			match self.repair_unfinished(&path) {
+				Ok(Repaired::Removed) => {}
+				Ok(Repaired::Unfinished) => match self.finalize_file(&path) {
+					Ok(()) => {
+						logging::log_info!("Recovered unfinished file {:?}", path)
+					}
+					Err(e) => {
+						logging::log_warn!("Failed to finalize file {:?}: {}", path, e);
+						// Continue processing other files even if this one fails
+					}
+				},
+				// Crashed between writing the trailer and the rename
+				Ok(Repaired::Complete) => {
+					let new_path = path.with_extension(Self::TEMP_EXTENSION);
+					if let Err(e) = self.fs.rename(&path, &new_path) {
+						logging::log_warn!("Failed to finalize file {:?}: {}", path, e);
+					}
+				}
+				Err(e) => {
+					logging::log_warn!("Failed to repair file {:?}: {}", path, e);
+				}
+			}
+		}
+
+		Ok(max_index)
+	}
+
+	/// Cuts an unfinished file back to its last complete event, dropping anything a crash
+	/// left half-written after it. Files without a complete event are deleted, and files
+	/// that already hold a complete envelope are left as they are.
+	fn repair_unfinished(&self, path: &Path) -> Result<Repaired> {
+		let content = self.fs.read(path)?;
+		// A trailer written before the rename; appending another would corrupt it
+		if serde_json::from_slice::<Value>(&content)
+			.is_ok_and(|content| content.get("batch").is_some_and(Value::is_array))
+		{
+			return Ok(Repaired::Complete);
+		}
+		let Some(body) = content.strip_prefix(Self::HEADER) else {
+			if Self::HEADER.starts_with(&content) {
+				// Torn while writing the header
+				self.fs.remove_file(path)?;
+				return Ok(Repaired::Removed);
+			}
+			// Not written by this store; leave it to validation
+			return Ok(Repaired::Unfinished);
+		};
+
+		let mut valid_len = 0;
+		let mut events = 0;
+		let mut rest = body;
+		loop {
+			let mut stream = serde_json::Deserializer::from_slice(rest).into_iter::<Value>();
+			if !matches!(stream.next(), Some(Ok(_))) {
+				break;
+			}
+			let end = body.len() - rest.len() + stream.byte_offset();
+			valid_len = end;
+			events += 1;
+			match body[end..].strip_prefix(b",") {
+				Some(next) => rest = next,
+				None => break,
+			}
+		}
+
+		if events == 0 {
+			self.fs.remove_file(path)?;
+			self.remove_attachments(path);
+			return Ok(Repaired::Removed);
+		}
+
+		let len = Self::HEADER.len() + valid_len;
+		if len < content.len() {
+			logging::log_warn!(
+				"Discarding {} incomplete bytes at the end of {:?}",
+				content.len() - len,
+				path
+			);
+			let tmp_path = path.with_file_name(format!(".{}.rewrite", file_name(path)));
+			self.fs.write(&tmp_path, &content[..len])?;
+			self.fs.rename(&tmp_path, path)?;
+		}
+		Ok(Repaired::Unfinished)
+	}
+
+	/// Finalizes a file by completing the JSON structure and renaming with .temp extension
+	fn finalize_file(&self, path: &Path) -> Result<()> {
+		// Hash before the trailer is written, so the timestamp doesn't affect it
+		let hash = if self.content_addressed {
+			Some(file_hash(self.hasher.hash(&self.fs.read(path)?)))
+		} else {
+			None
+		};
+
+		self.ensure_space(self.trailer_len(), false)?;
+		{
+			let mut file = self.fs.append(path)?;
+			write!(
+				file,
+				"],\"sentAt\":\"{}\",\"writeKey\":\"{}\"}}",
+				self.clock.now().format("%Y-%m-%dT%H:%M:%S.%3fZ"),
+				self.config.write_key
+			)?;
+			file.flush()?;
+		}
+
+		// Run validation if configured
+		if let Some(validator) = &self.file_validator {
+			validator(path)?;
+		}
+
+		// Rename to .temp to mark as complete
+		let new_path = match hash {
+			Some(hash) => {
+				if self.has_finalized_hash(&hash)? {
+					// Identical batch already finalized - drop this one
+					self.remove_attachments(path);
+					return self.fs.remove_file(path);
+				}
+				self.config.storage_location.join(format!(
+					"{}-{}.{}",
+					file_name(path),
+					hash,
+					Self::TEMP_EXTENSION
+				))
+			}
+			None => self
+				.config
+				.storage_location
+				.join(file_name(&path.with_extension(Self::TEMP_EXTENSION))),
+		};
+		self.fs.rename(path, &new_path)?;
+
+		Ok(())
+	}
+
+	/// Applies the store's filters to an event about to be written, returning `None` if
+	/// it must not be written
+	fn prepare(&self, mut data: Value) -> Option<Value> {
+		// Re-adding an envelope that was already delivered would duplicate it server-side
+		if self.delivered.is_redelivery(&data) {
+			return None;
+		}
+		self.field_filter.apply(&mut data);
+		if let Some(migrations) = &self.migrations {
+			migrations.stamp(&mut data);
+		}
+		received::stamp(&mut data, self.received_at.as_deref(), self.clock.now());
+		Some(data)
+	}
+
+	/// Serializes events into the scratch buffer, returning where each one ends.
+	///
+	/// The buffer is taken out of the store so the events can be written while it's
+	/// borrowed; hand it back with [`return_scratch`](Self::return_scratch).
+	fn serialize_to_scratch(&mut self, events: &[Value]) -> Result<(Vec<u8>, Vec<usize>)> {
+		let mut scratch = std::mem::take(&mut self.scratch);
+		scratch.clear();
+		let mut ends = Vec::with_capacity(events.len());
+		for event in events {
+			if let Err(e) = self.json_format.write(&mut scratch, event) {
+				self.return_scratch(scratch);
+				return Err(e);
+			}
+			ends.push(scratch.len());
+		}
+		Ok((scratch, ends))
+	}
+
+	/// Puts the scratch buffer back, shrinking it if a large append grew it.
+	fn return_scratch(&mut self, mut scratch: Vec<u8>) {
+		if scratch.capacity() > self.scratch_capacity {
+			scratch.clear();
+			scratch.shrink_to(self.scratch_capacity);
+		}
+		self.scratch = scratch;
+	}
+
+	/// Adds serialized events to the current file, starting a file if there is none.
+	/// They are written with a single vectored write, or buffered, depending on the
+	/// flush policy.
+	fn write_events(&mut self, events: &[&[u8]]) -> Result<()> {
+		if events.is_empty() {
+			return Ok(());
+		}
+		self.start_file_if_needed()?;
+		let has_events = self.current_size > Self::HEADER.len();
+		self.current_size += events.iter().map(|event| event.len()).sum::<usize>();
+
+		let mut parts: Vec<&[u8]> = Vec::with_capacity(events.len() * 2 + 1);
+		parts.push(&self.buffer);
+		for (i, event) in events.iter().enumerate() {
+			if has_events || i > 0 {
+				parts.push(b",");
+			}
+			parts.push(event);
+		}
+
+		if let FlushPolicy::Buffered(max_bytes) = self.flush_policy {
+			let buffered: usize = parts.iter().map(|part| part.len()).sum();
+			if buffered < max_bytes {
+				let events = parts[1..].concat();
+				self.buffer.extend_from_slice(&events);
+				return Ok(());
+			}
+		}
+
+		let writer = self
+			.writer
+			.as_mut()
+			.ok_or_else(|| io::Error::other("No active writer"))?;
+		write_all_vectored(writer, &parts)?;
+		writer.flush()?;
+		self.buffer.clear();
+		Ok(())
+	}
+
+	/// Writes a serialized appended event, making room for it and rotating first.
+	fn write_appended(&mut self, event: &[u8]) -> Result<()> {
+		// Leave room for the separator and the trailer written when the file is finalized
+		self.ensure_space(event.len() + 1 + self.trailer_len(), true)?;
+
+		if self.writer.is_some() && self.current_size >= self.config.max_file_size {
+			self.finish_file()?;
+		}
+		self.write_events(&[event])
+	}
+
+	/// Writes serialized appended events ending at `ends` in `serialized`, with a single
+	/// write per file, rotating like `append` where the group crosses `max_file_size`.
+	fn write_appended_many(&mut self, serialized: &[u8], ends: &[usize]) -> Result<()> {
+		let mut events: Vec<&[u8]> = Vec::with_capacity(ends.len());
+		let mut event_start = 0;
+		for &end in ends {
+			events.push(&serialized[event_start..end]);
+			event_start = end;
+		}
+		let bytes: usize = events.iter().map(|event| event.len() + 1).sum();
+		self.ensure_space(bytes + self.trailer_len(), true)?;
+
+		let mut start = 0;
+		let mut size = match self.writer {
+			Some(_) => self.current_size,
+			None => Self::HEADER.len(),
+		};
+		for (i, event) in events.iter().enumerate() {
+			if size >= self.config.max_file_size {
+				self.write_events(&events[start..i])?;
+				self.finish_file()?;
+				start = i;
+				size = Self::HEADER.len();
+			}
+			size += event.len();
+		}
+		self.write_events(&events[start..])
+	}
+
+	/// Bytes of events buffered for `path`
+	fn buffered_len(&self, path: &Path) -> u64 {
+		if Some(path) == self.current_path.as_deref() {
+			self.buffer.len() as u64
+		} else {
+			0
+		}
+	}
+
+	/// Writes buffered events to the current file.
+	fn flush_buffer(&mut self) -> Result<()> {
+		if self.buffer.is_empty() {
+			return Ok(());
+		}
+		let writer = self
+			.writer
+			.as_mut()
+			.ok_or_else(|| io::Error::other("No active writer"))?;
+		writer.write_all(&self.buffer)?;
+		writer.flush()?;
+		self.buffer.clear();
+		Ok(())
+	}
+
+	/// Whether events have been written to the file being written
+	fn has_unfinished_events(&self) -> bool {
+		self.writer.is_some() && self.current_size > Self::HEADER.len()
+	}
+
+	/// Finalizes the file being written. A file without events yet (started for a
+	/// reservation) is kept open instead.
+	///
+	/// While the store is quiesced the file stays open, and keeps growing past
+	/// `max_file_size` until the guard is dropped.
+	fn finish_file(&mut self) -> Result<()> {
+		if !self.has_unfinished_events() || self.is_quiesced() {
+			return Ok(());
+		}
+		self.flush_buffer()?;
+		let writer = match self.writer.take() {
+			Some(mut writer) => {
+				writer.flush()?;
+				writer
+			}
+			None => return Ok(()),
+		};
+
+		// Drop the writer to close the file
+		drop(writer);
+
+		// Use the stored path
+		if let Some(current_path) = self.current_path.take() {
+			self.finalize_file(&current_path)?;
+		}
+
+		self.current_size = 0;
+		// Reserved events must not have to start a file when committed
+		if self.reserved_bytes > 0 {
+			self.start_file_if_needed()?;
+		}
+		Ok(())
+	}
+
+	fn sorted_files(&self, include_unfinished: bool) -> Result<Vec<PathBuf>> {
+		let delivered_log = self.delivered_log_path();
+		let cursor_file = self.cursor_path();
+		let manifest = self.manifest_path();
+		let drop_log = self.drop_log_path();
+		let removed_log = self.removed_log_path();
+		let mut entries = self.fs.read_dir(&self.config.storage_location)?;
+		if let Some(staging) = self
+			.staging_location
+			.as_ref()
+			.filter(|_| include_unfinished)
+		{
+			entries.extend(self.fs.read_dir(staging)?);
+		}
+		let mut files: Vec<PathBuf> = entries
+			.into_iter()
+			.filter(|e| !e.is_symlink)
+			.map(|e| e.path)
+			.filter(|p| {
+				*p != delivered_log
+					&& *p != cursor_file
+					&& *p != manifest
+					&& *p != drop_log
+					&& *p != removed_log
+			})
+			.filter(|p| file_name(p) != Self::CACHEDIR_TAG)
+			.filter(|p| {
+				if include_unfinished {
+					true
+				} else {
+					p.extension().and_then(|ext| ext.to_str()) == Some(Self::TEMP_EXTENSION)
+				}
+			})
+			.collect();
+
+		// Order data files by their numeric index, so "10-events" comes after "9-events"
+		files.sort_by(|a, b| {
+			let index = |p: &Path| Self::file_index(p).and_then(|i| i.parse::<u32>().ok());
+			index(a).cmp(&index(b)).then_with(|| {
+				a.file_name()
+					.unwrap_or_default()
+					.cmp(b.file_name().unwrap_or_default())
+			})
+		});
+		Ok(files)
+	}
+
+	fn up_to_size(&self, max_bytes: usize, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+		let mut result = Vec::new();
+		let mut total_size: u64 = 0;
+
+		for file in files {
+			if let Ok(metadata) = self.fs.metadata(file) {
+				let size = metadata.len;
+				if total_size + size <= max_bytes as u64 {
+					result.push(file.clone());
+					total_size += size;
+				} else {
+					break;
+				}
+			}
+		}
+
+		Ok(result)
+	}
+}
+
+impl DataStore for DirectoryStore {
+	type Output = Vec<PathBuf>;
+
+	fn has_data(&self) -> bool {
+		// Check if we have an active writer with data
+		if self.has_unfinished_events() {
+			return true;
+		}
+
+		// Check directory for the store's own data files, or handoff files the next
+		// fetch will adopt. Rejected and staged files are never fetched.
+		self.fs
+			.read_dir(&self.config.storage_location)
+			.map(|entries| {
+				entries.iter().any(|e| {
+					// The file being written has no events, or we'd have returned above
+					if e.is_symlink || Some(&e.path) == self.current_path.as_ref() {
+						return false;
+					}
+					self.own_index(&e.path).is_some()
+						|| (self.accept_external && self.is_handoff(&e.path))
+				})
+			})
+			.unwrap_or(false)
+	}
+
+	fn reset(&mut self) {
+		if self.read_only {
+			logging::log_warn!("Not resetting a read-only store");
+			return;
+		}
+		if self.is_quiesced() {
+			logging::log_warn!("Not resetting a quiesced store");
+			return;
+		}
+		// Abandon the file being written, so later appends don't go to a deleted file
+		self.writer = None;
+		self.buffer.clear();
+		self.current_path = None;
+		self.current_size = 0;
+		self.reserved_bytes = 0;
+
+		if let Ok(files) = self.sorted_files(true) {
+			let _ = self.remove(
+				&files
+					.iter()
+					.map(|p| Box::new(p.clone()) as Box<dyn Equivalent>)
+					.collect::<Vec<_>>(),
+			);
+		}
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		let Some(data) = self.prepare(data) else {
+			return Ok(());
+		};
+
+		let (scratch, _) = self.serialize_to_scratch(std::slice::from_ref(&data))?;
+		let result = self.write_appended(&scratch);
+		self.return_scratch(scratch);
+		result
+	}
+
+	/// Serializes the whole group into the scratch buffer before writing it.
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		let items: Vec<Value> = items
+			.into_iter()
+			.filter_map(|data| self.prepare(data))
+			.collect();
+		let (scratch, ends) = self.serialize_to_scratch(&items)?;
+		let result = self.write_appended_many(&scratch, &ends);
+		self.return_scratch(scratch);
+		result
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.flush_buffer()
+	}
+
+	fn warm_up(&mut self) -> Result<()> {
+		self.start_file_if_needed()?;
+		self.scratch
+			.reserve(self.scratch_capacity.saturating_sub(self.scratch.len()));
+		if let FlushPolicy::Buffered(max_bytes) = self.flush_policy {
+			self.buffer
+				.reserve(max_bytes.saturating_sub(self.buffer.len()));
+		}
+		Ok(())
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		if self.writer.is_some() {
+			self.finish_file()?;
+		}
+
+		if self.accept_external {
+			self.adopt_external_files()?;
+		}
+
+		let mut files = self.sorted_files(false)?;
+
+		// Remove files a previous drain delivered but didn't get to remove
+		let through = self
+			.cursor
+			.as_ref()
+			.and_then(|c| c.delivered_through)
+			.filter(|_| !self.is_quiesced());
+		if let Some(through) = through {
+			let (delivered, pending): (Vec<PathBuf>, Vec<PathBuf>) =
+				files.into_iter().partition(|file| {
+					Self::file_index(file)
+						.and_then(|index| index.parse::<u32>().ok())
+						.is_some_and(|index| index <= through)
+				});
+			for file in delivered {
+				if let Err(e) = self.fs.remove_file(&file) {
+					logging::log_warn!("Failed to remove delivered file {:?}: {}", file, e);
+				}
+				self.remove_attachments(&file);
+			}
+			files = pending;
+		}
+
+		let mut files = self.in_fetch_order(files);
+		let pending = files.clone();
+		if let Some(max_bytes) = max_bytes {
+			files = self.up_to_size(max_bytes, &files)?;
+		}
+
+		if let Some(count) = count {
+			files.truncate(count);
+		}
+
+		if files.is_empty() {
+			return Ok(None);
+		}
+
+		let fetched: HashSet<&PathBuf> = files.iter().collect();
+		let (remaining_items, remaining_bytes) = pending
+			.iter()
+			.filter(|file| !fetched.contains(file))
+			.filter_map(|file| self.fs.metadata(file).ok())
+			.fold((0, 0), |(count, bytes), metadata| {
+				(count + 1, bytes + metadata.len)
+			});
+
+		// Read-only stores return files as they are
+		if let Some(migrations) = self.migrations.as_ref().filter(|_| !self.read_only) {
+			for file in &files {
+				self.migrate_file(migrations, file);
+			}
+		}
+
+		let removable = files
+			.iter()
+			.map(|p| Box::new(p.clone()) as Box<dyn Equivalent>)
+			.collect::<Vec<_>>();
+
+		let attachments = self.attachment_handles(&files);
+
+		let names: Vec<String> = files
+			.iter()
+			.filter_map(|f| f.file_name()?.to_str().map(str::to_string))
+			.collect();
+		// A retry of a batch that wasn't removed keeps its id
+		let batch_id = match &mut self.cursor {
+			Some(cursor) => match &cursor.in_flight {
+				Some((batch_id, in_flight)) if *in_flight == names => batch_id.clone(),
+				_ => {
+					let batch_id = new_uuid();
+					cursor.in_flight = Some((batch_id.clone(), names));
+					self.save_cursor()?;
+					batch_id
+				}
+			},
+			None => self.in_flight.id_for(names, |a, b| a == b),
+		};
+		if self.retain_removed > 0 {
+			self.fetched.record(&batch_id, Arc::new(files[0].clone()));
+		}
+
+		Ok(Some(DataResult {
+			data: Some(files),
+			removable: Some(removable),
+			batch_id: Some(batch_id),
+			attachments,
+			remaining_items,
+			remaining_bytes,
+		}))
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		self.check_writable()?;
+		let mut file_items: Vec<(PathBuf, Vec<usize>)> = Vec::new();
+
+		let mut removed_events = Vec::new();
+
+		for item in data {
+			if let Some(path) = item.as_any().downcast_ref::<PathBuf>() {
+				let path = self.resolve_path(path);
+				if self.retain_removed > 0 {
+					// Unfinished files can't be read as batches, and have nothing uploaded
+					removed_events.extend(self.read_batch(&path).unwrap_or_default());
+				}
+				if let Err(e) = self.fs.remove_file(&path) {
+					logging::log_warn!("Failed to remove file {:?}: {}", path, e);
+				}
+				self.remove_attachments(&path);
+			} else if let Some(file_item) = item.as_any().downcast_ref::<FileItem>() {
+				let path = self.resolve_path(&file_item.path);
+				match file_items.iter_mut().find(|(p, _)| *p == path) {
+					Some((_, indices)) => indices.push(file_item.index),
+					None => file_items.push((path, vec![file_item.index])),
+				}
+			}
+		}
+
+		for (path, indices) in file_items {
+			// The whole file may already be gone if it was also removed by path
+			if self.fs.metadata(&path).is_ok() {
+				if self.retain_removed > 0 {
+					let batch = self.read_batch(&path).unwrap_or_default();
+					removed_events.extend(
+						indices
+							.iter()
+							.filter_map(|&index| batch.get(index).cloned()),
+					);
+				}
+				self.remove_file_items(&path, &indices)?;
+			}
+		}
+
+		// Files removed or rewritten can't be fetched again as the same batch
+		let touched: HashSet<&str> = data
+			.iter()
+			.filter_map(|item| {
+				let any = item.as_any();
+				any.downcast_ref::<PathBuf>()
+					.or_else(|| any.downcast_ref::<FileItem>().map(|item| &item.path))
+			})
+			.filter_map(|path| path.file_name()?.to_str())
+			.collect();
+		self.in_flight
+			.forget(|name| touched.contains(name.as_str()));
+
+		if self.retain_removed > 0 {
+			let batch_id = self.fetched.take(data);
+			self.retain_removed_batch(batch_id, removed_events);
+		}
+		Ok(())
+	}
+
+	fn debug_config(&self) -> Value {
+		json!({
+			"store": "DirectoryStore",
+			"writeKey": self.config.write_key,
+			"storageLocation": self.config.storage_location,
+			"stagingLocation": self.staging_location,
+			"baseFilename": self.config.base_filename,
+			"maxFileSize": self.config.max_file_size,
+			"jsonFormat": format!("{:?}", self.json_format),
+			"contentAddressed": self.content_addressed,
+			"hasher": self.hasher.name(),
+			"diskReserve": self.disk_reserve,
+			"diskFullPolicy": format!("{:?}", self.disk_full_policy),
+			"flushPolicy": format!("{:?}", self.flush_policy),
+			"fetchOrder": format!("{:?}", self.fetch_order),
+			"readOnly": self.read_only,
+			"scratchCapacity": self.scratch_capacity,
+			"acceptExternal": self.accept_external,
+			"deliveryCursor": self.cursor.is_some(),
+			"hasFileValidator": self.file_validator.is_some(),
+			"fieldFilter": self.field_filter.to_json(),
+			"schema": self.migrations.as_ref().map(SchemaMigrations::to_json),
+			"receivedAtField": self.received_at,
+		})
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		match capability {
+			Capability::Attachments
+			| Capability::Reservation
+			| Capability::Delivery
+			| Capability::EnqueueTimes
+			| Capability::PendingSize
+			| Capability::Preview
+			| Capability::PendingEvents
+			| Capability::DropReport
+			| Capability::MoveDestination
+			| Capability::RemovedBatches
+			| Capability::Status
+			| Capability::Expire => true,
+			#[cfg(feature = "anonymize")]
+			Capability::Anonymize => true,
+			Capability::Pinning
+			| Capability::Routing
+			| Capability::Delayed
+			| Capability::Upsert
+			| Capability::Consent
+			| Capability::MoveSource
+			| Capability::WriteBatchTo
+			| Capability::FetchOlderThan => false,
+		}
+	}
+}
+
+impl DataStoreExt for DirectoryStore {
+	fn reserve(&mut self, estimated_bytes: usize) -> Result<()> {
+		self.ensure_space(estimated_bytes + 1 + self.trailer_len(), true)?;
+
+		// Rotate now if the reserved events wouldn't fit in the current file
+		let reserved = self.reserved_bytes + estimated_bytes + 1;
+		if self.has_unfinished_events() && self.current_size + reserved > self.config.max_file_size
+		{
+			self.finish_file()?;
+		}
+		self.start_file_if_needed()?;
+
+		self.reserved_bytes += estimated_bytes;
+		Ok(())
+	}
+
+	fn commit_reserved(&mut self, data: Value, estimated_bytes: usize) -> Result<()> {
+		self.reserved_bytes = self.reserved_bytes.saturating_sub(estimated_bytes);
+		let Some(data) = self.prepare(data) else {
+			return Ok(());
+		};
+		let (scratch, _) = self.serialize_to_scratch(std::slice::from_ref(&data))?;
+		let result = self.write_events(&[&scratch]);
+		self.return_scratch(scratch);
+		result
+	}
+
+	fn release_reserved(&mut self, estimated_bytes: usize) {
+		self.reserved_bytes = self.reserved_bytes.saturating_sub(estimated_bytes);
+	}
+
+	fn append_with_attachments(
+		&mut self,
+		mut data: Value,
+		attachments: Vec<Attachment>,
+	) -> Result<()> {
+		if self.delivered.is_redelivery(&data) {
+			return Ok(());
+		}
+
+		let attachments = attachment::attach(&mut data, attachments)?;
+		let attachment_bytes: usize = attachments.iter().map(|(_, a)| a.data.len()).sum();
+		self.ensure_space(attachment_bytes, true)?;
+		self.append(data)?;
+		// The event is now in the current file; its attachments are named after it
+		self.write_attachments(&attachments)
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		if self.delivered.insert(batch_id) {
+			self.save_delivered()?;
+		}
+
+		// Files fetched out of index order can't move the cursor past older files still
+		// pending, which it would otherwise count as delivered
+		let oldest_pending = match &self.cursor {
+			Some(DeliveryCursor {
+				in_flight: Some((in_flight_id, files)),
+				..
+			}) if in_flight_id == batch_id => self
+				.sorted_files(false)?
+				.iter()
+				.filter(|file| {
+					file.file_name()
+						.and_then(|name| name.to_str())
+						.is_some_and(|name| !files.iter().any(|f| f == name))
+				})
+				.filter_map(|file| Self::file_index(file)?.parse::<u32>().ok())
+				.min(),
+			_ => None,
+		};
+
+		if let Some(cursor) = &mut self.cursor {
+			if let Some((in_flight_id, files)) = &cursor.in_flight {
+				if in_flight_id == batch_id {
+					let newest = files
+						.iter()
+						.filter_map(|f| Self::file_index(Path::new(f))?.parse::<u32>().ok())
+						.filter(|index| match oldest_pending {
+							Some(oldest) => *index < oldest,
+							None => true,
+						})
+						.max();
+					cursor.delivered_through = cursor.delivered_through.max(newest);
+					cursor.in_flight = None;
+					self.save_cursor()?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.delivered.contains(batch_id)
+	}
+
+	/// Returns one time per data file (not per event): when the file was created,
+	/// which is when its first event was appended.
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		Ok(self
+			.sorted_files(true)?
+			.iter()
+			.filter(|file| Self::file_index(file).is_some())
+			.filter_map(|file| self.created_at(file))
+			.collect())
+	}
+
+	/// Reads events from finished and in-progress data files.
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		let mut events = Vec::new();
+		for file in self.sorted_files(true)? {
+			if Self::file_index(&file).is_none() {
+				continue;
+			}
+			events.extend(self.file_events(&file)?);
+		}
+		Ok(events)
+	}
+
+	/// Counts data files (finished and in progress) and their size on disk.
+	fn pending_size(&self) -> Result<PendingSize> {
+		let mut size = PendingSize::default();
+		for file in self.sorted_files(true)? {
+			if Self::file_index(&file).is_none() {
+				continue;
+			}
+			if let Ok(metadata) = self.fs.metadata(&file) {
+				size.items += 1;
+				size.bytes += metadata.len + self.buffered_len(&file);
+			}
+		}
+		Ok(size)
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.sorted_files(true)
+			.ok()?
+			.iter()
+			.filter(|file| Self::file_index(file).is_some())
+			.find_map(|file| self.created_at(file))
+	}
+
+	/// Mirrors fetch's file selection without finishing the current file or removing
+	/// delivered ones. External files not adopted yet aren't counted.
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		let mut files = self.undelivered_files()?;
+		// Fetch finishes the file being written first
+		if self.has_unfinished_events() {
+			files.extend(self.current_path.clone());
+		}
+		let mut files = self.in_fetch_order(files);
+		if let Some(max_bytes) = max_bytes {
+			files = self.up_to_size(max_bytes, &files)?;
+		}
+		if let Some(count) = count {
+			files.truncate(count);
+		}
+
+		let mut preview = BatchPreview::default();
+		for file in &files {
+			if let Ok(metadata) = self.fs.metadata(file) {
+				preview.add(metadata.len + self.buffered_len(file), metadata.created);
+			}
+		}
+		Ok(preview)
+	}
+
+	/// Finishes the current file, then rewrites every data file holding an anonymized
+	/// event. Files that can't be read or parsed are skipped and left in place.
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		self.check_writable()?;
+		if self.writer.is_some() {
+			self.finish_file()?;
+		}
+
+		let mut changed = 0;
+		for path in self.sorted_files(false)? {
+			let mut content: Value = match self
+				.fs
+				.read(&path)
+				.and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+			{
+				Ok(content) => content,
+				Err(e) => {
+					logging::log_warn!("Failed to read file {:?}: {}", path, e);
+					continue;
+				}
+			};
+			let Some(batch) = content.get_mut("batch").and_then(Value::as_array_mut) else {
+				continue;
+			};
+			let in_file = batch
+				.iter_mut()
+				.map(|item| anonymizer.anonymize(item))
+				.filter(|changed| *changed)
+				.count();
+			if in_file > 0 {
+				self.rewrite_file(&path, &content)?;
+				changed += in_file;
+			}
+		}
+		Ok(changed)
+	}
+
+	/// Returns the drops recorded in the store's hidden `.{base_filename}-drops.json`
+	/// file, which survives restarts, and deletes it.
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		let mut drops = self.load_drops();
+		match self.fs.remove_file(&self.drop_log_path()) {
+			Ok(()) => Ok(drops.take()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Writes the events to a hidden `.{move_id}.{base_filename}.staged` file, which
+	/// releasing renames into the queue like a finalized data file.
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		self.check_writable()?;
+		let path = self.staged_path(move_id)?;
+		let events: Vec<Value> = items
+			.into_iter()
+			.filter_map(|data| self.prepare(data))
+			.collect();
+		let content = self.batch_file_content(events);
+		self.write_batch_file(&path, &content)
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		let path = self.staged_path(move_id)?;
+		if self.fs.metadata(&path).is_err() {
+			return Ok(());
+		}
+		let new_path = self.config.storage_location.join(format!(
+			"{}-{}.{}",
+			self.next_index(),
+			self.config.base_filename,
+			Self::TEMP_EXTENSION
+		));
+		self.fs.rename(&path, &new_path)
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		match self.fs.remove_file(&self.staged_path(move_id)?) {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+			_ => Ok(()),
+		}
+	}
+
+	/// Reads the retained batches from the store's hidden `.{base_filename}-removed.json`
+	/// file.
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		Ok(self.load_removed().batches())
+	}
+
+	/// Writes the events to a data file of their own, whose envelope is flagged with
+	/// `"replay": true`.
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		self.check_writable()?;
+		let mut events = self
+			.load_removed()
+			.events_of(batch_id)
+			.ok_or_else(|| replay::not_retained(batch_id))?
+			.to_vec();
+		for event in &mut events {
+			replay::tag(event, batch_id)?;
+		}
+		let count = events.len();
+
+		let mut content = self.batch_file_content(events);
+		replay::flag(&mut content);
+		let path = self.config.storage_location.join(format!(
+			"{}-{}.{}",
+			self.next_index(),
+			self.config.base_filename,
+			Self::TEMP_EXTENSION
+		));
+		self.write_batch_file(&path, &content)?;
+		Ok(count)
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		let suffix = format!(".{}.{}", self.config.base_filename, Self::STAGED_EXTENSION);
+		Ok(self
+			.fs
+			.read_dir(&self.config.storage_location)?
+			.into_iter()
+			.filter(|e| e.is_file)
+			.filter_map(|e| {
+				file_name(&e.path)
+					.strip_prefix('.')?
+					.strip_suffix(suffix.as_str())
+					.map(str::to_string)
+			})
+			.collect())
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		Ok(StoreStatus::Persisted)
+	}
+
+	/// Deletes finalized files finished before the cutoff, and drops old events from the
+	/// file finished after it by their own time: their `timestamp`, or the field set with
+	/// [`set_received_at_field`](DirectoryStore::set_received_at_field). Events without
+	/// either are kept, as is the file being written.
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.check_writable()?;
+		let now = self.clock.now();
+		let Some(cutoff) = TimeDelta::from_std(age)
+			.ok()
+			.and_then(|age| now.checked_sub_signed(age))
+		else {
+			return Ok(0);
+		};
+
+		let mut expired = 0;
+		for path in self.sorted_files(false)? {
+			if Self::file_index(&path).is_none() {
+				continue;
+			}
+			let Some(created) = self.created_at(&path) else {
+				continue;
+			};
+			if created > cutoff {
+				// Files are started in order, so the rest are newer
+				break;
+			}
+			let Ok(content) = serde_json::from_slice::<Value>(&self.fs.read(&path)?) else {
+				continue;
+			};
+			let Some(batch) = content.get("batch").and_then(Value::as_array) else {
+				continue;
+			};
+			let finalized = content
+				.get("sentAt")
+				.and_then(Value::as_str)
+				.and_then(parse_time)
+				.unwrap_or(now);
+
+			if finalized <= cutoff {
+				// Every event was written before the file was finalized
+				self.fs.remove_file(&path)?;
+				self.remove_attachments(&path);
+				self.record_drop(DropReason::Expired, batch.len(), created, finalized);
+				if let Some(listener) = &self.eviction_listener {
+					listener.notify(DropReason::Expired, batch, batch.len());
+				}
+				expired += batch.len();
+				continue;
+			}
+
+			let stale: Vec<(usize, DateTime<Utc>)> = batch
+				.iter()
+				.enumerate()
+				.filter_map(|(position, event)| Some((position, self.event_time(event)?)))
+				.filter(|(_, time)| *time <= cutoff)
+				.collect();
+			let (Some(first), Some(last)) = (
+				stale.iter().map(|(_, time)| *time).min(),
+				stale.iter().map(|(_, time)| *time).max(),
+			) else {
+				continue;
+			};
+			let positions: Vec<usize> = stale.iter().map(|(position, _)| *position).collect();
+			self.remove_file_items(&path, &positions)?;
+			self.record_drop(DropReason::Expired, positions.len(), first, last);
+			if let Some(listener) = &self.eviction_listener {
+				listener.notify(
+					DropReason::Expired,
+					positions.iter().map(|&position| &batch[position]),
+					positions.len(),
+				);
+			}
+			expired += positions.len();
+		}
+		Ok(expired)
+	}
+
+	/// Fetches return one removable per data file; these are split into one per event
+	fn event_removables(
+		&self,
+		removable: Vec<Box<dyn Equivalent>>,
+	) -> Result<Vec<Box<dyn Equivalent>>> {
+		let mut events = Vec::with_capacity(removable.len());
+		for item in removable {
+			match item.as_any().downcast_ref::<PathBuf>() {
+				Some(path) => events.extend(self.item_removables(path)?),
+				None => events.push(item),
+			}
+		}
+		Ok(events)
+	}
+}
+
+impl Drop for DirectoryStore {
+	fn drop(&mut self) {
+		if let Err(e) = self.flush_buffer() {
+			logging::log_warn!("Failed to write buffered events: {}", e);
+		}
+	}
+}
+
+/// A [`DirectoryStore`] whose fetches return the events themselves instead of file paths.
+///
+/// The fetched files are read and their events combined into a single
+/// `{"batch": [...], "sentAt": ..., "writeKey": ..., "batchId": ...}` envelope, the same
+/// shape MemoryStore and WebStore return, so uploaders can treat every store alike and
+/// never handle paths (which may not stay valid, e.g. when iOS moves the app container).
+///
+/// Removable items line up one-to-one with the events in the batch, so the result can be
+/// split with [`BatchChunker`](crate::BatchChunker) or partially acknowledged with
+/// [`TransientDB::remove_accepted`](crate::TransientDB::remove_accepted).
+///
+/// Files that can't be read or parsed are skipped and left in place.
+///
+/// Events in consent categories that aren't allowed (see
+/// [`TransientDB::set_allowed_categories`](crate::TransientDB::set_allowed_categories))
+/// are left out of the batch, but the files holding them still count toward the fetch
+/// limits until they are removed with
+/// [`TransientDB::purge_revoked`](crate::TransientDB::purge_revoked).
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use serde_json::json;
+/// use transientdb::{DirectoryConfig, DirectoryContentStore, DirectoryStore, TransientDB};
+///
+/// let store = DirectoryStore::new(DirectoryConfig {
+///     write_key: "test".into(),
+///     storage_location: PathBuf::from("/tmp/data-contents"),
+///     base_filename: "events".into(),
+///     max_file_size: 1024,
+/// })?;
+/// let db = TransientDB::new(DirectoryContentStore::new(store));
+/// db.reset();
+///
+/// db.append(json!({"event": "test"}))?;
+///
+/// let result = db.fetch(None, None)?.unwrap();
+/// let batch = result.data.unwrap();
+/// assert_eq!(batch["batch"][0]["event"], "test");
+///
+/// db.remove(&result.removable.unwrap())?;
+/// assert!(!db.has_data());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct DirectoryContentStore {
+	store: DirectoryStore,
+	consent: ConsentFilter,
+	/// Supplies the context added to fetched envelopes
+	context: Option<Box<dyn ContextProvider>>,
+	/// Supplies the offset fetched timestamps are corrected by
+	clock_offset: Option<Box<dyn ClockOffsetProvider>>,
+}
+
+impl DirectoryContentStore {
+	/// Wraps a DirectoryStore so that fetches return event contents.
+	pub fn new(store: DirectoryStore) -> Self {
+		Self {
+			store,
+			consent: ConsentFilter::default(),
+			context: None,
+			clock_offset: None,
+		}
+	}
+
+	/// Sets the provider of the `context` object added to each fetched batch envelope,
+	/// see [`ContextProvider`].
+	pub fn set_context_provider<P: ContextProvider + 'static>(&mut self, provider: P) {
+		self.context = Some(Box::new(provider));
+	}
+
+	/// Sets the provider of the offset from the server's clock that fetched batches'
+	/// `sentAt` and event timestamps are corrected by, see [`ClockOffsetProvider`].
+	pub fn set_clock_offset_provider<P: ClockOffsetProvider + 'static>(&mut self, provider: P) {
+		self.clock_offset = Some(Box::new(provider));
+	}
+
+	/// Returns the wrapped store.
+	pub fn inner(&self) -> &DirectoryStore {
+		&self.store
+	}
+
+	/// Returns the wrapped store mutably, e.g. to change its settings.
+	pub fn inner_mut(&mut self) -> &mut DirectoryStore {
+		&mut self.store
+	}
+
+	/// Unwraps the store.
+	pub fn into_inner(self) -> DirectoryStore {
+		self.store
+	}
+
+	/// Reads the events of the next batch like [`fetch`](DataStore::fetch), passing them
+	/// and the other envelope fields to `build` for the result's data.
+	fn fetch_with<O>(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+		build: impl FnOnce(&Self, Vec<Value>, Value) -> Result<O>,
+	) -> Result<Option<DataResult<O>>> {
+		let Some(result) = self.store.fetch(count, max_bytes)? else {
+			return Ok(None);
+		};
+
+		let mut items = Vec::new();
+		let mut removable: Vec<Box<dyn Equivalent>> = Vec::new();
+		let paths = result.data.unwrap_or_default();
+		let batches = self.store.read_batches(&paths);
+		for (path, batch) in paths.into_iter().zip(batches) {
+			let batch = match batch {
+				Ok(batch) => batch,
+				Err(e) => {
+					logging::log_warn!("Failed to read file {:?}: {}", path, e);
+					continue;
+				}
+			};
+			for (index, item) in batch.into_iter().enumerate() {
+				if !self.consent.allows(&item) {
+					continue;
+				}
+				removable.push(Box::new(FileItem {
+					path: path.clone(),
+					index,
+				}));
+				items.push(item);
+			}
+		}
+
+		if items.is_empty() {
+			return Ok(None);
+		}
+
+		// Leave out attachments of events skipped for lack of consent
+		let attachments = result.attachments.map(|handles| {
+			let referenced: HashSet<&str> =
+				items.iter().flat_map(attachment::referenced_ids).collect();
+			handles
+				.into_iter()
+				.filter(|handle| referenced.contains(handle.id.as_str()))
+				.collect::<Vec<_>>()
+		});
+
+		let offset = skew::offset(self.clock_offset.as_deref());
+		for item in &mut items {
+			skew::correct_in_place(item, offset);
+		}
+
+		let batch_id = result.batch_id.unwrap_or_else(new_uuid);
+		let sent_at = self.store.clock.now() + offset.unwrap_or_else(TimeDelta::zero);
+		let mut fields = serde_json::json!({
+			"sentAt": sent_at.to_rfc3339(),
+			"writeKey": self.store.config.write_key,
+			"batchId": batch_id
+		});
+		context::add_to(&mut fields, self.context.as_deref());
+		let data = build(self, items, fields)?;
+
+		Ok(Some(DataResult {
+			data: Some(data),
+			removable: Some(removable),
+			batch_id: Some(batch_id),
+			attachments: attachments.filter(|handles| !handles.is_empty()),
+			remaining_items: result.remaining_items,
+			remaining_bytes: result.remaining_bytes,
+		}))
+	}
+}
+
+impl From<DirectoryStore> for DirectoryContentStore {
+	fn from(store: DirectoryStore) -> Self {
+		Self::new(store)
+	}
+}
+
+impl DataStore for DirectoryContentStore {
+	type Output = Value;
+
+	fn has_data(&self) -> bool {
+		self.store.has_data()
+	}
+
+	fn reset(&mut self) {
+		self.store.reset()
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		self.store.append(data)
+	}
+
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		self.store.append_many(items)
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.store.flush()
+	}
+
+	fn warm_up(&mut self) -> Result<()> {
+		self.store.warm_up()
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		self.fetch_with(count, max_bytes, |store, items, fields| {
+			let mut envelope = fields;
+			envelope["batch"] = Value::from(items);
+			Ok(store.store.json_format.normalize(envelope))
+		})
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		self.store.remove(data)
+	}
+
+	fn debug_config(&self) -> Value {
+		let mut config = self.store.debug_config();
+		config["store"] = json!("DirectoryContentStore");
+		config["allowedCategories"] = self.consent.to_json();
+		config
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		match capability {
+			Capability::Consent | Capability::WriteBatchTo => true,
+			other => self.store.supports(other),
+		}
+	}
+}
+
+impl DataStoreExt for DirectoryContentStore {
+	fn append_with_attachments(&mut self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		self.store.append_with_attachments(data, attachments)
+	}
+
+	fn append_pinned(&mut self, data: Value) -> Result<()> {
+		self.store.append_pinned(data)
+	}
+
+	fn append_for(&mut self, write_key: &str, data: Value) -> Result<()> {
+		self.store.append_for(write_key, data)
+	}
+
+	fn append_delayed(&mut self, data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		self.store.append_delayed(data, not_before)
+	}
+
+	fn upsert(&mut self, key: &str, data: Value) -> Result<()> {
+		self.store.upsert(key, data)
+	}
+
+	fn reserve(&mut self, estimated_bytes: usize) -> Result<()> {
+		self.store.reserve(estimated_bytes)
+	}
+
+	fn commit_reserved(&mut self, data: Value, estimated_bytes: usize) -> Result<()> {
+		self.store.commit_reserved(data, estimated_bytes)
+	}
+
+	fn release_reserved(&mut self, estimated_bytes: usize) {
+		self.store.release_reserved(estimated_bytes)
+	}
+
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.consent.set(categories);
+		Ok(())
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		self.store.anonymize(anonymizer)
+	}
+
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		self.store.drop_report()
+	}
+
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		self.store.stage_move(move_id, items)
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		self.store.release_move(move_id)
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		self.store.discard_move(move_id)
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		self.store.held_moves()
+	}
+
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		self.store.recently_removed()
+	}
+
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		self.store.replay(batch_id)
+	}
+
+	fn write_batch_to(
+		&mut self,
+		writer: &mut dyn Write,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		self.fetch_with(count, max_bytes, |store, items, fields| {
+			envelope::write(writer, store.store.json_format, items.iter(), fields)
+		})
+	}
+
+	/// Counts data files, like the wrapped store, rather than the events in them.
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		self.store.preview_fetch(count, max_bytes)
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		self.store.check_writable()?;
+		if self.store.writer.is_some() {
+			self.store.finish_file()?;
+		}
+
+		let mut purged = 0;
+		for path in self.store.sorted_files(false)? {
+			let batch = match self.store.read_batch(&path) {
+				Ok(batch) => batch,
+				Err(e) => {
+					logging::log_warn!("Failed to read file {:?}: {}", path, e);
+					continue;
+				}
+			};
+			let revoked: Vec<usize> = batch
+				.iter()
+				.enumerate()
+				.filter(|(_, item)| !self.consent.allows(item))
+				.map(|(index, _)| index)
+				.collect();
+			if !revoked.is_empty() {
+				self.store.remove_file_items(&path, &revoked)?;
+				purged += revoked.len();
+			}
+		}
+		Ok(purged)
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		self.store.mark_delivered(batch_id)
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.store.is_delivered(batch_id)
+	}
+
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		self.store.enqueue_times()
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.store.oldest_enqueue_time()
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		self.store.pending_size()
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		self.store.pending_events()
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		self.store.status()
+	}
+
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.store.expire_older_than(age)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		write_all_vectored, DirectoryConfig, DirectoryContentStore, DirectoryStore, FetchOrder,
+		FlushPolicy, OpenMode,
+	};
+	#[cfg(feature = "anonymize")]
+	use crate::Anonymizer;
+	use crate::{
+		Attachment, ClockOffset, DataStore, DataStoreExt, DropReason, Equivalent, FieldFilter,
+		JsonFormat, JsonPointer, SchemaMigrations, SimClock, SimFs, TimeSource, TransientError,
+	};
+	use chrono::{DateTime, Utc};
+	use serde_json::json;
+	use serde_json::Value;
+	use std::collections::HashSet;
+	use std::fs;
+	use std::io;
+	use std::io::Result;
+	use std::path::PathBuf;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use tempfile::TempDir;
+
+	#[test]
+	fn test_directory_store_basic_operations() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Test append
+		let event = serde_json::json!({
+			"event": "test",
+			"properties": {
+				"value": 123
+			}
+		});
+		store.append(event.clone())?;
+		store.append(event.clone())?;
+
+		// Force file completion
+		if let Some(result) = store.fetch(None, None)? {
+			assert!(result.data.is_some());
+			let files = result.data.unwrap();
+			assert_eq!(files.len(), 1);
+
+			// Verify file content
+			let content = fs::read_to_string(&files[0])?;
+			assert!(content.contains("\"event\":\"test\""));
+			assert!(content.contains("\"writeKey\":\"test-key\""));
+		} else {
+			panic!("Expected data but got none");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_recovers_unfinished_files() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+
+		// Create initial store instance
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		// First store instance - create some unfinished files
+		{
+			let mut store = DirectoryStore::new(config.clone())?;
+
+			// Add some entries but don't finish_file()
+			store.append(json!({"event": "test1", "value": 123}))?;
+			store.append(json!({"event": "test2", "value": 456}))?;
+
+			// Let store drop without explicit finish
+		}
+
+		// Create a finished file to ensure we don't touch it
+		{
+			let mut store = DirectoryStore::new(config.clone())?;
+			store.append(json!({"event": "finished", "value": 789}))?;
+			store.finish_file()?; // Explicitly finish this one
+		}
+
+		// Create new store instance which should trigger recovery
+		let mut store = DirectoryStore::new(config)?;
+
+		// Verify we can read all the data
+		if let Some(result) = store.fetch(None, None)? {
+			let files = result.data.unwrap();
+			assert!(!files.is_empty(), "Should have recovered files");
+
+			// Read and verify contents of all files
+			for path in files {
+				let content = fs::read_to_string(&path)?;
+				let json: Value = serde_json::from_str(&content)?;
+
+				// Verify structure
+				assert!(json.get("batch").is_some(), "Should have batch field");
+				assert!(json.get("sentAt").is_some(), "Should have sentAt field");
+				assert_eq!(
+					json.get("writeKey").and_then(Value::as_str),
+					Some("test-key"),
+					"Should have correct writeKey"
+				);
+
+				// All files should have .temp extension now
+				assert_eq!(
+					path.extension().and_then(|ext| ext.to_str()),
+					Some("temp"),
+					"All files should be finalized"
+				);
+			}
+		}
+
+		// Verify we can still append new data
+		store.append(json!({"event": "new", "value": 999}))?;
+		store.finish_file()?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_file_rotation() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100, // Small size to force rotation
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Add multiple events that should span files
+		for i in 0..5 {
+			let event = serde_json::json!({
+				"event": "test",
+				"index": i,
+				"data": "some longer data to help hit size limit...."
+			});
+			store.append(event)?;
+		}
+
+		// Fetch all files
+		if let Some(result) = store.fetch(None, None)? {
+			let files = result.data.unwrap();
+			assert!(files.len() > 1, "Expected multiple files due to size limit");
+
+			// Verify each file is properly formatted
+			for file in &files {
+				let content = fs::read_to_string(file)?.trim().to_string();
+				assert!(content.starts_with("{ \"batch\": ["));
+				assert!(content.ends_with("}"));
+				assert!(content.contains("\"writeKey\":\"test-key\""));
+
+				// Validate JSON structure
+				let parsed: serde_json::Value = serde_json::from_str(&content)?;
+				assert!(parsed.get("batch").is_some(), "Missing 'batch' field");
+				assert!(parsed.get("writeKey").is_some(), "Missing 'writeKey' field");
+				assert!(parsed.get("sentAt").is_some(), "Missing 'sentAt' field");
+			}
+		} else {
+			panic!("Expected data but got none");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_with_size_limit() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 200,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Create several files of known size
+		for i in 0..5 {
+			let event = serde_json::json!({
+				"event": "test",
+				"index": i,
+				"data": "padding data...."
+			});
+			store.append(event)?;
+		}
+
+		// Fetch with byte limit
+		if let Some(result) = store.fetch(None, Some(250))? {
+			let files = result.data.unwrap();
+			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
+			assert!(total_size <= 250, "Fetch exceeded size limit");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_file_cleanup() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 200,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Add some events
+		let event = serde_json::json!({"event": "test"});
+		store.append(event)?;
+
+		// Fetch and then remove
+		if let Some(result) = store.fetch(None, None)? {
+			let files = result.data.unwrap();
+			let removable = result.removable.unwrap();
+
+			// Verify files exist
+			for file in &files {
+				assert!(file.exists());
+			}
+
+			// Remove the files
+			store.remove(&removable)?;
+
+			// Verify files are gone
+			for file in &files {
+				assert!(!file.exists());
+			}
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_file_validator() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Set a validator that ensures files are larger than 10 bytes
+		store.set_file_validator(|path| {
+			let metadata = fs::metadata(path)?;
+			if metadata.len() < 10 {
+				return Err(io::Error::other("File too small"));
+			}
+			Ok(())
+		});
+
+		let event = serde_json::json!({"event": "test"});
+		store.append(event)?;
+
+		// Fetch should trigger validation
+		let result = store.fetch(None, None)?;
+		assert!(result.is_some());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_limits() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100, // Small size to force multiple files
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+
+		// Create 10 files with predictable sizes
+		for i in 0..10 {
+			let event = serde_json::json!({
+				"event": "test",
+				"index": i,
+				"data": "padding data to make files bigger..."
+			});
+			store.append(event)?;
+		}
+
+		// Test count limit only
+		if let Some(result) = store.fetch(Some(3), None)? {
+			let files = result.data.unwrap();
+			assert_eq!(files.len(), 3, "Count limit not respected");
+		}
+
+		// Test byte limit only
+		if let Some(result) = store.fetch(None, Some(250))? {
+			let files = result.data.unwrap();
+			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
+			assert!(total_size <= 250, "Byte limit not respected");
+		}
+
+		// Test both count and byte limits
+		if let Some(result) = store.fetch(Some(5), Some(200))? {
+			let files = result.data.unwrap();
+			assert!(
+				files.len() <= 5,
+				"Count limit not respected in combined test"
+			);
+
+			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
+			assert!(
+				total_size <= 200,
+				"Byte limit not respected in combined test"
+			);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_addressed_dedup() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		store.set_content_addressed(true);
+
+		store.append(json!({"event": "test", "value": 1}))?;
+		store.finish_file()?;
+
+		// The same events again should not produce a second file
+		store.append(json!({"event": "test", "value": 1}))?;
+		store.finish_file()?;
+
+		// Different events do
+		store.append(json!({"event": "test", "value": 2}))?;
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 2, "Duplicate batch should have been dropped");
+
+		for file in &files {
+			let name = file.file_name().unwrap().to_str().unwrap();
+			let hash = name.trim_end_matches(".temp").rsplit('-').next().unwrap();
+			assert_eq!(hash.len(), 16, "File should be named by content hash");
+			serde_json::from_str::<Value>(&fs::read_to_string(file)?)?;
+		}
+
+		// Named and deduplicated by SHA-256 instead
+		#[cfg(feature = "sha256")]
+		{
+			let temp_dir = TempDir::new()?;
+			let mut store = DirectoryStore::new(DirectoryConfig {
+				write_key: "test-key".to_string(),
+				storage_location: temp_dir.path().to_owned(),
+				base_filename: "events".to_string(),
+				max_file_size: 1024,
+			})?;
+			store.set_content_addressed(true);
+			store.set_hasher(crate::Sha256Hasher);
+			for _ in 0..2 {
+				store.append(json!({"event": "test", "value": 1}))?;
+				store.finish_file()?;
+			}
+			let files = store.fetch(None, None)?.unwrap().data.unwrap();
+			assert_eq!(files.len(), 1);
+			let name = files[0].file_name().unwrap().to_str().unwrap();
+			let hash = name.trim_end_matches(".temp").rsplit('-').next().unwrap();
+			assert_eq!(hash.len(), 64);
+			assert_eq!(store.debug_config()["hasher"], "sha256");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_addressed_encodes_unsafe_hashes() -> Result<()> {
+		/// Hashes to a base64-like name with characters invalid in file names
+		struct SlashHasher;
+
+		impl crate::Hasher for SlashHasher {
+			fn name(&self) -> &str {
+				"slash"
+			}
+
+			fn hash(&self, bytes: &[u8]) -> String {
+				format!("a/b-c.{}", bytes.len())
+			}
+		}
+
+		let temp_dir = TempDir::new()?;
+		let mut store = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		})?;
+		store.set_content_addressed(true);
+		store.set_hasher(SlashHasher);
+		for _ in 0..2 {
+			store.append(json!({"event": "test", "value": 1}))?;
+			store.finish_file()?;
+		}
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 1, "Duplicate batch should have been dropped");
+		let name = files[0].file_name().unwrap().to_str().unwrap();
+		assert!(name.contains("-events-x612f622d632e"), "{}", name);
+		assert_eq!(super::file_hash(String::new()), "x");
+		assert_eq!(super::file_hash("abc123".into()), "abc123");
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_json_formats() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		store.set_json_format(JsonFormat::Pretty);
+		store.append(json!({"event": "test", "nested": {"b": 1, "a": 2}}))?;
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		let content = fs::read_to_string(&files[0])?;
+		assert!(
+			content.contains("\n  \"event\": \"test\""),
+			"Events should be pretty"
+		);
+
+		// Still valid JSON
+		let parsed: Value = serde_json::from_str(&content)?;
+		assert_eq!(parsed["batch"][0]["nested"]["a"], 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_attachments() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		let minidump = Attachment {
+			name: "crash.dmp".to_string(),
+			content_type: "application/octet-stream".to_string(),
+			data: vec![0xde, 0xad, 0xbe, 0xef],
+		};
+		store.append_with_attachments(json!({"event": "crash"}), vec![minidump])?;
+
+		let result = store.fetch(None, None)?.unwrap();
+		let handles = result.attachments.unwrap();
+		assert_eq!(handles.len(), 1);
+		assert_eq!(handles[0].name, "crash.dmp");
+		assert_eq!(handles[0].content_type, "application/octet-stream");
+		assert_eq!(handles[0].content.read()?, vec![0xde, 0xad, 0xbe, 0xef]);
+
+		// The event references the attachment by id
+		let files = result.data.unwrap();
+		let batch: Value = serde_json::from_str(&fs::read_to_string(&files[0])?)?;
+		assert_eq!(
+			batch["batch"][0]["_attachments"][0]["id"],
+			handles[0].id.as_str()
+		);
+
+		// Removing the data file takes the attachment with it, leaving just the manifest
+		store.remove(&result.removable.unwrap())?;
+		let remaining: Vec<_> = fs::read_dir(temp_dir.path())?
+			.map(|entry| entry.map(|e| e.file_name()))
+			.collect::<Result<_>>()?;
+		assert_eq!(remaining, vec![".events-format.json"]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_partial_item_removal() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..4 {
+			store.append(json!({"index": i}))?;
+		}
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		let items = store.item_removables(&files[0])?;
+		assert_eq!(items.len(), 4);
+
+		// Drop events 1 and 3, keep the rest queued
+		store.remove(&[items.into_iter().nth(1).unwrap()])?;
+		let items = store.item_removables(&files[0])?;
+		assert_eq!(items.len(), 3);
+		store.remove(&items[2..])?;
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		let content: Value = serde_json::from_str(&fs::read_to_string(&files[0])?)?;
+		let remaining: Vec<i64> = content["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|item| item["index"].as_i64().unwrap())
+			.collect();
+		assert_eq!(remaining, vec![0, 2]);
+		assert_eq!(content["writeKey"], "test-key");
+
+		// Removing the remaining items deletes the file
+		let items = store.item_removables(&files[0])?;
+		store.remove(&items)?;
+		assert!(!files[0].exists());
+		assert!(!store.has_data());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_store_returns_events() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		for i in 0..10 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		assert!(store.inner().sorted_files(true)?.len() > 1);
+
+		// Events from every file are combined, in order
+		let result = store.fetch(None, None)?.unwrap();
+		let batch = result.data.unwrap();
+		assert_eq!(batch["writeKey"], "test-key");
+		assert_eq!(batch["batchId"], result.batch_id.unwrap().as_str());
+		let items = batch["batch"].as_array().unwrap();
+		let indices: Vec<i64> = items
+			.iter()
+			.map(|item| item["index"].as_i64().unwrap())
+			.collect();
+		assert_eq!(indices, (0..10).collect::<Vec<_>>());
+
+		// Removables line up with the events
+		let removable = result.removable.unwrap();
+		assert_eq!(removable.len(), 10);
+		store.remove(&removable[..9])?;
+
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"].as_array().unwrap().len(), 1);
+		assert_eq!(batch["batch"][0]["index"], 9);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_store_write_batch_to() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		for i in 0..5 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		assert_eq!(
+			store
+				.inner_mut()
+				.write_batch_to(&mut Vec::new(), None, None)
+				.unwrap_err()
+				.kind(),
+			io::ErrorKind::Unsupported
+		);
+
+		let mut out = Vec::new();
+		let result = store.write_batch_to(&mut out, None, None)?.unwrap();
+		assert_eq!(result.data, Some(out.len() as u64));
+		let batch: Value = serde_json::from_slice(&out).unwrap();
+		assert_eq!(batch["writeKey"], "test-key");
+		assert_eq!(batch["batchId"], result.batch_id.unwrap().as_str());
+		let indices: Vec<i64> = batch["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|item| item["index"].as_i64().unwrap())
+			.collect();
+		assert_eq!(indices, (0..5).collect::<Vec<_>>());
+
+		store.remove(&result.removable.unwrap())?;
+		assert!(store.write_batch_to(&mut out, None, None)?.is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_store_context_provider() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		store.append(json!({"index": 0}))?;
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert!(batch.get("context").is_none());
+
+		// The provider is asked on every fetch
+		let fetches = Arc::new(AtomicUsize::new(0));
+		let counter = fetches.clone();
+		store.set_context_provider(move || match counter.fetch_add(1, Ordering::SeqCst) {
+			0 => json!({"app": {"version": "1.0"}}),
+			_ => Value::Null,
+		});
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["context"], json!({"app": {"version": "1.0"}}));
+		assert_eq!(batch["batch"][0], json!({"index": 0}));
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert!(batch.get("context").is_none());
+		assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_store_clock_offset() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		let offset = ClockOffset::new();
+		store.set_clock_offset_provider(offset.clone());
+		store.append(json!({"n": 0, "timestamp": "2024-05-01T12:00:00Z"}))?;
+		store.append(json!({"n": 1}))?;
+
+		// Nothing is corrected until the offset is known
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"][0]["timestamp"], "2024-05-01T12:00:00Z");
+
+		offset.set(chrono::TimeDelta::minutes(-90));
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(
+			batch["batch"],
+			json!([{"n": 0, "timestamp": "2024-05-01T10:30:00Z"}, {"n": 1}])
+		);
+		let sent_at = DateTime::parse_from_rfc3339(batch["sentAt"].as_str().unwrap()).unwrap();
+		assert!(sent_at < Utc::now() - chrono::TimeDelta::minutes(89));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_content_store_consent_categories() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		let categories = ["analytics", "advertising", "functional"];
+		for i in 0..9 {
+			store.append(json!({"index": i, "_consent": categories[i % 3]}))?;
+		}
+		let allowed = HashSet::from(["analytics".to_string(), "functional".to_string()]);
+		store.set_allowed_categories(Some(allowed))?;
+
+		let indices = |store: &mut DirectoryContentStore| -> Result<Vec<i64>> {
+			let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+			Ok(batch["batch"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|item| item["index"].as_i64().unwrap())
+				.collect())
+		};
+		assert_eq!(indices(&mut store)?, vec![0, 2, 3, 5, 6, 8]);
+
+		// Purging rewrites the files without the revoked events
+		assert_eq!(store.purge_revoked()?, 3);
+		assert_eq!(store.purge_revoked()?, 0);
+		store.set_allowed_categories(None)?;
+		assert_eq!(indices(&mut store)?, vec![0, 2, 3, 5, 6, 8]);
+
+		Ok(())
+	}
+
+	#[cfg(feature = "anonymize")]
+	#[test]
+	fn test_anonymize_rewrites_files() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..5 {
+			store.append(json!({"index": i, "user": {"email": format!("{}@example.com", i)}}))?;
+		}
+		// The last event is still in the unfinished file
+		assert!(store.sorted_files(true)?.len() > 1);
+
+		let fields = [JsonPointer::new("/user/email")?];
+		assert_eq!(store.anonymize(&Anonymizer::new(&fields)?)?, 5);
+
+		let events = store.pending_events()?;
+		assert_eq!(events.len(), 5);
+		for (i, event) in events.iter().enumerate() {
+			assert_eq!(event["index"], i);
+			assert_eq!(event["user"]["email"].as_str().unwrap().len(), 64);
+		}
+		for file in fs::read_dir(temp_dir.path())? {
+			let content = fs::read_to_string(file?.path())?;
+			assert!(!content.contains("example.com"));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_field_filter_keeps_fields_off_disk() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		store.set_field_filter(FieldFilter {
+			allow: Some(vec![
+				JsonPointer::new("/event")?,
+				JsonPointer::new("/user")?,
+			]),
+			deny: vec![JsonPointer::new("/user/email")?],
+		});
+		store.append(json!({
+			"event": "signup",
+			"user": {"id": 1, "email": "someone@example.com"},
+			"token": "s3cr3t",
+		}))?;
+
+		for file in fs::read_dir(temp_dir.path())? {
+			let content = fs::read_to_string(file?.path())?;
+			assert!(!content.contains("example.com") && !content.contains("s3cr3t"));
+		}
+		assert_eq!(
+			store.pending_events()?,
+			vec![json!({"event": "signup", "user": {"id": 1}})]
+		);
+		assert_eq!(
+			store.debug_config()["fieldFilter"]["deny"],
+			json!(["/user/email"])
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_migrates_files_from_previous_version() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		// Queued by the previous app version
+		let mut store = DirectoryStore::new(config.clone())?;
+		for i in 0..4 {
+			store.append(json!({"index": i, "userName": format!("user{}", i)}))?;
+		}
+		drop(store);
+
+		// The new version renamed a field
+		let mut store = DirectoryStore::new(config)?;
+		store.set_migrations(SchemaMigrations::new(1).migration(0, |mut event| {
+			if let Some(fields) = event.as_object_mut() {
+				if let Some(name) = fields.remove("userName") {
+					fields.insert("user".to_string(), json!({"name": name}));
+				}
+			}
+			Ok(event)
+		}));
+		store.append(json!({"index": 4, "user": {"name": "user4"}}))?;
+
+		let mut store = DirectoryContentStore::new(store);
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		let items = batch["batch"].as_array().unwrap();
+		assert_eq!(items.len(), 5);
+		for (i, item) in items.iter().enumerate() {
+			assert_eq!(item["user"]["name"], format!("user{}", i));
+			assert_eq!(item["_schemaVersion"], 1);
+			assert!(item.get("userName").is_none());
+		}
+
+		// The files were rewritten, so they are only migrated once
+		for event in store.pending_events()? {
+			assert_eq!(event["_schemaVersion"], 1);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_refuses_newer_format() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+		let manifest = temp_dir.path().join(".events-format.json");
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.append(json!({"event": "test"}))?;
+		drop(store);
+		let written: Value = serde_json::from_str(&fs::read_to_string(&manifest)?)?;
+		assert_eq!(written["format"], DirectoryStore::FORMAT_VERSION);
+
+		// A later release wrote its own format; its files are left alone
+		fs::write(&manifest, r#"{"format": 99}"#)?;
+		let files_before = fs::read_dir(temp_dir.path())?.count();
+		let err = DirectoryStore::new(config.clone()).err().unwrap();
+		assert_eq!(
+			TransientError::from_io(&err),
+			Some(&TransientError::IncompatibleFormat {
+				found: 99,
+				supported: DirectoryStore::FORMAT_VERSION,
+			})
+		);
+		assert_eq!(fs::read_dir(temp_dir.path())?.count(), files_before);
+
+		// A manifest torn on first write is rewritten
+		fs::write(&manifest, r#"{"form"#)?;
+		let store = DirectoryStore::new(config)?;
+		assert_eq!(store.pending_events()?, vec![json!({"event": "test"})]);
+		let written: Value = serde_json::from_str(&fs::read_to_string(&manifest)?)?;
+		assert_eq!(written["format"], DirectoryStore::FORMAT_VERSION);
+		Ok(())
+	}
+
+	#[test]
+	fn test_preview_fetch_matches_fetch() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..10 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		let finished = store.sorted_files(false)?.len();
+
+		// The preview counts the file being written, without finishing it
+		let preview = store.preview_fetch(None, None)?;
+		assert_eq!(preview.items, finished + 1);
+		assert_eq!(store.sorted_files(false)?.len(), finished);
+		assert!(preview.oldest.is_some() && preview.oldest <= preview.newest);
+
+		let preview = store.preview_fetch(Some(2), None)?;
+		let files = store.fetch(Some(2), None)?.unwrap().data.unwrap();
+		assert_eq!(preview.items, files.len());
+		let bytes: u64 = files
+			.iter()
+			.map(|f| fs::metadata(f).map(|m| m.len()))
+			.sum::<Result<u64>>()?;
+		assert_eq!(preview.bytes, bytes);
+		Ok(())
+	}
+
+	#[test]
+	fn test_buffered_events_are_written_at_flush_points() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.set_flush_policy(FlushPolicy::Buffered(100))?;
+		store.append(json!({"index": 0}))?;
+		store.append(json!({"index": 1}))?;
+
+		// Held in memory, but still counted
+		let current = store.current_path.clone().unwrap();
+		assert_eq!(fs::read(&current)?, DirectoryStore::HEADER);
+		assert!(store.has_data());
+		assert_eq!(store.pending_events()?.len(), 2);
+
+		// Written once enough has built up
+		store.append(json!({"index": 2, "padding": "x".repeat(80)}))?;
+		assert!(store.buffer.is_empty());
+		store.append(json!({"index": 3}))?;
+		store.flush()?;
+		assert!(store.buffer.is_empty());
+
+		// Dropping the store writes what's left
+		store.append(json!({"index": 4}))?;
+		drop(store);
+		let mut store = DirectoryStore::new(config)?;
+		let indices: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|e| e["index"].clone())
+			.collect();
+		assert_eq!(indices, (0..5).map(|i| json!(i)).collect::<Vec<_>>());
+		assert!(store.fetch(None, None)?.is_some());
+		Ok(())
+	}
+
+	#[test]
+	fn test_scratch_buffer_shrinks_after_large_events() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 4096,
+		};
+
+		let mut store = DirectoryStore::new(config)?.with_scratch_capacity(64);
+		store.append(json!({"index": 0}))?;
+		store.append(json!({"index": 1, "padding": "x".repeat(1000)}))?;
+		assert!(store.scratch.capacity() <= 64);
+		store.append_many(vec![json!({"index": 2}), json!({"index": 3})])?;
+
+		let indices: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|e| e["index"].clone())
+			.collect();
+		assert_eq!(indices, (0..4).map(|i| json!(i)).collect::<Vec<_>>());
+		Ok(())
+	}
+
+	#[test]
+	fn test_read_batches_keeps_file_order() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..12 {
+			store.append(json!({"index": i, "padding": "x".repeat(60)}))?;
+		}
+		store.finish_file()?;
+		let mut files = store.sorted_files(false)?;
+		assert!(files.len() > 2);
+		files.insert(1, temp_dir.path().join("missing.events.temp"));
+
+		let batches = store.read_batches(&files);
+		assert_eq!(batches.len(), files.len());
+		assert!(batches[1].is_err());
+		let indices: Vec<Value> = batches
+			.into_iter()
+			.filter_map(Result::ok)
+			.flatten()
+			.map(|e| e["index"].clone())
+			.collect();
+		assert_eq!(indices, (0..12).map(|i| json!(i)).collect::<Vec<_>>());
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_reports_remaining_files() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let mut store = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		})?;
+		for i in 0..3 {
+			store.append(json!({"index": i, "padding": "x".repeat(60)}))?;
+		}
+
+		let result = store.fetch(Some(1), None)?.unwrap();
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(result.remaining_items, files.len() - 1);
+		let sizes: u64 = files[1..]
+			.iter()
+			.map(|file| fs::metadata(file).map(|m| m.len()))
+			.sum::<Result<u64>>()?;
+		assert_eq!(result.remaining_bytes, sizes);
+
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!((result.remaining_items, result.remaining_bytes), (0, 0));
+		Ok(())
+	}
+
+	#[test]
+	fn test_batches_by_id() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let mut store = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		})?;
+		for i in 0..3 {
+			store.append(json!({"index": i, "padding": "x".repeat(150)}))?;
+		}
+		// The file being written isn't listed
+		store.append(json!({"index": 3}))?;
+
+		let batches = store.list_batches()?;
+		let ids: Vec<&str> = batches.iter().map(|batch| batch.id.as_str()).collect();
+		assert_eq!(ids, vec!["1", "2", "3"]);
+		for batch in &batches {
+			assert_eq!(batch.items, 1);
+			assert_eq!(batch.bytes, fs::metadata(&batch.path)?.len());
+		}
+
+		// Pick the newest batch first
+		let result = store.fetch_batch("3")?.unwrap();
+		assert_eq!(result.data.as_deref(), Some(&[batches[2].path.clone()][..]));
+		assert_eq!(result.remaining_items, 2);
+		store.remove(&result.removable.unwrap())?;
+
+		assert!(store.remove_batch("1")?);
+		assert!(!store.remove_batch("1")?);
+		assert!(store.fetch_batch("7")?.is_none());
+		let ids: Vec<String> = store.list_batches()?.into_iter().map(|b| b.id).collect();
+		assert_eq!(ids, vec!["2"]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_quiesce() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let mut store = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		})?;
+		store.append(json!({"index": 0}))?;
+
+		let guard = store.quiesce()?;
+		assert!(store.is_quiesced());
+		let ids: Vec<String> = store.list_batches()?.into_iter().map(|b| b.id).collect();
+		assert_eq!(ids, vec!["1"]);
+
+		// Appends past max_file_size stay in one unfinished file
+		for i in 1..4 {
+			store.append(json!({"index": i, "padding": "x".repeat(150)}))?;
+		}
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!(result.data.as_ref().map(Vec::len), Some(1));
+		let removable = result.removable.unwrap();
+		let err = store.remove(&removable).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+		assert!(store.remove_batch("1").is_err());
+		store.reset();
+		assert_eq!(store.list_batches()?.len(), 1);
+
+		drop(guard);
+		assert!(!store.is_quiesced());
+		store.remove(&removable)?;
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!(result.data.as_ref().map(Vec::len), Some(1));
+		assert_eq!(store.read_batch(&result.data.unwrap()[0])?.len(), 3);
+		Ok(())
+	}
+
+	#[test]
+	fn test_warm_up() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+		let mut store = DirectoryStore::new(config.clone())?;
+		let entries = fs::read_dir(temp_dir.path())?.count();
+		store.warm_up()?;
+		assert_eq!(fs::read_dir(temp_dir.path())?.count(), entries + 1);
+		assert!(!store.has_data());
+		assert!(store.fetch(None, None)?.is_none());
+
+		// The first append goes into the file warm_up opened
+		store.append(json!({"index": 0}))?;
+		assert_eq!(fs::read_dir(temp_dir.path())?.count(), entries + 1);
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!(store.read_batch(&result.data.unwrap()[0])?.len(), 1);
+		store.remove(&result.removable.unwrap())?;
+
+		// A warmed-up file nothing was appended to is cleaned up on the next start
+		store.warm_up()?;
+		drop(store);
+		let mut store = DirectoryStore::new(config)?;
+		assert!(!store.has_data());
+		assert!(store.fetch(None, None)?.is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_mapped() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..3 {
+			store.append(json!({"index": i, "padding": "x".repeat(60)}))?;
+		}
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		// SAFETY: nothing else writes to the temporary directory
+		let result = unsafe { store.fetch_mapped(None, None)? }.unwrap();
+		let mapped = result.data.unwrap();
+		assert_eq!(mapped.len(), files.len());
+		for (file, path) in mapped.iter().zip(&files) {
+			assert_eq!(file.is_mapped(), cfg!(unix));
+			assert_eq!(&file[..], fs::read(path)?.as_slice());
+		}
+
+		// Removing the batch leaves the mappings readable
+		store.remove(&result.removable.unwrap())?;
+		assert!(!store.has_data());
+		let batch: Value = serde_json::from_slice(&mapped[0])?;
+		assert_eq!(batch["batch"][0]["index"], 0);
+		Ok(())
+	}
+
+	#[test]
+	fn test_staging_location() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let outbox = temp_dir.path().join("outbox");
+		let staging = temp_dir.path().join("staging");
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: outbox.clone(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+		let data_files = |dir: &std::path::Path| -> Result<Vec<String>> {
+			let mut names: Vec<String> = fs::read_dir(dir)?
+				.filter_map(|e| e.ok()?.file_name().into_string().ok())
+				.filter(|name| !name.starts_with('.'))
+				.collect();
+			names.sort();
+			Ok(names)
+		};
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.set_staging_location(Some(staging.clone()))?;
+		store.append(json!({"index": 0}))?;
+		assert_eq!(data_files(&staging)?, vec!["1-events"]);
+		assert!(data_files(&outbox)?.is_empty());
+		assert_eq!(store.pending_events()?.len(), 1);
+
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files, vec![outbox.join("1-events.temp")]);
+		assert!(data_files(&staging)?.is_empty());
+
+		// An unfinished file left by a crash is recovered into the outbox
+		store.append(json!({"index": 1}))?;
+		drop(store);
+		let mut store = DirectoryStore::new(config)?;
+		store.set_staging_location(Some(staging.clone()))?;
+		assert!(data_files(&staging)?.is_empty());
+		assert_eq!(data_files(&outbox)?, vec!["1-events.temp", "2-events.temp"]);
+
+		// New files don't reuse the recovered file's index
+		store.append(json!({"index": 2}))?;
+		assert_eq!(data_files(&staging)?, vec!["3-events"]);
+
+		store.reset();
+		assert!(data_files(&staging)?.is_empty());
+		assert!(data_files(&outbox)?.is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn test_exclude_from_backup() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let staging = temp_dir.path().join("staging");
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().join("outbox"),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.set_staging_location(Some(staging.clone()))?;
+		let marked = std::cell::RefCell::new(Vec::new());
+		store.exclude_from_backup_with(|dir| {
+			marked.borrow_mut().push(dir.to_path_buf());
+			Ok(())
+		})?;
+		assert_eq!(
+			marked.into_inner(),
+			vec![config.storage_location.clone(), staging.clone()]
+		);
+		let tag = fs::read_to_string(config.storage_location.join("CACHEDIR.TAG"))?;
+		assert!(tag.starts_with("Signature: 8a477f597d28d172789f06886806bc55"));
+
+		// The tags aren't mistaken for data
+		store.append(json!({"event": "a"}))?;
+		assert_eq!(store.pending_events()?.len(), 1);
+		assert_eq!(store.fetch(None, None)?.unwrap().data.unwrap().len(), 1);
+		store.reset();
+		assert!(!store.has_data());
+		assert!(config.storage_location.join("CACHEDIR.TAG").exists());
+		assert!(staging.join("CACHEDIR.TAG").exists());
+		Ok(())
+	}
+
+	#[test]
+	fn test_write_all_vectored_resumes_short_writes() -> Result<()> {
+		// Accepts at most 3 bytes per call
+		struct Trickle(Vec<u8>);
+		impl io::Write for Trickle {
+			fn write(&mut self, buf: &[u8]) -> Result<usize> {
+				let n = buf.len().min(3);
+				self.0.extend_from_slice(&buf[..n]);
+				Ok(n)
+			}
+			fn flush(&mut self) -> Result<()> {
+				Ok(())
+			}
+		}
+
+		let mut writer = Trickle(Vec::new());
+		write_all_vectored(&mut writer, &[b"", b"{\"a\":1}", b",", b"", b"{\"b\":2}"])?;
+		assert_eq!(writer.0, b"{\"a\":1},{\"b\":2}");
+		Ok(())
+	}
+
+	#[test]
+	fn test_append_many_rotates_like_append() -> Result<()> {
+		let events: Vec<Value> = (0..12)
+			.map(|i| json!({"index": i, "padding": "x".repeat(i * 5)}))
+			.collect();
+		let open = |dir: &TempDir| {
+			DirectoryStore::new(DirectoryConfig {
+				write_key: "test-key".to_string(),
+				storage_location: dir.path().to_owned(),
+				base_filename: "events".to_string(),
+				max_file_size: 150,
+			})
+		};
+
+		let one_by_one = TempDir::new()?;
+		let mut store = open(&one_by_one)?;
+		store.append(json!({"index": "first"}))?;
+		for event in events.clone() {
+			store.append(event)?;
+		}
+		let expected: Vec<Vec<Value>> = store
+			.fetch(None, None)?
+			.unwrap()
+			.data
+			.unwrap()
+			.iter()
+			.map(|f| store.read_batch(f))
+			.collect::<Result<_>>()?;
+
+		let grouped = TempDir::new()?;
+		let mut store = open(&grouped)?;
+		store.append(json!({"index": "first"}))?;
+		store.append_many(events)?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		let actual: Vec<Vec<Value>> = files
+			.iter()
+			.map(|f| store.read_batch(f))
+			.collect::<Result<_>>()?;
+
+		assert!(expected.len() > 1);
+		assert_eq!(actual, expected);
+		Ok(())
+	}
+
+	#[test]
+	fn test_reserved_events_are_committed_without_rotation() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		store.append(json!({"index": 0, "padding": "x".repeat(40)}))?;
+
+		// The reserved event wouldn't fit, so the file is rotated now
+		store.reserve(60)?;
+		assert_eq!(store.sorted_files(false)?.len(), 1);
+		let reserved_in = store.current_path.clone();
+		assert!(reserved_in.is_some());
+
+		// A fetch leaves a file open for the reservation
+		let fetched = store.fetch(None, None)?.unwrap();
+		assert_eq!(fetched.data.unwrap().len(), 1);
+		store.remove(&fetched.removable.unwrap())?;
+		assert_eq!(store.current_path, reserved_in);
+		assert!(!store.has_data());
+		assert_eq!(store.preview_fetch(None, None)?.items, 0);
+
+		// Larger than estimated, and past max_file_size, but still not rotated
+		store.commit_reserved(json!({"index": 1, "padding": "x".repeat(120)}), 60)?;
+		assert_eq!(store.current_path, reserved_in);
+		assert!(store.has_data());
+
+		store.append(json!({"index": 2}))?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 2);
+		let events: Vec<Value> = files
+			.iter()
+			.map(|f| store.read_batch(f))
+			.collect::<Result<Vec<_>>>()?
+			.concat();
+		assert_eq!(events[0]["index"], 1);
+		assert_eq!(events[1]["index"], 2);
+
+		// Reservations don't outlive a reset
+		store.reserve(80)?;
+		store.reset();
+		store.append(json!({"index": 3}))?;
+		store.reserve(10)?;
+		store.commit_reserved(json!({"index": 4}), 10)?;
+		assert_eq!(store.fetch(None, None)?.unwrap().data.unwrap().len(), 1);
+		Ok(())
+	}
+
+	#[test]
+	fn test_stale_paths_are_re_resolved() -> Result<()> {
+		let old_dir = TempDir::new()?;
+		let new_dir = TempDir::new()?;
+		let config = |location: &std::path::Path| DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: location.to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		// First launch: fetch files, remember the paths, then "relocate" the container
+		let mut store = DirectoryStore::new(config(old_dir.path()))?;
+		for i in 0..10 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		let old_paths = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert!(old_paths.len() > 1);
+		let relative = store.relative_path(&old_paths[1]).unwrap();
+		drop(store);
+		for path in &old_paths {
+			fs::rename(path, new_dir.path().join(path.file_name().unwrap()))?;
+		}
+
+		// Next launch: removing the stale absolute paths and a relative one still works
+		let mut store = DirectoryStore::new(config(new_dir.path()))?;
+		assert_eq!(
+			store.resolve_path(&relative),
+			new_dir.path().join(&relative)
+		);
+		store.remove(&[Box::new(old_paths[0].clone()) as Box<dyn Equivalent>])?;
+		store.remove(&[Box::new(relative) as Box<dyn Equivalent>])?;
+
+		let remaining = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(remaining.len(), old_paths.len() - 2);
+		assert!(!new_dir
+			.path()
+			.join(old_paths[0].file_name().unwrap())
+			.exists());
+
+		Ok(())
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_symlinks_are_not_followed() -> Result<()> {
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_dir = TempDir::new()?;
+		let outside = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		// A link to an unfinished "data file" elsewhere must not be finalized or fetched
+		let target = outside.path().join("secret");
+		fs::write(&target, "secret contents")?;
+		std::os::unix::fs::symlink(&target, temp_dir.path().join("5-events"))?;
+		std::os::unix::fs::symlink(&target, temp_dir.path().join("6-events.temp"))?;
+
+		let mut store = DirectoryStore::new(config)?;
+		store.restrict_permissions()?;
+		let mode = fs::metadata(temp_dir.path())?.permissions().mode();
+		assert_eq!(mode & 0o777, 0o700);
+
+		assert_eq!(fs::read_to_string(&target)?, "secret contents");
+		assert!(!store.has_data());
+		assert!(store.fetch(None, None)?.is_none());
+
+		store.append(json!({"event": "test"}))?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 1);
+		assert!(!fs::symlink_metadata(&files[0])?.file_type().is_symlink());
+
+		// A store directory that is itself a link is refused
+		let link_dir = outside.path().join("link");
+		std::os::unix::fs::symlink(temp_dir.path(), &link_dir)?;
+		let linked = DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: link_dir,
+			base_filename: "other".to_string(),
+			max_file_size: 1024,
+		})?;
+		assert_eq!(
+			linked.restrict_permissions().unwrap_err().kind(),
+			io::ErrorKind::PermissionDenied
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_spill_memory_to_disk() -> Result<()> {
+		use crate::{move_events, recover_moves, MemoryConfig, MemoryStore};
+
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+		let mut memory = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		let mut disk = DirectoryContentStore::new(DirectoryStore::new(config.clone())?);
+		for i in 0..5 {
+			memory.append(json!({"index": i}))?;
+		}
+
+		assert_eq!(move_events(&mut memory, &mut disk, 2)?, 2);
+		let batch = disk.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"], json!([{"index": 0}, {"index": 1}]));
+		disk.reset();
+
+		// Died after staging on disk: the staged events aren't fetchable yet
+		let copies = memory.begin_move("interrupted", 2)?;
+		disk.stage_move("interrupted", copies)?;
+		assert!(disk.fetch(None, None)?.is_none());
+		assert!(disk.stage_move("../escape", vec![]).is_err());
+
+		// The next instance finishes the move
+		drop(disk);
+		let mut disk = DirectoryContentStore::new(DirectoryStore::new(config)?);
+		assert_eq!(disk.held_moves()?, vec!["interrupted"]);
+		assert_eq!(recover_moves(&mut memory, &mut disk)?, 1);
+		let batch = disk.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"], json!([{"index": 2}, {"index": 3}]));
+		assert_eq!(memory.pending_events()?, vec![json!({"index": 4})]);
+		assert!(disk.held_moves()?.is_empty());
+
+		Ok(())
+	}
+
+	#[cfg(any(unix, windows))]
+	#[test]
+	fn test_disk_reserve() -> Result<()> {
+		use super::DiskFullPolicy;
+		use crate::{Evicted, TransientError};
+		use std::sync::Mutex;
+
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		for i in 0..10 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+
+		// No volume has this much free space
+		store.set_disk_reserve(Some(u64::MAX / 2));
+		let err = store.append(json!({"index": 10})).unwrap_err();
+		assert!(matches!(
+			TransientError::from_io(&err),
+			Some(TransientError::DiskFull { .. })
+		));
+		// Queued data can still be fetched
+		assert!(store.fetch(None, None)?.is_some());
+
+		// Evicting frees everything it can, but still can't satisfy the reserve
+		store.set_disk_full_policy(DiskFullPolicy::EvictOldest);
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let listened = evicted.clone();
+		store.set_eviction_listener(
+			move |dropped: Evicted<'_>| {
+				let mut listened = listened.lock().unwrap();
+				listened.extend(dropped.events.iter().map(|event| event["index"].clone()));
+			},
+			usize::MAX,
+		);
+		assert!(store.append(json!({"index": 10})).is_err());
+		assert!(store.fetch(None, None)?.is_none());
+		assert_eq!(
+			*evicted.lock().unwrap(),
+			(0..10).map(|i| json!(i)).collect::<Vec<_>>()
+		);
+
+		store.set_disk_reserve(None);
+		store.append(json!({"index": 11}))?;
+		assert!(store.has_data());
+
+		// The evicted events are reported, also by a later instance, and reset doesn't
+		// clear the report
+		store.reset();
+		drop(store);
+		let mut store = DirectoryStore::new(config)?;
+		let report = store.drop_report()?;
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].reason, crate::DropReason::DiskFull);
+		assert_eq!(report[0].count, 10);
+		assert!(report[0].first <= report[0].last);
+		assert!(store.drop_report()?.is_empty());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_external_handoff() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		store.append(json!({"source": "local"}))?;
+
+		fs::write(
+			temp_dir.path().join("producer.events.json"),
+			r#"{"batch":[{"source":"external"}],"writeKey":"test-key"}"#,
+		)?;
+		fs::write(temp_dir.path().join("broken.events.json"), "{\"batch\": [")?;
+		fs::write(temp_dir.path().join(".partial.events.json"), "{")?;
+
+		// Not picked up until enabled
+		assert_eq!(store.fetch(None, None)?.unwrap().data.unwrap().len(), 1);
+
+		store.set_accept_external(true);
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 2);
+		let sources: Vec<String> = files
+			.iter()
+			.map(|f| {
+				let content: Value = serde_json::from_str(&fs::read_to_string(f).unwrap()).unwrap();
+				content["batch"][0]["source"].as_str().unwrap().to_string()
+			})
+			.collect();
+		assert_eq!(sources, vec!["local", "external"]);
+
+		assert!(temp_dir.path().join("broken.events.json.rejected").exists());
+		assert!(temp_dir.path().join(".partial.events.json").exists());
+
+		// Rejected files aren't data
+		let removable = store.fetch(None, None)?.unwrap().removable.unwrap();
+		store.remove(&removable)?;
+		assert!(!store.has_data());
+
+		// Waiting handoff files are, but aren't adopted until fetched
+		let handoff = temp_dir.path().join("late.events.json");
+		fs::write(
+			&handoff,
+			r#"{"batch":[{"source":"late"}],"writeKey":"test-key"}"#,
+		)?;
+		assert!(store.has_data());
+		assert!(handoff.exists());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_external_handoff_present_on_open() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		// Named like the documented example, so it looks like an index-prefixed file
+		let handoff = temp_dir.path().join("1700000000-worker3.events.json");
+		let content = r#"{"batch":[{"source":"external"}],"writeKey":"test-key"}"#;
+		fs::write(&handoff, content)?;
+
+		let mut store = DirectoryStore::new(config)?;
+		assert_eq!(fs::read_to_string(&handoff)?, content);
+
+		store.set_accept_external(true);
+		store.append(json!({"source": "local"}))?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		let names: Vec<&str> = files.iter().map(|f| super::file_name(f)).collect();
+		assert_eq!(names, vec!["1-events.temp", "2-events.temp"]);
+		let sources: Vec<String> = files
+			.iter()
+			.map(|f| {
+				let content: Value = serde_json::from_str(&fs::read_to_string(f).unwrap()).unwrap();
+				content["batch"][0]["source"].as_str().unwrap().to_string()
+			})
+			.collect();
+		assert_eq!(sources, vec!["local", "external"]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_import_file() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().join("queue"),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let source = temp_dir.path().join("legacy.ndjson");
+		let lines: Vec<String> = (0..10)
+			.map(|i| json!({"index": i, "padding": "x".repeat(20)}).to_string())
+			.collect();
+		fs::write(&source, lines.join("\n") + "\n{broken\n")?;
+
+		let mut store = DirectoryStore::new(config)?;
+		let report = store.import_file(&source)?;
+		assert_eq!(report.imported, 10);
+		assert_eq!(report.errors.len(), 1);
+		assert_eq!(report.errors[0].position, 11);
+
+		// Imported events are queued like any other, across rotated files
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert!(files.len() > 1);
+		let mut indices = Vec::new();
+		for file in &files {
+			let content: Value = serde_json::from_str(&fs::read_to_string(file)?)?;
+			for item in content["batch"].as_array().unwrap() {
+				indices.push(item["index"].as_i64().unwrap());
+			}
+		}
+		assert_eq!(indices, (0..10).collect::<Vec<_>>());
+
+		Ok(())
+	}
+
+	#[cfg(feature = "watch")]
+	#[test]
+	fn test_watch_external() -> Result<()> {
+		use std::sync::mpsc;
+		use std::time::Duration;
+
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		let (wake, woken) = mpsc::channel();
+		store.watch_external(move || {
+			let _ = wake.send(());
+		})?;
+
+		let staging = temp_dir.path().join(".producer.events.json");
+		fs::write(&staging, r#"{"batch":[{"source":"external"}]}"#)?;
+		fs::rename(&staging, temp_dir.path().join("producer.events.json"))?;
+
+		woken
+			.recv_timeout(Duration::from_secs(5))
+			.expect("Watcher should report the handoff file");
+		assert!(store.has_data());
+		assert_eq!(store.fetch(None, None)?.unwrap().data.unwrap().len(), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_delivery_cursor_resumes() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.set_delivery_cursor(true)?;
+		for i in 0..10 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+
+		// Deliver the first two files, then "crash" before removing them
+		let first = store.fetch(Some(2), None)?.unwrap();
+		store.mark_delivered(first.batch_id.as_ref().unwrap())?;
+		// The next files are fetched but the upload is interrupted
+		let second = store.fetch(Some(2), None)?.unwrap();
+		drop(store);
+
+		let mut store = DirectoryStore::new(config.clone())?;
+		store.set_delivery_cursor(true)?;
+		let resumed = store.fetch(Some(2), None)?.unwrap();
+		assert_eq!(
+			resumed.data, second.data,
+			"Should resume after delivered files"
+		);
+		assert_eq!(
+			resumed.batch_id, second.batch_id,
+			"In-flight batch id is reused"
+		);
+		for file in first.data.unwrap() {
+			assert!(!file.exists(), "Delivered files should be cleaned up");
+		}
+
+		// New files sort after everything delivered, even once the directory is empty
+		store.mark_delivered(resumed.batch_id.as_ref().unwrap())?;
+		store.reset();
+		drop(store);
+		let mut store = DirectoryStore::new(config)?;
+		store.set_delivery_cursor(true)?;
+		store.append(json!({"index": 10}))?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_pending_events_include_unfinished_file() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..6 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		assert!(store.sorted_files(false)?.len() > 1);
+
+		// Listing events doesn't finish the file being written
+		let events = store.pending_events()?;
+		let indices: Vec<i64> = events
+			.iter()
+			.map(|e| e["index"].as_i64().unwrap())
+			.collect();
+		assert_eq!(indices, (0..6).collect::<Vec<_>>());
+		assert!(store.writer.is_some());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_reset_while_writing() -> Result<()> {
 		let temp_dir = TempDir::new()?;
-
 		let config = DirectoryConfig {
 			write_key: "test-key".to_string(),
 			storage_location: temp_dir.path().to_owned(),
@@ -470,301 +5752,470 @@ mod tests {
 		};
 
 		let mut store = DirectoryStore::new(config)?;
+		store.append(json!({"event": "before"}))?;
+		store.reset();
+		store.append(json!({"event": "after"}))?;
 
-		// Test append
-		let event = serde_json::json!({
-			"event": "test",
-			"properties": {
-				"value": 123
-			}
-		});
-		store.append(event.clone())?;
-		store.append(event.clone())?;
-
-		// Force file completion
-		if let Some(result) = store.fetch(None, None)? {
-			assert!(result.data.is_some());
-			let files = result.data.unwrap();
-			assert_eq!(files.len(), 1);
-
-			// Verify file content
-			let content = fs::read_to_string(&files[0])?;
-			assert!(content.contains("\"event\":\"test\""));
-			assert!(content.contains("\"writeKey\":\"test-key\""));
-		} else {
-			panic!("Expected data but got none");
-		}
+		let events = store.pending_events()?;
+		assert_eq!(events, vec![json!({"event": "after"})]);
+		assert!(store.fetch(None, None)?.is_some());
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_recovers_unfinished_files() -> Result<()> {
+	fn test_files_are_fetched_in_index_order() -> Result<()> {
 		let temp_dir = TempDir::new()?;
-
-		// Create initial store instance
 		let config = DirectoryConfig {
 			write_key: "test-key".to_string(),
 			storage_location: temp_dir.path().to_owned(),
 			base_filename: "events".to_string(),
-			max_file_size: 1024,
+			max_file_size: 100,
 		};
 
-		// First store instance - create some unfinished files
-		{
-			let mut store = DirectoryStore::new(config.clone())?;
+		let mut store = DirectoryStore::new(config)?;
+		for i in 0..30 {
+			store.append(json!({"index": i, "padding": "x".repeat(40)}))?;
+		}
 
-			// Add some entries but don't finish_file()
-			store.append(json!({"event": "test1", "value": 123}))?;
-			store.append(json!({"event": "test2", "value": 456}))?;
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert!(files.len() > 10);
+		let mut indices = Vec::new();
+		for file in &files {
+			for item in store.read_batch(file)? {
+				indices.push(item["index"].as_i64().unwrap());
+			}
+		}
+		assert_eq!(indices, (0..30).collect::<Vec<_>>());
 
-			// Let store drop without explicit finish
+		Ok(())
+	}
+
+	/// What a crash-point workload knows about its events when it stops
+	#[derive(Default)]
+	struct CrashLedger {
+		/// Appended and not removed
+		kept: Vec<Value>,
+		/// Removed successfully
+		removed: Vec<Value>,
+		/// Every event the workload tried to write
+		attempted: Vec<Value>,
+	}
+
+	/// Appends across several files, then removes a whole file and a single event,
+	/// stopping at the first error
+	fn crash_workload(
+		store: &mut DirectoryStore,
+		fs: &SimFs,
+		ledger: &mut CrashLedger,
+	) -> Result<()> {
+		let event = |n: usize| json!({"n": n, "padding": "x".repeat(40)});
+		for n in 0..5 {
+			ledger.attempted.push(event(n));
+			store.append(event(n))?;
+			ledger.kept.push(event(n));
 		}
 
-		// Create a finished file to ensure we don't touch it
-		{
-			let mut store = DirectoryStore::new(config.clone())?;
-			store.append(json!({"event": "finished", "value": 789}))?;
-			store.finish_file()?; // Explicitly finish this one
+		let result = store.fetch(None, None)?.unwrap();
+		let files = result.data.unwrap();
+		let first = store.read_batch(&files[0])?;
+		store.remove(&result.removable.unwrap()[..1])?;
+		// Failing to delete a whole file is logged rather than returned
+		if fs.crashed() {
+			return Err(io::Error::other("Crashed during removal"));
 		}
+		ledger.kept.retain(|e| !first.contains(e));
+		ledger.removed.extend(first);
 
-		// Create new store instance which should trigger recovery
-		let mut store = DirectoryStore::new(config)?;
+		let second = store.read_batch(&files[1])?;
+		let items = store.item_removables(&files[1])?;
+		store.remove(&items[..1])?;
+		ledger.kept.retain(|e| *e != second[0]);
+		ledger.removed.push(second[0].clone());
 
-		// Verify we can read all the data
-		if let Some(result) = store.fetch(None, None)? {
-			let files = result.data.unwrap();
-			assert!(!files.is_empty(), "Should have recovered files");
+		for n in 5..7 {
+			ledger.attempted.push(event(n));
+			store.append(event(n))?;
+			ledger.kept.push(event(n));
+		}
+		Ok(())
+	}
 
-			// Read and verify contents of all files
-			for path in files {
-				let content = fs::read_to_string(&path)?;
-				let json: Value = serde_json::from_str(&content)?;
+	#[test]
+	fn test_recovers_from_crash_at_every_step() -> Result<()> {
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: PathBuf::from("/sim/events"),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+		let open = |fs: &SimFs, clock: &SimClock| {
+			DirectoryStore::with_fs(
+				config.clone(),
+				Arc::new(fs.clone()),
+				Arc::new(clock.clone()),
+			)
+		};
 
-				// Verify structure
-				assert!(json.get("batch").is_some(), "Should have batch field");
-				assert!(json.get("sentAt").is_some(), "Should have sentAt field");
-				assert_eq!(
-					json.get("writeKey").and_then(Value::as_str),
-					Some("test-key"),
-					"Should have correct writeKey"
-				);
+		// Count the steps of a clean run
+		let clock = SimClock::default();
+		let fs = SimFs::new(clock.clone());
+		let mut ledger = CrashLedger::default();
+		let mut store = open(&fs, &clock)?;
+		let opened = fs.ops();
+		crash_workload(&mut store, &fs, &mut ledger)?;
+		let steps = fs.ops() - opened;
+		assert!(steps > 20);
 
-				// All files should have .temp extension now
-				assert_eq!(
-					path.extension().and_then(|ext| ext.to_str()),
-					Some("temp"),
-					"All files should be finalized"
+		for step in 0..steps {
+			let clock = SimClock::default();
+			let fs = SimFs::new(clock.clone());
+			let mut ledger = CrashLedger::default();
+			let mut store = open(&fs, &clock)?;
+			fs.crash_at(fs.ops() + step);
+			assert!(crash_workload(&mut store, &fs, &mut ledger).is_err());
+			drop(store);
+			assert!(fs.crashed());
+
+			fs.recover();
+			let mut store = open(&fs, &clock)?;
+			let events = store.pending_events()?;
+			for event in &ledger.kept {
+				assert!(events.contains(event), "step {}: lost {}", step, event);
+			}
+			for event in &events {
+				assert!(
+					!ledger.removed.contains(event),
+					"step {}: resurrected {}",
+					step,
+					event
+				);
+				assert!(
+					ledger.attempted.contains(event),
+					"step {}: phantom {}",
+					step,
+					event
 				);
 			}
-		}
+			let mut unique = events.clone();
+			unique.dedup();
+			assert_eq!(unique.len(), events.len(), "step {}: duplicates", step);
 
-		// Verify we can still append new data
-		store.append(json!({"event": "new", "value": 999}))?;
-		store.finish_file()?;
+			// The recovered store keeps working
+			store.append(json!({"after": "recovery"}))?;
+			while let Some(result) = store.fetch(None, None)? {
+				store.remove(&result.removable.unwrap())?;
+			}
+			assert!(store.pending_events()?.is_empty());
+		}
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_file_rotation() -> Result<()> {
+	fn test_delivered_batches_persist() -> Result<()> {
 		let temp_dir = TempDir::new()?;
-
 		let config = DirectoryConfig {
 			write_key: "test-key".to_string(),
 			storage_location: temp_dir.path().to_owned(),
 			base_filename: "events".to_string(),
-			max_file_size: 100, // Small size to force rotation
+			max_file_size: 1024,
+		};
+
+		let batch_id = {
+			let mut store = DirectoryStore::new(config.clone())?;
+			store.append(json!({"event": "test"}))?;
+
+			let result = store.fetch(None, None)?.unwrap();
+			let batch_id = result.batch_id.unwrap();
+			store.mark_delivered(&batch_id)?;
+			store.remove(&result.removable.unwrap())?;
+			batch_id
 		};
 
+		// A new instance remembers the delivered batch and ignores re-adds of it
 		let mut store = DirectoryStore::new(config)?;
+		assert!(store.is_delivered(&batch_id));
+		assert!(!store.has_data());
 
-		// Add multiple events that should span files
-		for i in 0..5 {
-			let event = serde_json::json!({
-				"event": "test",
-				"index": i,
-				"data": "some longer data to help hit size limit...."
-			});
-			store.append(event)?;
-		}
+		store.append(json!({"batchId": batch_id, "batch": []}))?;
+		assert!(store.fetch(None, None)?.is_none());
 
-		// Fetch all files
-		if let Some(result) = store.fetch(None, None)? {
-			let files = result.data.unwrap();
-			assert!(files.len() > 1, "Expected multiple files due to size limit");
+		Ok(())
+	}
 
-			// Verify each file is properly formatted
-			for file in &files {
-				let content = fs::read_to_string(file)?.trim().to_string();
-				assert!(content.starts_with("{ \"batch\": ["));
-				assert!(content.ends_with("}"));
-				assert!(content.contains("\"writeKey\":\"test-key\""));
+	#[test]
+	fn test_retried_fetches_keep_their_batch_id() -> Result<()> {
+		let clock = SimClock::default();
+		let fs = SimFs::new(clock.clone());
+		let mut store = DirectoryStore::with_fs(
+			DirectoryConfig {
+				write_key: "test-key".to_string(),
+				storage_location: PathBuf::from("/sim/events"),
+				base_filename: "events".to_string(),
+				max_file_size: 1024,
+			},
+			Arc::new(fs),
+			Arc::new(clock),
+		)?;
+		store.append(json!({"n": 0}))?;
+		store.append(json!({"n": 1}))?;
 
-				// Validate JSON structure
-				let parsed: serde_json::Value = serde_json::from_str(&content)?;
-				assert!(parsed.get("batch").is_some(), "Missing 'batch' field");
-				assert!(parsed.get("writeKey").is_some(), "Missing 'writeKey' field");
-				assert!(parsed.get("sentAt").is_some(), "Missing 'sentAt' field");
-			}
-		} else {
-			panic!("Expected data but got none");
-		}
+		let first = store.fetch(None, None)?.unwrap();
+		let retried = store.fetch(None, None)?.unwrap();
+		assert_eq!(retried.batch_id, first.batch_id);
+		let id = store.list_batches()?[0].id.clone();
+		assert_eq!(store.fetch_batch(&id)?.unwrap().batch_id, first.batch_id);
 
+		// A file rewritten by a partial removal holds another batch
+		let files = first.removable.unwrap();
+		let path = files[0].as_any().downcast_ref::<PathBuf>().unwrap();
+		let items = store.item_removables(path)?;
+		store.remove(&items[..1])?;
+		let rewritten = store.fetch(None, None)?.unwrap();
+		assert_ne!(rewritten.batch_id, first.batch_id);
 		Ok(())
 	}
 
 	#[test]
-	fn test_fetch_with_size_limit() -> Result<()> {
+	fn test_recently_removed_survives_restarts() -> Result<()> {
 		let temp_dir = TempDir::new()?;
 		let config = DirectoryConfig {
 			write_key: "test-key".to_string(),
 			storage_location: temp_dir.path().to_owned(),
 			base_filename: "events".to_string(),
-			max_file_size: 200,
+			max_file_size: 1024,
 		};
 
-		let mut store = DirectoryStore::new(config)?;
-
-		// Create several files of known size
-		for i in 0..5 {
-			let event = serde_json::json!({
-				"event": "test",
-				"index": i,
-				"data": "padding data...."
-			});
-			store.append(event)?;
+		{
+			let mut store = DirectoryStore::new(config.clone())?;
+			store.set_retain_removed(5);
+			store.append(json!({"event": "first"}))?;
+			let result = store.fetch(None, None)?.unwrap();
+			store.remove(&result.removable.unwrap())?;
 		}
 
-		// Fetch with byte limit
-		if let Some(result) = store.fetch(None, Some(250))? {
-			let files = result.data.unwrap();
-			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
-			assert!(total_size <= 250, "Fetch exceeded size limit");
-		}
+		let mut store = DirectoryStore::new(config)?;
+		store.set_retain_removed(5);
+		let removed = store.recently_removed()?;
+		assert_eq!(removed.len(), 1);
+		assert_eq!(removed[0].events[0]["event"], "first");
 
+		// Reset clears the queue, not the record of what was delivered
+		store.append(json!({"event": "second"}))?;
+		store.reset();
+		assert!(!store.has_data());
+		assert_eq!(store.recently_removed()?.len(), 1);
 		Ok(())
 	}
 
 	#[test]
-	fn test_file_cleanup() -> Result<()> {
+	fn test_replay_writes_a_flagged_file() -> Result<()> {
 		let temp_dir = TempDir::new()?;
 		let config = DirectoryConfig {
 			write_key: "test-key".to_string(),
 			storage_location: temp_dir.path().to_owned(),
 			base_filename: "events".to_string(),
-			max_file_size: 200,
+			max_file_size: 1024,
 		};
 
-		let mut store = DirectoryStore::new(config)?;
+		let batch_id = {
+			let mut store = DirectoryStore::new(config.clone())?;
+			store.set_retain_removed(5);
+			store.append(json!({"event": "lost"}))?;
+			let result = store.fetch(None, None)?.unwrap();
+			store.remove(&result.removable.unwrap())?;
+			result.batch_id.unwrap()
+		};
 
-		// Add some events
-		let event = serde_json::json!({"event": "test"});
-		store.append(event)?;
+		// The batch id is retained on disk, so the replay can follow a restart
+		let mut store = DirectoryStore::new(config)?;
+		store.set_retain_removed(5);
+		assert_eq!(store.replay(&batch_id)?, 1);
 
-		// Fetch and then remove
-		if let Some(result) = store.fetch(None, None)? {
-			let files = result.data.unwrap();
-			let removable = result.removable.unwrap();
+		let files = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(files.len(), 1);
+		let envelope: Value = serde_json::from_str(&fs::read_to_string(&files[0])?)?;
+		assert_eq!(envelope["replay"], true);
+		assert_eq!(envelope["writeKey"], "test-key");
+		assert_eq!(
+			envelope["batch"],
+			json!([{"event": "lost", "_replayOf": batch_id}])
+		);
+		Ok(())
+	}
 
-			// Verify files exist
-			for file in &files {
-				assert!(file.exists());
-			}
+	#[test]
+	fn test_expire_older_than_deletes_stale_files() -> Result<()> {
+		let clock = SimClock::default();
+		let fs = SimFs::new(clock.clone());
+		let mut store = DirectoryStore::with_fs(
+			DirectoryConfig {
+				write_key: "test-key".to_string(),
+				storage_location: PathBuf::from("/sim/events"),
+				base_filename: "events".to_string(),
+				max_file_size: 1024,
+			},
+			Arc::new(fs.clone()),
+			Arc::new(clock.clone()),
+		)?;
+		let minutes = |n: u64| std::time::Duration::from_secs(n * 60);
+		let append = |store: &mut DirectoryStore, n: u32| {
+			let timestamp = clock.now().to_rfc3339();
+			store.append(json!({"n": n, "timestamp": timestamp}))
+		};
 
-			// Remove the files
-			store.remove(&removable)?;
+		// A file finalized an hour ago, and one with an event on either side of the cutoff
+		append(&mut store, 0)?;
+		append(&mut store, 1)?;
+		store.finish_file()?;
+		clock.advance(minutes(10));
+		append(&mut store, 2)?;
+		clock.advance(minutes(10));
+		append(&mut store, 3)?;
+		store.finish_file()?;
+		append(&mut store, 4)?;
+		clock.advance(minutes(40));
 
-			// Verify files are gone
-			for file in &files {
-				assert!(!file.exists());
-			}
-		}
+		assert_eq!(store.expire_older_than(minutes(45))?, 3);
+		let remaining: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|event| event["n"].clone())
+			.collect();
+		assert_eq!(remaining, [json!(3), json!(4)]);
 
+		let expired: usize = store
+			.drop_report()?
+			.iter()
+			.filter(|drop| drop.reason == DropReason::Expired)
+			.map(|drop| drop.count)
+			.sum();
+		assert_eq!(expired, 3);
+		assert_eq!(store.expire_older_than(minutes(45))?, 0);
 		Ok(())
 	}
 
 	#[test]
-	fn test_file_validator() -> Result<()> {
-		let temp_dir = TempDir::new()?;
-		let config = DirectoryConfig {
-			write_key: "test-key".to_string(),
-			storage_location: temp_dir.path().to_owned(),
-			base_filename: "events".to_string(),
-			max_file_size: 1024,
+	fn test_oldest_first_across_files() -> Result<()> {
+		let clock = SimClock::default();
+		let fs = SimFs::new(clock.clone());
+		let open = || {
+			let mut store = DirectoryStore::with_fs(
+				DirectoryConfig {
+					write_key: "test-key".to_string(),
+					storage_location: PathBuf::from("/sim/events"),
+					base_filename: "events".to_string(),
+					max_file_size: 1024,
+				},
+				Arc::new(fs.clone()),
+				Arc::new(clock.clone()),
+			)?;
+			store.set_delivery_cursor(true)?;
+			Ok::<_, io::Error>(store)
+		};
+		let first_event = |store: &DirectoryStore, files: &[PathBuf]| {
+			store
+				.read_batch(&files[0])
+				.map(|batch| batch[0]["n"].clone())
 		};
 
-		let mut store = DirectoryStore::new(config)?;
-
-		// Set a validator that ensures files are larger than 10 bytes
-		store.set_file_validator(|path| {
-			let metadata = fs::metadata(path)?;
-			if metadata.len() < 10 {
-				return Err(io::Error::new(io::ErrorKind::Other, "File too small"));
-			}
-			Ok(())
-		});
+		// Files started in the opposite order to their events
+		let mut store = open()?;
+		store.append(json!({"n": 0, "timestamp": "2024-01-03T00:00:00Z"}))?;
+		store.finish_file()?;
+		store.append(json!({"n": 1, "timestamp": "2024-01-02T00:00:00Z"}))?;
+		store.finish_file()?;
+		store.append(json!({"n": 2}))?;
+		store.append(json!({"n": 3, "timestamp": "2024-01-01T00:00:00Z"}))?;
 
-		let event = serde_json::json!({"event": "test"});
-		store.append(event)?;
+		let files = store.fetch(Some(1), None)?.unwrap().data.unwrap();
+		assert_eq!(first_event(&store, &files)?, 0);
 
-		// Fetch should trigger validation
-		let result = store.fetch(None, None)?;
-		assert!(result.is_some());
+		store.set_fetch_order(FetchOrder::OldestFirstAcrossFiles);
+		store.append(json!({"n": 4, "timestamp": "2024-01-04T00:00:00Z"}))?;
+		let preview = store.preview_fetch(Some(2), None)?;
+		let result = store.fetch(Some(2), None)?.unwrap();
+		let files = result.data.unwrap();
+		let bytes: u64 = files
+			.iter()
+			.filter_map(|file| store.fs.metadata(file).ok())
+			.map(|metadata| metadata.len)
+			.sum();
+		assert_eq!((preview.items, preview.bytes), (files.len(), bytes));
+		assert_eq!(first_event(&store, &files)?, 2);
+		assert_eq!(first_event(&store, &files[1..])?, 1);
 
+		// Delivering them mustn't count the file holding event 0 as delivered. The cursor
+		// can't cover them either while it's pending, so they'd be sent again, not lost
+		store.mark_delivered(&result.batch_id.unwrap())?;
+		drop(store);
+		let mut store = open()?;
+		let pending: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|event| event["n"].clone())
+			.collect();
+		assert!(pending.contains(&json!(0)));
+		assert_eq!(store.fetch(None, None)?.unwrap().data.unwrap().len(), 4);
 		Ok(())
 	}
 
 	#[test]
-	fn test_fetch_limits() -> Result<()> {
-		let temp_dir = TempDir::new()?;
-		let config = DirectoryConfig {
+	fn test_open_modes() -> Result<()> {
+		let clock = SimClock::default();
+		let fs = SimFs::new(clock.clone());
+		let config = |location: &str| DirectoryConfig {
 			write_key: "test-key".to_string(),
-			storage_location: temp_dir.path().to_owned(),
+			storage_location: PathBuf::from(location),
 			base_filename: "events".to_string(),
-			max_file_size: 100, // Small size to force multiple files
+			max_file_size: 1024,
 		};
+		let open = |location: &str, mode: OpenMode| {
+			DirectoryStore::open_with_fs(
+				config(location),
+				mode,
+				Arc::new(fs.clone()),
+				Arc::new(clock.clone()),
+			)
+		};
+		let read_only =
+			|err: io::Error| TransientError::from_io(&err) == Some(&TransientError::ReadOnly);
 
-		let mut store = DirectoryStore::new(config)?;
-
-		// Create 10 files with predictable sizes
-		for i in 0..10 {
-			let event = serde_json::json!({
-				"event": "test",
-				"index": i,
-				"data": "padding data to make files bigger..."
-			});
-			store.append(event)?;
-		}
-
-		// Test count limit only
-		if let Some(result) = store.fetch(Some(3), None)? {
-			let files = result.data.unwrap();
-			assert_eq!(files.len(), 3, "Count limit not respected");
+		for mode in [OpenMode::OpenExisting, OpenMode::ReadOnly] {
+			let err = open("/sim/missing", mode).err().unwrap();
+			assert_eq!(err.kind(), io::ErrorKind::NotFound);
 		}
 
-		// Test byte limit only
-		if let Some(result) = store.fetch(None, Some(250))? {
-			let files = result.data.unwrap();
-			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
-			assert!(total_size <= 250, "Byte limit not respected");
-		}
+		// A finalized file, and one left unfinished as if by a crash
+		let mut store = open("/sim/events", OpenMode::Create)?;
+		store.append(json!({"n": 0}))?;
+		store.finish_file()?;
+		store.append(json!({"n": 1}))?;
+		drop(store);
+		let files = fs.files();
+		let ops = fs.ops();
 
-		// Test both count and byte limits
-		if let Some(result) = store.fetch(Some(5), Some(200))? {
-			let files = result.data.unwrap();
-			assert!(
-				files.len() <= 5,
-				"Count limit not respected in combined test"
-			);
+		let mut store = open("/sim/events", OpenMode::ReadOnly)?;
+		assert!(store.is_read_only());
+		assert_eq!(store.pending_events()?, [json!({"n": 0}), json!({"n": 1})]);
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!(result.data.unwrap().len(), 1);
 
-			let total_size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
-			assert!(
-				total_size <= 200,
-				"Byte limit not respected in combined test"
-			);
-		}
+		assert!(read_only(
+			store.remove(&result.removable.unwrap()).unwrap_err()
+		));
+		assert!(read_only(store.append(json!({"n": 2})).unwrap_err()));
+		assert!(read_only(store.set_delivery_cursor(true).unwrap_err()));
+		assert!(read_only(store.drop_report().unwrap_err()));
+		store.reset();
+		drop(store);
+		assert_eq!((fs.files(), fs.ops()), (files, ops));
 
+		// Opening existing stores otherwise works as usual
+		let mut store = open("/sim/events", OpenMode::OpenExisting)?;
+		store.append(json!({"n": 2}))?;
+		assert_eq!(store.pending_events()?.len(), 3);
 		Ok(())
 	}
 
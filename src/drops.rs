@@ -0,0 +1,311 @@
+//! Records of events a store dropped without delivering them.
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+/// Why a store dropped events, see [`DropRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DropReason {
+	/// Appends took the store over its item limit, and the eviction policy (by default,
+	/// oldest first) chose these events to make room.
+	Capacity,
+	/// A DirectoryStore with [`DiskFullPolicy::EvictOldest`](crate::DiskFullPolicy::EvictOldest)
+	/// deleted a data file to make room on disk.
+	DiskFull,
+	/// A store with [`DownSampling`](crate::DownSampling) was under sustained pressure,
+	/// and sampled these low-priority events out as they were appended.
+	Sampled,
+	/// The events had been queued longer than
+	/// [`expire_older_than()`](crate::DataStoreExt::expire_older_than) allowed.
+	Expired,
+}
+
+impl DropReason {
+	fn as_str(self) -> &'static str {
+		match self {
+			DropReason::Capacity => "capacity",
+			DropReason::DiskFull => "disk_full",
+			DropReason::Sampled => "sampled",
+			DropReason::Expired => "expired",
+		}
+	}
+
+	fn parse(reason: &str) -> Option<Self> {
+		match reason {
+			"capacity" => Some(DropReason::Capacity),
+			"disk_full" => Some(DropReason::DiskFull),
+			"sampled" => Some(DropReason::Sampled),
+			"expired" => Some(DropReason::Expired),
+			_ => None,
+		}
+	}
+}
+
+/// Events a store dropped for the same reason, from
+/// [`drop_report()`](crate::TransientDB::drop_report).
+///
+/// Consecutive drops for the same reason are merged into one record, so a full store
+/// evicting an event per append keeps a single record growing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropRecord {
+	/// Why the events were dropped.
+	pub reason: DropReason,
+	/// Number of events dropped. For [`DropReason::DiskFull`], 0 if the deleted file
+	/// couldn't be read.
+	pub count: usize,
+	/// When the oldest dropped event was appended.
+	pub first: DateTime<Utc>,
+	/// When the newest dropped event was appended.
+	pub last: DateTime<Utc>,
+}
+
+impl DropRecord {
+	fn to_json(&self) -> Value {
+		json!({
+			"reason": self.reason.as_str(),
+			"count": self.count,
+			"first": self.first.to_rfc3339(),
+			"last": self.last.to_rfc3339(),
+		})
+	}
+
+	fn from_json(value: &Value) -> Option<Self> {
+		let time = |key: &str| {
+			DateTime::parse_from_rfc3339(value.get(key)?.as_str()?)
+				.ok()
+				.map(|time| time.with_timezone(&Utc))
+		};
+		Some(Self {
+			reason: DropReason::parse(value.get("reason")?.as_str()?)?,
+			count: usize::try_from(value.get("count")?.as_u64()?).ok()?,
+			first: time("first")?,
+			last: time("last")?,
+		})
+	}
+}
+
+/// Events a store just dropped, passed to an [`EvictionListener`].
+#[derive(Debug, Clone, Copy)]
+pub struct Evicted<'a> {
+	/// Why the events were dropped.
+	pub reason: DropReason,
+	/// The dropped events, oldest first, up to the listener's `max_events`.
+	pub events: &'a [Value],
+	/// Number of events dropped, including any beyond `max_events`.
+	pub count: usize,
+}
+
+/// Notified with the events a store drops, set with `set_eviction_listener`.
+///
+/// Where [`drop_report()`](crate::TransientDB::drop_report) only counts drops, a
+/// listener sees the events themselves, so it can persist them elsewhere or log their
+/// identifiers. It's called as the store drops them, while the store is locked: it
+/// mustn't use the same database, and should be quick.
+///
+/// Implemented for closures taking an [`Evicted`].
+///
+/// # Examples
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use serde_json::json;
+/// use transientdb::{DataStore, DropReason, Evicted, MemoryConfig, MemoryStore};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 2,
+///     max_fetch_size: 1024,
+/// });
+/// let evicted_ids = Arc::new(Mutex::new(Vec::new()));
+/// let ids = evicted_ids.clone();
+/// store.set_eviction_listener(
+///     move |evicted: Evicted<'_>| {
+///         assert_eq!(evicted.reason, DropReason::Capacity);
+///         let mut ids = ids.lock().unwrap();
+///         ids.extend(evicted.events.iter().map(|event| event["id"].clone()));
+///     },
+///     100,
+/// );
+///
+/// for id in 1..=3 {
+///     store.append(json!({"id": id}))?;
+/// }
+/// assert_eq!(*evicted_ids.lock().unwrap(), vec![json!(1)]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait EvictionListener: Send + Sync {
+	/// Called with events the store dropped.
+	fn evicted(&self, evicted: Evicted<'_>);
+}
+
+impl<F> EvictionListener for F
+where
+	F: Fn(Evicted<'_>) + Send + Sync,
+{
+	fn evicted(&self, evicted: Evicted<'_>) {
+		self(evicted)
+	}
+}
+
+/// A store's eviction listener, with the number of events it's passed at most.
+pub(crate) struct EvictionNotifier {
+	listener: Box<dyn EvictionListener>,
+	max_events: usize,
+}
+
+impl EvictionNotifier {
+	pub(crate) fn new(listener: Box<dyn EvictionListener>, max_events: usize) -> Self {
+		Self {
+			listener,
+			max_events,
+		}
+	}
+
+	/// Passes the first `max_events` of `count` dropped events to the listener.
+	pub(crate) fn notify<'v>(
+		&self,
+		reason: DropReason,
+		events: impl IntoIterator<Item = &'v Value>,
+		count: usize,
+	) {
+		let events: Vec<Value> = events.into_iter().take(self.max_events).cloned().collect();
+		self.listener.evicted(Evicted {
+			reason,
+			events: &events,
+			count,
+		});
+	}
+}
+
+/// A bounded log of drop records, oldest first.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DropLog {
+	records: Vec<DropRecord>,
+}
+
+impl DropLog {
+	/// Number of records kept; once exceeded, the oldest are forgotten.
+	pub(crate) const CAPACITY: usize = 100;
+
+	/// Restores a log from a JSON array, as produced by [`to_json`](Self::to_json).
+	/// Entries that can't be read are ignored.
+	pub(crate) fn from_json(value: &Value) -> Self {
+		let records = value
+			.as_array()
+			.map(|records| records.iter().filter_map(DropRecord::from_json).collect())
+			.unwrap_or_default();
+		Self { records }
+	}
+
+	pub(crate) fn to_json(&self) -> Value {
+		Value::from(
+			self.records
+				.iter()
+				.map(DropRecord::to_json)
+				.collect::<Vec<_>>(),
+		)
+	}
+
+	#[cfg(all(feature = "web", target_arch = "wasm32"))]
+	pub(crate) fn is_empty(&self) -> bool {
+		self.records.is_empty()
+	}
+
+	/// Records that `count` events appended between `first` and `last` were dropped.
+	pub(crate) fn record(
+		&mut self,
+		reason: DropReason,
+		count: usize,
+		first: DateTime<Utc>,
+		last: DateTime<Utc>,
+	) {
+		match self.records.last_mut() {
+			Some(newest) if newest.reason == reason => {
+				newest.count += count;
+				newest.first = newest.first.min(first);
+				newest.last = newest.last.max(last);
+			}
+			_ => self.records.push(DropRecord {
+				reason,
+				count,
+				first,
+				last,
+			}),
+		}
+		if self.records.len() > Self::CAPACITY {
+			self.records.drain(..self.records.len() - Self::CAPACITY);
+		}
+	}
+
+	/// Records dropped events from their enqueue times. Does nothing if there are none.
+	pub(crate) fn record_times(
+		&mut self,
+		reason: DropReason,
+		times: impl IntoIterator<Item = DateTime<Utc>>,
+	) {
+		let mut range: Option<(usize, DateTime<Utc>, DateTime<Utc>)> = None;
+		for time in times {
+			range = Some(match range {
+				Some((count, first, last)) => (count + 1, first.min(time), last.max(time)),
+				None => (1, time, time),
+			});
+		}
+		if let Some((count, first, last)) = range {
+			self.record(reason, count, first, last);
+		}
+	}
+
+	/// Returns the records and clears the log.
+	pub(crate) fn take(&mut self) -> Vec<DropRecord> {
+		std::mem::take(&mut self.records)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::Duration;
+
+	#[test]
+	fn test_drop_log_merges_and_round_trips() {
+		let start = Utc::now();
+		let mut log = DropLog::default();
+		log.record_times(DropReason::Capacity, [start + Duration::seconds(5), start]);
+		log.record_times(DropReason::Capacity, [start + Duration::seconds(9)]);
+		log.record(
+			DropReason::DiskFull,
+			40,
+			start,
+			start + Duration::seconds(1),
+		);
+		log.record_times(DropReason::Capacity, []);
+
+		let restored = DropLog::from_json(&log.to_json()).take();
+		assert_eq!(restored, log.take());
+		assert!(log.take().is_empty());
+
+		assert_eq!(restored.len(), 2);
+		assert_eq!(restored[0].reason, DropReason::Capacity);
+		assert_eq!(restored[0].count, 3);
+		assert_eq!(restored[0].first, start);
+		assert_eq!(restored[0].last, start + Duration::seconds(9));
+		assert_eq!(restored[1].count, 40);
+	}
+
+	#[test]
+	fn test_drop_log_is_bounded() {
+		let now = Utc::now();
+		let mut log = DropLog::default();
+		for i in 0..DropLog::CAPACITY + 5 {
+			let reason = if i % 2 == 0 {
+				DropReason::Capacity
+			} else {
+				DropReason::DiskFull
+			};
+			log.record(reason, i, now, now);
+		}
+		let records = log.take();
+		assert_eq!(records.len(), DropLog::CAPACITY);
+		assert_eq!(records[0].count, 5);
+	}
+}
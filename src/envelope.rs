@@ -0,0 +1,134 @@
+//! Streaming batch envelopes into writers, for batches too large to build in memory.
+
+use crate::JsonFormat;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::{Map, Value};
+use std::borrow::Borrow;
+use std::io::{self, BufWriter, Result, Write};
+
+/// Writes a batch envelope holding `events` and the other envelope `fields` (sentAt,
+/// writeKey, ...) to `writer` in `format`, returning the number of bytes written.
+///
+/// The output is identical to writing the envelope as a whole, but events are
+/// serialized one at a time, so neither the envelope nor its JSON is built in memory.
+pub(crate) fn write<I>(
+	writer: &mut dyn Write,
+	format: JsonFormat,
+	events: I,
+	fields: Value,
+) -> Result<u64>
+where
+	I: Iterator + Clone,
+	I::Item: Borrow<Value>,
+{
+	let Value::Object(fields) = format.normalize(fields) else {
+		return Err(io::Error::other("Envelope fields are not a JSON object"));
+	};
+	let envelope = Envelope {
+		events: Events {
+			events,
+			canonical: format == JsonFormat::Canonical,
+		},
+		fields: &fields,
+	};
+
+	// serde_json writes in small pieces, which would cost a call each on files and sockets
+	let mut out = BufWriter::new(Counter { writer, bytes: 0 });
+	match format {
+		JsonFormat::Compact | JsonFormat::Canonical => serde_json::to_writer(&mut out, &envelope),
+		JsonFormat::Pretty => serde_json::to_writer_pretty(&mut out, &envelope),
+	}
+	.map_err(io::Error::from)?;
+	out.flush()?;
+	Ok(out.get_ref().bytes)
+}
+
+struct Envelope<'f, I> {
+	events: Events<I>,
+	fields: &'f Map<String, Value>,
+}
+
+impl<I> Serialize for Envelope<'_, I>
+where
+	I: Iterator + Clone,
+	I::Item: Borrow<Value>,
+{
+	fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		let mut map = serializer.serialize_map(Some(self.fields.len() + 1))?;
+		// "batch" sorts first among the envelope keys, as it does in a built envelope
+		map.serialize_entry("batch", &self.events)?;
+		for (key, value) in self.fields.iter().filter(|(key, _)| *key != "batch") {
+			map.serialize_entry(key, value)?;
+		}
+		map.end()
+	}
+}
+
+struct Events<I> {
+	events: I,
+	canonical: bool,
+}
+
+impl<I> Serialize for Events<I>
+where
+	I: Iterator + Clone,
+	I::Item: Borrow<Value>,
+{
+	fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		let mut seq = serializer.serialize_seq(None)?;
+		for event in self.events.clone() {
+			let event = event.borrow();
+			if self.canonical {
+				seq.serialize_element(&JsonFormat::Canonical.normalize(event.clone()))?;
+			} else {
+				seq.serialize_element(event)?;
+			}
+		}
+		seq.end()
+	}
+}
+
+/// Counts the bytes written through it.
+struct Counter<'w> {
+	writer: &'w mut dyn Write,
+	bytes: u64,
+}
+
+impl Write for Counter<'_> {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		let written = self.writer.write(buf)?;
+		self.bytes += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.writer.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn test_streamed_envelope_matches_built_envelope() -> Result<()> {
+		let events = [json!({"b": 1, "a": [{"d": 2, "c": 3}]}), json!({"n": 2})];
+		let fields = json!({"writeKey": "key", "sentAt": "now", "batchId": "id"});
+		for format in [
+			JsonFormat::Compact,
+			JsonFormat::Canonical,
+			JsonFormat::Pretty,
+		] {
+			let mut built = fields.clone();
+			built["batch"] = Value::from(events.to_vec());
+			let expected = format.serialize(&format.normalize(built));
+
+			let mut out = Vec::new();
+			let written = write(&mut out, format, events.iter(), fields.clone())?;
+			assert_eq!(String::from_utf8(out).unwrap(), expected);
+			assert_eq!(written, expected.len() as u64);
+		}
+		Ok(())
+	}
+}
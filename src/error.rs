@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors specific to TransientDB.
+///
+/// Store methods return `std::io::Result`, so these are delivered wrapped in an
+/// [`io::Error`]. Use [`TransientError::from_io`] to check for them.
+///
+/// # Examples
+/// ```
+/// use std::io;
+/// use transientdb::TransientError;
+///
+/// let err: io::Error = TransientError::DiskFull { available: 10, required: 20 }.into();
+/// assert!(matches!(
+///     TransientError::from_io(&err),
+///     Some(TransientError::DiskFull { .. })
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransientError {
+	/// There isn't enough free disk space to write without dipping into the configured reserve.
+	DiskFull {
+		/// Free bytes available to the process.
+		available: u64,
+		/// Free bytes needed for the write, including the reserve.
+		required: u64,
+	},
+	/// The stored data was written in a newer format than this version of the crate
+	/// supports, by a later release. It is left untouched rather than misread.
+	IncompatibleFormat {
+		/// Format version of the stored data.
+		found: u64,
+		/// Newest format version this crate can read.
+		supported: u64,
+	},
+	/// An event couldn't be pinned because the store already holds as many pinned
+	/// events as it allows.
+	TooManyPinned {
+		/// Number of pinned events the store allows.
+		limit: usize,
+	},
+	/// The store was opened read-only, so it can't append, remove or otherwise change
+	/// what's stored.
+	ReadOnly,
+}
+
+impl TransientError {
+	/// Returns the TransientError wrapped in an io::Error, if there is one.
+	pub fn from_io(err: &io::Error) -> Option<&TransientError> {
+		err.get_ref()?.downcast_ref()
+	}
+}
+
+impl fmt::Display for TransientError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			TransientError::DiskFull {
+				available,
+				required,
+			} => write!(
+				f,
+				"Disk full: {} bytes available, {} required",
+				available, required
+			),
+			TransientError::IncompatibleFormat { found, supported } => write!(
+				f,
+				"Stored data has format version {}, but this version of transientdb only supports up to {}",
+				found, supported
+			),
+			TransientError::TooManyPinned { limit } => write!(
+				f,
+				"The store already holds its limit of {} pinned events",
+				limit
+			),
+			TransientError::ReadOnly => write!(f, "The store was opened read-only"),
+		}
+	}
+}
+
+impl Error for TransientError {}
+
+impl From<TransientError> for io::Error {
+	fn from(err: TransientError) -> Self {
+		io::Error::other(err)
+	}
+}
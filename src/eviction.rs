@@ -0,0 +1,279 @@
+//! Choosing which events a full store drops.
+
+use crate::JsonPointer;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::fmt;
+use std::io::{self, Result};
+
+/// Key of the flag marking events that are never evicted.
+pub(crate) const PINNED_KEY: &str = "_pinned";
+
+/// How many pinned events a store holds unless told otherwise.
+pub(crate) const DEFAULT_MAX_PINNED: usize = 100;
+
+/// Marks an event as exempt from eviction.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the flag.
+pub(crate) fn pin(data: &mut Value) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can be pinned",
+		)
+	})?;
+	object.insert(PINNED_KEY.to_string(), Value::Bool(true));
+	Ok(())
+}
+
+/// Whether an event was appended with `append_pinned`.
+pub(crate) fn is_pinned(event: &Value) -> bool {
+	event.get(PINNED_KEY).and_then(Value::as_bool) == Some(true)
+}
+
+/// An event a store may drop to get back under its `max_items`, passed to an
+/// [`EvictionPolicy`].
+#[derive(Debug, Clone, Copy)]
+pub struct EvictionCandidate<'a> {
+	/// The event.
+	pub event: &'a Value,
+	/// Serialized size of the event in bytes.
+	pub size: usize,
+	/// When the event was appended.
+	pub enqueued_at: DateTime<Utc>,
+}
+
+/// Decides which events a MemoryStore or WebStore drops when appends take it over
+/// `max_items`, set with `set_eviction_policy`.
+///
+/// Pinned events (see [`DataStoreExt::append_pinned`](crate::DataStoreExt::append_pinned))
+/// are never candidates.
+///
+/// Stores share one policy interface, so a store queueing crash reports can keep its
+/// oldest reports while a clickstream store drops the least important events.
+///
+/// # Examples
+/// ```
+/// use transientdb::{EvictionCandidate, EvictionPolicy};
+///
+/// /// Drops the newest events, keeping the first ones queued.
+/// struct NewestFirst;
+///
+/// impl EvictionPolicy for NewestFirst {
+///     fn victims(&self, candidates: &[EvictionCandidate], excess: usize) -> Vec<usize> {
+///         (candidates.len() - excess..candidates.len()).collect()
+///     }
+/// }
+/// ```
+pub trait EvictionPolicy: Send + Sync {
+	/// Returns the positions in `candidates` (queued events, oldest first) of the
+	/// `excess` events to drop.
+	///
+	/// Positions out of range or repeated are ignored, and if fewer than `excess` are
+	/// left, the store drops the oldest of the others to make up the difference.
+	fn victims(&self, candidates: &[EvictionCandidate], excess: usize) -> Vec<usize>;
+}
+
+/// Drops the oldest events first, which is what stores do without a policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FifoEviction;
+
+impl EvictionPolicy for FifoEviction {
+	fn victims(&self, _candidates: &[EvictionCandidate], excess: usize) -> Vec<usize> {
+		(0..excess).collect()
+	}
+}
+
+/// Drops the events with the lowest numeric priority first, read from a field of
+/// each event, and the oldest among equal priorities.
+///
+/// Events without a numeric value in the field have priority 0.
+///
+/// # Examples
+/// ```
+/// use transientdb::{JsonPointer, PriorityEviction};
+///
+/// // Events with {"priority": 10} outlive those with {"priority": 1}
+/// let policy = PriorityEviction::new(JsonPointer::new("/priority")?);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PriorityEviction {
+	field: JsonPointer,
+}
+
+impl PriorityEviction {
+	/// Reads each event's priority from `field`.
+	pub fn new(field: JsonPointer) -> Self {
+		Self { field }
+	}
+
+	fn priority(&self, event: &Value) -> f64 {
+		self.field
+			.get(event)
+			.and_then(Value::as_f64)
+			.unwrap_or_default()
+	}
+}
+
+impl EvictionPolicy for PriorityEviction {
+	fn victims(&self, candidates: &[EvictionCandidate], excess: usize) -> Vec<usize> {
+		let mut order: Vec<(f64, usize)> = candidates
+			.iter()
+			.enumerate()
+			.map(|(position, candidate)| (self.priority(candidate.event), position))
+			.collect();
+		// Stable, so equal priorities stay oldest first
+		order.sort_by(|a, b| a.0.total_cmp(&b.0));
+		order
+			.into_iter()
+			.take(excess)
+			.map(|(_, position)| position)
+			.collect()
+	}
+}
+
+/// Drops the events a cost function rates highest first, and the oldest among equal
+/// costs.
+///
+/// # Examples
+/// ```
+/// use transientdb::CostEviction;
+///
+/// // Drop the largest events, so as many as possible are kept
+/// let policy = CostEviction::new(|candidate| candidate.size as u64);
+/// ```
+pub struct CostEviction<F> {
+	cost: F,
+}
+
+impl<F> CostEviction<F>
+where
+	F: Fn(&EvictionCandidate) -> u64 + Send + Sync,
+{
+	/// Rates each candidate with `cost`.
+	pub fn new(cost: F) -> Self {
+		Self { cost }
+	}
+}
+
+impl<F> fmt::Debug for CostEviction<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("CostEviction").finish_non_exhaustive()
+	}
+}
+
+impl<F> EvictionPolicy for CostEviction<F>
+where
+	F: Fn(&EvictionCandidate) -> u64 + Send + Sync,
+{
+	fn victims(&self, candidates: &[EvictionCandidate], excess: usize) -> Vec<usize> {
+		let mut order: Vec<(u64, usize)> = candidates
+			.iter()
+			.enumerate()
+			.map(|(position, candidate)| ((self.cost)(candidate), position))
+			.collect();
+		order.sort_by_key(|(cost, _)| Reverse(*cost));
+		order
+			.into_iter()
+			.take(excess)
+			.map(|(_, position)| position)
+			.collect()
+	}
+}
+
+/// Asks `policy` which of `candidates` to drop, returning exactly `excess` positions
+/// (or all of them, if there are fewer) in ascending order.
+pub(crate) fn select_victims(
+	policy: &dyn EvictionPolicy,
+	candidates: &[EvictionCandidate],
+	excess: usize,
+) -> Vec<usize> {
+	let excess = excess.min(candidates.len());
+	let mut evicted = vec![false; candidates.len()];
+	let mut count = 0;
+	for position in policy.victims(candidates, excess) {
+		if count == excess {
+			break;
+		}
+		if let Some(slot) = evicted.get_mut(position).filter(|slot| !**slot) {
+			*slot = true;
+			count += 1;
+		}
+	}
+	// Make up for a policy that chose too few
+	for slot in evicted.iter_mut() {
+		if count == excess {
+			break;
+		}
+		if !*slot {
+			*slot = true;
+			count += 1;
+		}
+	}
+	evicted
+		.iter()
+		.enumerate()
+		.filter(|(_, evicted)| **evicted)
+		.map(|(position, _)| position)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		select_victims, CostEviction, EvictionCandidate, EvictionPolicy, FifoEviction,
+		PriorityEviction,
+	};
+	use crate::JsonPointer;
+	use chrono::Utc;
+	use serde_json::{json, Value};
+
+	fn candidates(events: &[Value]) -> Vec<EvictionCandidate<'_>> {
+		events
+			.iter()
+			.map(|event| EvictionCandidate {
+				event,
+				size: event.to_string().len(),
+				enqueued_at: Utc::now(),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_policies() -> std::io::Result<()> {
+		let events = vec![
+			json!({"priority": 5, "n": 0}),
+			json!({"n": 1, "padding": "xxxxxxxxxxxxxxxxxxxx"}),
+			json!({"priority": 1, "n": 2}),
+			json!({"priority": 5, "n": 3}),
+		];
+		let candidates = candidates(&events);
+
+		assert_eq!(select_victims(&FifoEviction, &candidates, 2), vec![0, 1]);
+
+		let priority = PriorityEviction::new(JsonPointer::new("/priority")?);
+		assert_eq!(select_victims(&priority, &candidates, 2), vec![1, 2]);
+		assert_eq!(select_victims(&priority, &candidates, 3), vec![0, 1, 2]);
+
+		let largest = CostEviction::new(|candidate: &EvictionCandidate| candidate.size as u64);
+		assert_eq!(select_victims(&largest, &candidates, 1), vec![1]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_misbehaving_policy_still_frees_room() {
+		struct Careless;
+		impl EvictionPolicy for Careless {
+			fn victims(&self, _: &[EvictionCandidate], _: usize) -> Vec<usize> {
+				vec![3, 3, 99]
+			}
+		}
+
+		let events = vec![json!(0), json!(1), json!(2), json!(3)];
+		let candidates = candidates(&events);
+		assert_eq!(select_victims(&Careless, &candidates, 2), vec![0, 3]);
+		assert_eq!(select_victims(&Careless, &candidates, 9).len(), 4);
+	}
+}
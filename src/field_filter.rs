@@ -0,0 +1,218 @@
+//! Stripping fields from events before they are stored.
+
+use crate::attachment::ATTACHMENTS_KEY;
+use crate::consent::CONSENT_KEY;
+use crate::eviction::PINNED_KEY;
+use crate::JsonPointer;
+use serde_json::{Map, Value};
+
+/// Fields stripped from every event at append time, so they never reach memory, disk or
+/// IndexedDB.
+///
+/// `allow`, if set, keeps only the listed fields (with everything below them); `deny`
+/// then removes the listed fields. Fields the store adds itself (`_attachments`,
+/// `_consent`) are never stripped. Non-object events are stored unchanged.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{DataStore, DataStoreExt, FieldFilter, JsonPointer, MemoryConfig, MemoryStore};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// });
+/// store.set_field_filter(FieldFilter {
+///     allow: None,
+///     deny: vec![JsonPointer::new("/user/email")?, JsonPointer::new("/token")?],
+/// });
+///
+/// store.append(json!({"event": "login", "token": "s3cr3t", "user": {"id": 1, "email": "a@b.c"}}))?;
+/// assert_eq!(store.pending_events()?, vec![json!({"event": "login", "user": {"id": 1}})]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldFilter {
+	/// Fields to keep, dropping all others. `None` keeps every field.
+	pub allow: Option<Vec<JsonPointer>>,
+	/// Fields to remove.
+	pub deny: Vec<JsonPointer>,
+}
+
+impl FieldFilter {
+	/// Whether the filter leaves events unchanged.
+	pub fn is_empty(&self) -> bool {
+		self.allow.is_none() && self.deny.is_empty()
+	}
+
+	/// Strips the filtered fields from an event.
+	pub(crate) fn apply(&self, event: &mut Value) {
+		if self.is_empty() || !event.is_object() {
+			return;
+		}
+		if let Some(allow) = &self.allow {
+			let paths: Vec<Vec<String>> = allow.iter().map(JsonPointer::tokens).collect();
+			let paths: Vec<&[String]> = paths.iter().map(Vec::as_slice).collect();
+			let reserved = Self::take_reserved(event);
+			keep_paths(event, &paths);
+			if let Some(fields) = event.as_object_mut() {
+				fields.extend(reserved);
+			}
+		}
+		for field in &self.deny {
+			let tokens = field.tokens();
+			if let [parent @ .., last] = tokens.as_slice() {
+				if parent.is_empty() && Self::is_reserved(last) {
+					continue;
+				}
+				remove_path(event, parent, last);
+			}
+		}
+	}
+
+	/// Describes the filter for `debug_config`.
+	pub(crate) fn to_json(&self) -> Value {
+		let pointers =
+			|fields: &[JsonPointer]| Value::from_iter(fields.iter().map(JsonPointer::as_str));
+		serde_json::json!({
+			"allow": self.allow.as_deref().map(pointers),
+			"deny": pointers(&self.deny),
+		})
+	}
+
+	fn is_reserved(key: &str) -> bool {
+		key == ATTACHMENTS_KEY || key == CONSENT_KEY || key == PINNED_KEY
+	}
+
+	fn take_reserved(event: &mut Value) -> Map<String, Value> {
+		let mut reserved = Map::new();
+		if let Some(fields) = event.as_object_mut() {
+			for key in [ATTACHMENTS_KEY, CONSENT_KEY, PINNED_KEY] {
+				if let Some(value) = fields.remove(key) {
+					reserved.insert(key.to_string(), value);
+				}
+			}
+		}
+		reserved
+	}
+}
+
+/// Drops everything in `value` not on one of `paths`. An empty path keeps the whole value.
+fn keep_paths(value: &mut Value, paths: &[&[String]]) {
+	if paths.iter().any(|path| path.is_empty()) {
+		return;
+	}
+	let below = |key: &str| -> Vec<&[String]> {
+		paths
+			.iter()
+			.filter(|path| path[0] == key)
+			.map(|path| &path[1..])
+			.collect()
+	};
+	match value {
+		Value::Object(fields) => {
+			fields.retain(|key, child| {
+				let paths = below(key);
+				keep_paths(child, &paths);
+				!paths.is_empty()
+			});
+		}
+		Value::Array(items) => {
+			let mut index = 0;
+			items.retain_mut(|item| {
+				let paths = below(&index.to_string());
+				index += 1;
+				keep_paths(item, &paths);
+				!paths.is_empty()
+			});
+		}
+		_ => {}
+	}
+}
+
+/// Removes the field `last` of the container at `parent`, if there is one.
+fn remove_path(value: &mut Value, parent: &[String], last: &str) {
+	let mut container = value;
+	for token in parent {
+		container = match container {
+			Value::Object(fields) => match fields.get_mut(token) {
+				Some(child) => child,
+				None => return,
+			},
+			Value::Array(items) => match token.parse::<usize>().ok().and_then(|i| items.get_mut(i))
+			{
+				Some(child) => child,
+				None => return,
+			},
+			_ => return,
+		};
+	}
+	match container {
+		Value::Object(fields) => {
+			fields.remove(last);
+		}
+		Value::Array(items) => {
+			if let Some(index) = last.parse::<usize>().ok().filter(|i| *i < items.len()) {
+				items.remove(index);
+			}
+		}
+		_ => {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::FieldFilter;
+	use crate::JsonPointer;
+	use serde_json::json;
+
+	fn pointers(fields: &[&str]) -> Vec<JsonPointer> {
+		fields
+			.iter()
+			.map(|f| JsonPointer::new(*f).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn test_allow_and_deny() {
+		let filter = FieldFilter {
+			allow: Some(pointers(&["/event", "/context/app", "/items/0", "/user"])),
+			deny: pointers(&["/user/email", "/context/app/token", "/_consent"]),
+		};
+		let mut event = json!({
+			"event": "checkout",
+			"context": {"app": {"version": "1.2", "token": "t"}, "ip": "10.0.0.1"},
+			"items": [{"sku": "a"}, {"sku": "b"}],
+			"user": {"id": 7, "email": "a@b.c"},
+			"password": "hunter2",
+			"_consent": "analytics",
+			"_attachments": [{"id": "x"}],
+		});
+		filter.apply(&mut event);
+		assert_eq!(
+			event,
+			json!({
+				"event": "checkout",
+				"context": {"app": {"version": "1.2"}},
+				"items": [{"sku": "a"}],
+				"user": {"id": 7},
+				"_consent": "analytics",
+				"_attachments": [{"id": "x"}],
+			})
+		);
+
+		// Escaped keys, array elements and missing fields
+		let filter = FieldFilter {
+			allow: None,
+			deny: pointers(&["/a~1b", "/list/1", "/missing/deep"]),
+		};
+		let mut event = json!({"a/b": 1, "list": [0, 1, 2], "c": 3});
+		filter.apply(&mut event);
+		assert_eq!(event, json!({"list": [0, 2], "c": 3}));
+
+		let mut scalar = json!("not an object");
+		filter.apply(&mut scalar);
+		assert_eq!(scalar, json!("not an object"));
+	}
+}
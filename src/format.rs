@@ -0,0 +1,84 @@
+use serde_json::{Map, Value};
+use std::io::{self, Result, Write};
+
+/// Controls how stores serialize JSON.
+///
+/// The format applies to batch envelopes, DirectoryStore file contents, and
+/// events persisted to IndexedDB by WebStore.
+///
+/// # Examples
+/// ```
+/// use transientdb::JsonFormat;
+/// use serde_json::json;
+///
+/// let value = json!({"b": 1, "a": {"d": 2, "c": 3}});
+///
+/// assert_eq!(JsonFormat::Canonical.serialize(&value), r#"{"a":{"c":3,"d":2},"b":1}"#);
+/// assert!(!JsonFormat::Compact.serialize(&value).contains(' '));
+/// assert!(JsonFormat::Pretty.serialize(&value).contains('\n'));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonFormat {
+	/// Minimal JSON with no extra whitespace. Object keys keep their existing order.
+	#[default]
+	Compact,
+	/// Compact JSON with object keys sorted recursively, so equal values always
+	/// serialize to identical bytes. Use this when hashing or deduplicating batches.
+	Canonical,
+	/// Indented, human-readable JSON for debugging and exports.
+	Pretty,
+}
+
+impl JsonFormat {
+	/// Prepares a value for output in this format.
+	///
+	/// For `Canonical` this sorts object keys recursively; other formats return the value unchanged.
+	pub fn normalize(&self, value: Value) -> Value {
+		match self {
+			JsonFormat::Canonical => Self::canonicalize(value),
+			JsonFormat::Compact | JsonFormat::Pretty => value,
+		}
+	}
+
+	/// Serializes a value to a string in this format.
+	pub fn serialize(&self, value: &Value) -> String {
+		match self {
+			JsonFormat::Compact => value.to_string(),
+			JsonFormat::Canonical => Self::canonicalize(value.clone()).to_string(),
+			JsonFormat::Pretty => {
+				serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+			}
+		}
+	}
+
+	/// Serializes a value into a writer in this format.
+	pub fn write<W: Write>(&self, writer: W, value: &Value) -> Result<()> {
+		match self {
+			JsonFormat::Compact => serde_json::to_writer(writer, value),
+			JsonFormat::Canonical => {
+				serde_json::to_writer(writer, &Self::canonicalize(value.clone()))
+			}
+			JsonFormat::Pretty => serde_json::to_writer_pretty(writer, value),
+		}
+		.map_err(io::Error::from)
+	}
+
+	fn canonicalize(value: Value) -> Value {
+		match value {
+			Value::Object(map) => {
+				let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+				entries.sort_by(|a, b| a.0.cmp(&b.0));
+				Value::Object(
+					entries
+						.into_iter()
+						.map(|(key, value)| (key, Self::canonicalize(value)))
+						.collect::<Map<_, _>>(),
+				)
+			}
+			Value::Array(items) => {
+				Value::Array(items.into_iter().map(Self::canonicalize).collect())
+			}
+			other => other,
+		}
+	}
+}
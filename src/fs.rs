@@ -0,0 +1,324 @@
+//! Filesystem and clock abstractions used by DirectoryStore.
+//!
+//! [`StdFs`] and [`SystemClock`] are the defaults. Swapping in other implementations,
+//! such as the deterministic [`SimFs`](crate::SimFs) and [`SimClock`](crate::SimClock),
+//! lets the store's logic be exercised without touching a real disk.
+
+use crate::platform;
+use crate::TransientError;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{Result, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// An entry returned by [`Fs::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEntry {
+	/// Full path of the entry.
+	pub path: PathBuf,
+	/// Whether the entry is a regular file.
+	pub is_file: bool,
+	/// Whether the entry is a symbolic link. Links are never followed by the store.
+	pub is_symlink: bool,
+}
+
+/// File information returned by [`Fs::metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetadata {
+	/// Size of the file in bytes.
+	pub len: u64,
+	/// When the file was created, or last modified where creation times aren't recorded.
+	pub created: Option<DateTime<Utc>>,
+}
+
+/// The contents of a file returned by [`Fs::read_mapped`].
+///
+/// Dereferences to the file's bytes, which are memory-mapped where the filesystem and
+/// platform support it, and read into memory otherwise. A mapped file must not be
+/// truncated while this is alive, see [`Fs::read_mapped`].
+pub struct MappedFile(MappedContent);
+
+enum MappedContent {
+	Read(Vec<u8>),
+	#[cfg(unix)]
+	Mapped(platform::Mmap),
+}
+
+impl MappedFile {
+	/// Whether the bytes are mapped from the file rather than copied into memory.
+	pub fn is_mapped(&self) -> bool {
+		match self.0 {
+			MappedContent::Read(_) => false,
+			#[cfg(unix)]
+			MappedContent::Mapped(_) => true,
+		}
+	}
+}
+
+impl From<Vec<u8>> for MappedFile {
+	fn from(bytes: Vec<u8>) -> Self {
+		Self(MappedContent::Read(bytes))
+	}
+}
+
+impl Deref for MappedFile {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match &self.0 {
+			MappedContent::Read(bytes) => bytes,
+			#[cfg(unix)]
+			MappedContent::Mapped(map) => map.as_slice(),
+		}
+	}
+}
+
+impl AsRef<[u8]> for MappedFile {
+	fn as_ref(&self) -> &[u8] {
+		self
+	}
+}
+
+impl fmt::Debug for MappedFile {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("MappedFile")
+			.field("len", &self.len())
+			.field("mapped", &self.is_mapped())
+			.finish()
+	}
+}
+
+/// A writable file handle returned by [`Fs::create_new`] and [`Fs::append`].
+pub type FsWriter = Box<dyn Write + Send>;
+
+/// The file operations DirectoryStore performs.
+///
+/// Paths are always inside (or are) the store's storage location, except for the
+/// directories passed to [`create_dir_all`](Self::create_dir_all).
+pub trait Fs: Send + Sync {
+	/// Creates a directory and any missing parents.
+	fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+	/// Lists the entries of a directory.
+	fn read_dir(&self, path: &Path) -> Result<Vec<FsEntry>>;
+
+	/// Returns the size and creation time of a file.
+	fn metadata(&self, path: &Path) -> Result<FsMetadata>;
+
+	/// Reads a whole file.
+	fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+	/// Reads a whole file as UTF-8.
+	fn read_to_string(&self, path: &Path) -> Result<String> {
+		String::from_utf8(self.read(path)?)
+			.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+	}
+
+	/// Reads a whole file, memory-mapping it where possible.
+	///
+	/// The default implementation reads the file with [`read`](Self::read).
+	///
+	/// # Safety
+	///
+	/// The file must not be truncated or written in place while the returned
+	/// [`MappedFile`] is alive. Reading a mapped page past the new end of the file
+	/// raises `SIGBUS`. Removing the file or replacing it with a rename is fine.
+	unsafe fn read_mapped(&self, path: &Path) -> Result<MappedFile> {
+		self.read(path).map(MappedFile::from)
+	}
+
+	/// Creates or truncates a file and writes `contents` to it.
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>;
+
+	/// Creates a file that must not already exist, failing with `AlreadyExists` if it does.
+	fn create_new(&self, path: &Path) -> Result<FsWriter>;
+
+	/// Opens an existing file for appending.
+	fn append(&self, path: &Path) -> Result<FsWriter>;
+
+	/// Removes a file.
+	fn remove_file(&self, path: &Path) -> Result<()>;
+
+	/// Renames a file, replacing `to` if it exists.
+	fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+	/// Returns the space available to the store at `path`, or `None` if unknown.
+	fn available_space(&self, _path: &Path) -> Option<u64> {
+		None
+	}
+
+	/// Whether paths are real paths that `std::fs` can open.
+	///
+	/// When they are, fetched attachments refer to their files instead of being read into
+	/// memory, and features that need the OS (permissions, watching) are available.
+	fn is_native(&self) -> bool {
+		false
+	}
+}
+
+/// Where DirectoryStore gets the current time from.
+pub trait TimeSource: Send + Sync {
+	/// Returns the current time.
+	fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real filesystem, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl Fs for StdFs {
+	fn create_dir_all(&self, path: &Path) -> Result<()> {
+		fs::create_dir_all(path)
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<FsEntry>> {
+		Ok(fs::read_dir(path)?
+			.filter_map(Result::ok)
+			.map(|entry| {
+				let file_type = entry.file_type().ok();
+				FsEntry {
+					path: entry.path(),
+					is_file: file_type.is_some_and(|t| t.is_file()),
+					is_symlink: file_type.is_some_and(|t| t.is_symlink()),
+				}
+			})
+			.collect())
+	}
+
+	fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+		let metadata = fs::metadata(path)?;
+		Ok(FsMetadata {
+			len: metadata.len(),
+			created: metadata
+				.created()
+				.or_else(|_| metadata.modified())
+				.ok()
+				.map(Into::into),
+		})
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>> {
+		fs::read(path)
+	}
+
+	fn read_to_string(&self, path: &Path) -> Result<String> {
+		fs::read_to_string(path)
+	}
+
+	#[cfg(unix)]
+	unsafe fn read_mapped(&self, path: &Path) -> Result<MappedFile> {
+		// SAFETY: the caller keeps the file from being truncated while it's mapped
+		Ok(match unsafe { platform::map_file(path)? } {
+			Some(map) => MappedFile(MappedContent::Mapped(map)),
+			None => MappedFile::from(Vec::new()),
+		})
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+		fs::write(path, contents)
+	}
+
+	fn create_new(&self, path: &Path) -> Result<FsWriter> {
+		Ok(Box::new(
+			OpenOptions::new().write(true).create_new(true).open(path)?,
+		))
+	}
+
+	fn append(&self, path: &Path) -> Result<FsWriter> {
+		Ok(Box::new(OpenOptions::new().append(true).open(path)?))
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()> {
+		platform::remove_file(path)
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+		platform::rename(from, to)
+	}
+
+	fn available_space(&self, path: &Path) -> Option<u64> {
+		platform::available_space(path)
+	}
+
+	fn is_native(&self) -> bool {
+		true
+	}
+}
+
+/// Passes reads through to another [`Fs`] and refuses every change with
+/// [`TransientError::ReadOnly`], for stores opened read-only.
+pub(crate) struct ReadOnlyFs(pub(crate) Arc<dyn Fs>);
+
+impl ReadOnlyFs {
+	fn refuse<T>() -> Result<T> {
+		Err(TransientError::ReadOnly.into())
+	}
+}
+
+impl Fs for ReadOnlyFs {
+	fn create_dir_all(&self, _path: &Path) -> Result<()> {
+		Self::refuse()
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<FsEntry>> {
+		self.0.read_dir(path)
+	}
+
+	fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+		self.0.metadata(path)
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>> {
+		self.0.read(path)
+	}
+
+	fn read_to_string(&self, path: &Path) -> Result<String> {
+		self.0.read_to_string(path)
+	}
+
+	unsafe fn read_mapped(&self, path: &Path) -> Result<MappedFile> {
+		// SAFETY: the caller upholds the contract of the wrapped filesystem
+		unsafe { self.0.read_mapped(path) }
+	}
+
+	fn write(&self, _path: &Path, _contents: &[u8]) -> Result<()> {
+		Self::refuse()
+	}
+
+	fn create_new(&self, _path: &Path) -> Result<FsWriter> {
+		Self::refuse()
+	}
+
+	fn append(&self, _path: &Path) -> Result<FsWriter> {
+		Self::refuse()
+	}
+
+	fn remove_file(&self, _path: &Path) -> Result<()> {
+		Self::refuse()
+	}
+
+	fn rename(&self, _from: &Path, _to: &Path) -> Result<()> {
+		Self::refuse()
+	}
+
+	fn available_space(&self, path: &Path) -> Option<u64> {
+		self.0.available_space(path)
+	}
+
+	fn is_native(&self) -> bool {
+		self.0.is_native()
+	}
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+	fn now(&self) -> DateTime<Utc> {
+		Utc::now()
+	}
+}
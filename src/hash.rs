@@ -0,0 +1,180 @@
+//! Hashes of stored content, used to name and deduplicate batches.
+//!
+//! [`Hasher`] only covers naming and deduplicating content-addressed batch files. Debug
+//! dumps hash values with a salted [`XxHash64Hasher`] but don't take a `Hasher`, while
+//! anonymization and batch signing need a keyed cryptographic hash and use SHA-256
+//! directly. Nothing in the crate checksums stored data.
+
+#[cfg(feature = "sha256")]
+use sha2::{Digest, Sha256};
+
+/// Hashes content for deduplication and content-addressed names, see
+/// [`DirectoryStore::set_hasher`](crate::DirectoryStore::set_hasher).
+///
+/// Hashes end up in file names, so they must be non-empty and ASCII alphanumeric;
+/// lowercase hex is the safe choice. Other output is hex-encoded before use.
+///
+/// # Examples
+/// ```
+/// use transientdb::Hasher;
+///
+/// /// Hashes by length only, to show the shape of an implementation
+/// struct LengthHasher;
+///
+/// impl Hasher for LengthHasher {
+///     fn name(&self) -> &str {
+///         "length"
+///     }
+///
+///     fn hash(&self, bytes: &[u8]) -> String {
+///         format!("{:016x}", bytes.len())
+///     }
+/// }
+/// ```
+pub trait Hasher: Send + Sync {
+	/// Short name of the algorithm, e.g. `"sha256"`, shown in debug dumps.
+	fn name(&self) -> &str;
+
+	/// Hash of `bytes`, non-empty and ASCII alphanumeric only.
+	fn hash(&self, bytes: &[u8]) -> String;
+}
+
+/// XXH64 with a seed of 0, as lowercase hex. Fast, but not collision resistant against
+/// someone crafting events on purpose. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XxHash64Hasher;
+
+impl XxHash64Hasher {
+	const PRIME_1: u64 = 0x9e3779b185ebca87;
+	const PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+	const PRIME_3: u64 = 0x165667b19e3779f9;
+	const PRIME_4: u64 = 0x85ebca77c2b2ae63;
+	const PRIME_5: u64 = 0x27d4eb2f165667c5;
+
+	fn round(acc: u64, lane: u64) -> u64 {
+		acc.wrapping_add(lane.wrapping_mul(Self::PRIME_2))
+			.rotate_left(31)
+			.wrapping_mul(Self::PRIME_1)
+	}
+
+	fn merge_round(acc: u64, lane: u64) -> u64 {
+		(acc ^ Self::round(0, lane))
+			.wrapping_mul(Self::PRIME_1)
+			.wrapping_add(Self::PRIME_4)
+	}
+
+	fn read_u64(bytes: &[u8]) -> u64 {
+		let mut word = [0; 8];
+		word.copy_from_slice(&bytes[..8]);
+		u64::from_le_bytes(word)
+	}
+
+	fn digest(bytes: &[u8]) -> u64 {
+		let mut stripes = bytes.chunks_exact(32);
+		let mut hash = if bytes.len() >= 32 {
+			let mut lanes = [
+				Self::PRIME_1.wrapping_add(Self::PRIME_2),
+				Self::PRIME_2,
+				0,
+				0u64.wrapping_sub(Self::PRIME_1),
+			];
+			for stripe in &mut stripes {
+				for (lane, word) in lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+					*lane = Self::round(*lane, Self::read_u64(word));
+				}
+			}
+			let hash = lanes[0]
+				.rotate_left(1)
+				.wrapping_add(lanes[1].rotate_left(7))
+				.wrapping_add(lanes[2].rotate_left(12))
+				.wrapping_add(lanes[3].rotate_left(18));
+			lanes
+				.iter()
+				.fold(hash, |hash, &lane| Self::merge_round(hash, lane))
+		} else {
+			Self::PRIME_5
+		};
+		hash = hash.wrapping_add(bytes.len() as u64);
+
+		let mut rest = stripes.remainder();
+		while rest.len() >= 8 {
+			hash ^= Self::round(0, Self::read_u64(rest));
+			hash = hash
+				.rotate_left(27)
+				.wrapping_mul(Self::PRIME_1)
+				.wrapping_add(Self::PRIME_4);
+			rest = &rest[8..];
+		}
+		if rest.len() >= 4 {
+			let mut word = [0; 4];
+			word.copy_from_slice(&rest[..4]);
+			hash ^= (u32::from_le_bytes(word) as u64).wrapping_mul(Self::PRIME_1);
+			hash = hash
+				.rotate_left(23)
+				.wrapping_mul(Self::PRIME_2)
+				.wrapping_add(Self::PRIME_3);
+			rest = &rest[4..];
+		}
+		for &byte in rest {
+			hash ^= (byte as u64).wrapping_mul(Self::PRIME_5);
+			hash = hash.rotate_left(11).wrapping_mul(Self::PRIME_1);
+		}
+
+		hash ^= hash >> 33;
+		hash = hash.wrapping_mul(Self::PRIME_2);
+		hash ^= hash >> 29;
+		hash = hash.wrapping_mul(Self::PRIME_3);
+		hash ^ (hash >> 32)
+	}
+}
+
+impl Hasher for XxHash64Hasher {
+	fn name(&self) -> &str {
+		"xxh64"
+	}
+
+	fn hash(&self, bytes: &[u8]) -> String {
+		format!("{:016x}", Self::digest(bytes))
+	}
+}
+
+/// SHA-256, as lowercase hex, for deployments that require a cryptographic hash.
+/// Requires the `sha256` feature.
+#[cfg(feature = "sha256")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+#[cfg(feature = "sha256")]
+impl Hasher for Sha256Hasher {
+	fn name(&self) -> &str {
+		"sha256"
+	}
+
+	fn hash(&self, bytes: &[u8]) -> String {
+		Sha256::digest(bytes)
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_hashers_match_reference_values() {
+		assert_eq!(XxHash64Hasher.hash(b""), "ef46db3751d8e999");
+		assert_eq!(XxHash64Hasher.hash(b"abc"), "44bc2cf5ad770999");
+		// Long enough for the 32-byte stripes
+		assert_eq!(
+			XxHash64Hasher.hash(b"Nobody inspects the spammish repetition"),
+			"fbcea83c8a378bf1"
+		);
+		#[cfg(feature = "sha256")]
+		assert_eq!(
+			Sha256Hasher.hash(b"abc"),
+			"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+		);
+	}
+}
@@ -0,0 +1,179 @@
+//! Importing events produced outside the queue, e.g. files left behind by an older SDK.
+
+use crate::DataStore;
+use serde_json::Value;
+use std::io::{Read, Result};
+
+/// The outcome of importing events.
+///
+/// Malformed input is reported here rather than failing the import, so one bad line
+/// doesn't cost the rest of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+	/// Number of events accepted for appending to the queue. Like any append, the store
+	/// may still drop some of them, e.g. for consent, sampling or when it's full.
+	pub imported: usize,
+	/// Entries that were skipped.
+	pub errors: Vec<ImportError>,
+}
+
+/// An entry skipped during an import.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+	/// 1-based line number for NDJSON input, or 1-based position in the array for JSON input
+	/// and in the file's batch for a Segment queue file.
+	pub position: usize,
+	/// Why the entry was skipped.
+	pub message: String,
+}
+
+/// Reads events and appends them to a store.
+///
+/// See [`parse_events`] for the accepted formats. Errors reading the input or appending
+/// to the store abort the import.
+pub(crate) fn import_events<S, R>(store: &mut S, reader: R) -> Result<ImportReport>
+where
+	S: DataStore + ?Sized,
+	R: Read,
+{
+	let (events, report) = parse_events(reader)?;
+	store.append_many(events)?;
+	Ok(report)
+}
+
+/// Reads events to import, returning them with a report of the entries skipped and
+/// `imported` set to the number of events returned.
+///
+/// Accepts, in order of detection:
+/// - a JSON array of events
+/// - a batch envelope (`{"batch": [...], ...}`), as written by DirectoryStore
+/// - a single JSON event object
+/// - newline-delimited JSON (NDJSON), one event per line
+///
+/// Events must be JSON objects. Anything else, and NDJSON lines that aren't UTF-8 or
+/// don't parse, are recorded in the report and skipped. Errors reading the input abort the import.
+pub(crate) fn parse_events<R: Read>(mut reader: R) -> Result<(Vec<Value>, ImportReport)> {
+	let mut content = Vec::new();
+	reader.read_to_end(&mut content)?;
+
+	let mut report = ImportReport::default();
+	let entries: Vec<(usize, std::result::Result<Value, String>)> =
+		match serde_json::from_slice::<Value>(&content) {
+			Ok(Value::Array(items)) => items.into_iter().map(Ok).enumerate().collect(),
+			Ok(Value::Object(mut object)) => match object.remove("batch") {
+				Some(Value::Array(items)) => items.into_iter().map(Ok).enumerate().collect(),
+				Some(batch) => {
+					object.insert("batch".to_string(), batch);
+					vec![(0, Ok(Value::Object(object)))]
+				}
+				None => vec![(0, Ok(Value::Object(object)))],
+			},
+			Ok(other) => vec![(0, Ok(other))],
+			Err(_) => content
+				.split(|&b| b == b'\n')
+				.enumerate()
+				.filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+				.map(|(index, line)| {
+					let event = std::str::from_utf8(line)
+						.map_err(|e| format!("Line is not valid UTF-8: {}", e))
+						.and_then(|line| {
+							serde_json::from_str::<Value>(line).map_err(|e| e.to_string())
+						});
+					(index, event)
+				})
+				.collect(),
+		};
+
+	let mut events = Vec::new();
+	for (index, entry) in entries {
+		match entry {
+			Ok(event @ Value::Object(_)) => events.push(event),
+			Ok(_) => report.errors.push(ImportError {
+				position: index + 1,
+				message: "Event is not a JSON object".to_string(),
+			}),
+			Err(message) => report.errors.push(ImportError {
+				position: index + 1,
+				message,
+			}),
+		}
+	}
+
+	report.imported = events.len();
+	Ok((events, report))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::import_events;
+	use crate::{DataStore, MemoryConfig, MemoryStore};
+	use serde_json::{json, Value};
+	use std::io::Result;
+
+	fn store() -> MemoryStore {
+		MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 10_000,
+		})
+	}
+
+	fn queued(store: &mut MemoryStore) -> Result<Vec<Value>> {
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		Ok(batch["batch"].as_array().unwrap().clone())
+	}
+
+	#[test]
+	fn test_import_ndjson_with_bad_lines() -> Result<()> {
+		let mut store = store();
+		let input = "{\"event\": 1}\n\nnot json\n{\"event\": 2}\n42\n";
+
+		let report = import_events(&mut store, input.as_bytes())?;
+		assert_eq!(report.imported, 2);
+		let positions: Vec<usize> = report.errors.iter().map(|e| e.position).collect();
+		assert_eq!(positions, vec![3, 5]);
+
+		assert_eq!(
+			queued(&mut store)?,
+			vec![json!({"event": 1}), json!({"event": 2})]
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_import_ndjson_with_invalid_utf8() -> Result<()> {
+		let mut store = store();
+		let input = b"{\"event\": 1}\r\n{\"event\": \"\xff\"}\n{\"event\": 2}\n";
+
+		let report = import_events(&mut store, &input[..])?;
+		assert_eq!(report.imported, 2);
+		assert_eq!(report.errors.len(), 1);
+		assert_eq!(report.errors[0].position, 2);
+		assert!(report.errors[0].message.contains("UTF-8"));
+
+		assert_eq!(
+			queued(&mut store)?,
+			vec![json!({"event": 1}), json!({"event": 2})]
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_import_json_documents() -> Result<()> {
+		let mut store = store();
+
+		let report = import_events(&mut store, r#"[{"event": 1}, "oops"]"#.as_bytes())?;
+		assert_eq!(report.imported, 1);
+		assert_eq!(report.errors[0].position, 2);
+
+		let envelope = r#"{
+			"batch": [{"event": 2}, {"event": 3}],
+			"sentAt": "2024-01-01T00:00:00.000Z",
+			"writeKey": "old-sdk"
+		}"#;
+		assert_eq!(import_events(&mut store, envelope.as_bytes())?.imported, 2);
+
+		assert_eq!(queued(&mut store)?.len(), 3);
+		Ok(())
+	}
+}
@@ -0,0 +1,603 @@
+//! Deferred opening of a store until it's first needed.
+
+use crate::logging;
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	Attachment, BatchPreview, Capability, DataResult, DataStore, DataStoreExt, DropRecord,
+	Equivalent, PendingSize, RemovedBatch, StoreStatus,
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Result, Write};
+use std::time::Duration;
+#[cfg(all(
+	feature = "web",
+	target_arch = "wasm32",
+	not(target_feature = "atomics")
+))]
+use std::{cell::RefCell, future::Future, mem, pin::Pin, rc::Rc};
+
+enum Opener<S> {
+	Sync(Box<dyn FnMut() -> Result<S> + Send>),
+	#[cfg(all(
+		feature = "web",
+		target_arch = "wasm32",
+		not(target_feature = "atomics")
+	))]
+	Async(Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = S>>>>),
+}
+
+enum State<S> {
+	Unopened(Opener<S>),
+	/// An async open is running and will put the store in the slot
+	#[cfg(all(
+		feature = "web",
+		target_arch = "wasm32",
+		not(target_feature = "atomics")
+	))]
+	Opening(Rc<RefCell<Option<S>>>),
+	Open(S),
+}
+
+/// An event appended while the store was being opened
+enum Early {
+	Event(Value),
+	Pinned(Value),
+	For(String, Value),
+	Upsert(String, Value),
+	Delayed(Value, DateTime<Utc>),
+}
+
+fn not_open() -> io::Error {
+	io::Error::new(io::ErrorKind::WouldBlock, "The store isn't open yet")
+}
+
+/// A store that isn't created until it's first needed.
+///
+/// Creating a store can cost a directory scan, file opens or an IndexedDB open, which
+/// sessions that never emit an event don't need to pay for at startup. A LazyStore
+/// holds the code that creates the store and runs it on the first append (or fetch,
+/// [`warm_up`](DataStore::warm_up) and other calls that change the store). If creating
+/// the store fails, the call gets the error and the next call tries again.
+///
+/// Until the store is open, [`has_data`](DataStore::has_data) only knows about events
+/// appended to this LazyStore, so events left from an earlier session aren't reported
+/// until something opens the store. Call `warm_up` to open it, e.g. when the app goes
+/// to the background.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{DirectoryConfig, DirectoryStore, LazyStore, TransientDB};
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().to_owned();
+/// let db = TransientDB::new(LazyStore::new(move || {
+///     DirectoryStore::new(DirectoryConfig {
+///         write_key: "my-key".into(),
+///         storage_location: path.clone(),
+///         base_filename: "events".into(),
+///         max_file_size: 1024 * 1024,
+///     })
+/// }));
+///
+/// // Nothing has been created on disk yet
+/// assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+///
+/// db.append(json!({"event": "purchase"})).unwrap();
+/// assert!(db.has_data());
+/// ```
+pub struct LazyStore<S> {
+	state: State<S>,
+	early: VecDeque<Early>,
+	max_buffered: usize,
+}
+
+impl<S> LazyStore<S> {
+	/// Default for [`set_max_buffered`](Self::set_max_buffered)
+	pub const DEFAULT_MAX_BUFFERED: usize = 1000;
+
+	/// Creates a LazyStore that calls `open` to create the store when it's first needed.
+	pub fn new(open: impl FnMut() -> Result<S> + Send + 'static) -> Self {
+		Self::with_opener(Opener::Sync(Box::new(open)))
+	}
+
+	/// Creates a LazyStore for a store that is opened asynchronously, such as a
+	/// [`WebStore`](crate::WebStore).
+	///
+	/// `open` is called when the store is first needed, and its future runs in the
+	/// background. Events appended until it completes are held in memory (see
+	/// [`set_max_buffered`](Self::set_max_buffered)) and added to the store in order
+	/// once it's open; fetches return nothing in the meantime.
+	///
+	/// Only available with the `web` feature on `wasm32` without the `atomics` target
+	/// feature.
+	///
+	/// # Examples
+	/// ```no_run
+	/// use transientdb::{LazyStore, TransientDB, WebConfig, WebStore};
+	///
+	/// let db = TransientDB::new(LazyStore::new_async(|| {
+	///     WebStore::new(WebConfig {
+	///         write_key: "my-key".into(),
+	///         database_name: "my-events".into(),
+	///         max_items: 1000,
+	///         max_fetch_size: 1024 * 1024,
+	///     })
+	/// }));
+	/// ```
+	#[cfg(all(
+		feature = "web",
+		target_arch = "wasm32",
+		not(target_feature = "atomics")
+	))]
+	pub fn new_async<F, Fut>(open: F) -> Self
+	where
+		F: FnOnce() -> Fut + 'static,
+		Fut: Future<Output = S> + 'static,
+	{
+		Self::with_opener(Opener::Async(Box::new(move || {
+			Box::pin(open()) as Pin<Box<dyn Future<Output = S>>>
+		})))
+	}
+
+	fn with_opener(opener: Opener<S>) -> Self {
+		Self {
+			state: State::Unopened(opener),
+			early: VecDeque::new(),
+			max_buffered: Self::DEFAULT_MAX_BUFFERED,
+		}
+	}
+
+	/// Sets how many events appended while an async open is running are held in
+	/// memory. Past that, the oldest are dropped.
+	///
+	/// Default: [`DEFAULT_MAX_BUFFERED`](Self::DEFAULT_MAX_BUFFERED)
+	pub fn set_max_buffered(&mut self, max_buffered: usize) {
+		self.max_buffered = max_buffered;
+		while self.early.len() > max_buffered {
+			self.early.pop_front();
+		}
+	}
+
+	/// Returns whether the store has been created.
+	pub fn is_open(&self) -> bool {
+		matches!(self.state, State::Open(_))
+	}
+
+	/// Returns the store, if it has been created.
+	pub fn inner(&self) -> Option<&S> {
+		match &self.state {
+			State::Open(store) => Some(store),
+			_ => None,
+		}
+	}
+
+	/// Returns the store mutably, if it has been created.
+	pub fn inner_mut(&mut self) -> Option<&mut S> {
+		match &mut self.state {
+			State::Open(store) => Some(store),
+			_ => None,
+		}
+	}
+
+	fn hold(&mut self, early: Early) {
+		if self.early.len() >= self.max_buffered {
+			if self.early.pop_front().is_none() {
+				return;
+			}
+			logging::log_warn!("The store is still opening; dropped the oldest buffered event");
+		}
+		self.early.push_back(early);
+	}
+
+	fn buffered_events(&self) -> Vec<Value> {
+		self.early
+			.iter()
+			.map(|early| match early {
+				Early::Event(data)
+				| Early::Pinned(data)
+				| Early::For(_, data)
+				| Early::Upsert(_, data)
+				| Early::Delayed(data, _) => data.clone(),
+			})
+			.collect()
+	}
+}
+
+impl<S: DataStoreExt + 'static> LazyStore<S> {
+	/// Opens the store if it isn't yet and adds the events held while it was opening.
+	/// Returns `None` while an async open is still running.
+	fn open(&mut self) -> Result<Option<&mut S>> {
+		let opened = match &mut self.state {
+			State::Unopened(Opener::Sync(open)) => Some(open()?),
+			#[cfg(all(
+				feature = "web",
+				target_arch = "wasm32",
+				not(target_feature = "atomics")
+			))]
+			State::Unopened(Opener::Async(_)) => {
+				let slot = Rc::new(RefCell::new(None));
+				let previous = mem::replace(&mut self.state, State::Opening(Rc::clone(&slot)));
+				if let State::Unopened(Opener::Async(open)) = previous {
+					let future = open();
+					wasm_bindgen_futures::spawn_local(async move {
+						let store = future.await;
+						*slot.borrow_mut() = Some(store);
+					});
+				}
+				None
+			}
+			#[cfg(all(
+				feature = "web",
+				target_arch = "wasm32",
+				not(target_feature = "atomics")
+			))]
+			State::Opening(slot) => slot.borrow_mut().take(),
+			State::Open(_) => None,
+		};
+		if let Some(store) = opened {
+			self.state = State::Open(store);
+		}
+
+		let State::Open(store) = &mut self.state else {
+			return Ok(None);
+		};
+		while let Some(early) = self.early.pop_front() {
+			let result = match &early {
+				Early::Event(data) => store.append(data.clone()),
+				Early::Pinned(data) => store.append_pinned(data.clone()),
+				Early::For(write_key, data) => store.append_for(write_key, data.clone()),
+				Early::Upsert(key, data) => store.upsert(key, data.clone()),
+				Early::Delayed(data, not_before) => store.append_delayed(data.clone(), *not_before),
+			};
+			if let Err(e) = result {
+				self.early.push_front(early);
+				return Err(e);
+			}
+		}
+		Ok(Some(store))
+	}
+
+	fn open_or_err(&mut self) -> Result<&mut S> {
+		self.open()?.ok_or_else(not_open)
+	}
+}
+
+impl<S: DataStoreExt + 'static> DataStore for LazyStore<S> {
+	type Output = S::Output;
+
+	fn has_data(&self) -> bool {
+		match &self.state {
+			State::Open(store) => !self.early.is_empty() || store.has_data(),
+			_ => !self.early.is_empty(),
+		}
+	}
+
+	fn reset(&mut self) {
+		match self.open() {
+			Ok(Some(store)) => store.reset(),
+			Ok(None) => {}
+			Err(e) => logging::log_warn!("Failed to open the store to reset it: {}", e),
+		}
+		self.early.clear();
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.append(data),
+			None => {
+				self.hold(Early::Event(data));
+				Ok(())
+			}
+		}
+	}
+
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.append_many(items),
+			None => {
+				for data in items {
+					self.hold(Early::Event(data));
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.flush(),
+			None => Ok(()),
+		}
+	}
+
+	fn warm_up(&mut self) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.warm_up(),
+			None => Ok(()),
+		}
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		match self.open()? {
+			Some(store) => store.fetch(count, max_bytes),
+			None => Ok(None),
+		}
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.remove(data),
+			// Nothing can have been fetched from a store that isn't open
+			None => Ok(()),
+		}
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		self.inner().is_some_and(|store| store.supports(capability))
+	}
+
+	fn debug_config(&self) -> Value {
+		match self.inner() {
+			Some(store) => store.debug_config(),
+			None => json!({
+				"open": false,
+				"bufferedEvents": self.early.len(),
+				"maxBuffered": self.max_buffered,
+			}),
+		}
+	}
+}
+
+impl<S: DataStoreExt + 'static> DataStoreExt for LazyStore<S> {
+	fn append_with_attachments(&mut self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		self.open_or_err()?
+			.append_with_attachments(data, attachments)
+	}
+
+	fn append_pinned(&mut self, data: Value) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.append_pinned(data),
+			None => {
+				self.hold(Early::Pinned(data));
+				Ok(())
+			}
+		}
+	}
+
+	fn append_for(&mut self, write_key: &str, data: Value) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.append_for(write_key, data),
+			None => {
+				self.hold(Early::For(write_key.to_string(), data));
+				Ok(())
+			}
+		}
+	}
+
+	fn append_delayed(&mut self, data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.append_delayed(data, not_before),
+			None => {
+				self.hold(Early::Delayed(data, not_before));
+				Ok(())
+			}
+		}
+	}
+
+	fn upsert(&mut self, key: &str, data: Value) -> Result<()> {
+		match self.open()? {
+			Some(store) => store.upsert(key, data),
+			None => {
+				// Only the latest state needs holding
+				self.early
+					.retain(|early| !matches!(early, Early::Upsert(held, _) if held == key));
+				self.hold(Early::Upsert(key.to_string(), data));
+				Ok(())
+			}
+		}
+	}
+
+	fn reserve(&mut self, estimated_bytes: usize) -> Result<()> {
+		self.open_or_err()?.reserve(estimated_bytes)
+	}
+
+	fn commit_reserved(&mut self, data: Value, estimated_bytes: usize) -> Result<()> {
+		self.open_or_err()?.commit_reserved(data, estimated_bytes)
+	}
+
+	fn release_reserved(&mut self, estimated_bytes: usize) {
+		if let State::Open(store) = &mut self.state {
+			store.release_reserved(estimated_bytes);
+		}
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		self.open_or_err()?.mark_delivered(batch_id)
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.inner()
+			.is_some_and(|store| store.is_delivered(batch_id))
+	}
+
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.open_or_err()?.set_allowed_categories(categories)
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		self.open_or_err()?.purge_revoked()
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		self.open_or_err()?.anonymize(anonymizer)
+	}
+
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		self.open_or_err()?.drop_report()
+	}
+
+	fn begin_move(&mut self, move_id: &str, count: usize) -> Result<Vec<Value>> {
+		self.open_or_err()?.begin_move(move_id, count)
+	}
+
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		self.open_or_err()?.stage_move(move_id, items)
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		self.open_or_err()?.release_move(move_id)
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		self.open_or_err()?.discard_move(move_id)
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		self.inner().ok_or_else(not_open)?.held_moves()
+	}
+
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		self.inner().ok_or_else(not_open)?.recently_removed()
+	}
+
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		self.open_or_err()?.replay(batch_id)
+	}
+
+	fn write_batch_to(
+		&mut self,
+		writer: &mut dyn Write,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		self.open_or_err()?.write_batch_to(writer, count, max_bytes)
+	}
+
+	fn fetch_older_than(&mut self, age: Duration) -> Result<Option<DataResult<S::Output>>> {
+		self.open_or_err()?.fetch_older_than(age)
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		match self.inner() {
+			Some(store) => store.status(),
+			None => Ok(StoreStatus::Opening),
+		}
+	}
+
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.open_or_err()?.expire_older_than(age)
+	}
+
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		self.inner()
+			.ok_or_else(not_open)?
+			.preview_fetch(count, max_bytes)
+	}
+
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		self.inner().ok_or_else(not_open)?.enqueue_times()
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		self.inner().ok_or_else(not_open)?.pending_size()
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		let mut events = match self.inner() {
+			Some(store) => store.pending_events()?,
+			None => Vec::new(),
+		};
+		events.extend(self.buffered_events());
+		Ok(events)
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.inner()?.oldest_enqueue_time()
+	}
+
+	fn event_removables(
+		&self,
+		removable: Vec<Box<dyn Equivalent>>,
+	) -> Result<Vec<Box<dyn Equivalent>>> {
+		match self.inner() {
+			Some(store) => store.event_removables(removable),
+			None => Ok(removable),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{MemoryConfig, MemoryStore};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	fn memory_store() -> MemoryStore {
+		MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		})
+	}
+
+	#[test]
+	fn test_opens_on_first_append() -> Result<()> {
+		let opens = Arc::new(AtomicUsize::new(0));
+		let counter = opens.clone();
+		let mut store = LazyStore::new(move || {
+			counter.fetch_add(1, Ordering::SeqCst);
+			Ok(memory_store())
+		});
+		assert!(!store.has_data());
+		assert!(!store.is_open());
+		assert_eq!(
+			store.pending_size().unwrap_err().kind(),
+			io::ErrorKind::WouldBlock
+		);
+		assert_eq!(opens.load(Ordering::SeqCst), 0);
+
+		store.append(json!({"index": 0}))?;
+		store.append(json!({"index": 1}))?;
+		assert!(store.is_open());
+		assert_eq!(opens.load(Ordering::SeqCst), 1);
+		assert_eq!(store.pending_events()?.len(), 2);
+
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"][1]["index"], 1);
+		Ok(())
+	}
+
+	#[test]
+	fn test_open_failure_is_retried() -> Result<()> {
+		let attempts = Arc::new(AtomicUsize::new(0));
+		let counter = attempts.clone();
+		let mut store = LazyStore::new(move || {
+			if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+				return Err(io::Error::other("disk not ready"));
+			}
+			Ok(memory_store())
+		});
+
+		assert!(store.append(json!({"index": 0})).is_err());
+		assert!(!store.is_open());
+		store.warm_up()?;
+		assert!(store.is_open());
+		assert!(!store.has_data());
+		assert_eq!(attempts.load(Ordering::SeqCst), 2);
+		Ok(())
+	}
+}
@@ -1,28 +1,210 @@
+// Panics inside a store abort the whole WASM instance, taking the host app with it,
+// so library code reports failures as errors instead. Panics are reserved for invalid
+// configuration passed to constructors, which is documented on each.
+#![cfg_attr(
+	not(test),
+	deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)
+)]
+
+mod age;
+#[cfg(feature = "anonymize")]
+mod anonymize;
+mod attachment;
+mod capability;
+mod chunker;
+mod consent;
+mod context;
+mod debug;
+#[cfg(all(feature = "debug-server", not(target_arch = "wasm32")))]
+mod debug_server;
+mod delay;
+mod delivery;
+#[cfg(all(feature = "devtools", target_arch = "wasm32"))]
+mod devtools;
 mod directory;
+mod drops;
+mod envelope;
+mod error;
+mod eviction;
+mod field_filter;
+mod format;
+mod fs;
+mod hash;
+mod import;
+mod lazy;
+mod live_drain;
+mod logging;
 mod memory;
+mod moves;
+mod platform;
+mod pointer;
+#[cfg(all(feature = "prometheus", not(target_arch = "wasm32")))]
+mod prometheus;
+#[cfg(any(test, all(feature = "web", target_arch = "wasm32")))]
+mod quota;
+mod received;
+mod removed;
+mod replay;
+mod routing;
+mod sampling;
+#[cfg(test)]
+mod scenarios;
+mod schema;
+mod segment;
+#[cfg(all(feature = "web", target_arch = "wasm32", target_feature = "atomics"))]
+mod shared_web;
+mod signing;
+mod sim;
+mod sink;
+mod sized;
+mod skew;
+#[cfg(any(test, all(feature = "web", target_arch = "wasm32")))]
+mod snapshot;
+mod stats;
+mod status;
+#[cfg(feature = "subscribe")]
+mod subscribe;
 mod transient;
+mod upsert;
+#[cfg(feature = "vfs")]
+mod virtual_fs;
 
 #[cfg(all(feature = "web", target_arch = "wasm32"))]
 mod web;
+#[cfg(all(feature = "websocket", target_arch = "wasm32"))]
+mod websocket;
 
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 use std::any::Any;
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::io::Result;
+use std::io::{self, Result, Write};
+use std::time::Duration;
 
-pub use directory::{DirectoryConfig, DirectoryStore};
+pub use age::AgeHistogram;
+#[cfg(feature = "anonymize")]
+pub use anonymize::Anonymizer;
+pub use attachment::{Attachment, AttachmentContent, AttachmentHandle};
+pub use capability::Capability;
+pub use chunker::{BatchChunk, BatchChunker, ChunkedBatch};
+pub use context::ContextProvider;
+#[cfg(all(feature = "debug-server", not(target_arch = "wasm32")))]
+pub use debug_server::DebugServer;
+#[cfg(all(feature = "devtools", target_arch = "wasm32"))]
+pub use devtools::install_devtools;
+pub use directory::{
+	BatchInfo, DirectoryConfig, DirectoryContentStore, DirectoryStore, DiskFullPolicy, FetchOrder,
+	FileItem, FlushPolicy, OpenMode, QuiesceGuard,
+};
+pub use drops::{DropReason, DropRecord, Evicted, EvictionListener};
+pub use error::TransientError;
+pub use eviction::{
+	CostEviction, EvictionCandidate, EvictionPolicy, FifoEviction, PriorityEviction,
+};
+pub use field_filter::FieldFilter;
+pub use format::JsonFormat;
+pub use fs::{Fs, FsEntry, FsMetadata, FsWriter, MappedFile, StdFs, SystemClock, TimeSource};
+#[cfg(feature = "sha256")]
+pub use hash::Sha256Hasher;
+pub use hash::{Hasher, XxHash64Hasher};
+pub use import::{ImportError, ImportReport};
+pub use lazy::LazyStore;
+pub use live_drain::{LiveDrain, LiveMessage};
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub use logging::ConsoleLogger;
+#[cfg(target_arch = "wasm32")]
+pub use logging::{set_logger, Logger};
 pub use memory::{MemoryConfig, MemoryStore};
-pub use transient::TransientDB;
+pub use moves::{move_events, recover_moves};
+pub use pointer::JsonPointer;
+#[cfg(all(feature = "prometheus", not(target_arch = "wasm32")))]
+pub use prometheus::PrometheusExporter;
+pub use removed::RemovedBatch;
+pub use sampling::DownSampling;
+pub use schema::SchemaMigrations;
+#[cfg(all(feature = "web", target_arch = "wasm32", target_feature = "atomics"))]
+pub use shared_web::SharedWebStore;
+#[cfg(feature = "signing")]
+pub use signing::HmacSha256Signer;
+pub use signing::{BatchSigner, SignedStore};
+pub use sim::{SimClock, SimFs};
+pub use sink::{DeliveryResult, DrainPolicy, DrainSummary, Sink};
+pub use skew::{ClockOffset, ClockOffsetProvider};
+pub use stats::{BatchPreview, PendingSize, QueueStats, TypeStats};
+pub use status::StoreStatus;
+pub use transient::{HeldStore, Inline, LocalTransientDB, RejectedEvents, Slot, TransientDB};
+#[cfg(feature = "vfs")]
+pub use virtual_fs::VfsFs;
 
 #[cfg(all(feature = "web", target_arch = "wasm32"))]
-pub use web::{PersistenceState, WebConfig, WebStore};
+pub use web::{
+	IdbError, IdbOperation, PersistenceFailure, PersistenceFailureKind, PersistenceState,
+	WebConfig, WebStore,
+};
+#[cfg(all(feature = "websocket", target_arch = "wasm32"))]
+pub use websocket::WebSocketDrain;
 
 /// Represents the result of a data fetch operation.
 /// Contains either raw data bytes or paths to data files, along with items that can be removed.
+///
+/// Fields may be added in minor releases, so stores outside this crate build it with
+/// [`new`](Self::new) and the `with_*` methods.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct DataResult<T> {
 	pub data: Option<T>,
 	pub removable: Option<Vec<Box<dyn Equivalent>>>,
+	/// Unique identifier (UUID) for this fetch result.
+	/// Send it to the server as an idempotency key, and pass it to `mark_delivered()`
+	/// once the server has accepted the batch.
+	pub batch_id: Option<String>,
+	/// Attachments referenced by the fetched events, if any.
+	pub attachments: Option<Vec<AttachmentHandle>>,
+	/// Number of fetchable items left after this batch, so an uploader can decide
+	/// whether to fetch again once it's removed. Counted like
+	/// [`PendingSize::items`]: events for MemoryStore and WebStore, data files for
+	/// DirectoryStore.
+	pub remaining_items: usize,
+	/// Size in bytes of the items counted by `remaining_items`.
+	pub remaining_bytes: u64,
+}
+
+impl<T> DataResult<T> {
+	/// Creates a fetch result with no batch id, attachments or remaining items.
+	///
+	/// # Arguments
+	/// * `data` - The fetched data
+	/// * `removable` - Items to pass to `remove()` once the data is delivered
+	pub fn new(data: Option<T>, removable: Option<Vec<Box<dyn Equivalent>>>) -> Self {
+		Self {
+			data,
+			removable,
+			batch_id: None,
+			attachments: None,
+			remaining_items: 0,
+			remaining_bytes: 0,
+		}
+	}
+
+	/// Sets the id that identifies this batch to the server and to `mark_delivered()`.
+	pub fn with_batch_id(mut self, batch_id: impl Into<String>) -> Self {
+		self.batch_id = Some(batch_id.into());
+		self
+	}
+
+	/// Sets the attachments referenced by the fetched events.
+	pub fn with_attachments(mut self, attachments: Vec<AttachmentHandle>) -> Self {
+		self.attachments = Some(attachments);
+		self
+	}
+
+	/// Sets how many fetchable items, of how many bytes, are left after this batch.
+	pub fn with_remaining(mut self, items: usize, bytes: u64) -> Self {
+		self.remaining_items = items;
+		self.remaining_bytes = bytes;
+		self
+	}
 }
 
 /// Trait for types that can be compared for equality and downcasted.
@@ -38,6 +220,24 @@ pub trait Equivalent: Any + Debug {
 /// A trait for implementing persistent data stores that support batched operations.
 /// Provides a common interface for storing, retrieving, and managing data with support
 /// for size limits and batch processing.
+///
+/// # Stability
+/// Third-party stores can rely on this contract across minor releases:
+/// * This trait holds only the required methods (`has_data`, `reset`, `append`,
+///   `fetch`, `remove`) and a few defaults every store can provide. It and the
+///   signatures of existing methods only change in a major release.
+/// * Optional capabilities live in [`DataStoreExt`]. New ones arrive there as methods
+///   with a default implementation, failing with `ErrorKind::Unsupported` where they
+///   can't be emulated, and a new [`Capability`] variant, so existing implementations
+///   keep compiling and report them unsupported.
+/// * Types a store builds, returns or matches on, like [`DataResult`], [`Capability`]
+///   and [`DropReason`], are `#[non_exhaustive]`. Build a `DataResult` with
+///   [`DataResult::new`].
+/// * Traits stores implement, like this one and [`DataStoreExt`], are open. Traits only
+///   this crate implements, like [`HeldStore`], are sealed, so they can change in minor
+///   releases without breaking anyone.
+///
+/// CI checks the public API against the last release with `cargo semver-checks`.
 pub trait DataStore {
 	/// The type of data returned by fetch operations.
 	type Output;
@@ -54,6 +254,37 @@ pub trait DataStore {
 	/// * `data` - JSON value to store
 	fn append(&mut self, data: Value) -> Result<()>;
 
+	/// Appends several items at once, oldest first.
+	///
+	/// Stores override this to evict and write once for the whole group rather than per
+	/// item. If it fails, items before the failing one may have been appended.
+	///
+	/// The default implementation appends the items one by one.
+	///
+	/// # Arguments
+	/// * `items` - JSON values to store
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		for data in items {
+			self.append(data)?;
+		}
+		Ok(())
+	}
+
+	/// Writes out any appended items the store is still holding in memory.
+	///
+	/// The default implementation does nothing, for stores that don't buffer appends.
+	fn flush(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	/// Does the setup the first append would otherwise pay for, such as opening files
+	/// and allocating buffers, so it can be done at a convenient time like app launch.
+	///
+	/// The default implementation does nothing, for stores with no such setup.
+	fn warm_up(&mut self) -> Result<()> {
+		Ok(())
+	}
+
 	/// Fetches a batch of data from the store, respecting optional count and size limits.
 	///
 	/// # Arguments
@@ -69,7 +300,469 @@ pub trait DataStore {
 
 	/// Removes previously fetched data from the store.
 	///
+	/// Only the items passed are removed; `data` may be any subset of a fetch's removables.
+	/// Everything else from the same fetch stays queued and is returned again by the next
+	/// fetch, so an uploader can drop individual records the server rejected and retry the
+	/// rest. Each removable removes at most one item, even if other queued items are equal.
+	///
 	/// # Arguments
 	/// * `data` - Slice of removable items from a previous fetch operation
 	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()>;
+
+	/// Describes the store's configuration, for diagnostics such as
+	/// `TransientDB::debug_dump`.
+	///
+	/// The default implementation returns an empty object.
+	fn debug_config(&self) -> Value {
+		Value::Object(Default::default())
+	}
+
+	/// Whether the store implements an optional capability, rather than failing its
+	/// methods with `Unsupported`.
+	///
+	/// The default implementation supports none; stores implementing optional methods
+	/// should override it to match.
+	fn supports(&self, _capability: Capability) -> bool {
+		false
+	}
+}
+
+/// Optional capabilities of a [`DataStore`], each with a default implementation.
+///
+/// [`TransientDB`] needs its store to implement this trait too. A store without any
+/// optional capability implements it with an empty block, and gets the defaults, which
+/// fail with `ErrorKind::Unsupported` or do nothing.
+///
+/// Capabilities are added here in minor releases, never to [`DataStore`]. A store that
+/// overrides any of these methods should report it from
+/// [`supports`](DataStore::supports).
+///
+/// # Examples
+/// ```
+/// use std::io::Result;
+/// use serde_json::{json, Value};
+/// use transientdb::{DataResult, DataStore, DataStoreExt, Equivalent, TransientDB};
+///
+/// #[derive(Default)]
+/// struct LatestOnly(Option<Value>);
+///
+/// impl DataStore for LatestOnly {
+///     type Output = Value;
+///
+///     fn has_data(&self) -> bool {
+///         self.0.is_some()
+///     }
+///
+///     fn reset(&mut self) {
+///         self.0 = None;
+///     }
+///
+///     fn append(&mut self, data: Value) -> Result<()> {
+///         self.0 = Some(data);
+///         Ok(())
+///     }
+///
+///     fn fetch(&mut self, _: Option<usize>, _: Option<usize>) -> Result<Option<DataResult<Value>>> {
+///         Ok(self.0.take().map(|data| DataResult::new(Some(json!({"batch": [data]})), None)))
+///     }
+///
+///     fn remove(&mut self, _data: &[Box<dyn Equivalent>]) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl DataStoreExt for LatestOnly {}
+///
+/// let db = TransientDB::new(LatestOnly::default());
+/// db.append(json!({"screen": "home"}))?;
+/// db.append(json!({"screen": "settings"}))?;
+/// let batch = db.fetch(None, None)?.unwrap();
+/// assert_eq!(batch.data.unwrap()["batch"][0]["screen"], "settings");
+/// assert!(db.append_pinned(json!({"screen": "home"})).is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait DataStoreExt: DataStore {
+	/// Appends a new item together with binary attachments.
+	///
+	/// The item must be a JSON object; an `_attachments` array describing each attachment
+	/// (`id`, `name`, `contentType`, `size`) is added to it. The attachments are returned with
+	/// any fetch that includes the item and are removed along with it.
+	///
+	/// The default implementation only accepts an empty list of attachments.
+	///
+	/// # Arguments
+	/// * `data` - JSON object to store
+	/// * `attachments` - Binary payloads belonging to the item
+	fn append_with_attachments(&mut self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		if attachments.is_empty() {
+			return self.append(data);
+		}
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support attachments",
+		))
+	}
+
+	/// Appends an item that is never evicted to make room for others, for records that
+	/// mustn't be lost, like purchase receipts queued offline.
+	///
+	/// The item must be a JSON object; a `_pinned: true` field is added to it. It stays
+	/// queued until fetched and removed. Stores limit how many pinned items they hold
+	/// and fail with [`TransientError::TooManyPinned`] beyond that.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn append_pinned(&mut self, _data: Value) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support pinned items",
+		))
+	}
+
+	/// Appends an item to be sent with another write key than the store's, for clients
+	/// sending events to several workspaces.
+	///
+	/// The item must be a JSON object; a `_writeKey` field is added to it. Fetches then
+	/// return items of one write key at a time, with that key as the envelope's
+	/// `writeKey`.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn append_for(&mut self, _write_key: &str, _data: Value) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support other write keys",
+		))
+	}
+
+	/// Appends an item that isn't fetched before `not_before`, e.g. a retry scheduled
+	/// for later or an event to send once the session has ended.
+	///
+	/// The item must be a JSON object; a `_notBefore` field with the time is added to
+	/// it, so the time is persisted with the item. Until then the item stays queued but
+	/// fetches skip it.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn append_delayed(&mut self, _data: Value, _not_before: DateTime<Utc>) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support delayed items",
+		))
+	}
+
+	/// Appends an item that replaces the queued item upserted with the same `key`, for
+	/// state like the latest user traits, where intermediate states needn't be sent.
+	///
+	/// The previous item is removed and the new one queued after the others. The item
+	/// must be a JSON object; an `_upsertKey` field is added to it. Items appended
+	/// without a key are never replaced.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn upsert(&mut self, _key: &str, _data: Value) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support replacing items",
+		))
+	}
+
+	/// Makes room for an item of about `estimated_bytes` to be appended later with
+	/// [`commit_reserved`](Self::commit_reserved).
+	///
+	/// Does whatever an append may have to do up front (evicting old items, rotating to a
+	/// new file, checking free space), so the commit doesn't. Each reservation must be
+	/// followed by exactly one `commit_reserved` or
+	/// [`release_reserved`](Self::release_reserved) with the same estimate.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn reserve(&mut self, _estimated_bytes: usize) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support reservations",
+		))
+	}
+
+	/// Appends an item into room made by [`reserve`](Self::reserve), without evicting
+	/// items or rotating files, even if the item turns out larger than estimated.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn commit_reserved(&mut self, _data: Value, _estimated_bytes: usize) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support reservations",
+		))
+	}
+
+	/// Gives back room made by [`reserve`](Self::reserve) that won't be committed.
+	///
+	/// The default implementation does nothing.
+	fn release_reserved(&mut self, _estimated_bytes: usize) {}
+
+	/// Records that the batch with the given id was delivered.
+	///
+	/// Stores remember a bounded number of delivered batch ids and ignore appends of
+	/// envelopes whose `batchId` has already been delivered.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn mark_delivered(&mut self, _batch_id: &str) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not track delivered batches",
+		))
+	}
+
+	/// Checks whether the batch with the given id was previously marked as delivered.
+	fn is_delivered(&self, _batch_id: &str) -> bool {
+		false
+	}
+
+	/// Returns when each pending item was enqueued, oldest first.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not track enqueue times",
+		))
+	}
+
+	/// Returns how much data is pending in the store.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn pending_size(&self) -> Result<PendingSize> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not report its size",
+		))
+	}
+
+	/// Returns what [`fetch`](DataStore::fetch) with the same limits would return, without
+	/// building the batch or changing anything, so a flush scheduler can decide cheaply
+	/// whether a fetch is worth it.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn preview_fetch(
+		&self,
+		_count: Option<usize>,
+		_max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support previewing fetches",
+		))
+	}
+
+	/// Returns all pending events, oldest first, without removing them.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support listing events",
+		))
+	}
+
+	/// Returns when the oldest pending item was enqueued, or `None` if the store is empty
+	/// or doesn't track enqueue times.
+	///
+	/// The default implementation uses [`enqueue_times`](Self::enqueue_times); stores can
+	/// override it with something cheaper.
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.enqueue_times().ok()?.into_iter().min()
+	}
+
+	/// Limits fetches to events in the given consent categories.
+	///
+	/// Events appended without a category are always fetched. Events in other categories
+	/// stay queued, and are fetched again if their category is allowed later; delete them
+	/// with [`purge_revoked`](Self::purge_revoked). `None` allows every category, which is
+	/// the default.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn set_allowed_categories(&mut self, _categories: Option<HashSet<String>>) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support consent categories",
+		))
+	}
+
+	/// Deletes pending events whose consent category isn't allowed, returning how many
+	/// were deleted.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn purge_revoked(&mut self) -> Result<usize> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support consent categories",
+		))
+	}
+
+	/// Rewrites the anonymizer's fields in every pending event to salted hashes, returning
+	/// how many events were changed.
+	///
+	/// Events already fetched but not yet removed are anonymized too; removables from a
+	/// fetch made before anonymizing may no longer match them, so fetch again afterwards.
+	///
+	/// Requires the `anonymize` feature. The default implementation returns an
+	/// `Unsupported` error.
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, _anonymizer: &Anonymizer) -> Result<usize> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support anonymizing events",
+		))
+	}
+
+	/// Returns records of the events the store dropped without delivering them, such as
+	/// evictions to stay within its limits, and clears them.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not record dropped events",
+		))
+	}
+
+	/// Hides up to `count` of the oldest fetchable events under `move_id` and returns
+	/// copies of them, as the source of [`move_events`].
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn begin_move(&mut self, _move_id: &str, _count: usize) -> Result<Vec<Value>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support moving events out",
+		))
+	}
+
+	/// Queues events hidden under `move_id`, as the destination of [`move_events`].
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn stage_move(&mut self, _move_id: &str, _items: Vec<Value>) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support moving events in",
+		))
+	}
+
+	/// Makes the events held under `move_id` fetchable again.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn release_move(&mut self, _move_id: &str) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support moving events",
+		))
+	}
+
+	/// Deletes the events held under `move_id`.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn discard_move(&mut self, _move_id: &str) -> Result<()> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not support moving events",
+		))
+	}
+
+	/// Lists the ids of moves the store holds events for, see [`recover_moves`].
+	///
+	/// The default implementation returns an empty list, since such a store can't
+	/// hold events.
+	fn held_moves(&self) -> Result<Vec<String>> {
+		Ok(Vec::new())
+	}
+
+	/// Returns the batches most recently removed, oldest first, if the store was told
+	/// to retain them.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not retain removed batches",
+		))
+	}
+
+	/// Queues the events of a retained removed batch again, returning how many, for
+	/// when the server accepted the batch but lost it.
+	///
+	/// Replayed events are tagged with the original batch id in a `_replayOf` field,
+	/// and fetched in envelopes flagged with `"replay": true`.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn replay(&mut self, _batch_id: &str) -> Result<usize> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not replay removed batches",
+		))
+	}
+
+	/// Like [`fetch`](DataStore::fetch), but streams the batch envelope as JSON into
+	/// `writer` instead of building it, for batches too large to hold in memory twice.
+	///
+	/// The result's `data` is the number of bytes written. Output is buffered, so the
+	/// writer can be a file, socket or compressor as is.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn write_batch_to(
+		&mut self,
+		_writer: &mut dyn Write,
+		_count: Option<usize>,
+		_max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not stream batches",
+		))
+	}
+
+	/// Fetches a batch of the items enqueued at least `age` ago, or None if there are
+	/// none, so flush policies like "send anything older than 30 seconds" don't have to
+	/// wait for a full batch or read event payloads.
+	///
+	/// Items are taken from the front of the queue, up to the store's `max_fetch_size`.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn fetch_older_than(&mut self, _age: Duration) -> Result<Option<DataResult<Self::Output>>> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not track enqueue times",
+		))
+	}
+
+	/// Whether the store's events survive a restart, or why not, so apps can adjust,
+	/// e.g. flush more often while a WebStore is memory-only.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn status(&self) -> Result<StoreStatus> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not report its status",
+		))
+	}
+
+	/// Drops the items enqueued at least `age` ago, returning how many, for maintenance
+	/// jobs that keep stale events from ever being sent.
+	///
+	/// Dropped items are reported by [`drop_report`](DataStoreExt::drop_report) as
+	/// [`DropReason::Expired`] and passed to the eviction listener, if any. Pinned items
+	/// and items held by a move are kept.
+	///
+	/// The default implementation returns an `Unsupported` error.
+	fn expire_older_than(&mut self, _age: Duration) -> Result<usize> {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"This store does not expire items",
+		))
+	}
+
+	/// Splits the removables of a fetch into one per event, in batch order, so single
+	/// events of a batch can be removed by their index in it.
+	///
+	/// The default implementation returns them as they are, for stores whose fetches
+	/// return one removable per event. Stores returning one per file or batch override it.
+	fn event_removables(
+		&self,
+		removable: Vec<Box<dyn Equivalent>>,
+	) -> Result<Vec<Box<dyn Equivalent>>> {
+		Ok(removable)
+	}
 }
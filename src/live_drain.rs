@@ -0,0 +1,207 @@
+//! Draining a queue over a message channel, such as a WebSocket, as events arrive.
+//!
+//! Unlike [`TransientDB::drain_into`], which delivers batches in a loop and waits for
+//! each result, a live drain is driven by the channel: it hands out one micro-batch at
+//! a time, and the next once the receiver acks the last, so events flow with the
+//! latency of one round trip rather than of a flush interval.
+
+use crate::{Equivalent, TransientDB};
+use serde_json::Value;
+use std::io::{self, Result};
+use std::ops::Deref;
+
+/// A micro-batch to send over the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveMessage {
+	/// The batch id the receiver acks the message with.
+	pub batch_id: String,
+	/// The batch envelope as JSON text.
+	pub text: String,
+}
+
+struct InFlight {
+	message: LiveMessage,
+	removable: Vec<Box<dyn Equivalent>>,
+}
+
+/// Streams a queue's events over a message channel in micro-batches, removing each
+/// once the receiver acks it.
+///
+/// Each message is a batch envelope of up to `batch_count` events (1 to send events
+/// individually), and its receiver acks it by replying `{"ack": "<batchId>"}`. Only
+/// one message is unacked at a time, so events are delivered in order, and one lost
+/// on the way is sent again with [`pending`](Self::pending) rather than skipped.
+///
+/// The drain doesn't own the channel, so it works with any WebSocket library: send
+/// what [`next_message`](Self::next_message) returns, pass replies to
+/// [`handle_reply`](Self::handle_reply), and call `next_message` again after appending.
+/// With the `websocket` feature, `WebSocketDrain` does this
+/// for a browser WebSocket.
+///
+/// `db` is shared with the drain: a reference, `Rc` or `Arc`.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{LiveDrain, MemoryConfig, MemoryStore, TransientDB};
+///
+/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// }));
+/// let mut drain = LiveDrain::new(&db, 1);
+///
+/// db.append(json!({"event": "click"}))?;
+/// db.append(json!({"event": "scroll"}))?;
+///
+/// let first = drain.next_message()?.unwrap();
+/// // Nothing more is sent until the receiver acks the first message
+/// assert!(drain.next_message()?.is_none());
+///
+/// let reply = format!(r#"{{"ack": "{}"}}"#, first.batch_id);
+/// let second = drain.handle_reply(&reply)?.unwrap();
+/// assert!(second.text.contains("scroll"));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct LiveDrain<P> {
+	db: P,
+	batch_count: usize,
+	in_flight: Option<InFlight>,
+}
+
+impl<P> LiveDrain<P>
+where
+	P: Deref<Target = TransientDB<Value>>,
+{
+	/// Creates a drain sending up to `batch_count` events per message (at least 1).
+	pub fn new(db: P, batch_count: usize) -> Self {
+		Self {
+			db,
+			batch_count: batch_count.max(1),
+			in_flight: None,
+		}
+	}
+
+	/// Fetches the next message to send, or None if a message is awaiting its ack or
+	/// nothing is queued.
+	pub fn next_message(&mut self) -> Result<Option<LiveMessage>> {
+		if self.in_flight.is_some() {
+			return Ok(None);
+		}
+		let Some(batch) = self.db.fetch(Some(self.batch_count), None)? else {
+			return Ok(None);
+		};
+		let (Some(data), Some(batch_id), Some(removable)) =
+			(batch.data, batch.batch_id, batch.removable)
+		else {
+			return Ok(None);
+		};
+		let message = LiveMessage {
+			batch_id,
+			text: data.to_string(),
+		};
+		self.in_flight = Some(InFlight {
+			message: message.clone(),
+			removable,
+		});
+		Ok(Some(message))
+	}
+
+	/// The message awaiting its ack, to send again after reconnecting.
+	pub fn pending(&self) -> Option<&LiveMessage> {
+		self.in_flight.as_ref().map(|in_flight| &in_flight.message)
+	}
+
+	/// Removes the message with this batch id from the queue, once its receiver acked
+	/// it. Returns false if it isn't the message awaiting its ack, e.g. a duplicate ack.
+	pub fn ack(&mut self, batch_id: &str) -> Result<bool> {
+		let Some(in_flight) = self
+			.in_flight
+			.take_if(|in_flight| in_flight.message.batch_id == batch_id)
+		else {
+			return Ok(false);
+		};
+		// Record the delivery before removing, as drain_into does
+		match self.db.mark_delivered(batch_id) {
+			Err(e) if e.kind() != io::ErrorKind::Unsupported => {
+				self.in_flight = Some(in_flight);
+				return Err(e);
+			}
+			_ => {}
+		}
+		if let Err(e) = self.db.remove(&in_flight.removable) {
+			self.in_flight = Some(in_flight);
+			return Err(e);
+		}
+		Ok(true)
+	}
+
+	/// Handles a reply from the receiver, returning the next message to send if it acked
+	/// the pending one. Replies other than acks are ignored.
+	pub fn handle_reply(&mut self, text: &str) -> Result<Option<LiveMessage>> {
+		let Some(batch_id) = parse_ack(text) else {
+			return Ok(None);
+		};
+		if !self.ack(&batch_id)? {
+			return Ok(None);
+		}
+		self.next_message()
+	}
+}
+
+/// The batch id of an ack reply, `{"ack": "<batchId>"}`.
+fn parse_ack(text: &str) -> Option<String> {
+	match serde_json::from_str::<Value>(text)
+		.ok()?
+		.get_mut("ack")?
+		.take()
+	{
+		Value::String(batch_id) => Some(batch_id),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{MemoryConfig, MemoryStore};
+	use serde_json::json;
+
+	#[test]
+	fn test_micro_batches_are_removed_on_ack() -> Result<()> {
+		let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test".into(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		}));
+		for i in 0..5 {
+			db.append(json!({"n": i}))?;
+		}
+		let mut drain = LiveDrain::new(&db, 2);
+
+		let first = drain.next_message()?.unwrap();
+		let envelope: Value = serde_json::from_str(&first.text).unwrap();
+		assert_eq!(envelope["batch"], json!([{"n": 0}, {"n": 1}]));
+		assert_eq!(envelope["batchId"], first.batch_id.as_str());
+		assert!(drain.next_message()?.is_none());
+		assert_eq!(drain.pending(), Some(&first));
+
+		// Other replies and stale acks leave the message pending
+		assert!(drain.handle_reply("not json")?.is_none());
+		assert!(drain.handle_reply(r#"{"ack": "other"}"#)?.is_none());
+		assert_eq!(drain.pending(), Some(&first));
+
+		let reply = json!({"ack": first.batch_id}).to_string();
+		let second = drain.handle_reply(&reply)?.unwrap();
+		assert!(db.is_delivered(&first.batch_id));
+		assert!(!drain.ack(&first.batch_id)?);
+		assert!(drain.ack(&second.batch_id)?);
+
+		let last = drain.next_message()?.unwrap();
+		assert!(drain.ack(&last.batch_id)?);
+		assert!(drain.next_message()?.is_none());
+		assert!(!db.has_data());
+		Ok(())
+	}
+}
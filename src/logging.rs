@@ -0,0 +1,205 @@
+//! Internal diagnostics.
+//!
+//! Warnings and recovery notices are emitted through the [`log`] crate on native
+//! targets, under the `transientdb` target, so host apps control verbosity and
+//! destinations with whatever logger they install. On WASM they go to a [`Logger`]
+//! set with [`set_logger`], which defaults to the browser console when the `web`
+//! feature is enabled.
+
+use log::Level;
+#[cfg(not(all(feature = "small-wasm", target_arch = "wasm32")))]
+use std::fmt::Arguments;
+
+/// Log target used for all messages from this crate.
+#[cfg(not(target_arch = "wasm32"))]
+const TARGET: &str = "transientdb";
+
+/// Receives the crate's diagnostics on WASM targets.
+///
+/// Implemented for closures taking a level and a message.
+///
+/// # Examples
+/// ```no_run
+/// # #[cfg(target_arch = "wasm32")]
+/// # {
+/// use transientdb::set_logger;
+///
+/// // Only report errors, e.g. to an app's own telemetry
+/// set_logger(Some(Box::new(|level: log::Level, message: &str| {
+///     if level == log::Level::Error {
+///         report_error(message);
+///     }
+/// })));
+/// # fn report_error(_: &str) {}
+/// # }
+/// ```
+#[cfg(target_arch = "wasm32")]
+pub trait Logger {
+	/// Handles one message.
+	fn log(&self, level: Level, message: &str);
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<F: Fn(Level, &str)> Logger for F {
+	fn log(&self, level: Level, message: &str) {
+		self(level, message)
+	}
+}
+
+/// Logs messages at or above `level` to the browser console.
+///
+/// This is the default logger on WASM with the `web` feature, at `Level::Warn`.
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+pub struct ConsoleLogger {
+	/// Least severe level that is logged.
+	pub level: Level,
+}
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+impl Logger for ConsoleLogger {
+	fn log(&self, level: Level, message: &str) {
+		if level > self.level {
+			return;
+		}
+		let message = message.into();
+		match level {
+			Level::Error => web_sys::console::error_1(&message),
+			Level::Warn => web_sys::console::warn_1(&message),
+			Level::Info => web_sys::console::info_1(&message),
+			Level::Debug | Level::Trace => web_sys::console::debug_1(&message),
+		}
+	}
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_logger() -> Option<Box<dyn Logger>> {
+	#[cfg(feature = "web")]
+	return Some(Box::new(ConsoleLogger { level: Level::Warn }));
+	#[cfg(not(feature = "web"))]
+	return None;
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+	static LOGGER: std::cell::RefCell<Option<Box<dyn Logger>>> =
+		std::cell::RefCell::new(default_logger());
+}
+
+/// Replaces the logger receiving the crate's diagnostics on WASM; `None` silences them.
+#[cfg(target_arch = "wasm32")]
+pub fn set_logger(logger: Option<Box<dyn Logger>>) {
+	LOGGER.with(|current| *current.borrow_mut() = logger);
+}
+
+#[cfg(not(all(feature = "small-wasm", target_arch = "wasm32")))]
+pub(crate) fn log(level: Level, args: Arguments) {
+	#[cfg(not(target_arch = "wasm32"))]
+	log::log!(target: TARGET, level, "{}", args);
+
+	#[cfg(target_arch = "wasm32")]
+	LOGGER.with(|logger| {
+		if let Some(logger) = logger.borrow().as_ref() {
+			logger.log(level, &args.to_string());
+		}
+	});
+}
+
+/// Logs a message as written, without formatting its arguments into it.
+#[cfg(all(feature = "small-wasm", target_arch = "wasm32"))]
+pub(crate) fn log_static(level: Level, message: &'static str) {
+	LOGGER.with(|logger| {
+		if let Some(logger) = logger.borrow().as_ref() {
+			logger.log(level, message);
+		}
+	});
+}
+
+/// Logs a warning: something failed, but the store carried on.
+#[cfg(not(all(feature = "small-wasm", target_arch = "wasm32")))]
+macro_rules! log_warn {
+	($($arg:tt)+) => {
+		$crate::logging::log(::log::Level::Warn, format_args!($($arg)+))
+	};
+}
+
+/// Logs a warning with its placeholders left unfilled, keeping formatting code out of
+/// `small-wasm` builds.
+#[cfg(all(feature = "small-wasm", target_arch = "wasm32"))]
+macro_rules! log_warn {
+	($message:literal $(, $arg:expr)* $(,)?) => {{
+		$(let _ = &$arg;)*
+		$crate::logging::log_static(::log::Level::Warn, $message)
+	}};
+}
+
+/// Logs a notice about a recovery action the store took on its own.
+#[cfg(not(all(feature = "small-wasm", target_arch = "wasm32")))]
+macro_rules! log_info {
+	($($arg:tt)+) => {
+		$crate::logging::log(::log::Level::Info, format_args!($($arg)+))
+	};
+}
+
+/// Logs a notice with its placeholders left unfilled, like `log_warn`.
+#[cfg(all(feature = "small-wasm", target_arch = "wasm32"))]
+macro_rules! log_info {
+	($message:literal $(, $arg:expr)* $(,)?) => {{
+		$(let _ = &$arg;)*
+		$crate::logging::log_static(::log::Level::Info, $message)
+	}};
+}
+
+pub(crate) use {log_info, log_warn};
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+	use crate::{DirectoryConfig, DirectoryStore};
+	use std::sync::Mutex;
+	use tempfile::TempDir;
+
+	static MESSAGES: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+	struct Recorder;
+
+	impl log::Log for Recorder {
+		fn enabled(&self, metadata: &log::Metadata) -> bool {
+			metadata.target() == super::TARGET
+		}
+
+		fn log(&self, record: &log::Record) {
+			if self.enabled(record.metadata()) {
+				let message = record.args().to_string();
+				MESSAGES.lock().unwrap().push((record.level(), message));
+			}
+		}
+
+		fn flush(&self) {}
+	}
+
+	#[test]
+	fn test_recovery_is_logged() -> std::io::Result<()> {
+		log::set_logger(&Recorder).unwrap();
+		log::set_max_level(log::LevelFilter::Info);
+
+		let temp_dir = TempDir::new()?;
+		std::fs::write(
+			temp_dir.path().join("7-events"),
+			"{ \"batch\": [{\"event\":\"test\"}",
+		)?;
+		DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		})?;
+
+		let messages = MESSAGES.lock().unwrap();
+		assert!(messages.iter().any(|(level, message)| {
+			*level == log::Level::Info
+				&& message.starts_with("Recovered unfinished file")
+				&& message.contains("7-events")
+		}));
+		Ok(())
+	}
+}
@@ -1,14 +1,45 @@
-use crate::{DataResult, DataStore, Equivalent};
+use crate::attachment::{self, Attachment, AttachmentContent, AttachmentHandle};
+use crate::consent::ConsentFilter;
+use crate::context;
+use crate::delay;
+use crate::delivery::{DeliveredBatches, InFlightBatches};
+use crate::drops::{DropLog, DropReason, EvictionNotifier};
+use crate::envelope;
+use crate::eviction::{self, EvictionCandidate, EvictionPolicy};
+use crate::field_filter::FieldFilter;
+use crate::moves;
+use crate::received;
+use crate::removed::{FetchedBatches, RemovedRing};
+use crate::replay;
+use crate::routing;
+use crate::sampling::{DownSampling, Sampler};
+use crate::schema::SchemaMigrations;
+use crate::sized::SizedValue;
+use crate::skew;
+use crate::upsert;
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	BatchPreview, Capability, ClockOffsetProvider, ContextProvider, DataResult, DataStore,
+	DataStoreExt, DropRecord, Equivalent, EvictionListener, JsonFormat, PendingSize, RemovedBatch,
+	StoreStatus, TransientError,
+};
+use chrono::{DateTime, TimeDelta, Utc};
 use serde_json::json;
 use serde_json::Value;
 use std::any::Any;
-use std::collections::VecDeque;
-use std::io::Result;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Result, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
 impl Equivalent for Value {
 	fn equals(&self, other: &dyn Equivalent) -> bool {
-		if let Some(other_value) = other.as_any().downcast_ref::<Value>() {
+		let other = other.as_any();
+		if let Some(other_value) = other.downcast_ref::<Value>() {
 			self == other_value
+		} else if let Some(other_value) = other.downcast_ref::<Arc<Value>>() {
+			self == &**other_value
 		} else {
 			false
 		}
@@ -19,6 +50,21 @@ impl Equivalent for Value {
 	}
 }
 
+/// Items are shared between the store and the removables of a fetch, so fetching doesn't
+/// copy them twice.
+impl Equivalent for Arc<Value> {
+	fn equals(&self, other: &dyn Equivalent) -> bool {
+		match other.as_any().downcast_ref::<Arc<Value>>() {
+			Some(other_value) => Arc::ptr_eq(self, other_value) || self == other_value,
+			None => (**self).equals(other),
+		}
+	}
+
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
 /// Configuration options for the in-memory data store.
 ///
 /// This struct provides the configuration parameters needed to create a new MemoryStore instance.
@@ -61,7 +107,48 @@ pub struct MemoryConfig {
 /// - The store's write key
 pub struct MemoryStore {
 	config: MemoryConfig,
-	items: VecDeque<Value>,
+	items: VecDeque<SizedValue>,
+	/// When each item in `items` was appended, in the same order
+	enqueued: VecDeque<DateTime<Utc>>,
+	delivered: DeliveredBatches,
+	/// Ids of fetched batches not yet removed, by their items
+	in_flight: InFlightBatches<Arc<Value>>,
+	json_format: JsonFormat,
+	attachments: HashMap<String, Attachment>,
+	consent: ConsentFilter,
+	field_filter: FieldFilter,
+	migrations: Option<SchemaMigrations>,
+	/// Field stamped with the time on events appended without a timestamp
+	received_at: Option<String>,
+	/// Drops low-priority events under sustained pressure, if set
+	sampler: Option<Sampler>,
+	/// Chooses the items to drop when over capacity; the oldest if unset
+	eviction: Option<Box<dyn EvictionPolicy>>,
+	/// Notified with the events the store drops, if set
+	eviction_listener: Option<EvictionNotifier>,
+	/// Supplies the context added to fetched envelopes
+	context: Option<Box<dyn ContextProvider>>,
+	/// Supplies the offset fetched timestamps are corrected by
+	clock_offset: Option<Box<dyn ClockOffsetProvider>>,
+	/// Items evicted since the last drop report
+	drops: DropLog,
+	/// The last removed batches, if retained for debugging
+	removed: RemovedRing,
+	/// Ids of recent fetches, while removed batches are retained
+	fetched: FetchedBatches,
+	/// Set once a replayed item is queued, so fetches don't check every item for one
+	/// until then
+	replayed: bool,
+	/// Set once a delayed item is queued, so fetches don't check every item's time
+	/// until then
+	delayed: bool,
+	/// Set once an item is held by a move, so fetches and evictions don't check every
+	/// item for one until then
+	moving: bool,
+	/// How many pinned items may be queued
+	max_pinned: usize,
+	/// Number of reserved items not yet committed
+	reserved: usize,
 }
 
 impl MemoryStore {
@@ -70,6 +157,7 @@ impl MemoryStore {
 	/// # Panics
 	/// * If max_fetch_size is less than 100 bytes
 	/// * If max_items is 0
+	#[allow(clippy::panic)] // Invalid configuration is a programming error
 	pub fn new(config: MemoryConfig) -> Self {
 		if config.max_fetch_size < 100 {
 			panic!("max_fetch_size < 100 bytes? What are you even trying to fetch, empty arrays?");
@@ -81,263 +169,1334 @@ impl MemoryStore {
 		Self {
 			config,
 			items: VecDeque::new(),
+			enqueued: VecDeque::new(),
+			delivered: DeliveredBatches::new(DeliveredBatches::DEFAULT_CAPACITY),
+			in_flight: InFlightBatches::default(),
+			json_format: JsonFormat::default(),
+			attachments: HashMap::new(),
+			consent: ConsentFilter::default(),
+			field_filter: FieldFilter::default(),
+			migrations: None,
+			received_at: None,
+			sampler: None,
+			eviction: None,
+			eviction_listener: None,
+			context: None,
+			clock_offset: None,
+			drops: DropLog::default(),
+			removed: RemovedRing::default(),
+			fetched: FetchedBatches::default(),
+			replayed: false,
+			delayed: false,
+			moving: false,
+			max_pinned: eviction::DEFAULT_MAX_PINNED,
+			reserved: 0,
 		}
 	}
 
-	/// Creates a JSON batch object containing the provided items and metadata.
+	/// Sets how many items appended with [`append_pinned`](DataStoreExt::append_pinned) may
+	/// be queued at once (100 by default).
 	///
-	/// # Arguments
-	/// * `items` - Slice of JSON values to include in the batch
+	/// Pinned items count towards `max_items` but are never evicted, so if pins take up
+	/// all of it the store holds more than `max_items` items.
+	pub fn set_max_pinned(&mut self, max_pinned: usize) {
+		self.max_pinned = max_pinned;
+	}
+
+	/// Sets which items are dropped when appends take the store over `max_items`,
+	/// instead of the oldest.
 	///
-	/// # Returns
-	/// A JSON value containing:
-	/// - A `batch` array of the provided items
-	/// - A `sentAt` timestamp in RFC3339 format
-	/// - The store's `writeKey`
-	fn create_batch(&self, items: &[Value]) -> Value {
-		json!({
-			"batch": items,
-			"sentAt": chrono::Utc::now().to_rfc3339(),
-			"writeKey": self.config.write_key
-		})
+	/// The policy is shown every queued item each time the store evicts.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DataStore, DataStoreExt, JsonPointer, MemoryConfig, MemoryStore, PriorityEviction};
+	///
+	/// let mut store = MemoryStore::new(MemoryConfig {
+	///     write_key: "my-store".into(),
+	///     max_items: 2,
+	///     max_fetch_size: 1024,
+	/// });
+	/// store.set_eviction_policy(PriorityEviction::new(JsonPointer::new("/priority")?));
+	///
+	/// store.append(json!({"event": "purchase", "priority": 10}))?;
+	/// store.append(json!({"event": "scroll"}))?;
+	/// store.append(json!({"event": "click"}))?;
+	///
+	/// // The purchase outlives the older scroll
+	/// let events = store.pending_events()?;
+	/// assert_eq!(events[0]["event"], "purchase");
+	/// assert_eq!(events[1]["event"], "click");
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn set_eviction_policy<P: EvictionPolicy + 'static>(&mut self, policy: P) {
+		self.eviction = Some(Box::new(policy));
 	}
 
-	fn get_item_size(item: &Value) -> usize {
-		item.to_string().len()
+	/// Sets a listener notified with the events the store drops, when appends take it
+	/// over `max_items` or down-sampling drops them, passing it at most `max_events` of them per drop.
+	pub fn set_eviction_listener<L: EvictionListener + 'static>(
+		&mut self,
+		listener: L,
+		max_events: usize,
+	) {
+		self.eviction_listener = Some(EvictionNotifier::new(Box::new(listener), max_events));
 	}
-}
 
-impl DataStore for MemoryStore {
-	type Output = Value;
+	/// Sets the JSON format used for fetched batch envelopes.
+	///
+	/// `Canonical` sorts object keys in the envelope and its events. Since fetch returns
+	/// a `Value`, `Compact` and `Pretty` only differ once the caller serializes it.
+	pub fn set_json_format(&mut self, format: JsonFormat) {
+		self.json_format = format;
+	}
 
-	fn has_data(&self) -> bool {
-		!self.items.is_empty()
+	/// Retains the last `batches` removed batches for
+	/// [`recently_removed`](DataStoreExt::recently_removed), so developers can inspect what
+	/// was uploaded. 0, the default, retains none.
+	///
+	/// Meant for debug builds: retained events stay in memory.
+	pub fn set_retain_removed(&mut self, batches: usize) {
+		self.removed.set_capacity(batches);
 	}
 
-	fn reset(&mut self) {
-		self.items.clear();
+	/// Sets the provider of the `context` object added to each fetched batch envelope,
+	/// see [`ContextProvider`].
+	pub fn set_context_provider<P: ContextProvider + 'static>(&mut self, provider: P) {
+		self.context = Some(Box::new(provider));
 	}
 
-	fn append(&mut self, data: Value) -> Result<()> {
-		self.items.push_back(data);
+	/// Sets the provider of the offset from the server's clock that fetched batches'
+	/// `sentAt` and event timestamps are corrected by, see [`ClockOffsetProvider`].
+	pub fn set_clock_offset_provider<P: ClockOffsetProvider + 'static>(&mut self, provider: P) {
+		self.clock_offset = Some(Box::new(provider));
+	}
 
-		while self.items.len() > self.config.max_items {
-			self.items.pop_front();
+	/// Sets fields to strip from events as they are appended, so they are never stored.
+	///
+	/// Only affects events appended afterwards.
+	pub fn set_field_filter(&mut self, filter: FieldFilter) {
+		self.field_filter = filter;
+	}
+
+	/// Sets the event schema version stamped on appended events, and migrates events
+	/// already queued that were stored with an older version.
+	pub fn set_migrations(&mut self, migrations: SchemaMigrations) {
+		for item in self.items.iter_mut() {
+			if SchemaMigrations::event_version(item) < migrations.version() {
+				item.update(|value| migrations.migrate(value));
+			}
 		}
+		self.migrations = Some(migrations);
+	}
 
-		Ok(())
+	/// Sets the field (e.g. `receivedAt`) stamped with the time an event is appended, on
+	/// events appended without a `timestamp` field, so they can still be ordered
+	/// downstream. `None` stops stamping.
+	///
+	/// Only affects events appended afterwards.
+	pub fn set_received_at_field(&mut self, field: Option<&str>) {
+		self.received_at = field.map(str::to_string);
 	}
 
-	fn fetch(
+	/// Sets adaptive down-sampling, dropping appended events of low-priority types while
+	/// the store stays over a threshold; see [`DownSampling`]. `None` turns it off.
+	pub fn set_down_sampling(&mut self, sampling: Option<DownSampling>) {
+		self.sampler = sampling.map(Sampler::new);
+	}
+
+	/// Creates a JSON batch object containing the provided items and metadata.
+	///
+	/// # Arguments
+	/// * `items` - Slice of JSON values to include in the batch
+	/// * `batch_id` - Identifier of the fetch result this batch belongs to
+	///
+	/// # Returns
+	/// A JSON value containing:
+	/// - A `batch` array of the provided items
+	/// - A `sentAt` timestamp in RFC3339 format
+	/// - Timestamps corrected for the clock offset, if there's a provider
+	/// - The items' `writeKey`
+	/// - The `batchId` of the fetch result
+	/// - The `context` from the context provider, if set
+	/// - `"replay": true`, if any of the items were replayed
+	fn create_batch(&self, items: &[Arc<Value>], batch_id: &str, write_key: &str) -> Value {
+		let offset = skew::offset(self.clock_offset.as_deref());
+		let mut envelope = self.envelope_fields(items, batch_id, write_key, offset);
+		envelope["batch"] = items
+			.iter()
+			.map(|item| skew::correct(item, offset).into_owned())
+			.collect();
+		self.json_format.normalize(envelope)
+	}
+
+	/// Writes the batch [`create_batch`](Self::create_batch) would create to `writer`,
+	/// returning the number of bytes written.
+	fn write_batch(
+		&self,
+		writer: &mut dyn Write,
+		items: &[Arc<Value>],
+		batch_id: &str,
+		write_key: &str,
+	) -> Result<u64> {
+		let offset = skew::offset(self.clock_offset.as_deref());
+		let fields = self.envelope_fields(items, batch_id, write_key, offset);
+		envelope::write(
+			writer,
+			self.json_format,
+			items.iter().map(|item| skew::correct(item, offset)),
+			fields,
+		)
+	}
+
+	/// The envelope of a batch of `items`, without the `batch` array
+	fn envelope_fields(
+		&self,
+		items: &[Arc<Value>],
+		batch_id: &str,
+		write_key: &str,
+		offset: Option<TimeDelta>,
+	) -> Value {
+		let sent_at = Utc::now() + offset.unwrap_or_else(TimeDelta::zero);
+		let mut envelope = json!({
+			"sentAt": sent_at.to_rfc3339(),
+			"writeKey": write_key,
+			"batchId": batch_id
+		});
+		context::add_to(&mut envelope, self.context.as_deref());
+		if self.replayed && items.iter().any(|item| replay::is_replay(item)) {
+			replay::flag(&mut envelope);
+		}
+		envelope
+	}
+
+	/// Selects the next batch like [`fetch`](DataStore::fetch), passing its items, batch
+	/// id and write key to `build` for the result's data.
+	fn fetch_with<O>(
 		&mut self,
 		count: Option<usize>,
 		max_bytes: Option<usize>,
-	) -> Result<Option<DataResult<Self::Output>>> {
+		build: impl FnOnce(&Self, &[Arc<Value>], &str, &str) -> Result<O>,
+	) -> Result<Option<DataResult<O>>> {
 		let max_bytes = max_bytes.unwrap_or(self.config.max_fetch_size);
+		let now = Utc::now();
+		let Some(write_key) = self.batch_write_key(now).map(str::to_string) else {
+			return Ok(None);
+		};
 		let mut accumulated_size = 0;
-		let mut num_items = 0;
+		let mut items: Vec<Arc<Value>> = Vec::new();
 
-		// Just look at items without draining
-		for item in self.items.iter() {
-			let item_size = Self::get_item_size(item);
+		// Just look at items without draining, skipping those without consent, delayed
+		// or for other write keys
+		for item in self
+			.items
+			.iter()
+			.filter(|item| self.in_batch(item, &write_key, now))
+		{
+			let item_size = item.size();
 			if accumulated_size + item_size > max_bytes {
 				break;
 			}
 			if let Some(count) = count {
-				if num_items >= count {
+				if items.len() >= count {
 					break;
 				}
 			}
 			accumulated_size += item_size;
-			num_items += 1;
+			items.push(item.shared().clone());
 		}
 
-		if num_items == 0 {
+		if items.is_empty() {
 			return Ok(None);
 		}
 
-		// Create vectors of items and removable references
-		let items: Vec<Value> = self.items.iter().take(num_items).cloned().collect();
-
+		let (allowed_items, allowed_bytes) = self
+			.items
+			.iter()
+			.filter(|item| self.fetchable(item, now))
+			.fold((0, 0), |(count, bytes), item| {
+				(count + 1, bytes + item.size() as u64)
+			});
+		let remaining_items = allowed_items - items.len();
+		let remaining_bytes = allowed_bytes - accumulated_size as u64;
+
+		// Removables share the items instead of copying them
 		let removable: Vec<Box<dyn Equivalent>> = items
 			.iter()
 			.map(|item| Box::new(item.clone()) as Box<dyn Equivalent>)
 			.collect();
 
-		let batch = self.create_batch(&items);
+		// A retry of a batch that wasn't removed keeps its id
+		let batch_id = self.in_flight.id_for(items.clone(), Arc::ptr_eq);
+		let data = build(self, &items, &batch_id, &write_key)?;
+		if self.removed.is_enabled() {
+			self.fetched
+				.record(&batch_id, Arc::new(Arc::clone(&items[0])));
+		}
 
 		Ok(Some(DataResult {
-			data: Some(batch),
+			data: Some(data),
 			removable: Some(removable),
+			batch_id: Some(batch_id),
+			attachments: self.attachment_handles(&items),
+			remaining_items,
+			remaining_bytes,
 		}))
 	}
 
-	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
-		// Remove items that match the provided equivalents
-		self.items
-			.retain(|item| !data.iter().any(|removable| removable.equals(item)));
-		Ok(())
+	/// Whether an item may be fetched at `now`: it has consent, isn't delayed and isn't
+	/// held by a move
+	fn fetchable(&self, item: &Value, now: DateTime<Utc>) -> bool {
+		self.consent.allows(item)
+			&& (!self.delayed || delay::is_due(item, now))
+			&& (!self.moving || !moves::is_held(item))
 	}
-}
-
-#[cfg(test)]
-mod tests {
-	use crate::memory::{MemoryConfig, MemoryStore};
-	use crate::DataStore;
-	use serde_json::{json, Value};
-	use std::io::Result;
-
-	#[test]
-	fn test_basic_operations() -> Result<()> {
-		let config = MemoryConfig {
-			write_key: "test-key".to_string(),
-			max_items: 1000,
-			max_fetch_size: 1024,
-		};
-
-		let mut store = MemoryStore::new(config);
-
-		// Test empty state
-		assert!(!store.has_data());
 
-		// Test append
-		let event = json!({"event": "test", "value": 123});
-		store.append(event.clone())?;
-		assert!(store.has_data());
+	/// Write key of the next batch: that of the oldest item that may be fetched
+	fn batch_write_key(&self, now: DateTime<Utc>) -> Option<&str> {
+		self.items
+			.iter()
+			.find(|item| self.fetchable(item, now))
+			.map(|item| routing::write_key(item, &self.config.write_key))
+	}
 
-		// Test fetch - data should still be there after fetch
-		if let Some(result) = store.fetch(None, None)? {
-			let batch: Value = result.data.unwrap();
-			let items = batch["batch"].as_array().unwrap();
-			assert_eq!(items.len(), 1);
-			assert_eq!(items[0]["value"], 123);
+	/// Whether an item may go in a batch for `write_key`
+	fn in_batch(&self, item: &Value, write_key: &str, now: DateTime<Utc>) -> bool {
+		self.fetchable(item, now) && routing::write_key(item, &self.config.write_key) == write_key
+	}
 
-			// Verify items are still in store after fetch
-			assert!(store.has_data());
+	/// How many items may be queued, leaving room for reserved ones
+	fn capacity(&self) -> usize {
+		self.config.max_items.saturating_sub(self.reserved).max(1)
+	}
 
-			// Now remove the items
-			if let Some(removable) = result.removable {
-				store.remove(&removable)?;
-				// Verify items were removed
-				assert!(!store.has_data());
-			} else {
-				panic!("Expected removable items but got none");
+	/// Drops items chosen by the eviction policy, or the oldest, until at most
+	/// `capacity` are left. Pinned items and items held by a move are never dropped, so
+	/// more may be left.
+	fn evict_to(&mut self, capacity: usize) {
+		if self.items.len() <= capacity {
+			return;
+		}
+		let excess = self.items.len() - capacity;
+		let unpinned = self
+			.items
+			.iter()
+			.enumerate()
+			.filter(|(_, item)| {
+				!eviction::is_pinned(item) && (!self.moving || !moves::is_held(item))
+			})
+			.map(|(position, _)| position);
+		let victims: Vec<usize> = match &self.eviction {
+			Some(policy) => {
+				let positions: Vec<usize> = unpinned.collect();
+				let candidates: Vec<EvictionCandidate> = positions
+					.iter()
+					.map(|&position| EvictionCandidate {
+						event: &self.items[position],
+						size: self.items[position].size(),
+						enqueued_at: self.enqueued[position],
+					})
+					.collect();
+				eviction::select_victims(policy.as_ref(), &candidates, excess)
+					.into_iter()
+					.map(|candidate| positions[candidate])
+					.collect()
 			}
-		} else {
-			panic!("Expected data but got none");
+			None => unpinned.take(excess).collect(),
+		};
+		if victims.is_empty() {
+			return;
+		}
+		self.drops.record_times(
+			DropReason::Capacity,
+			victims.iter().map(|&position| self.enqueued[position]),
+		);
+		if let Some(listener) = &self.eviction_listener {
+			listener.notify(
+				DropReason::Capacity,
+				victims.iter().map(|&position| &*self.items[position]),
+				victims.len(),
+			);
+		}
+		for position in victims.into_iter().rev() {
+			self.items.remove(position);
+			self.enqueued.remove(position);
 		}
+		self.prune_attachments();
+	}
 
-		Ok(())
+	/// Applies the store's filters to an item about to be queued, returning `None` if it
+	/// must not be queued
+	fn prepare(&self, mut data: Value) -> Option<Value> {
+		// Re-adding an envelope that was already delivered would duplicate it server-side
+		if self.delivered.is_redelivery(&data) {
+			return None;
+		}
+		self.field_filter.apply(&mut data);
+		if let Some(migrations) = &self.migrations {
+			migrations.stamp(&mut data);
+		}
+		received::stamp(&mut data, self.received_at.as_deref(), Utc::now());
+		Some(data)
 	}
 
-	#[test]
-	fn test_fifo_behavior() -> Result<()> {
-		let config = MemoryConfig {
-			write_key: "test-key".to_string(),
-			max_items: 3, // Small limit to test FIFO
-			max_fetch_size: 1024,
+	/// Whether down-sampling keeps an item about to be queued, recording it as dropped
+	/// if not
+	fn sample(&mut self, data: &Value, now: DateTime<Utc>) -> bool {
+		let Some(sampler) = &mut self.sampler else {
+			return true;
 		};
-
-		let mut store = MemoryStore::new(config);
-
-		// Add more items than max_items
-		for i in 0..5 {
-			store.append(json!({"index": i}))?;
+		if sampler.admit(data, self.items.len(), now) {
+			return true;
 		}
+		self.drops.record_times(DropReason::Sampled, [now]);
+		if let Some(listener) = &self.eviction_listener {
+			listener.notify(DropReason::Sampled, [data], 1);
+		}
+		false
+	}
 
-		// Should only have last 3 items
-		assert!(store.has_data());
+	/// Queues a prepared item
+	fn push(&mut self, data: Value, enqueued_at: DateTime<Utc>) {
+		self.delayed |= delay::is_delayed(&data);
+		self.replayed |= replay::is_replay(&data);
+		self.items.push_back(SizedValue::new(data));
+		self.enqueued.push_back(enqueued_at);
+	}
 
-		// Verify they're the right items (2,3,4)
-		if let Some(result) = store.fetch(None, None)? {
-			let batch: Value = result.data.unwrap();
-			let items = batch["batch"].as_array().unwrap();
-			assert_eq!(items.len(), 3);
-			assert_eq!(items[0]["index"], 2);
-			assert_eq!(items[1]["index"], 3);
-			assert_eq!(items[2]["index"], 4);
+	/// Drops attachments whose events are no longer in the store
+	fn prune_attachments(&mut self) {
+		if self.attachments.is_empty() {
+			return;
 		}
+		let referenced: HashSet<&str> = self
+			.items
+			.iter()
+			.flat_map(|item| attachment::referenced_ids(item))
+			.collect();
+		self.attachments
+			.retain(|id, _| referenced.contains(id.as_str()));
+	}
 
-		Ok(())
+	/// Builds handles for the attachments referenced by the given items
+	fn attachment_handles(&self, items: &[Arc<Value>]) -> Option<Vec<AttachmentHandle>> {
+		if self.attachments.is_empty() {
+			return None;
+		}
+		let handles: Vec<AttachmentHandle> = items
+			.iter()
+			.flat_map(|item| attachment::referenced_ids(item))
+			.filter_map(|id| {
+				self.attachments.get(id).map(|attachment| AttachmentHandle {
+					id: id.to_string(),
+					name: attachment.name.clone(),
+					content_type: attachment.content_type.clone(),
+					content: AttachmentContent::Bytes(attachment.data.clone()),
+				})
+			})
+			.collect();
+		(!handles.is_empty()).then_some(handles)
 	}
+}
 
-	#[test]
-	fn test_fetch_limits() -> Result<()> {
-		let config = MemoryConfig {
-			write_key: "test-key".to_string(),
-			max_items: 100,
-			max_fetch_size: 1000,
-		};
+impl DataStore for MemoryStore {
+	type Output = Value;
 
-		let mut store = MemoryStore::new(config);
+	/// Items that aren't due yet, lack consent or are held by a move stay queued, but
+	/// don't count, as fetches skip them.
+	fn has_data(&self) -> bool {
+		let now = Utc::now();
+		self.items.iter().any(|item| self.fetchable(item, now))
+	}
 
-		// Add items with predictable sizes
-		for i in 0..10 {
-			let padding = "x".repeat(50); // Each item will be roughly ~70 bytes
-			store.append(json!({
-				"index": i,
-				"padding": padding
-			}))?;
-		}
+	fn reset(&mut self) {
+		self.items.clear();
+		self.enqueued.clear();
+		self.in_flight.clear();
+		self.attachments.clear();
+		self.delayed = false;
+		self.replayed = false;
+		self.moving = false;
+		self.reserved = 0;
+	}
 
-		// Test count limit
-		if let Some(result) = store.fetch(Some(3), None)? {
-			let batch: Value = result.data.unwrap();
-			let items = batch["batch"].as_array().unwrap();
-			assert_eq!(items.len(), 3, "Count limit not respected");
+	fn append(&mut self, data: Value) -> Result<()> {
+		let Some(data) = self.prepare(data) else {
+			return Ok(());
+		};
+		let now = Utc::now();
+		if !self.sample(&data, now) {
+			// Drop its attachments too, if it had any
+			self.prune_attachments();
+			return Ok(());
 		}
+		self.push(data, now);
+		self.evict_to(self.capacity());
+		Ok(())
+	}
 
-		// Add more items for size limit test
-		for i in 0..10 {
-			let padding = "x".repeat(50);
-			store.append(json!({
-				"index": i,
-				"padding": padding
-			}))?;
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		let now = Utc::now();
+		for data in items {
+			if let Some(data) = self.prepare(data) {
+				if self.sample(&data, now) {
+					self.push(data, now);
+				}
+			}
 		}
+		self.evict_to(self.capacity());
+		Ok(())
+	}
 
-		// Test byte limit (200 bytes should get us about 2-3 items)
-		if let Some(result) = store.fetch(None, Some(200))? {
-			let items = result.data.unwrap();
-			let items = items["batch"].as_array().unwrap();
-			assert!(items.len() <= 3, "Too many items for byte limit");
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		self.fetch_with(count, max_bytes, |store, items, batch_id, write_key| {
+			Ok(store.create_batch(items, batch_id, write_key))
+		})
+	}
 
-			// Each raw item should be under the limit
-			let total_raw_size: usize = items
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		// Remove the first item matching each removable. Matching one-to-one keeps equal
+		// items that weren't part of the fetch (e.g. duplicates further back) queued.
+		let mut pending: Vec<&Box<dyn Equivalent>> = data.iter().collect();
+		let keep: Vec<bool> = self
+			.items
+			.iter()
+			.map(|item| {
+				match pending
+					.iter()
+					.position(|removable| removable.equals(item.shared()))
+				{
+					Some(position) => {
+						pending.swap_remove(position);
+						false
+					}
+					None => true,
+				}
+			})
+			.collect();
+
+		if self.removed.is_enabled() {
+			let events = self
+				.items
 				.iter()
-				.map(|item| MemoryStore::get_item_size(item))
-				.sum();
-			assert!(total_raw_size <= 200, "Raw items exceed byte limit");
+				.zip(&keep)
+				.filter(|(_, keep)| !**keep)
+				.map(|(item, _)| (**item).clone())
+				.collect();
+			let batch_id = self.fetched.take(data);
+			self.removed.push(batch_id, events, Utc::now());
 		}
 
+		let removed: Vec<&Arc<Value>> = self
+			.items
+			.iter()
+			.zip(&keep)
+			.filter(|(_, keep)| !**keep)
+			.map(|(item, _)| item.shared())
+			.collect();
+		self.in_flight
+			.forget(|item| removed.iter().any(|removed| Arc::ptr_eq(item, removed)));
+
+		let mut keep_item = keep.iter();
+		self.items.retain(|_| *keep_item.next().unwrap_or(&true));
+		let mut keep_time = keep.iter();
+		self.enqueued.retain(|_| *keep_time.next().unwrap_or(&true));
+		self.prune_attachments();
 		Ok(())
 	}
 
-	#[test]
-	fn test_reset() -> Result<()> {
-		let config = MemoryConfig {
-			write_key: "test-key".to_string(),
-			max_items: 100,
-			max_fetch_size: 1000,
-		};
+	fn debug_config(&self) -> Value {
+		json!({
+			"store": "MemoryStore",
+			"writeKey": self.config.write_key,
+			"maxItems": self.config.max_items,
+			"maxFetchSize": self.config.max_fetch_size,
+			"jsonFormat": format!("{:?}", self.json_format),
+			"allowedCategories": self.consent.to_json(),
+			"fieldFilter": self.field_filter.to_json(),
+			"schema": self.migrations.as_ref().map(SchemaMigrations::to_json),
+			"receivedAtField": self.received_at,
+			"downSampling": self.sampler.as_ref().map(Sampler::to_json),
+		})
+	}
 
-		let mut store = MemoryStore::new(config);
+	fn supports(&self, capability: Capability) -> bool {
+		match capability {
+			Capability::Attachments
+			| Capability::Pinning
+			| Capability::Routing
+			| Capability::Delayed
+			| Capability::Upsert
+			| Capability::Reservation
+			| Capability::Delivery
+			| Capability::EnqueueTimes
+			| Capability::PendingSize
+			| Capability::Preview
+			| Capability::PendingEvents
+			| Capability::Consent
+			| Capability::DropReport
+			| Capability::MoveSource
+			| Capability::MoveDestination
+			| Capability::RemovedBatches
+			| Capability::WriteBatchTo
+			| Capability::FetchOlderThan
+			| Capability::Status
+			| Capability::Expire => true,
+			#[cfg(feature = "anonymize")]
+			Capability::Anonymize => true,
+		}
+	}
+}
 
-		// Add some items
-		for i in 0..5 {
-			store.append(json!({"index": i}))?;
+impl DataStoreExt for MemoryStore {
+	fn append_with_attachments(
+		&mut self,
+		mut data: Value,
+		attachments: Vec<Attachment>,
+	) -> Result<()> {
+		if self.delivered.is_redelivery(&data) {
+			return Ok(());
 		}
-		// we have 5 items
-		assert!(store.has_data());
 
-		// Reset and verify
-		store.reset();
-		assert!(!store.has_data());
+		let attachments = attachment::attach(&mut data, attachments)?;
+		self.attachments.extend(attachments);
+		self.append(data)
+	}
 
-		Ok(())
+	fn append_pinned(&mut self, mut data: Value) -> Result<()> {
+		eviction::pin(&mut data)?;
+		let pinned = self
+			.items
+			.iter()
+			.filter(|item| eviction::is_pinned(item))
+			.count();
+		if pinned >= self.max_pinned {
+			return Err(TransientError::TooManyPinned {
+				limit: self.max_pinned,
+			}
+			.into());
+		}
+		self.append(data)
 	}
 
-	#[test]
+	fn append_for(&mut self, write_key: &str, mut data: Value) -> Result<()> {
+		routing::tag(&mut data, write_key)?;
+		self.append(data)
+	}
+
+	fn append_delayed(&mut self, mut data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		delay::tag(&mut data, not_before)?;
+		self.append(data)
+	}
+
+	fn upsert(&mut self, key: &str, mut data: Value) -> Result<()> {
+		upsert::tag(&mut data, key)?;
+		if let Some(position) = self
+			.items
+			.iter()
+			.position(|item| upsert::has_key(item, key))
+		{
+			self.items.remove(position);
+			self.enqueued.remove(position);
+			self.prune_attachments();
+		}
+		self.append(data)
+	}
+
+	fn reserve(&mut self, _estimated_bytes: usize) -> Result<()> {
+		self.reserved += 1;
+		// Like appends, keep the newest item, so reserving more than max_items doesn't
+		// empty the queue before anything is committed
+		self.evict_to(self.capacity());
+		// Committing mustn't reallocate either
+		self.items.reserve(self.reserved);
+		self.enqueued.reserve(self.reserved);
+		Ok(())
+	}
+
+	fn commit_reserved(&mut self, data: Value, _estimated_bytes: usize) -> Result<()> {
+		self.reserved = self.reserved.saturating_sub(1);
+		let now = Utc::now();
+		if let Some(data) = self.prepare(data) {
+			if self.sample(&data, now) {
+				self.push(data, now);
+			}
+		}
+		Ok(())
+	}
+
+	fn release_reserved(&mut self, _estimated_bytes: usize) {
+		self.reserved = self.reserved.saturating_sub(1);
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		self.delivered.insert(batch_id);
+		Ok(())
+	}
+
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		Ok(self.enqueued.iter().copied().collect())
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.enqueued.front().copied()
+	}
+
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		let max_bytes = max_bytes.unwrap_or(self.config.max_fetch_size) as u64;
+		let mut preview = BatchPreview::default();
+		let now = Utc::now();
+		let Some(write_key) = self.batch_write_key(now) else {
+			return Ok(preview);
+		};
+
+		// Mirrors fetch, without cloning the items
+		let items = self.items.iter().zip(&self.enqueued);
+		for (item, enqueued) in items.filter(|(item, _)| self.in_batch(item, write_key, now)) {
+			let item_size = item.size() as u64;
+			if preview.bytes + item_size > max_bytes {
+				break;
+			}
+			if count.is_some_and(|count| preview.items >= count) {
+				break;
+			}
+			preview.add(item_size, Some(*enqueued));
+		}
+		Ok(preview)
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		Ok(self.items.iter().map(|item| (**item).clone()).collect())
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		Ok(PendingSize {
+			items: self.items.len(),
+			bytes: self.items.iter().map(|item| item.size() as u64).sum(),
+		})
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.delivered.contains(batch_id)
+	}
+
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.consent.set(categories);
+		Ok(())
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		let keep: Vec<bool> = self
+			.items
+			.iter()
+			.map(|item| self.consent.allows(item))
+			.collect();
+		let purged = keep.iter().filter(|keep| !**keep).count();
+
+		let mut keep_item = keep.iter();
+		self.items.retain(|_| *keep_item.next().unwrap_or(&true));
+		let mut keep_time = keep.iter();
+		self.enqueued.retain(|_| *keep_time.next().unwrap_or(&true));
+		self.prune_attachments();
+		Ok(purged)
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		Ok(self
+			.items
+			.iter_mut()
+			.map(|item| item.update(|value| anonymizer.anonymize(value)))
+			.filter(|changed| *changed)
+			.count())
+	}
+
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		Ok(self.drops.take())
+	}
+
+	fn begin_move(&mut self, move_id: &str, count: usize) -> Result<Vec<Value>> {
+		let now = Utc::now();
+		let mut copies = Vec::new();
+		for position in 0..self.items.len() {
+			if copies.len() >= count {
+				break;
+			}
+			let item = &self.items[position];
+			// Attachments stay behind with their events
+			if !item.is_object()
+				|| !self.fetchable(item, now)
+				|| attachment::referenced_ids(item).next().is_some()
+			{
+				continue;
+			}
+			copies.push((**item).clone());
+			self.items[position].update(|value| moves::tag(value, move_id).is_ok());
+			self.moving = true;
+		}
+		Ok(copies)
+	}
+
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		let mut held = Vec::with_capacity(items.len());
+		for data in items {
+			if let Some(mut data) = self.prepare(data) {
+				moves::tag(&mut data, move_id)?;
+				held.push(data);
+			}
+		}
+		let now = Utc::now();
+		for data in held {
+			self.push(data, now);
+			self.moving = true;
+		}
+		self.evict_to(self.capacity());
+		Ok(())
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		for item in self.items.iter_mut() {
+			if moves::move_id(item) == Some(move_id) {
+				item.update(moves::untag);
+			}
+		}
+		Ok(())
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		let keep: Vec<bool> = self
+			.items
+			.iter()
+			.map(|item| moves::move_id(item) != Some(move_id))
+			.collect();
+
+		let mut keep_item = keep.iter();
+		self.items.retain(|_| *keep_item.next().unwrap_or(&true));
+		let mut keep_time = keep.iter();
+		self.enqueued.retain(|_| *keep_time.next().unwrap_or(&true));
+		Ok(())
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		let mut held: Vec<String> = Vec::new();
+		if !self.moving {
+			return Ok(held);
+		}
+		for move_id in self.items.iter().filter_map(|item| moves::move_id(item)) {
+			if !held.iter().any(|id| id == move_id) {
+				held.push(move_id.to_string());
+			}
+		}
+		Ok(held)
+	}
+
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		Ok(self.removed.batches())
+	}
+
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		let mut events = self
+			.removed
+			.events_of(batch_id)
+			.ok_or_else(|| replay::not_retained(batch_id))?
+			.to_vec();
+		for event in &mut events {
+			replay::tag(event, batch_id)?;
+		}
+		let count = events.len();
+		self.append_many(events)?;
+		Ok(count)
+	}
+
+	fn write_batch_to(
+		&mut self,
+		writer: &mut dyn Write,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		self.fetch_with(count, max_bytes, |store, items, batch_id, write_key| {
+			store.write_batch(writer, items, batch_id, write_key)
+		})
+	}
+
+	fn fetch_older_than(&mut self, age: Duration) -> Result<Option<DataResult<Self::Output>>> {
+		let now = Utc::now();
+		let Some(cutoff) = TimeDelta::from_std(age)
+			.ok()
+			.and_then(|age| now.checked_sub_signed(age))
+		else {
+			return Ok(None);
+		};
+		let Some(write_key) = self.batch_write_key(now) else {
+			return Ok(None);
+		};
+		// Items are queued in arrival order, so the old enough ones are at the front
+		let old = self
+			.items
+			.iter()
+			.zip(&self.enqueued)
+			.filter(|(item, _)| self.in_batch(item, write_key, now))
+			.take_while(|(_, enqueued)| **enqueued <= cutoff)
+			.count();
+		if old == 0 {
+			return Ok(None);
+		}
+		self.fetch(Some(old), None)
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		Ok(StoreStatus::MemoryOnly { reason: None })
+	}
+
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		let Some(cutoff) = TimeDelta::from_std(age)
+			.ok()
+			.and_then(|age| Utc::now().checked_sub_signed(age))
+		else {
+			return Ok(0);
+		};
+		let expired: Vec<usize> = (0..self.items.len())
+			.filter(|&position| {
+				let item = &self.items[position];
+				self.enqueued[position] <= cutoff
+					&& !eviction::is_pinned(item)
+					&& (!self.moving || !moves::is_held(item))
+			})
+			.collect();
+		if expired.is_empty() {
+			return Ok(0);
+		}
+		self.drops.record_times(
+			DropReason::Expired,
+			expired.iter().map(|&position| self.enqueued[position]),
+		);
+		if let Some(listener) = &self.eviction_listener {
+			listener.notify(
+				DropReason::Expired,
+				expired.iter().map(|&position| &*self.items[position]),
+				expired.len(),
+			);
+		}
+		for &position in expired.iter().rev() {
+			self.items.remove(position);
+			self.enqueued.remove(position);
+		}
+		self.prune_attachments();
+		Ok(expired.len())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::memory::{MemoryConfig, MemoryStore};
+	use crate::sized::serialized_len;
+	#[cfg(feature = "anonymize")]
+	use crate::Anonymizer;
+	use crate::{
+		Attachment, DataStore, DataStoreExt, DownSampling, DropReason, Equivalent, Evicted,
+		FifoEviction, JsonPointer, TransientError,
+	};
+	use chrono::{Duration, Utc};
+	use serde_json::{json, Value};
+	use std::collections::HashSet;
+	use std::io::Result;
+	use std::sync::{Arc, Mutex};
+
+	#[test]
+	fn test_basic_operations() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 1000,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+
+		// Test empty state
+		assert!(!store.has_data());
+
+		// Test append
+		let event = json!({"event": "test", "value": 123});
+		store.append(event.clone())?;
+		assert!(store.has_data());
+
+		// Test fetch - data should still be there after fetch
+		if let Some(result) = store.fetch(None, None)? {
+			let batch: Value = result.data.unwrap();
+			let items = batch["batch"].as_array().unwrap();
+			assert_eq!(items.len(), 1);
+			assert_eq!(items[0]["value"], 123);
+
+			// Verify items are still in store after fetch
+			assert!(store.has_data());
+
+			// Now remove the items
+			if let Some(removable) = result.removable {
+				store.remove(&removable)?;
+				// Verify items were removed
+				assert!(!store.has_data());
+			} else {
+				panic!("Expected removable items but got none");
+			}
+		} else {
+			panic!("Expected data but got none");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_fifo_behavior() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 3, // Small limit to test FIFO
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+
+		// Add more items than max_items
+		for i in 0..5 {
+			store.append(json!({"index": i}))?;
+		}
+
+		// Should only have last 3 items
+		assert!(store.has_data());
+
+		// Verify they're the right items (2,3,4)
+		if let Some(result) = store.fetch(None, None)? {
+			let batch: Value = result.data.unwrap();
+			let items = batch["batch"].as_array().unwrap();
+			assert_eq!(items.len(), 3);
+			assert_eq!(items[0]["index"], 2);
+			assert_eq!(items[1]["index"], 3);
+			assert_eq!(items[2]["index"], 4);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_limits() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1000,
+		};
+
+		let mut store = MemoryStore::new(config);
+
+		// Add items with predictable sizes
+		for i in 0..10 {
+			let padding = "x".repeat(50); // Each item will be roughly ~70 bytes
+			store.append(json!({
+				"index": i,
+				"padding": padding
+			}))?;
+		}
+
+		// Test count limit
+		if let Some(result) = store.fetch(Some(3), None)? {
+			let batch: Value = result.data.unwrap();
+			let items = batch["batch"].as_array().unwrap();
+			assert_eq!(items.len(), 3, "Count limit not respected");
+		}
+
+		// Add more items for size limit test
+		for i in 0..10 {
+			let padding = "x".repeat(50);
+			store.append(json!({
+				"index": i,
+				"padding": padding
+			}))?;
+		}
+
+		// Test byte limit (200 bytes should get us about 2-3 items)
+		if let Some(result) = store.fetch(None, Some(200))? {
+			let items = result.data.unwrap();
+			let items = items["batch"].as_array().unwrap();
+			assert!(items.len() <= 3, "Too many items for byte limit");
+
+			// Each raw item should be under the limit
+			let total_raw_size: usize = items.iter().map(serialized_len).sum();
+			assert!(total_raw_size <= 200, "Raw items exceed byte limit");
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_reports_remaining() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1000,
+		});
+		store.set_allowed_categories(Some(HashSet::from(["analytics".to_string()])))?;
+		for i in 0..5 {
+			store.append(json!({"index": i}))?;
+		}
+		// Not fetchable, so not remaining either
+		store.append(json!({"index": 5, "_consent": "ads"}))?;
+
+		let result = store.fetch(Some(2), None)?.unwrap();
+		assert_eq!(result.remaining_items, 3);
+		let expected: usize = (2..5).map(|i| serialized_len(&json!({"index": i}))).sum();
+		assert_eq!(result.remaining_bytes, expected as u64);
+
+		store.remove(&result.removable.unwrap())?;
+		let result = store.fetch(None, None)?.unwrap();
+		assert_eq!((result.remaining_items, result.remaining_bytes), (0, 0));
+		Ok(())
+	}
+
+	#[test]
+	fn test_interrupted_moves_are_recovered() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 10,
+			max_fetch_size: 1000,
+		};
+		let fetched = |store: &mut MemoryStore| -> Result<Vec<Value>> {
+			Ok(store
+				.fetch(None, None)?
+				.and_then(|result| result.data)
+				.map(|batch| batch["batch"].as_array().cloned().unwrap_or_default())
+				.unwrap_or_default())
+		};
+		let mut src = MemoryStore::new(config.clone());
+		let mut dst = MemoryStore::new(config);
+		for n in 0..4 {
+			src.append(json!({"n": n}))?;
+		}
+
+		// Died after the copies were staged: no event is visible twice
+		let copies = src.begin_move("move-1", 2)?;
+		assert_eq!(copies, vec![json!({"n": 0}), json!({"n": 1})]);
+		dst.stage_move("move-1", copies)?;
+		assert_eq!(fetched(&mut src)?, vec![json!({"n": 2}), json!({"n": 3})]);
+		assert!(fetched(&mut dst)?.is_empty());
+
+		// Died before anything reached the destination
+		src.begin_move("move-2", 1)?;
+		assert_eq!(src.held_moves()?, vec!["move-1", "move-2"]);
+
+		assert_eq!(crate::recover_moves(&mut src, &mut dst)?, 2);
+		assert_eq!(fetched(&mut src)?, vec![json!({"n": 2}), json!({"n": 3})]);
+		assert_eq!(fetched(&mut dst)?, vec![json!({"n": 0}), json!({"n": 1})]);
+		assert!(src.held_moves()?.is_empty());
+		assert!(dst.held_moves()?.is_empty());
+
+		// A move that completes
+		assert_eq!(crate::move_events(&mut src, &mut dst, 5)?, 2);
+		assert_eq!(dst.pending_events()?.len(), 4);
+		assert!(!src.has_data());
+		Ok(())
+	}
+
+	#[test]
+	fn test_drop_report_records_evictions() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 3,
+			max_fetch_size: 1000,
+		});
+		let start = Utc::now();
+		for n in 0..5 {
+			store.append(json!({"n": n}))?;
+		}
+		store.append_many((5..8).map(|n| json!({"n": n})).collect())?;
+
+		let report = store.drop_report()?;
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].reason, DropReason::Capacity);
+		assert_eq!(report[0].count, 5);
+		assert!(report[0].first >= start && report[0].first <= report[0].last);
+
+		// Reported drops aren't reported again
+		assert!(store.drop_report()?.is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn test_eviction_listener_sees_dropped_events() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 2,
+			max_fetch_size: 1000,
+		});
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let listened = evicted.clone();
+		store.set_eviction_listener(
+			move |dropped: Evicted<'_>| {
+				let events: Vec<Value> = dropped.events.to_vec();
+				listened
+					.lock()
+					.unwrap()
+					.push((dropped.reason, events, dropped.count));
+			},
+			2,
+		);
+
+		store.append_many((0..5).map(|n| json!({"n": n})).collect())?;
+		store.append(json!({"n": 5}))?;
+
+		// At most two events are passed per drop, but all are counted
+		let evicted = evicted.lock().unwrap();
+		assert_eq!(
+			*evicted,
+			[
+				(
+					DropReason::Capacity,
+					vec![json!({"n": 0}), json!({"n": 1})],
+					3
+				),
+				(DropReason::Capacity, vec![json!({"n": 3})], 1),
+			]
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_down_sampling_drops_low_priority_events_under_pressure() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1000,
+		});
+		store.set_down_sampling(Some(DownSampling {
+			threshold: 3,
+			sustain: std::time::Duration::ZERO,
+			type_key: JsonPointer::new("/event")?,
+			low_priority: vec!["scroll".into()],
+		}));
+		for event in [
+			"scroll", "click", "scroll", "scroll", "click", "scroll", "scroll",
+		] {
+			store.append(json!({"event": event}))?;
+		}
+
+		// Under the threshold everything is queued, then only the first scroll is kept
+		let events = store.pending_events()?;
+		let kinds: Vec<&str> = events.iter().filter_map(|e| e["event"].as_str()).collect();
+		assert_eq!(kinds, ["scroll", "click", "scroll", "scroll", "click"]);
+
+		let report = store.drop_report()?;
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].reason, DropReason::Sampled);
+		assert_eq!(report[0].count, 2);
+		Ok(())
+	}
+
+	#[test]
+	fn test_pinned_items_are_not_evicted() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 3,
+			max_fetch_size: 1000,
+		});
+		store.set_max_pinned(2);
+		store.append(json!({"n": 0}))?;
+		store.append_pinned(json!({"n": 1}))?;
+		for n in 2..6 {
+			store.append(json!({"n": n}))?;
+		}
+		let pending = store.pending_events()?;
+		assert_eq!(pending[0], json!({"n": 1, "_pinned": true}));
+		assert_eq!(pending[1..], [json!({"n": 4}), json!({"n": 5})]);
+
+		store.append_pinned(json!({"n": 6}))?;
+		let err = store.append_pinned(json!({"n": 7})).unwrap_err();
+		assert_eq!(
+			TransientError::from_io(&err),
+			Some(&TransientError::TooManyPinned { limit: 2 })
+		);
+		assert!(store.append_pinned(json!("not an object")).is_err());
+
+		// Even with a policy, pins are never candidates
+		store.set_eviction_policy(FifoEviction);
+		store.append(json!({"n": 8}))?;
+		store.append(json!({"n": 9}))?;
+		let pending: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|e| e["n"].clone())
+			.collect();
+		assert_eq!(pending, vec![json!(1), json!(6), json!(9)]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_expire_older_than_keeps_recent_and_pinned_items() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 10,
+			max_fetch_size: 1000,
+		});
+		let expired = Arc::new(Mutex::new(Vec::new()));
+		let listened = expired.clone();
+		store.set_eviction_listener(
+			move |dropped: Evicted<'_>| {
+				listened
+					.lock()
+					.unwrap()
+					.push((dropped.reason, dropped.count));
+			},
+			10,
+		);
+		store.append(json!({"n": 0}))?;
+		store.append_pinned(json!({"n": 1}))?;
+		store.append(json!({"n": 2}))?;
+
+		assert_eq!(
+			store.expire_older_than(std::time::Duration::from_secs(3600))?,
+			0
+		);
+		assert_eq!(store.expire_older_than(std::time::Duration::ZERO)?, 2);
+		assert_eq!(store.pending_events()?, [json!({"n": 1, "_pinned": true})]);
+
+		let report = store.drop_report()?;
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].reason, DropReason::Expired);
+		assert_eq!(report[0].count, 2);
+		assert_eq!(*expired.lock().unwrap(), [(DropReason::Expired, 2)]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_reset() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1000,
+		};
+
+		let mut store = MemoryStore::new(config);
+
+		// Add some items
+		for i in 0..5 {
+			store.append(json!({"index": i}))?;
+		}
+		// we have 5 items
+		assert!(store.has_data());
+
+		// Reset and verify
+		store.reset();
+		assert!(!store.has_data());
+
+		Ok(())
+	}
+
+	#[test]
 	fn test_memory_store_max_fetch_size_edge_cases() -> Result<()> {
 		let config = MemoryConfig {
 			write_key: "test-key".to_string(),
@@ -417,6 +1576,620 @@ mod tests {
 		Ok(())
 	}
 
+	#[test]
+	fn test_partial_removal_keeps_rest_queued() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+		store.append(json!({"event": "a"}))?;
+		store.append(json!({"event": "rejected"}))?;
+		store.append(json!({"event": "c"}))?;
+		// Identical to the fetched one, but not part of the fetch
+		store.append(json!({"event": "rejected"}))?;
+
+		let result = store.fetch(Some(3), None)?.unwrap();
+		let removable = result.removable.unwrap();
+
+		// The server rejected the second event - drop just that one
+		store.remove(&removable[1..2])?;
+
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		let events: Vec<&str> = batch["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|item| item["event"].as_str().unwrap())
+			.collect();
+		assert_eq!(events, vec!["a", "c", "rejected"]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_removables_share_fetched_items() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+		store.append(json!({"event": "a"}))?;
+		store.append(json!({"event": "b"}))?;
+
+		let removable = store.fetch(None, None)?.unwrap().removable.unwrap();
+		let shared = removable[0].as_any().downcast_ref::<Arc<Value>>().unwrap();
+		assert!(Arc::ptr_eq(shared, store.items[0].shared()));
+
+		// Plain values still remove matching items
+		store.remove(&removable[..1])?;
+		store.remove(&[Box::new(json!({"event": "b"})) as Box<dyn Equivalent>])?;
+		assert!(!store.has_data());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_attachments() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+		let screenshot = Attachment {
+			name: "screen.png".to_string(),
+			content_type: "image/png".to_string(),
+			data: vec![1, 2, 3],
+		};
+		store.append_with_attachments(json!({"event": "crash"}), vec![screenshot])?;
+		store.append(json!({"event": "plain"}))?;
+
+		let result = store.fetch(None, None)?.unwrap();
+		let batch = result.data.unwrap();
+		let reference = &batch["batch"][0]["_attachments"][0];
+		assert_eq!(reference["name"], "screen.png");
+		assert_eq!(reference["size"], 3);
+
+		let handles = result.attachments.unwrap();
+		assert_eq!(handles.len(), 1);
+		assert_eq!(handles[0].id, reference["id"].as_str().unwrap());
+		assert_eq!(handles[0].content.read()?, vec![1, 2, 3]);
+
+		// Attachments go away with their events
+		store.remove(&result.removable.unwrap())?;
+		assert!(store.attachments.is_empty());
+
+		// Only objects can carry attachments
+		assert!(store
+			.append_with_attachments(json!("text"), vec![])
+			.is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_delivered_batches_are_not_re_added() -> Result<()> {
+		let config = MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = MemoryStore::new(config);
+		store.append(json!({"event": "test"}))?;
+
+		let result = store.fetch(None, None)?.unwrap();
+		let batch_id = result.batch_id.clone().unwrap();
+		let envelope = result.data.unwrap();
+		assert_eq!(envelope["batchId"], batch_id.as_str());
+
+		// Upload succeeded - mark delivered and clean up
+		store.mark_delivered(&batch_id)?;
+		store.remove(&result.removable.unwrap())?;
+		assert!(store.is_delivered(&batch_id));
+
+		// Re-adding the delivered envelope is ignored
+		store.append(envelope)?;
+		assert!(!store.has_data());
+
+		// Each fetch gets its own id
+		store.append(json!({"event": "next"}))?;
+		let next = store.fetch(None, None)?.unwrap();
+		assert_ne!(next.batch_id.unwrap(), batch_id);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_retried_fetches_keep_their_batch_id() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.append(json!({"n": 0}))?;
+		store.append(json!({"n": 1}))?;
+
+		// The upload failed, so the same items are fetched again
+		let first = store.fetch(Some(1), None)?.unwrap();
+		let retried = store.fetch(Some(1), None)?.unwrap();
+		assert_eq!(retried.batch_id, first.batch_id);
+		assert_eq!(
+			retried.data.unwrap()["batchId"],
+			first.batch_id.as_deref().unwrap()
+		);
+
+		// Other items are another batch
+		let both = store.fetch(None, None)?.unwrap();
+		assert_ne!(both.batch_id, first.batch_id);
+
+		store.remove(&first.removable.unwrap())?;
+		let rest = store.fetch(None, None)?.unwrap();
+		assert_ne!(rest.batch_id, first.batch_id);
+		assert_ne!(rest.batch_id, both.batch_id);
+		Ok(())
+	}
+
+	#[test]
+	fn test_consent_categories() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.append(json!({"event": "page_view", "_consent": "analytics"}))?;
+		store.append(json!({"event": "ad_click", "_consent": "advertising"}))?;
+		store.append(json!({"event": "untagged"}))?;
+
+		let events = |store: &mut MemoryStore| -> Result<Vec<Value>> {
+			Ok(match store.fetch(None, None)? {
+				Some(result) => result.data.unwrap()["batch"]
+					.as_array()
+					.unwrap()
+					.iter()
+					.map(|e| e["event"].clone())
+					.collect(),
+				None => Vec::new(),
+			})
+		};
+
+		// Revoked events stay queued but aren't fetched
+		store.set_allowed_categories(Some(HashSet::from(["analytics".to_string()])))?;
+		assert_eq!(events(&mut store)?, vec!["page_view", "untagged"]);
+		assert_eq!(
+			store.debug_config()["allowedCategories"],
+			json!(["analytics"])
+		);
+
+		// Until consent is granted again
+		store.set_allowed_categories(None)?;
+		assert_eq!(events(&mut store)?.len(), 3);
+
+		store.set_allowed_categories(Some(HashSet::new()))?;
+		assert_eq!(store.purge_revoked()?, 2);
+		store.set_allowed_categories(None)?;
+		assert_eq!(events(&mut store)?, vec!["untagged"]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_has_data_ignores_events_without_consent() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.append(json!({"event": "ad_click", "_consent": "advertising"}))?;
+		assert!(store.has_data());
+
+		store.set_allowed_categories(Some(HashSet::from(["analytics".to_string()])))?;
+		assert!(!store.has_data());
+		assert!(store.fetch(None, None)?.is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn test_append_for_other_write_keys() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.append_for("partner", json!({"index": 0}))?;
+		store.append(json!({"index": 1}))?;
+		store.append_for("partner", json!({"index": 2}))?;
+		store.append_for("test-key", json!({"index": 3}))?;
+		assert!(store.append_for("partner", json!("not an object")).is_err());
+
+		// Each batch holds one write key, starting with the oldest item's
+		let mut batches = Vec::new();
+		while let Some(result) = store.fetch(None, None)? {
+			let preview = store.preview_fetch(None, None)?;
+			let batch = result.data.unwrap();
+			let indices: Vec<Value> = batch["batch"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|e| e["index"].clone())
+				.collect();
+			assert_eq!(preview.items, indices.len());
+			assert_eq!(
+				result.remaining_items,
+				4 - indices.len() - batches.len() * 2
+			);
+			batches.push((batch["writeKey"].clone(), indices));
+			store.remove(&result.removable.unwrap())?;
+		}
+		assert_eq!(
+			batches,
+			vec![
+				(json!("partner"), vec![json!(0), json!(2)]),
+				(json!("test-key"), vec![json!(1), json!(3)]),
+			]
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_upsert_replaces_by_key() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.upsert("traits", json!({"plan": "free"}))?;
+		store.upsert("device", json!({"push": false}))?;
+		store.append(json!({"event": "click"}))?;
+		store.append(json!({"event": "click"}))?;
+		store.upsert("traits", json!({"plan": "pro"}))?;
+		assert!(store.upsert("traits", json!(null)).is_err());
+
+		let events = store.pending_events()?;
+		assert_eq!(events.len(), 4);
+		assert_eq!(events[0]["push"], false);
+		assert_eq!(events[3]["plan"], "pro");
+
+		// A state replaced while its batch is being sent stays queued once it's removed
+		let result = store.fetch(None, None)?.unwrap();
+		store.upsert("traits", json!({"plan": "team"}))?;
+		store.remove(&result.removable.unwrap())?;
+		let events = store.pending_events()?;
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0]["plan"], "team");
+		assert_eq!(store.enqueue_times()?.len(), 1);
+		Ok(())
+	}
+
+	#[test]
+	fn test_delayed_items_are_fetched_once_due() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		let now = Utc::now();
+		store.append_delayed(json!({"index": 0}), now + Duration::hours(1))?;
+		store.append(json!({"index": 1}))?;
+		store.append_delayed(json!({"index": 2}), now - Duration::seconds(1))?;
+		assert!(store
+			.append_delayed(json!([]), now + Duration::hours(1))
+			.is_err());
+
+		let result = store.fetch(None, None)?.unwrap();
+		let batch = result.data.unwrap();
+		let indices: Vec<&Value> = batch["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|e| &e["index"])
+			.collect();
+		assert_eq!(indices, vec![&json!(1), &json!(2)]);
+		assert_eq!(result.remaining_items, 0);
+		assert_eq!(store.preview_fetch(None, None)?.items, 2);
+		store.remove(&result.removable.unwrap())?;
+
+		// The delayed item stays queued, but isn't data to fetch yet
+		assert!(!store.has_data());
+		assert!(store.fetch(None, None)?.is_none());
+		assert_eq!(store.pending_events()?[0]["index"], 0);
+		Ok(())
+	}
+
+	#[cfg(feature = "anonymize")]
+	#[test]
+	fn test_anonymize() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		store.append(json!({"event": "a", "userId": "u-1"}))?;
+		store.append(json!({"event": "b"}))?;
+		store.append(json!({"event": "c", "userId": "u-1"}))?;
+
+		let anonymizer = Anonymizer::with_salt(&[JsonPointer::new("/userId")?], b"salt".to_vec());
+		assert_eq!(store.anonymize(&anonymizer)?, 2);
+
+		let events = store.pending_events()?;
+		assert_eq!(events[0]["userId"], events[2]["userId"]);
+		assert_ne!(events[0]["userId"], "u-1");
+		assert_eq!(events[1], json!({"event": "b"}));
+		Ok(())
+	}
+
+	#[test]
+	fn test_append_many() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 5,
+			max_fetch_size: 1024,
+		});
+		store.append(json!({"index": 0}))?;
+		store.append_many((1..8).map(|i| json!({"index": i})).collect())?;
+
+		// The oldest items are evicted, as if appended one by one
+		let indices: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|e| e["index"].clone())
+			.collect();
+		assert_eq!(indices, (3..8).map(|i| json!(i)).collect::<Vec<_>>());
+		assert_eq!(store.enqueue_times()?.len(), 5);
+		Ok(())
+	}
+
+	#[test]
+	fn test_reserved_items_are_committed_without_eviction() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 3,
+			max_fetch_size: 1024,
+		});
+		for i in 0..3 {
+			store.append(json!({"index": i}))?;
+		}
+
+		// Reserving makes room up front
+		store.reserve(16)?;
+		assert_eq!(store.items.len(), 2);
+		// Appends leave the room alone
+		store.append(json!({"index": 3}))?;
+		assert_eq!(store.items.len(), 2);
+
+		store.commit_reserved(json!({"index": 4}), 16)?;
+		let indices: Vec<Value> = store
+			.pending_events()?
+			.iter()
+			.map(|e| e["index"].clone())
+			.collect();
+		assert_eq!(indices, vec![json!(2), json!(3), json!(4)]);
+
+		// Released room is used by appends again
+		store.reserve(16)?;
+		store.release_reserved(16);
+		store.append(json!({"index": 5}))?;
+		assert_eq!(store.items.len(), 3);
+
+		// Reserving more than max_items keeps the newest item
+		for _ in 0..5 {
+			store.reserve(16)?;
+		}
+		assert_eq!(store.pending_events()?, vec![json!({"index": 5})]);
+
+		// Reset drops the reservations with the items
+		store.reset();
+		for i in 0..3 {
+			store.append(json!({"index": i}))?;
+		}
+		assert_eq!(store.items.len(), 3);
+		Ok(())
+	}
+
+	#[test]
+	fn test_preview_fetch_matches_fetch() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		assert_eq!(store.preview_fetch(None, None)?.items, 0);
+		for i in 0..10 {
+			store.append(json!({"index": i}))?;
+		}
+
+		for (count, max_bytes) in [(None, None), (Some(4), None), (None, Some(50))] {
+			let preview = store.preview_fetch(count, max_bytes)?;
+			let result = store.fetch(count, max_bytes)?.unwrap();
+			let batch = result.data.unwrap();
+			let items = batch["batch"].as_array().unwrap();
+			assert_eq!(preview.items, items.len());
+			assert_eq!(
+				preview.bytes,
+				items
+					.iter()
+					.map(|i| i.to_string().len() as u64)
+					.sum::<u64>()
+			);
+			assert_eq!(preview.oldest, store.enqueued.front().copied());
+			assert_eq!(preview.newest, store.enqueued.get(items.len() - 1).copied());
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_recently_removed_keeps_the_last_batches() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024 * 1024,
+		});
+
+		// Nothing is retained until enabled
+		store.append(json!({"n": 0}))?;
+		let result = store.fetch(None, None)?.unwrap();
+		store.remove(&result.removable.unwrap())?;
+		assert!(store.recently_removed()?.is_empty());
+
+		store.set_retain_removed(2);
+		for n in 1..4 {
+			store.append(json!({"n": n}))?;
+			store.append(json!({"n": n * 10}))?;
+			let result = store.fetch(Some(1), None)?.unwrap();
+			store.remove(&result.removable.unwrap())?;
+		}
+
+		let removed = store.recently_removed()?;
+		assert_eq!(removed.len(), 2);
+		assert_eq!(removed[0].events, vec![json!({"n": 10})]);
+		assert_eq!(removed[1].events, vec![json!({"n": 2})]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_replay_requeues_a_removed_batch() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024 * 1024,
+		});
+		store.set_retain_removed(5);
+
+		store.append(json!({"n": 1}))?;
+		store.append(json!({"n": 2}))?;
+		let lost = store.fetch(Some(1), None)?.unwrap();
+		let lost_id = lost.batch_id.unwrap();
+		store.remove(&lost.removable.unwrap())?;
+		assert_eq!(
+			store.recently_removed()?[0].batch_id.as_deref(),
+			Some(lost_id.as_str())
+		);
+		assert_eq!(
+			store.replay("unknown").unwrap_err().kind(),
+			std::io::ErrorKind::NotFound
+		);
+
+		// The replay queues behind the events already waiting, in an envelope of its own
+		// once those are delivered
+		assert_eq!(store.replay(&lost_id)?, 1);
+		let next = store.fetch(Some(1), None)?.unwrap();
+		let envelope = next.data.unwrap();
+		assert_eq!(envelope["batch"], json!([{"n": 2}]));
+		assert!(envelope.get("replay").is_none());
+		store.remove(&next.removable.unwrap())?;
+
+		let replayed = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(replayed["replay"], true);
+		assert_eq!(replayed["batch"], json!([{"n": 1, "_replayOf": lost_id}]));
+		Ok(())
+	}
+
+	#[test]
+	fn test_write_batch_to_streams_the_fetched_envelope() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024 * 1024,
+		});
+		store.set_json_format(crate::JsonFormat::Canonical);
+		for i in 0..3 {
+			store.append(json!({"n": i, "b": 1, "a": 2}))?;
+		}
+
+		let fetched = store.fetch(Some(2), None)?.unwrap();
+		let mut out = Vec::new();
+		let written = store.write_batch_to(&mut out, Some(2), None)?.unwrap();
+		assert_eq!(written.data, Some(out.len() as u64));
+		assert_eq!(written.remaining_items, fetched.remaining_items);
+		assert_eq!(written.removable.as_ref().unwrap().len(), 2);
+
+		// The same envelope as fetched, apart from its batch id and send time
+		let mut streamed: Value = serde_json::from_slice(&out).unwrap();
+		let mut expected = fetched.data.unwrap();
+		assert_eq!(streamed["batchId"], written.batch_id.unwrap().as_str());
+		for envelope in [&mut streamed, &mut expected] {
+			let fields = envelope.as_object_mut().unwrap();
+			fields.remove("batchId");
+			fields.remove("sentAt");
+		}
+		assert_eq!(streamed, expected);
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.starts_with(r#"{"batch":[{"a":2,"b":1,"n":0}"#));
+
+		store.remove(&written.removable.unwrap())?;
+		assert_eq!(store.pending_size()?.items, 1);
+		Ok(())
+	}
+
+	#[test]
+	fn test_fetch_older_than_takes_only_old_items() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024 * 1024,
+		});
+		store.append(json!({"n": 0}))?;
+		store.append_for("other-key", json!({"n": 1}))?;
+		store.append(json!({"n": 2}))?;
+		// Pretend the first three items have been waiting a minute
+		for enqueued in store.enqueued.iter_mut() {
+			*enqueued -= Duration::minutes(1);
+		}
+		store.append(json!({"n": 3}))?;
+
+		let age = std::time::Duration::from_secs(30);
+		let result = store.fetch_older_than(age)?.unwrap();
+		let batch = result.data.unwrap();
+		assert_eq!(batch["batch"], json!([{"n": 0}, {"n": 2}]));
+		assert_eq!(result.remaining_items, 2);
+		store.remove(&result.removable.unwrap())?;
+
+		let other = store.fetch_older_than(age)?.unwrap();
+		let batch = other.data.unwrap();
+		assert_eq!(batch["writeKey"], "other-key");
+		assert_eq!(batch["batch"][0]["n"], 1);
+		store.remove(&other.removable.unwrap())?;
+
+		// The last item is too recent
+		assert!(store.fetch_older_than(age)?.is_none());
+		assert!(store.fetch_older_than(std::time::Duration::ZERO)?.is_some());
+		Ok(())
+	}
+
+	#[test]
+	fn test_received_at_is_stamped_on_events_without_a_timestamp() -> Result<()> {
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024 * 1024,
+		});
+		store.append(json!({"n": 0}))?;
+		store.set_received_at_field(Some("receivedAt"));
+		store.append(json!({"n": 1}))?;
+		store.append(json!({"n": 2, "timestamp": "2024-05-01T12:00:00Z"}))?;
+		store.append_many(vec![json!({"n": 3})])?;
+		store.set_received_at_field(None);
+		store.append(json!({"n": 4}))?;
+
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		let stamped: Vec<bool> = batch["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|event| event.get("receivedAt").is_some())
+			.collect();
+		assert_eq!(stamped, vec![false, true, false, true, false]);
+		let received_at = batch["batch"][1]["receivedAt"].as_str().unwrap();
+		assert!(chrono::DateTime::parse_from_rfc3339(received_at).is_ok());
+		Ok(())
+	}
+
 	#[test]
 	#[should_panic(
 		expected = "max_fetch_size < 100 bytes? What are you even trying to fetch, empty arrays?"
@@ -0,0 +1,138 @@
+//! Moving events between stores, e.g. spilling a memory queue over to disk.
+//!
+//! A move is made in two phases. The events are first held in both stores under the
+//! move's id, in a `_moveId` field: the source hides the originals and the destination
+//! queues hidden copies. Only then does the source discard its originals, after which
+//! the destination releases the copies. Held events are never fetched, so an event is
+//! never visible in both stores, and one of them always has it, whenever the process
+//! dies; [`recover_moves`] finishes or rolls back moves that were cut short.
+
+use crate::delivery::new_uuid;
+use crate::DataStoreExt;
+use serde_json::Value;
+use std::io::{self, Result};
+
+/// Key of the move id added to held events.
+pub(crate) const MOVE_KEY: &str = "_moveId";
+
+/// Holds an event under a move.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the id.
+pub(crate) fn tag(data: &mut Value, move_id: &str) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can be moved",
+		)
+	})?;
+	object.insert(MOVE_KEY.to_string(), Value::from(move_id));
+	Ok(())
+}
+
+/// Releases an event from its move. Returns false if it wasn't held.
+pub(crate) fn untag(data: &mut Value) -> bool {
+	data.as_object_mut()
+		.and_then(|object| object.remove(MOVE_KEY))
+		.is_some()
+}
+
+/// Id of the move an event is held under, if any.
+pub(crate) fn move_id(event: &Value) -> Option<&str> {
+	event.get(MOVE_KEY).and_then(Value::as_str)
+}
+
+/// Whether an event is held under a move.
+pub(crate) fn is_held(event: &Value) -> bool {
+	event.get(MOVE_KEY).is_some()
+}
+
+/// Moves up to `count` of the oldest fetchable events from `src` to the end of `dst`,
+/// returning how many were moved.
+///
+/// Events are held in both stores while they move, hidden from fetches, so they are
+/// never visible in both or in neither. If this fails partway, or the process dies,
+/// call [`recover_moves`] with the same stores to finish or roll back the move. Events
+/// with attachments stay in `src`.
+///
+/// `src` must support [`DataStoreExt::begin_move`] (MemoryStore and WebStore do) and `dst`
+/// [`DataStoreExt::stage_move`] (MemoryStore, WebStore and DirectoryStore). Don't move
+/// events while a batch fetched from `src` is still being uploaded, since they may be
+/// among the ones moved.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{move_events, DataStore, MemoryConfig, MemoryStore};
+///
+/// let config = MemoryConfig {
+///     write_key: "my-key".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// };
+/// let mut hot = MemoryStore::new(config.clone());
+/// let mut cold = MemoryStore::new(config);
+///
+/// for i in 0..5 {
+///     hot.append(json!({"event": "tick", "n": i}))?;
+/// }
+/// assert_eq!(move_events(&mut hot, &mut cold, 3)?, 3);
+///
+/// let batch = cold.fetch(None, None)?.unwrap().data.unwrap();
+/// assert_eq!(batch["batch"].as_array().unwrap().len(), 3);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn move_events<S, D>(src: &mut S, dst: &mut D, count: usize) -> Result<usize>
+where
+	S: DataStoreExt + ?Sized,
+	D: DataStoreExt + ?Sized,
+{
+	let move_id = new_uuid();
+	let events = src.begin_move(&move_id, count)?;
+	if events.is_empty() {
+		return Ok(0);
+	}
+	let moved = events.len();
+
+	if let Err(e) = dst.stage_move(&move_id, events) {
+		// Roll back: whatever reached `dst` goes, then the originals come back. Should
+		// that fail, both stores still hold the move for `recover_moves`.
+		if dst.discard_move(&move_id).is_ok() {
+			src.release_move(&move_id)?;
+		}
+		return Err(e);
+	}
+	src.discard_move(&move_id)?;
+	dst.release_move(&move_id)?;
+	Ok(moved)
+}
+
+/// Finishes or rolls back moves between `src` and `dst` that [`move_events`] didn't
+/// complete, returning how many it resolved.
+///
+/// Call it after a failed move, and on startup before using stores events are moved
+/// between. A move held by both stores is finished; one held by a single store is
+/// released there, which rolls it back if the events never reached `dst`. Either way,
+/// each event ends up visible in exactly one store.
+pub fn recover_moves<S, D>(src: &mut S, dst: &mut D) -> Result<usize>
+where
+	S: DataStoreExt + ?Sized,
+	D: DataStoreExt + ?Sized,
+{
+	let in_src = src.held_moves()?;
+	let in_dst = dst.held_moves()?;
+
+	for move_id in &in_src {
+		if in_dst.contains(move_id) {
+			src.discard_move(move_id)?;
+			dst.release_move(move_id)?;
+		} else {
+			src.release_move(move_id)?;
+		}
+	}
+	let mut resolved = in_src.len();
+	for move_id in in_dst.iter().filter(|id| !in_src.contains(id)) {
+		dst.release_move(move_id)?;
+		resolved += 1;
+	}
+	Ok(resolved)
+}
@@ -0,0 +1,377 @@
+//! Platform-specific file system handling for DirectoryStore.
+//!
+//! Windows needs extra care: paths longer than `MAX_PATH` need the extended-length
+//! `\\?\` prefix, device names like `CON` or `NUL` can't be used as file or directory
+//! names, and files held open by another process (antivirus, indexers, backup agents)
+//! briefly fail to delete or rename with a sharing violation.
+
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+#[cfg(any(windows, test))]
+use std::io;
+
+/// Device names Windows reserves in every directory, with or without an extension.
+#[cfg(any(windows, test))]
+const RESERVED_NAMES: &[&str] = &[
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+	"COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks whether a path component is a reserved Windows device name, such as `nul` or `COM1.log`.
+#[cfg(any(windows, test))]
+fn is_reserved_name(name: &str) -> bool {
+	let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+	RESERVED_NAMES
+		.iter()
+		.any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Fails with `InvalidInput` if the name is a reserved Windows device name.
+#[cfg(any(windows, test))]
+fn check_name(name: &str) -> Result<()> {
+	if is_reserved_name(name) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("'{}' is a reserved device name on Windows", name),
+		));
+	}
+	Ok(())
+}
+
+/// Adds the extended-length prefix to an absolute Windows path, so it can exceed `MAX_PATH`.
+#[cfg(any(windows, test))]
+fn extended_length(path: &str) -> String {
+	if path.starts_with(r"\\?\") {
+		path.to_string()
+	} else if let Some(share) = path.strip_prefix(r"\\") {
+		format!(r"\\?\UNC\{}", share)
+	} else {
+		format!(r"\\?\{}", path)
+	}
+}
+
+/// Validates and normalizes the storage location and base file name for this platform.
+///
+/// On Windows, rejects reserved device names in the directory components and base name,
+/// and returns the location as an absolute extended-length path. Elsewhere the location
+/// is returned unchanged.
+#[cfg(windows)]
+pub(crate) fn prepare_location(location: &Path, base_filename: &str) -> Result<PathBuf> {
+	use std::path::Component;
+
+	check_name(base_filename)?;
+	for component in location.components() {
+		if let Component::Normal(name) = component {
+			check_name(&name.to_string_lossy())?;
+		}
+	}
+
+	let absolute = std::path::absolute(location)?;
+	Ok(PathBuf::from(extended_length(&absolute.to_string_lossy())))
+}
+
+#[cfg(not(windows))]
+pub(crate) fn prepare_location(location: &Path, _base_filename: &str) -> Result<PathBuf> {
+	Ok(location.to_path_buf())
+}
+
+/// Checks whether a path is a symbolic link, without following it.
+pub(crate) fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Restricts a store directory to its owner.
+///
+/// Fails if the directory is a symbolic link or, on Unix, isn't owned by the current
+/// user; otherwise sets its permissions to `0700`. Other platforms only get the
+/// symbolic link check, as directories there inherit their parent's access control.
+pub(crate) fn restrict_directory(path: &Path) -> Result<()> {
+	if is_symlink(path) {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::PermissionDenied,
+			format!("Store directory {:?} is a symbolic link", path),
+		));
+	}
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+		let metadata = fs::metadata(path)?;
+		// SAFETY: geteuid has no preconditions and cannot fail
+		let uid = unsafe { libc::geteuid() };
+		if metadata.uid() != uid {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::PermissionDenied,
+				format!(
+					"Store directory {:?} is owned by uid {}, not the current user ({})",
+					path,
+					metadata.uid(),
+					uid
+				),
+			));
+		}
+		fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+	}
+
+	Ok(())
+}
+
+/// Returns the free disk space, in bytes, available to this process on the volume holding `path`.
+///
+/// Returns `None` if it can't be determined on this platform.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+	use std::ffi::CString;
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+	// SAFETY: statvfs is plain old data, and `path` is a valid NUL-terminated string
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+	if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+		return None;
+	}
+	#[allow(clippy::unnecessary_cast)]
+	Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+	use std::os::windows::ffi::OsStrExt;
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetDiskFreeSpaceExW(
+			directory_name: *const u16,
+			free_bytes_available_to_caller: *mut u64,
+			total_number_of_bytes: *mut u64,
+			total_number_of_free_bytes: *mut u64,
+		) -> i32;
+	}
+
+	let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+	let mut available = 0u64;
+	// SAFETY: `wide` is NUL-terminated, and the optional out-pointers may be null
+	let ok = unsafe {
+		GetDiskFreeSpaceExW(
+			wide.as_ptr(),
+			&mut available,
+			std::ptr::null_mut(),
+			std::ptr::null_mut(),
+		)
+	};
+	(ok != 0).then_some(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn available_space(_path: &Path) -> Option<u64> {
+	None
+}
+
+/// A read-only memory mapping of a whole file, unmapped when dropped.
+#[cfg(unix)]
+pub(crate) struct Mmap {
+	ptr: *mut libc::c_void,
+	len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this value alone
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+	pub(crate) fn as_slice(&self) -> &[u8] {
+		// SAFETY: `ptr` points to `len` readable bytes until the mapping is dropped
+		unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+	}
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+	fn drop(&mut self) {
+		// SAFETY: `ptr` and `len` describe a mapping made by `map_file`
+		unsafe {
+			libc::munmap(self.ptr, self.len);
+		}
+	}
+}
+
+/// Maps a whole file into memory read-only, or returns `None` if it's empty, as empty
+/// files can't be mapped.
+///
+/// The mapping outlives the file handle and stays valid after the file is removed or
+/// replaced by a rename, but not if the file is truncated in place.
+///
+/// # Safety
+///
+/// The file must not be truncated while the mapping is alive, as reading past its new
+/// end raises `SIGBUS`.
+#[cfg(unix)]
+pub(crate) unsafe fn map_file(path: &Path) -> Result<Option<Mmap>> {
+	use std::os::unix::io::AsRawFd;
+
+	let file = fs::File::open(path)?;
+	let len = usize::try_from(file.metadata()?.len()).map_err(std::io::Error::other)?;
+	if len == 0 {
+		return Ok(None);
+	}
+	// SAFETY: `file` is open for reading, and a private read-only mapping of it can't
+	// alias any Rust-owned memory
+	let ptr = unsafe {
+		libc::mmap(
+			std::ptr::null_mut(),
+			len,
+			libc::PROT_READ,
+			libc::MAP_PRIVATE,
+			file.as_raw_fd(),
+			0,
+		)
+	};
+	if ptr == libc::MAP_FAILED {
+		return Err(std::io::Error::last_os_error());
+	}
+	let map = Mmap { ptr, len };
+	// Catch a truncation that raced with mapping, before any page is read
+	if file.metadata()?.len() != len as u64 {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			"File changed size while being mapped",
+		));
+	}
+	Ok(Some(map))
+}
+
+/// Marks a store directory with `FILE_ATTRIBUTE_TEMPORARY`, which backup and sync tools
+/// take as a sign its contents needn't be kept.
+#[cfg(windows)]
+pub(crate) fn mark_temporary(path: &Path) -> Result<()> {
+	use std::os::windows::ffi::OsStrExt;
+
+	const FILE_ATTRIBUTE_TEMPORARY: u32 = 0x100;
+	const INVALID_FILE_ATTRIBUTES: u32 = u32::MAX;
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetFileAttributesW(file_name: *const u16) -> u32;
+		fn SetFileAttributesW(file_name: *const u16, attributes: u32) -> i32;
+	}
+
+	let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+	// SAFETY: `wide` is NUL-terminated
+	let attributes = unsafe { GetFileAttributesW(wide.as_ptr()) };
+	if attributes == INVALID_FILE_ATTRIBUTES {
+		return Err(std::io::Error::last_os_error());
+	}
+	// SAFETY: as above
+	if unsafe { SetFileAttributesW(wide.as_ptr(), attributes | FILE_ATTRIBUTE_TEMPORARY) } == 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn mark_temporary(_path: &Path) -> Result<()> {
+	Ok(())
+}
+
+/// Removes a file, retrying briefly if another process has it open.
+pub(crate) fn remove_file(path: &Path) -> Result<()> {
+	retry_on_sharing_violation(|| fs::remove_file(path))
+}
+
+/// Renames a file, retrying briefly if another process has the source or target open.
+pub(crate) fn rename(from: &Path, to: &Path) -> Result<()> {
+	retry_on_sharing_violation(|| fs::rename(from, to))
+}
+
+#[cfg(windows)]
+fn retry_on_sharing_violation(mut op: impl FnMut() -> Result<()>) -> Result<()> {
+	use std::time::Duration;
+
+	// ERROR_ACCESS_DENIED (also reported for delete-pending files),
+	// ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+	const RETRYABLE: &[i32] = &[5, 32, 33];
+	const RETRIES: u32 = 5;
+
+	let mut delay = Duration::from_millis(10);
+	for _ in 0..RETRIES {
+		match op() {
+			Err(e)
+				if e.raw_os_error()
+					.is_some_and(|code| RETRYABLE.contains(&code)) =>
+			{
+				std::thread::sleep(delay);
+				delay *= 2;
+			}
+			result => return result,
+		}
+	}
+	op()
+}
+
+#[cfg(not(windows))]
+fn retry_on_sharing_violation(mut op: impl FnMut() -> Result<()>) -> Result<()> {
+	op()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_name, extended_length, is_reserved_name};
+
+	#[test]
+	fn test_reserved_names() {
+		assert!(is_reserved_name("CON"));
+		assert!(is_reserved_name("nul"));
+		assert!(is_reserved_name("Com1.log"));
+		assert!(is_reserved_name("aux.tar.gz"));
+		assert!(!is_reserved_name("console"));
+		assert!(!is_reserved_name("events"));
+		assert!(!is_reserved_name("COM10"));
+		assert!(check_name("lpt9").is_err());
+	}
+
+	#[test]
+	fn test_extended_length_paths() {
+		assert_eq!(extended_length(r"C:\data\events"), r"\\?\C:\data\events");
+		assert_eq!(
+			extended_length(r"\\server\share\events"),
+			r"\\?\UNC\server\share\events"
+		);
+		assert_eq!(extended_length(r"\\?\C:\data"), r"\\?\C:\data");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_map_file() -> std::io::Result<()> {
+		use super::map_file;
+
+		let dir = tempfile::tempdir()?;
+		let path = dir.path().join("mapped");
+		std::fs::write(&path, b"{\"batch\":[]}")?;
+		// SAFETY: nothing truncates the file while it's mapped
+		let map = unsafe { map_file(&path)? }.unwrap();
+
+		// Still readable once the file is gone
+		std::fs::remove_file(&path)?;
+		assert_eq!(map.as_slice(), b"{\"batch\":[]}");
+
+		std::fs::write(&path, b"")?;
+		// SAFETY: as above
+		assert!(unsafe { map_file(&path)? }.is_none());
+		Ok(())
+	}
+
+	#[cfg(any(unix, windows))]
+	#[test]
+	fn test_available_space() {
+		use super::available_space;
+
+		let dir = std::env::temp_dir();
+		assert!(available_space(&dir).is_some_and(|space| space > 0));
+	}
+}
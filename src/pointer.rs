@@ -0,0 +1,74 @@
+use serde_json::Value;
+use std::fmt;
+use std::io::{self, Result};
+use std::str::FromStr;
+
+/// A JSON Pointer (RFC 6901) naming a field inside an event, e.g. `/user/email` or
+/// `/context/ips/0`.
+///
+/// `~1` stands for a `/` and `~0` for a `~` within a key. The empty pointer, which would
+/// name the whole event, isn't accepted.
+///
+/// # Examples
+/// ```
+/// use transientdb::JsonPointer;
+///
+/// let pointer = JsonPointer::new("/user/email")?;
+/// assert_eq!(pointer.as_str(), "/user/email");
+/// assert!(JsonPointer::new("user/email").is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JsonPointer(String);
+
+impl JsonPointer {
+	/// Parses a pointer, which must start with `/`.
+	pub fn new(pointer: impl Into<String>) -> Result<Self> {
+		let pointer = pointer.into();
+		if !pointer.starts_with('/') {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("JSON pointer must start with '/': {:?}", pointer),
+			));
+		}
+		Ok(Self(pointer))
+	}
+
+	/// Returns the pointer as written.
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+
+	/// Returns the unescaped reference tokens, e.g. `["a/b", "c"]` for `/a~1b/c`.
+	pub(crate) fn tokens(&self) -> Vec<String> {
+		self.0[1..]
+			.split('/')
+			.map(|token| token.replace("~1", "/").replace("~0", "~"))
+			.collect()
+	}
+
+	/// Returns the field the pointer names in `value`, if it exists.
+	pub(crate) fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+		value.pointer(&self.0)
+	}
+
+	/// Returns the field the pointer names in `value`, if it exists.
+	#[cfg(feature = "anonymize")]
+	pub(crate) fn get_mut<'a>(&self, value: &'a mut Value) -> Option<&'a mut Value> {
+		value.pointer_mut(&self.0)
+	}
+}
+
+impl FromStr for JsonPointer {
+	type Err = io::Error;
+
+	fn from_str(pointer: &str) -> Result<Self> {
+		Self::new(pointer)
+	}
+}
+
+impl fmt::Display for JsonPointer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
@@ -0,0 +1,274 @@
+//! Prometheus exporter for queue metrics.
+
+use crate::{QueueStats, TransientDB};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufRead, BufReader, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Exposes a TransientDB's [`stats`](TransientDB::stats) in the Prometheus text format,
+/// either through a node_exporter textfile or a small `/metrics` HTTP endpoint.
+///
+/// Requires the `prometheus` feature.
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use transientdb::{MemoryConfig, MemoryStore, PrometheusExporter, TransientDB};
+///
+/// let db = Arc::new(TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// })));
+///
+/// let exporter = PrometheusExporter::new(db).with_label("queue", "events");
+/// assert!(exporter
+///     .render()
+///     .contains("transientdb_pending_items{queue=\"events\"} 0"));
+/// ```
+pub struct PrometheusExporter<T> {
+	db: Arc<TransientDB<T>>,
+	labels: Vec<(String, String)>,
+}
+
+impl<T> Clone for PrometheusExporter<T> {
+	fn clone(&self) -> Self {
+		Self {
+			db: self.db.clone(),
+			labels: self.labels.clone(),
+		}
+	}
+}
+
+impl<T> PrometheusExporter<T> {
+	/// Creates an exporter for the given database.
+	pub fn new(db: Arc<TransientDB<T>>) -> Self {
+		Self {
+			db,
+			labels: Vec::new(),
+		}
+	}
+
+	/// Adds a label to every exported sample, e.g. to tell several spools apart.
+	pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+		self.labels.push((name.into(), value.into()));
+		self
+	}
+
+	/// Renders the current metrics in the Prometheus text exposition format.
+	pub fn render(&self) -> String {
+		render(&self.db.stats(), &self.labels)
+	}
+
+	/// Writes the current metrics to a file for node_exporter's textfile collector.
+	///
+	/// The file is written next to its destination and renamed into place, so the
+	/// collector never reads a partial file.
+	pub fn write_textfile(&self, path: impl AsRef<Path>) -> Result<()> {
+		let path = path.as_ref();
+		let mut temp = path.as_os_str().to_owned();
+		temp.push(".tmp");
+		fs::write(&temp, self.render())?;
+		fs::rename(&temp, path)
+	}
+}
+
+impl<T: 'static> PrometheusExporter<T> {
+	/// Serves the metrics at `GET /metrics` on the given listener from a background thread.
+	///
+	/// The thread runs until the listener fails; other paths get a 404.
+	pub fn serve(self, listener: TcpListener) -> JoinHandle<()> {
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					// A misbehaving client only affects its own request.
+					Ok(stream) => {
+						let _ = self.respond(stream);
+					}
+					Err(_) => continue,
+				}
+			}
+		})
+	}
+
+	fn respond(&self, mut stream: TcpStream) -> Result<()> {
+		stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+		let mut reader = BufReader::new(stream.try_clone()?);
+		let mut request_line = String::new();
+		reader.read_line(&mut request_line)?;
+		// Drain the headers so the client isn't reset before reading the response.
+		let mut header = String::new();
+		while reader.read_line(&mut header)? > 2 {
+			header.clear();
+		}
+
+		let mut parts = request_line.split_whitespace();
+		let (status, body) = match (parts.next(), parts.next()) {
+			(Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+			_ => ("404 Not Found", String::from("Not Found\n")),
+		};
+		write!(
+			stream,
+			"HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+			status,
+			body.len(),
+			body
+		)?;
+		stream.flush()
+	}
+}
+
+fn render(stats: &QueueStats, labels: &[(String, String)]) -> String {
+	let labels = format_labels(labels);
+	let mut out = String::new();
+	let mut metric = |name: &str, kind: &str, help: &str, value: Option<String>| {
+		if let Some(value) = value {
+			let _ = writeln!(out, "# HELP transientdb_{} {}", name, help);
+			let _ = writeln!(out, "# TYPE transientdb_{} {}", name, kind);
+			let _ = writeln!(out, "transientdb_{}{} {}", name, labels, value);
+		}
+	};
+
+	let pending = stats.pending;
+	metric(
+		"pending_items",
+		"gauge",
+		"Items waiting to be delivered.",
+		pending.map(|p| p.items.to_string()),
+	);
+	metric(
+		"pending_bytes",
+		"gauge",
+		"Size of the items waiting to be delivered.",
+		pending.map(|p| p.bytes.to_string()),
+	);
+	metric(
+		"oldest_age_seconds",
+		"gauge",
+		"Age of the oldest pending item.",
+		stats.oldest_age.map(|age| age.as_secs_f64().to_string()),
+	);
+	let counters = [
+		("appended_total", "Items appended.", stats.appended),
+		(
+			"append_errors_total",
+			"Appends that failed.",
+			stats.append_errors,
+		),
+		(
+			"fetches_total",
+			"Fetches that returned a batch.",
+			stats.fetched,
+		),
+		(
+			"fetch_errors_total",
+			"Fetches that failed.",
+			stats.fetch_errors,
+		),
+		(
+			"removed_total",
+			"Items removed after delivery.",
+			stats.removed,
+		),
+		(
+			"remove_errors_total",
+			"Removals that failed.",
+			stats.remove_errors,
+		),
+	];
+	for (name, help, value) in counters {
+		metric(name, "counter", help, Some(value.to_string()));
+	}
+	out
+}
+
+fn format_labels(labels: &[(String, String)]) -> String {
+	if labels.is_empty() {
+		return String::new();
+	}
+	let pairs: Vec<String> = labels
+		.iter()
+		.map(|(name, value)| {
+			let value = value
+				.replace('\\', "\\\\")
+				.replace('"', "\\\"")
+				.replace('\n', "\\n");
+			format!("{}=\"{}\"", name, value)
+		})
+		.collect();
+	format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{MemoryConfig, MemoryStore, PendingSize};
+	use serde_json::json;
+	use std::io::Read;
+	use std::net::Shutdown;
+
+	#[test]
+	fn test_render() {
+		let stats = QueueStats {
+			pending: Some(PendingSize {
+				items: 3,
+				bytes: 120,
+			}),
+			oldest_age: None,
+			appended: 5,
+			remove_errors: 1,
+			..QueueStats::default()
+		};
+		let text = render(&stats, &[("queue".to_string(), "a \"b\"\\c".to_string())]);
+		assert!(text.contains("# TYPE transientdb_pending_items gauge\n"));
+		assert!(text.contains("transientdb_pending_items{queue=\"a \\\"b\\\"\\\\c\"} 3\n"));
+		assert!(text.contains("transientdb_pending_bytes{queue=\"a \\\"b\\\"\\\\c\"} 120\n"));
+		assert!(text.contains("transientdb_appended_total{queue=\"a \\\"b\\\"\\\\c\"} 5\n"));
+		assert!(text.contains("transientdb_remove_errors_total{queue=\"a \\\"b\\\"\\\\c\"} 1\n"));
+		// Metrics the store can't report are left out rather than reported as zero.
+		assert!(!text.contains("oldest_age_seconds"));
+	}
+
+	#[test]
+	fn test_textfile_and_http() {
+		let db = Arc::new(TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test".into(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		})));
+		db.append(json!({"event": "one"})).unwrap();
+		db.append(json!({"event": "two"})).unwrap();
+		let exporter = PrometheusExporter::new(db.clone());
+
+		let dir = std::env::temp_dir().join("transientdb-prometheus-test");
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("transientdb.prom");
+		exporter.write_textfile(&path).unwrap();
+		let text = fs::read_to_string(&path).unwrap();
+		assert!(text.contains("transientdb_pending_items 2\n"));
+		assert!(text.contains("transientdb_appended_total 2\n"));
+		fs::remove_dir_all(&dir).unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		exporter.serve(listener);
+
+		let get = |path: &str| {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+			stream.shutdown(Shutdown::Write).unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		};
+		let response = get("/metrics");
+		assert!(response.starts_with("HTTP/1.1 200 OK"));
+		assert!(response.contains("transientdb_pending_items 2\n"));
+		assert!(get("/other").starts_with("HTTP/1.1 404"));
+	}
+}
@@ -0,0 +1,158 @@
+//! Admission of writes against a cached estimate of storage usage and quota, so a store
+//! stops writing before the browser's quota is reached rather than failing each write
+//! with `QuotaExceededError` once it is.
+//!
+//! Estimates are requested in the background and go stale, so the bytes admitted since
+//! the last one are added to its usage to project where usage is now.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{json, Value};
+
+/// How long an estimate is used before another is requested.
+const REFRESH_INTERVAL: TimeDelta = TimeDelta::seconds(30);
+
+/// Bytes admitted since the last estimate after which another is requested, however
+/// recent it is.
+const REFRESH_BYTES: u64 = 1024 * 1024;
+
+struct Estimate {
+	usage: u64,
+	quota: u64,
+	at: DateTime<Utc>,
+}
+
+/// Decides whether writes may go to storage, given the last estimate.
+pub(crate) struct QuotaGate {
+	/// Fraction of the quota usage may reach
+	threshold: f64,
+	estimate: Option<Estimate>,
+	/// Bytes admitted since `estimate`
+	admitted: u64,
+	/// Set while an estimate is being requested
+	refreshing: bool,
+	/// Set once a write was refused, until one is admitted again
+	limited: bool,
+}
+
+impl QuotaGate {
+	/// A gate admitting writes while usage stays within `threshold` of the quota, clamped
+	/// to `0.0..=1.0`.
+	pub(crate) fn new(threshold: f64) -> Self {
+		Self {
+			threshold: if threshold.is_nan() {
+				1.0
+			} else {
+				threshold.clamp(0.0, 1.0)
+			},
+			estimate: None,
+			admitted: 0,
+			refreshing: false,
+			limited: false,
+		}
+	}
+
+	/// Whether a new estimate should be requested. Returns true once until
+	/// [`update`](Self::update) or [`estimate_failed`](Self::estimate_failed) is called.
+	pub(crate) fn wants_estimate(&mut self, now: DateTime<Utc>) -> bool {
+		if self.refreshing {
+			return false;
+		}
+		let stale = match &self.estimate {
+			Some(estimate) => {
+				now - estimate.at >= REFRESH_INTERVAL || self.admitted >= REFRESH_BYTES
+			}
+			None => true,
+		};
+		self.refreshing = stale;
+		stale
+	}
+
+	/// Takes a new estimate.
+	pub(crate) fn update(&mut self, usage: u64, quota: u64, now: DateTime<Utc>) {
+		self.estimate = Some(Estimate {
+			usage,
+			quota,
+			at: now,
+		});
+		self.admitted = 0;
+		self.refreshing = false;
+	}
+
+	/// Notes that requesting an estimate failed, so another can be requested. The last
+	/// estimate, if any, is still used.
+	pub(crate) fn estimate_failed(&mut self) {
+		self.refreshing = false;
+	}
+
+	/// Whether `bytes` more may be written, counting them if so. Writes are admitted
+	/// until there's an estimate to check them against.
+	pub(crate) fn admit(&mut self, bytes: u64) -> bool {
+		let admitted = match &self.estimate {
+			Some(estimate) => {
+				let projected = estimate.usage + self.admitted + bytes;
+				projected as f64 <= estimate.quota as f64 * self.threshold
+			}
+			None => true,
+		};
+		if admitted {
+			self.admitted += bytes;
+		}
+		self.limited = !admitted;
+		admitted
+	}
+
+	/// Whether the last write was refused.
+	pub(crate) fn is_limited(&self) -> bool {
+		self.limited
+	}
+
+	/// Describes the gate for debug dumps.
+	pub(crate) fn to_json(&self) -> Value {
+		json!({
+			"threshold": self.threshold,
+			"usage": self.estimate.as_ref().map(|estimate| estimate.usage + self.admitted),
+			"quota": self.estimate.as_ref().map(|estimate| estimate.quota),
+			"limited": self.limited,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_gate_refuses_writes_past_the_threshold() {
+		let now = Utc::now();
+		let mut gate = QuotaGate::new(0.8);
+
+		// Admitted until there's an estimate
+		assert!(gate.wants_estimate(now));
+		assert!(!gate.wants_estimate(now));
+		assert!(gate.admit(500));
+
+		gate.update(600, 1000, now);
+		assert!(!gate.wants_estimate(now));
+		assert!(gate.admit(150));
+		assert!(!gate.is_limited());
+		// 600 used + 150 admitted + 100 more is over 800
+		assert!(!gate.admit(100));
+		assert!(gate.is_limited());
+		assert!(gate.admit(50));
+		assert!(!gate.is_limited());
+
+		// Estimates go stale with time, and with bytes written
+		assert!(gate.wants_estimate(now + REFRESH_INTERVAL));
+		gate.estimate_failed();
+		assert!(gate.wants_estimate(now + REFRESH_INTERVAL));
+		gate.update(0, 10 * REFRESH_BYTES, now);
+		assert!(gate.admit(REFRESH_BYTES));
+		assert!(gate.wants_estimate(now));
+
+		// Nothing fits under a zero threshold
+		let mut gate = QuotaGate::new(0.0);
+		gate.update(0, 1000, now);
+		assert!(!gate.admit(1));
+		assert_eq!(gate.to_json()["limited"], true);
+	}
+}
@@ -0,0 +1,53 @@
+//! Stamping events queued without a timestamp with the time they were received.
+
+use crate::skew::TIMESTAMP_KEY;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+
+/// Stamps `at` into the `field` of an event, unless the event has a `timestamp` or the
+/// field already. Returns whether the event was stamped.
+pub(crate) fn stamp(event: &mut Value, field: Option<&str>, at: DateTime<Utc>) -> bool {
+	let (Some(field), Some(fields)) = (field, event.as_object_mut()) else {
+		return false;
+	};
+	if fields.contains_key(TIMESTAMP_KEY) || fields.contains_key(field) {
+		return false;
+	}
+	fields.insert(
+		field.to_string(),
+		Value::String(at.to_rfc3339_opts(SecondsFormat::Millis, true)),
+	);
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn test_stamps_only_events_without_a_time() {
+		let at = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+			.unwrap()
+			.with_timezone(&Utc);
+
+		let mut event = json!({"event": "tap"});
+		assert!(stamp(&mut event, Some("receivedAt"), at));
+		assert_eq!(
+			event,
+			json!({"event": "tap", "receivedAt": "2024-05-01T12:00:00.000Z"})
+		);
+		// Stamping again keeps the first time
+		assert!(!stamp(&mut event, Some("receivedAt"), Utc::now()));
+
+		for mut event in [
+			json!({"event": "tap", "timestamp": "2024-01-01T00:00:00Z"}),
+			json!("not an object"),
+		] {
+			let original = event.clone();
+			assert!(!stamp(&mut event, Some("queuedAt"), at));
+			assert_eq!(event, original);
+		}
+		assert!(!stamp(&mut json!({}), None, at));
+	}
+}
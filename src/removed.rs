@@ -0,0 +1,199 @@
+//! Recently removed batches, retained for debugging.
+
+use crate::Equivalent;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Events removed from a store by one `remove()` call, from
+/// [`recently_removed()`](crate::TransientDB::recently_removed).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemovedBatch {
+	/// Id of the fetch the events were removed after, to pass to
+	/// [`replay()`](crate::TransientDB::replay). None if the store didn't see the fetch,
+	/// e.g. because it was made before a restart.
+	pub batch_id: Option<String>,
+	/// When the events were removed.
+	pub removed_at: DateTime<Utc>,
+	/// The removed events, oldest first.
+	pub events: Vec<Value>,
+}
+
+impl RemovedBatch {
+	fn to_json(&self) -> Value {
+		json!({
+			"batchId": self.batch_id,
+			"removedAt": self.removed_at.to_rfc3339(),
+			"events": self.events,
+		})
+	}
+
+	fn from_json(value: &Value) -> Option<Self> {
+		let removed_at = DateTime::parse_from_rfc3339(value.get("removedAt")?.as_str()?)
+			.ok()?
+			.with_timezone(&Utc);
+		Some(Self {
+			batch_id: value
+				.get("batchId")
+				.and_then(Value::as_str)
+				.map(str::to_string),
+			removed_at,
+			events: value.get("events")?.as_array()?.clone(),
+		})
+	}
+}
+
+/// The last `capacity` removed batches, oldest first. Retains nothing with a capacity
+/// of 0.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RemovedRing {
+	batches: VecDeque<RemovedBatch>,
+	capacity: usize,
+}
+
+impl RemovedRing {
+	pub(crate) fn new(capacity: usize) -> Self {
+		Self {
+			batches: VecDeque::new(),
+			capacity,
+		}
+	}
+
+	/// Restores a ring from a JSON array, as produced by [`to_json`](Self::to_json).
+	/// Entries that can't be read are ignored.
+	pub(crate) fn from_json(value: &Value, capacity: usize) -> Self {
+		let mut ring = Self::new(capacity);
+		if let Some(batches) = value.as_array() {
+			ring.batches = batches.iter().filter_map(RemovedBatch::from_json).collect();
+			ring.trim();
+		}
+		ring
+	}
+
+	pub(crate) fn to_json(&self) -> Value {
+		Value::from(
+			self.batches
+				.iter()
+				.map(RemovedBatch::to_json)
+				.collect::<Vec<_>>(),
+		)
+	}
+
+	/// Whether removed batches are retained at all.
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.capacity > 0
+	}
+
+	pub(crate) fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		self.trim();
+	}
+
+	/// Retains a removed batch, forgetting the oldest beyond the capacity.
+	pub(crate) fn push(
+		&mut self,
+		batch_id: Option<String>,
+		events: Vec<Value>,
+		removed_at: DateTime<Utc>,
+	) {
+		if events.is_empty() || !self.is_enabled() {
+			return;
+		}
+		self.batches.push_back(RemovedBatch {
+			batch_id,
+			removed_at,
+			events,
+		});
+		self.trim();
+	}
+
+	pub(crate) fn batches(&self) -> Vec<RemovedBatch> {
+		self.batches.iter().cloned().collect()
+	}
+
+	/// The events of the newest retained batch removed after the fetch `batch_id`.
+	pub(crate) fn events_of(&self, batch_id: &str) -> Option<&[Value]> {
+		self.batches
+			.iter()
+			.rev()
+			.find(|batch| batch.batch_id.as_deref() == Some(batch_id))
+			.map(|batch| batch.events.as_slice())
+	}
+
+	fn trim(&mut self) {
+		while self.batches.len() > self.capacity {
+			self.batches.pop_front();
+		}
+	}
+}
+
+/// Ids of recent fetches, so a removal can be matched to the batch it delivered.
+///
+/// Each fetch is identified by its first removable, which the removables passed to
+/// `remove()` are compared against.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FetchedBatches {
+	fetches: VecDeque<(String, Arc<dyn Equivalent + Send + Sync>)>,
+}
+
+impl FetchedBatches {
+	/// Number of fetches remembered; older ones were most likely abandoned.
+	const CAPACITY: usize = 16;
+
+	pub(crate) fn record(&mut self, batch_id: &str, first: Arc<dyn Equivalent + Send + Sync>) {
+		self.fetches.push_back((batch_id.to_string(), first));
+		if self.fetches.len() > Self::CAPACITY {
+			self.fetches.pop_front();
+		}
+	}
+
+	/// Forgets and returns the id of the fetch the removables came from, if known.
+	pub(crate) fn take(&mut self, removed: &[Box<dyn Equivalent>]) -> Option<String> {
+		let position = self.fetches.iter().rposition(|(_, first)| {
+			removed
+				.iter()
+				.any(|removable| removable.equals(first.as_ref()))
+		})?;
+		self.fetches.remove(position).map(|(batch_id, _)| batch_id)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_removed_ring_keeps_the_newest_batches() {
+		let now = Utc::now();
+		let mut ring = RemovedRing::default();
+		ring.push(None, vec![json!({"n": 0})], now);
+		assert!(ring.batches().is_empty());
+
+		ring.set_capacity(2);
+		for n in 1..4 {
+			ring.push(Some(format!("batch-{}", n)), vec![json!({"n": n})], now);
+		}
+		ring.push(None, Vec::new(), now);
+
+		let restored = RemovedRing::from_json(&ring.to_json(), 1).batches();
+		let batches = ring.batches();
+		assert_eq!(batches.len(), 2);
+		assert_eq!(batches[0].events, vec![json!({"n": 2})]);
+		assert_eq!(restored, batches[1..]);
+		assert_eq!(ring.events_of("batch-3"), Some(&[json!({"n": 3})][..]));
+		assert_eq!(ring.events_of("batch-1"), None);
+	}
+
+	#[test]
+	fn test_fetched_batches_match_removals() {
+		let first = Arc::new(json!({"n": 1}));
+		let mut fetched = FetchedBatches::default();
+		fetched.record("a", Arc::new(Arc::clone(&first)));
+		fetched.record("b", Arc::new(Arc::new(json!({"n": 2}))));
+
+		let removed: Vec<Box<dyn Equivalent>> = vec![Box::new(Arc::clone(&first))];
+		assert_eq!(fetched.take(&removed).as_deref(), Some("a"));
+		assert_eq!(fetched.take(&removed), None);
+	}
+}
@@ -0,0 +1,51 @@
+//! Events queued again by `replay`, after the batch they were delivered in was lost.
+//!
+//! A replayed event carries the id of the batch it was first delivered in, in a
+//! `_replayOf` field, and envelopes containing replayed events are flagged with
+//! `"replay": true`, so the server can tell them from new traffic.
+
+use serde_json::Value;
+use std::io::{self, Result};
+
+/// Key of the original batch id added to replayed events.
+pub(crate) const REPLAY_OF_KEY: &str = "_replayOf";
+
+/// Key of the flag added to envelopes containing replayed events.
+pub(crate) const ENVELOPE_KEY: &str = "replay";
+
+/// Marks an event as replayed from the batch `batch_id`.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the id.
+pub(crate) fn tag(data: &mut Value, batch_id: &str) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can be replayed",
+		)
+	})?;
+	object.insert(REPLAY_OF_KEY.to_string(), Value::from(batch_id));
+	Ok(())
+}
+
+/// Whether an event was replayed.
+pub(crate) fn is_replay(event: &Value) -> bool {
+	event.get(REPLAY_OF_KEY).is_some()
+}
+
+/// Flags an envelope as containing replayed events.
+pub(crate) fn flag(envelope: &mut Value) {
+	if let Some(envelope) = envelope.as_object_mut() {
+		envelope.insert(ENVELOPE_KEY.to_string(), Value::Bool(true));
+	}
+}
+
+/// The error for a batch that wasn't retained, or was removed without a known id.
+pub(crate) fn not_retained(batch_id: &str) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::NotFound,
+		format!(
+			"Batch {} isn't among the retained removed batches",
+			batch_id
+		),
+	)
+}
@@ -0,0 +1,33 @@
+//! Write keys for queued events.
+//!
+//! An event appended for another write key than its store's (e.g. a second workspace
+//! the client sends to) carries it in a `_writeKey` field. Stores that support this
+//! fetch the events of one write key at a time, with that key in the batch envelope.
+
+use serde_json::Value;
+use std::io::{self, Result};
+
+/// Key of the write key added to events.
+pub(crate) const WRITE_KEY_KEY: &str = "_writeKey";
+
+/// Records the write key an event is sent with.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the key.
+pub(crate) fn tag(data: &mut Value, write_key: &str) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can carry a write key",
+		)
+	})?;
+	object.insert(WRITE_KEY_KEY.to_string(), Value::from(write_key));
+	Ok(())
+}
+
+/// The write key an event is sent with: its own, or else the store's.
+pub(crate) fn write_key<'a>(event: &'a Value, store_key: &'a str) -> &'a str {
+	event
+		.get(WRITE_KEY_KEY)
+		.and_then(Value::as_str)
+		.unwrap_or(store_key)
+}
@@ -0,0 +1,172 @@
+//! Dropping low-priority events while a store stays under pressure.
+
+use crate::{eviction, moves, JsonPointer};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Adaptive down-sampling of low-priority events, set with `set_down_sampling`.
+///
+/// Once a store has held at least `threshold` events for `sustain`, it keeps only every
+/// second event of the `low_priority` types it's appended, then every fourth after
+/// another `sustain`, and so on, up to one in 1024. Events of other types, pinned events
+/// and events being moved are always kept. Sampling stops as soon as an append finds
+/// the store below the threshold again.
+///
+/// This degrades gracefully under a long outage: the store keeps making room for the
+/// events that matter, where evicting would drop the oldest of any type. Sampled events
+/// are reported by `drop_report` as [`DropReason::Sampled`](crate::DropReason::Sampled).
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use serde_json::json;
+/// use transientdb::{DataStore, DownSampling, JsonPointer, MemoryConfig, MemoryStore};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 10_000,
+///     max_fetch_size: 1024,
+/// });
+/// store.set_down_sampling(Some(DownSampling {
+///     threshold: 8_000,
+///     sustain: Duration::from_secs(60),
+///     type_key: JsonPointer::new("/event")?,
+///     low_priority: vec!["mousemove".into(), "scroll".into()],
+/// }));
+///
+/// store.append(json!({"event": "mousemove"}))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownSampling {
+	/// Number of queued events at which the store is under pressure.
+	pub threshold: usize,
+	/// How long pressure must last before sampling starts, and before each doubling of
+	/// the rate. Zero keeps one event in 1024 as soon as the store reaches the threshold.
+	pub sustain: Duration,
+	/// Field holding an event's type.
+	pub type_key: JsonPointer,
+	/// Types of events that may be dropped.
+	pub low_priority: Vec<String>,
+}
+
+/// Tracks pressure on a store and decides which appended events to keep.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+	config: DownSampling,
+	/// When appends started finding the store at or over the threshold
+	pressure_since: Option<DateTime<Utc>>,
+	/// Low-priority events appended at the current rate, to keep every Nth
+	seen: u64,
+	/// Low-priority events appended per event kept
+	keep_one_in: u64,
+}
+
+impl Sampler {
+	/// Doublings of the rate before it stops rising, keeping one event in 1024.
+	const MAX_DOUBLINGS: u32 = 10;
+
+	pub(crate) fn new(config: DownSampling) -> Self {
+		Self {
+			config,
+			pressure_since: None,
+			seen: 0,
+			keep_one_in: 1,
+		}
+	}
+
+	/// Whether to queue an event appended at `now` to a store holding `queued` events.
+	pub(crate) fn admit(&mut self, event: &Value, queued: usize, now: DateTime<Utc>) -> bool {
+		let keep_one_in = self.rate(queued, now);
+		if keep_one_in != self.keep_one_in {
+			// Each new rate starts by keeping an event
+			self.keep_one_in = keep_one_in;
+			self.seen = 0;
+		}
+		if keep_one_in == 1 || !self.is_low_priority(event) {
+			return true;
+		}
+		self.seen += 1;
+		// The rate is a power of two
+		(self.seen - 1) & (keep_one_in - 1) == 0
+	}
+
+	/// Records the store's size, returning how many low-priority events should be
+	/// appended per event kept.
+	fn rate(&mut self, queued: usize, now: DateTime<Utc>) -> u64 {
+		if queued < self.config.threshold {
+			self.pressure_since = None;
+			return 1;
+		}
+		let since = *self.pressure_since.get_or_insert(now);
+		let sustained = (now - since).to_std().unwrap_or_default();
+		let doublings = match sustained
+			.as_nanos()
+			.checked_div(self.config.sustain.as_nanos())
+		{
+			Some(periods) => periods.min(u128::from(Self::MAX_DOUBLINGS)) as u32,
+			None => Self::MAX_DOUBLINGS,
+		};
+		1 << doublings
+	}
+
+	fn is_low_priority(&self, event: &Value) -> bool {
+		if eviction::is_pinned(event) || moves::is_held(event) {
+			return false;
+		}
+		let Some(Value::String(kind)) = self.config.type_key.get(event) else {
+			return false;
+		};
+		self.config.low_priority.iter().any(|low| low == kind)
+	}
+
+	/// Describes the settings for `debug_config`.
+	pub(crate) fn to_json(&self) -> Value {
+		json!({
+			"threshold": self.config.threshold,
+			"sustainMs": self.config.sustain.as_millis() as u64,
+			"typeKey": self.config.type_key.to_string(),
+			"lowPriority": self.config.low_priority,
+			"underPressureSince": self.pressure_since.map(|since| since.to_rfc3339()),
+			"keepOneIn": self.keep_one_in,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeDelta;
+
+	#[test]
+	fn test_rate_rises_with_sustained_pressure() {
+		let mut sampler = Sampler::new(DownSampling {
+			threshold: 100,
+			sustain: Duration::from_secs(10),
+			type_key: JsonPointer::new("/event").unwrap(),
+			low_priority: vec!["scroll".into()],
+		});
+		let scroll = json!({"event": "scroll"});
+		let click = json!({"event": "click"});
+		let start = Utc::now();
+		let kept = |sampler: &mut Sampler, at: DateTime<Utc>| {
+			(0..8).filter(|_| sampler.admit(&scroll, 150, at)).count()
+		};
+
+		// Nothing is dropped at first over the threshold, nor below it
+		assert_eq!(kept(&mut sampler, start), 8);
+		assert!(sampler.admit(&scroll, 99, start));
+
+		assert_eq!(kept(&mut sampler, start), 8);
+		let later = start + TimeDelta::seconds(10);
+		assert_eq!(kept(&mut sampler, later), 4);
+		assert!(sampler.admit(&click, 150, later));
+		assert_eq!(kept(&mut sampler, start + TimeDelta::seconds(25)), 2);
+		assert_eq!(kept(&mut sampler, start + TimeDelta::days(1)), 1);
+
+		// Relieving the pressure resets the rate
+		assert!(sampler.admit(&scroll, 50, start + TimeDelta::days(1)));
+		assert_eq!(kept(&mut sampler, start + TimeDelta::days(1)), 8);
+	}
+}
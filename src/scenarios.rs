@@ -0,0 +1,201 @@
+//! Scenarios run alike against every store that fetches batch envelopes, so they're
+//! known to behave the same rather than assumed to.
+//!
+//! MemoryStore and DirectoryContentStore run them below; WebStore runs them in its
+//! wasm-bindgen tests. Scenarios taking a store with `max_items` of
+//! [`EVICTING_MAX_ITEMS`] only apply to the stores that evict by count.
+
+use crate::{DataResult, DataStoreExt, DropReason};
+use serde_json::{json, Value};
+use std::io::{ErrorKind, Result};
+use std::time::Duration;
+
+/// Write key of the stores the scenarios run against.
+pub(crate) const WRITE_KEY: &str = "scenario-key";
+
+/// `max_items` of the stores [`eviction`] runs against.
+pub(crate) const EVICTING_MAX_ITEMS: usize = 3;
+
+fn events(range: std::ops::Range<i64>) -> Vec<Value> {
+	range.map(|n| json!({"event": "tap", "n": n})).collect()
+}
+
+fn numbers(result: &DataResult<Value>) -> Vec<i64> {
+	result.data.as_ref().map_or_else(Vec::new, |envelope| {
+		envelope["batch"]
+			.as_array()
+			.into_iter()
+			.flatten()
+			.filter_map(|event| event["n"].as_i64())
+			.collect()
+	})
+}
+
+/// Appended events come back in order, in an envelope with the store's metadata, and
+/// are gone once removed.
+pub(crate) fn append_fetch_remove<S>(store: &mut S) -> Result<()>
+where
+	S: DataStoreExt<Output = Value> + ?Sized,
+{
+	store.reset();
+	assert!(!store.has_data());
+	assert!(store.fetch(None, None)?.is_none());
+
+	for event in events(0..3) {
+		store.append(event)?;
+	}
+	store.append_many(events(3..5))?;
+	assert!(store.has_data());
+	assert_eq!(store.pending_events()?, events(0..5));
+
+	let result = store.fetch(None, None)?.expect("a batch");
+	assert_eq!(numbers(&result), [0, 1, 2, 3, 4]);
+	let envelope = result.data.as_ref().expect("an envelope");
+	assert_eq!(envelope["writeKey"], WRITE_KEY);
+	assert_eq!(
+		envelope["batchId"],
+		result.batch_id.as_deref().expect("a batch id")
+	);
+	assert!(envelope["sentAt"].is_string());
+
+	// Fetching doesn't remove
+	assert_eq!(
+		numbers(&store.fetch(None, None)?.expect("a batch")),
+		[0, 1, 2, 3, 4]
+	);
+
+	store.remove(&result.removable.expect("removables"))?;
+	assert!(!store.has_data());
+	assert!(store.fetch(None, None)?.is_none());
+	Ok(())
+}
+
+/// Removing some of a batch's events leaves the others queued, in order.
+pub(crate) fn partial_removal<S>(store: &mut S) -> Result<()>
+where
+	S: DataStoreExt<Output = Value> + ?Sized,
+{
+	store.reset();
+	store.append_many(events(0..4))?;
+
+	let mut removable = store
+		.fetch(None, None)?
+		.and_then(|result| result.removable)
+		.expect("removables");
+	assert_eq!(removable.len(), 4);
+	let taken = [removable.remove(2), removable.remove(0)];
+	store.remove(&taken)?;
+
+	// Removables are only good until the next removal, since a DirectoryStore
+	// addresses events by their position in a file
+	let result = store.fetch(None, None)?.expect("a batch");
+	assert_eq!(numbers(&result), [1, 3]);
+	store.remove(&result.removable.expect("removables"))?;
+	assert!(!store.has_data());
+
+	// Removing what's gone is harmless
+	store.remove(&taken)?;
+	assert!(!store.has_data());
+	Ok(())
+}
+
+/// Events appended after a fetch aren't removed with it.
+pub(crate) fn append_during_fetch<S>(store: &mut S) -> Result<()>
+where
+	S: DataStoreExt<Output = Value> + ?Sized,
+{
+	store.reset();
+	store.append_many(events(0..2))?;
+	let result = store.fetch(None, None)?.expect("a batch");
+	store.append_many(events(2..4))?;
+
+	store.remove(&result.removable.expect("removables"))?;
+	assert_eq!(numbers(&store.fetch(None, None)?.expect("a batch")), [2, 3]);
+	store.reset();
+	assert!(!store.has_data());
+	Ok(())
+}
+
+/// A store over its `max_items` drops the oldest events, and reports them.
+pub(crate) fn eviction<S>(store: &mut S) -> Result<()>
+where
+	S: DataStoreExt<Output = Value> + ?Sized,
+{
+	store.reset();
+	store.drop_report()?;
+	for event in events(0..5) {
+		store.append(event)?;
+	}
+	assert_eq!(store.pending_events()?, events(2..5));
+
+	let report = store.drop_report()?;
+	assert_eq!(report.len(), 1);
+	assert_eq!(report[0].reason, DropReason::Capacity);
+	assert_eq!(report[0].count, 5 - EVICTING_MAX_ITEMS);
+	Ok(())
+}
+
+/// Fetching by age only takes events queued long enough ago. Stores that don't track
+/// enqueue times must say so.
+pub(crate) fn age<S>(store: &mut S) -> Result<()>
+where
+	S: DataStoreExt<Output = Value> + ?Sized,
+{
+	store.reset();
+	store.append_many(events(0..2))?;
+	match store.fetch_older_than(Duration::from_secs(3600)) {
+		Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+		result => assert!(result?.is_none()),
+	}
+	let result = store.fetch_older_than(Duration::ZERO)?.expect("a batch");
+	assert_eq!(numbers(&result), [0, 1]);
+	store.reset();
+	Ok(())
+}
+
+mod tests {
+	use super::*;
+	use crate::{
+		DirectoryConfig, DirectoryContentStore, DirectoryStore, MemoryConfig, MemoryStore,
+	};
+	use tempfile::TempDir;
+
+	fn memory_store(max_items: usize) -> MemoryStore {
+		MemoryStore::new(MemoryConfig {
+			write_key: WRITE_KEY.to_string(),
+			max_items,
+			max_fetch_size: 64 * 1024,
+		})
+	}
+
+	fn directory_store(temp_dir: &TempDir) -> Result<DirectoryContentStore> {
+		let store = DirectoryStore::new(DirectoryConfig {
+			write_key: WRITE_KEY.to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			// Small files, so batches span several
+			max_file_size: 100,
+		})?;
+		Ok(DirectoryContentStore::new(store))
+	}
+
+	#[test]
+	fn test_memory_store_scenarios() -> Result<()> {
+		let mut store = memory_store(100);
+		append_fetch_remove(&mut store)?;
+		partial_removal(&mut store)?;
+		append_during_fetch(&mut store)?;
+		age(&mut store)?;
+		eviction(&mut memory_store(EVICTING_MAX_ITEMS))
+	}
+
+	#[test]
+	fn test_directory_store_scenarios() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let mut store = directory_store(&temp_dir)?;
+		append_fetch_remove(&mut store)?;
+		partial_removal(&mut store)?;
+		append_during_fetch(&mut store)?;
+		age(&mut store)
+	}
+}
@@ -0,0 +1,195 @@
+//! Versioning of stored event payloads.
+
+use crate::logging;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Result;
+use std::sync::Arc;
+
+/// Key of the schema version stamped on events.
+pub(crate) const SCHEMA_VERSION_KEY: &str = "_schemaVersion";
+
+type Migration = Arc<dyn Fn(Value) -> Result<Value> + Send + Sync>;
+
+/// The current event schema version and how to upgrade events stored by older versions.
+///
+/// A store given migrations stamps each appended event with a `_schemaVersion` field
+/// holding the current version, and runs older events through the registered
+/// migrations before handing them out, so an app update that changes event shape can
+/// still send what the previous version queued. Events without a `_schemaVersion` are
+/// taken to be version 0.
+///
+/// A migration registered with [`migration`](Self::migration) for version `n` upgrades
+/// an event from `n` to `n + 1`; versions without one are assumed to share the shape of
+/// the next. If a migration fails, the event is kept as it was and a warning is logged.
+/// Events stamped with a newer version than the current one (after a downgrade) are
+/// left alone.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{DataStore, MemoryConfig, MemoryStore, SchemaMigrations};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// });
+/// // Queued by the previous app version
+/// store.append(json!({"event": "purchase", "price": "9.99"}))?;
+///
+/// // Version 1 sends prices as numbers
+/// store.set_migrations(SchemaMigrations::new(1).migration(0, |mut event| {
+///     if let Some(price) = event["price"].as_str().and_then(|p| p.parse::<f64>().ok()) {
+///         event["price"] = json!(price);
+///     }
+///     Ok(event)
+/// }));
+///
+/// let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+/// assert_eq!(batch["batch"][0]["price"], 9.99);
+/// assert_eq!(batch["batch"][0]["_schemaVersion"], 1);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SchemaMigrations {
+	version: u32,
+	migrations: BTreeMap<u32, Migration>,
+}
+
+impl SchemaMigrations {
+	/// Creates a registry whose current schema version is `version`.
+	pub fn new(version: u32) -> Self {
+		Self {
+			version,
+			migrations: BTreeMap::new(),
+		}
+	}
+
+	/// Registers the migration upgrading events from version `from` to `from + 1`.
+	pub fn migration<F>(mut self, from: u32, migrate: F) -> Self
+	where
+		F: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+	{
+		self.migrations.insert(from, Arc::new(migrate));
+		self
+	}
+
+	/// The current schema version.
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	/// Returns the schema version an event was stored with.
+	pub fn event_version(event: &Value) -> u32 {
+		event
+			.get(SCHEMA_VERSION_KEY)
+			.and_then(Value::as_u64)
+			.and_then(|version| u32::try_from(version).ok())
+			.unwrap_or(0)
+	}
+
+	/// Stamps an event with the current version.
+	pub(crate) fn stamp(&self, event: &mut Value) {
+		if let Some(fields) = event.as_object_mut() {
+			fields.insert(SCHEMA_VERSION_KEY.to_string(), Value::from(self.version));
+		}
+	}
+
+	/// Upgrades an event to the current version, returning whether it changed.
+	pub(crate) fn migrate(&self, event: &mut Value) -> bool {
+		let from = Self::event_version(event);
+		if from >= self.version || !event.is_object() {
+			return false;
+		}
+
+		let mut migrated = event.clone();
+		for (version, migrate) in self.migrations.range(from..self.version) {
+			migrated = match migrate(migrated) {
+				Ok(migrated) => migrated,
+				Err(e) => {
+					logging::log_warn!(
+						"Failed to migrate event from schema version {}: {}",
+						version,
+						e
+					);
+					return false;
+				}
+			};
+		}
+		self.stamp(&mut migrated);
+		*event = migrated;
+		true
+	}
+
+	/// Describes the registry for `debug_config`.
+	pub(crate) fn to_json(&self) -> Value {
+		serde_json::json!({
+			"version": self.version,
+			"migrations": self.migrations.keys().collect::<Vec<_>>(),
+		})
+	}
+}
+
+impl fmt::Debug for SchemaMigrations {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("SchemaMigrations")
+			.field("version", &self.version)
+			.field("migrations", &self.migrations.keys().collect::<Vec<_>>())
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::SchemaMigrations;
+	use serde_json::json;
+	use std::io;
+
+	#[test]
+	fn test_migrates_through_each_version() {
+		let migrations = SchemaMigrations::new(3)
+			.migration(0, |mut event| {
+				event["name"] = event["event"].take();
+				Ok(event)
+			})
+			// No migration from 1: version 2 has the same shape
+			.migration(2, |mut event| {
+				event["name"] = json!(event["name"].as_str().unwrap_or("").to_uppercase());
+				Ok(event)
+			});
+
+		let mut event = json!({"event": "login"});
+		assert!(migrations.migrate(&mut event));
+		assert_eq!(
+			event,
+			json!({"event": null, "name": "LOGIN", "_schemaVersion": 3})
+		);
+
+		// Already current
+		assert!(!migrations.migrate(&mut event));
+
+		let mut v2 = json!({"name": "logout", "_schemaVersion": 2});
+		assert!(migrations.migrate(&mut v2));
+		assert_eq!(v2, json!({"name": "LOGOUT", "_schemaVersion": 3}));
+
+		// Newer than current, e.g. after a downgrade
+		let mut newer = json!({"name": "x", "_schemaVersion": 4});
+		assert!(!migrations.migrate(&mut newer));
+	}
+
+	#[test]
+	fn test_failed_migration_keeps_event() {
+		let migrations = SchemaMigrations::new(2)
+			.migration(0, |mut event| {
+				event["step"] = json!(1);
+				Ok(event)
+			})
+			.migration(1, |_| Err(io::Error::other("unexpected shape")));
+
+		let mut event = json!({"event": "login"});
+		assert!(!migrations.migrate(&mut event));
+		assert_eq!(event, json!({"event": "login"}));
+	}
+}
@@ -0,0 +1,204 @@
+//! Migrating the file queues of Segment's analytics-swift and analytics-kotlin SDKs.
+//!
+//! Both write events to numbered files in a directory, each holding a batch envelope
+//! that is opened when the file is created and closed when it is finished:
+//! - analytics-swift: `{index}-segment-events`, renamed to `{index}-segment-events.temp`
+//!   once finished
+//! - analytics-kotlin: `{writeKey}-{index}.tmp`, renamed to `{writeKey}-{index}` once
+//!   finished
+//!
+//! A file still being written ends partway through the batch, e.g. `{"batch":[{...},`,
+//! so events are read one at a time rather than parsing the file as a whole.
+
+use crate::import::{ImportError, ImportReport};
+use crate::DataStoreExt;
+use serde_json::{Deserializer, Value};
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Index of a queue file, or None if the name isn't that of a queue file.
+fn queue_index(name: &str) -> Option<u64> {
+	if name.starts_with('.') {
+		return None;
+	}
+	let stem = name
+		.strip_suffix(".temp")
+		.or_else(|| name.strip_suffix(".tmp"))
+		.unwrap_or(name);
+	let index = match stem.strip_suffix("-segment-events") {
+		Some(index) => index,
+		None => stem.rsplit_once('-')?.1,
+	};
+	if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	index.parse().ok()
+}
+
+/// The queue files in `dir`, oldest first.
+fn queue_files(dir: &Path) -> Result<Vec<PathBuf>> {
+	let mut files: Vec<(u64, PathBuf)> = fs::read_dir(dir)?
+		.filter_map(|entry| entry.ok())
+		.filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+		.filter_map(|entry| {
+			let index = queue_index(entry.file_name().to_str()?)?;
+			Some((index, entry.path()))
+		})
+		.collect();
+	files.sort();
+	Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The entries of a queue file's batch, in order: each event, or why it can't be read.
+/// None if the file doesn't hold a batch envelope.
+fn read_batch(content: &str) -> Option<Vec<std::result::Result<Value, String>>> {
+	let rest = content.trim_start().strip_prefix('{')?.trim_start();
+	let rest = rest.strip_prefix("\"batch\"")?.trim_start();
+	let mut rest = rest.strip_prefix(':')?.trim_start().strip_prefix('[')?;
+
+	let mut events = Vec::new();
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+		if rest.is_empty() || rest.starts_with(']') {
+			break;
+		}
+		let mut stream = Deserializer::from_str(rest).into_iter::<Value>();
+		match stream.next() {
+			Some(Ok(event)) => {
+				events.push(Ok(event));
+				rest = &rest[stream.byte_offset()..];
+			}
+			// A write cut short leaves the last event incomplete, and nothing after it
+			Some(Err(e)) => {
+				events.push(Err(e.to_string()));
+				break;
+			}
+			None => break,
+		}
+	}
+	Some(events)
+}
+
+/// Appends the pending events of the Segment file queue in `dir` to a store, oldest
+/// first, deleting each queue file once its events are queued.
+///
+/// Files in `dir` that aren't queue files are left alone. Events that can't be read,
+/// such as one cut short by a crash, are recorded in the report with the name of their
+/// file. Errors reading the directory or the files, appending to the store or deleting
+/// an imported file abort the import.
+pub(crate) fn import_queue<S>(store: &mut S, dir: &Path) -> Result<ImportReport>
+where
+	S: DataStoreExt + ?Sized,
+{
+	let mut report = ImportReport::default();
+	for path in queue_files(dir)? {
+		let content = fs::read_to_string(&path)?;
+		let Some(entries) = read_batch(&content) else {
+			continue;
+		};
+		let name = path
+			.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+
+		let mut events = Vec::new();
+		for (index, entry) in entries.into_iter().enumerate() {
+			let message = match entry {
+				Ok(event @ Value::Object(_)) => {
+					events.push(event);
+					continue;
+				}
+				Ok(_) => "Event is not a JSON object".to_string(),
+				Err(message) => message,
+			};
+			report.errors.push(ImportError {
+				position: index + 1,
+				message: format!("{}: {}", name, message),
+			});
+		}
+
+		report.imported += events.len();
+		store.append_many(events)?;
+		fs::remove_file(&path)?;
+	}
+	Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{DataStore, MemoryConfig, MemoryStore};
+	use serde_json::json;
+
+	#[test]
+	fn test_queue_file_names() {
+		assert_eq!(queue_index("3-segment-events"), Some(3));
+		assert_eq!(queue_index("12-segment-events.temp"), Some(12));
+		assert_eq!(queue_index("my-write-key-4.tmp"), Some(4));
+		assert_eq!(queue_index("my-write-key-5"), Some(5));
+		assert_eq!(queue_index("settings.json"), None);
+		assert_eq!(queue_index("-segment-events"), None);
+		assert_eq!(queue_index(".0-segment-events.temp"), None);
+	}
+
+	#[test]
+	fn test_read_finished_and_unfinished_batches() {
+		let finished =
+			r#"{"batch":[{"n":1},{"n":2}],"sentAt":"2024-01-01T00:00:00.000Z","writeKey":"key"}"#;
+		let events: Vec<Value> = read_batch(finished)
+			.unwrap()
+			.into_iter()
+			.map(|event| event.unwrap())
+			.collect();
+		assert_eq!(events, vec![json!({"n": 1}), json!({"n": 2})]);
+
+		let unfinished = "{ \"batch\": [{\"n\":1},\n{\"n\":2},";
+		assert_eq!(read_batch(unfinished).unwrap().len(), 2);
+		assert!(read_batch("{\"batch\":[").unwrap().is_empty());
+
+		let cut_short = read_batch("{\"batch\":[{\"n\":1},{\"n\":").unwrap();
+		assert_eq!(cut_short.len(), 2);
+		assert!(cut_short[1].is_err());
+
+		assert!(read_batch("{\"event\":\"not a queue file\"}").is_none());
+	}
+
+	#[test]
+	fn test_import_queue_oldest_first() -> Result<()> {
+		let dir = tempfile::TempDir::new()?;
+		let mut store = MemoryStore::new(MemoryConfig {
+			write_key: "key".to_string(),
+			max_items: 100,
+			max_fetch_size: 10_000,
+		});
+		fs::write(
+			dir.path().join("key-10.tmp"),
+			r#"{"batch":[{"n":3},42,{"n":"#,
+		)?;
+		fs::write(
+			dir.path().join("key-9"),
+			r#"{"batch":[{"n":1},{"n":2}],"writeKey":"key"}"#,
+		)?;
+		fs::write(dir.path().join("settings-1"), "{}")?;
+
+		let report = import_queue(&mut store, dir.path())?;
+		assert_eq!(report.imported, 3);
+		let errors: Vec<(usize, bool)> = report
+			.errors
+			.iter()
+			.map(|e| (e.position, e.message.starts_with("key-10.tmp: ")))
+			.collect();
+		assert_eq!(errors, vec![(2, true), (3, true)]);
+
+		let batch = store.fetch(None, None)?.unwrap().data.unwrap();
+		assert_eq!(batch["batch"], json!([{"n": 1}, {"n": 2}, {"n": 3}]));
+
+		// Only the unrecognized file is left
+		let left: Vec<_> = fs::read_dir(dir.path())?
+			.map(|entry| entry.map(|e| e.file_name()))
+			.collect::<Result<_>>()?;
+		assert_eq!(left, vec!["settings-1"]);
+		Ok(())
+	}
+}
@@ -0,0 +1,361 @@
+//! A WebStore owned by one thread and used from others, for threaded WASM builds.
+
+use crate::web::{StoredEvent, WebStore};
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	Attachment, AttachmentHandle, BatchPreview, Capability, DataResult, DataStore, DataStoreExt,
+	DropRecord, Equivalent, PendingSize, RemovedBatch, StoreStatus,
+};
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::{self, UnboundedSender};
+use futures_core::Stream;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future;
+use std::io::{Error, Result, Write};
+use std::pin::Pin;
+use std::time::Duration;
+
+type Command = Box<dyn FnOnce(&mut WebStore) + Send>;
+
+/// A fetch result with its removables in a form that can cross threads
+struct Detached<T = Value> {
+	data: Option<T>,
+	removable: Option<Vec<StoredEvent>>,
+	batch_id: Option<String>,
+	attachments: Option<Vec<AttachmentHandle>>,
+	remaining_items: usize,
+	remaining_bytes: u64,
+}
+
+impl<T> Detached<T> {
+	fn new(result: DataResult<T>) -> Self {
+		Self {
+			data: result.data,
+			removable: result.removable.map(|removable| stored_events(&removable)),
+			batch_id: result.batch_id,
+			attachments: result.attachments,
+			remaining_items: result.remaining_items,
+			remaining_bytes: result.remaining_bytes,
+		}
+	}
+
+	fn attach(self) -> DataResult<T> {
+		DataResult {
+			data: self.data,
+			removable: self.removable.map(boxed),
+			batch_id: self.batch_id,
+			attachments: self.attachments,
+			remaining_items: self.remaining_items,
+			remaining_bytes: self.remaining_bytes,
+		}
+	}
+}
+
+fn stored_events(removable: &[Box<dyn Equivalent>]) -> Vec<StoredEvent> {
+	removable
+		.iter()
+		.filter_map(|item| item.as_any().downcast_ref::<StoredEvent>().cloned())
+		.collect()
+}
+
+fn boxed(events: Vec<StoredEvent>) -> Vec<Box<dyn Equivalent>> {
+	events
+		.into_iter()
+		.map(|event| Box::new(event) as Box<dyn Equivalent>)
+		.collect()
+}
+
+fn owner_gone() -> Error {
+	Error::other("The thread owning the WebStore is gone")
+}
+
+/// A [`WebStore`] running on the thread that created it, used from other threads.
+///
+/// On WASM built with atomics and shared memory, [`TransientDB`](crate::TransientDB)
+/// requires `Send` stores, which a WebStore isn't: its IndexedDB handles belong to the
+/// thread (usually the main thread) that opened them. [`spawn`](Self::spawn) moves the
+/// store into a task on that thread, and the returned handle is a `Send` store whose
+/// calls are run by that task, one at a time, in the order they were made.
+///
+/// Each call blocks its thread until the owning thread has run it, so use the handle
+/// from workers only. Browsers don't allow blocking the main thread, and a call from
+/// the owning thread itself would never complete.
+///
+/// Only available with the `web` feature on `wasm32` built with the `atomics` target
+/// feature.
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use transientdb::{SharedWebStore, TransientDB, WebConfig, WebStore};
+///
+/// // On the main thread
+/// let store = WebStore::new(WebConfig {
+///     write_key: "my-key".into(),
+///     database_name: "my-events".into(),
+///     max_items: 1000,
+///     max_fetch_size: 1024 * 1024,
+/// })
+/// .await;
+/// let db = std::sync::Arc::new(TransientDB::new(SharedWebStore::spawn(store)));
+///
+/// // Hand `db` to workers, which append and drain through the main thread's store
+/// # Ok(())
+/// # }
+/// ```
+pub struct SharedWebStore {
+	commands: UnboundedSender<Command>,
+}
+
+impl SharedWebStore {
+	/// Moves `store` into a task on the current thread, which runs calls made through
+	/// the returned handle until every handle is dropped.
+	pub fn spawn(mut store: WebStore) -> Self {
+		let (commands, mut received) = mpsc::unbounded::<Command>();
+		wasm_bindgen_futures::spawn_local(async move {
+			while let Some(command) =
+				future::poll_fn(|cx| Pin::new(&mut received).poll_next(cx)).await
+			{
+				command(&mut store);
+			}
+		});
+		Self { commands }
+	}
+
+	/// Runs `f` on the owning thread and returns its result, for the WebStore methods
+	/// outside [`DataStore`] such as [`WebStore::set_limits`].
+	///
+	/// Fails if the owning thread's task is gone.
+	pub fn execute<R, F>(&self, f: F) -> Result<R>
+	where
+		F: FnOnce(&mut WebStore) -> R + Send + 'static,
+		R: Send + 'static,
+	{
+		let (reply, response) = std::sync::mpsc::channel();
+		self.commands
+			.unbounded_send(Box::new(move |store: &mut WebStore| {
+				let _ = reply.send(f(store));
+			}))
+			.map_err(|_| owner_gone())?;
+		response.recv().map_err(|_| owner_gone())
+	}
+}
+
+impl DataStore for SharedWebStore {
+	type Output = Value;
+
+	fn has_data(&self) -> bool {
+		self.execute(|store| store.has_data()).unwrap_or(false)
+	}
+
+	fn reset(&mut self) {
+		let _ = self.execute(|store| store.reset());
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		self.execute(move |store| store.append(data))?
+	}
+
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		self.execute(move |store| store.append_many(items))?
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.execute(|store| store.flush())?
+	}
+
+	fn warm_up(&mut self) -> Result<()> {
+		self.execute(|store| store.warm_up())?
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		let result = self.execute(move |store| {
+			store
+				.fetch(count, max_bytes)
+				.map(|result| result.map(Detached::new))
+		})??;
+		Ok(result.map(Detached::attach))
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		let events = stored_events(data);
+		self.execute(move |store| store.remove(&boxed(events)))?
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		self.execute(move |store| store.supports(capability))
+			.unwrap_or(false)
+	}
+
+	fn debug_config(&self) -> Value {
+		self.execute(|store| store.debug_config())
+			.unwrap_or_else(|_| Value::Object(Default::default()))
+	}
+}
+
+impl DataStoreExt for SharedWebStore {
+	fn append_with_attachments(&mut self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		self.execute(move |store| store.append_with_attachments(data, attachments))?
+	}
+
+	fn append_pinned(&mut self, data: Value) -> Result<()> {
+		self.execute(move |store| store.append_pinned(data))?
+	}
+
+	fn append_for(&mut self, write_key: &str, data: Value) -> Result<()> {
+		let write_key = write_key.to_string();
+		self.execute(move |store| store.append_for(&write_key, data))?
+	}
+
+	fn append_delayed(&mut self, data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		self.execute(move |store| store.append_delayed(data, not_before))?
+	}
+
+	fn upsert(&mut self, key: &str, data: Value) -> Result<()> {
+		let key = key.to_string();
+		self.execute(move |store| store.upsert(&key, data))?
+	}
+
+	fn reserve(&mut self, estimated_bytes: usize) -> Result<()> {
+		self.execute(move |store| store.reserve(estimated_bytes))?
+	}
+
+	fn commit_reserved(&mut self, data: Value, estimated_bytes: usize) -> Result<()> {
+		self.execute(move |store| store.commit_reserved(data, estimated_bytes))?
+	}
+
+	fn release_reserved(&mut self, estimated_bytes: usize) {
+		let _ = self.execute(move |store| store.release_reserved(estimated_bytes));
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		let batch_id = batch_id.to_string();
+		self.execute(move |store| store.mark_delivered(&batch_id))?
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		let batch_id = batch_id.to_string();
+		self.execute(move |store| store.is_delivered(&batch_id))
+			.unwrap_or(false)
+	}
+
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.execute(move |store| store.set_allowed_categories(categories))?
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		self.execute(|store| store.purge_revoked())?
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		let anonymizer = anonymizer.clone();
+		self.execute(move |store| store.anonymize(&anonymizer))?
+	}
+
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		self.execute(|store| store.drop_report())?
+	}
+
+	fn begin_move(&mut self, move_id: &str, count: usize) -> Result<Vec<Value>> {
+		let move_id = move_id.to_string();
+		self.execute(move |store| store.begin_move(&move_id, count))?
+	}
+
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		let move_id = move_id.to_string();
+		self.execute(move |store| store.stage_move(&move_id, items))?
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		let move_id = move_id.to_string();
+		self.execute(move |store| store.release_move(&move_id))?
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		let move_id = move_id.to_string();
+		self.execute(move |store| store.discard_move(&move_id))?
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		self.execute(|store| store.held_moves())?
+	}
+
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		self.execute(|store| store.recently_removed())?
+	}
+
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		let batch_id = batch_id.to_string();
+		self.execute(move |store| store.replay(&batch_id))?
+	}
+
+	/// The writer can't be sent to the owning thread, so the envelope is streamed into a
+	/// buffer there and copied to `writer` here.
+	fn write_batch_to(
+		&mut self,
+		writer: &mut dyn Write,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		let (result, buffer) = self.execute(move |store| {
+			let mut buffer = Vec::new();
+			let result = store
+				.write_batch_to(&mut buffer, count, max_bytes)
+				.map(|result| result.map(Detached::new));
+			(result, buffer)
+		})?;
+		let result = result?;
+		writer.write_all(&buffer)?;
+		Ok(result.map(Detached::attach))
+	}
+
+	fn fetch_older_than(&mut self, age: Duration) -> Result<Option<DataResult<Self::Output>>> {
+		let result = self.execute(move |store| {
+			store
+				.fetch_older_than(age)
+				.map(|result| result.map(Detached::new))
+		})??;
+		Ok(result.map(Detached::attach))
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		self.execute(move |store| store.status())?
+	}
+
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.execute(move |store| store.expire_older_than(age))?
+	}
+
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		self.execute(move |store| store.preview_fetch(count, max_bytes))?
+	}
+
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		self.execute(|store| store.enqueue_times())?
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		self.execute(|store| store.pending_size())?
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		self.execute(|store| store.pending_events())?
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.execute(|store| store.oldest_enqueue_time())
+			.ok()
+			.flatten()
+	}
+}
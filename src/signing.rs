@@ -0,0 +1,518 @@
+//! Signing of fetched batch envelopes.
+
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	Attachment, BatchPreview, Capability, DataResult, DataStore, DataStoreExt, DropRecord,
+	Equivalent, JsonFormat, PendingSize, RemovedBatch, StoreStatus,
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{self, Result, Write};
+use std::time::Duration;
+
+/// Signs batch envelopes for a [`SignedStore`].
+///
+/// Implement this to sign with any algorithm, e.g. Ed25519 through `ed25519-dalek`, or
+/// with a key that never leaves a platform keystore (Keychain, Android Keystore) by
+/// calling into it from [`sign`](Self::sign). With the `signing` feature,
+/// `HmacSha256Signer` is provided.
+pub trait BatchSigner: Send + Sync {
+	/// Name of the algorithm, sent to the server as the signature's `alg`,
+	/// e.g. `"hmac-sha256"` or `"ed25519"`.
+	fn algorithm(&self) -> &str;
+
+	/// Identifies the signing key, sent as the signature's `keyId`, so the server can
+	/// pick the right key to verify with while keys are being rotated.
+	fn key_id(&self) -> Option<&str> {
+		None
+	}
+
+	/// Signs `message`, returning the raw signature bytes.
+	fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// A store whose fetched batch envelopes carry a signature, so the server can verify a
+/// batch came from an authentic client.
+///
+/// Wraps any store returning `{"batch": [...], ...}` envelopes (MemoryStore, WebStore,
+/// DirectoryContentStore) and adds a field to each fetched envelope:
+///
+/// ```json
+/// "signature": {"alg": "hmac-sha256", "keyId": "2024-06", "value": "<hex>"}
+/// ```
+///
+/// `keyId` is left out if the signer has none. The signature covers the envelope
+/// without its `signature` field, serialized as [`JsonFormat::Canonical`] (see
+/// [`signing_payload`](Self::signing_payload)); the envelope includes `batchId` and
+/// `sentAt`, so a server can also reject replays. Splitting a signed batch, e.g. with
+/// [`BatchChunker`](crate::BatchChunker), leaves chunks the signature doesn't cover.
+///
+/// # Examples
+/// ```
+/// use serde_json::json;
+/// use transientdb::{BatchSigner, MemoryConfig, MemoryStore, SignedStore, TransientDB};
+///
+/// struct Keystore;
+///
+/// impl BatchSigner for Keystore {
+///     fn algorithm(&self) -> &str {
+///         "ed25519"
+///     }
+///
+///     fn sign(&self, message: &[u8]) -> std::io::Result<Vec<u8>> {
+///         // Call the platform keystore here; the key never enters the process
+///         Ok(message.iter().rev().copied().collect())
+///     }
+/// }
+///
+/// let store = MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// });
+/// let db = TransientDB::new(SignedStore::new(store, Keystore));
+/// db.append(json!({"event": "test"}))?;
+///
+/// let envelope = db.fetch(None, None)?.unwrap().data.unwrap();
+/// assert_eq!(envelope["signature"]["alg"], "ed25519");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SignedStore<S> {
+	store: S,
+	signer: Box<dyn BatchSigner>,
+}
+
+impl<S> SignedStore<S> {
+	/// Wraps a store so that fetched envelopes are signed by `signer`.
+	pub fn new(store: S, signer: impl BatchSigner + 'static) -> Self {
+		Self {
+			store,
+			signer: Box::new(signer),
+		}
+	}
+
+	/// Returns the wrapped store.
+	pub fn inner(&self) -> &S {
+		&self.store
+	}
+
+	/// Returns the wrapped store mutably, e.g. to change its settings.
+	pub fn inner_mut(&mut self) -> &mut S {
+		&mut self.store
+	}
+
+	/// Unwraps the store.
+	pub fn into_inner(self) -> S {
+		self.store
+	}
+
+	/// Returns the bytes a signature covers: the envelope without its `signature` field,
+	/// as canonical JSON. Servers verify a batch by recomputing this.
+	pub fn signing_payload(envelope: &Value) -> String {
+		let mut unsigned = envelope.clone();
+		if let Some(fields) = unsigned.as_object_mut() {
+			fields.remove("signature");
+		}
+		JsonFormat::Canonical.serialize(&unsigned)
+	}
+
+	fn sign(&self, envelope: &mut Value) -> Result<()> {
+		let signature = self
+			.signer
+			.sign(Self::signing_payload(envelope).as_bytes())?;
+		let mut field = json!({
+			"alg": self.signer.algorithm(),
+			"value": signature.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+		});
+		if let Some(key_id) = self.signer.key_id() {
+			field["keyId"] = json!(key_id);
+		}
+
+		envelope
+			.as_object_mut()
+			.ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::InvalidData,
+					"Batch envelope is not an object",
+				)
+			})?
+			.insert("signature".to_string(), field);
+		Ok(())
+	}
+}
+
+impl<S: DataStoreExt<Output = Value>> DataStore for SignedStore<S> {
+	type Output = Value;
+
+	fn has_data(&self) -> bool {
+		self.store.has_data()
+	}
+
+	fn reset(&mut self) {
+		self.store.reset()
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		self.store.append(data)
+	}
+
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		self.store.append_many(items)
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.store.flush()
+	}
+
+	fn warm_up(&mut self) -> Result<()> {
+		self.store.warm_up()
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		let Some(mut result) = self.store.fetch(count, max_bytes)? else {
+			return Ok(None);
+		};
+		if let Some(envelope) = result.data.as_mut() {
+			self.sign(envelope)?;
+		}
+		Ok(Some(result))
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		self.store.remove(data)
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		// Batches are written from signed fetches
+		capability == Capability::WriteBatchTo || self.store.supports(capability)
+	}
+
+	fn debug_config(&self) -> Value {
+		let mut config = self.store.debug_config();
+		if let Some(fields) = config.as_object_mut() {
+			fields.insert(
+				"signer".to_string(),
+				json!({"alg": self.signer.algorithm(), "keyId": self.signer.key_id()}),
+			);
+		}
+		config
+	}
+}
+
+impl<S: DataStoreExt<Output = Value>> DataStoreExt for SignedStore<S> {
+	fn append_with_attachments(&mut self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		self.store.append_with_attachments(data, attachments)
+	}
+
+	fn append_pinned(&mut self, data: Value) -> Result<()> {
+		self.store.append_pinned(data)
+	}
+
+	fn append_for(&mut self, write_key: &str, data: Value) -> Result<()> {
+		self.store.append_for(write_key, data)
+	}
+
+	fn append_delayed(&mut self, data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		self.store.append_delayed(data, not_before)
+	}
+
+	fn upsert(&mut self, key: &str, data: Value) -> Result<()> {
+		self.store.upsert(key, data)
+	}
+
+	fn reserve(&mut self, estimated_bytes: usize) -> Result<()> {
+		self.store.reserve(estimated_bytes)
+	}
+
+	fn commit_reserved(&mut self, data: Value, estimated_bytes: usize) -> Result<()> {
+		self.store.commit_reserved(data, estimated_bytes)
+	}
+
+	fn release_reserved(&mut self, estimated_bytes: usize) {
+		self.store.release_reserved(estimated_bytes)
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		self.store.mark_delivered(batch_id)
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.store.is_delivered(batch_id)
+	}
+
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.store.set_allowed_categories(categories)
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		self.store.purge_revoked()
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		self.store.anonymize(anonymizer)
+	}
+
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		self.store.drop_report()
+	}
+
+	fn begin_move(&mut self, move_id: &str, count: usize) -> Result<Vec<Value>> {
+		self.store.begin_move(move_id, count)
+	}
+
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		self.store.stage_move(move_id, items)
+	}
+
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		self.store.release_move(move_id)
+	}
+
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		self.store.discard_move(move_id)
+	}
+
+	fn held_moves(&self) -> Result<Vec<String>> {
+		self.store.held_moves()
+	}
+
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		self.store.recently_removed()
+	}
+
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		self.store.replay(batch_id)
+	}
+
+	/// The signature covers the whole envelope, so it is built and signed as by
+	/// [`fetch`](DataStore::fetch) before being written.
+	fn write_batch_to(
+		&mut self,
+		writer: &mut dyn Write,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		let Some(result) = self.fetch(count, max_bytes)? else {
+			return Ok(None);
+		};
+		let json = serde_json::to_vec(&result.data).map_err(io::Error::from)?;
+		writer.write_all(&json)?;
+		Ok(Some(DataResult {
+			data: Some(json.len() as u64),
+			removable: result.removable,
+			batch_id: result.batch_id,
+			attachments: result.attachments,
+			remaining_items: result.remaining_items,
+			remaining_bytes: result.remaining_bytes,
+		}))
+	}
+
+	fn fetch_older_than(&mut self, age: Duration) -> Result<Option<DataResult<Value>>> {
+		let Some(mut result) = self.store.fetch_older_than(age)? else {
+			return Ok(None);
+		};
+		if let Some(envelope) = result.data.as_mut() {
+			self.sign(envelope)?;
+		}
+		Ok(Some(result))
+	}
+
+	fn status(&self) -> Result<StoreStatus> {
+		self.store.status()
+	}
+
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.store.expire_older_than(age)
+	}
+
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		self.store.preview_fetch(count, max_bytes)
+	}
+
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		self.store.enqueue_times()
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		self.store.pending_size()
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		self.store.pending_events()
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.store.oldest_enqueue_time()
+	}
+
+	fn event_removables(
+		&self,
+		removable: Vec<Box<dyn Equivalent>>,
+	) -> Result<Vec<Box<dyn Equivalent>>> {
+		self.store.event_removables(removable)
+	}
+}
+
+/// Signs batches with HMAC-SHA256 using a shared secret.
+///
+/// Anyone holding the key can produce valid signatures, so this proves a batch came
+/// from a build that was given the key, not from a particular device. Requires the
+/// `signing` feature.
+///
+/// # Examples
+/// ```
+/// use transientdb::{BatchSigner, HmacSha256Signer};
+///
+/// let signer = HmacSha256Signer::new(b"build secret".to_vec()).with_key_id("2024-06");
+/// assert_eq!(signer.sign(b"payload")?.len(), 32);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "signing")]
+pub struct HmacSha256Signer {
+	key: Vec<u8>,
+	key_id: Option<String>,
+}
+
+#[cfg(feature = "signing")]
+impl HmacSha256Signer {
+	/// Creates a signer using `key` as the HMAC secret.
+	pub fn new(key: impl Into<Vec<u8>>) -> Self {
+		Self {
+			key: key.into(),
+			key_id: None,
+		}
+	}
+
+	/// Sets the key id sent with each signature.
+	pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+		self.key_id = Some(key_id.into());
+		self
+	}
+}
+
+#[cfg(feature = "signing")]
+impl BatchSigner for HmacSha256Signer {
+	fn algorithm(&self) -> &str {
+		"hmac-sha256"
+	}
+
+	fn key_id(&self) -> Option<&str> {
+		self.key_id.as_deref()
+	}
+
+	fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+		use hmac::{Hmac, Mac};
+
+		let mut mac = Hmac::<sha2::Sha256>::new_from_slice(&self.key)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+		mac.update(message);
+		Ok(mac.finalize().into_bytes().to_vec())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{BatchSigner, SignedStore};
+	use crate::{DataStore, DataStoreExt, MemoryConfig, MemoryStore};
+	use serde_json::json;
+	use std::io::Result;
+
+	/// Signs with the checksum of the message, so tests can recompute it
+	struct SumSigner;
+
+	impl BatchSigner for SumSigner {
+		fn algorithm(&self) -> &str {
+			"sum"
+		}
+
+		fn key_id(&self) -> Option<&str> {
+			Some("test")
+		}
+
+		fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+			let sum = message
+				.iter()
+				.fold(0u32, |sum, b| sum.wrapping_add(*b as u32));
+			Ok(sum.to_be_bytes().to_vec())
+		}
+	}
+
+	#[test]
+	fn test_fetched_envelopes_are_signed() -> Result<()> {
+		let store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		let mut store = SignedStore::new(store, SumSigner);
+		store.append(json!({"event": "a"}))?;
+		store.append(json!({"event": "b"}))?;
+
+		let result = store.fetch(None, None)?.unwrap();
+		let envelope = result.data.unwrap();
+		let signature = &envelope["signature"];
+		assert_eq!(signature["alg"], "sum");
+		assert_eq!(signature["keyId"], "test");
+
+		// The server recomputes the signature over the envelope without it
+		let payload = SignedStore::<MemoryStore>::signing_payload(&envelope);
+		assert!(!payload.contains("signature"));
+		assert!(payload.contains(result.batch_id.as_deref().unwrap()));
+		let expected: String = SumSigner
+			.sign(payload.as_bytes())?
+			.iter()
+			.map(|b| format!("{:02x}", b))
+			.collect();
+		assert_eq!(signature["value"], expected);
+
+		store.remove(&result.removable.unwrap())?;
+		assert!(!store.has_data());
+		Ok(())
+	}
+
+	#[test]
+	fn test_written_batches_are_signed() -> Result<()> {
+		let store = MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 100,
+			max_fetch_size: 1024,
+		});
+		let mut store = SignedStore::new(store, SumSigner);
+		store.append(json!({"event": "a"}))?;
+
+		let mut out = Vec::new();
+		let result = store.write_batch_to(&mut out, None, None)?.unwrap();
+		assert_eq!(result.data, Some(out.len() as u64));
+		let envelope: serde_json::Value = serde_json::from_slice(&out)?;
+		assert_eq!(envelope["signature"]["alg"], "sum");
+		assert_eq!(envelope["batchId"], result.batch_id.unwrap().as_str());
+		Ok(())
+	}
+
+	#[cfg(feature = "signing")]
+	#[test]
+	fn test_hmac_sha256_matches_rfc_4231() -> Result<()> {
+		use super::HmacSha256Signer;
+
+		// RFC 4231 test case 2
+		let signature =
+			HmacSha256Signer::new(b"Jefe".to_vec()).sign(b"what do ya want for nothing?")?;
+		let hex: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+		assert_eq!(
+			hex,
+			"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+		);
+		Ok(())
+	}
+}
@@ -0,0 +1,363 @@
+//! Deterministic in-memory filesystem and clock for simulation tests.
+//!
+//! [`SimFs`] keeps files in memory and can be told to crash at any operation, so tests
+//! can enumerate every crash point of a workload: run it once to count the operations,
+//! then rerun it crashing at each one, reopen the store on the surviving state and check
+//! what was recovered.
+
+use crate::fs::{Fs, FsEntry, FsMetadata, FsWriter, TimeSource};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, IoSlice, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// A manually advanced clock.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct SimClock {
+	now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl SimClock {
+	/// Creates a clock stopped at `start`.
+	pub fn new(start: DateTime<Utc>) -> Self {
+		Self {
+			now: Arc::new(Mutex::new(start)),
+		}
+	}
+
+	/// Moves the clock forward.
+	pub fn advance(&self, by: Duration) {
+		let mut now = self.now.lock().unwrap_or_else(PoisonError::into_inner);
+		let by = chrono::Duration::from_std(by).unwrap_or(chrono::Duration::zero());
+		*now = now.checked_add_signed(by).unwrap_or(*now);
+	}
+}
+
+impl Default for SimClock {
+	/// A clock stopped at 2024-01-01T00:00:00Z.
+	fn default() -> Self {
+		Self::new(
+			Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0)
+				.single()
+				.unwrap_or_default(),
+		)
+	}
+}
+
+impl TimeSource for SimClock {
+	fn now(&self) -> DateTime<Utc> {
+		*self.now.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+#[derive(Debug, Clone)]
+struct SimFile {
+	data: Vec<u8>,
+	created: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct SimState {
+	files: BTreeMap<PathBuf, SimFile>,
+	dirs: BTreeSet<PathBuf>,
+	/// Mutating operations performed so far
+	ops: u64,
+	/// Operation number at which to crash
+	crash_at: Option<u64>,
+	crashed: bool,
+}
+
+/// An in-memory filesystem with deterministic behavior and injectable crashes.
+///
+/// Clones share the same files, so a test can keep one handle while the store owns
+/// another, and reopen a store on the same state after a crash.
+///
+/// Every mutating operation (creating, writing to, renaming or removing a file) counts as
+/// one step. After [`crash_at`](Self::crash_at) steps, the next one fails; a write that
+/// fails this way is torn, keeping only the first half of its bytes. From then on every
+/// operation fails, as if the process had died, until [`recover`](Self::recover).
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use serde_json::json;
+/// use transientdb::{DataStore, DataStoreExt, DirectoryConfig, DirectoryStore, SimClock, SimFs};
+///
+/// let clock = SimClock::default();
+/// let fs = SimFs::new(clock.clone());
+/// let config = DirectoryConfig {
+///     write_key: "test".into(),
+///     storage_location: PathBuf::from("/sim/events"),
+///     base_filename: "events".into(),
+///     max_file_size: 1024,
+/// };
+///
+/// let mut store = DirectoryStore::with_fs(
+///     config.clone(),
+///     Arc::new(fs.clone()),
+///     Arc::new(clock.clone()),
+/// ).unwrap();
+/// store.append(json!({"event": "kept"})).unwrap();
+///
+/// // Crash on the next operation
+/// fs.crash_at(fs.ops());
+/// assert!(store.append(json!({"event": "torn"})).is_err());
+/// drop(store);
+///
+/// // Restart on whatever made it to "disk"
+/// fs.recover();
+/// let store = DirectoryStore::with_fs(config, Arc::new(fs.clone()), Arc::new(clock)).unwrap();
+/// assert_eq!(store.pending_events().unwrap(), vec![json!({"event": "kept"})]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SimFs {
+	state: Arc<Mutex<SimState>>,
+	clock: SimClock,
+}
+
+impl SimFs {
+	/// Creates an empty filesystem whose file creation times come from `clock`.
+	pub fn new(clock: SimClock) -> Self {
+		Self {
+			state: Arc::new(Mutex::new(SimState::default())),
+			clock,
+		}
+	}
+
+	/// Number of mutating operations performed so far.
+	pub fn ops(&self) -> u64 {
+		self.state().ops
+	}
+
+	/// Makes operation number `op` (counting from zero) fail and crash the filesystem.
+	pub fn crash_at(&self, op: u64) {
+		self.state().crash_at = Some(op);
+	}
+
+	/// Whether a crash has happened and not yet been recovered from.
+	pub fn crashed(&self) -> bool {
+		self.state().crashed
+	}
+
+	/// Clears a crash, keeping files as they were when it happened.
+	pub fn recover(&self) {
+		let mut state = self.state();
+		state.crashed = false;
+		state.crash_at = None;
+	}
+
+	/// Lists all files, in path order.
+	pub fn files(&self) -> Vec<PathBuf> {
+		self.state().files.keys().cloned().collect()
+	}
+
+	fn state(&self) -> MutexGuard<'_, SimState> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn crash_error() -> io::Error {
+		io::Error::other("Simulated crash")
+	}
+
+	/// Locks the state for an operation that doesn't change anything.
+	fn read_op(&self) -> Result<MutexGuard<'_, SimState>> {
+		let state = self.state();
+		if state.crashed {
+			return Err(Self::crash_error());
+		}
+		Ok(state)
+	}
+
+	/// Locks the state for a mutating operation, crashing if this is the chosen one.
+	/// Returns whether the operation is the one that crashes, so writes can be torn.
+	fn write_op(&self) -> Result<(MutexGuard<'_, SimState>, bool)> {
+		let mut state = self.read_op()?;
+		let op = state.ops;
+		state.ops += 1;
+		let crashing = state.crash_at == Some(op);
+		if crashing {
+			state.crashed = true;
+		}
+		Ok((state, crashing))
+	}
+
+	fn not_found(path: &Path) -> io::Error {
+		io::Error::new(
+			io::ErrorKind::NotFound,
+			format!("No such file: {}", path.display()),
+		)
+	}
+
+	fn check_parent(state: &SimState, path: &Path) -> Result<()> {
+		match path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() && !state.dirs.contains(parent) => {
+				Err(Self::not_found(parent))
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Fs for SimFs {
+	fn create_dir_all(&self, path: &Path) -> Result<()> {
+		let mut state = self.read_op()?;
+		for ancestor in path.ancestors() {
+			if !ancestor.as_os_str().is_empty() {
+				state.dirs.insert(ancestor.to_owned());
+			}
+		}
+		Ok(())
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<FsEntry>> {
+		let state = self.read_op()?;
+		if !state.dirs.contains(path) {
+			return Err(Self::not_found(path));
+		}
+		let files = state.files.keys().map(|file| (file, true));
+		let dirs = state.dirs.iter().map(|dir| (dir, false));
+		Ok(files
+			.chain(dirs)
+			.filter(|(entry, _)| entry.parent() == Some(path))
+			.map(|(entry, is_file)| FsEntry {
+				path: entry.clone(),
+				is_file,
+				is_symlink: false,
+			})
+			.collect())
+	}
+
+	fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+		let state = self.read_op()?;
+		let file = state.files.get(path).ok_or_else(|| Self::not_found(path))?;
+		Ok(FsMetadata {
+			len: file.data.len() as u64,
+			created: Some(file.created),
+		})
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>> {
+		let state = self.read_op()?;
+		let file = state.files.get(path).ok_or_else(|| Self::not_found(path))?;
+		Ok(file.data.clone())
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+		let (mut state, crashing) = self.write_op()?;
+		Self::check_parent(&state, path)?;
+		let kept = if crashing {
+			&contents[..contents.len() / 2]
+		} else {
+			contents
+		};
+		let created = self.clock.now();
+		let file = state.files.entry(path.to_owned()).or_insert(SimFile {
+			data: Vec::new(),
+			created,
+		});
+		file.data = kept.to_vec();
+		if crashing {
+			return Err(Self::crash_error());
+		}
+		Ok(())
+	}
+
+	fn create_new(&self, path: &Path) -> Result<FsWriter> {
+		let (mut state, crashing) = self.write_op()?;
+		if crashing {
+			return Err(Self::crash_error());
+		}
+		Self::check_parent(&state, path)?;
+		if state.files.contains_key(path) {
+			return Err(io::Error::new(
+				io::ErrorKind::AlreadyExists,
+				format!("File exists: {}", path.display()),
+			));
+		}
+		let created = self.clock.now();
+		state.files.insert(
+			path.to_owned(),
+			SimFile {
+				data: Vec::new(),
+				created,
+			},
+		);
+		Ok(Box::new(SimWriter {
+			fs: self.clone(),
+			path: path.to_owned(),
+		}))
+	}
+
+	fn append(&self, path: &Path) -> Result<FsWriter> {
+		let state = self.read_op()?;
+		if !state.files.contains_key(path) {
+			return Err(Self::not_found(path));
+		}
+		Ok(Box::new(SimWriter {
+			fs: self.clone(),
+			path: path.to_owned(),
+		}))
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()> {
+		let (mut state, crashing) = self.write_op()?;
+		if crashing {
+			return Err(Self::crash_error());
+		}
+		state
+			.files
+			.remove(path)
+			.map(|_| ())
+			.ok_or_else(|| Self::not_found(path))
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+		let (mut state, crashing) = self.write_op()?;
+		if crashing {
+			return Err(Self::crash_error());
+		}
+		Self::check_parent(&state, to)?;
+		let file = state
+			.files
+			.remove(from)
+			.ok_or_else(|| Self::not_found(from))?;
+		state.files.insert(to.to_owned(), file);
+		Ok(())
+	}
+}
+
+/// Appends to a file in a [`SimFs`]. Like an open handle to an unlinked file, writes
+/// after the file was removed succeed but go nowhere.
+struct SimWriter {
+	fs: SimFs,
+	path: PathBuf,
+}
+
+impl Write for SimWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		let (mut state, crashing) = self.fs.write_op()?;
+		let kept = if crashing { &buf[..buf.len() / 2] } else { buf };
+		if let Some(file) = state.files.get_mut(&self.path) {
+			file.data.extend_from_slice(kept);
+		}
+		if crashing {
+			return Err(SimFs::crash_error());
+		}
+		Ok(buf.len())
+	}
+
+	/// Writes all the buffers in one operation, like a real `writev`.
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+		let buf: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+		self.write(&buf)
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		self.fs.read_op().map(|_| ())
+	}
+}
@@ -0,0 +1,331 @@
+//! Delivering queued batches to a destination.
+//!
+//! A [`Sink`] is anything batches can be delivered to: an HTTP endpoint, a Kafka topic,
+//! an S3 bucket. [`TransientDB::drain_into`](crate::TransientDB::drain_into) runs the
+//! fetch, deliver, remove/retry loop against any sink, so integrations only need to
+//! implement the delivery itself.
+
+use crate::{DataResult, RejectedEvents};
+use std::time::Duration;
+
+/// The outcome of delivering one batch to a [`Sink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryResult {
+	/// The whole batch was accepted. It is removed and marked delivered.
+	Delivered,
+	/// Only the events at these indices (within the batch) were accepted. The rest are
+	/// requeued or dropped according to [`DrainPolicy::rejected`].
+	Partial(Vec<usize>),
+	/// Delivery failed temporarily (network error, rate limiting, server error).
+	/// The batch stays queued and is retried after a delay.
+	Retry,
+	/// The destination permanently refused the batch. It is removed without being
+	/// delivered, so it can't block the queue.
+	Rejected,
+}
+
+/// A destination that batches are delivered to.
+///
+/// Implemented for closures taking a fetch result, which is handy for simple sinks
+/// and tests.
+///
+/// # Examples
+/// ```
+/// use serde_json::Value;
+/// use transientdb::{DataResult, DeliveryResult, Sink};
+///
+/// struct StdoutSink;
+///
+/// impl Sink<Value> for StdoutSink {
+///     fn deliver(&mut self, batch: &DataResult<Value>) -> DeliveryResult {
+///         match &batch.data {
+///             Some(data) => {
+///                 println!("{}", data);
+///                 DeliveryResult::Delivered
+///             }
+///             None => DeliveryResult::Rejected,
+///         }
+///     }
+/// }
+/// ```
+pub trait Sink<T> {
+	/// Delivers a fetched batch, reporting how it went.
+	fn deliver(&mut self, batch: &DataResult<T>) -> DeliveryResult;
+}
+
+impl<T, F> Sink<T> for F
+where
+	F: FnMut(&DataResult<T>) -> DeliveryResult,
+{
+	fn deliver(&mut self, batch: &DataResult<T>) -> DeliveryResult {
+		self(batch)
+	}
+}
+
+/// Controls how [`TransientDB::drain_into`](crate::TransientDB::drain_into) drains the queue.
+#[derive(Debug, Clone)]
+pub struct DrainPolicy {
+	/// Maximum number of items per fetched batch (`None` for the store's default).
+	pub batch_count: Option<usize>,
+	/// Maximum size in bytes per fetched batch (`None` for the store's default).
+	pub batch_bytes: Option<usize>,
+	/// Stop after this many batches have been delivered (`None` to drain everything).
+	pub max_batches: Option<usize>,
+	/// Give up after this many consecutive retries, leaving the remaining data queued.
+	pub max_retries: u32,
+	/// Delay before the first retry. Doubles with each consecutive retry.
+	/// Retries aren't delayed on WASM, where the thread can't sleep.
+	pub retry_delay: Duration,
+	/// What to do with events a sink rejects in a [`DeliveryResult::Partial`] result.
+	pub rejected: RejectedEvents,
+}
+
+impl Default for DrainPolicy {
+	fn default() -> Self {
+		Self {
+			batch_count: None,
+			batch_bytes: None,
+			max_batches: None,
+			max_retries: 3,
+			retry_delay: Duration::from_millis(500),
+			rejected: RejectedEvents::default(),
+		}
+	}
+}
+
+/// What a drain did.
+///
+/// Items are counted by their removables: events for MemoryStore, WebStore and
+/// DirectoryContentStore, files for DirectoryStore.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrainSummary {
+	/// Batches fully or partially accepted by the sink.
+	pub batches_sent: usize,
+	/// Items accepted by the sink and removed from the queue.
+	pub events_sent: usize,
+	/// Items removed from the queue without being delivered.
+	pub events_dropped: usize,
+	/// Number of retries performed.
+	pub retries: u32,
+	/// Whether the drain stopped because `max_retries` was exceeded.
+	pub gave_up: bool,
+	/// Whether the drain ran out of time with data still queued.
+	pub timed_out: bool,
+	/// How long the drain took.
+	pub duration: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{DeliveryResult, DrainPolicy};
+	use crate::{
+		DataResult, DirectoryConfig, DirectoryStore, MemoryConfig, MemoryStore, RejectedEvents,
+		TransientDB,
+	};
+	use serde_json::{json, Value};
+	use std::fs;
+	use std::io::Result;
+	use std::path::PathBuf;
+	use std::time::Duration;
+	use tempfile::TempDir;
+
+	fn db(events: usize) -> Result<TransientDB<Value>> {
+		let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test-key".to_string(),
+			max_items: 1000,
+			max_fetch_size: 10_000,
+		}));
+		for i in 0..events {
+			db.append(json!({"index": i}))?;
+		}
+		Ok(db)
+	}
+
+	fn policy() -> DrainPolicy {
+		DrainPolicy {
+			batch_count: Some(10),
+			retry_delay: Duration::from_millis(1),
+			..DrainPolicy::default()
+		}
+	}
+
+	#[test]
+	fn test_drain_delivers_everything() -> Result<()> {
+		let db = db(25)?;
+		let mut received = Vec::new();
+		let summary = db.drain_into(
+			&mut |batch: &DataResult<Value>| {
+				for item in batch.data.as_ref().unwrap()["batch"].as_array().unwrap() {
+					received.push(item["index"].as_i64().unwrap());
+				}
+				DeliveryResult::Delivered
+			},
+			&policy(),
+		)?;
+
+		assert_eq!(summary.batches_sent, 3);
+		assert_eq!(summary.events_sent, 25);
+		assert_eq!(received, (0..25).collect::<Vec<_>>());
+		assert!(!db.has_data());
+		Ok(())
+	}
+
+	#[test]
+	fn test_drain_retries_then_gives_up() -> Result<()> {
+		let db = db(5)?;
+		let mut attempts = 0;
+		let summary = db.drain_into(
+			&mut |_: &DataResult<Value>| {
+				attempts += 1;
+				DeliveryResult::Retry
+			},
+			&policy(),
+		)?;
+
+		assert_eq!(attempts, 4);
+		assert_eq!(summary.retries, 3);
+		assert!(summary.gave_up);
+		assert!(db.has_data(), "Undelivered data should stay queued");
+		Ok(())
+	}
+
+	#[test]
+	fn test_drain_skips_batches_already_delivered() -> Result<()> {
+		let db = db(15)?;
+		// A batch was delivered, but removing it failed
+		let batch = db.fetch(Some(10), None)?.unwrap();
+		db.mark_delivered(batch.batch_id.as_deref().unwrap())?;
+
+		let mut received = Vec::new();
+		let summary = db.drain_into(
+			&mut |batch: &DataResult<Value>| {
+				for item in batch.data.as_ref().unwrap()["batch"].as_array().unwrap() {
+					received.push(item["index"].as_i64().unwrap());
+				}
+				DeliveryResult::Delivered
+			},
+			&policy(),
+		)?;
+
+		assert_eq!(summary.batches_sent, 1);
+		assert_eq!(received, (10..15).collect::<Vec<_>>());
+		assert!(!db.has_data());
+		Ok(())
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[test]
+	fn test_drain_until_empty_times_out() -> Result<()> {
+		let db = db(5)?;
+		let summary = db.drain_until_empty(
+			&mut |_: &DataResult<Value>| DeliveryResult::Retry,
+			Duration::from_millis(300),
+		)?;
+
+		assert!(summary.timed_out);
+		assert!(!summary.gave_up);
+		assert!(summary.retries > 0);
+		assert!(summary.duration >= Duration::from_millis(300));
+		assert!(summary.duration < Duration::from_secs(5));
+		assert!(db.has_data());
+
+		// A working sink empties the queue well before the deadline
+		let summary = db.drain_until_empty(
+			&mut |_: &DataResult<Value>| DeliveryResult::Delivered,
+			Duration::from_secs(5),
+		)?;
+		assert!(!summary.timed_out);
+		assert_eq!(summary.events_sent, 5);
+		assert!(!db.has_data());
+		Ok(())
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[test]
+	fn test_drain_until_empty_ignores_events_not_yet_due() -> Result<()> {
+		use chrono::{TimeDelta, Utc};
+
+		let db = db(0)?;
+		db.append_delayed(json!({"event": "later"}), Utc::now() + TimeDelta::hours(1))?;
+
+		// Nothing can be fetched before the deadline, so nothing was left over
+		let summary = db.drain_until_empty(
+			&mut |_: &DataResult<Value>| DeliveryResult::Retry,
+			Duration::ZERO,
+		)?;
+		assert!(!summary.timed_out);
+
+		db.append(json!({"event": "now"}))?;
+		let summary = db.drain_until_empty(
+			&mut |_: &DataResult<Value>| DeliveryResult::Retry,
+			Duration::ZERO,
+		)?;
+		assert!(summary.timed_out);
+		Ok(())
+	}
+
+	#[test]
+	fn test_drain_partial_and_rejected() -> Result<()> {
+		let db = db(20)?;
+		let mut calls = 0;
+		let summary = db.drain_into(
+			&mut |batch: &DataResult<Value>| {
+				calls += 1;
+				let len = batch.removable.as_ref().unwrap().len();
+				match calls {
+					// Accept all but the first event of the first batch
+					1 => DeliveryResult::Partial((1..len).collect()),
+					_ => DeliveryResult::Rejected,
+				}
+			},
+			&DrainPolicy {
+				rejected: RejectedEvents::DeadLetter,
+				..policy()
+			},
+		)?;
+
+		assert_eq!(summary.events_sent, 9);
+		assert_eq!(summary.events_dropped, 11);
+		assert!(!db.has_data());
+		Ok(())
+	}
+
+	#[test]
+	fn test_drain_partial_from_directory_store() -> Result<()> {
+		let temp_dir = TempDir::new()?;
+		let db = TransientDB::new(DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		})?);
+		for i in 0..6 {
+			db.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+
+		// One removable per file, but the sink accepts events by their index
+		let mut calls = 0;
+		let summary = db.drain_into(
+			&mut |_: &DataResult<Vec<PathBuf>>| {
+				calls += 1;
+				match calls {
+					1 => DeliveryResult::Partial(vec![0, 2, 4]),
+					_ => DeliveryResult::Retry,
+				}
+			},
+			&policy(),
+		)?;
+		assert_eq!(summary.events_sent, 3);
+		assert!(summary.gave_up);
+
+		let mut indices = Vec::new();
+		for file in db.fetch(None, None)?.unwrap().data.unwrap() {
+			let content: Value = serde_json::from_str(&fs::read_to_string(&file)?)?;
+			for item in content["batch"].as_array().unwrap() {
+				indices.push(item["index"].as_i64().unwrap());
+			}
+		}
+		assert_eq!(indices, vec![1, 3, 5]);
+		Ok(())
+	}
+}
@@ -0,0 +1,106 @@
+//! Stored events with their serialized size measured once.
+
+use serde_json::Value;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Returns the length of `value` serialized as compact JSON, without building the string.
+pub(crate) fn serialized_len(value: &Value) -> usize {
+	let mut counter = ByteCounter(0);
+	// Writing to the counter can't fail, and a `Value` always serializes
+	let _ = serde_json::to_writer(&mut counter, value);
+	counter.0
+}
+
+/// Counts the bytes written to it.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0 += buf.len();
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// An event held by a store, with its serialized size measured when it was stored.
+///
+/// Fetches check every candidate against `max_bytes`, so measuring once at append keeps
+/// fetch planning from serializing each event again. The value is shared, so clones
+/// handed out as removables don't copy it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SizedValue {
+	value: Arc<Value>,
+	size: usize,
+}
+
+impl SizedValue {
+	pub(crate) fn new(value: Value) -> Self {
+		let size = serialized_len(&value);
+		Self {
+			value: Arc::new(value),
+			size,
+		}
+	}
+
+	/// Serialized length of the value in bytes.
+	pub(crate) fn size(&self) -> usize {
+		self.size
+	}
+
+	/// The shared value.
+	pub(crate) fn shared(&self) -> &Arc<Value> {
+		&self.value
+	}
+
+	/// Takes the value out, copying it only if it's still shared.
+	#[cfg(all(feature = "web", target_arch = "wasm32"))]
+	pub(crate) fn into_value(self) -> Value {
+		Arc::unwrap_or_clone(self.value)
+	}
+
+	/// Changes the value in place, measuring it again if `change` reports a change.
+	pub(crate) fn update(&mut self, change: impl FnOnce(&mut Value) -> bool) -> bool {
+		let changed = change(Arc::make_mut(&mut self.value));
+		if changed {
+			self.size = serialized_len(&self.value);
+		}
+		changed
+	}
+}
+
+impl Deref for SizedValue {
+	type Target = Value;
+
+	fn deref(&self) -> &Value {
+		&self.value
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{serialized_len, SizedValue};
+	use serde_json::json;
+
+	#[test]
+	fn test_size_matches_serialized_json() {
+		for value in [
+			json!(null),
+			json!("caf\u{e9} \"quoted\""),
+			json!({"event": "login", "props": {"n": [1, 2.5, -3]}}),
+		] {
+			assert_eq!(serialized_len(&value), value.to_string().len());
+		}
+
+		let mut item = SizedValue::new(json!({"event": "a"}));
+		assert!(item.update(|value| {
+			value["padding"] = json!("x".repeat(20));
+			true
+		}));
+		assert_eq!(item.size(), item.to_string().len());
+	}
+}
@@ -0,0 +1,182 @@
+//! Correcting batch timestamps for the client's clock skew.
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Field of an event holding the time it happened, corrected along with `sentAt`.
+pub(crate) const TIMESTAMP_KEY: &str = "timestamp";
+
+/// Supplies how far the server's clock is ahead of the client's, set with
+/// `set_clock_offset_provider`.
+///
+/// A store with a provider adds the offset to the `sentAt` of each batch envelope it
+/// fetches, and to the `timestamp` field of its events when that's an RFC 3339 string,
+/// so a device with a wrong clock still reports when things happened. The provider is
+/// asked at fetch time; returning None, e.g. before the server time is known, leaves
+/// timestamps as they are.
+///
+/// Implemented for [`ClockOffset`], and for closures returning an offset.
+pub trait ClockOffsetProvider: Send + Sync {
+	/// Returns the server's clock minus the client's, if known.
+	fn offset(&self) -> Option<TimeDelta>;
+}
+
+impl<F> ClockOffsetProvider for F
+where
+	F: Fn() -> Option<TimeDelta> + Send + Sync,
+{
+	fn offset(&self) -> Option<TimeDelta> {
+		self()
+	}
+}
+
+/// A clock offset shared between a store and the code that talks to the server.
+///
+/// Clones share the offset, so give one to the store with `set_clock_offset_provider`
+/// and [`observe`](Self::observe) the server's time, e.g. from the `Date` header, when
+/// responses come in.
+///
+/// # Examples
+/// ```
+/// use chrono::TimeDelta;
+/// use serde_json::json;
+/// use transientdb::{ClockOffset, DataStore, MemoryConfig, MemoryStore};
+///
+/// let mut store = MemoryStore::new(MemoryConfig {
+///     write_key: "my-key".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// });
+/// let offset = ClockOffset::new();
+/// store.set_clock_offset_provider(offset.clone());
+///
+/// store.append(json!({"event": "tap", "timestamp": "2024-05-01T12:00:00Z"}))?;
+///
+/// // The first response reveals the device clock is an hour behind, e.g. with
+/// // `offset.observe(server_time)`
+/// offset.set(TimeDelta::hours(1));
+///
+/// let envelope = store.fetch(None, None)?.unwrap().data.unwrap();
+/// assert_eq!(envelope["batch"][0]["timestamp"], "2024-05-01T13:00:00Z");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClockOffset {
+	/// Offset in milliseconds, or `UNKNOWN`
+	millis: Arc<AtomicI64>,
+}
+
+impl ClockOffset {
+	const UNKNOWN: i64 = i64::MIN;
+
+	/// Creates an offset that isn't known yet.
+	pub fn new() -> Self {
+		Self {
+			millis: Arc::new(AtomicI64::new(Self::UNKNOWN)),
+		}
+	}
+
+	/// Sets the offset from the server's current time, as reported in a response.
+	pub fn observe(&self, server_time: DateTime<Utc>) {
+		self.set(server_time - Utc::now());
+	}
+
+	/// Sets the offset: the server's clock minus the client's.
+	pub fn set(&self, offset: TimeDelta) {
+		let millis = offset.num_milliseconds().max(Self::UNKNOWN + 1);
+		self.millis.store(millis, Ordering::Relaxed);
+	}
+
+	/// Forgets the offset, leaving timestamps uncorrected.
+	pub fn clear(&self) {
+		self.millis.store(Self::UNKNOWN, Ordering::Relaxed);
+	}
+
+	/// The offset, if known.
+	pub fn get(&self) -> Option<TimeDelta> {
+		match self.millis.load(Ordering::Relaxed) {
+			Self::UNKNOWN => None,
+			millis => Some(TimeDelta::milliseconds(millis)),
+		}
+	}
+}
+
+impl Default for ClockOffset {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ClockOffsetProvider for ClockOffset {
+	fn offset(&self) -> Option<TimeDelta> {
+		self.get()
+	}
+}
+
+/// Asks a store's provider for the offset, if it has one.
+pub(crate) fn offset(provider: Option<&dyn ClockOffsetProvider>) -> Option<TimeDelta> {
+	provider?.offset().filter(|offset| !offset.is_zero())
+}
+
+/// An event with its timestamp corrected by `offset`, borrowed if there's nothing to
+/// correct.
+pub(crate) fn correct(event: &Value, offset: Option<TimeDelta>) -> Cow<'_, Value> {
+	let Some(corrected) = offset.and_then(|offset| corrected_timestamp(event, offset)) else {
+		return Cow::Borrowed(event);
+	};
+	let mut event = event.clone();
+	event[TIMESTAMP_KEY] = Value::String(corrected);
+	Cow::Owned(event)
+}
+
+/// Corrects an event's timestamp in place.
+pub(crate) fn correct_in_place(event: &mut Value, offset: Option<TimeDelta>) {
+	if let Some(corrected) = offset.and_then(|offset| corrected_timestamp(event, offset)) {
+		event[TIMESTAMP_KEY] = Value::String(corrected);
+	}
+}
+
+fn corrected_timestamp(event: &Value, offset: TimeDelta) -> Option<String> {
+	let timestamp = event.get(TIMESTAMP_KEY)?.as_str()?;
+	let time = DateTime::parse_from_rfc3339(timestamp).ok()?;
+	let corrected = time.with_timezone(&Utc).checked_add_signed(offset)?;
+	Some(corrected.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn test_correct_timestamps() {
+		let offset = Some(TimeDelta::milliseconds(-1500));
+		let event = json!({"event": "tap", "timestamp": "2024-05-01T12:00:00.250+02:00"});
+		assert_eq!(
+			correct(&event, offset)["timestamp"],
+			"2024-05-01T09:59:58.750Z"
+		);
+		assert!(matches!(correct(&event, None), Cow::Borrowed(_)));
+
+		// Events without a readable timestamp are left alone
+		for event in [json!({"event": "tap"}), json!({"timestamp": 1714564800})] {
+			assert!(matches!(correct(&event, offset), Cow::Borrowed(_)));
+		}
+
+		let clock = ClockOffset::new();
+		assert_eq!(offset_of(&clock), None);
+		clock.set(TimeDelta::seconds(30));
+		assert_eq!(offset_of(&clock), Some(TimeDelta::seconds(30)));
+		clock.set(TimeDelta::zero());
+		assert_eq!(offset_of(&clock), None);
+		clock.clear();
+		assert_eq!(clock.get(), None);
+	}
+
+	fn offset_of(provider: &dyn ClockOffsetProvider) -> Option<TimeDelta> {
+		offset(Some(provider))
+	}
+}
@@ -0,0 +1,191 @@
+//! Emergency snapshots of a queue in small key-value storage, such as localStorage.
+//!
+//! localStorage holds about 5 MB per origin and throws once a write goes past it, so a
+//! snapshot is split into chunks, written newest first. When a chunk doesn't fit, its
+//! oldest events are dropped until it does and older chunks are left out: a full quota
+//! costs the oldest events, never the whole snapshot.
+
+use serde_json::{json, Value};
+
+/// Storage a snapshot is written to.
+pub(crate) trait SnapshotStorage {
+	fn get(&self, key: &str) -> Option<String>;
+	/// Stores a value, returning false if it doesn't fit or can't be written.
+	fn set(&mut self, key: &str, value: &str) -> bool;
+	fn remove(&mut self, key: &str);
+}
+
+/// Largest chunk written, in UTF-16 code units as localStorage counts them.
+const CHUNK_UNITS: usize = 256 * 1024;
+
+fn chunk_key(prefix: &str, index: usize) -> String {
+	format!("{}:{}", prefix, index)
+}
+
+/// Number of chunks of the snapshot under `prefix`, 0 if there's none.
+fn chunk_count(storage: &dyn SnapshotStorage, prefix: &str) -> usize {
+	storage
+		.get(prefix)
+		.and_then(|manifest| serde_json::from_str::<Value>(&manifest).ok())
+		.and_then(|manifest| manifest.get("chunks")?.as_u64())
+		.and_then(|chunks| usize::try_from(chunks).ok())
+		.unwrap_or(0)
+}
+
+/// Replaces the snapshot under `prefix` with `events`, oldest first, returning how many
+/// of them fit.
+pub(crate) fn save<'v>(
+	storage: &mut dyn SnapshotStorage,
+	prefix: &str,
+	events: impl DoubleEndedIterator<Item = &'v Value>,
+) -> usize {
+	// The old snapshot's room is needed for the new one
+	clear(storage, prefix);
+
+	// Chunks of serialized events, newest first within and across chunks
+	let mut chunks: Vec<Vec<String>> = Vec::new();
+	let mut chunk = Vec::new();
+	let mut units = 2;
+	for event in events.rev() {
+		let event = event.to_string();
+		let len = event.encode_utf16().count() + 1;
+		if !chunk.is_empty() && units + len > CHUNK_UNITS {
+			chunks.push(std::mem::take(&mut chunk));
+			units = 2;
+		}
+		units += len;
+		chunk.push(event);
+	}
+	if !chunk.is_empty() {
+		chunks.push(chunk);
+	}
+
+	let (mut written, mut saved) = (0, 0);
+	for mut chunk in chunks {
+		let full = chunk.len();
+		while !chunk.is_empty() {
+			let oldest_first: Vec<&str> = chunk.iter().rev().map(String::as_str).collect();
+			let content = format!("[{}]", oldest_first.join(","));
+			if storage.set(&chunk_key(prefix, written), &content) {
+				written += 1;
+				saved += chunk.len();
+				break;
+			}
+			// Probe for what fits, dropping the oldest half each time
+			chunk.truncate(chunk.len() / 2);
+		}
+		if chunk.len() < full {
+			// The quota is used up, so older chunks won't fit either
+			break;
+		}
+	}
+
+	if written > 0 && !storage.set(prefix, &json!({"chunks": written}).to_string()) {
+		clear_chunks(storage, prefix, written);
+		return 0;
+	}
+	saved
+}
+
+/// Reads the snapshot under `prefix`, oldest event first. Chunks that can't be read
+/// are skipped.
+pub(crate) fn load(storage: &dyn SnapshotStorage, prefix: &str) -> Vec<Value> {
+	(0..chunk_count(storage, prefix))
+		.rev()
+		.filter_map(|index| storage.get(&chunk_key(prefix, index)))
+		.filter_map(|content| match serde_json::from_str(&content) {
+			Ok(Value::Array(events)) => Some(events),
+			_ => None,
+		})
+		.flatten()
+		.collect()
+}
+
+/// Deletes the snapshot under `prefix`.
+pub(crate) fn clear(storage: &mut dyn SnapshotStorage, prefix: &str) {
+	let chunks = chunk_count(storage, prefix);
+	storage.remove(prefix);
+	clear_chunks(storage, prefix, chunks);
+}
+
+fn clear_chunks(storage: &mut dyn SnapshotStorage, prefix: &str, chunks: usize) {
+	for index in 0..chunks {
+		storage.remove(&chunk_key(prefix, index));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	/// Storage with a quota on the total length of keys and values, like localStorage
+	struct QuotaStorage {
+		items: HashMap<String, String>,
+		quota: usize,
+	}
+
+	impl QuotaStorage {
+		fn used(&self) -> usize {
+			self.items.iter().map(|(k, v)| k.len() + v.len()).sum()
+		}
+	}
+
+	impl SnapshotStorage for QuotaStorage {
+		fn get(&self, key: &str) -> Option<String> {
+			self.items.get(key).cloned()
+		}
+
+		fn set(&mut self, key: &str, value: &str) -> bool {
+			let old = self.items.remove(key);
+			if self.used() + key.len() + value.len() > self.quota {
+				if let Some(old) = old {
+					self.items.insert(key.to_string(), old);
+				}
+				return false;
+			}
+			self.items.insert(key.to_string(), value.to_string());
+			true
+		}
+
+		fn remove(&mut self, key: &str) {
+			self.items.remove(key);
+		}
+	}
+
+	#[test]
+	fn test_snapshot_keeps_the_newest_events_that_fit() {
+		let mut storage = QuotaStorage {
+			items: HashMap::new(),
+			quota: usize::MAX,
+		};
+		let events: Vec<Value> = (0..1000)
+			.map(|n| json!({"n": n, "padding": "x".repeat(1000)}))
+			.collect();
+
+		// Everything fits in several chunks
+		assert_eq!(save(&mut storage, "snap", events.iter()), 1000);
+		assert!(chunk_count(&storage, "snap") > 1);
+		assert_eq!(load(&storage, "snap"), events);
+
+		// A smaller snapshot replaces it, leaving no chunks behind
+		assert_eq!(save(&mut storage, "snap", events[..2].iter()), 2);
+		assert_eq!(storage.items.len(), 2);
+		assert_eq!(load(&storage, "snap"), events[..2]);
+
+		// With room for about half, the oldest are left out
+		storage.quota = 500 * 1024;
+		let saved = save(&mut storage, "snap", events.iter());
+		assert!(saved > 100 && saved < 500, "saved {}", saved);
+		assert_eq!(load(&storage, "snap"), events[1000 - saved..]);
+
+		clear(&mut storage, "snap");
+		assert!(storage.items.is_empty());
+		assert!(load(&storage, "snap").is_empty());
+
+		// Nothing fits
+		storage.quota = 10;
+		assert_eq!(save(&mut storage, "snap", events.iter()), 0);
+		assert!(storage.items.is_empty());
+	}
+}
@@ -0,0 +1,249 @@
+//! Queue metrics.
+
+use crate::sized::serialized_len;
+use crate::JsonPointer;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How much data a store holds.
+///
+/// MemoryStore and WebStore count events and their serialized size; DirectoryStore
+/// counts data files and their size on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingSize {
+	/// Number of pending items.
+	pub items: usize,
+	/// Total size of the pending items in bytes.
+	pub bytes: u64,
+}
+
+/// What a fetch with the same limits would return, computed without building the batch.
+///
+/// Like [`PendingSize`], MemoryStore and WebStore count events and their serialized
+/// size; DirectoryStore counts data files and their size on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchPreview {
+	/// Number of items the fetch would return; 0 if it would return nothing.
+	pub items: usize,
+	/// Total size of those items in bytes.
+	pub bytes: u64,
+	/// When the oldest of those items was appended, if the store tracks it.
+	pub oldest: Option<DateTime<Utc>>,
+	/// When the newest of those items was appended, if the store tracks it.
+	pub newest: Option<DateTime<Utc>>,
+}
+
+impl BatchPreview {
+	/// Adds an item to the preview.
+	pub(crate) fn add(&mut self, bytes: u64, enqueued: Option<DateTime<Utc>>) {
+		self.items += 1;
+		self.bytes += bytes;
+		if let Some(enqueued) = enqueued {
+			self.oldest = Some(self.oldest.map_or(enqueued, |oldest| oldest.min(enqueued)));
+			self.newest = Some(self.newest.map_or(enqueued, |newest| newest.max(enqueued)));
+		}
+	}
+}
+
+/// A snapshot of a TransientDB's queue depth and operation counters.
+///
+/// Counters cover operations made through the TransientDB since it was created.
+///
+/// # Examples
+/// ```
+/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+/// use serde_json::json;
+///
+/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "test".into(),
+///     max_items: 100,
+///     max_fetch_size: 1024,
+/// }));
+/// db.append(json!({"event": "test"})).unwrap();
+///
+/// let stats = db.stats();
+/// assert_eq!(stats.pending.unwrap().items, 1);
+/// assert_eq!(stats.appended, 1);
+/// assert_eq!(stats.append_errors, 0);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueStats {
+	/// Data currently queued, if the store can report it.
+	pub pending: Option<PendingSize>,
+	/// Age of the oldest pending item, if the store tracks enqueue times.
+	pub oldest_age: Option<Duration>,
+	/// Items successfully appended.
+	pub appended: u64,
+	/// Appends that failed.
+	pub append_errors: u64,
+	/// Fetches that returned a batch.
+	pub fetched: u64,
+	/// Fetches that failed.
+	pub fetch_errors: u64,
+	/// Removable items passed to successful removals.
+	pub removed: u64,
+	/// Removals that failed.
+	pub remove_errors: u64,
+}
+
+/// Operation counters kept by a TransientDB.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+	appended: AtomicU64,
+	append_errors: AtomicU64,
+	fetched: AtomicU64,
+	fetch_errors: AtomicU64,
+	removed: AtomicU64,
+	remove_errors: AtomicU64,
+}
+
+impl Counters {
+	pub(crate) fn record_append<T>(&self, result: &Result<T>) {
+		match result {
+			Ok(_) => self.appended.fetch_add(1, Ordering::Relaxed),
+			Err(_) => self.append_errors.fetch_add(1, Ordering::Relaxed),
+		};
+	}
+
+	pub(crate) fn record_append_many<T>(&self, result: &Result<T>, items: usize) {
+		match result {
+			Ok(_) => self.appended.fetch_add(items as u64, Ordering::Relaxed),
+			Err(_) => self.append_errors.fetch_add(1, Ordering::Relaxed),
+		};
+	}
+
+	pub(crate) fn record_fetch<T>(&self, result: &Result<Option<T>>) {
+		match result {
+			Ok(Some(_)) => self.fetched.fetch_add(1, Ordering::Relaxed),
+			Ok(None) => 0,
+			Err(_) => self.fetch_errors.fetch_add(1, Ordering::Relaxed),
+		};
+	}
+
+	pub(crate) fn record_remove<T>(&self, result: &Result<T>, items: usize) {
+		match result {
+			Ok(_) => self.removed.fetch_add(items as u64, Ordering::Relaxed),
+			Err(_) => self.remove_errors.fetch_add(1, Ordering::Relaxed),
+		};
+	}
+
+	/// Fills in the counter fields of a snapshot.
+	pub(crate) fn snapshot(&self, stats: &mut QueueStats) {
+		stats.appended = self.appended.load(Ordering::Relaxed);
+		stats.append_errors = self.append_errors.load(Ordering::Relaxed);
+		stats.fetched = self.fetched.load(Ordering::Relaxed);
+		stats.fetch_errors = self.fetch_errors.load(Ordering::Relaxed);
+		stats.removed = self.removed.load(Ordering::Relaxed);
+		stats.remove_errors = self.remove_errors.load(Ordering::Relaxed);
+	}
+}
+
+/// Running size statistics of the events of one type, from
+/// [`stats_by_type()`](crate::TransientDB::stats_by_type).
+///
+/// Sizes are those of the events serialized as compact JSON, as appended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeStats {
+	/// Events of the type appended.
+	pub count: u64,
+	/// Total size of those events in bytes.
+	pub total_bytes: u64,
+	/// Size of the largest of them in bytes.
+	pub max_bytes: u64,
+}
+
+impl TypeStats {
+	/// Average size of the events in bytes, or 0 if there were none.
+	pub fn avg_bytes(&self) -> u64 {
+		self.total_bytes.checked_div(self.count).unwrap_or(0)
+	}
+}
+
+/// Per-type statistics of appended events, keyed by the field a pointer names.
+pub(crate) struct TypeTracker {
+	key: JsonPointer,
+	types: HashMap<String, TypeStats>,
+}
+
+impl TypeTracker {
+	/// Number of types tracked; events of further types are counted under the empty
+	/// type, so a field with unbounded values can't grow the map without limit.
+	pub(crate) const MAX_TYPES: usize = 256;
+
+	pub(crate) fn new(key: JsonPointer) -> Self {
+		Self {
+			key,
+			types: HashMap::new(),
+		}
+	}
+
+	/// The type and size of an event. The type is the field's value if it's a string,
+	/// its JSON text for other values, and empty if the event doesn't have it.
+	pub(crate) fn measure(&self, event: &Value) -> (String, u64) {
+		let kind = match self.key.get(event) {
+			Some(Value::String(kind)) => kind.clone(),
+			Some(other) => other.to_string(),
+			None => String::new(),
+		};
+		(kind, serialized_len(event) as u64)
+	}
+
+	pub(crate) fn record(&mut self, kind: String, bytes: u64) {
+		let kind = if self.types.len() >= Self::MAX_TYPES && !self.types.contains_key(&kind) {
+			String::new()
+		} else {
+			kind
+		};
+		let stats = self.types.entry(kind).or_default();
+		stats.count += 1;
+		stats.total_bytes += bytes;
+		stats.max_bytes = stats.max_bytes.max(bytes);
+	}
+
+	pub(crate) fn stats(&self) -> HashMap<String, TypeStats> {
+		self.types.clone()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn test_type_tracker() -> Result<()> {
+		let mut tracker = TypeTracker::new(JsonPointer::new("/event")?);
+		for event in [
+			json!({"event": "tap"}),
+			json!({"event": "tap", "x": 100}),
+			json!({"event": 7}),
+			json!({"other": true}),
+		] {
+			let (kind, bytes) = tracker.measure(&event);
+			tracker.record(kind, bytes);
+		}
+
+		let stats = tracker.stats();
+		let tap = stats["tap"];
+		assert_eq!(tap.count, 2);
+		assert_eq!(
+			tap.max_bytes,
+			serialized_len(&json!({"event": "tap", "x": 100})) as u64
+		);
+		assert_eq!(tap.avg_bytes(), tap.total_bytes / 2);
+		assert_eq!(stats["7"].count, 1);
+		assert_eq!(stats[""].count, 1);
+
+		for i in 0..TypeTracker::MAX_TYPES {
+			tracker.record(format!("type-{}", i), 1);
+		}
+		assert_eq!(tracker.stats().len(), TypeTracker::MAX_TYPES);
+		// "tap", "7" and "" were tracked first, so the last 3 types are counted under ""
+		assert_eq!(tracker.stats()[""].count, 1 + 3);
+		Ok(())
+	}
+}
@@ -0,0 +1,26 @@
+//! Where a store keeps its events, for apps that hold it behind a [`TransientDB`](crate::TransientDB).
+
+/// Whether a store's events survive a restart, from
+/// [`status()`](crate::DataStoreExt::status).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StoreStatus {
+	/// Events are written to durable storage, such as files or IndexedDB.
+	Persisted,
+	/// Events are held in memory only and are lost when the process or page goes away.
+	MemoryOnly {
+		/// Why the store couldn't persist, or `None` for stores that never do, such as
+		/// MemoryStore.
+		reason: Option<String>,
+	},
+	/// Storage is still being opened, as by a WebStore made with `new_deferred` or a
+	/// LazyStore that hasn't been needed yet. Events are held in memory until it opens.
+	Opening,
+}
+
+impl StoreStatus {
+	/// Whether events survive a restart.
+	pub fn is_persisted(&self) -> bool {
+		matches!(self, StoreStatus::Persisted)
+	}
+}
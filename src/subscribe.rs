@@ -0,0 +1,147 @@
+//! Live copies of appended events, for consumers that watch the traffic without
+//! draining the queue.
+
+use futures_channel::mpsc::{self, Receiver, Sender};
+use serde_json::Value;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Open subscriptions of a TransientDB.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+	senders: Mutex<Vec<Sender<Value>>>,
+}
+
+impl Subscribers {
+	/// Number of events a subscription holds before newer ones are dropped.
+	pub(crate) const CAPACITY: usize = 1024;
+
+	/// Opens a subscription.
+	pub(crate) fn subscribe(&self) -> Receiver<Value> {
+		let (sender, receiver) = mpsc::channel(Self::CAPACITY);
+		self.senders().push(sender);
+		receiver
+	}
+
+	/// Copies `data` if anyone is subscribed, so appends cost nothing extra otherwise.
+	pub(crate) fn copy<V: Clone>(&self, data: &V) -> Option<V> {
+		let mut senders = self.senders();
+		senders.retain(|sender| !sender.is_closed());
+		(!senders.is_empty()).then(|| data.clone())
+	}
+
+	/// Sends events to every subscription, dropping them for subscriptions that are full.
+	pub(crate) fn publish(&self, events: impl IntoIterator<Item = Value>) {
+		let mut senders = self.senders();
+		for event in events {
+			senders.retain_mut(|sender| match sender.try_send(event.clone()) {
+				Ok(()) => true,
+				Err(e) => !e.is_disconnected(),
+			});
+		}
+	}
+
+	fn senders(&self) -> MutexGuard<'_, Vec<Sender<Value>>> {
+		self.senders.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{MemoryConfig, MemoryStore, TransientDB};
+	use futures_core::Stream;
+	use serde_json::json;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Wake, Waker};
+
+	struct NoopWaker;
+
+	impl Wake for NoopWaker {
+		fn wake(self: Arc<Self>) {}
+	}
+
+	fn poll(stream: &mut (impl Stream<Item = Value> + Unpin)) -> Poll<Option<Value>> {
+		let waker = Waker::from(Arc::new(NoopWaker));
+		Pin::new(stream).poll_next(&mut Context::from_waker(&waker))
+	}
+
+	fn db() -> TransientDB<Value> {
+		TransientDB::new(MemoryStore::new(MemoryConfig {
+			write_key: "test".into(),
+			max_items: 10,
+			max_fetch_size: 1024 * 1024,
+		}))
+	}
+
+	#[test]
+	fn test_subscribers_see_appends_without_draining() {
+		let db = db();
+		db.append(json!({"event": "before"})).unwrap();
+
+		let mut events = db.subscribe();
+		assert_eq!(poll(&mut events), Poll::Pending);
+
+		db.append(json!({"event": "a"})).unwrap();
+		db.append_many(vec![json!({"event": "b"}), json!({"event": "c"})])
+			.unwrap();
+		db.upsert("traits", json!({"plan": "pro"})).unwrap();
+
+		for expected in ["a", "b", "c"] {
+			match poll(&mut events) {
+				Poll::Ready(Some(event)) => assert_eq!(event["event"], expected),
+				other => panic!("expected {}, got {:?}", expected, other),
+			}
+		}
+		assert!(matches!(poll(&mut events), Poll::Ready(Some(event)) if event["plan"] == "pro"));
+		assert_eq!(poll(&mut events), Poll::Pending);
+
+		// A failed append isn't sent
+		assert!(db.append_for("other", json!("not an object")).is_err());
+		assert_eq!(poll(&mut events), Poll::Pending);
+
+		// The queue is untouched
+		let batch = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+		assert_eq!(batch["batch"].as_array().unwrap().len(), 5);
+	}
+
+	#[test]
+	fn test_slow_and_dropped_subscribers() {
+		let db = db();
+		let mut slow = db.subscribe();
+		let dropped = db.subscribe();
+		drop(dropped);
+
+		for i in 0..Subscribers::CAPACITY + 10 {
+			db.append(json!({"n": i})).unwrap();
+		}
+
+		let mut received = 0;
+		while let Poll::Ready(Some(_)) = poll(&mut slow) {
+			received += 1;
+		}
+		assert!(received >= Subscribers::CAPACITY);
+		assert!(received < Subscribers::CAPACITY + 10);
+		assert_eq!(db.stats().appended, Subscribers::CAPACITY as u64 + 10);
+	}
+
+	#[test]
+	fn test_imported_events_are_published_and_counted() {
+		let db = db();
+		let mut events = db.subscribe();
+
+		let report = db
+			.import_events("{\"event\": \"a\"}\nnot json\n{\"event\": \"b\"}\n".as_bytes())
+			.unwrap();
+		assert_eq!(report.imported, 2);
+
+		for expected in ["a", "b"] {
+			match poll(&mut events) {
+				Poll::Ready(Some(event)) => assert_eq!(event["event"], expected),
+				other => panic!("expected {}, got {:?}", expected, other),
+			}
+		}
+		assert_eq!(poll(&mut events), Poll::Pending);
+		assert_eq!(db.stats().appended, 2);
+	}
+}
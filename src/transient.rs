@@ -1,7 +1,111 @@
-use crate::{DataResult, DataStore, Equivalent};
-use serde_json::Value;
-use std::io::Result;
-use std::sync::Mutex;
+use crate::age::{self, StalenessAlert};
+use crate::debug;
+use crate::stats::{Counters, TypeTracker};
+#[cfg(feature = "subscribe")]
+use crate::subscribe::Subscribers;
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	AgeHistogram, Attachment, BatchPreview, Capability, ChunkedBatch, DataResult, DataStoreExt,
+	DeliveryResult, DrainPolicy, DrainSummary, DropRecord, Equivalent, ImportReport, JsonPointer,
+	QueueStats, RemovedBatch, Sink, StoreStatus, TypeStats,
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Result, Write};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Locks a mutex, recovering it if a thread panicked while holding it.
+///
+/// Stores keep their own state consistent, so a panic elsewhere (typically in a caller's
+/// closure) must not make the database unusable for everyone else.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What [`TransientDB::remove_accepted`] does with events the server rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectedEvents {
+	/// Leave rejected events queued so they're sent again with the next fetch.
+	/// Suited to transient failures such as rate limiting.
+	#[default]
+	Requeue,
+	/// Remove rejected events from the queue so they're never retried.
+	/// Suited to permanent failures such as validation errors; the caller is
+	/// responsible for recording them elsewhere if they should be kept.
+	DeadLetter,
+}
+
+/// The boxed store a [`TransientDB`] holds unless told otherwise: `Send` wherever there
+/// are threads to send it to.
+#[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+type SharedStore<T> = dyn DataStoreExt<Output = T> + Send;
+
+/// The boxed store a [`TransientDB`] holds unless told otherwise. WASM without the
+/// atomics target feature has no threads, so stores such as WebStore needn't be `Send`.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+type SharedStore<T> = dyn DataStoreExt<Output = T>;
+
+/// A boxed store that needn't be `Send`, held by a [`LocalTransientDB`].
+type LocalStore<T> = dyn DataStoreExt<Output = T>;
+
+/// How a [`TransientDB`] holds a store of type `D`: inline for a concrete store type,
+/// boxed for a `dyn DataStoreExt`.
+///
+/// It is implemented for every sized store and for the `dyn DataStoreExt` types. It is
+/// sealed, so it can't be implemented outside this crate and may change in minor releases.
+pub trait HeldStore: sealed::Sealed {
+	/// What the database keeps behind its mutex.
+	type Holder: DerefMut<Target = Self>;
+}
+
+mod sealed {
+	use crate::DataStoreExt;
+
+	/// Keeps [`HeldStore`](super::HeldStore) from being implemented outside this crate
+	pub trait Sealed {}
+
+	impl<S: DataStoreExt> Sealed for S {}
+	impl<T> Sealed for dyn DataStoreExt<Output = T> {}
+	impl<T> Sealed for dyn DataStoreExt<Output = T> + Send {}
+}
+
+impl<S: DataStoreExt> HeldStore for S {
+	type Holder = Inline<S>;
+}
+
+impl<T> HeldStore for dyn DataStoreExt<Output = T> {
+	type Holder = Box<Self>;
+}
+
+impl<T> HeldStore for dyn DataStoreExt<Output = T> + Send {
+	type Holder = Box<Self>;
+}
+
+/// A concrete store held in place by a [`TransientDB`], without a box.
+///
+/// Created by [`TransientDB::new_typed`]; it dereferences to the store and can't be
+/// built directly.
+#[derive(Debug)]
+pub struct Inline<S>(S);
+
+impl<S> Deref for Inline<S> {
+	type Target = S;
+
+	fn deref(&self) -> &S {
+		&self.0
+	}
+}
+
+impl<S> DerefMut for Inline<S> {
+	fn deref_mut(&mut self) -> &mut S {
+		&mut self.0
+	}
+}
 
 /// A thread-safe wrapper around a DataStore implementation that provides temporary data storage
 /// with batch processing capabilities.
@@ -9,36 +113,61 @@ use std::sync::Mutex;
 /// TransientDB uses interior mutability through a Mutex to allow concurrent access to the
 /// underlying data store. It's designed for scenarios where data needs to be temporarily
 /// stored and processed in batches, such as queuing events or logs.
-pub struct TransientDB<T> {
-	#[cfg(not(target_arch = "wasm32"))]
-	store: Mutex<Box<dyn DataStore<Output = T> + Send>>,
+///
+/// `D` is the store type. By default it is a boxed `dyn DataStoreExt + Send`, so the
+/// database is `Send + Sync` and can be shared between threads, and the backend can be
+/// chosen at runtime. An app with a single known backend can use
+/// [`new_typed`](TransientDB::new_typed) instead, for a `TransientDB<T, MemoryStore>`
+/// say, which holds the store without a box and calls it without dynamic dispatch. On
+/// WASM without the atomics target feature, it is plain `dyn DataStoreExt`, and the
+/// database is neither `Send` nor `Sync`. That is sound without any `unsafe`, since there
+/// are no other threads. For stores that aren't `Send`, such as ones holding `Rc`s, see
+/// [`LocalTransientDB`].
+pub struct TransientDB<T, D: HeldStore + ?Sized = SharedStore<T>> {
+	store: Mutex<D::Holder>,
 
-	#[cfg(target_arch = "wasm32")]
-	store: Mutex<Box<dyn DataStore<Output = T>>>,
-}
+	staleness: Mutex<Option<StalenessAlert>>,
+
+	counters: Counters,
+
+	type_stats: Mutex<Option<TypeTracker>>,
+
+	#[cfg(feature = "subscribe")]
+	subscribers: Subscribers,
 
-// SAFETY: On WASM32, there are no threads. Send and Sync are vacuously satisfied
-// because there's nowhere to send to and nothing to synchronize with.
-//
-// This allows types like WebStore (which contains Rc<IdbDatabase>) to be used
-// with TransientDB on WASM targets without requiring complex trait gymnastics
-// that would propagate through the entire codebase.
-//
-// NOTE: If WASM gains real threading support (wasm32 + atomics + shared memory),
-// this will need to be revisited. However, that would likely be a different
-// compilation target requiring explicit opt-in.
-#[cfg(target_arch = "wasm32")]
-unsafe impl<T> Send for TransientDB<T> {}
+	output: PhantomData<fn() -> T>,
+}
 
-#[cfg(target_arch = "wasm32")]
-unsafe impl<T> Sync for TransientDB<T> {}
+/// A [`TransientDB`] for stores that aren't `Send`, confined to the thread that created
+/// it, made with [`new_local`](TransientDB::new_local).
+///
+/// It has the same methods as a TransientDB. Being neither `Send` nor `Sync`, it needs
+/// no `unsafe` to hold thread-bound stores, such as a WebStore on any WASM target or an
+/// app's own `Rc`-based store on native. On WASM without the atomics target feature it
+/// is the same type as `TransientDB`.
+///
+/// # Examples
+/// ```
+/// use std::rc::Rc;
+/// use transientdb::{LocalTransientDB, MemoryConfig, MemoryStore};
+///
+/// let db = Rc::new(LocalTransientDB::new_local(MemoryStore::new(MemoryConfig {
+///     write_key: "my-store".into(),
+///     max_items: 1000,
+///     max_fetch_size: 1024 * 1024,
+/// })));
+/// db.append(serde_json::json!({"event": "login"}))?;
+/// assert!(db.has_data());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub type LocalTransientDB<T> = TransientDB<T, LocalStore<T>>;
 
 impl<T> TransientDB<T> {
 	/// Creates a new TransientDB instance with the provided data store implementation.
 	///
 	/// # Arguments
-	/// * `store` - Any implementation of DataStore that is Send + 'static (on native)
-	///   or just DataStore + 'static (on WASM)
+	/// * `store` - Any implementation of [`DataStoreExt`] that is Send + 'static (on
+	///   native and threaded WASM) or just 'static (on WASM)
 	///
 	/// # Examples
 	/// ```
@@ -52,18 +181,94 @@ impl<T> TransientDB<T> {
 	/// let store = MemoryStore::new(config);
 	/// let db = TransientDB::new(store);
 	/// ```
-	#[cfg(not(target_arch = "wasm32"))]
-	pub fn new(store: impl DataStore<Output = T> + Send + 'static) -> Self {
-		Self {
-			store: Mutex::new(Box::new(store)),
-		}
+	#[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+	pub fn new(store: impl DataStoreExt<Output = T> + Send + 'static) -> Self {
+		Self::with_box(Box::new(store))
 	}
 
 	/// Creates a new TransientDB instance with the provided data store implementation.
-	#[cfg(target_arch = "wasm32")]
-	pub fn new(store: impl DataStore<Output = T> + 'static) -> Self {
+	#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+	pub fn new(store: impl DataStoreExt<Output = T> + 'static) -> Self {
+		Self::with_box(Box::new(store))
+	}
+
+	/// Creates a new TransientDB instance with a store that's already boxed, such as one
+	/// picked at runtime.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{DataStoreExt, MemoryConfig, MemoryStore, TransientDB};
+	/// use serde_json::Value;
+	///
+	/// let store: Box<dyn DataStoreExt<Output = Value> + Send> =
+	///     Box::new(MemoryStore::new(MemoryConfig {
+	///         write_key: "my-store".into(),
+	///         max_items: 1000,
+	///         max_fetch_size: 1024 * 1024,
+	///     }));
+	/// let db = TransientDB::new_dyn(store);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn new_dyn(store: Box<SharedStore<T>>) -> Self {
+		Self::with_box(store)
+	}
+}
+
+impl<S: DataStoreExt> TransientDB<S::Output, S> {
+	/// Creates a TransientDB holding a store of a known type, without boxing it.
+	///
+	/// Every call goes straight to the store rather than through a vtable. The database
+	/// is `Send + Sync` if the store is `Send`.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	/// use serde_json::{json, Value};
+	///
+	/// let db: TransientDB<Value, MemoryStore> = TransientDB::new_typed(MemoryStore::new(
+	///     MemoryConfig {
+	///         write_key: "my-store".into(),
+	///         max_items: 1000,
+	///         max_fetch_size: 1024 * 1024,
+	///     },
+	/// ));
+	/// db.append(json!({"event": "login"}))?;
+	/// assert!(db.has_data());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn new_typed(store: S) -> Self {
+		Self::with_holder(Inline(store))
+	}
+}
+
+impl<T> LocalTransientDB<T> {
+	/// Creates a database confined to the current thread, for stores that aren't `Send`.
+	///
+	/// See [`LocalTransientDB`] for an example.
+	pub fn new_local(store: impl DataStoreExt<Output = T> + 'static) -> Self {
+		Self::with_box(Box::new(store))
+	}
+}
+
+impl<T, D: ?Sized> TransientDB<T, D>
+where
+	D: DataStoreExt<Output = T> + HeldStore<Holder = Box<D>>,
+{
+	fn with_box(store: Box<D>) -> Self {
+		Self::with_holder(store)
+	}
+}
+
+impl<T, D: DataStoreExt<Output = T> + HeldStore + ?Sized> TransientDB<T, D> {
+	fn with_holder(store: D::Holder) -> Self {
 		Self {
-			store: Mutex::new(Box::new(store)),
+			store: Mutex::new(store),
+			staleness: Mutex::new(None),
+			counters: Counters::default(),
+			type_stats: Mutex::new(None),
+			#[cfg(feature = "subscribe")]
+			subscribers: Subscribers::default(),
+			output: PhantomData,
 		}
 	}
 
@@ -85,7 +290,7 @@ impl<T> TransientDB<T> {
 	/// assert!(db.has_data());
 	/// ```
 	pub fn has_data(&self) -> bool {
-		self.store.lock().unwrap().has_data()
+		lock(&self.store).has_data()
 	}
 
 	/// Removes all data from the store and resets it to initial state.
@@ -108,7 +313,7 @@ impl<T> TransientDB<T> {
 	/// assert!(!db.has_data());
 	/// ```
 	pub fn reset(&self) {
-		self.store.lock().unwrap().reset();
+		lock(&self.store).reset();
 	}
 
 	/// Appends a new item to the store.
@@ -141,7 +346,426 @@ impl<T> TransientDB<T> {
 	/// })).unwrap();
 	/// ```
 	pub fn append(&self, data: Value) -> Result<()> {
-		self.store.lock().unwrap().append(data)
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).append(data);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Appends several items at once, oldest first.
+	///
+	/// Takes the lock once for the whole group, and stores evict old items once and write
+	/// the group together (a single write per data file for DirectoryStore, a single
+	/// IndexedDB transaction for WebStore), so restoring a backlog of events doesn't cost a
+	/// round trip per event. If it fails, some of the items may have been appended.
+	///
+	/// # Arguments
+	/// * `items` - JSON values to store
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append_many((0..10).map(|i| json!({"index": i}))).unwrap();
+	/// assert_eq!(db.stats().appended, 10);
+	/// ```
+	pub fn append_many<I>(&self, items: I) -> Result<()>
+	where
+		I: IntoIterator<Item = Value>,
+	{
+		let items: Vec<Value> = items.into_iter().collect();
+		let count = items.len();
+		let copy = self.watched(&items);
+		let measured = self.measure(&items);
+		let result = lock(&self.store).append_many(items);
+		self.counters.record_append_many(&result, count);
+		result?;
+		self.record_types(measured);
+		self.publish(copy.into_iter().flatten());
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Appends a new item together with binary attachments.
+	///
+	/// The item must be a JSON object. It gains an `_attachments` array referencing each
+	/// attachment by id, and the attachments are returned in `DataResult::attachments`
+	/// whenever the item is fetched. They are removed along with the item.
+	///
+	/// # Arguments
+	/// * `data` - JSON object to store
+	/// * `attachments` - Binary payloads belonging to the item
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{Attachment, TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append_with_attachments(
+	///     json!({"event": "crash"}),
+	///     vec![Attachment {
+	///         name: "crash.dmp".into(),
+	///         content_type: "application/octet-stream".into(),
+	///         data: vec![0, 1, 2, 3],
+	///     }],
+	/// ).unwrap();
+	///
+	/// if let Ok(Some(result)) = db.fetch(None, None) {
+	///     for attachment in result.attachments.unwrap_or_default() {
+	///         // Upload as a multipart part named by attachment.id...
+	///         let bytes = attachment.content.read().unwrap();
+	///         assert_eq!(bytes.len(), 4);
+	///     }
+	/// }
+	/// ```
+	pub fn append_with_attachments(&self, data: Value, attachments: Vec<Attachment>) -> Result<()> {
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).append_with_attachments(data, attachments);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		Ok(())
+	}
+
+	/// Appends a new item tagged with a consent category.
+	///
+	/// The item must be a JSON object. It gains a `_consent` field holding `category`,
+	/// and is only fetched while the category is allowed by
+	/// [`set_allowed_categories`](Self::set_allowed_categories). Items appended without a
+	/// category are always fetched.
+	///
+	/// # Arguments
+	/// * `data` - JSON object to store
+	/// * `category` - Consent category, e.g. `"analytics"`, `"advertising"` or `"functional"`
+	///
+	/// # Examples
+	/// ```
+	/// use std::collections::HashSet;
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append_with_consent(json!({"event": "page_view"}), "analytics").unwrap();
+	/// db.append_with_consent(json!({"event": "ad_click"}), "advertising").unwrap();
+	///
+	/// // The user revoked advertising consent in the CMP
+	/// db.set_allowed_categories(Some(HashSet::from(["analytics".to_string()]))).unwrap();
+	/// assert_eq!(db.purge_revoked().unwrap(), 1);
+	///
+	/// let batch = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// assert_eq!(batch["batch"].as_array().unwrap().len(), 1);
+	/// ```
+	pub fn append_with_consent(&self, mut data: Value, category: &str) -> Result<()> {
+		if let Err(e) = crate::consent::tag(&mut data, category) {
+			let result = Err(e);
+			self.counters.record_append(&result);
+			return result;
+		}
+		self.append(data)
+	}
+
+	/// Appends an item to be sent with `write_key` instead of the store's, for clients
+	/// fanning out events to several workspaces.
+	///
+	/// Fetches return items of one write key at a time, with that key as the envelope's
+	/// `writeKey`, so each batch can be sent to its workspace. The item must be a JSON
+	/// object; a `_writeKey` field is added to it. Supported by MemoryStore and WebStore.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "main".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append(json!({"event": "page_view"})).unwrap();
+	/// db.append_for("partner", json!({"event": "page_view"})).unwrap();
+	/// db.append(json!({"event": "click"})).unwrap();
+	///
+	/// let first = db.fetch(None, None).unwrap().unwrap();
+	/// let envelope = first.data.unwrap();
+	/// assert_eq!(envelope["writeKey"], "main");
+	/// assert_eq!(envelope["batch"].as_array().unwrap().len(), 2);
+	/// db.remove(&first.removable.unwrap()).unwrap();
+	///
+	/// let envelope = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// assert_eq!(envelope["writeKey"], "partner");
+	/// ```
+	pub fn append_for(&self, write_key: &str, data: Value) -> Result<()> {
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).append_for(write_key, data);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Appends an item that isn't fetched before `not_before`, for retries scheduled
+	/// for later or events to send after the session ends.
+	///
+	/// The item stays queued, and counts towards the store's limits, but fetches skip
+	/// it until its time. The item must be a JSON object; the time is added to it as a
+	/// `_notBefore` field, so stores persist it with the item. Supported by MemoryStore
+	/// and WebStore.
+	///
+	/// # Examples
+	/// ```
+	/// use chrono::{Duration, Utc};
+	/// use serde_json::json;
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "my-key".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append_delayed(json!({"event": "session_summary"}), Utc::now() + Duration::minutes(30))
+	///     .unwrap();
+	/// assert_eq!(db.stats().pending.unwrap().items, 1);
+	/// assert!(!db.has_data());
+	/// assert!(db.fetch(None, None).unwrap().is_none());
+	/// ```
+	pub fn append_delayed(&self, data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).append_delayed(data, not_before);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Appends an item that replaces the queued item upserted with the same `key`, so
+	/// only the latest state is sent, e.g. for user traits that change several times
+	/// between uploads.
+	///
+	/// The replaced item is removed and the new one queued after the others; items
+	/// appended with [`append`](Self::append) are never replaced. The item must be a
+	/// JSON object; an `_upsertKey` field is added to it. Supported by MemoryStore and
+	/// WebStore.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "my-key".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.upsert("traits", json!({"plan": "free"})).unwrap();
+	/// db.append(json!({"event": "upgrade_clicked"})).unwrap();
+	/// db.upsert("traits", json!({"plan": "pro"})).unwrap();
+	///
+	/// let batch = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// let events = batch["batch"].as_array().unwrap();
+	/// assert_eq!(events.len(), 2);
+	/// assert_eq!(events[1]["plan"], "pro");
+	/// ```
+	pub fn upsert(&self, key: &str, data: Value) -> Result<()> {
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).upsert(key, data);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Appends an item that is never evicted to make room for others, for records that
+	/// mustn't be lost, like purchase receipts queued offline.
+	///
+	/// The item stays queued until fetched and removed. Stores hold a limited number of
+	/// pinned items (see `MemoryStore::set_max_pinned`) and fail with
+	/// [`TransientError::TooManyPinned`](crate::TransientError::TooManyPinned) beyond it.
+	/// Supported by MemoryStore and WebStore.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 2,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append_pinned(json!({"event": "receipt", "order": 42})).unwrap();
+	/// for i in 0..5 {
+	///     db.append(json!({"event": "scroll", "n": i})).unwrap();
+	/// }
+	///
+	/// let batch = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// assert_eq!(batch["batch"][0]["event"], "receipt");
+	/// assert_eq!(batch["batch"][1]["n"], 4);
+	/// ```
+	pub fn append_pinned(&self, data: Value) -> Result<()> {
+		let copy = self.watched(&data);
+		let measured = self.measure([&data]);
+		let result = lock(&self.store).append_pinned(data);
+		self.counters.record_append(&result);
+		result?;
+		self.record_types(measured);
+		self.publish(copy);
+		self.check_staleness_periodically();
+		Ok(())
+	}
+
+	/// Writes out any appended items the store is still holding in memory, e.g. by a
+	/// DirectoryStore with [`FlushPolicy::Buffered`](crate::FlushPolicy::Buffered).
+	///
+	/// Call it before the process may be suspended or killed.
+	pub fn flush(&self) -> Result<()> {
+		lock(&self.store).flush()
+	}
+
+	/// Prepares the store for appending, e.g. creating a DirectoryStore's in-progress
+	/// file, so the first append doesn't pay for it.
+	///
+	/// Call it at app launch, off the UI thread.
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::json;
+	/// use transientdb::{DirectoryConfig, DirectoryStore, TransientDB};
+	///
+	/// let dir = tempfile::tempdir().unwrap();
+	/// let db = TransientDB::new(DirectoryStore::new(DirectoryConfig {
+	///     write_key: "my-key".into(),
+	///     storage_location: dir.path().to_owned(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024 * 1024,
+	/// }).unwrap());
+	///
+	/// db.warm_up().unwrap();
+	/// assert!(!db.has_data());
+	///
+	/// db.append(json!({"event": "app_open"})).unwrap();
+	/// assert!(db.has_data());
+	/// ```
+	pub fn warm_up(&self) -> Result<()> {
+		lock(&self.store).warm_up()
+	}
+
+	/// Reserves room for an item to be appended later with [`Slot::commit`].
+	///
+	/// For producers that can't tolerate jitter, such as frame-render or audio threads:
+	/// reserve off the hot path, and commit on it. Anything an append may have to do up
+	/// front (evicting old items, rotating to a new data file, checking free space) is done
+	/// here instead, so the commit only takes the lock and writes the item. Dropping the
+	/// slot without committing gives the room back.
+	///
+	/// The estimate decides whether a DirectoryStore rotates to a new file now; an item
+	/// larger than estimated is still committed, into the same file. Items reserved in a
+	/// MemoryStore count against `max_items` until committed. A `reset()` between reserve
+	/// and commit discards the room, so the commit starts a new file.
+	///
+	/// # Arguments
+	/// * `estimated_bytes` - Expected serialized size of the item
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't support reservations, or any error the
+	/// store hits making room, e.g. [`TransientError::DiskFull`](crate::TransientError::DiskFull).
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// // Before the frame
+	/// let slot = db.reserve(64).unwrap();
+	///
+	/// // During the frame
+	/// slot.commit(json!({"event": "frame_dropped", "ms": 21})).unwrap();
+	/// assert!(db.has_data());
+	/// ```
+	pub fn reserve(&self, estimated_bytes: usize) -> Result<Slot<'_, T, D>> {
+		lock(&self.store).reserve(estimated_bytes)?;
+		Ok(Slot {
+			db: self,
+			estimated_bytes,
+			committed: false,
+		})
+	}
+
+	/// Imports externally produced events into the queue.
+	///
+	/// Reads NDJSON (one event per line), a JSON array of events, or a batch envelope
+	/// (`{"batch": [...], ...}`), appending the events with [`append_many`](Self::append_many)
+	/// so they're counted and published like any other.
+	/// Malformed entries are listed in the returned report instead of failing the import;
+	/// only errors reading the input or writing to the store are returned as `Err`.
+	///
+	/// # Arguments
+	/// * `reader` - Source of the events, e.g. a file from an older SDK version
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// let legacy = "{\"event\":\"a\"}\n{oops\n{\"event\":\"b\"}\n";
+	/// let report = db.import_events(legacy.as_bytes()).unwrap();
+	///
+	/// assert_eq!(report.imported, 2);
+	/// assert_eq!(report.errors.len(), 1);
+	/// assert_eq!(db.stats().appended, 2);
+	/// ```
+	pub fn import_events<R: Read>(&self, reader: R) -> Result<ImportReport> {
+		let (events, report) = crate::import::parse_events(reader)?;
+		self.append_many(events)?;
+		Ok(report)
 	}
 
 	/// Fetches a batch of data from the store, respecting optional count and size limits.
@@ -187,27 +811,193 @@ impl<T> TransientDB<T> {
 		count: Option<usize>,
 		max_bytes: Option<usize>,
 	) -> Result<Option<DataResult<T>>> {
-		self.store.lock().unwrap().fetch(count, max_bytes)
+		let result = lock(&self.store).fetch(count, max_bytes);
+		self.counters.record_fetch(&result);
+		self.check_staleness_periodically();
+		result
 	}
 
-	/// Removes previously fetched data from the store.
+	/// Fetches a batch like [`fetch`](Self::fetch), but streams its envelope as JSON
+	/// into `writer` (a file, socket or compressor) instead of building it in memory.
 	///
-	/// # Arguments
-	/// * `data` - Slice of removable items from a previous fetch operation
+	/// The result's `data` is the number of bytes written, and its `removable` items are
+	/// removed with [`remove`](Self::remove) as usual once the batch is delivered.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store can't stream batches, such as a plain
+	/// DirectoryStore, whose batches are files already. If writing fails the batch stays
+	/// queued, but `writer` may have received part of it.
 	///
 	/// # Examples
 	/// ```
 	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
-	/// use serde_json::json;
+	/// use serde_json::{json, Value};
 	///
 	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
 	///     write_key: "test".into(),
 	///     max_items: 100,
 	///     max_fetch_size: 1024,
 	/// }));
+	/// db.append(json!({"event": "tap"})).unwrap();
 	///
-	/// // Add and fetch data
-	/// db.append(json!({"test": "data"})).unwrap();
+	/// let mut body = Vec::new();
+	/// let result = db.write_batch_to(&mut body, None, None).unwrap().unwrap();
+	/// assert_eq!(result.data, Some(body.len() as u64));
+	///
+	/// let envelope: Value = serde_json::from_slice(&body).unwrap();
+	/// assert_eq!(envelope["batch"][0]["event"], "tap");
+	/// db.remove(&result.removable.unwrap()).unwrap();
+	/// ```
+	pub fn write_batch_to<W: Write>(
+		&self,
+		mut writer: W,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<u64>>> {
+		let result = lock(&self.store).write_batch_to(&mut writer, count, max_bytes);
+		self.counters.record_fetch(&result);
+		self.check_staleness_periodically();
+		result
+	}
+
+	/// Fetches a batch of the items enqueued at least `age` ago, regardless of how full
+	/// the batch is, or None if there are none.
+	///
+	/// Stores track when items were enqueued, so this doesn't read event payloads. Cheap
+	/// enough to call on every tick of a flush scheduler.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't track when each item was enqueued;
+	/// MemoryStore and WebStore do.
+	///
+	/// # Examples
+	/// ```
+	/// use std::time::Duration;
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"event": "tap"})).unwrap();
+	///
+	/// // Nothing has waited 30 seconds yet
+	/// assert!(db.fetch_older_than(Duration::from_secs(30)).unwrap().is_none());
+	/// assert!(db.fetch_older_than(Duration::ZERO).unwrap().is_some());
+	/// ```
+	pub fn fetch_older_than(&self, age: Duration) -> Result<Option<DataResult<T>>> {
+		let result = lock(&self.store).fetch_older_than(age);
+		self.counters.record_fetch(&result);
+		self.check_staleness_periodically();
+		result
+	}
+
+	/// Drops the items enqueued at least `age` ago, returning how many, so a host's
+	/// maintenance scheduler can keep stale events from ever being sent.
+	///
+	/// Dropped items show up in [`drop_report`](Self::drop_report) as
+	/// [`DropReason::Expired`](crate::DropReason::Expired). Pinned items are kept.
+	/// DirectoryStore deletes files finished before the cutoff outright, and goes by
+	/// each event's own time in the file that straddles it.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store can't expire items.
+	///
+	/// # Examples
+	/// ```
+	/// use std::time::Duration;
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"event": "tap"}))?;
+	///
+	/// // Nothing is a day old yet
+	/// assert_eq!(db.expire_older_than(Duration::from_secs(24 * 60 * 60))?, 0);
+	/// assert_eq!(db.expire_older_than(Duration::ZERO)?, 1);
+	/// assert!(!db.has_data());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn expire_older_than(&self, age: Duration) -> Result<usize> {
+		lock(&self.store).expire_older_than(age)
+	}
+
+	/// Reports what [`fetch`](Self::fetch) with the same limits would return, without
+	/// copying events, building a batch or changing the store.
+	///
+	/// Cheap enough to call on every tick of a flush scheduler, e.g. to wait until a
+	/// batch is full or its oldest event is old enough.
+	///
+	/// # Arguments
+	/// * `count` - Optional maximum number of items to fetch
+	/// * `max_bytes` - Optional maximum total size in bytes to fetch
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store can't preview fetches.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// for i in 0..5 {
+	///     db.append(json!({"index": i})).unwrap();
+	/// }
+	///
+	/// let preview = db.preview_fetch(Some(3), None).unwrap();
+	/// assert_eq!(preview.items, 3);
+	/// assert!(preview.oldest <= preview.newest);
+	///
+	/// // Only flush full batches
+	/// if preview.items == 3 {
+	///     let result = db.fetch(Some(3), None).unwrap().unwrap();
+	///     assert_eq!(result.removable.unwrap().len(), 3);
+	/// }
+	/// ```
+	pub fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		lock(&self.store).preview_fetch(count, max_bytes)
+	}
+
+	/// Removes previously fetched data from the store.
+	///
+	/// `data` may be a subset of a fetch's removables; only those items are removed and the
+	/// rest stay queued for the next fetch. This lets an uploader drop just the events a
+	/// server rejected. For DirectoryStore, use
+	/// [`DirectoryStore::item_removables`](crate::DirectoryStore::item_removables) to get
+	/// per-event removables for a fetched file.
+	///
+	/// # Arguments
+	/// * `data` - Slice of removable items from a previous fetch operation
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// // Add and fetch data
+	/// db.append(json!({"test": "data"})).unwrap();
 	///
 	/// if let Ok(Some(result)) = db.fetch(None, None) {
 	///     // Process the data...
@@ -219,6 +1009,997 @@ impl<T> TransientDB<T> {
 	/// }
 	/// ```
 	pub fn remove(&self, data: &[Box<dyn Equivalent>]) -> Result<()> {
-		self.store.lock().unwrap().remove(data)
+		let result = lock(&self.store).remove(data);
+		self.counters.record_remove(&result, data.len());
+		result
+	}
+
+	/// Removes the chunks of a [`ChunkedBatch`] that were delivered, leaving the rest queued.
+	///
+	/// # Arguments
+	/// * `batch` - Chunks produced by [`BatchChunker::split`](crate::BatchChunker::split)
+	/// * `delivered` - Indices of the chunks the server accepted
+	///
+	/// See [`BatchChunker`](crate::BatchChunker) for an example.
+	pub fn remove_chunks(&self, batch: &ChunkedBatch, delivered: &[usize]) -> Result<()> {
+		let mut store = lock(&self.store);
+		for chunk in batch
+			.chunks
+			.iter()
+			.filter(|chunk| delivered.contains(&chunk.index))
+		{
+			let result = store.remove(&chunk.removable);
+			self.counters.record_remove(&result, chunk.removable.len());
+			result?;
+		}
+		Ok(())
+	}
+
+	/// Removes the events of a fetched batch that the server accepted, and requeues or
+	/// dead-letters the rest.
+	///
+	/// Many ingestion APIs report per-record results for a batch. Pass the indices of the
+	/// accepted events within the batch, and choose what happens to the rejected ones.
+	/// Returns the indices of the rejected events, so the caller can look them up in the
+	/// uploaded batch (e.g. to log them or write them to a dead-letter file).
+	///
+	/// The removables are split into one per event first, so this also works with stores
+	/// that return one per file, like [`DirectoryStore`](crate::DirectoryStore).
+	///
+	/// # Arguments
+	/// * `removable` - All removable items from a fetch, in batch order
+	/// * `accepted` - Indices (within the batch) of the events the server accepted
+	/// * `rejected` - What to do with the events that weren't accepted
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{MemoryConfig, MemoryStore, RejectedEvents, TransientDB};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"event": "ok"})).unwrap();
+	/// db.append(json!({"event": "invalid"})).unwrap();
+	/// db.append(json!({"event": "ok"})).unwrap();
+	///
+	/// let result = db.fetch(None, None).unwrap().unwrap();
+	/// let rejected = db
+	///     .remove_accepted(result.removable.unwrap(), &[0, 2], RejectedEvents::DeadLetter)
+	///     .unwrap();
+	///
+	/// assert_eq!(rejected, vec![1]);
+	/// assert!(!db.has_data());
+	/// ```
+	pub fn remove_accepted(
+		&self,
+		removable: Vec<Box<dyn Equivalent>>,
+		accepted: &[usize],
+		rejected: RejectedEvents,
+	) -> Result<Vec<usize>> {
+		let mut store = lock(&self.store);
+		let mut to_remove = Vec::new();
+		let mut rejected_indices = Vec::new();
+
+		for (index, item) in store.event_removables(removable)?.into_iter().enumerate() {
+			if accepted.contains(&index) || rejected == RejectedEvents::DeadLetter {
+				to_remove.push(item);
+			}
+			if !accepted.contains(&index) {
+				rejected_indices.push(index);
+			}
+		}
+
+		let result = store.remove(&to_remove);
+		self.counters.record_remove(&result, to_remove.len());
+		result?;
+		Ok(rejected_indices)
+	}
+
+	/// Delivers queued batches to a sink until the queue is empty.
+	///
+	/// Repeatedly fetches a batch, hands it to `sink`, and acts on the result:
+	/// - [`DeliveryResult::Delivered`] removes the batch and marks it delivered
+	/// - [`DeliveryResult::Partial`] removes the accepted events, handling the rest per
+	///   [`DrainPolicy::rejected`]
+	/// - [`DeliveryResult::Retry`] keeps the batch and retries it after a growing delay
+	/// - [`DeliveryResult::Rejected`] drops the batch
+	///
+	/// The drain stops when the queue is empty, `max_batches` batches were sent, or
+	/// `max_retries` consecutive retries failed. Requeued partial rejections count as
+	/// retries, so a sink that keeps rejecting the same events can't loop forever.
+	/// The store is only locked while fetching and removing, never during delivery.
+	///
+	/// A fetched batch whose id is already marked delivered is removed without being
+	/// sent again. That covers a failed removal after delivery, and with stores that
+	/// keep batch ids across restarts (`WebStore`, or
+	/// [`DirectoryStore`](crate::DirectoryStore) with a delivery cursor) a crash
+	/// between delivery and removal as well.
+	///
+	/// # Arguments
+	/// * `sink` - Where to deliver batches
+	/// * `policy` - Batch sizes, limits and retry behaviour
+	///
+	/// # Examples
+	/// ```
+	/// use serde_json::{json, Value};
+	/// use transientdb::{DataResult, DeliveryResult, DrainPolicy, MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// for i in 0..10 {
+	///     db.append(json!({"index": i})).unwrap();
+	/// }
+	///
+	/// let mut upload = |batch: &DataResult<Value>| {
+	///     // Send batch.data somewhere...
+	///     DeliveryResult::Delivered
+	/// };
+	/// let summary = db.drain_into(&mut upload, &DrainPolicy::default()).unwrap();
+	///
+	/// assert_eq!(summary.events_sent, 10);
+	/// assert!(!db.has_data());
+	/// ```
+	pub fn drain_into<S: Sink<T> + ?Sized>(
+		&self,
+		sink: &mut S,
+		policy: &DrainPolicy,
+	) -> Result<DrainSummary> {
+		self.drain(sink, policy, None)
+	}
+
+	/// Delivers queued batches to a sink, blocking until the queue is empty or `timeout`
+	/// has passed.
+	///
+	/// Meant for app shutdown or "flush now" actions, where the caller wants to wait for
+	/// everything to be sent. Unlike [`drain_into`](Self::drain_into), temporary failures
+	/// are retried until the time is up rather than a fixed number of times. A delivery
+	/// already in progress when the time runs out is allowed to finish.
+	///
+	/// The returned summary reports what was sent and dropped, how long it took, and
+	/// whether the drain timed out with data still queued.
+	///
+	/// # Arguments
+	/// * `sink` - Where to deliver batches
+	/// * `timeout` - How long to keep trying
+	///
+	/// # Examples
+	/// ```
+	/// use std::time::Duration;
+	/// use serde_json::{json, Value};
+	/// use transientdb::{DataResult, DeliveryResult, MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"event": "shutdown"})).unwrap();
+	///
+	/// let mut upload = |_: &DataResult<Value>| DeliveryResult::Delivered;
+	/// let summary = db.drain_until_empty(&mut upload, Duration::from_secs(5)).unwrap();
+	///
+	/// assert!(!summary.timed_out);
+	/// assert!(!db.has_data());
+	/// ```
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn drain_until_empty<S: Sink<T> + ?Sized>(
+		&self,
+		sink: &mut S,
+		timeout: Duration,
+	) -> Result<DrainSummary> {
+		let policy = DrainPolicy {
+			max_retries: u32::MAX,
+			..DrainPolicy::default()
+		};
+		let deadline = chrono::Duration::from_std(timeout)
+			.ok()
+			.and_then(|timeout| Utc::now().checked_add_signed(timeout))
+			.unwrap_or(DateTime::<Utc>::MAX_UTC);
+		self.drain(sink, &policy, Some(deadline))
+	}
+
+	/// The delivery loop behind `drain_into` and `drain_until_empty`
+	fn drain<S: Sink<T> + ?Sized>(
+		&self,
+		sink: &mut S,
+		policy: &DrainPolicy,
+		deadline: Option<DateTime<Utc>>,
+	) -> Result<DrainSummary> {
+		let started = Utc::now();
+		let mut summary = DrainSummary::default();
+		let mut consecutive_retries = 0;
+
+		while !matches!(policy.max_batches, Some(max) if summary.batches_sent >= max) {
+			let Some(mut batch) = self.fetch(policy.batch_count, policy.batch_bytes)? else {
+				break;
+			};
+			// Checked after fetching, as events that aren't fetchable yet (delayed, held
+			// by a move, ...) don't count as left over
+			if deadline.is_some_and(|deadline| Utc::now() >= deadline) {
+				summary.timed_out = true;
+				break;
+			}
+			if let Some(batch_id) = &batch.batch_id {
+				if self.is_delivered(batch_id) {
+					// Delivered before, but the removal didn't happen
+					self.remove(&batch.removable.take().unwrap_or_default())?;
+					continue;
+				}
+			}
+			let outcome = sink.deliver(&batch);
+			let removable = batch.removable.take().unwrap_or_default();
+
+			let retry = match outcome {
+				DeliveryResult::Delivered => {
+					summary.batches_sent += 1;
+					summary.events_sent += removable.len();
+					// Record the delivery before removing, so a batch left behind by a
+					// failed removal is recognized when it's fetched again
+					if let Some(batch_id) = &batch.batch_id {
+						match self.mark_delivered(batch_id) {
+							Err(e) if e.kind() != io::ErrorKind::Unsupported => return Err(e),
+							_ => {}
+						}
+					}
+					self.remove(&removable)?;
+					false
+				}
+				DeliveryResult::Partial(accepted) => {
+					// Accepted indices are events, but stores like DirectoryStore return
+					// a removable per file
+					let removable = lock(&self.store).event_removables(removable)?;
+					let total = removable.len();
+					let rejected = self.remove_accepted(removable, &accepted, policy.rejected)?;
+					summary.batches_sent += 1;
+					summary.events_sent += total - rejected.len();
+					match policy.rejected {
+						RejectedEvents::DeadLetter => {
+							summary.events_dropped += rejected.len();
+							false
+						}
+						RejectedEvents::Requeue => !rejected.is_empty(),
+					}
+				}
+				DeliveryResult::Retry => true,
+				DeliveryResult::Rejected => {
+					summary.events_dropped += removable.len();
+					self.remove(&removable)?;
+					false
+				}
+			};
+
+			if !retry {
+				consecutive_retries = 0;
+				continue;
+			}
+			if consecutive_retries >= policy.max_retries {
+				summary.gave_up = true;
+				break;
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			{
+				let mut delay = policy
+					.retry_delay
+					.saturating_mul(2u32.saturating_pow(consecutive_retries));
+				if let Some(deadline) = deadline {
+					let remaining = (deadline - Utc::now()).to_std().unwrap_or_default();
+					delay = delay.min(remaining);
+				}
+				std::thread::sleep(delay);
+			}
+			consecutive_retries = consecutive_retries.saturating_add(1);
+			summary.retries = summary.retries.saturating_add(1);
+		}
+
+		summary.duration = (Utc::now() - started).to_std().unwrap_or_default();
+		Ok(summary)
+	}
+
+	/// Returns a snapshot of the queue's size and operation counters.
+	///
+	/// See [`QueueStats`] for an example.
+	pub fn stats(&self) -> QueueStats {
+		let mut stats = {
+			let store = lock(&self.store);
+			QueueStats {
+				pending: store.pending_size().ok(),
+				oldest_age: store
+					.oldest_enqueue_time()
+					.map(|oldest| age::age_at(oldest, Utc::now())),
+				..QueueStats::default()
+			}
+		};
+		self.counters.snapshot(&mut stats);
+		stats
+	}
+
+	/// Starts tracking the count and size of appended items per type, the type being the
+	/// field `key` names, e.g. `/event` or `/type`, for finding the events that blow up
+	/// payloads. Read the statistics with [`stats_by_type`](Self::stats_by_type).
+	///
+	/// Items without the field are counted under the empty type, as are items of new
+	/// types once 256 types are tracked. Replaces any previous key, clearing the
+	/// statistics gathered with it.
+	pub fn set_type_key(&self, key: JsonPointer) {
+		*lock(&self.type_stats) = Some(TypeTracker::new(key));
+	}
+
+	/// Returns the running statistics of the items appended, per type, since
+	/// [`set_type_key`](Self::set_type_key) was called; empty if it wasn't.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{JsonPointer, MemoryConfig, MemoryStore, TransientDB};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.set_type_key(JsonPointer::new("/event")?);
+	///
+	/// db.append(json!({"event": "tap"}))?;
+	/// db.append(json!({"event": "screen", "properties": {"name": "Checkout"}}))?;
+	/// db.append(json!({"event": "tap"}))?;
+	///
+	/// let stats = db.stats_by_type();
+	/// assert_eq!(stats["tap"].count, 2);
+	/// assert!(stats["screen"].avg_bytes() > stats["tap"].max_bytes);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn stats_by_type(&self) -> HashMap<String, TypeStats> {
+		lock(&self.type_stats)
+			.as_ref()
+			.map(TypeTracker::stats)
+			.unwrap_or_default()
+	}
+
+	/// Returns a copy of every pending event, oldest first, as of the call.
+	///
+	/// The store is locked only while the events are copied (or, for DirectoryStore,
+	/// read from its files), so a debug dashboard or local analytics can take its time
+	/// over the snapshot without holding up appends and uploads. Events appended or
+	/// removed afterwards aren't reflected in it.
+	///
+	/// Fails with `Unsupported` for stores that can't list their events.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "my-key".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"event": "login"}))?;
+	/// db.append(json!({"event": "purchase"}))?;
+	///
+	/// let snapshot = db.snapshot()?;
+	/// db.append(json!({"event": "logout"}))?;
+	///
+	/// let events: Vec<&str> = snapshot.iter().filter_map(|e| e["event"].as_str()).collect();
+	/// assert_eq!(events, vec!["login", "purchase"]);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn snapshot(&self) -> Result<Vec<Value>> {
+		lock(&self.store).pending_events()
+	}
+
+	/// Writes a redacted summary of the database for attaching to bug reports.
+	///
+	/// The dump is a JSON object with the store's configuration, its [`stats`](Self::stats),
+	/// and skeletons of the first and last `events` pending events. Skeletons keep the
+	/// keys, array lengths, booleans and nulls of an event but replace strings and numbers
+	/// with their type and a hash, so payload contents (and the write key and storage
+	/// paths) aren't leaked while equal values stay recognizable. Hashes are salted with
+	/// a random value that isn't written out, so they only match within one dump.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::{json, Value};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "secret-key".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// db.append(json!({"email": "someone@example.com"})).unwrap();
+	///
+	/// let mut dump = Vec::new();
+	/// db.debug_dump(&mut dump, 5).unwrap();
+	/// let text = String::from_utf8(dump).unwrap();
+	/// assert!(!text.contains("someone@example.com"));
+	/// assert!(!text.contains("secret-key"));
+	///
+	/// let dump: Value = serde_json::from_str(&text).unwrap();
+	/// assert_eq!(dump["firstEvents"][0]["email"].as_str().unwrap().len(), 23);
+	/// ```
+	pub fn debug_dump<W: Write>(&self, writer: W, events: usize) -> Result<()> {
+		let redactor = debug::Redactor::new()?;
+		let stats = self.stats();
+		let (config, pending) = {
+			let store = lock(&self.store);
+			(store.debug_config(), store.pending_events())
+		};
+
+		let (first, last, events_error) = match pending {
+			Ok(pending) => {
+				let first_count = events.min(pending.len());
+				let last_count = events.min(pending.len() - first_count);
+				let first: Vec<Value> = pending[..first_count]
+					.iter()
+					.map(|event| redactor.skeleton(event))
+					.collect();
+				let last: Vec<Value> = pending[pending.len() - last_count..]
+					.iter()
+					.map(|event| redactor.skeleton(event))
+					.collect();
+				(first, last, None)
+			}
+			Err(e) => (Vec::new(), Vec::new(), Some(e.to_string())),
+		};
+
+		let dump = json!({
+			"version": env!("CARGO_PKG_VERSION"),
+			"generatedAt": Utc::now().to_rfc3339(),
+			"config": redactor.redact_config(config),
+			"stats": debug::stats_json(&stats),
+			"firstEvents": first,
+			"lastEvents": last,
+			"eventsError": events_error,
+		});
+		serde_json::to_writer_pretty(writer, &dump)?;
+		Ok(())
+	}
+
+	/// Returns a histogram of how long pending items have been queued.
+	///
+	/// Stores count items differently: MemoryStore and WebStore per event, DirectoryStore
+	/// per data file. See [`AgeHistogram`] for an example.
+	///
+	/// # Arguments
+	/// * `bounds` - Bucket upper bounds, e.g. [`AgeHistogram::DEFAULT_BOUNDS`]
+	pub fn age_histogram(&self, bounds: &[Duration]) -> Result<AgeHistogram> {
+		let times = lock(&self.store).enqueue_times()?;
+		Ok(AgeHistogram::from_times(&times, Utc::now(), bounds))
+	}
+
+	/// Registers a callback for when the oldest pending item gets older than `threshold`.
+	///
+	/// A queue that isn't shrinking may have an idle uploader or a wedged one; only the
+	/// latter lets events grow old. The callback receives the oldest item's age. It fires
+	/// once when the queue becomes stale, and again only after the queue has caught up
+	/// (its oldest item is younger than the threshold) and become stale once more.
+	///
+	/// The queue is checked during `append()` and `fetch()` (at most once a second), and
+	/// whenever [`check_staleness`](Self::check_staleness) is called, e.g. from a timer.
+	/// Replaces any previously registered alert.
+	///
+	/// # Examples
+	/// ```
+	/// use std::time::Duration;
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.set_staleness_alert(Duration::from_secs(15 * 60), |age| {
+	///     eprintln!("Uploader looks stuck: oldest event is {:?} old", age);
+	/// });
+	/// ```
+	pub fn set_staleness_alert<F>(&self, threshold: Duration, callback: F)
+	where
+		F: Fn(Duration) + Send + Sync + 'static,
+	{
+		*lock(&self.staleness) = Some(StalenessAlert {
+			threshold,
+			callback: Box::new(callback),
+			last_check: None,
+			fired: false,
+		});
+	}
+
+	/// Checks the age of the oldest pending item against the staleness alert.
+	///
+	/// Returns the oldest item's age if the queue is stale, invoking the callback if this
+	/// is the start of a stale episode. Returns `None` if the queue isn't stale, no alert
+	/// is registered, or the store doesn't track enqueue times.
+	pub fn check_staleness(&self) -> Option<Duration> {
+		let oldest = lock(&self.store).oldest_enqueue_time();
+		let now = Utc::now();
+
+		let mut staleness = lock(&self.staleness);
+		let alert = staleness.as_mut()?;
+		alert.last_check = Some(now);
+
+		let age = oldest.map(|oldest| age::age_at(oldest, now));
+		match age {
+			Some(age) if age >= alert.threshold => {
+				if !alert.fired {
+					alert.fired = true;
+					(alert.callback)(age);
+				}
+				Some(age)
+			}
+			_ => {
+				alert.fired = false;
+				None
+			}
+		}
+	}
+
+	/// Returns a stream of copies of the events appended from now on, for local
+	/// consumers such as a debug overlay or an in-app event validator.
+	///
+	/// Events stay queued for upload; subscribers only see copies, as they were passed
+	/// in, after the store accepted them, including those added by
+	/// [`import_events`](Self::import_events). Each subscription holds
+	/// up to 1024 events; when a subscriber falls behind, newer events are dropped for
+	/// it instead of holding up appends. Dropping the stream ends the subscription.
+	///
+	/// Requires the `subscribe` feature.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{MemoryConfig, MemoryStore, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// let events = db.subscribe();
+	/// // Hand `events` to a task that validates each event as it's appended...
+	/// # drop(events);
+	/// ```
+	#[cfg(feature = "subscribe")]
+	pub fn subscribe(&self) -> impl futures_core::Stream<Item = Value> + Unpin + Send {
+		self.subscribers.subscribe()
+	}
+
+	/// Copies an item for subscribers, if there are any
+	#[cfg(feature = "subscribe")]
+	fn watched<V: Clone>(&self, data: &V) -> Option<V> {
+		self.subscribers.copy(data)
+	}
+
+	#[cfg(not(feature = "subscribe"))]
+	fn watched<V>(&self, _data: &V) -> Option<V> {
+		None
+	}
+
+	/// Sends appended items to subscribers
+	#[cfg(feature = "subscribe")]
+	fn publish(&self, events: impl IntoIterator<Item = Value>) {
+		self.subscribers.publish(events);
+	}
+
+	#[cfg(not(feature = "subscribe"))]
+	fn publish(&self, _events: impl IntoIterator<Item = Value>) {}
+
+	/// Types and sizes of items being appended, if [`set_type_key`](Self::set_type_key)
+	/// was called
+	fn measure<'a>(&self, items: impl IntoIterator<Item = &'a Value>) -> Vec<(String, u64)> {
+		match &*lock(&self.type_stats) {
+			Some(tracker) => items
+				.into_iter()
+				.map(|item| tracker.measure(item))
+				.collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Adds appended items to the per-type statistics
+	fn record_types(&self, measured: Vec<(String, u64)>) {
+		if measured.is_empty() {
+			return;
+		}
+		if let Some(tracker) = &mut *lock(&self.type_stats) {
+			for (kind, bytes) in measured {
+				tracker.record(kind, bytes);
+			}
+		}
+	}
+
+	/// Runs `check_staleness` if an alert is registered and it hasn't run recently
+	fn check_staleness_periodically(&self) {
+		let due = match &*lock(&self.staleness) {
+			Some(StalenessAlert {
+				last_check: Some(last),
+				..
+			}) => age::age_at(*last, Utc::now()) >= StalenessAlert::CHECK_INTERVAL,
+			Some(_) => true,
+			None => false,
+		};
+		if due {
+			self.check_staleness();
+		}
+	}
+
+	/// Records that a fetched batch was accepted by the server.
+	///
+	/// The store remembers a bounded number of delivered batch ids, and ignores
+	/// any later append of an envelope carrying one of them.
+	///
+	/// # Arguments
+	/// * `batch_id` - The `batch_id` of a previous fetch result
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append(json!({"test": "data"})).unwrap();
+	///
+	/// if let Ok(Some(result)) = db.fetch(None, None) {
+	///     let batch_id = result.batch_id.unwrap();
+	///     // Upload using batch_id as the idempotency key...
+	///
+	///     db.mark_delivered(&batch_id).unwrap();
+	///     db.remove(&result.removable.unwrap()).unwrap();
+	///     assert!(db.is_delivered(&batch_id));
+	/// }
+	/// ```
+	pub fn mark_delivered(&self, batch_id: &str) -> Result<()> {
+		lock(&self.store).mark_delivered(batch_id)
+	}
+
+	/// Checks whether a batch was previously marked as delivered.
+	///
+	/// # Arguments
+	/// * `batch_id` - The `batch_id` of a previous fetch result
+	pub fn is_delivered(&self, batch_id: &str) -> bool {
+		lock(&self.store).is_delivered(batch_id)
+	}
+
+	/// Sets the consent categories items may be fetched from.
+	///
+	/// Items appended with [`append_with_consent`](Self::append_with_consent) in any other
+	/// category stay queued but are skipped by fetch, so they are sent if consent is
+	/// granted again; call [`purge_revoked`](Self::purge_revoked) to delete them instead.
+	/// `None`, the default, allows every category.
+	///
+	/// # Arguments
+	/// * `categories` - Categories the user consents to, or `None` to allow all
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't support consent categories.
+	pub fn set_allowed_categories(&self, categories: Option<HashSet<String>>) -> Result<()> {
+		lock(&self.store).set_allowed_categories(categories)
+	}
+
+	/// Deletes queued items whose consent category isn't currently allowed.
+	///
+	/// Returns the number of items deleted.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't support consent categories.
+	pub fn purge_revoked(&self) -> Result<usize> {
+		let result = lock(&self.store).purge_revoked();
+		if let Ok(purged) = result {
+			self.counters.record_remove(&result, purged);
+		}
+		result
+	}
+
+	/// Rewrites fields of every pending event to salted hashes.
+	///
+	/// For when a user opts out of tracking but aggregate events may still be sent:
+	/// identifying fields of events already queued (in memory, in files or in IndexedDB)
+	/// are replaced by SHA-256 hashes under a random salt that is used for this call only.
+	/// Events that shared a value still share its hash, but hashes can't be matched
+	/// against known values or against another call's. See [`Anonymizer`] to hash with a
+	/// fixed salt through the store directly.
+	///
+	/// Returns the number of events changed. Removables from fetches made before the
+	/// call may no longer match their events, so fetch again afterwards. Requires the
+	/// `anonymize` feature.
+	///
+	/// # Arguments
+	/// * `fields` - Fields to anonymize; events without them are left unchanged
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store can't rewrite its events.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{JsonPointer, TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// db.append(json!({"event": "purchase", "userId": "u-123", "context": {"ip": "10.0.0.1"}})).unwrap();
+	///
+	/// // The user opted out
+	/// let fields = ["/userId", "/context/ip"].map(|f| JsonPointer::new(f).unwrap());
+	/// assert_eq!(db.anonymize(&fields).unwrap(), 1);
+	///
+	/// let batch = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// assert_ne!(batch["batch"][0]["userId"], "u-123");
+	/// ```
+	#[cfg(feature = "anonymize")]
+	pub fn anonymize(&self, fields: &[JsonPointer]) -> Result<usize> {
+		let anonymizer = Anonymizer::new(fields)?;
+		lock(&self.store).anonymize(&anonymizer)
+	}
+
+	/// Returns records of the items the store dropped without delivering them since the
+	/// last call, and clears them.
+	///
+	/// Each [`DropRecord`](crate::DropRecord) gives the number of items dropped for a
+	/// reason, such as evictions beyond `max_items`, and when the first and last of them
+	/// were appended, so the app can report the loss, e.g. with an event of its own.
+	/// MemoryStore keeps the records in memory; WebStore mirrors them to localStorage and
+	/// DirectoryStore to a hidden file, so they survive restarts.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't record dropped items.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 2,
+	///     max_fetch_size: 1024,
+	/// }));
+	///
+	/// for i in 0..5 {
+	///     db.append(json!({"event": "scroll", "n": i})).unwrap();
+	/// }
+	///
+	/// let report = db.drop_report().unwrap();
+	/// assert_eq!(report[0].count, 3);
+	///
+	/// for record in report {
+	///     db.append(json!({
+	///         "event": "events_dropped",
+	///         "count": record.count,
+	///         "from": record.first.to_rfc3339(),
+	///         "to": record.last.to_rfc3339(),
+	///     }))
+	///     .unwrap();
+	/// }
+	/// ```
+	pub fn drop_report(&self) -> Result<Vec<DropRecord>> {
+		lock(&self.store).drop_report()
+	}
+
+	/// Whether the store implements an optional capability, so callers can check before
+	/// relying on it rather than handle `Unsupported` errors.
+	///
+	/// # Examples
+	/// ```
+	/// use std::path::PathBuf;
+	/// use transientdb::{
+	///     Capability, DirectoryConfig, DirectoryStore, MemoryConfig, MemoryStore, TransientDB,
+	/// };
+	///
+	/// let memory = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// assert!(memory.supports(Capability::Pinning));
+	///
+	/// let directory = TransientDB::new(DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: PathBuf::from("/tmp/capabilities"),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?);
+	/// assert!(!directory.supports(Capability::Pinning));
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn supports(&self, capability: Capability) -> bool {
+		lock(&self.store).supports(capability)
+	}
+
+	/// Whether the store's events survive a restart, or why not. Lets apps that wrap a
+	/// WebStore check whether it fell back to memory-only mode, and flush more often if so.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store doesn't report its status.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{MemoryConfig, MemoryStore, StoreStatus, TransientDB};
+	///
+	/// let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// }));
+	/// assert_eq!(db.status()?, StoreStatus::MemoryOnly { reason: None });
+	/// assert!(!db.status()?.is_persisted());
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn status(&self) -> Result<StoreStatus> {
+		lock(&self.store).status()
+	}
+
+	/// Returns the batches most recently removed after delivery, oldest first, so
+	/// developers can inspect exactly what was uploaded.
+	///
+	/// Nothing is retained until enabled on the store with `set_retain_removed`, which
+	/// MemoryStore, WebStore and DirectoryStore provide. Intended for debug builds, since
+	/// retained events take memory or disk space.
+	///
+	/// # Errors
+	/// Returns `Unsupported` if the store can't retain removed batches.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let mut store = MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// });
+	/// store.set_retain_removed(2);
+	/// let db = TransientDB::new(store);
+	///
+	/// db.append(json!({"event": "signup"})).unwrap();
+	/// let batch = db.fetch(None, None).unwrap().unwrap();
+	/// db.remove(&batch.removable.unwrap()).unwrap();
+	///
+	/// let removed = db.recently_removed().unwrap();
+	/// assert_eq!(removed[0].events[0]["event"], "signup");
+	/// ```
+	pub fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		lock(&self.store).recently_removed()
+	}
+
+	/// Queues the events of a delivered batch again, for recovering from a server-side
+	/// outage that accepted batches and then lost them. Returns how many events were
+	/// queued.
+	///
+	/// The batch must still be among the store's
+	/// [`recently_removed`](Self::recently_removed) batches, so retention has to be
+	/// enabled before the batch is fetched. Replayed events carry the original batch id
+	/// in a `_replayOf` field, and envelopes containing them are flagged with
+	/// `"replay": true`; they are fetched under a new batch id, so the server's
+	/// idempotency check doesn't discard them.
+	///
+	/// # Errors
+	/// Returns `NotFound` if the batch isn't retained, `InvalidInput` if its events
+	/// aren't JSON objects, and `Unsupported` if the store can't replay batches.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{TransientDB, MemoryStore, MemoryConfig};
+	/// use serde_json::json;
+	///
+	/// let mut store = MemoryStore::new(MemoryConfig {
+	///     write_key: "test".into(),
+	///     max_items: 100,
+	///     max_fetch_size: 1024,
+	/// });
+	/// store.set_retain_removed(10);
+	/// let db = TransientDB::new(store);
+	///
+	/// db.append(json!({"event": "purchase"})).unwrap();
+	/// let batch = db.fetch(None, None).unwrap().unwrap();
+	/// let batch_id = batch.batch_id.unwrap();
+	/// db.remove(&batch.removable.unwrap()).unwrap();
+	///
+	/// // The server reports that it lost the batch
+	/// assert_eq!(db.replay(&batch_id).unwrap(), 1);
+	///
+	/// let envelope = db.fetch(None, None).unwrap().unwrap().data.unwrap();
+	/// assert_eq!(envelope["replay"], true);
+	/// assert_eq!(envelope["batch"][0]["_replayOf"], batch_id.as_str());
+	/// ```
+	pub fn replay(&self, batch_id: &str) -> Result<usize> {
+		lock(&self.store).replay(batch_id)
+	}
+
+	/// Runs `f` with the store locked, to call methods the database doesn't wrap, such as
+	/// a DirectoryStore's `list_batches` or a WebStore's `persistence_state`.
+	///
+	/// With a typed database, made by [`new_typed`](TransientDB::new_typed), `f` gets the
+	/// concrete store. Events appended or removed through it aren't counted in
+	/// [`stats`](TransientDB::stats) or published to subscribers. `f` must not call back
+	/// into the database, which would deadlock.
+	///
+	/// # Examples
+	/// ```
+	/// use transientdb::{DirectoryConfig, DirectoryStore, TransientDB};
+	/// use serde_json::json;
+	/// # let dir = tempfile::TempDir::new()?;
+	///
+	/// let db = TransientDB::new_typed(DirectoryStore::new(DirectoryConfig {
+	///     write_key: "test".into(),
+	///     storage_location: dir.path().to_owned(),
+	///     base_filename: "events".into(),
+	///     max_file_size: 1024,
+	/// })?);
+	/// db.append(json!({"event": "login"}))?;
+	/// db.fetch(None, None)?;
+	///
+	/// let batches = db.with_store(|store| store.list_batches())?;
+	/// assert_eq!(batches.len(), 1);
+	/// # Ok::<(), std::io::Error>(())
+	/// ```
+	pub fn with_store<R>(&self, f: impl FnOnce(&mut D) -> R) -> R {
+		f(&mut lock(&self.store))
+	}
+}
+
+/// Room for one item in a [`TransientDB`], made by [`TransientDB::reserve`].
+///
+/// Committing never evicts items or rotates files. Dropping the slot uncommitted releases
+/// the room.
+#[must_use = "dropping a Slot releases the room it reserved"]
+pub struct Slot<'a, T, D: DataStoreExt<Output = T> + HeldStore + ?Sized = SharedStore<T>> {
+	db: &'a TransientDB<T, D>,
+	estimated_bytes: usize,
+	committed: bool,
+}
+
+impl<T, D: DataStoreExt<Output = T> + HeldStore + ?Sized> Slot<'_, T, D> {
+	/// The size the slot was reserved for.
+	pub fn estimated_bytes(&self) -> usize {
+		self.estimated_bytes
+	}
+
+	/// Appends an item into the reserved room.
+	///
+	/// # Arguments
+	/// * `data` - JSON value to store
+	pub fn commit(mut self, data: Value) -> Result<()> {
+		self.committed = true;
+		let copy = self.db.watched(&data);
+		let measured = self.db.measure([&data]);
+		let result = lock(&self.db.store).commit_reserved(data, self.estimated_bytes);
+		self.db.counters.record_append(&result);
+		result?;
+		self.db.record_types(measured);
+		self.db.publish(copy);
+		Ok(())
+	}
+}
+
+impl<T, D: DataStoreExt<Output = T> + HeldStore + ?Sized> Drop for Slot<'_, T, D> {
+	fn drop(&mut self) {
+		if !self.committed {
+			lock(&self.db.store).release_reserved(self.estimated_bytes);
+		}
+	}
+}
+
+impl<T, D: DataStoreExt<Output = T> + HeldStore + ?Sized> std::fmt::Debug for Slot<'_, T, D> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Slot")
+			.field("estimated_bytes", &self.estimated_bytes)
+			.finish()
 	}
 }
@@ -0,0 +1,30 @@
+//! Keys of state events, which replace the queued event with the same key.
+//!
+//! An event appended with `upsert` carries its key in an `_upsertKey` field, so that a
+//! later event with the same key can find and replace it, and only the latest state is
+//! sent.
+
+use serde_json::Value;
+use std::io::{self, Result};
+
+/// Key of the upsert key added to events.
+pub(crate) const UPSERT_KEY: &str = "_upsertKey";
+
+/// Records the key an event replaces others by.
+///
+/// Fails if the event isn't a JSON object, since there is nowhere to put the key.
+pub(crate) fn tag(data: &mut Value, key: &str) -> Result<()> {
+	let object = data.as_object_mut().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only JSON objects can be upserted",
+		)
+	})?;
+	object.insert(UPSERT_KEY.to_string(), Value::from(key));
+	Ok(())
+}
+
+/// Whether an event was upserted with `key`.
+pub(crate) fn has_key(event: &Value, key: &str) -> bool {
+	event.get(UPSERT_KEY).and_then(Value::as_str) == Some(key)
+}
@@ -0,0 +1,212 @@
+//! [`Fs`] adapter for the `vfs` crate.
+
+use crate::fs::{Fs, FsEntry, FsMetadata, FsWriter};
+use std::io::{self, Read, Result, Write};
+use std::path::{Component, Path};
+use vfs::error::VfsErrorKind;
+use vfs::{VfsError, VfsFileType, VfsPath};
+
+/// Runs a DirectoryStore on any filesystem from the [`vfs`](https://crates.io/crates/vfs)
+/// crate, for platforms without direct `std::fs` access such as sandboxed app storage
+/// or game consoles.
+///
+/// Store paths are resolved inside `root`: a storage location of `/queue/events` is the
+/// directory `queue/events` under it. Renames replace the destination by removing it
+/// first, so unlike on a real filesystem they aren't atomic.
+///
+/// Requires the `vfs` feature.
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use serde_json::json;
+/// use transientdb::{DataStore, DirectoryConfig, DirectoryStore, SystemClock, VfsFs};
+///
+/// let root = vfs::VfsPath::new(vfs::MemoryFS::new());
+/// let mut store = DirectoryStore::with_fs(
+///     DirectoryConfig {
+///         write_key: "test".into(),
+///         storage_location: PathBuf::from("/queue"),
+///         base_filename: "events".into(),
+///         max_file_size: 1024,
+///     },
+///     Arc::new(VfsFs::new(root.clone())),
+///     Arc::new(SystemClock),
+/// )?;
+///
+/// store.append(json!({"event": "test"}))?;
+/// let files = store.fetch(None, None)?.unwrap().data.unwrap();
+/// assert!(root.join("queue")?.join(files[0].file_name().unwrap().to_str().unwrap())?.exists()?);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct VfsFs {
+	root: VfsPath,
+}
+
+impl VfsFs {
+	/// Creates an adapter resolving store paths inside `root`.
+	pub fn new(root: VfsPath) -> Self {
+		Self { root }
+	}
+
+	/// Maps a store path onto the VFS, ignoring any root or drive prefix.
+	fn resolve(&self, path: &Path) -> Result<VfsPath> {
+		let mut resolved = self.root.clone();
+		for component in path.components() {
+			match component {
+				Component::Normal(name) => {
+					let name = name.to_str().ok_or_else(|| {
+						io::Error::new(
+							io::ErrorKind::InvalidInput,
+							format!("Path is not valid UTF-8: {:?}", path),
+						)
+					})?;
+					resolved = resolved.join(name).map_err(to_io)?;
+				}
+				Component::ParentDir => resolved = resolved.parent(),
+				Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+			}
+		}
+		Ok(resolved)
+	}
+}
+
+fn to_io(error: VfsError) -> io::Error {
+	let kind = match error.kind() {
+		VfsErrorKind::IoError(e) => e.kind(),
+		VfsErrorKind::FileNotFound => io::ErrorKind::NotFound,
+		VfsErrorKind::FileExists | VfsErrorKind::DirectoryExists => io::ErrorKind::AlreadyExists,
+		VfsErrorKind::InvalidPath => io::ErrorKind::InvalidInput,
+		VfsErrorKind::NotSupported => io::ErrorKind::Unsupported,
+		_ => io::ErrorKind::Other,
+	};
+	io::Error::new(kind, error.to_string())
+}
+
+impl Fs for VfsFs {
+	fn create_dir_all(&self, path: &Path) -> Result<()> {
+		self.resolve(path)?.create_dir_all().map_err(to_io)
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<FsEntry>> {
+		let mut entries = Vec::new();
+		for entry in self.resolve(path)?.read_dir().map_err(to_io)? {
+			let is_file = entry
+				.metadata()
+				.is_ok_and(|m| m.file_type == VfsFileType::File);
+			entries.push(FsEntry {
+				path: path.join(entry.filename()),
+				is_file,
+				is_symlink: false,
+			});
+		}
+		Ok(entries)
+	}
+
+	fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+		let metadata = self.resolve(path)?.metadata().map_err(to_io)?;
+		Ok(FsMetadata {
+			len: metadata.len,
+			created: metadata.created.or(metadata.modified).map(Into::into),
+		})
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>> {
+		let mut bytes = Vec::new();
+		self.resolve(path)?
+			.open_file()
+			.map_err(to_io)?
+			.read_to_end(&mut bytes)?;
+		Ok(bytes)
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+		let mut file = self.resolve(path)?.create_file().map_err(to_io)?;
+		file.write_all(contents)?;
+		file.flush()
+	}
+
+	fn create_new(&self, path: &Path) -> Result<FsWriter> {
+		let path = self.resolve(path)?;
+		if path.exists().map_err(to_io)? {
+			return Err(io::Error::new(
+				io::ErrorKind::AlreadyExists,
+				format!("File exists: {}", path.as_str()),
+			));
+		}
+		Ok(Box::new(path.create_file().map_err(to_io)?))
+	}
+
+	fn append(&self, path: &Path) -> Result<FsWriter> {
+		Ok(Box::new(self.resolve(path)?.append_file().map_err(to_io)?))
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()> {
+		self.resolve(path)?.remove_file().map_err(to_io)
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+		let to = self.resolve(to)?;
+		if to.exists().map_err(to_io)? {
+			to.remove_file().map_err(to_io)?;
+		}
+		self.resolve(from)?.move_file(&to).map_err(to_io)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::VfsFs;
+	use crate::{DataStore, DataStoreExt, DirectoryConfig, DirectoryStore, SystemClock};
+	use serde_json::json;
+	use std::io::Result;
+	use std::path::PathBuf;
+	use std::sync::Arc;
+	use vfs::{MemoryFS, VfsPath};
+
+	#[test]
+	fn test_directory_store_over_vfs() -> Result<()> {
+		let root = VfsPath::new(MemoryFS::new());
+		let config = DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: PathBuf::from("/queue/events"),
+			base_filename: "events".to_string(),
+			max_file_size: 100,
+		};
+		let open = || {
+			DirectoryStore::with_fs(
+				config.clone(),
+				Arc::new(VfsFs::new(root.clone())),
+				Arc::new(SystemClock),
+			)
+		};
+
+		let mut store = open()?;
+		store.set_delivery_cursor(true)?;
+		for i in 0..6 {
+			store.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+		}
+		let result = store.fetch(Some(1), None)?.unwrap();
+		store.remove(&result.removable.unwrap())?;
+		store.append(json!({"index": 6}))?;
+		drop(store);
+
+		// Reopening recovers the unfinished file from the VFS
+		let mut store = open()?;
+		let indices: Vec<i64> = store
+			.pending_events()?
+			.iter()
+			.filter_map(|e| e["index"].as_i64())
+			.collect();
+		assert!(indices.len() > 1 && !indices.contains(&0));
+		assert_eq!(indices.last(), Some(&6));
+
+		while let Some(result) = store.fetch(None, None)? {
+			store.remove(&result.removable.unwrap())?;
+		}
+		assert!(!store.has_data());
+		Ok(())
+	}
+}
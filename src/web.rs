@@ -19,19 +19,63 @@
 //! └─────────────────────────────────────────────────────┘
 //! ```
 
-use crate::{DataResult, DataStore, Equivalent};
+use crate::attachment::{self, Attachment, AttachmentContent, AttachmentHandle};
+use crate::consent::ConsentFilter;
+use crate::context;
+use crate::delay;
+use crate::delivery::{DeliveredBatches, InFlightBatches};
+use crate::drops::{DropLog, DropReason, EvictionNotifier};
+use crate::envelope;
+use crate::eviction::{self, EvictionCandidate, EvictionPolicy};
+use crate::field_filter::FieldFilter;
+use crate::logging;
+use crate::moves;
+use crate::quota::QuotaGate;
+use crate::received;
+use crate::removed::{FetchedBatches, RemovedRing};
+use crate::replay;
+use crate::routing;
+use crate::sampling::{DownSampling, Sampler};
+use crate::schema::SchemaMigrations;
+use crate::sized::SizedValue;
+use crate::skew;
+use crate::snapshot::{self, SnapshotStorage};
+use crate::upsert;
+#[cfg(feature = "anonymize")]
+use crate::Anonymizer;
+use crate::{
+	BatchPreview, Capability, ClockOffsetProvider, ContextProvider, DataResult, DataStore,
+	DataStoreExt, DropRecord, Equivalent, EvictionListener, JsonFormat, PendingSize, RemovedBatch,
+	StoreStatus, TransientError,
+};
+use chrono::{DateTime, TimeDelta, Utc};
 use serde_json::{json, Value};
 use std::any::Any;
-use std::cell::RefCell;
-use std::collections::VecDeque;
-use std::io::{Error, Result};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io::{Error, Result, Write};
+use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use web_sys::{IdbDatabase, IdbRequest};
+use web_sys::{DomException, IdbDatabase, IdbFactory, IdbRequest, IdbTransaction};
 
-const DB_VERSION: u32 = 1;
+/// Version of the IndexedDB database, which doubles as the format version of its records:
+/// bump it whenever they change in a way older releases can't read.
+const DB_VERSION: u32 = 2;
 const STORE_NAME: &str = "events";
+const ATTACHMENTS_STORE_NAME: &str = "attachments";
+/// Events converted per slice of hydration, between which the browser can run other tasks
+const DEFAULT_HYDRATION_CHUNK_SIZE: usize = 250;
+/// Delays before each background retry of a failed IndexedDB open
+const REOPEN_DELAYS_MS: [i32; 3] = [500, 2_000, 10_000];
+/// Delays before each attempt of a deferred open, the first made right away
+const DEFERRED_OPEN_DELAYS_MS: [i32; 4] = [0, 500, 2_000, 10_000];
 
 /// Configuration for the web-based data store.
 #[derive(Clone)]
@@ -51,24 +95,25 @@ pub struct WebConfig {
 
 /// Internal representation of a stored event with its IndexedDB key
 #[derive(Clone, Debug)]
-struct StoredEvent {
-	/// Auto-generated IndexedDB key
+pub(crate) struct StoredEvent {
+	/// Identifies the event within the store. Unlike the IndexedDB key, it doesn't
+	/// change when a memory-only backlog is written to a reopened database.
+	seq: u64,
+	/// IndexedDB key
 	idb_key: Option<u32>,
-	/// The actual event data
-	value: Value,
+	/// The actual event data, shared with the removables of a fetch
+	value: SizedValue,
+	/// When the event was appended, or hydrated from IndexedDB for events
+	/// persisted by an earlier session
+	enqueued_at: DateTime<Utc>,
 }
 
 impl Equivalent for StoredEvent {
 	fn equals(&self, other: &dyn Equivalent) -> bool {
-		if let Some(other_event) = other.as_any().downcast_ref::<StoredEvent>() {
-			// Compare by idb_key if both have one, otherwise by value
-			match (&self.idb_key, &other_event.idb_key) {
-				(Some(a), Some(b)) => a == b,
-				_ => self.value == other_event.value,
-			}
-		} else {
-			false
-		}
+		other
+			.as_any()
+			.downcast_ref::<StoredEvent>()
+			.is_some_and(|other_event| self.seq == other_event.seq)
 	}
 
 	fn as_any(&self) -> &dyn Any {
@@ -76,6 +121,25 @@ impl Equivalent for StoredEvent {
 	}
 }
 
+/// localStorage, as storage for emergency snapshots
+struct LocalStorage(web_sys::Storage);
+
+impl SnapshotStorage for LocalStorage {
+	fn get(&self, key: &str) -> Option<String> {
+		self.0.get_item(key).ok().flatten()
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> bool {
+		self.0.set_item(key, value).is_ok()
+	}
+
+	fn remove(&mut self, key: &str) {
+		if let Err(e) = self.0.remove_item(key) {
+			logging::log_warn!("Failed to remove {} from localStorage: {:?}", key, e);
+		}
+	}
+}
+
 /// Indicates the persistence state of the WebStore.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PersistenceState {
@@ -90,6 +154,198 @@ pub enum PersistenceState {
 	MemoryOnly,
 }
 
+/// What kind of failure left a WebStore memory-only, see [`PersistenceFailure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PersistenceFailureKind {
+	/// The browser doesn't provide IndexedDB, or there is no `window` (e.g. in a worker).
+	Unavailable,
+	/// IndexedDB refused to open, typically because of private browsing, a storage
+	/// policy, or third-party storage restrictions.
+	Blocked,
+	/// The origin's storage quota is used up.
+	QuotaExceeded,
+	/// The database was written in a newer format; see [`WebStore::format_error`].
+	IncompatibleFormat,
+	/// Any other failure.
+	Unknown,
+}
+
+/// Why a WebStore couldn't use IndexedDB, from
+/// [`persistence_details()`](WebStore::persistence_details).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistenceFailure {
+	/// What kind of failure it was.
+	pub kind: PersistenceFailureKind,
+	/// Name of the `DOMException` the browser reported, such as `"SecurityError"`, if any.
+	pub exception: Option<String>,
+	/// Description of the failure, for logs.
+	pub message: String,
+}
+
+impl PersistenceFailure {
+	fn new(kind: PersistenceFailureKind, message: impl Into<String>) -> Self {
+		Self {
+			kind,
+			exception: None,
+			message: message.into(),
+		}
+	}
+
+	/// Classifies an error thrown or reported by IndexedDB
+	fn from_js(context: &str, error: &JsValue) -> Self {
+		let Some(exception) = error.dyn_ref::<DomException>() else {
+			#[cfg(feature = "small-wasm")]
+			let message = context.to_string();
+			#[cfg(not(feature = "small-wasm"))]
+			let message = format!("{}: {:?}", context, error);
+			return Self::new(PersistenceFailureKind::Unknown, message);
+		};
+		let name = exception.name();
+		let kind = match name.as_str() {
+			"QuotaExceededError" => PersistenceFailureKind::QuotaExceeded,
+			// Firefox reports InvalidStateError in private windows
+			"SecurityError" | "NotAllowedError" | "InvalidStateError" => {
+				PersistenceFailureKind::Blocked
+			}
+			"VersionError" => PersistenceFailureKind::IncompatibleFormat,
+			_ => PersistenceFailureKind::Unknown,
+		};
+		Self {
+			kind,
+			message: format!("{}: {}: {}", context, name, exception.message()),
+			exception: Some(name),
+		}
+	}
+
+	/// Whether a later open may succeed, e.g. after Firefox's `InvalidStateError` right
+	/// after startup. A missing IndexedDB or a newer format won't go away.
+	fn is_transient(&self) -> bool {
+		!matches!(
+			self.kind,
+			PersistenceFailureKind::Unavailable | PersistenceFailureKind::IncompatibleFormat
+		)
+	}
+
+	/// Recovers the failure from an error returned by `open_database`
+	fn from_io(error: &Error) -> Self {
+		if let Some(failure) = error.get_ref().and_then(|e| e.downcast_ref::<Self>()) {
+			return failure.clone();
+		}
+		let kind = match TransientError::from_io(error) {
+			Some(TransientError::IncompatibleFormat { .. }) => {
+				PersistenceFailureKind::IncompatibleFormat
+			}
+			_ => PersistenceFailureKind::Unknown,
+		};
+		Self::new(kind, error.to_string())
+	}
+}
+
+/// Turns an error from a JS call into an `io::Error` naming the call.
+///
+/// With the `small-wasm` feature the JS value is left out of the message, which keeps
+/// its formatting code out of the binary.
+fn js_error(context: &'static str) -> impl Fn(JsValue) -> Error {
+	move |error| {
+		#[cfg(feature = "small-wasm")]
+		{
+			let _ = error;
+			Error::other(context)
+		}
+		#[cfg(not(feature = "small-wasm"))]
+		Error::other(format!("{}: {:?}", context, error))
+	}
+}
+
+/// Turns an error thrown by IndexedDB into an `io::Error` carrying its
+/// [`PersistenceFailure`], so the `DOMException` can be recovered from it.
+fn idb_error(context: &'static str) -> impl Fn(JsValue) -> Error {
+	move |error| PersistenceFailure::from_js(context, &error).into()
+}
+
+impl fmt::Display for PersistenceFailure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.message)
+	}
+}
+
+impl std::error::Error for PersistenceFailure {}
+
+impl From<PersistenceFailure> for Error {
+	fn from(failure: PersistenceFailure) -> Self {
+		Error::other(failure)
+	}
+}
+
+/// A background IndexedDB operation of a WebStore, see [`IdbError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IdbOperation {
+	/// Writing appended events.
+	Add,
+	/// Overwriting an event, e.g. when anonymizing or stamping it.
+	Put,
+	/// Deleting a removed or evicted event.
+	Delete,
+	/// Writing an attachment.
+	PutAttachment,
+	/// Deleting an attachment no event refers to anymore.
+	DeleteAttachment,
+}
+
+impl IdbOperation {
+	fn describe(self) -> &'static str {
+		match self {
+			IdbOperation::Add => "write",
+			IdbOperation::Put => "update",
+			IdbOperation::Delete => "delete",
+			IdbOperation::PutAttachment => "attachment write",
+			IdbOperation::DeleteAttachment => "attachment delete",
+		}
+	}
+}
+
+/// A background IndexedDB operation that failed, passed to the observer set with
+/// [`set_idb_error_observer()`](WebStore::set_idb_error_observer).
+///
+/// The events involved stay queued in memory, so a failed write loses them only if the
+/// page closes before they're fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdbError {
+	/// What the store was doing.
+	pub operation: IdbOperation,
+	/// How many events the operation covered.
+	pub events: usize,
+	/// Why it failed: its kind, such as `QuotaExceeded`, and the browser's `DOMException`
+	/// name, whether a request failed or the transaction was aborted.
+	pub failure: PersistenceFailure,
+}
+
+/// Type alias for the observer of failed IndexedDB operations
+type IdbErrorObserver = Box<dyn Fn(&IdbError)>;
+
+/// Reports failed background operations, shared with the tasks running them
+#[derive(Default)]
+struct IdbErrors {
+	observer: RefCell<Option<IdbErrorObserver>>,
+	count: Cell<u64>,
+}
+
+impl IdbErrors {
+	fn report(&self, operation: IdbOperation, events: usize, error: &Error) {
+		logging::log_warn!("IndexedDB {} failed: {:?}", operation.describe(), error);
+		self.count.set(self.count.get() + 1);
+		if let Some(observer) = &*self.observer.borrow() {
+			observer(&IdbError {
+				operation,
+				events,
+				failure: PersistenceFailure::from_io(error),
+			});
+		}
+	}
+}
+
 /// A browser-based data store using IndexedDB for persistence.
 ///
 /// Events are stored in an in-memory queue for fast synchronous access,
@@ -102,9 +358,18 @@ pub enum PersistenceState {
 /// unavailable (private browsing, third-party context, storage blocked, etc),
 /// the store falls back to memory-only mode and logs a warning to the console.
 ///
+/// Failures that may be transient, like Firefox's `InvalidStateError` right after
+/// startup, are retried a few times in the background. When a retry succeeds, the
+/// store switches to `Persisted` on its next operation: events from an earlier
+/// session are loaded ahead of those buffered in memory, which are written to
+/// IndexedDB. Call [`retry_persistence()`](Self::retry_persistence) to try again
+/// yourself, e.g. once the user grants storage access.
+///
 /// Use [`persistence_state()`](Self::persistence_state) to check the current
 /// mode and adjust behavior accordingly (e.g., flush more aggressively when
-/// in memory-only mode to minimize the data loss window).
+/// in memory-only mode to minimize the data loss window), and
+/// [`persistence_details()`](Self::persistence_details) to find out why
+/// IndexedDB couldn't be used.
 ///
 /// # Third-Party Context Warning
 ///
@@ -122,23 +387,207 @@ pub struct WebStore {
 	temp_key_counter: u32,
 	/// Current persistence state
 	persistence_state: PersistenceState,
+	/// Ids of batches already delivered, mirrored to localStorage
+	delivered: DeliveredBatches,
+	/// Ids of fetched batches not yet removed, by event `seq`, mirrored to localStorage
+	/// by IndexedDB key
+	in_flight: InFlightBatches<u64>,
+	/// Events evicted since the last drop report, mirrored to localStorage
+	drops: DropLog,
+	/// The last removed batches, if retained for debugging
+	removed: RemovedRing,
+	/// Ids of recent fetches, while removed batches are retained
+	fetched: FetchedBatches,
+	/// Format used for batch envelopes and IndexedDB writes
+	json_format: JsonFormat,
+	/// Attachment bytes by id, mirrored to the attachments object store
+	attachments: HashMap<String, Attachment>,
+	/// Consent categories fetches are limited to
+	consent: ConsentFilter,
+	/// Fields stripped from appended events
+	field_filter: FieldFilter,
+	/// Schema version stamped on appended events, and how to upgrade older ones
+	migrations: Option<SchemaMigrations>,
+	/// Field stamped with the time on events queued without a timestamp
+	received_at: Option<String>,
+	/// Drops low-priority events under sustained pressure, if set
+	sampler: Option<Sampler>,
+	/// Stops writes to IndexedDB near the storage quota, if set
+	quota: Option<Rc<RefCell<QuotaGate>>>,
+	/// Failed background IndexedDB operations, and who to tell about them
+	idb_errors: Rc<IdbErrors>,
+	/// Set if the database was written in a newer format and left alone
+	format_error: Option<TransientError>,
+	/// Why IndexedDB couldn't be used, while memory-only
+	persistence_failure: Option<PersistenceFailure>,
+	/// Chooses the events to drop when over capacity; the oldest if unset
+	eviction: Option<Box<dyn EvictionPolicy>>,
+	/// Notified with the events the store drops, if set
+	eviction_listener: Option<EvictionNotifier>,
+	/// Supplies the context added to fetched envelopes
+	context: Option<Box<dyn ContextProvider>>,
+	/// Supplies the offset fetched timestamps are corrected by
+	clock_offset: Option<Box<dyn ClockOffsetProvider>>,
+	/// Set once an event is held by a move, so fetches and evictions don't check every
+	/// event for one until then
+	moving: bool,
+	/// Set once a delayed event is queued, so fetches don't check every event's time
+	/// until then
+	delayed: bool,
+	/// Set once a replayed event is queued, so fetches don't check every event for one
+	/// until then
+	replayed: bool,
+	/// How many pinned events may be queued
+	max_pinned: usize,
+	/// Number of reserved events not yet committed
+	reserved: usize,
+	/// Next [`StoredEvent::seq`]
+	next_seq: u64,
+	/// Database opened by a background retry, adopted on the next operation
+	reopened: Rc<RefCell<Option<Reopened>>>,
+	/// Why background opens gave up, adopted on the next operation
+	reopen_failure: Rc<RefCell<Option<Error>>>,
+	/// Events loaded by the hydration task, merged on the next operation
+	hydration: Rc<RefCell<Hydration>>,
+}
+
+/// Progress of a time-sliced hydration, shared with the task converting the records
+struct Hydration {
+	/// Converted events not yet merged into the store
+	loaded: Vec<(Option<u32>, Value)>,
+	/// Persisted attachments, loaded once all events are
+	attachments: HashMap<String, Attachment>,
+	/// Hydrated events have keys below this, events appended meanwhile at or above it
+	boundary: u32,
+	complete: bool,
+	/// Set by `reset`, so the task deletes the records it hasn't converted
+	cancelled: bool,
+	wakers: Vec<Waker>,
+}
+
+impl Hydration {
+	fn finished() -> Self {
+		Self {
+			loaded: Vec::new(),
+			attachments: HashMap::new(),
+			boundary: 0,
+			complete: true,
+			cancelled: false,
+			wakers: Vec::new(),
+		}
+	}
+
+	fn finish(&mut self) {
+		self.complete = true;
+		for waker in self.wakers.drain(..) {
+			waker.wake();
+		}
+	}
+}
+
+/// Resolves once a hydration has loaded every persisted event
+struct HydrationComplete(Rc<RefCell<Hydration>>);
+
+impl Future for HydrationComplete {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		let mut hydration = self.0.borrow_mut();
+		if hydration.complete {
+			Poll::Ready(())
+		} else {
+			hydration.wakers.push(cx.waker().clone());
+			Poll::Pending
+		}
+	}
+}
+
+/// A database a background retry managed to open, with what it already held
+struct Reopened {
+	db: IdbDatabase,
+	events: Vec<(Option<u32>, Value)>,
+	attachments: HashMap<String, Attachment>,
 }
 
 impl WebStore {
 	/// Creates a new WebStore with IndexedDB persistence.
 	///
 	/// This opens (or creates) the IndexedDB database and hydrates
-	/// in-memory state from any previously persisted events.
+	/// in-memory state from any previously persisted events. Hydration converts
+	/// events in slices, yielding to the browser in between, so a large backlog
+	/// doesn't block the page in one long task; see
+	/// [`new_hydrating()`](Self::new_hydrating) to return before it completes.
 	///
 	/// If IndexedDB is unavailable (private browsing, third-party context,
 	/// storage blocked, etc), the store falls back to memory-only mode
-	/// and logs a warning to the console. Check [`persistence_state()`](Self::persistence_state)
+	/// and logs a warning (see [`set_logger`](crate::set_logger)). Check [`persistence_state()`](Self::persistence_state)
 	/// to detect this condition.
 	///
 	/// # Panics
 	/// * If max_fetch_size is less than 100 bytes
 	/// * If max_items is 0
 	pub async fn new(config: WebConfig) -> Self {
+		let mut store = Self::open(config, DEFAULT_HYDRATION_CHUNK_SIZE).await;
+		store.hydration_complete().await;
+		store.adopt_loaded();
+		store
+	}
+
+	/// Creates a new WebStore that hydrates in the background, converting
+	/// `chunk_size` persisted events per slice.
+	///
+	/// Returns once IndexedDB is open, so startup isn't held up by a large backlog.
+	/// The store can be used right away: events appended meanwhile are queued after
+	/// the hydrated ones, which each operation merges as they're loaded, so fetches
+	/// made before [`hydration_complete()`](Self::hydration_complete) resolves may
+	/// miss older events.
+	///
+	/// # Panics
+	/// Same as [`new()`](Self::new).
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// let store = WebStore::new_hydrating(config, 100).await;
+	/// let hydrated = store.hydration_complete();
+	/// // ... render the page ...
+	/// hydrated.await;
+	/// ```
+	pub async fn new_hydrating(config: WebConfig, chunk_size: usize) -> Self {
+		Self::open(config, chunk_size).await
+	}
+
+	/// Creates a new WebStore that can be used right away and opens IndexedDB in the
+	/// background, so startup doesn't wait on it.
+	///
+	/// Until IndexedDB is open the store is memory-only, with appended events (at most
+	/// `max_items`, as always) held in memory. Once it's open, the store switches to
+	/// [`PersistenceState::Persisted`] on its next operation: events from an earlier
+	/// session are loaded ahead of those appended meanwhile, which are written to
+	/// IndexedDB. Failures that may be transient are retried like after
+	/// [`new()`](Self::new); if opening fails for good, the store stays memory-only
+	/// and [`persistence_details()`](Self::persistence_details) says why.
+	/// [`is_opening()`](Self::is_opening) tells whether the open is still running.
+	///
+	/// # Panics
+	/// Same as [`new()`](Self::new).
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// let mut store = WebStore::new_deferred(config);
+	/// store.append(json!({"event": "app_start"}))?; // Doesn't wait for IndexedDB
+	/// ```
+	pub fn new_deferred(config: WebConfig) -> Self {
+		let mut store = Self::memory_only(config);
+		store.restore_snapshot();
+		store.spawn_reopen(&DEFERRED_OPEN_DELAYS_MS);
+		store
+	}
+
+	/// Creates a memory-only store, checking the configuration
+	#[allow(clippy::panic)] // Invalid configuration is a programming error
+	fn memory_only(config: WebConfig) -> Self {
 		if config.max_fetch_size < 100 {
 			panic!("max_fetch_size < 100 bytes? What are you even trying to fetch, empty arrays?");
 		}
@@ -152,35 +601,73 @@ impl WebStore {
 			db: None,
 			temp_key_counter: 0,
 			persistence_state: PersistenceState::MemoryOnly,
+			delivered: DeliveredBatches::new(DeliveredBatches::DEFAULT_CAPACITY),
+			in_flight: InFlightBatches::default(),
+			drops: DropLog::default(),
+			removed: RemovedRing::default(),
+			fetched: FetchedBatches::default(),
+			json_format: JsonFormat::default(),
+			attachments: HashMap::new(),
+			consent: ConsentFilter::default(),
+			field_filter: FieldFilter::default(),
+			migrations: None,
+			received_at: None,
+			sampler: None,
+			quota: None,
+			idb_errors: Rc::default(),
+			format_error: None,
+			persistence_failure: None,
+			eviction: None,
+			eviction_listener: None,
+			context: None,
+			clock_offset: None,
+			moving: false,
+			delayed: false,
+			replayed: false,
+			max_pinned: eviction::DEFAULT_MAX_PINNED,
+			reserved: 0,
+			next_seq: 0,
+			reopened: Rc::new(RefCell::new(None)),
+			reopen_failure: Rc::new(RefCell::new(None)),
+			hydration: Rc::new(RefCell::new(Hydration::finished())),
 		};
+		store.delivered = store.load_delivered();
+		store.drops = store.load_drops();
+		store
+	}
+
+	/// Opens IndexedDB and starts hydrating, falling back to memory-only
+	async fn open(config: WebConfig, chunk_size: usize) -> Self {
+		let mut store = Self::memory_only(config);
 
 		// Attempt to open IndexedDB - fall back to memory-only if it fails
-		match store.open_database().await {
+		match Self::open_database(&store.config.database_name).await {
 			Ok(db) => {
 				store.db = Some(Rc::new(db));
 				store.persistence_state = PersistenceState::Persisted;
 
 				// Hydrate from IndexedDB
-				if let Err(e) = store.hydrate().await {
-					web_sys::console::warn_1(
-						&format!("Failed to hydrate from IndexedDB, starting fresh: {:?}", e)
-							.into(),
-					);
+				if let Err(e) = store.hydrate(chunk_size).await {
+					logging::log_warn!("Failed to hydrate from IndexedDB, starting fresh: {:?}", e);
 				}
 			}
 			Err(e) => {
-				web_sys::console::warn_1(
-					&format!(
-						"IndexedDB unavailable ({}), falling back to memory-only storage. \
+				let failure = PersistenceFailure::from_io(&e);
+				store.format_error = TransientError::from_io(&e).cloned();
+				logging::log_warn!(
+					"IndexedDB unavailable ({}), falling back to memory-only storage. \
                          Events will not persist across page refreshes. \
                          Consider increasing flush frequency.",
-						e
-					)
-					.into(),
+					e
 				);
 				// persistence_state already set to MemoryOnly
+				if failure.is_transient() {
+					store.schedule_reopen();
+				}
+				store.persistence_failure = Some(failure);
 			}
 		}
+		store.restore_snapshot();
 
 		store
 	}
@@ -204,6 +691,75 @@ impl WebStore {
 		self.persistence_state
 	}
 
+	/// Returns why IndexedDB couldn't be used, or `None` if the store is persisted.
+	///
+	/// Distinguishes browsers without IndexedDB, opens blocked by private browsing or
+	/// policy, and exhausted quota, and carries the name of the browser's
+	/// `DOMException`, for telemetry about how often and why persistence fails.
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// if let Some(failure) = store.persistence_details() {
+	///     telemetry.record("storage_unavailable", failure.kind, failure.exception.as_deref());
+	/// }
+	/// ```
+	pub fn persistence_details(&self) -> Option<&PersistenceFailure> {
+		self.persistence_failure.as_ref()
+	}
+
+	/// Returns `true` while a store created with [`new_deferred()`](Self::new_deferred)
+	/// is still opening IndexedDB.
+	pub fn is_opening(&self) -> bool {
+		!self.is_persisted()
+			&& self.persistence_failure.is_none()
+			&& self.reopened.borrow().is_none()
+			&& self.reopen_failure.borrow().is_none()
+	}
+
+	/// Tries to open IndexedDB again if the store is memory-only, e.g. once the user
+	/// grants storage access, and switches to [`PersistenceState::Persisted`] on success.
+	///
+	/// Events buffered in memory are written to IndexedDB with their attachments, after
+	/// any left by an earlier session. Does nothing if the store is already persisted.
+	/// On failure the store stays memory-only and
+	/// [`persistence_details()`](Self::persistence_details) describes the new failure.
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// if storage_access_granted {
+	///     store.retry_persistence().await?;
+	/// }
+	/// ```
+	pub async fn retry_persistence(&mut self) -> Result<()> {
+		self.adopt_loaded();
+		if self.is_persisted() {
+			return Ok(());
+		}
+		match Self::reopen(&self.config.database_name).await {
+			Ok(reopened) => {
+				*self.reopened.borrow_mut() = Some(reopened);
+				self.adopt_reopened();
+				Ok(())
+			}
+			Err(e) => {
+				self.format_error = TransientError::from_io(&e).cloned();
+				self.persistence_failure = Some(PersistenceFailure::from_io(&e));
+				Err(e)
+			}
+		}
+	}
+
+	/// Returns [`TransientError::IncompatibleFormat`] if the database was written in a
+	/// newer format, by a later release of this crate.
+	///
+	/// The store then runs memory-only and leaves the database untouched, rather than
+	/// misreading its records.
+	pub fn format_error(&self) -> Option<&TransientError> {
+		self.format_error.as_ref()
+	}
+
 	/// Returns `true` if IndexedDB persistence is available.
 	///
 	/// Convenience method equivalent to checking if
@@ -212,170 +768,1353 @@ impl WebStore {
 		self.persistence_state == PersistenceState::Persisted
 	}
 
-	/// Opens or creates the IndexedDB database
-	async fn open_database(&self) -> Result<IdbDatabase> {
-		let window = web_sys::window().ok_or_else(|| Error::other("No window object"))?;
-
-		let idb_factory = window
-			.indexed_db()
-			.map_err(|e| Error::other(format!("IndexedDB error: {:?}", e)))?
-			.ok_or_else(|| Error::other("IndexedDB not available"))?;
-
-		// Create open request
-		let open_request = idb_factory
-			.open_with_f64(&self.config.database_name, DB_VERSION as f64)
-			.map_err(|e| Error::other(format!("Failed to open DB: {:?}", e)))?;
+	/// Changes `max_items` and `max_fetch_size` of a running store, e.g. from remote config,
+	/// without rebuilding it and hydrating again.
+	///
+	/// A lower `max_items` evicts the oldest events right away, from memory and IndexedDB.
+	/// Fails with `InvalidInput` for limits [`new()`](Self::new) would reject, leaving the
+	/// current ones in place.
+	pub fn set_limits(&mut self, max_items: usize, max_fetch_size: usize) -> Result<()> {
+		if max_items == 0 || max_fetch_size < 100 {
+			return Err(Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!(
+					"invalid limits: max_items {} (must be > 0), max_fetch_size {} (must be >= 100)",
+					max_items, max_fetch_size
+				),
+			));
+		}
+		self.adopt_loaded();
+		self.config.max_items = max_items;
+		self.config.max_fetch_size = max_fetch_size;
+		self.evict_to(max_items.saturating_sub(self.reserved).max(1));
+		Ok(())
+	}
 
-		// Set up upgrade handler for first-time creation
-		let on_upgrade = Closure::once(move |event: web_sys::IdbVersionChangeEvent| {
-			let target = event.target().unwrap();
-			let request: IdbRequest = target.unchecked_into();
-			let db: IdbDatabase = request.result().unwrap().unchecked_into();
+	/// Sets how many events appended with [`append_pinned`](DataStoreExt::append_pinned) may
+	/// be queued at once (100 by default).
+	///
+	/// Pinned events count towards `max_items` but are never evicted, so if pins take up
+	/// all of it the store holds more than `max_items` events.
+	pub fn set_max_pinned(&mut self, max_pinned: usize) {
+		self.max_pinned = max_pinned;
+	}
 
-			// Create object store if it doesn't exist
-			if !db.object_store_names().contains(STORE_NAME) {
-				let params = web_sys::IdbObjectStoreParameters::new();
-				params.set_auto_increment(true);
-				params.set_key_path(&JsValue::from_str("_idb_key"));
+	/// Sets which events are dropped, from memory and IndexedDB, when appends take the
+	/// store over `max_items`, instead of the oldest.
+	///
+	/// The policy is shown every queued event each time the store evicts.
+	pub fn set_eviction_policy<P: EvictionPolicy + 'static>(&mut self, policy: P) {
+		self.eviction = Some(Box::new(policy));
+	}
 
-				db.create_object_store_with_optional_parameters(STORE_NAME, &params)
-					.expect("Failed to create object store");
-			}
-		});
-		open_request.set_onupgradeneeded(Some(on_upgrade.as_ref().unchecked_ref()));
-		on_upgrade.forget(); // Prevent closure from being dropped
+	/// Sets a listener notified with the events the store drops, when appends take it
+	/// over `max_items` or down-sampling drops them, passing it at most `max_events` of them per drop.
+	pub fn set_eviction_listener<L: EvictionListener + 'static>(
+		&mut self,
+		listener: L,
+		max_events: usize,
+	) {
+		self.eviction_listener = Some(EvictionNotifier::new(Box::new(listener), max_events));
+	}
 
-		// Wait for success/error
-		let db = Self::await_request::<IdbDatabase>(&open_request).await?;
+	/// Sets the JSON format used for fetched batch envelopes and events persisted to IndexedDB.
+	///
+	/// `Canonical` sorts object keys, which also fixes the property order of persisted records.
+	pub fn set_json_format(&mut self, format: JsonFormat) {
+		self.json_format = format;
+	}
 
-		Ok(db)
+	/// Retains the last `batches` removed batches for
+	/// [`recently_removed`](DataStoreExt::recently_removed), so developers can inspect what
+	/// was uploaded. 0, the default, retains none.
+	///
+	/// Meant for debug builds: retained events are kept in memory, not IndexedDB.
+	pub fn set_retain_removed(&mut self, batches: usize) {
+		self.removed.set_capacity(batches);
 	}
 
-	/// Loads all existing events from IndexedDB into memory
-	async fn hydrate(&mut self) -> Result<()> {
-		let db = match &self.db {
-			Some(db) => db.clone(),
-			None => return Ok(()), // No db, nothing to hydrate
-		};
+	/// Sets the provider of the `context` object added to each fetched batch envelope,
+	/// see [`ContextProvider`].
+	pub fn set_context_provider<P: ContextProvider + 'static>(&mut self, provider: P) {
+		self.context = Some(Box::new(provider));
+	}
 
-		let transaction = db
-			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readonly)
-			.map_err(|e| Error::other(format!("Transaction error: {:?}", e)))?;
+	/// Sets the provider of the offset from the server's clock that fetched batches'
+	/// `sentAt` and event timestamps are corrected by, see [`ClockOffsetProvider`].
+	pub fn set_clock_offset_provider<P: ClockOffsetProvider + 'static>(&mut self, provider: P) {
+		self.clock_offset = Some(Box::new(provider));
+	}
 
-		let store = transaction
-			.object_store(STORE_NAME)
-			.map_err(|e| Error::other(format!("Object store error: {:?}", e)))?;
-
-		let request = store
-			.get_all()
-			.map_err(|e| Error::other(format!("GetAll error: {:?}", e)))?;
-
-		let result = Self::await_request::<JsValue>(&request).await?;
-
-		// Parse results into our items queue
-		if let Ok(array) = result.dyn_into::<js_sys::Array>() {
-			for item in array.iter() {
-				if let Ok(obj) = js_sys::JSON::stringify(&item) {
-					if let Ok(s) = obj.as_string().ok_or(()) {
-						if let Ok(mut value) = serde_json::from_str::<Value>(&s) {
-							// Extract the idb_key and remove it from the value
-							let idb_key = value
-								.get("_idb_key")
-								.and_then(|k| k.as_u64())
-								.map(|k| k as u32);
-
-							if let Some(obj) = value.as_object_mut() {
-								obj.remove("_idb_key");
-							}
+	/// Sets fields to strip from events as they are appended, so they are never stored.
+	///
+	/// Only affects events appended afterwards.
+	pub fn set_field_filter(&mut self, filter: FieldFilter) {
+		self.field_filter = filter;
+	}
 
-							self.items.push_back(StoredEvent { idb_key, value });
-						}
-					}
-				}
+	/// Sets the event schema version stamped on appended events, and migrates events
+	/// hydrated from IndexedDB that were stored with an older version, writing the
+	/// upgraded events back.
+	pub fn set_migrations(&mut self, migrations: SchemaMigrations) {
+		let mut changed = Vec::new();
+		for item in self.items.iter_mut() {
+			if SchemaMigrations::event_version(&item.value) < migrations.version()
+				&& item.value.update(|value| migrations.migrate(value))
+			{
+				changed.push(item.clone());
 			}
 		}
-
-		// Update temp_key_counter to be higher than any existing key
-		if let Some(max_key) = self.items.iter().filter_map(|e| e.idb_key).max() {
-			self.temp_key_counter = max_key + 1;
+		for event in changed {
+			self.replace_in_idb(event);
 		}
-
-		Ok(())
+		self.migrations = Some(migrations);
 	}
 
-	/// Fire-and-forget write to IndexedDB
-	fn persist_event(&self, event: StoredEvent) {
-		let Some(db) = &self.db else { return };
-		let db = db.clone();
-		let write_key = self.config.write_key.clone();
-
-		spawn_local(async move {
-			if let Err(e) = Self::write_to_idb(&db, &write_key, &event).await {
-				// Log but don't fail - we still have it in memory
-				web_sys::console::warn_1(&format!("IndexedDB write failed: {:?}", e).into());
+	/// Sets the field (e.g. `receivedAt`) stamped with the time an event is queued, on
+	/// events queued without a `timestamp` field, so they can still be ordered
+	/// downstream. `None` stops stamping.
+	///
+	/// Events hydrated from IndexedDB without either field are stamped with the time
+	/// they're loaded, including those already loaded, and written back.
+	pub fn set_received_at_field(&mut self, field: Option<&str>) {
+		self.received_at = field.map(str::to_string);
+		if field.is_none() {
+			return;
+		}
+		let mut changed = Vec::new();
+		for item in self.items.iter_mut() {
+			let (field, at) = (self.received_at.as_deref(), item.enqueued_at);
+			if item.value.update(|value| received::stamp(value, field, at)) {
+				changed.push(item.clone());
 			}
-		});
+		}
+		for event in changed {
+			self.replace_in_idb(event);
+		}
 	}
 
-	/// Actual IndexedDB write operation
-	async fn write_to_idb(db: &IdbDatabase, _write_key: &str, event: &StoredEvent) -> Result<()> {
-		let transaction = db
-			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readwrite)
-			.map_err(|e| Error::other(format!("Transaction error: {:?}", e)))?;
-
-		let store = transaction
-			.object_store(STORE_NAME)
-			.map_err(|e| Error::other(format!("Object store error: {:?}", e)))?;
-
-		// Convert to JsValue
-		let json_str = serde_json::to_string(&event.value)
-			.map_err(|e| Error::other(format!("JSON error: {:?}", e)))?;
+	/// Sets adaptive down-sampling, dropping appended events of low-priority types while
+	/// the store stays over a threshold; see [`DownSampling`]. `None` turns it off.
+	pub fn set_down_sampling(&mut self, sampling: Option<DownSampling>) {
+		self.sampler = sampling.map(Sampler::new);
+	}
 
-		let js_value = js_sys::JSON::parse(&json_str)
-			.map_err(|e| Error::other(format!("JS JSON parse error: {:?}", e)))?;
+	/// Stops writing new events and attachments to IndexedDB once the origin's storage
+	/// usage would go past `threshold` (a fraction of its quota, e.g. `0.9`), so a full
+	/// quota doesn't fail every write with `QuotaExceededError` or leave them half done.
+	/// `None` turns it off.
+	///
+	/// Usage and quota come from `navigator.storage.estimate()`, requested in the
+	/// background every 30 seconds or so, and after each megabyte written. Events
+	/// appended while over the threshold are queued in memory only, and are lost if the
+	/// page closes before they're fetched; see [`is_quota_limited()`](Self::is_quota_limited).
+	/// Events already written stay persisted.
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// store.set_quota_threshold(Some(0.9));
+	/// ```
+	pub fn set_quota_threshold(&mut self, threshold: Option<f64>) {
+		self.quota = threshold.map(|threshold| Rc::new(RefCell::new(QuotaGate::new(threshold))));
+	}
 
-		let request = store
-			.add(&js_value)
-			.map_err(|e| Error::other(format!("Add error: {:?}", e)))?;
+	/// Returns `true` if the last write was kept out of IndexedDB by the
+	/// [quota threshold](Self::set_quota_threshold), so new events are memory-only.
+	pub fn is_quota_limited(&self) -> bool {
+		self.quota
+			.as_ref()
+			.is_some_and(|gate| gate.borrow().is_limited())
+	}
 
-		Self::await_request::<JsValue>(&request).await?;
+	/// Calls `observer` with each background IndexedDB operation that fails (writes,
+	/// updates and deletes, whether a request failed or the transaction was aborted), so
+	/// SDKs can measure how reliable persistence is in the field. Failures are logged as
+	/// warnings either way.
+	///
+	/// # Example
+	///
+	/// ```ignore
+	/// store.set_idb_error_observer(|error: &IdbError| {
+	///     metrics.increment("idb_error", &[
+	///         ("operation", format!("{:?}", error.operation)),
+	///         ("kind", format!("{:?}", error.failure.kind)),
+	///     ]);
+	/// });
+	/// ```
+	pub fn set_idb_error_observer<F: Fn(&IdbError) + 'static>(&mut self, observer: F) {
+		*self.idb_errors.observer.borrow_mut() = Some(Box::new(observer));
+	}
 
-		Ok(())
+	/// Returns how many background IndexedDB operations have failed since the store was
+	/// created.
+	pub fn idb_error_count(&self) -> u64 {
+		self.idb_errors.count.get()
 	}
 
-	/// Fire-and-forget delete from IndexedDB
-	fn remove_from_idb(&self, idb_key: u32) {
-		let Some(db) = &self.db else { return };
-		let db = db.clone();
+	/// Whether `bytes` more may be written to IndexedDB under the quota threshold,
+	/// refreshing the storage estimate in the background when it's stale
+	fn admit_write(&self, bytes: usize) -> bool {
+		let Some(gate) = &self.quota else {
+			return true;
+		};
+		let (was_limited, admitted) = {
+			let mut state = gate.borrow_mut();
+			if state.wants_estimate(Utc::now()) {
+				Self::spawn_estimate(gate.clone());
+			}
+			(state.is_limited(), state.admit(bytes as u64))
+		};
+		if was_limited && admitted {
+			logging::log_info!(
+				"Storage usage is under the quota threshold again, persisting new events"
+			);
+		} else if !was_limited && !admitted {
+			logging::log_warn!(
+				"Storage usage is near the quota, keeping new events in memory only"
+			);
+		}
+		admitted
+	}
 
+	/// Requests a storage estimate in the background, handing it to `gate`
+	fn spawn_estimate(gate: Rc<RefCell<QuotaGate>>) {
 		spawn_local(async move {
-			if let Err(e) = Self::delete_from_idb(&db, idb_key).await {
-				web_sys::console::warn_1(&format!("IndexedDB delete failed: {:?}", e).into());
+			let estimate = async {
+				let window = web_sys::window().ok_or_else(|| Error::other("No window"))?;
+				let promise = window
+					.navigator()
+					.storage()
+					.estimate()
+					.map_err(js_error("Storage estimate error"))?;
+				let estimate = wasm_bindgen_futures::JsFuture::from(promise)
+					.await
+					.map_err(js_error("Storage estimate error"))?;
+				let field = |name: &str| {
+					js_sys::Reflect::get(&estimate, &JsValue::from_str(name))
+						.ok()
+						.and_then(|value| value.as_f64())
+						.ok_or_else(|| Error::other(format!("Storage estimate has no {}", name)))
+				};
+				Ok::<_, Error>((field("usage")?, field("quota")?))
+			}
+			.await;
+
+			match estimate {
+				Ok((usage, quota)) => {
+					gate.borrow_mut()
+						.update(usage as u64, quota as u64, Utc::now())
+				}
+				Err(e) => {
+					logging::log_warn!("Couldn't estimate storage usage: {:?}", e);
+					gate.borrow_mut().estimate_failed();
+				}
 			}
 		});
 	}
 
-	/// Actual IndexedDB delete operation
-	async fn delete_from_idb(db: &IdbDatabase, idb_key: u32) -> Result<()> {
-		let transaction = db
-			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readwrite)
-			.map_err(|e| Error::other(format!("Transaction error: {:?}", e)))?;
+	/// localStorage key holding the delivered batch ids for this database
+	fn delivered_storage_key(&self) -> String {
+		format!("transientdb:{}:delivered", self.config.database_name)
+	}
 
-		let store = transaction
-			.object_store(STORE_NAME)
-			.map_err(|e| Error::other(format!("Object store error: {:?}", e)))?;
+	fn local_storage() -> Option<web_sys::Storage> {
+		web_sys::window()?.local_storage().ok().flatten()
+	}
 
-		let request = store
-			.delete(&JsValue::from(idb_key))
-			.map_err(|e| Error::other(format!("Delete error: {:?}", e)))?;
+	/// Reads delivered batch ids from localStorage, if available
+	fn load_delivered(&self) -> DeliveredBatches {
+		Self::local_storage()
+			.and_then(|storage| {
+				storage
+					.get_item(&self.delivered_storage_key())
+					.ok()
+					.flatten()
+			})
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|ids| DeliveredBatches::from_json(&ids, DeliveredBatches::DEFAULT_CAPACITY))
+			.unwrap_or_else(|| DeliveredBatches::new(DeliveredBatches::DEFAULT_CAPACITY))
+	}
 
-		Self::await_request::<JsValue>(&request).await?;
+	/// Mirrors delivered batch ids to localStorage. Failure leaves them in memory only.
+	fn save_delivered(&self) {
+		let Some(storage) = Self::local_storage() else {
+			return;
+		};
+		let content = self.delivered.to_json().to_string();
+		if let Err(e) = storage.set_item(&self.delivered_storage_key(), &content) {
+			logging::log_warn!("Failed to persist delivered batches: {:?}", e);
+		}
+	}
 
-		Ok(())
+	/// localStorage key holding the in-flight batch ids for this database
+	fn in_flight_storage_key(&self) -> String {
+		format!("transientdb:{}:in-flight", self.config.database_name)
 	}
 
-	/// Helper to await an IdbRequest and extract the result
-	async fn await_request<T: JsCast>(request: &IdbRequest) -> Result<T> {
+	/// Mirrors the in-flight batches to localStorage, by the IndexedDB keys of their
+	/// events. Failure leaves them in memory only.
+	fn save_in_flight(&self) {
+		let Some(storage) = Self::local_storage() else {
+			return;
+		};
+		let batches: Vec<Value> = self
+			.in_flight
+			.iter()
+			.filter_map(|(batch_id, seqs)| {
+				let keys = seqs
+					.iter()
+					.map(|seq| {
+						self.items
+							.iter()
+							.find(|item| item.seq == *seq)
+							.and_then(|item| item.idb_key)
+					})
+					.collect::<Option<Vec<u32>>>()?;
+				Some(json!({"batchId": batch_id, "keys": keys}))
+			})
+			.collect();
+		let key = self.in_flight_storage_key();
+		let saved = if batches.is_empty() {
+			storage.remove_item(&key)
+		} else {
+			storage.set_item(&key, &Value::from(batches).to_string())
+		};
+		if let Err(e) = saved {
+			logging::log_warn!("Failed to persist in-flight batches: {:?}", e);
+		}
+	}
+
+	/// Restores the in-flight batches of an earlier session once its events are
+	/// hydrated. Batches with an event no longer stored are dropped: their keys may be
+	/// reused by events appended since.
+	fn restore_in_flight(&mut self) {
+		let saved = Self::local_storage()
+			.and_then(|storage| {
+				storage
+					.get_item(&self.in_flight_storage_key())
+					.ok()
+					.flatten()
+			})
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok());
+		let Some(Value::Array(batches)) = saved else {
+			return;
+		};
+		for batch in &batches {
+			let Some(batch_id) = batch.get("batchId").and_then(Value::as_str) else {
+				continue;
+			};
+			let seqs = batch
+				.get("keys")
+				.and_then(Value::as_array)
+				.into_iter()
+				.flatten()
+				.map(|key| {
+					let key = key.as_u64()?;
+					self.items
+						.iter()
+						.find(|item| item.idb_key.map(u64::from) == Some(key))
+						.map(|item| item.seq)
+				})
+				.collect::<Option<Vec<u64>>>();
+			if let Some(seqs) = seqs.filter(|seqs| !seqs.is_empty()) {
+				self.in_flight.insert(batch_id.to_string(), seqs);
+			}
+		}
+		self.save_in_flight();
+	}
+
+	/// localStorage key holding the drop log for this database
+	fn drops_storage_key(&self) -> String {
+		format!("transientdb:{}:drops", self.config.database_name)
+	}
+
+	/// Reads the drop log from localStorage, if available
+	fn load_drops(&self) -> DropLog {
+		Self::local_storage()
+			.and_then(|storage| storage.get_item(&self.drops_storage_key()).ok().flatten())
+			.and_then(|content| serde_json::from_str::<Value>(&content).ok())
+			.map(|records| DropLog::from_json(&records))
+			.unwrap_or_default()
+	}
+
+	/// Mirrors the drop log to localStorage. Failure leaves it in memory only.
+	fn save_drops(&self) {
+		let Some(storage) = Self::local_storage() else {
+			return;
+		};
+		let key = self.drops_storage_key();
+		let saved = if self.drops.is_empty() {
+			storage.remove_item(&key)
+		} else {
+			storage.set_item(&key, &self.drops.to_json().to_string())
+		};
+		if let Err(e) = saved {
+			logging::log_warn!("Failed to persist drop log: {:?}", e);
+		}
+	}
+
+	/// localStorage key prefix of the emergency snapshot for this database
+	fn snapshot_storage_key(&self) -> String {
+		format!("transientdb:{}:snapshot", self.config.database_name)
+	}
+
+	/// Saves the queued events to localStorage, so a store that can't use IndexedDB
+	/// doesn't lose them on a page refresh. Returns the number of events saved.
+	///
+	/// Call it when the page is hidden, e.g. on `pagehide`, while the store is
+	/// [`PersistenceState::MemoryOnly`]. The next WebStore opened with the same database
+	/// name queues the saved events again and deletes the snapshot, writing them to
+	/// IndexedDB if it can. A persisted store saves nothing, since IndexedDB keeps its
+	/// events, and deletes any snapshot left.
+	///
+	/// localStorage holds only about 5 MB per origin, so the snapshot is written in
+	/// chunks, newest events first. If the quota runs out, the oldest events are left
+	/// out of the snapshot (they stay queued in this store), rather than the write
+	/// failing or the whole snapshot being lost.
+	///
+	/// # Errors
+	/// Fails if localStorage isn't available.
+	pub fn save_snapshot(&mut self) -> Result<usize> {
+		self.adopt_loaded();
+		let mut storage = Self::local_storage()
+			.map(LocalStorage)
+			.ok_or_else(|| Error::other("localStorage is unavailable"))?;
+		let prefix = self.snapshot_storage_key();
+		if self.is_persisted() {
+			snapshot::clear(&mut storage, &prefix);
+			return Ok(0);
+		}
+		let saved = snapshot::save(
+			&mut storage,
+			&prefix,
+			self.items.iter().map(|item| &*item.value),
+		);
+		if saved < self.items.len() {
+			logging::log_warn!(
+				"localStorage quota reached, snapshot holds {} of {} events",
+				saved,
+				self.items.len()
+			);
+		}
+		Ok(saved)
+	}
+
+	/// Queues the events of an emergency snapshot, after any already queued, and
+	/// deletes it
+	fn restore_snapshot(&mut self) {
+		let Some(mut storage) = Self::local_storage().map(LocalStorage) else {
+			return;
+		};
+		let prefix = self.snapshot_storage_key();
+		let events = snapshot::load(&storage, &prefix);
+		snapshot::clear(&mut storage, &prefix);
+		if events.is_empty() {
+			return;
+		}
+		logging::log_info!("Restoring {} events from localStorage", events.len());
+		let mut restored = Vec::with_capacity(events.len());
+		for value in events {
+			let event = self.stored_event(Some(self.temp_key_counter), value);
+			self.temp_key_counter += 1;
+			self.items.push_back(event.clone());
+			restored.push(event);
+		}
+		self.evict_to(self.config.max_items.max(1));
+		if self.db.is_some() {
+			let kept = restored.len().min(self.items.len());
+			self.persist_events(restored.split_off(restored.len() - kept));
+		}
+	}
+
+	/// Creates any object stores missing from the database being upgraded
+	fn create_object_stores(request: &IdbRequest) -> std::result::Result<(), JsValue> {
+		let db: IdbDatabase = request.result()?.unchecked_into();
+
+		// Create object store if it doesn't exist
+		if !db.object_store_names().contains(STORE_NAME) {
+			let params = web_sys::IdbObjectStoreParameters::new();
+			params.set_auto_increment(true);
+			params.set_key_path(&JsValue::from_str("_idb_key"));
+
+			db.create_object_store_with_optional_parameters(STORE_NAME, &params)?;
+		}
+
+		// Added in version 2
+		if !db.object_store_names().contains(ATTACHMENTS_STORE_NAME) {
+			let params = web_sys::IdbObjectStoreParameters::new();
+			params.set_key_path(&JsValue::from_str("id"));
+
+			db.create_object_store_with_optional_parameters(ATTACHMENTS_STORE_NAME, &params)?;
+		}
+		Ok(())
+	}
+
+	/// Opens or creates the IndexedDB database
+	async fn open_database(database_name: &str) -> Result<IdbDatabase> {
+		let window = web_sys::window().ok_or_else(|| {
+			PersistenceFailure::new(PersistenceFailureKind::Unavailable, "No window object")
+		})?;
+
+		let idb_factory = window
+			.indexed_db()
+			.map_err(|e| PersistenceFailure::from_js("IndexedDB error", &e))?
+			.ok_or_else(|| {
+				PersistenceFailure::new(
+					PersistenceFailureKind::Unavailable,
+					"IndexedDB not available",
+				)
+			})?;
+
+		// Create open request
+		let open_request = idb_factory
+			.open_with_f64(database_name, DB_VERSION as f64)
+			.map_err(|e| PersistenceFailure::from_js("Failed to open DB", &e))?;
+
+		// Set up upgrade handler for first-time creation
+		let on_upgrade = Closure::once(move |event: web_sys::IdbVersionChangeEvent| {
+			let Some(target) = event.target() else { return };
+			let request: IdbRequest = target.unchecked_into();
+			if let Err(e) = Self::create_object_stores(&request) {
+				logging::log_warn!("IndexedDB upgrade failed: {:?}", e);
+				// Aborting the upgrade fails the open request, so the store falls back to memory
+				if let Some(transaction) = request.transaction() {
+					let _ = transaction.abort();
+				}
+			}
+		});
+		open_request.set_onupgradeneeded(Some(on_upgrade.as_ref().unchecked_ref()));
+		on_upgrade.forget(); // Prevent closure from being dropped
+
+		// Wait for success/error
+		match Self::await_request::<IdbDatabase>(&open_request).await {
+			Ok(db) => Ok(db),
+			// The database has a higher version than ours: a later release wrote it
+			Err(_) if Self::is_version_error(&open_request) => {
+				Err(TransientError::IncompatibleFormat {
+					found: Self::stored_version(database_name, &idb_factory).await?,
+					supported: DB_VERSION as u64,
+				}
+				.into())
+			}
+			Err(e) => match open_request.error() {
+				Ok(Some(exception)) => {
+					Err(PersistenceFailure::from_js("Failed to open DB", &exception).into())
+				}
+				_ => Err(e),
+			},
+		}
+	}
+
+	fn is_version_error(request: &IdbRequest) -> bool {
+		matches!(request.error(), Ok(Some(error)) if error.name() == "VersionError")
+	}
+
+	/// Reads the version of an existing database without upgrading it
+	async fn stored_version(database_name: &str, idb_factory: &IdbFactory) -> Result<u64> {
+		let request = idb_factory
+			.open(database_name)
+			.map_err(js_error("Failed to open DB"))?;
+		let db = Self::await_request::<IdbDatabase>(&request).await?;
+		let version = db.version() as u64;
+		db.close();
+		Ok(version)
+	}
+
+	/// Reads the persisted records and starts converting them into events in slices of
+	/// `chunk_size`, on a task yielding to the browser between slices.
+	///
+	/// Keys are read up front, so events appended meanwhile get keys above them.
+	async fn hydrate(&mut self, chunk_size: usize) -> Result<()> {
+		let db = match &self.db {
+			Some(db) => db.clone(),
+			None => return Ok(()), // No db, nothing to hydrate
+		};
+
+		let records = Self::load_records(&db).await?;
+
+		// Update temp_key_counter to be higher than any existing key
+		let key = JsValue::from_str("_idb_key");
+		let max_key = records
+			.iter()
+			.filter_map(|record| js_sys::Reflect::get(&record, &key).ok()?.as_f64())
+			.map(|key| key as u32)
+			.max();
+		if let Some(max_key) = max_key {
+			self.temp_key_counter = max_key + 1;
+		}
+
+		let mut hydration = Hydration::finished();
+		hydration.boundary = self.temp_key_counter;
+		hydration.complete = false;
+		self.hydration = Rc::new(RefCell::new(hydration));
+		let slot = Rc::downgrade(&self.hydration);
+		let chunk_size = chunk_size.max(1);
+
+		spawn_local(async move {
+			let total = records.length();
+			let mut start = 0;
+			while start < total {
+				let Some(hydration) = slot.upgrade() else {
+					return;
+				};
+				let end = total.min(start.saturating_add(chunk_size as u32));
+				if hydration.borrow().cancelled {
+					// The store was reset before these were loaded
+					for record in records.slice(start, total).iter() {
+						if let Some((Some(idb_key), _)) = Self::parse_record(&record) {
+							if let Err(e) = Self::delete_from_idb(&db, idb_key).await {
+								logging::log_warn!("IndexedDB delete failed: {:?}", e);
+							}
+						}
+					}
+					hydration.borrow_mut().finish();
+					return;
+				}
+
+				let chunk = records.slice(start, end);
+				let events = chunk
+					.iter()
+					.filter_map(|record| Self::parse_record(&record));
+				hydration.borrow_mut().loaded.extend(events);
+				start = end;
+
+				// Let the browser run other tasks before the next slice
+				drop(hydration);
+				if start < total {
+					Self::sleep(0).await;
+				}
+			}
+
+			let attachments = Self::load_attachments(&db).await.unwrap_or_else(|e| {
+				logging::log_warn!("Failed to load attachments from IndexedDB: {:?}", e);
+				HashMap::new()
+			});
+			if let Some(hydration) = slot.upgrade() {
+				let mut hydration = hydration.borrow_mut();
+				hydration.attachments = attachments;
+				hydration.finish();
+			}
+		});
+		Ok(())
+	}
+
+	/// Returns a future that resolves once every persisted event has been loaded.
+	///
+	/// Stores from [`new()`](Self::new) are hydrated already. For stores from
+	/// [`new_hydrating()`](Self::new_hydrating), the loaded events are merged into the
+	/// store on its next operation. The future doesn't borrow the store, so it can be
+	/// awaited while the store is in use elsewhere.
+	pub fn hydration_complete(&self) -> impl Future<Output = ()> {
+		HydrationComplete(self.hydration.clone())
+	}
+
+	/// Merges what background tasks loaded since the last operation
+	fn adopt_loaded(&mut self) {
+		self.adopt_hydrated();
+		self.adopt_reopened();
+	}
+
+	/// Merges events converted by the hydration task, ahead of any appended meanwhile
+	fn adopt_hydrated(&mut self) {
+		let (loaded, attachments) = {
+			let mut hydration = self.hydration.borrow_mut();
+			let attachments = (hydration.complete && !hydration.cancelled)
+				.then(|| std::mem::take(&mut hydration.attachments));
+			(std::mem::take(&mut hydration.loaded), attachments)
+		};
+		if loaded.is_empty() && attachments.as_ref().filter(|a| !a.is_empty()).is_none() {
+			return;
+		}
+
+		let boundary = self.hydration.borrow().boundary;
+		let hydrated = self
+			.items
+			.iter()
+			.take_while(|item| item.idb_key.is_some_and(|key| key < boundary))
+			.count();
+		let appended = self.items.split_off(hydrated);
+		let mut changed = Vec::new();
+		for (idb_key, value) in loaded {
+			let mut event = self.stored_event(idb_key, value);
+			let mut updated = false;
+			if let Some(migrations) = &self.migrations {
+				updated = SchemaMigrations::event_version(&event.value) < migrations.version()
+					&& event.value.update(|value| migrations.migrate(value));
+			}
+			let (field, at) = (self.received_at.as_deref(), event.enqueued_at);
+			updated |= event
+				.value
+				.update(|value| received::stamp(value, field, at));
+			if updated {
+				changed.push(event.clone());
+			}
+			self.items.push_back(event);
+		}
+		self.items.extend(appended);
+		for event in changed {
+			self.replace_in_idb(event);
+		}
+
+		let complete = attachments.is_some();
+		if let Some(attachments) = attachments {
+			for (id, attachment) in attachments {
+				self.attachments.entry(id).or_insert(attachment);
+			}
+			self.prune_attachments();
+		}
+		if complete {
+			self.restore_in_flight();
+		}
+		self.evict_to(self.config.max_items.saturating_sub(self.reserved).max(1));
+	}
+
+	/// Reads all persisted records
+	async fn load_records(db: &IdbDatabase) -> Result<js_sys::Array> {
+		let transaction = db
+			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readonly)
+			.map_err(idb_error("Transaction error"))?;
+
+		let store = transaction
+			.object_store(STORE_NAME)
+			.map_err(js_error("Object store error"))?;
+
+		let request = store.get_all().map_err(js_error("GetAll error"))?;
+
+		Self::await_request::<js_sys::Array>(&request).await
+	}
+
+	/// Reads all persisted events with their IndexedDB keys
+	async fn load_events(db: &IdbDatabase) -> Result<Vec<(Option<u32>, Value)>> {
+		let records = Self::load_records(db).await?;
+		Ok(records
+			.iter()
+			.filter_map(|record| Self::parse_record(&record))
+			.collect())
+	}
+
+	/// Converts a persisted record into an event and its IndexedDB key
+	fn parse_record(record: &JsValue) -> Option<(Option<u32>, Value)> {
+		let json = js_sys::JSON::stringify(record).ok()?.as_string()?;
+		let mut value = serde_json::from_str::<Value>(&json).ok()?;
+
+		// Extract the idb_key and remove it from the value
+		let idb_key = value
+			.get("_idb_key")
+			.and_then(|k| k.as_u64())
+			.map(|k| k as u32);
+
+		if let Some(obj) = value.as_object_mut() {
+			obj.remove("_idb_key");
+		}
+		Some((idb_key, value))
+	}
+
+	/// Reads all persisted attachments by id
+	async fn load_attachments(db: &IdbDatabase) -> Result<HashMap<String, Attachment>> {
+		let transaction = db
+			.transaction_with_str_and_mode(
+				ATTACHMENTS_STORE_NAME,
+				web_sys::IdbTransactionMode::Readonly,
+			)
+			.map_err(idb_error("Transaction error"))?;
+
+		let store = transaction
+			.object_store(ATTACHMENTS_STORE_NAME)
+			.map_err(js_error("Object store error"))?;
+
+		let request = store.get_all().map_err(js_error("GetAll error"))?;
+
+		let result = Self::await_request::<js_sys::Array>(&request).await?;
+
+		let field = |record: &JsValue, name: &str| {
+			js_sys::Reflect::get(record, &JsValue::from_str(name)).ok()
+		};
+		let mut attachments = HashMap::new();
+		for record in result.iter() {
+			let (Some(id), Some(name), Some(content_type), Some(data)) = (
+				field(&record, "id").and_then(|v| v.as_string()),
+				field(&record, "name").and_then(|v| v.as_string()),
+				field(&record, "contentType").and_then(|v| v.as_string()),
+				field(&record, "data").and_then(|v| v.dyn_into::<js_sys::Uint8Array>().ok()),
+			) else {
+				continue;
+			};
+			attachments.insert(
+				id,
+				Attachment {
+					name,
+					content_type,
+					data: data.to_vec(),
+				},
+			);
+		}
+		Ok(attachments)
+	}
+
+	/// Retries opening IndexedDB in the background, a few times with growing delays.
+	fn schedule_reopen(&self) {
+		self.spawn_reopen(&REOPEN_DELAYS_MS);
+	}
+
+	/// Tries opening IndexedDB in the background after each of `delays`, until it opens
+	/// or fails in a way that isn't transient.
+	///
+	/// A successful open is left in `reopened` for [`adopt_reopened`](Self::adopt_reopened),
+	/// since the task can't borrow the store, and the last failure in `reopen_failure`
+	/// if the task gives up.
+	fn spawn_reopen(&self, delays: &'static [i32]) {
+		let database_name = self.config.database_name.clone();
+		let slot = Rc::downgrade(&self.reopened);
+		let failure_slot = Rc::downgrade(&self.reopen_failure);
+
+		spawn_local(async move {
+			let mut last_error = None;
+			for (attempt, &delay) in delays.iter().enumerate() {
+				if delay > 0 {
+					Self::sleep(delay).await;
+				}
+				// The store is gone
+				if slot.strong_count() == 0 {
+					return;
+				}
+
+				match Self::reopen(&database_name).await {
+					Ok(reopened) => {
+						match slot.upgrade() {
+							Some(slot) => *slot.borrow_mut() = Some(reopened),
+							None => reopened.db.close(),
+						}
+						return;
+					}
+					Err(e) => {
+						let transient = PersistenceFailure::from_io(&e).is_transient();
+						logging::log_warn!(
+							"IndexedDB open attempt {} of {} failed: {}",
+							attempt + 1,
+							delays.len(),
+							e
+						);
+						last_error = Some(e);
+						if !transient {
+							break;
+						}
+					}
+				}
+			}
+			if let (Some(slot), Some(e)) = (failure_slot.upgrade(), last_error) {
+				*slot.borrow_mut() = Some(e);
+			}
+		});
+	}
+
+	/// Opens the database and loads what it holds, for a memory-only store to switch to
+	async fn reopen(database_name: &str) -> Result<Reopened> {
+		let db = Self::open_database(database_name).await?;
+		let events = match Self::load_events(&db).await {
+			Ok(events) => events,
+			Err(e) => {
+				// Writing the backlog without knowing the keys in use could collide
+				db.close();
+				return Err(e);
+			}
+		};
+		// Attachments without their events would be pruned anyway
+		let attachments = Self::load_attachments(&db).await.unwrap_or_else(|e| {
+			logging::log_warn!("Failed to load attachments from IndexedDB: {:?}", e);
+			HashMap::new()
+		});
+		Ok(Reopened {
+			db,
+			events,
+			attachments,
+		})
+	}
+
+	/// Resolves after `ms` milliseconds
+	async fn sleep(ms: i32) {
+		let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+			if let Some(window) = web_sys::window() {
+				let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms);
+			}
+		});
+		let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+	}
+
+	/// Switches to a database opened by a background retry, if there is one.
+	///
+	/// Events from an earlier session go ahead of the memory-only backlog, which is
+	/// given fresh keys and written to IndexedDB with its attachments.
+	fn adopt_reopened(&mut self) {
+		if let Some(e) = self.reopen_failure.borrow_mut().take() {
+			if self.db.is_none() {
+				logging::log_warn!(
+					"IndexedDB unavailable ({}), staying memory-only. \
+                         Events will not persist across page refreshes.",
+					e
+				);
+				self.format_error = TransientError::from_io(&e).cloned();
+				self.persistence_failure = Some(PersistenceFailure::from_io(&e));
+			}
+		}
+		let Some(reopened) = self.reopened.borrow_mut().take() else {
+			return;
+		};
+		// Already switched by retry_persistence
+		if self.db.is_some() {
+			reopened.db.close();
+			return;
+		}
+
+		self.db = Some(Rc::new(reopened.db));
+		self.persistence_state = PersistenceState::Persisted;
+		self.persistence_failure = None;
+
+		let backlog: Vec<StoredEvent> = self.items.drain(..).collect();
+		for (idb_key, value) in reopened.events {
+			let event = self.stored_event(idb_key, value);
+			self.items.push_back(event);
+		}
+		self.temp_key_counter = self
+			.items
+			.iter()
+			.filter_map(|e| e.idb_key)
+			.max()
+			.map_or(0, |max_key| max_key + 1);
+		let backlog_len = backlog.len();
+		for mut event in backlog {
+			event.idb_key = Some(self.temp_key_counter);
+			self.temp_key_counter += 1;
+			self.items.push_back(event);
+		}
+		let buffered_attachments: Vec<String> = self.attachments.keys().cloned().collect();
+		self.attachments.extend(reopened.attachments);
+
+		// Upgrades events from the earlier session, as after hydrating
+		if let Some(migrations) = self.migrations.take() {
+			self.set_migrations(migrations);
+		}
+
+		self.evict_to(self.config.max_items.saturating_sub(self.reserved).max(1));
+		let backlog_start = self.items.len().saturating_sub(backlog_len);
+		let backlog: Vec<StoredEvent> = self.items.range(backlog_start..).cloned().collect();
+		logging::log_info!(
+			"IndexedDB opened on retry, writing {} buffered events",
+			backlog.len()
+		);
+		self.persist_events(backlog);
+		self.prune_attachments();
+		for id in buffered_attachments {
+			if let Some(attachment) = self.attachments.get(&id) {
+				self.persist_attachment(&id, attachment);
+			}
+		}
+	}
+
+	/// Wraps a value in a new [`StoredEvent`]
+	fn stored_event(&mut self, idb_key: Option<u32>, value: Value) -> StoredEvent {
+		let seq = self.next_seq;
+		self.next_seq += 1;
+		self.delayed |= delay::is_delayed(&value);
+		self.moving |= moves::is_held(&value);
+		self.replayed |= replay::is_replay(&value);
+		StoredEvent {
+			seq,
+			idb_key,
+			value: SizedValue::new(value),
+			enqueued_at: Utc::now(),
+		}
+	}
+
+	/// Filters an appended event and adds it to the in-memory queue, returning it for
+	/// persisting, or `None` if it must not be queued
+	fn push_event(&mut self, mut data: Value) -> Option<StoredEvent> {
+		// Re-adding an envelope that was already delivered would duplicate it server-side
+		if self.delivered.is_redelivery(&data) {
+			return None;
+		}
+		self.field_filter.apply(&mut data);
+		if let Some(migrations) = &self.migrations {
+			migrations.stamp(&mut data);
+		}
+		let now = Utc::now();
+		received::stamp(&mut data, self.received_at.as_deref(), now);
+		if let Some(sampler) = &mut self.sampler {
+			if !sampler.admit(&data, self.items.len(), now) {
+				self.drops.record_times(DropReason::Sampled, [now]);
+				self.save_drops();
+				if let Some(listener) = &self.eviction_listener {
+					listener.notify(DropReason::Sampled, [&data], 1);
+				}
+				return None;
+			}
+		}
+
+		let event = self.stored_event(Some(self.temp_key_counter), data);
+		self.temp_key_counter += 1;
+
+		// Add to memory (sync)
+		self.items.push_back(event.clone());
+		Some(event)
+	}
+
+	/// Drops events chosen by the eviction policy, or the oldest, until at most
+	/// `capacity` are left. Pinned events and events held by a move are never dropped,
+	/// so more may be left.
+	fn evict_to(&mut self, capacity: usize) {
+		if self.items.len() <= capacity {
+			return;
+		}
+		let excess = self.items.len() - capacity;
+		let unpinned = self
+			.items
+			.iter()
+			.enumerate()
+			.filter(|(_, item)| {
+				!eviction::is_pinned(&item.value) && (!self.moving || !moves::is_held(&item.value))
+			})
+			.map(|(position, _)| position);
+		let victims: Vec<usize> = match &self.eviction {
+			Some(policy) => {
+				let positions: Vec<usize> = unpinned.collect();
+				let candidates: Vec<EvictionCandidate> = positions
+					.iter()
+					.map(|&position| EvictionCandidate {
+						event: &self.items[position].value,
+						size: Self::get_item_size(&self.items[position]),
+						enqueued_at: self.items[position].enqueued_at,
+					})
+					.collect();
+				eviction::select_victims(policy.as_ref(), &candidates, excess)
+					.into_iter()
+					.map(|candidate| positions[candidate])
+					.collect()
+			}
+			None => unpinned.take(excess).collect(),
+		};
+		if victims.is_empty() {
+			return;
+		}
+		self.drops.record_times(
+			DropReason::Capacity,
+			victims
+				.iter()
+				.map(|&position| self.items[position].enqueued_at),
+		);
+		if let Some(listener) = &self.eviction_listener {
+			listener.notify(
+				DropReason::Capacity,
+				victims.iter().map(|&position| &*self.items[position].value),
+				victims.len(),
+			);
+		}
+		self.save_drops();
+		for position in victims.into_iter().rev() {
+			if let Some(key) = self.items.remove(position).and_then(|event| event.idb_key) {
+				self.remove_from_idb(key);
+			}
+		}
+		self.prune_attachments();
+	}
+
+	/// Fire-and-forget write to IndexedDB, in a single transaction
+	fn persist_events(&self, events: Vec<StoredEvent>) {
+		let Some(db) = &self.db else { return };
+		if events.is_empty() || !self.admit_write(events.iter().map(Self::get_item_size).sum()) {
+			return;
+		}
+		let db = db.clone();
+		let write_key = self.config.write_key.clone();
+		let json_format = self.json_format;
+		let errors = self.idb_errors.clone();
+
+		spawn_local(async move {
+			if let Err(e) = Self::write_to_idb(&db, &write_key, &events, json_format).await {
+				// Report but don't fail - we still have it in memory
+				errors.report(IdbOperation::Add, events.len(), &e);
+			}
+		});
+	}
+
+	/// Actual IndexedDB write operation
+	async fn write_to_idb(
+		db: &IdbDatabase,
+		_write_key: &str,
+		events: &[StoredEvent],
+		json_format: JsonFormat,
+	) -> Result<()> {
+		let transaction = db
+			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readwrite)
+			.map_err(idb_error("Transaction error"))?;
+
+		let committed = Self::await_transaction(&transaction);
+		let store = transaction
+			.object_store(STORE_NAME)
+			.map_err(js_error("Object store error"))?;
+
+		for event in events {
+			// Convert to JsValue
+			let json_str = json_format.serialize(&event.value);
+
+			let js_value =
+				js_sys::JSON::parse(&json_str).map_err(js_error("JS JSON parse error"))?;
+
+			// Write under the key the event has in memory, so removing it by that key works
+			if let Some(idb_key) = event.idb_key.filter(|_| js_value.is_object()) {
+				js_sys::Reflect::set(
+					&js_value,
+					&JsValue::from_str("_idb_key"),
+					&JsValue::from(idb_key),
+				)
+				.map_err(js_error("Key error"))?;
+			}
+
+			store.add(&js_value).map_err(js_error("Add error"))?;
+		}
+
+		committed.await
+	}
+
+	/// Fire-and-forget overwrite of an event already written to IndexedDB
+	fn replace_in_idb(&self, event: StoredEvent) {
+		let Some(db) = &self.db else { return };
+		let Some(idb_key) = event.idb_key else { return };
+		let db = db.clone();
+		let json_format = self.json_format;
+		let errors = self.idb_errors.clone();
+
+		spawn_local(async move {
+			if let Err(e) =
+				Self::put_to_idb(&db, idb_key, event.value.into_value(), json_format).await
+			{
+				errors.report(IdbOperation::Put, 1, &e);
+			}
+		});
+	}
+
+	/// Actual IndexedDB overwrite operation
+	async fn put_to_idb(
+		db: &IdbDatabase,
+		idb_key: u32,
+		mut value: Value,
+		json_format: JsonFormat,
+	) -> Result<()> {
+		// The key is stored in-line, so only objects were ever written
+		let Some(fields) = value.as_object_mut() else {
+			return Ok(());
+		};
+		fields.insert("_idb_key".to_string(), json!(idb_key));
+
+		let transaction = db
+			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readwrite)
+			.map_err(idb_error("Transaction error"))?;
+
+		let committed = Self::await_transaction(&transaction);
+		let store = transaction
+			.object_store(STORE_NAME)
+			.map_err(js_error("Object store error"))?;
+
+		let js_value = js_sys::JSON::parse(&json_format.serialize(&value))
+			.map_err(js_error("JS JSON parse error"))?;
+
+		store.put(&js_value).map_err(js_error("Put error"))?;
+
+		committed.await
+	}
+
+	/// Fire-and-forget write of an attachment to IndexedDB
+	fn persist_attachment(&self, id: &str, attachment: &Attachment) {
+		let Some(db) = &self.db else { return };
+		if !self.admit_write(attachment.data.len()) {
+			return;
+		}
+		let db = db.clone();
+
+		let record = js_sys::Object::new();
+		let data = js_sys::Uint8Array::from(attachment.data.as_slice());
+		for (key, value) in [
+			("id", JsValue::from_str(id)),
+			("name", JsValue::from_str(&attachment.name)),
+			("contentType", JsValue::from_str(&attachment.content_type)),
+			("data", data.into()),
+		] {
+			let _ = js_sys::Reflect::set(&record, &JsValue::from_str(key), &value);
+		}
+		let errors = self.idb_errors.clone();
+
+		spawn_local(async move {
+			let result = async {
+				let transaction = db
+					.transaction_with_str_and_mode(
+						ATTACHMENTS_STORE_NAME,
+						web_sys::IdbTransactionMode::Readwrite,
+					)
+					.map_err(idb_error("Transaction error"))?;
+				let committed = Self::await_transaction(&transaction);
+				let store = transaction
+					.object_store(ATTACHMENTS_STORE_NAME)
+					.map_err(js_error("Object store error"))?;
+				store.put(&record).map_err(js_error("Put error"))?;
+				committed.await
+			}
+			.await;
+
+			if let Err(e) = result {
+				errors.report(IdbOperation::PutAttachment, 1, &e);
+			}
+		});
+	}
+
+	/// Fire-and-forget delete of an attachment from IndexedDB
+	fn remove_attachment_from_idb(&self, id: String) {
+		let Some(db) = &self.db else { return };
+		let db = db.clone();
+		let errors = self.idb_errors.clone();
+
+		spawn_local(async move {
+			let result = async {
+				let transaction = db
+					.transaction_with_str_and_mode(
+						ATTACHMENTS_STORE_NAME,
+						web_sys::IdbTransactionMode::Readwrite,
+					)
+					.map_err(idb_error("Transaction error"))?;
+				let committed = Self::await_transaction(&transaction);
+				let store = transaction
+					.object_store(ATTACHMENTS_STORE_NAME)
+					.map_err(js_error("Object store error"))?;
+				store
+					.delete(&JsValue::from_str(&id))
+					.map_err(js_error("Delete error"))?;
+				committed.await
+			}
+			.await;
+
+			if let Err(e) = result {
+				errors.report(IdbOperation::DeleteAttachment, 1, &e);
+			}
+		});
+	}
+
+	/// Drops attachments whose events are no longer in the store, in memory and IndexedDB
+	fn prune_attachments(&mut self) {
+		if self.attachments.is_empty() {
+			return;
+		}
+		let referenced: HashSet<String> = self
+			.items
+			.iter()
+			.flat_map(|item| attachment::referenced_ids(&item.value))
+			.map(str::to_string)
+			.collect();
+		let orphaned: Vec<String> = self
+			.attachments
+			.keys()
+			.filter(|id| !referenced.contains(*id))
+			.cloned()
+			.collect();
+		for id in orphaned {
+			self.attachments.remove(&id);
+			self.remove_attachment_from_idb(id);
+		}
+	}
+
+	/// Builds handles for the attachments referenced by the given items
+	fn attachment_handles(&self, items: &[StoredEvent]) -> Option<Vec<AttachmentHandle>> {
+		if self.attachments.is_empty() {
+			return None;
+		}
+		let handles: Vec<AttachmentHandle> = items
+			.iter()
+			.flat_map(|item| attachment::referenced_ids(&item.value))
+			.filter_map(|id| {
+				self.attachments.get(id).map(|attachment| AttachmentHandle {
+					id: id.to_string(),
+					name: attachment.name.clone(),
+					content_type: attachment.content_type.clone(),
+					content: AttachmentContent::Bytes(attachment.data.clone()),
+				})
+			})
+			.collect();
+		(!handles.is_empty()).then_some(handles)
+	}
+
+	/// Fire-and-forget delete from IndexedDB
+	fn remove_from_idb(&self, idb_key: u32) {
+		let Some(db) = &self.db else { return };
+		let db = db.clone();
+		let errors = self.idb_errors.clone();
+
+		spawn_local(async move {
+			if let Err(e) = Self::delete_from_idb(&db, idb_key).await {
+				errors.report(IdbOperation::Delete, 1, &e);
+			}
+		});
+	}
+
+	/// Actual IndexedDB delete operation
+	async fn delete_from_idb(db: &IdbDatabase, idb_key: u32) -> Result<()> {
+		let transaction = db
+			.transaction_with_str_and_mode(STORE_NAME, web_sys::IdbTransactionMode::Readwrite)
+			.map_err(idb_error("Transaction error"))?;
+
+		let committed = Self::await_transaction(&transaction);
+		let store = transaction
+			.object_store(STORE_NAME)
+			.map_err(js_error("Object store error"))?;
+
+		store
+			.delete(&JsValue::from(idb_key))
+			.map_err(js_error("Delete error"))?;
+
+		committed.await
+	}
+
+	/// Waits for a transaction to commit. Failures carry the `DOMException` of the
+	/// request that failed, or of the abort, such as `QuotaExceededError`, which is only
+	/// reported once the transaction tries to commit.
+	///
+	/// Call it right after creating the transaction, so the handlers are in place before
+	/// it can complete.
+	fn await_transaction(transaction: &IdbTransaction) -> impl Future<Output = Result<()>> {
+		let (sender, receiver) = futures_channel::oneshot::channel();
+		let sender = Rc::new(RefCell::new(Some(sender)));
+
+		let complete_sender = sender.clone();
+		let oncomplete = Closure::once(move |_event: web_sys::Event| {
+			if let Some(sender) = complete_sender.borrow_mut().take() {
+				let _ = sender.send(Ok(()));
+			}
+		});
+
+		let aborted = transaction.clone();
+		let onabort = Closure::once(move |_event: web_sys::Event| {
+			if let Some(sender) = sender.borrow_mut().take() {
+				let failure = match aborted.error() {
+					Some(exception) => {
+						PersistenceFailure::from_js("IndexedDB transaction aborted", &exception)
+					}
+					None => PersistenceFailure::new(
+						PersistenceFailureKind::Unknown,
+						"IndexedDB transaction aborted",
+					),
+				};
+				let _ = sender.send(Err(failure.into()));
+			}
+		});
+
+		transaction.set_oncomplete(Some(oncomplete.as_ref().unchecked_ref()));
+		transaction.set_onabort(Some(onabort.as_ref().unchecked_ref()));
+
+		oncomplete.forget();
+		onabort.forget();
+
+		async move { receiver.await.map_err(|_| Error::other("Channel closed"))? }
+	}
+
+	/// Helper to await an IdbRequest and extract the result
+	async fn await_request<T: JsCast>(request: &IdbRequest) -> Result<T> {
 		let (sender, receiver) = futures_channel::oneshot::channel();
 		let sender = Rc::new(RefCell::new(Some(sender)));
 
@@ -405,137 +2144,748 @@ impl WebStore {
 
 		request
 			.result()
-			.map_err(|e| Error::other(format!("Result error: {:?}", e)))?
+			.map_err(js_error("Result error"))?
 			.dyn_into::<T>()
 			.map_err(|_| Error::other("Type cast failed"))
 	}
 
 	/// Creates a JSON batch object containing the provided items and metadata.
-	fn create_batch(&self, items: &[StoredEvent]) -> Value {
-		let values: Vec<&Value> = items.iter().map(|e| &e.value).collect();
+	fn create_batch(&self, items: &[StoredEvent], batch_id: &str, write_key: &str) -> Value {
+		let offset = skew::offset(self.clock_offset.as_deref());
+		let mut envelope = self.envelope_fields(items, batch_id, write_key, offset);
+		envelope["batch"] = items
+			.iter()
+			.map(|e| skew::correct(&e.value, offset).into_owned())
+			.collect();
+		self.json_format.normalize(envelope)
+	}
+
+	/// Writes the batch [`create_batch`](Self::create_batch) would create to `writer`,
+	/// returning the number of bytes written.
+	fn write_batch(
+		&self,
+		writer: &mut dyn Write,
+		items: &[StoredEvent],
+		batch_id: &str,
+		write_key: &str,
+	) -> Result<u64> {
+		let offset = skew::offset(self.clock_offset.as_deref());
+		let fields = self.envelope_fields(items, batch_id, write_key, offset);
+		envelope::write(
+			writer,
+			self.json_format,
+			items.iter().map(|e| skew::correct(&e.value, offset)),
+			fields,
+		)
+	}
+
+	/// The envelope of a batch of `items`, without the `batch` array
+	fn envelope_fields(
+		&self,
+		items: &[StoredEvent],
+		batch_id: &str,
+		write_key: &str,
+		offset: Option<TimeDelta>,
+	) -> Value {
+		let mut envelope = json!({
+			"sentAt": Self::now_rfc3339(offset),
+			"writeKey": write_key,
+			"batchId": batch_id
+		});
+		context::add_to(&mut envelope, self.context.as_deref());
+		if self.replayed && items.iter().any(|e| replay::is_replay(&e.value)) {
+			replay::flag(&mut envelope);
+		}
+		envelope
+	}
+
+	/// Selects the next batch like [`fetch`](DataStore::fetch), passing its events, batch
+	/// id and write key to `build` for the result's data.
+	fn fetch_with<O>(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+		build: impl FnOnce(&Self, &[StoredEvent], &str, &str) -> Result<O>,
+	) -> Result<Option<DataResult<O>>> {
+		self.adopt_loaded();
+		let max_bytes = max_bytes.unwrap_or(self.config.max_fetch_size);
+		let now = Utc::now();
+		let Some(write_key) = self.batch_write_key(now).map(str::to_string) else {
+			return Ok(None);
+		};
+		let mut accumulated_size = 0;
+		let mut items: Vec<StoredEvent> = Vec::new();
+
+		for item in self
+			.items
+			.iter()
+			.filter(|item| self.in_batch(item, &write_key, now))
+		{
+			let item_size = Self::get_item_size(item);
+			if accumulated_size + item_size > max_bytes {
+				break;
+			}
+			if let Some(count) = count {
+				if items.len() >= count {
+					break;
+				}
+			}
+			accumulated_size += item_size;
+			items.push(item.clone());
+		}
+
+		if items.is_empty() {
+			return Ok(None);
+		}
+
+		let (allowed_items, allowed_bytes) = self
+			.items
+			.iter()
+			.filter(|item| self.fetchable(item, now))
+			.fold((0, 0), |(count, bytes), item| {
+				(count + 1, bytes + Self::get_item_size(item) as u64)
+			});
+		let remaining_items = allowed_items - items.len();
+		let remaining_bytes = allowed_bytes - accumulated_size as u64;
+
+		// Cloning an event only clones its key and a reference to the value
+		let removable: Vec<Box<dyn Equivalent>> = items
+			.iter()
+			.map(|item| Box::new(item.clone()) as Box<dyn Equivalent>)
+			.collect();
+
+		// A retry of a batch that wasn't removed keeps its id, even after a reload
+		let seqs = items.iter().map(|item| item.seq).collect();
+		let batch_id = self.in_flight.id_for(seqs, |a, b| a == b);
+		self.save_in_flight();
+		let data = build(self, &items, &batch_id, &write_key)?;
+		if self.removed.is_enabled() {
+			self.fetched.record(&batch_id, Arc::new(items[0].clone()));
+		}
+
+		Ok(Some(DataResult {
+			data: Some(data),
+			removable: Some(removable),
+			batch_id: Some(batch_id),
+			attachments: self.attachment_handles(&items),
+			remaining_items,
+			remaining_bytes,
+		}))
+	}
+
+	/// Whether an event may be fetched at `now`: it has consent, isn't delayed and isn't
+	/// held by a move
+	fn fetchable(&self, item: &StoredEvent, now: DateTime<Utc>) -> bool {
+		self.fetchable_value(&item.value, now)
+	}
+
+	/// Like [`fetchable`](Self::fetchable), for events loaded from IndexedDB but not
+	/// adopted yet
+	fn fetchable_value(&self, value: &Value, now: DateTime<Utc>) -> bool {
+		self.consent.allows(value)
+			&& (!self.delayed || delay::is_due(value, now))
+			&& (!self.moving || !moves::is_held(value))
+	}
+
+	/// Write key of the next batch: that of the oldest event that may be fetched
+	fn batch_write_key(&self, now: DateTime<Utc>) -> Option<&str> {
+		self.items
+			.iter()
+			.find(|item| self.fetchable(item, now))
+			.map(|item| routing::write_key(&item.value, &self.config.write_key))
+	}
+
+	/// Whether an event may go in a batch for `write_key`
+	fn in_batch(&self, item: &StoredEvent, write_key: &str, now: DateTime<Utc>) -> bool {
+		self.fetchable(item, now)
+			&& routing::write_key(&item.value, &self.config.write_key) == write_key
+	}
+
+	/// Get current timestamp in RFC3339 format using js_sys::Date, shifted by `offset`
+	fn now_rfc3339(offset: Option<TimeDelta>) -> String {
+		let shift = offset.map_or(0, |offset| offset.num_milliseconds());
+		let date = js_sys::Date::new(&JsValue::from_f64(js_sys::Date::now() + shift as f64));
+		date.to_iso_string().into()
+	}
+
+	fn get_item_size(item: &StoredEvent) -> usize {
+		item.value.size()
+	}
+}
+
+impl DataStore for WebStore {
+	type Output = Value;
+
+	/// Events that aren't due yet, lack consent or are held by a move stay queued, but
+	/// don't count, as fetches skip them.
+	fn has_data(&self) -> bool {
+		let now = Utc::now();
+		self.items.iter().any(|item| self.fetchable(item, now))
+			|| self
+				.hydration
+				.borrow()
+				.loaded
+				.iter()
+				.any(|(_, value)| self.fetchable_value(value, now))
+			|| self.reopened.borrow().as_ref().is_some_and(|reopened| {
+				reopened
+					.events
+					.iter()
+					.any(|(_, value)| self.fetchable_value(value, now))
+			})
+	}
+
+	fn reset(&mut self) {
+		self.adopt_loaded();
+		// Events still to be hydrated are deleted by the hydration task
+		self.hydration.borrow_mut().cancelled = true;
+		// Clear memory
+		let items: Vec<StoredEvent> = self.items.drain(..).collect();
+		self.in_flight.clear();
+		self.save_in_flight();
+
+		// Fire-and-forget clear from IndexedDB
+		for item in items {
+			if let Some(key) = item.idb_key {
+				self.remove_from_idb(key);
+			}
+		}
+		self.prune_attachments();
+	}
+
+	fn append(&mut self, data: Value) -> Result<()> {
+		self.adopt_loaded();
+		if let Some(event) = self.push_event(data) {
+			// Enforce max_items, leaving room for reserved events
+			self.evict_to(self.config.max_items.saturating_sub(self.reserved).max(1));
+
+			// Fire-and-forget persist to IndexedDB
+			self.persist_events(vec![event]);
+		} else {
+			// Drop its attachments too, if it had any
+			self.prune_attachments();
+		}
+		Ok(())
+	}
+
+	fn append_many(&mut self, items: Vec<Value>) -> Result<()> {
+		self.adopt_loaded();
+		let mut events: Vec<StoredEvent> = items
+			.into_iter()
+			.filter_map(|data| self.push_event(data))
+			.collect();
+		let capacity = self.config.max_items.saturating_sub(self.reserved).max(1);
+		self.evict_to(capacity);
+
+		// Events already evicted by later ones in the group needn't be written
+		let evicted = events.len().saturating_sub(capacity);
+		self.persist_events(events.split_off(evicted));
+		Ok(())
+	}
+
+	fn fetch(
+		&mut self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<Option<DataResult<Self::Output>>> {
+		self.fetch_with(count, max_bytes, |store, items, batch_id, write_key| {
+			Ok(store.create_batch(items, batch_id, write_key))
+		})
+	}
+
+	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
+		self.adopt_loaded();
+		// First, collect keys to remove from IndexedDB
+		let keys_to_remove: Vec<u32> = self
+			.items
+			.iter()
+			.filter(|item| data.iter().any(|removable| removable.equals(*item)))
+			.filter_map(|item| item.idb_key)
+			.collect();
+
+		if self.removed.is_enabled() {
+			let events = self
+				.items
+				.iter()
+				.filter(|item| data.iter().any(|removable| removable.equals(*item)))
+				.map(|item| (*item.value).clone())
+				.collect();
+			let batch_id = self.fetched.take(data);
+			self.removed.push(batch_id, events, Utc::now());
+		}
+
+		let removed: HashSet<u64> = self
+			.items
+			.iter()
+			.filter(|item| data.iter().any(|removable| removable.equals(*item)))
+			.map(|item| item.seq)
+			.collect();
+		self.in_flight.forget(|seq| removed.contains(seq));
+		self.save_in_flight();
+
+		// Remove from memory
+		self.items
+			.retain(|item| !data.iter().any(|removable| removable.equals(item)));
+
+		// Fire-and-forget delete from IndexedDB
+		for key in keys_to_remove {
+			self.remove_from_idb(key);
+		}
+		self.prune_attachments();
+
+		Ok(())
+	}
+
+	fn debug_config(&self) -> Value {
 		json!({
-			"batch": values,
-			"sentAt": Self::now_rfc3339(),
-			"writeKey": self.config.write_key
+			"store": "WebStore",
+			"writeKey": self.config.write_key,
+			"databaseName": self.config.database_name,
+			"maxItems": self.config.max_items,
+			"maxFetchSize": self.config.max_fetch_size,
+			"jsonFormat": format!("{:?}", self.json_format),
+			"persistence": format!("{:?}", self.persistence_state),
+			"allowedCategories": self.consent.to_json(),
+			"fieldFilter": self.field_filter.to_json(),
+			"schema": self.migrations.as_ref().map(SchemaMigrations::to_json),
+			"receivedAtField": self.received_at,
+			"downSampling": self.sampler.as_ref().map(Sampler::to_json),
+			"quota": self.quota.as_ref().map(|gate| gate.borrow().to_json()),
+			"idbErrors": self.idb_errors.count.get(),
+		})
+	}
+
+	fn supports(&self, capability: Capability) -> bool {
+		match capability {
+			Capability::Attachments
+			| Capability::Pinning
+			| Capability::Routing
+			| Capability::Delayed
+			| Capability::Upsert
+			| Capability::Reservation
+			| Capability::Delivery
+			| Capability::EnqueueTimes
+			| Capability::PendingSize
+			| Capability::Preview
+			| Capability::PendingEvents
+			| Capability::Consent
+			| Capability::DropReport
+			| Capability::MoveSource
+			| Capability::MoveDestination
+			| Capability::RemovedBatches
+			| Capability::WriteBatchTo
+			| Capability::FetchOlderThan
+			| Capability::Status
+			| Capability::Expire => true,
+			#[cfg(feature = "anonymize")]
+			Capability::Anonymize => true,
+		}
+	}
+}
+
+impl DataStoreExt for WebStore {
+	fn append_pinned(&mut self, mut data: Value) -> Result<()> {
+		self.adopt_loaded();
+		eviction::pin(&mut data)?;
+		let pinned = self
+			.items
+			.iter()
+			.filter(|item| eviction::is_pinned(&item.value))
+			.count();
+		if pinned >= self.max_pinned {
+			return Err(TransientError::TooManyPinned {
+				limit: self.max_pinned,
+			}
+			.into());
+		}
+		self.append(data)
+	}
+
+	fn append_for(&mut self, write_key: &str, mut data: Value) -> Result<()> {
+		routing::tag(&mut data, write_key)?;
+		self.append(data)
+	}
+
+	fn append_delayed(&mut self, mut data: Value, not_before: DateTime<Utc>) -> Result<()> {
+		delay::tag(&mut data, not_before)?;
+		self.append(data)
+	}
+
+	fn upsert(&mut self, key: &str, mut data: Value) -> Result<()> {
+		self.adopt_loaded();
+		upsert::tag(&mut data, key)?;
+		if let Some(position) = self
+			.items
+			.iter()
+			.position(|item| upsert::has_key(&item.value, key))
+		{
+			if let Some(idb_key) = self.items.remove(position).and_then(|item| item.idb_key) {
+				self.remove_from_idb(idb_key);
+			}
+			self.prune_attachments();
+		}
+		self.append(data)
+	}
+
+	fn reserve(&mut self, _estimated_bytes: usize) -> Result<()> {
+		self.adopt_loaded();
+		self.reserved += 1;
+		self.evict_to(self.config.max_items.saturating_sub(self.reserved));
+		// Committing mustn't reallocate either
+		self.items.reserve(self.reserved);
+		Ok(())
+	}
+
+	fn commit_reserved(&mut self, data: Value, _estimated_bytes: usize) -> Result<()> {
+		self.adopt_loaded();
+		self.reserved = self.reserved.saturating_sub(1);
+		if let Some(event) = self.push_event(data) {
+			self.persist_events(vec![event]);
+		}
+		Ok(())
+	}
+
+	fn release_reserved(&mut self, _estimated_bytes: usize) {
+		self.reserved = self.reserved.saturating_sub(1);
+	}
+
+	fn append_with_attachments(
+		&mut self,
+		mut data: Value,
+		attachments: Vec<Attachment>,
+	) -> Result<()> {
+		self.adopt_loaded();
+		if self.delivered.is_redelivery(&data) {
+			return Ok(());
+		}
+
+		let attachments = attachment::attach(&mut data, attachments)?;
+		for (id, attachment) in attachments {
+			self.persist_attachment(&id, &attachment);
+			self.attachments.insert(id, attachment);
+		}
+		self.append(data)
+	}
+
+	fn mark_delivered(&mut self, batch_id: &str) -> Result<()> {
+		if self.delivered.insert(batch_id) {
+			self.save_delivered();
+		}
+		Ok(())
+	}
+
+	fn is_delivered(&self, batch_id: &str) -> bool {
+		self.delivered.contains(batch_id)
+	}
+
+	/// Events persisted by an earlier session report when they were hydrated,
+	/// since IndexedDB records don't carry their append time.
+	fn enqueue_times(&self) -> Result<Vec<DateTime<Utc>>> {
+		Ok(self.items.iter().map(|item| item.enqueued_at).collect())
+	}
+
+	fn oldest_enqueue_time(&self) -> Option<DateTime<Utc>> {
+		self.items.front().map(|item| item.enqueued_at)
+	}
+
+	fn preview_fetch(
+		&self,
+		count: Option<usize>,
+		max_bytes: Option<usize>,
+	) -> Result<BatchPreview> {
+		let max_bytes = max_bytes.unwrap_or(self.config.max_fetch_size) as u64;
+		let mut preview = BatchPreview::default();
+		let now = Utc::now();
+		let Some(write_key) = self.batch_write_key(now) else {
+			return Ok(preview);
+		};
+
+		// Mirrors fetch, without cloning the items
+		for item in self
+			.items
+			.iter()
+			.filter(|item| self.in_batch(item, write_key, now))
+		{
+			let item_size = Self::get_item_size(item) as u64;
+			if preview.bytes + item_size > max_bytes {
+				break;
+			}
+			if count.is_some_and(|count| preview.items >= count) {
+				break;
+			}
+			preview.add(item_size, Some(item.enqueued_at));
+		}
+		Ok(preview)
+	}
+
+	fn pending_events(&self) -> Result<Vec<Value>> {
+		Ok(self
+			.items
+			.iter()
+			.map(|item| (*item.value).clone())
+			.collect())
+	}
+
+	fn pending_size(&self) -> Result<PendingSize> {
+		Ok(PendingSize {
+			items: self.items.len(),
+			bytes: self
+				.items
+				.iter()
+				.map(|item| Self::get_item_size(item) as u64)
+				.sum(),
 		})
 	}
 
-	/// Get current timestamp in RFC3339 format using js_sys::Date
-	fn now_rfc3339() -> String {
-		let date = js_sys::Date::new_0();
-		date.to_iso_string().into()
+	fn set_allowed_categories(&mut self, categories: Option<HashSet<String>>) -> Result<()> {
+		self.consent.set(categories);
+		Ok(())
+	}
+
+	fn purge_revoked(&mut self) -> Result<usize> {
+		self.adopt_loaded();
+		let consent = &self.consent;
+		let (kept, revoked): (VecDeque<StoredEvent>, VecDeque<StoredEvent>) = self
+			.items
+			.drain(..)
+			.partition(|item| consent.allows(&item.value));
+		self.items = kept;
+
+		// Fire-and-forget delete from IndexedDB
+		for key in revoked.iter().filter_map(|item| item.idb_key) {
+			self.remove_from_idb(key);
+		}
+		self.prune_attachments();
+		Ok(revoked.len())
+	}
+
+	#[cfg(feature = "anonymize")]
+	fn anonymize(&mut self, anonymizer: &Anonymizer) -> Result<usize> {
+		self.adopt_loaded();
+		let mut changed = Vec::new();
+		for item in self.items.iter_mut() {
+			if item.value.update(|value| anonymizer.anonymize(value)) {
+				changed.push(item.clone());
+			}
+		}
+
+		// Fire-and-forget rewrite in IndexedDB
+		let count = changed.len();
+		for event in changed {
+			self.replace_in_idb(event);
+		}
+		Ok(count)
 	}
 
-	fn get_item_size(item: &StoredEvent) -> usize {
-		item.value.to_string().len()
+	fn drop_report(&mut self) -> Result<Vec<DropRecord>> {
+		let records = self.drops.take();
+		self.save_drops();
+		Ok(records)
 	}
-}
 
-impl DataStore for WebStore {
-	type Output = Value;
+	fn begin_move(&mut self, move_id: &str, count: usize) -> Result<Vec<Value>> {
+		self.adopt_loaded();
+		let now = Utc::now();
+		let mut copies = Vec::new();
+		let mut changed = Vec::new();
+		for position in 0..self.items.len() {
+			if copies.len() >= count {
+				break;
+			}
+			let item = &self.items[position];
+			// Attachments stay behind with their events
+			if !item.value.is_object()
+				|| !self.fetchable(item, now)
+				|| attachment::referenced_ids(&item.value).next().is_some()
+			{
+				continue;
+			}
+			copies.push((*item.value).clone());
+			let item = &mut self.items[position];
+			item.value
+				.update(|value| moves::tag(value, move_id).is_ok());
+			changed.push(item.clone());
+			self.moving = true;
+		}
 
-	fn has_data(&self) -> bool {
-		!self.items.is_empty()
+		// Fire-and-forget rewrite in IndexedDB
+		for event in changed {
+			self.replace_in_idb(event);
+		}
+		Ok(copies)
 	}
 
-	fn reset(&mut self) {
-		// Clear memory
-		let items: Vec<StoredEvent> = self.items.drain(..).collect();
+	fn stage_move(&mut self, move_id: &str, items: Vec<Value>) -> Result<()> {
+		let mut held = Vec::with_capacity(items.len());
+		for mut data in items {
+			moves::tag(&mut data, move_id)?;
+			held.push(data);
+		}
+		self.append_many(held)
+	}
 
-		// Fire-and-forget clear from IndexedDB
-		for item in items {
-			if let Some(key) = item.idb_key {
-				self.remove_from_idb(key);
+	fn release_move(&mut self, move_id: &str) -> Result<()> {
+		self.adopt_loaded();
+		let mut changed = Vec::new();
+		for item in self.items.iter_mut() {
+			if moves::move_id(&item.value) == Some(move_id) {
+				item.value.update(moves::untag);
+				changed.push(item.clone());
 			}
 		}
+		for event in changed {
+			self.replace_in_idb(event);
+		}
+		Ok(())
 	}
 
-	fn append(&mut self, data: Value) -> Result<()> {
-		let event = StoredEvent {
-			idb_key: Some(self.temp_key_counter),
-			value: data,
-		};
-		self.temp_key_counter += 1;
-
-		// Add to memory (sync)
-		self.items.push_back(event.clone());
+	fn discard_move(&mut self, move_id: &str) -> Result<()> {
+		self.adopt_loaded();
+		let (discarded, kept) = std::mem::take(&mut self.items)
+			.into_iter()
+			.partition(|item| moves::move_id(&item.value) == Some(move_id));
+		self.items = kept;
+		for key in discarded
+			.into_iter()
+			.filter_map(|item: StoredEvent| item.idb_key)
+		{
+			self.remove_from_idb(key);
+		}
+		Ok(())
+	}
 
-		// Enforce max_items
-		while self.items.len() > self.config.max_items {
-			if let Some(removed) = self.items.pop_front() {
-				if let Some(key) = removed.idb_key {
-					self.remove_from_idb(key);
-				}
+	fn held_moves(&self) -> Result<Vec<String>> {
+		let mut held: Vec<String> = Vec::new();
+		if !self.moving {
+			return Ok(held);
+		}
+		for move_id in self
+			.items
+			.iter()
+			.filter_map(|item| moves::move_id(&item.value))
+		{
+			if !held.iter().any(|id| id == move_id) {
+				held.push(move_id.to_string());
 			}
 		}
+		Ok(held)
+	}
 
-		// Fire-and-forget persist to IndexedDB
-		self.persist_event(event);
+	fn recently_removed(&self) -> Result<Vec<RemovedBatch>> {
+		Ok(self.removed.batches())
+	}
 
-		Ok(())
+	fn replay(&mut self, batch_id: &str) -> Result<usize> {
+		let mut events = self
+			.removed
+			.events_of(batch_id)
+			.ok_or_else(|| replay::not_retained(batch_id))?
+			.to_vec();
+		for event in &mut events {
+			replay::tag(event, batch_id)?;
+		}
+		let count = events.len();
+		self.append_many(events)?;
+		Ok(count)
 	}
 
-	fn fetch(
+	fn write_batch_to(
 		&mut self,
+		writer: &mut dyn Write,
 		count: Option<usize>,
 		max_bytes: Option<usize>,
-	) -> Result<Option<DataResult<Self::Output>>> {
-		let max_bytes = max_bytes.unwrap_or(self.config.max_fetch_size);
-		let mut accumulated_size = 0;
-		let mut num_items = 0;
-
-		for item in self.items.iter() {
-			let item_size = Self::get_item_size(item);
-			if accumulated_size + item_size > max_bytes {
-				break;
-			}
-			if let Some(count) = count {
-				if num_items >= count {
-					break;
-				}
-			}
-			accumulated_size += item_size;
-			num_items += 1;
-		}
+	) -> Result<Option<DataResult<u64>>> {
+		self.fetch_with(count, max_bytes, |store, items, batch_id, write_key| {
+			store.write_batch(writer, items, batch_id, write_key)
+		})
+	}
 
-		if num_items == 0 {
+	fn fetch_older_than(&mut self, age: Duration) -> Result<Option<DataResult<Self::Output>>> {
+		self.adopt_loaded();
+		let now = Utc::now();
+		let Some(cutoff) = TimeDelta::from_std(age)
+			.ok()
+			.and_then(|age| now.checked_sub_signed(age))
+		else {
 			return Ok(None);
-		}
-
-		let items: Vec<StoredEvent> = self.items.iter().take(num_items).cloned().collect();
-
-		let removable: Vec<Box<dyn Equivalent>> = items
+		};
+		let Some(write_key) = self.batch_write_key(now) else {
+			return Ok(None);
+		};
+		// Events are queued in arrival order, so the old enough ones are at the front
+		let old = self
+			.items
 			.iter()
-			.map(|item| Box::new(item.clone()) as Box<dyn Equivalent>)
-			.collect();
-
-		let batch = self.create_batch(&items);
+			.filter(|item| self.in_batch(item, write_key, now))
+			.take_while(|item| item.enqueued_at <= cutoff)
+			.count();
+		if old == 0 {
+			return Ok(None);
+		}
+		self.fetch(Some(old), None)
+	}
 
-		Ok(Some(DataResult {
-			data: Some(batch),
-			removable: Some(removable),
-		}))
+	fn status(&self) -> Result<StoreStatus> {
+		if self.is_opening() {
+			return Ok(StoreStatus::Opening);
+		}
+		Ok(match self.persistence_state {
+			PersistenceState::Persisted if self.is_quota_limited() => StoreStatus::MemoryOnly {
+				reason: Some("Storage usage is over the quota threshold".to_string()),
+			},
+			PersistenceState::Persisted => StoreStatus::Persisted,
+			PersistenceState::MemoryOnly => StoreStatus::MemoryOnly {
+				reason: self
+					.persistence_failure
+					.as_ref()
+					.map(|failure| failure.message.clone()),
+			},
+		})
 	}
 
-	fn remove(&mut self, data: &[Box<dyn Equivalent>]) -> Result<()> {
-		// First, collect keys to remove from IndexedDB
-		let keys_to_remove: Vec<u32> = self
+	fn expire_older_than(&mut self, age: Duration) -> Result<usize> {
+		self.adopt_loaded();
+		let Some(cutoff) = TimeDelta::from_std(age)
+			.ok()
+			.and_then(|age| Utc::now().checked_sub_signed(age))
+		else {
+			return Ok(0);
+		};
+		let expired: Vec<usize> = self
 			.items
 			.iter()
-			.filter(|item| data.iter().any(|removable| removable.equals(*item)))
-			.filter_map(|item| item.idb_key)
+			.enumerate()
+			.filter(|(_, item)| {
+				item.enqueued_at <= cutoff
+					&& !eviction::is_pinned(&item.value)
+					&& (!self.moving || !moves::is_held(&item.value))
+			})
+			.map(|(position, _)| position)
 			.collect();
-
-		// Remove from memory
-		self.items
-			.retain(|item| !data.iter().any(|removable| removable.equals(item)));
-
-		// Fire-and-forget delete from IndexedDB
-		for key in keys_to_remove {
-			self.remove_from_idb(key);
+		if expired.is_empty() {
+			return Ok(0);
 		}
-
-		Ok(())
+		self.drops.record_times(
+			DropReason::Expired,
+			expired
+				.iter()
+				.map(|&position| self.items[position].enqueued_at),
+		);
+		if let Some(listener) = &self.eviction_listener {
+			listener.notify(
+				DropReason::Expired,
+				expired.iter().map(|&position| &*self.items[position].value),
+				expired.len(),
+			);
+		}
+		self.save_drops();
+		for &position in expired.iter().rev() {
+			if let Some(key) = self.items.remove(position).and_then(|event| event.idb_key) {
+				self.remove_from_idb(key);
+			}
+		}
+		self.prune_attachments();
+		Ok(expired.len())
 	}
 }
 
@@ -620,6 +2970,140 @@ mod tests {
 		}
 	}
 
+	#[wasm_bindgen_test]
+	async fn test_drop_report_survives_reopen() {
+		let config = WebConfig {
+			write_key: "test-key".to_string(),
+			database_name: "test-drop-report".to_string(),
+			max_items: 2,
+			max_fetch_size: 1024,
+		};
+
+		let mut store = WebStore::new(config.clone()).await;
+		store.drop_report().unwrap();
+		for i in 0..5 {
+			store.append(json!({"index": i})).unwrap();
+		}
+		drop(store);
+
+		// The log is mirrored to localStorage
+		let mut store = WebStore::new(config).await;
+		let report = store.drop_report().unwrap();
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].reason, crate::DropReason::Capacity);
+		assert_eq!(report[0].count, 3);
+		assert!(store.drop_report().unwrap().is_empty());
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_snapshot_is_restored_by_next_store() {
+		let config = test_config("test-snapshot");
+		let mut store = WebStore::new(config.clone()).await;
+		store.reset();
+		store.persistence_state = PersistenceState::MemoryOnly;
+		store.db = None;
+		store.append(json!({"index": 0})).unwrap();
+		store.append(json!({"index": 1})).unwrap();
+		assert_eq!(store.save_snapshot().unwrap(), 2);
+		drop(store);
+
+		let mut store = WebStore::new(config.clone()).await;
+		assert_eq!(
+			store.pending_events().unwrap(),
+			vec![json!({"index": 0}), json!({"index": 1})]
+		);
+		// A persisted store has nothing to save
+		if store.is_persisted() {
+			assert_eq!(store.save_snapshot().unwrap(), 0);
+		}
+		store.reset();
+
+		// The snapshot was consumed
+		let store = WebStore::new(config).await;
+		assert!(!store.has_data());
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_scenarios() {
+		use crate::scenarios::{self, EVICTING_MAX_ITEMS, WRITE_KEY};
+		let config = |database_name: &str, max_items| WebConfig {
+			write_key: WRITE_KEY.to_string(),
+			database_name: database_name.to_string(),
+			max_items,
+			max_fetch_size: 64 * 1024,
+		};
+
+		let mut store = WebStore::new(config("test-scenarios", 100)).await;
+		scenarios::append_fetch_remove(&mut store).unwrap();
+		scenarios::partial_removal(&mut store).unwrap();
+		scenarios::append_during_fetch(&mut store).unwrap();
+		scenarios::age(&mut store).unwrap();
+
+		let mut store = WebStore::new(config("test-scenarios-evicting", EVICTING_MAX_ITEMS)).await;
+		scenarios::eviction(&mut store).unwrap();
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_quota_threshold_keeps_new_events_in_memory() {
+		let config = test_config("test-quota-threshold");
+		let mut store = WebStore::new(config.clone()).await;
+		if !store.is_persisted() {
+			web_sys::console::log_1(&"Skipping quota test - no persistence".into());
+			return;
+		}
+		store.reset();
+
+		// Nothing fits under a zero threshold, once the first estimate is in
+		store.set_quota_threshold(Some(0.0));
+		store.append(json!({"event": "before-estimate"})).unwrap();
+		gloo_timers::future::TimeoutFuture::new(200).await;
+		store.append(json!({"event": "over-quota"})).unwrap();
+		assert!(store.is_quota_limited());
+		assert!(!store.status().unwrap().is_persisted());
+		assert_eq!(store.pending_events().unwrap().len(), 2);
+		gloo_timers::future::TimeoutFuture::new(100).await;
+		drop(store);
+
+		let mut store = WebStore::new(config).await;
+		assert_eq!(
+			store.pending_events().unwrap(),
+			vec![json!({"event": "before-estimate"})]
+		);
+		store.reset();
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_idb_errors_reach_the_observer() {
+		let mut store = WebStore::new(test_config("test-idb-errors")).await;
+		let Some(db) = store.db.clone() else {
+			web_sys::console::log_1(&"Skipping IndexedDB error test - no persistence".into());
+			return;
+		};
+		store.reset();
+		let errors = Rc::new(RefCell::new(Vec::new()));
+		let seen = errors.clone();
+		store.set_idb_error_observer(move |error| seen.borrow_mut().push(error.clone()));
+
+		// Writes to a closed database fail
+		db.close();
+		store
+			.append_many(vec![json!({"event": "a"}), json!({"event": "b"})])
+			.unwrap();
+		gloo_timers::future::TimeoutFuture::new(100).await;
+
+		let errors = errors.borrow();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].operation, IdbOperation::Add);
+		assert_eq!(errors[0].events, 2);
+		assert_eq!(
+			errors[0].failure.exception.as_deref(),
+			Some("InvalidStateError")
+		);
+		assert_eq!(store.idb_error_count(), 1);
+		// Still queued in memory
+		assert_eq!(store.pending_events().unwrap().len(), 2);
+	}
+
 	#[wasm_bindgen_test]
 	async fn test_fetch_count_limit() {
 		let mut store = WebStore::new(test_config("test-fetch-count")).await;
@@ -742,6 +3226,129 @@ mod tests {
 		assert_eq!(store.is_persisted(), state == PersistenceState::Persisted);
 	}
 
+	#[wasm_bindgen_test]
+	async fn test_newer_format_is_left_alone() {
+		let config = test_config("test-newer-format");
+
+		// A later release upgraded the database past our version
+		let idb_factory = web_sys::window().unwrap().indexed_db().unwrap().unwrap();
+		let request = idb_factory
+			.open_with_f64(&config.database_name, 99.0)
+			.unwrap();
+		let db = WebStore::await_request::<IdbDatabase>(&request)
+			.await
+			.unwrap();
+		db.close();
+
+		let mut store = WebStore::new(config).await;
+		assert_eq!(
+			store.format_error(),
+			Some(&TransientError::IncompatibleFormat {
+				found: 99,
+				supported: DB_VERSION as u64,
+			})
+		);
+		assert_eq!(store.persistence_state(), PersistenceState::MemoryOnly);
+
+		// Still usable in memory
+		store.append(json!({"event": "test"})).unwrap();
+		assert!(store.has_data());
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_reopened_database_takes_memory_backlog() {
+		let config = test_config("test-reopened-backlog");
+
+		// An earlier session left an event behind
+		{
+			let mut store = WebStore::new(config.clone()).await;
+			if !store.is_persisted() {
+				web_sys::console::log_1(&"Skipping reopen test - no persistence".into());
+				return;
+			}
+			store.reset();
+			store.append(json!({"event": "earlier"})).unwrap();
+			gloo_timers::future::TimeoutFuture::new(100).await;
+		}
+
+		// Start memory-only, as if the first open had failed
+		let mut store = WebStore::new(config.clone()).await;
+		if let Some(db) = store.db.take() {
+			db.close();
+		}
+		store.items.clear();
+		store.persistence_state = PersistenceState::MemoryOnly;
+		store.append(json!({"event": "sent"})).unwrap();
+		store.append(json!({"event": "buffered"})).unwrap();
+		let fetched = store.fetch(Some(1), None).unwrap().unwrap();
+
+		// A retry opens the database
+		let db = WebStore::open_database(&config.database_name)
+			.await
+			.unwrap();
+		let events = WebStore::load_events(&db).await.unwrap();
+		*store.reopened.borrow_mut() = Some(Reopened {
+			db,
+			events,
+			attachments: HashMap::new(),
+		});
+
+		// Removing what was fetched before the switch only removes that event
+		store.remove(&fetched.removable.unwrap()).unwrap();
+		assert!(store.is_persisted());
+		assert!(store.persistence_details().is_none());
+		let expected = vec![json!({"event": "earlier"}), json!({"event": "buffered"})];
+		assert_eq!(store.pending_events().unwrap(), expected);
+		gloo_timers::future::TimeoutFuture::new(100).await;
+		drop(store);
+
+		// The backlog was written to IndexedDB
+		let store = WebStore::new(config).await;
+		assert_eq!(store.pending_events().unwrap(), expected);
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_deferred_open() {
+		let config = test_config("test-deferred-open");
+		{
+			let mut store = WebStore::new(config.clone()).await;
+			if !store.is_persisted() {
+				web_sys::console::log_1(&"Skipping deferred open test - no persistence".into());
+				return;
+			}
+			store.reset();
+			store.append(json!({"event": "earlier"})).unwrap();
+			gloo_timers::future::TimeoutFuture::new(100).await;
+		}
+
+		// Usable before IndexedDB is open
+		let mut store = WebStore::new_deferred(config.clone());
+		assert!(store.is_opening());
+		assert_eq!(store.status().unwrap(), StoreStatus::Opening);
+		assert!(!store.is_persisted());
+		store.append(json!({"event": "early"})).unwrap();
+
+		gloo_timers::future::TimeoutFuture::new(200).await;
+		assert!(store.has_data());
+		// The next operation switches to the opened database
+		store.append(json!({"event": "later"})).unwrap();
+		let expected = vec![
+			json!({"event": "earlier"}),
+			json!({"event": "early"}),
+			json!({"event": "later"}),
+		];
+		assert_eq!(store.pending_events().unwrap(), expected);
+		assert!(store.is_persisted());
+		assert!(!store.is_opening());
+		assert_eq!(store.status().unwrap(), StoreStatus::Persisted);
+		gloo_timers::future::TimeoutFuture::new(100).await;
+		drop(store);
+
+		// The early events were written once the database opened
+		let store = WebStore::new(config).await;
+		assert_eq!(store.pending_events().unwrap(), expected);
+	}
+
 	#[wasm_bindgen_test]
 	async fn test_hydration_across_instances() {
 		let db_name = "test-hydration";
@@ -837,6 +3444,210 @@ mod tests {
 		}
 	}
 
+	#[wasm_bindgen_test]
+	async fn test_partial_removal_keeps_rest_queued() {
+		let mut store = WebStore::new(test_config("test-partial-removal")).await;
+		store.reset();
+
+		store.append(json!({"event": "a"})).unwrap();
+		store.append(json!({"event": "rejected"})).unwrap();
+		store.append(json!({"event": "c"})).unwrap();
+		store.append(json!({"event": "rejected"})).unwrap();
+
+		let result = store.fetch(Some(3), None).unwrap().unwrap();
+		let removable = result.removable.unwrap();
+		store.remove(&removable[1..2]).unwrap();
+
+		let batch = store.fetch(None, None).unwrap().unwrap().data.unwrap();
+		let events: Vec<&str> = batch["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|item| item["event"].as_str().unwrap())
+			.collect();
+		assert_eq!(events, vec!["a", "c", "rejected"]);
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_attachments() {
+		let mut store = WebStore::new(test_config("test-attachments")).await;
+		store.reset();
+
+		let screenshot = Attachment {
+			name: "screen.png".to_string(),
+			content_type: "image/png".to_string(),
+			data: vec![1, 2, 3],
+		};
+		store
+			.append_with_attachments(json!({"event": "feedback"}), vec![screenshot])
+			.unwrap();
+
+		let result = store.fetch(None, None).unwrap().unwrap();
+		let batch = result.data.unwrap();
+		let handles = result.attachments.unwrap();
+		assert_eq!(handles.len(), 1);
+		assert_eq!(
+			handles[0].id,
+			batch["batch"][0]["_attachments"][0]["id"].as_str().unwrap()
+		);
+		assert_eq!(handles[0].content.read().unwrap(), vec![1, 2, 3]);
+
+		store.remove(&result.removable.unwrap()).unwrap();
+		assert!(store.attachments.is_empty());
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_retry_persistence_writes_buffered_attachments() {
+		let config = test_config("test-retry-persistence");
+		let mut store = WebStore::new(config.clone()).await;
+		if !store.is_persisted() {
+			web_sys::console::log_1(&"Skipping retry test - no persistence".into());
+			return;
+		}
+		store.reset();
+		gloo_timers::future::TimeoutFuture::new(100).await;
+
+		// Memory-only, as if storage access hadn't been granted yet
+		if let Some(db) = store.db.take() {
+			db.close();
+		}
+		store.persistence_state = PersistenceState::MemoryOnly;
+		let screenshot = Attachment {
+			name: "screen.png".to_string(),
+			content_type: "image/png".to_string(),
+			data: vec![1, 2, 3],
+		};
+		store
+			.append_with_attachments(json!({"event": "feedback"}), vec![screenshot])
+			.unwrap();
+
+		store.retry_persistence().await.unwrap();
+		assert!(store.is_persisted());
+		// Already persisted
+		store.retry_persistence().await.unwrap();
+		gloo_timers::future::TimeoutFuture::new(100).await;
+		drop(store);
+
+		let mut store = WebStore::new(config).await;
+		let result = store.fetch(None, None).unwrap().unwrap();
+		assert_eq!(result.data.unwrap()["batch"][0]["event"], "feedback");
+		let handles = result.attachments.unwrap();
+		assert_eq!(handles[0].content.read().unwrap(), vec![1, 2, 3]);
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_set_limits() {
+		let mut store = WebStore::new(test_config("test-set-limits")).await;
+		store.reset();
+		for i in 0..5 {
+			store.append(json!({"event": i})).unwrap();
+		}
+
+		assert!(store.set_limits(0, 1024).is_err());
+		assert!(store.set_limits(10, 50).is_err());
+		assert_eq!(store.pending_events().unwrap().len(), 5);
+
+		// Lowering max_items evicts the oldest events at once
+		store.set_limits(2, 200).unwrap();
+		assert_eq!(
+			store.pending_events().unwrap(),
+			vec![json!({"event": 3}), json!({"event": 4})]
+		);
+		assert_eq!(store.debug_config()["maxFetchSize"], 200);
+
+		store.append(json!({"event": 5})).unwrap();
+		assert_eq!(store.pending_events().unwrap().len(), 2);
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_hydration_in_slices() {
+		let config = test_config("test-hydration-slices");
+		{
+			let mut store = WebStore::new(config.clone()).await;
+			if !store.is_persisted() {
+				web_sys::console::log_1(&"Skipping sliced hydration test - no persistence".into());
+				return;
+			}
+			store.reset();
+			let events = (0..5).map(|i| json!({"event": i})).collect();
+			store.append_many(events).unwrap();
+			gloo_timers::future::TimeoutFuture::new(100).await;
+		}
+
+		let mut store = WebStore::new_hydrating(config, 2).await;
+		let hydrated = store.hydration_complete();
+		// Appended before hydration finishes, but queued after the hydrated events
+		store.append(json!({"event": "new"})).unwrap();
+		hydrated.await;
+
+		let result = store.fetch(None, None).unwrap().unwrap();
+		let events: Vec<Value> = result.data.unwrap()["batch"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|item| item["event"].clone())
+			.collect();
+		assert_eq!(
+			events,
+			vec![
+				json!(0),
+				json!(1),
+				json!(2),
+				json!(3),
+				json!(4),
+				json!("new")
+			]
+		);
+		store.hydration_complete().await;
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_delivered_batches_are_not_re_added() {
+		let mut store = WebStore::new(test_config("test-delivered")).await;
+		store.append(json!({"event": "test"})).unwrap();
+
+		let result = store.fetch(None, None).unwrap().unwrap();
+		let batch_id = result.batch_id.clone().unwrap();
+		let envelope = result.data.unwrap();
+		assert_eq!(envelope["batchId"], batch_id.as_str());
+
+		store.mark_delivered(&batch_id).unwrap();
+		store.remove(&result.removable.unwrap()).unwrap();
+
+		// Give fire-and-forget IndexedDB writes/deletes time to complete
+		gloo_timers::future::TimeoutFuture::new(100).await;
+
+		// Delivered ids survive a new instance via localStorage
+		let mut store = WebStore::new(test_config("test-delivered")).await;
+		assert!(store.is_delivered(&batch_id));
+		store.append(envelope).unwrap();
+		assert!(!store.has_data());
+	}
+
+	#[wasm_bindgen_test]
+	async fn test_retried_fetches_keep_their_batch_id() {
+		let mut store = WebStore::new(test_config("test-in-flight")).await;
+		store.reset();
+		store.append(json!({"n": 0})).unwrap();
+		store.append(json!({"n": 1})).unwrap();
+
+		let first = store.fetch(Some(1), None).unwrap().unwrap();
+		let retried = store.fetch(Some(1), None).unwrap().unwrap();
+		assert_eq!(retried.batch_id, first.batch_id);
+		gloo_timers::future::TimeoutFuture::new(100).await;
+
+		// The id survives a reload, as long as the events are still stored
+		let mut store = WebStore::new(test_config("test-in-flight")).await;
+		store.hydration_complete().await;
+		let reloaded = store.fetch(Some(1), None).unwrap().unwrap();
+		assert_eq!(reloaded.batch_id, first.batch_id);
+
+		store.remove(&reloaded.removable.unwrap()).unwrap();
+		let next = store.fetch(Some(1), None).unwrap().unwrap();
+		assert_ne!(next.batch_id, first.batch_id);
+		store.reset();
+	}
+
 	#[wasm_bindgen_test]
 	#[should_panic(expected = "max_fetch_size < 100 bytes?")]
 	async fn test_rejects_tiny_max_fetch_size() {
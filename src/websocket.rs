@@ -0,0 +1,120 @@
+//! Running a live drain over a browser WebSocket.
+
+use crate::live_drain::{LiveDrain, LiveMessage};
+use crate::logging;
+use crate::TransientDB;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+use std::ops::Deref;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::{MessageEvent, WebSocket};
+
+/// A [`LiveDrain`] over a browser WebSocket, for real-time pipelines such as session
+/// replay.
+///
+/// Sends micro-batches of up to `batch_count` events once the socket is open, and the
+/// next each time the server acks one with `{"ack": "<batchId>"}`. Call
+/// [`pump`](Self::pump) after appending to send events when nothing is awaiting an
+/// ack. A message still unacked when the socket closes stays queued, and is sent again
+/// by the drain of the next socket.
+///
+/// Dropping the drain detaches it from the socket.
+///
+/// Requires the `websocket` feature.
+///
+/// # Examples
+/// ```no_run
+/// use std::rc::Rc;
+/// use serde_json::json;
+/// use transientdb::{MemoryConfig, MemoryStore, TransientDB, WebSocketDrain};
+/// use web_sys::WebSocket;
+///
+/// # fn run() -> std::io::Result<()> {
+/// let db = Rc::new(TransientDB::new(MemoryStore::new(MemoryConfig {
+///     write_key: "my-key".into(),
+///     max_items: 1000,
+///     max_fetch_size: 64 * 1024,
+/// })));
+/// let socket = WebSocket::new("wss://replay.example.com/ingest")
+///     .map_err(|_| std::io::Error::other("Failed to open the socket"))?;
+/// let drain = WebSocketDrain::new(db.clone(), socket, 10);
+///
+/// db.append(json!({"event": "mousemove", "x": 10, "y": 20}))?;
+/// drain.pump()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WebSocketDrain<P> {
+	drain: Rc<RefCell<LiveDrain<P>>>,
+	socket: WebSocket,
+}
+
+impl<P> WebSocketDrain<P>
+where
+	P: Deref<Target = TransientDB<Value>> + 'static,
+{
+	/// Starts draining `db` over `socket`, sending up to `batch_count` events per message.
+	pub fn new(db: P, socket: WebSocket, batch_count: usize) -> Self {
+		let drain = Rc::new(RefCell::new(LiveDrain::new(db, batch_count)));
+
+		let (reply_drain, reply_socket) = (drain.clone(), socket.clone());
+		let onmessage = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+			let Some(text) = event.data().as_string() else {
+				return;
+			};
+			let next = reply_drain.borrow_mut().handle_reply(&text);
+			if let Err(e) = next.and_then(|next| send(&reply_socket, next.as_ref())) {
+				logging::log_warn!("Live drain failed: {}", e);
+			}
+		});
+		socket.set_onmessage(Some(onmessage.into_js_value().unchecked_ref()));
+
+		let (open_drain, open_socket) = (drain.clone(), socket.clone());
+		let onopen = Closure::<dyn Fn()>::new(move || {
+			if let Err(e) = pump(&open_drain, &open_socket) {
+				logging::log_warn!("Live drain failed: {}", e);
+			}
+		});
+		socket.set_onopen(Some(onopen.into_js_value().unchecked_ref()));
+
+		Self { drain, socket }
+	}
+
+	/// Sends the next micro-batch if the socket is open and no message is awaiting an
+	/// ack.
+	///
+	/// # Errors
+	/// Fails if fetching from the queue or sending fails.
+	pub fn pump(&self) -> Result<()> {
+		pump(&self.drain, &self.socket)
+	}
+}
+
+impl<P> Drop for WebSocketDrain<P> {
+	fn drop(&mut self) {
+		self.socket.set_onmessage(None);
+		self.socket.set_onopen(None);
+	}
+}
+
+fn pump<P>(drain: &RefCell<LiveDrain<P>>, socket: &WebSocket) -> Result<()>
+where
+	P: Deref<Target = TransientDB<Value>>,
+{
+	if socket.ready_state() != WebSocket::OPEN {
+		return Ok(());
+	}
+	let next = drain.borrow_mut().next_message()?;
+	send(socket, next.as_ref())
+}
+
+fn send(socket: &WebSocket, message: Option<&LiveMessage>) -> Result<()> {
+	let Some(message) = message else {
+		return Ok(());
+	};
+	socket
+		.send_with_str(&message.text)
+		.map_err(|_| Error::other("Failed to send on the WebSocket"))
+}
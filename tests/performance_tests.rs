@@ -8,13 +8,14 @@ use std::io::Result;
 use std::time::{Duration, Instant};
 use tempfile::TempDir;
 use transientdb::TransientDB;
-use transientdb::{DirectoryConfig, DirectoryStore};
+use transientdb::{DirectoryConfig, DirectoryStore, FlushPolicy};
 use transientdb::{MemoryConfig, MemoryStore};
 
 /* run these tests locally with:
 
 cargo test --release benchmark_memory_store -- --nocapture
 cargo test --release benchmark_directory_store -- --nocapture
+cargo test --release benchmark_directory_flush_policies -- --nocapture
 
  */
 
@@ -262,3 +263,42 @@ fn benchmark_directory_store() -> Result<()> {
 
 	Ok(())
 }
+
+#[test]
+fn benchmark_directory_flush_policies() -> Result<()> {
+	println!("\n=== Directory Store Flush Policy Comparison ===");
+
+	const EVENTS: usize = 100_000;
+	for policy in [FlushPolicy::EveryAppend, FlushPolicy::Buffered(64 * 1024)] {
+		let temp_dir = TempDir::new()?;
+		let mut store = DirectoryStore::new(DirectoryConfig {
+			write_key: "bench-key".to_string(),
+			storage_location: temp_dir.path().to_owned(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024 * 1024,
+		})?;
+		store.set_flush_policy(policy)?;
+		let db = TransientDB::new(store);
+
+		let mut write_latencies = LatencyStats::new();
+		let write_start = Instant::now();
+		for i in 0..EVENTS {
+			let op_start = Instant::now();
+			db.append(json!({"id": i, "event": "tick"}))?;
+			write_latencies.add(op_start.elapsed());
+		}
+		db.flush()?;
+		let write_duration = write_start.elapsed();
+		write_latencies.calculate();
+
+		println!("\n{:?}:", policy);
+		println!(
+			"Events per second: {:.2}",
+			EVENTS as f64 / write_duration.as_secs_f64()
+		);
+		println!("P50 latency: {:?}", write_latencies.p50);
+		println!("P99 latency: {:?}", write_latencies.p99);
+	}
+
+	Ok(())
+}
@@ -14,7 +14,10 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, thread};
 use tempfile::TempDir;
-use transientdb::{DirectoryConfig, DirectoryStore, MemoryConfig, MemoryStore, TransientDB};
+use transientdb::{
+	DataStore, DirectoryConfig, DirectoryStore, Equivalent, FileItem, LazyStore, MemoryConfig,
+	MemoryStore, RejectedEvents, StoreStatus, TransientDB,
+};
 
 #[test]
 fn test_concurrent_appends() -> Result<()> {
@@ -633,6 +636,31 @@ fn test_transient_db_panic_recovery() -> Result<()> {
 	Ok(())
 }
 
+#[test]
+fn test_transient_db_survives_panicking_callback() -> Result<()> {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+
+	let config = MemoryConfig {
+		write_key: "test-key".to_string(),
+		max_items: 100,
+		max_fetch_size: 1024,
+	};
+	let db = TransientDB::new(MemoryStore::new(config));
+	db.append(json!({"event": "old"}))?;
+
+	// The callback panics while the database holds its staleness lock
+	db.set_staleness_alert(Duration::ZERO, |_| panic!("Simulated panic"));
+	assert!(catch_unwind(AssertUnwindSafe(|| db.check_staleness())).is_err());
+
+	// A poisoned lock must not take every later call down with it
+	db.set_staleness_alert(Duration::from_secs(3600), |_| {});
+	db.append(json!({"event": "new"}))?;
+	assert_eq!(db.check_staleness(), None);
+	assert!(db.fetch(None, None)?.is_some());
+
+	Ok(())
+}
+
 #[test]
 fn test_directory_store_concurrent_validation() -> Result<()> {
 	let temp_dir = TempDir::new()?;
@@ -842,3 +870,305 @@ fn test_heavy_concurrent_load() -> Result<()> {
 
 	Ok(())
 }
+
+#[test]
+fn test_reserve_and_commit() -> Result<()> {
+	let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+		write_key: "test-key-reserve".to_string(),
+		max_items: 2,
+		max_fetch_size: 1024,
+	}));
+	db.append(json!({"index": 0}))?;
+	db.append(json!({"index": 1}))?;
+
+	let slot = db.reserve(32)?;
+	assert_eq!(slot.estimated_bytes(), 32);
+	slot.commit(json!({"index": 2}))?;
+	assert_eq!(db.stats().appended, 3);
+
+	// Dropping an uncommitted slot gives its room back
+	drop(db.reserve(32)?);
+	db.append(json!({"index": 3}))?;
+	let result = db.fetch(None, None)?.unwrap();
+	assert_eq!(result.removable.unwrap().len(), 2);
+
+	Ok(())
+}
+
+#[test]
+fn test_remove_accepted() -> Result<()> {
+	let db = TransientDB::new(MemoryStore::new(MemoryConfig {
+		write_key: "test-key-remove-accepted".to_string(),
+		max_items: 100,
+		max_fetch_size: 1024,
+	}));
+	for i in 0..5 {
+		db.append(json!({"index": i}))?;
+	}
+
+	// Server accepted 0, 2 and 4 - requeue the rest
+	let result = db.fetch(None, None)?.unwrap();
+	let rejected = db.remove_accepted(
+		result.removable.unwrap(),
+		&[0, 2, 4],
+		RejectedEvents::Requeue,
+	)?;
+	assert_eq!(rejected, vec![1, 3]);
+
+	let result = db.fetch(None, None)?.unwrap();
+	let indices: Vec<i64> = result.data.as_ref().unwrap()["batch"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|item| item["index"].as_i64().unwrap())
+		.collect();
+	assert_eq!(indices, vec![1, 3]);
+
+	// Server rejected both again - dead-letter them this time
+	let rejected =
+		db.remove_accepted(result.removable.unwrap(), &[], RejectedEvents::DeadLetter)?;
+	assert_eq!(rejected, vec![0, 1]);
+	assert!(!db.has_data());
+
+	Ok(())
+}
+
+#[test]
+fn test_remove_accepted_directory_items() -> Result<()> {
+	let temp_dir = TempDir::new()?;
+	let store = DirectoryStore::new(DirectoryConfig {
+		write_key: "test-key".to_string(),
+		storage_location: temp_dir.path().to_owned(),
+		base_filename: "events".to_string(),
+		max_file_size: 10_000,
+	})?;
+	let db = TransientDB::new(store);
+	for i in 0..3 {
+		db.append(json!({"index": i}))?;
+	}
+
+	let files = db.fetch(None, None)?.unwrap().data.unwrap();
+	let items: Vec<Box<dyn Equivalent>> = (0..3)
+		.map(|index| {
+			Box::new(FileItem {
+				path: files[0].clone(),
+				index,
+			}) as Box<dyn Equivalent>
+		})
+		.collect();
+
+	db.remove_accepted(items, &[0, 2], RejectedEvents::Requeue)?;
+
+	let content: Value = serde_json::from_str(&fs::read_to_string(&files[0])?)?;
+	let batch = content["batch"].as_array().unwrap();
+	assert_eq!(batch.len(), 1);
+	assert_eq!(batch[0]["index"], 1);
+
+	Ok(())
+}
+
+#[test]
+fn test_remove_accepted_directory_files() -> Result<()> {
+	let temp_dir = TempDir::new()?;
+	let store = DirectoryStore::new(DirectoryConfig {
+		write_key: "test-key".to_string(),
+		storage_location: temp_dir.path().to_owned(),
+		base_filename: "events".to_string(),
+		max_file_size: 100,
+	})?;
+	let db = TransientDB::new(store);
+	for i in 0..6 {
+		db.append(json!({"index": i, "padding": "x".repeat(20)}))?;
+	}
+
+	// One removable per file, but the accepted indices are events across those files
+	let result = db.fetch(None, None)?.unwrap();
+	assert!(result.data.unwrap().len() > 1);
+	let rejected = db.remove_accepted(
+		result.removable.unwrap(),
+		&[0, 2, 4],
+		RejectedEvents::Requeue,
+	)?;
+	assert_eq!(rejected, vec![1, 3, 5]);
+
+	let mut indices = Vec::new();
+	for file in db.fetch(None, None)?.unwrap().data.unwrap() {
+		let content: Value = serde_json::from_str(&fs::read_to_string(&file)?)?;
+		for item in content["batch"].as_array().unwrap() {
+			indices.push(item["index"].as_i64().unwrap());
+		}
+	}
+	assert_eq!(indices, vec![1, 3, 5]);
+	assert_eq!(db.stats().removed, 3);
+
+	Ok(())
+}
+
+#[test]
+fn test_stats() -> Result<()> {
+	let temp_dir = TempDir::new()?;
+	let config = DirectoryConfig {
+		write_key: "test-key".to_string(),
+		storage_location: temp_dir.path().to_owned(),
+		base_filename: "events".to_string(),
+		max_file_size: 1024,
+	};
+	let db = TransientDB::new(DirectoryStore::new(config)?);
+
+	for i in 0..3 {
+		db.append(json!({"index": i}))?;
+	}
+	let stats = db.stats();
+	assert_eq!(stats.appended, 3);
+	assert_eq!(stats.pending.unwrap().items, 1);
+	assert!(stats.pending.unwrap().bytes > 0);
+	assert!(stats.oldest_age.is_some());
+
+	let batch = db.fetch(None, None)?.unwrap();
+	db.remove(&batch.removable.unwrap())?;
+	assert!(db.fetch(None, None)?.is_none());
+
+	let stats = db.stats();
+	assert_eq!(stats.fetched, 1);
+	assert_eq!(stats.removed, 1);
+	assert_eq!(stats.pending.unwrap().items, 0);
+	assert_eq!(stats.oldest_age, None);
+	assert_eq!(
+		stats.append_errors + stats.fetch_errors + stats.remove_errors,
+		0
+	);
+
+	Ok(())
+}
+
+#[test]
+fn test_typed_db_shared_between_threads() -> Result<()> {
+	let db: Arc<TransientDB<Value, MemoryStore>> =
+		Arc::new(TransientDB::new_typed(MemoryStore::new(MemoryConfig {
+			write_key: "test-key-typed".to_string(),
+			max_items: 1000,
+			max_fetch_size: 64 * 1024,
+		})));
+
+	let handles: Vec<_> = (0..4)
+		.map(|t| {
+			let db = db.clone();
+			thread::spawn(move || -> Result<()> {
+				for i in 0..25 {
+					db.append(json!({"thread": t, "index": i}))?;
+				}
+				Ok(())
+			})
+		})
+		.collect();
+	for handle in handles {
+		handle.join().unwrap()?;
+	}
+
+	db.reserve(32)?.commit(json!({"index": "reserved"}))?;
+	let result = db.fetch(None, None)?.unwrap();
+	assert_eq!(result.data.unwrap()["batch"].as_array().unwrap().len(), 101);
+	db.remove(&result.removable.unwrap())?;
+	assert!(!db.has_data());
+	Ok(())
+}
+
+#[test]
+fn test_snapshot() -> Result<()> {
+	let temp_dir = TempDir::new()?;
+	let db = Arc::new(TransientDB::new(DirectoryStore::new(DirectoryConfig {
+		write_key: "test-key".to_string(),
+		storage_location: temp_dir.path().to_owned(),
+		base_filename: "events".to_string(),
+		max_file_size: 10_000,
+	})?));
+	db.append(json!({"index": 0}))?;
+	let batch = db.fetch(None, None)?.unwrap();
+	db.append(json!({"index": 1}))?;
+
+	// Covers both the fetched file and the one being written
+	let snapshot = db.snapshot()?;
+	let indices: Vec<i64> = snapshot
+		.iter()
+		.map(|e| e["index"].as_i64().unwrap())
+		.collect();
+	assert_eq!(indices, vec![0, 1]);
+
+	// The store isn't locked while the snapshot is processed
+	let writer = {
+		let db = db.clone();
+		thread::spawn(move || db.append(json!({"index": 2})))
+	};
+	writer.join().unwrap()?;
+	db.remove(&batch.removable.unwrap())?;
+	assert_eq!(snapshot.len(), 2);
+
+	let indices: Vec<i64> = db
+		.snapshot()?
+		.iter()
+		.map(|e| e["index"].as_i64().unwrap())
+		.collect();
+	assert_eq!(indices, vec![1, 2]);
+	Ok(())
+}
+
+#[test]
+fn test_with_store() -> Result<()> {
+	let db = TransientDB::new_typed(MemoryStore::new(MemoryConfig {
+		write_key: "test-key-with-store".to_string(),
+		max_items: 100,
+		max_fetch_size: 1024,
+	}));
+
+	// Configure the concrete store through the database
+	assert!(db.recently_removed()?.is_empty());
+	db.with_store(|store| store.set_retain_removed(1));
+	db.append(json!({"index": 0}))?;
+	let batch = db.fetch(None, None)?.unwrap();
+	db.remove(&batch.removable.unwrap())?;
+	assert_eq!(db.recently_removed()?.len(), 1);
+
+	// Changes made through the store are seen by the database, but not counted
+	db.with_store(|store| store.append(json!({"index": 1})))?;
+	assert!(db.has_data());
+	assert_eq!(db.stats().appended, 1);
+
+	// A boxed database hands out the trait object
+	let boxed = TransientDB::new(MemoryStore::new(MemoryConfig {
+		write_key: "test-key-with-store".to_string(),
+		max_items: 100,
+		max_fetch_size: 1024,
+	}));
+	boxed.append(json!({"index": 0}))?;
+	assert!(boxed.with_store(|store| store.has_data()));
+	Ok(())
+}
+
+#[test]
+fn test_status() -> Result<()> {
+	let config = MemoryConfig {
+		write_key: "test-key-status".to_string(),
+		max_items: 100,
+		max_fetch_size: 1024,
+	};
+	let memory = TransientDB::new(MemoryStore::new(config));
+	assert_eq!(memory.status()?, StoreStatus::MemoryOnly { reason: None });
+
+	let temp_dir = TempDir::new()?;
+	let path = temp_dir.path().to_owned();
+	let lazy = TransientDB::new(LazyStore::new(move || {
+		DirectoryStore::new(DirectoryConfig {
+			write_key: "test-key".to_string(),
+			storage_location: path.clone(),
+			base_filename: "events".to_string(),
+			max_file_size: 1024,
+		})
+	}));
+	assert_eq!(lazy.status()?, StoreStatus::Opening);
+	assert!(!lazy.status()?.is_persisted());
+
+	// Opened by the first append
+	lazy.append(json!({"index": 0}))?;
+	assert_eq!(lazy.status()?, StoreStatus::Persisted);
+	Ok(())
+}
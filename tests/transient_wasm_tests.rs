@@ -4,10 +4,11 @@
 //! in a browser environment. This is a regression test for the MaybeSend
 //! fix that allows Rc<IdbDatabase> to be used with TransientDB on WASM.
 
-#![cfg(target_arch = "wasm32")]
+// Threaded WASM builds share a WebStore through SharedWebStore instead
+#![cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
 
 use serde_json::{json, Value};
-use transientdb::{TransientDB, WebConfig, WebStore};
+use transientdb::{LazyStore, TransientDB, WebConfig, WebStore};
 use wasm_bindgen_test::*;
 
 wasm_bindgen_test_configure!(run_in_browser);
@@ -34,6 +35,21 @@ async fn test_transientdb_creation() {
 	// If we got here without a compile error, the fix works!
 }
 
+#[wasm_bindgen_test]
+async fn test_persisted_store_has_no_failure_details() {
+	let store = WebStore::new(test_config("test-persistence-details")).await;
+	assert!(store.is_persisted());
+	assert!(store.persistence_details().is_none());
+}
+
+#[wasm_bindgen_test]
+async fn test_transientdb_reports_persistence() {
+	let store = WebStore::new(test_config("test-transientdb-status")).await;
+	let persisted = store.is_persisted();
+	let db = TransientDB::new(store);
+	assert_eq!(db.status().unwrap().is_persisted(), persisted);
+}
+
 #[wasm_bindgen_test]
 async fn test_transientdb_empty_state() {
 	let store = WebStore::new(test_config("test-empty")).await;
@@ -552,3 +568,63 @@ async fn test_transientdb_numeric_edge_cases() {
 		assert_eq!(items[0]["negative"], -42);
 	}
 }
+
+#[wasm_bindgen_test]
+async fn test_lazy_store_buffers_until_open() {
+	let db = TransientDB::new(LazyStore::new_async(|| {
+		WebStore::new(test_config("test-lazy"))
+	}));
+	assert!(!db.has_data());
+
+	// The first append starts opening the store; events are held until it's open
+	db.append(json!({"index": 0})).unwrap();
+	db.append(json!({"index": 1})).unwrap();
+	assert!(db.has_data());
+	assert!(db.fetch(None, None).unwrap().is_none());
+
+	gloo_timers::future::TimeoutFuture::new(100).await;
+	let result = db.fetch(None, None).unwrap().unwrap();
+	let batch: Value = result.data.unwrap();
+	let items = batch["batch"].as_array().unwrap();
+	assert_eq!(items.len(), 2);
+	assert_eq!(items[1]["index"], 1);
+}
+
+// =============================================================================
+// Devtools
+// =============================================================================
+
+#[cfg(feature = "devtools")]
+#[wasm_bindgen_test]
+async fn test_devtools_bindings() {
+	use std::rc::Rc;
+	use wasm_bindgen::{JsCast, JsValue};
+
+	let store = WebStore::new(test_config("test-devtools")).await;
+	let db = Rc::new(TransientDB::new(store));
+	db.reset();
+	db.append(json!({"event": "a"})).unwrap();
+	db.append(json!({"event": "b"})).unwrap();
+	transientdb::install_devtools(db.clone()).unwrap();
+
+	let window = web_sys::window().unwrap();
+	let bindings = js_sys::Reflect::get(&window, &JsValue::from_str("__transientdb")).unwrap();
+	let function = |name: &str| -> js_sys::Function {
+		js_sys::Reflect::get(&bindings, &JsValue::from_str(name))
+			.unwrap()
+			.unchecked_into()
+	};
+
+	let stats = function("stats").call0(&JsValue::NULL).unwrap();
+	let appended = js_sys::Reflect::get(&stats, &JsValue::from_str("appended")).unwrap();
+	assert_eq!(appended.as_f64(), Some(2.0));
+
+	let events = function("browse")
+		.call1(&JsValue::NULL, &JsValue::from(1))
+		.unwrap();
+	let events: js_sys::Array = events.unchecked_into();
+	assert_eq!(events.length(), 1);
+
+	function("purge").call0(&JsValue::NULL).unwrap();
+	assert!(!db.has_data());
+}
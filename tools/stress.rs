@@ -0,0 +1,431 @@
+//! Soak test for TransientDB stores.
+//!
+//! Hammers a store with concurrent appends, fetches, removals and resets while injecting
+//! faults (skipped and partial removals, simulated restarts of a DirectoryStore), then
+//! checks that every event was delivered exactly once unless its loss was declared:
+//! dropped by a reset, or evicted by a MemoryStore that was allowed to fill up.
+//!
+//! ```text
+//! cargo run --release --features stress --bin stress -- --store directory --seconds 600
+//! ```
+//!
+//! Options:
+//! - `--store memory|directory` - store to test (default `memory`)
+//! - `--seconds N` - how long to run (default 30)
+//! - `--writers N` - number of appending threads (default 4)
+//! - `--max-items N` - MemoryStore capacity; losses to eviction are allowed when given
+//! - `--seed N` - seed for fault injection, to reproduce a run
+//!
+//! Exits with status 1 if an invariant was violated.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+use transientdb::{
+	DirectoryConfig, DirectoryContentStore, DirectoryStore, Equivalent, MemoryConfig, MemoryStore,
+	TransientDB,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StoreKind {
+	Memory,
+	Directory,
+}
+
+struct Options {
+	store: StoreKind,
+	seconds: u64,
+	writers: u64,
+	max_items: Option<usize>,
+	seed: u64,
+}
+
+impl Options {
+	fn parse() -> Result<Self, String> {
+		let mut options = Options {
+			store: StoreKind::Memory,
+			seconds: 30,
+			writers: 4,
+			max_items: None,
+			seed: chrono::Utc::now().timestamp_nanos_opt().unwrap_or(1) as u64,
+		};
+
+		let mut args = env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+			match arg.as_str() {
+				"--store" => {
+					options.store = match value()?.as_str() {
+						"memory" => StoreKind::Memory,
+						"directory" => StoreKind::Directory,
+						other => return Err(format!("Unknown store: {}", other)),
+					}
+				}
+				"--seconds" => options.seconds = number(&value()?)?,
+				"--writers" => options.writers = number(&value()?)?,
+				"--max-items" => options.max_items = Some(number(&value()?)? as usize),
+				"--seed" => options.seed = number(&value()?)?,
+				other => return Err(format!("Unknown option: {}", other)),
+			}
+		}
+		if options.max_items.is_some() && options.store != StoreKind::Memory {
+			return Err("--max-items only applies to the memory store".to_string());
+		}
+		Ok(options)
+	}
+}
+
+fn number(value: &str) -> Result<u64, String> {
+	value
+		.parse()
+		.map_err(|_| format!("Not a number: {}", value))
+}
+
+/// xorshift64*, so runs can be reproduced from their seed
+struct Rng(u64);
+
+impl Rng {
+	fn new(seed: u64) -> Self {
+		Rng(seed.max(1))
+	}
+
+	fn below(&mut self, n: u64) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545f4914f6cdd1d) % n
+	}
+}
+
+/// What the harness knows about every event, updated together with the store operation
+/// it describes so a reset or restart never falls between the two.
+#[derive(Default)]
+struct Ledger {
+	appended: HashSet<u64>,
+	removed: HashSet<u64>,
+	dropped: HashSet<u64>,
+	/// Every id the consumer has seen, with how often
+	seen: HashMap<u64, u64>,
+	/// Highest sequence number first seen per writer, to check FIFO order
+	last_first_seen: HashMap<u64, u64>,
+	violations: Vec<String>,
+}
+
+impl Ledger {
+	fn violation(&mut self, message: String) {
+		if self.violations.len() < 20 {
+			eprintln!("VIOLATION: {}", message);
+		}
+		self.violations.push(message);
+	}
+}
+
+struct Harness {
+	options: Options,
+	dir: std::path::PathBuf,
+	/// Appends, fetches and removals hold this for reading; resets and restarts for writing
+	db: RwLock<TransientDB<Value>>,
+	ledger: Mutex<Ledger>,
+	stop: AtomicBool,
+	resets: AtomicU64,
+	restarts: AtomicU64,
+	skipped_removes: AtomicU64,
+	partial_removes: AtomicU64,
+	append_errors: AtomicU64,
+}
+
+/// Pending events at which writers pause
+const MAX_BACKLOG: usize = 50_000;
+
+fn event_id(writer: u64, seq: u64) -> u64 {
+	(writer << 40) | seq
+}
+
+impl Harness {
+	fn open(options: &Options, dir: &std::path::Path) -> std::io::Result<TransientDB<Value>> {
+		Ok(match options.store {
+			StoreKind::Memory => TransientDB::new(MemoryStore::new(MemoryConfig {
+				write_key: "stress".to_string(),
+				max_items: options.max_items.unwrap_or(usize::MAX),
+				max_fetch_size: 256 * 1024,
+			})),
+			StoreKind::Directory => {
+				let store = DirectoryStore::new(DirectoryConfig {
+					write_key: "stress".to_string(),
+					storage_location: dir.to_owned(),
+					base_filename: "events".to_string(),
+					max_file_size: 4096,
+				})?;
+				TransientDB::new(DirectoryContentStore::new(store))
+			}
+		})
+	}
+
+	fn writer(&self, writer: u64) {
+		let mut seq = 0;
+		while !self.stop.load(Ordering::Relaxed) {
+			// Back off while the consumer catches up, like an app whose queue is bounded
+			if self.backlog() > MAX_BACKLOG {
+				thread::sleep(Duration::from_millis(1));
+				continue;
+			}
+			let id = event_id(writer, seq);
+			let event = json!({"id": id, "writer": writer, "seq": seq, "padding": "x".repeat((seq % 64) as usize)});
+			let db = self.db.read().unwrap();
+			match db.append(event) {
+				Ok(()) => {
+					self.ledger.lock().unwrap().appended.insert(id);
+				}
+				Err(_) => {
+					self.append_errors.fetch_add(1, Ordering::Relaxed);
+				}
+			}
+			drop(db);
+			seq += 1;
+		}
+	}
+
+	/// Number of appended events not yet removed or dropped
+	fn backlog(&self) -> usize {
+		let ledger = self.ledger.lock().unwrap();
+		ledger.appended.len() - ledger.removed.len() - ledger.dropped.len()
+	}
+
+	/// Fetches and removes one batch; returns false if the store was empty.
+	fn consume_one(&self, rng: &mut Rng, faults: bool) -> bool {
+		let db = self.db.read().unwrap();
+		let result = match db.fetch(Some(1 + rng.below(200) as usize), None) {
+			Ok(Some(result)) => result,
+			Ok(None) => return false,
+			Err(e) => {
+				self.ledger
+					.lock()
+					.unwrap()
+					.violation(format!("Fetch failed: {}", e));
+				return false;
+			}
+		};
+
+		let ids: Vec<u64> = result
+			.data
+			.as_ref()
+			.and_then(|data| data["batch"].as_array())
+			.map(|batch| batch.iter().filter_map(|e| e["id"].as_u64()).collect())
+			.unwrap_or_default();
+		let removable = result.removable.unwrap_or_default();
+
+		{
+			let mut ledger = self.ledger.lock().unwrap();
+			if ids.len() != removable.len() {
+				ledger.violation(format!(
+					"Batch has {} events but {} removables",
+					ids.len(),
+					removable.len()
+				));
+			}
+			for &id in &ids {
+				if ledger.removed.contains(&id) {
+					ledger.violation(format!("Event {:#x} delivered again after removal", id));
+				}
+				if ledger.dropped.contains(&id) {
+					ledger.violation(format!("Event {:#x} delivered after a reset", id));
+				}
+				let count = ledger.seen.entry(id).or_insert(0);
+				*count += 1;
+				if *count == 1 {
+					let (writer, seq) = (id >> 40, id & ((1 << 40) - 1));
+					if let Some(&last) = ledger.last_first_seen.get(&writer) {
+						if seq < last {
+							ledger.violation(format!(
+								"Writer {} event {} delivered after event {}",
+								writer, seq, last
+							));
+						}
+					}
+					ledger.last_first_seen.insert(writer, seq);
+				}
+			}
+		}
+
+		// Fault injection: the upload "failed" entirely or only partly
+		let keep = match rng.below(10) {
+			0 | 1 if faults => {
+				self.skipped_removes.fetch_add(1, Ordering::Relaxed);
+				0
+			}
+			2 | 3 if faults && !removable.is_empty() => {
+				self.partial_removes.fetch_add(1, Ordering::Relaxed);
+				rng.below(removable.len() as u64) as usize
+			}
+			_ => removable.len(),
+		};
+		let accepted: Vec<Box<dyn Equivalent>> = removable.into_iter().take(keep).collect();
+		if !accepted.is_empty() {
+			match db.remove(&accepted) {
+				Ok(()) => {
+					let mut ledger = self.ledger.lock().unwrap();
+					ledger.removed.extend(ids.iter().take(keep));
+				}
+				Err(e) => {
+					self.ledger
+						.lock()
+						.unwrap()
+						.violation(format!("Remove failed: {}", e));
+				}
+			}
+		}
+		true
+	}
+
+	fn consumer(&self, seed: u64) {
+		let mut rng = Rng::new(seed);
+		while !self.stop.load(Ordering::Relaxed) {
+			if !self.consume_one(&mut rng, true) {
+				thread::sleep(Duration::from_millis(1));
+			}
+		}
+	}
+
+	fn chaos(&self, seed: u64) {
+		let mut rng = Rng::new(seed);
+		while !self.stop.load(Ordering::Relaxed) {
+			thread::sleep(Duration::from_millis(20 + rng.below(80)));
+			match rng.below(10) {
+				0 => {
+					// Taken for writing to keep everyone else out while the ledger catches up
+					#[allow(clippy::readonly_write_lock)]
+					let db = self.db.write().unwrap();
+					db.reset();
+					let mut ledger = self.ledger.lock().unwrap();
+					let pending: Vec<u64> = ledger
+						.appended
+						.iter()
+						.filter(|id| !ledger.removed.contains(id) && !ledger.dropped.contains(id))
+						.copied()
+						.collect();
+					ledger.dropped.extend(pending);
+					self.resets.fetch_add(1, Ordering::Relaxed);
+				}
+				1..=3 if self.options.store == StoreKind::Directory => {
+					let mut db = self.db.write().unwrap();
+					// Drop the old store before reopening, like a process restart
+					*db = Self::open(&self.options, &self.dir).unwrap_or_else(|e| {
+						panic!("Failed to reopen store: {}", e);
+					});
+					self.restarts.fetch_add(1, Ordering::Relaxed);
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+fn main() -> ExitCode {
+	let options = match Options::parse() {
+		Ok(options) => options,
+		Err(e) => {
+			eprintln!("{}", e);
+			return ExitCode::from(2);
+		}
+	};
+	println!(
+		"Stressing {:?} store for {}s with {} writers (seed {})",
+		options.store, options.seconds, options.writers, options.seed
+	);
+
+	let dir = env::temp_dir().join(format!("transientdb-stress-{}", std::process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	let db = match Harness::open(&options, &dir) {
+		Ok(db) => db,
+		Err(e) => {
+			eprintln!("Failed to open store: {}", e);
+			return ExitCode::from(2);
+		}
+	};
+	let seed = options.seed;
+	let seconds = options.seconds;
+	let writers = options.writers;
+	let harness = Harness {
+		options,
+		dir: dir.clone(),
+		db: RwLock::new(db),
+		ledger: Mutex::new(Ledger::default()),
+		stop: AtomicBool::new(false),
+		resets: AtomicU64::new(0),
+		restarts: AtomicU64::new(0),
+		skipped_removes: AtomicU64::new(0),
+		partial_removes: AtomicU64::new(0),
+		append_errors: AtomicU64::new(0),
+	};
+
+	let started = Instant::now();
+	thread::scope(|scope| {
+		for writer in 0..writers {
+			let harness = &harness;
+			scope.spawn(move || harness.writer(writer));
+		}
+		scope.spawn(|| harness.consumer(seed ^ 0x5eed));
+		scope.spawn(|| harness.chaos(seed));
+		thread::sleep(Duration::from_secs(seconds));
+		harness.stop.store(true, Ordering::Relaxed);
+	});
+
+	// Deliver whatever is left, without faults
+	let mut rng = Rng::new(seed);
+	while harness.consume_one(&mut rng, false) {}
+
+	let mut ledger = harness.ledger.lock().unwrap();
+	let phantoms: Vec<u64> = ledger
+		.seen
+		.keys()
+		.filter(|id| !ledger.appended.contains(id))
+		.copied()
+		.collect();
+	for id in phantoms {
+		ledger.violation(format!("Event {:#x} delivered but never appended", id));
+	}
+	let lost: Vec<u64> = ledger
+		.appended
+		.iter()
+		.filter(|id| !ledger.removed.contains(id) && !ledger.dropped.contains(id))
+		.copied()
+		.collect();
+	let evicted = if harness.options.max_items.is_some() {
+		lost.len()
+	} else {
+		for &id in &lost {
+			ledger.violation(format!("Event {:#x} was lost", id));
+		}
+		0
+	};
+	let redelivered: u64 = ledger.seen.values().map(|count| count - 1).sum();
+
+	println!("Ran for {:.1}s", started.elapsed().as_secs_f64());
+	println!("  appended:         {}", ledger.appended.len());
+	println!("  delivered:        {}", ledger.removed.len());
+	println!("  redelivered:      {}", redelivered);
+	println!("  dropped by reset: {}", ledger.dropped.len());
+	println!("  evicted:          {}", evicted);
+	println!(
+		"  faults:           {} resets, {} restarts, {} skipped and {} partial removals, {} failed appends",
+		harness.resets.load(Ordering::Relaxed),
+		harness.restarts.load(Ordering::Relaxed),
+		harness.skipped_removes.load(Ordering::Relaxed),
+		harness.partial_removes.load(Ordering::Relaxed),
+		harness.append_errors.load(Ordering::Relaxed),
+	);
+	let _ = fs::remove_dir_all(&dir);
+
+	if ledger.violations.is_empty() {
+		println!("All invariants held");
+		ExitCode::SUCCESS
+	} else {
+		println!("{} invariant violations", ledger.violations.len());
+		ExitCode::FAILURE
+	}
+}